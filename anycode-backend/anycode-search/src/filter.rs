@@ -0,0 +1,92 @@
+use anyhow::Result;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Include and exclude globs scoping a search, matched against the paths
+/// relative to the searched directory: `src/**`, `*.ts`. Globs without a
+/// `/` match in any directory, `*` doesn't cross directories. Files are
+/// searched when they match an include, or there is none, and no exclude.
+/// An excluded directory is not walked.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq, Hash)]
+pub struct PathFilter {
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+impl PathFilter {
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Fails for an invalid glob
+    pub fn compile(&self) -> Result<CompiledFilter> {
+        let include = match self.include.is_empty() {
+            true => None,
+            false => Some(glob_set(&self.include)?),
+        };
+        Ok(CompiledFilter { include, exclude: glob_set(&self.exclude)? })
+    }
+}
+
+fn glob_set(globs: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs.iter().map(|g| g.trim()).filter(|g| !g.is_empty()) {
+        let glob = glob.trim_start_matches("./");
+        let glob = match glob.contains('/') {
+            true => glob.trim_start_matches('/').to_string(),
+            false => format!("**/{}", glob),
+        };
+        builder.add(GlobBuilder::new(&glob).literal_separator(true).build()?);
+    }
+    Ok(builder.build()?)
+}
+
+pub struct CompiledFilter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl CompiledFilter {
+    /// Whether the file at `relative` is searched
+    pub fn matches_file(&self, relative: &Path) -> bool {
+        !self.exclude.is_match(relative) && self.include.as_ref().is_none_or(|i| i.is_match(relative))
+    }
+
+    /// Whether the directory at `relative` is walked
+    pub fn walks_dir(&self, relative: &Path) -> bool {
+        !self.exclude.is_match(relative)
+    }
+}
+
+#[cfg(test)]
+mod filter_tests {
+    use super::*;
+
+    fn filter(include: &[&str], exclude: &[&str]) -> CompiledFilter {
+        PathFilter {
+            include: include.iter().map(|s| s.to_string()).collect(),
+            exclude: exclude.iter().map(|s| s.to_string()).collect(),
+        }.compile().unwrap()
+    }
+
+    #[test]
+    fn test_matches() {
+        let ts = filter(&["*.ts"], &["node_modules", "src/gen/**"]);
+        assert!(ts.matches_file(Path::new("app.ts")));
+        assert!(ts.matches_file(Path::new("src/deep/app.ts")));
+        assert!(!ts.matches_file(Path::new("src/app.rs")));
+        assert!(!ts.matches_file(Path::new("src/gen/api.ts")));
+        assert!(!ts.walks_dir(Path::new("web/node_modules")));
+        assert!(ts.walks_dir(Path::new("src/gen")));
+
+        let src = filter(&["src/*"], &[]);
+        assert!(src.matches_file(Path::new("src/lib.rs")));
+        assert!(!src.matches_file(Path::new("src/a/lib.rs")));
+        assert!(filter(&[], &[]).matches_file(Path::new("any/file")));
+
+        assert!(PathFilter { include: vec!["a[".to_string()], exclude: Vec::new() }.compile().is_err());
+    }
+}
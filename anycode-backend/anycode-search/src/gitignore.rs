@@ -0,0 +1,125 @@
+use ::ignore::Match;
+use ::ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+// The .gitignore and .ignore files of the workspace, with git's semantics:
+// the rules of a directory apply below it, the rules of deeper directories
+// win over the ones above, `!` includes a path again and .ignore wins over
+// .gitignore in the same directory. The repository root also reads
+// .git/info/exclude, directories above it don't apply. Matchers are built
+// on first use of a directory and dropped with `invalidate` when one of
+// the files changes.
+
+const IGNORE_FILES: &[&str] = &[".gitignore", ".ignore"];
+
+struct Dir {
+    matcher: Option<Arc<Gitignore>>,
+    /// Has a .git, the directories above are not looked at
+    repo_root: bool,
+}
+
+static DIRS: RwLock<Option<HashMap<PathBuf, Arc<Dir>>>> = RwLock::new(None);
+
+fn build(dir: &Path) -> Dir {
+    let repo_root = dir.join(".git").exists();
+    let mut files = Vec::new();
+    if repo_root {
+        files.push(dir.join(".git").join("info").join("exclude"));
+    }
+    files.extend(IGNORE_FILES.iter().map(|f| dir.join(f)));
+
+    // Later files win over earlier ones
+    let mut builder = GitignoreBuilder::new(dir);
+    let mut found = false;
+    for file in files.iter().filter(|f| f.is_file()) {
+        found = true;
+        if let Some(e) = builder.add(file) {
+            crate::log(&format!("{}: {}", file.display(), e));
+        }
+    }
+    let matcher = match found.then(|| builder.build()) {
+        Some(Ok(gitignore)) if !gitignore.is_empty() => Some(Arc::new(gitignore)),
+        Some(Err(e)) => {
+            crate::log(&format!("{}: {}", dir.display(), e));
+            None
+        }
+        _ => None,
+    };
+    Dir { matcher, repo_root }
+}
+
+fn dir(path: &Path) -> Arc<Dir> {
+    if let Some(dir) = DIRS.read().unwrap().as_ref().and_then(|dirs| dirs.get(path)) {
+        return dir.clone();
+    }
+    let dir = Arc::new(build(path));
+    DIRS.write().unwrap().get_or_insert_with(HashMap::new).insert(path.to_path_buf(), dir.clone());
+    dir
+}
+
+/// Whether the ignore files of the directories above `path` exclude it
+pub fn is_ignored(path: &Path, is_dir: bool) -> bool {
+    let path = crate::absolute(path);
+    for ancestor in path.ancestors().skip(1) {
+        let dir = dir(ancestor);
+        if let Some(matcher) = &dir.matcher {
+            match matcher.matched_path_or_any_parents(&path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+        if dir.repo_root {
+            break;
+        }
+    }
+    false
+}
+
+/// Drop the matchers when `path` is an ignore file or a repository
+/// appeared or went away
+pub fn invalidate(path: &Path) {
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else { return };
+    let exclude = path.ends_with(Path::new(".git").join("info").join("exclude"));
+    if IGNORE_FILES.contains(&name) || name == ".git" || exclude {
+        *DIRS.write().unwrap() = None;
+    }
+}
+
+#[cfg(test)]
+mod gitignore_tests {
+    use super::*;
+
+    #[test]
+    fn test_hierarchical_rules() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        std::fs::create_dir_all(root.join(".git").join("info"))?;
+        std::fs::create_dir_all(root.join("app").join("vendor"))?;
+        std::fs::write(root.join(".git").join("info").join("exclude"), "notes.txt\n")?;
+        std::fs::write(root.join(".gitignore"), "target/\n*.log\n!keep.log\nvendor/\n")?;
+        std::fs::write(root.join("app").join(".gitignore"), "local.rs\n")?;
+        std::fs::write(root.join("app").join(".ignore"), "!vendor/\n")?;
+
+        assert!(is_ignored(&root.join("target"), true));
+        assert!(is_ignored(&root.join("target").join("debug").join("app"), false));
+        assert!(!is_ignored(&root.join("target"), false));
+        assert!(is_ignored(&root.join("build.log"), false));
+        assert!(!is_ignored(&root.join("keep.log"), false));
+        assert!(is_ignored(&root.join("notes.txt"), false));
+        assert!(is_ignored(&root.join("vendor"), true));
+
+        // Deeper rules win
+        assert!(is_ignored(&root.join("app").join("local.rs"), false));
+        assert!(!is_ignored(&root.join("local.rs"), false));
+        assert!(!is_ignored(&root.join("app").join("vendor"), true));
+        assert!(!is_ignored(&root.join("app").join("main.rs"), false));
+
+        std::fs::write(root.join("app").join(".gitignore"), "")?;
+        invalidate(&root.join("app").join(".gitignore"));
+        assert!(!is_ignored(&root.join("app").join("local.rs"), false));
+        Ok(())
+    }
+}
@@ -0,0 +1,318 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+// Effective ignore rules: the built-in defaults, REDAI_IGNORE_DIRS and
+// REDAI_IGNORE_FILES, and the workspace overrides in .anycode/ignore. The
+// overrides file has one entry per line, directories end with `/`, `#`
+// starts a comment:
+//
+//     # build output
+//     dist/
+//     *.log
+//
+// The .gitignore and .ignore files of the workspace apply on top of them,
+// see gitignore.rs. The defaults only list what is never worth showing,
+// build output and caches are left to the projects' own ignore files.
+
+const IGNORE_FILE: &str = "ignore";
+/// Per-workspace state directory of anycode, the overrides are kept there
+const WORKSPACE_DIR: &str = ".anycode";
+
+pub const DEFAULT_IGNORE_DIRS: &[&str] = &[
+    // Version control and IDEs
+    ".git", ".anycode",
+];
+
+pub const DEFAULT_IGNORE_FILES: &[&str] = &[
+    // System files
+    ".DS_Store", "Thumbs.db", "desktop.ini",
+    // Certificate and key files
+    "*.pem", "*.key", "*.crt", "*.p12",
+    // Images and video
+    "*.png", "*.jpg", "*.jpeg", "*.gif", "*.bmp", "*.tiff", "*.webp",
+    "*.svg", "*.ico",
+    "*.mp4", "*.mov", "*.avi", "*.mkv", "*.webm", "*.flv", "*.wmv",
+    "*mp3", "*.wav", "*.ogg", "*.aac", "*.flac", "*.m4a", "*.opus", "*.wma",
+    // Archives
+    "*.zip", "*.tar", "*.gz", "*.bz2", "*.xz", "*.7z",
+];
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct IgnoreRules {
+    pub dirs: Vec<String>,
+    pub files: Vec<String>,
+}
+
+impl IgnoreRules {
+    fn defaults() -> Self {
+        Self {
+            dirs: DEFAULT_IGNORE_DIRS.iter().map(|d| d.to_string()).collect(),
+            files: DEFAULT_IGNORE_FILES.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    fn from_env() -> Self {
+        let list = |var: &str| -> Vec<String> {
+            std::env::var(var).unwrap_or_default()
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect()
+        };
+        Self { dirs: list("REDAI_IGNORE_DIRS"), files: list("REDAI_IGNORE_FILES") }
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut rules = Self::default();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.strip_suffix('/') {
+                Some(dir) => rules.dirs.push(dir.to_string()),
+                None => rules.files.push(line.to_string()),
+            }
+        }
+        rules
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for dir in &self.dirs {
+            text.push_str(&format!("{}/\n", dir));
+        }
+        for file in &self.files {
+            text.push_str(&format!("{}\n", file));
+        }
+        text
+    }
+
+    /// Entries trimmed, without empty ones, trailing `/` and duplicates
+    fn normalized(self) -> Self {
+        let clean = |entries: Vec<String>| {
+            let mut out: Vec<String> = Vec::new();
+            for entry in entries {
+                let entry = entry.trim().trim_end_matches('/').to_string();
+                if !entry.is_empty() && !out.contains(&entry) {
+                    out.push(entry);
+                }
+            }
+            out
+        };
+        Self { dirs: clean(self.dirs), files: clean(self.files) }
+    }
+
+    fn extend(&mut self, other: &IgnoreRules) {
+        self.dirs.extend(other.dirs.iter().cloned());
+        self.files.extend(other.files.iter().cloned());
+    }
+}
+
+/// The layers of the effective rules, as reported by `ignore:get`
+#[derive(Debug, Serialize, Clone)]
+pub struct IgnoreLayers {
+    pub defaults: IgnoreRules,
+    pub env: IgnoreRules,
+    pub workspace: IgnoreRules,
+}
+
+impl IgnoreLayers {
+    fn effective(&self) -> IgnoreRules {
+        let mut rules = self.defaults.clone();
+        rules.extend(&self.env);
+        rules.extend(&self.workspace);
+        rules
+    }
+}
+
+struct State {
+    layers: IgnoreLayers,
+    effective: Arc<IgnoreRules>,
+}
+
+static STATE: RwLock<Option<State>> = RwLock::new(None);
+
+fn ignore_file() -> PathBuf {
+    crate::absolute(Path::new(WORKSPACE_DIR)).join(IGNORE_FILE)
+}
+
+fn load_layers(path: &Path) -> IgnoreLayers {
+    let workspace = std::fs::read_to_string(path)
+        .map(|text| IgnoreRules::parse(&text))
+        .unwrap_or_default();
+    IgnoreLayers { defaults: IgnoreRules::defaults(), env: IgnoreRules::from_env(), workspace }
+}
+
+fn install(layers: IgnoreLayers) -> Arc<IgnoreRules> {
+    let effective = Arc::new(layers.effective());
+    *STATE.write().unwrap() = Some(State { layers, effective: effective.clone() });
+    effective
+}
+
+/// The effective rules, loaded on first use
+pub fn rules() -> Arc<IgnoreRules> {
+    if let Some(state) = STATE.read().unwrap().as_ref() {
+        return state.effective.clone();
+    }
+    install(load_layers(&ignore_file()))
+}
+
+pub fn layers() -> IgnoreLayers {
+    rules();
+    STATE.read().unwrap().as_ref().unwrap().layers.clone()
+}
+
+/// Replace the workspace overrides and persist them to .anycode/ignore
+pub fn set_workspace(rules: IgnoreRules) -> Result<IgnoreLayers> {
+    let rules = rules.normalized();
+    let path = ignore_file();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, rules.to_text())?;
+
+    let mut layers = layers();
+    layers.workspace = rules;
+    install(layers.clone());
+    Ok(layers)
+}
+
+/// Checks if any part of the path matches an ignored directory
+pub fn is_ignored_dir(path: &std::path::Path) -> bool {
+    let rules = rules();
+    path.iter()
+        .any(|p|
+            rules.dirs.iter().any(|dir| dir.as_str() == p.to_string_lossy())
+        )
+}
+
+/// Checks if a file should be ignored based on its name or extension
+pub fn is_ignored_file(file_name: &str) -> bool {
+    let rules = rules();
+    rules.files.iter().any(|pattern| {
+        if pattern.starts_with('*') && pattern.len() > 1 {
+            // Handle wildcard patterns like "*.log"
+            let extension = &pattern[1..];
+            file_name.ends_with(extension)
+        } else {
+            // Exact match
+            file_name == pattern
+        }
+    })
+}
+
+/// Subtrees excluded for the current session on top of the static ignore rules
+static FOCUS_EXCLUDES: RwLock<Vec<PathBuf>> = RwLock::new(Vec::new());
+
+/// Replace the session excludes, returns the absolute paths that were stored
+pub fn set_focus_excludes(paths: &[String]) -> Vec<PathBuf> {
+    let excludes = absolute_excludes(paths);
+    *FOCUS_EXCLUDES.write().unwrap() = excludes.clone();
+    excludes
+}
+
+fn absolute_excludes(paths: &[String]) -> Vec<PathBuf> {
+    paths.iter()
+        .map(|p| crate::absolute(Path::new(p.trim())))
+        .collect()
+}
+
+pub fn focus_excludes() -> Vec<PathBuf> {
+    FOCUS_EXCLUDES.read().unwrap().clone()
+}
+
+/// Checks if the path is inside a subtree excluded for this session
+pub fn is_focus_excluded(path: &Path) -> bool {
+    is_excluded_by(path, &FOCUS_EXCLUDES.read().unwrap())
+}
+
+/// Checks if the path is inside one of the absolute subtrees `excludes`
+fn is_excluded_by(path: &Path, excludes: &[PathBuf]) -> bool {
+    if excludes.is_empty() {
+        return false;
+    }
+    let path = crate::absolute(path);
+    excludes.iter().any(|exclude| path.starts_with(exclude))
+}
+
+/// Checks if a path should be ignored (either directory or file)
+pub fn is_ignored_path(path: &std::path::Path) -> bool {
+    is_ignored_entry(path, path.is_dir())
+}
+
+/// Like [`is_ignored_path`] for a path known to be a directory or not,
+/// saves the lookup to walkers that have the file type already
+pub fn is_ignored_entry(path: &Path, is_dir: bool) -> bool {
+    // Check if any directory in the path should be ignored
+    if is_ignored_dir(path) {
+        return true;
+    }
+
+    // Check if the .gitignore and .ignore files exclude it
+    if crate::gitignore::is_ignored(path, is_dir) {
+        return true;
+    }
+
+    // Check if the path is excluded by the session focus
+    if is_focus_excluded(path) {
+        return true;
+    }
+
+    // Check if the file itself should be ignored
+    if let Some(file_name) = path.file_name()
+        && let Some(file_name_str) = file_name.to_str()
+    {
+        return is_ignored_file(file_name_str);
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod ignore_tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_write() {
+        let rules = IgnoreRules::parse("# build\ndist/\n\n  target/ \n*.log\nsecrets.txt\n");
+        assert_eq!(rules.dirs, ["dist", "target"]);
+        assert_eq!(rules.files, ["*.log", "secrets.txt"]);
+        assert_eq!(IgnoreRules::parse(&rules.to_text()), rules);
+    }
+
+    #[test]
+    fn test_normalized() {
+        let rules = IgnoreRules {
+            dirs: vec![" dist/".into(), "dist".into(), "".into()],
+            files: vec!["*.log".into()],
+        }.normalized();
+        assert_eq!(rules.dirs, ["dist"]);
+        assert_eq!(rules.files, ["*.log"]);
+    }
+
+    #[test]
+    fn test_effective_layers() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(IGNORE_FILE);
+        std::fs::write(&path, "vendor/\n")?;
+
+        let effective = load_layers(&path).effective();
+        assert!(effective.dirs.contains(&".git".to_string()));
+        assert!(effective.dirs.contains(&"vendor".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_focus_excludes() {
+        let dir = std::env::temp_dir().join("anycode-focus-test");
+        let excluded = dir.join("packages").join("huge");
+        let excludes = absolute_excludes(&[format!(" {} ", excluded.display())]);
+        assert_eq!(excludes, std::slice::from_ref(&excluded));
+
+        assert!(is_excluded_by(&excluded.join("src").join("lib.rs"), &excludes));
+        assert!(!is_excluded_by(&dir.join("packages").join("app").join("main.rs"), &excludes));
+        assert!(!is_excluded_by(&excluded.join("src").join("lib.rs"), &[]));
+    }
+}
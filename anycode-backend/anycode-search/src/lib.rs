@@ -0,0 +1,83 @@
+//! Workspace search of anycode, shared by the server, the desktop and CLI
+//! builds and any tool that wants the same results.
+//!
+//! - [`ignore`]: the effective ignore rules (built-in defaults, the
+//!   `REDAI_IGNORE_DIRS`/`REDAI_IGNORE_FILES` env vars and the workspace
+//!   overrides in `.anycode/ignore`) and the session focus excludes.
+//! - [`gitignore`]: the `.gitignore` and `.ignore` files of the workspace.
+//! - [`walk`]: enumerating the files of a directory that are not ignored.
+//! - [`filter`]: include and exclude globs scoping a search.
+//! - [`search`]: plain text search of files, directories and documents in
+//!   memory, with cancellation and results streamed over a channel.
+//!
+//! Searching a directory runs on the caller's tokio runtime:
+//!
+//! ```no_run
+//! use anycode_search::search::dir_search;
+//! use tokio_util::sync::CancellationToken;
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+//! let search = tokio::spawn(async move {
+//!     dir_search(std::path::Path::new("."), "TODO", CancellationToken::new(), tx).await
+//! });
+//! while let Some(file) = rx.recv().await {
+//!     println!("{}: {} matches", file.file_path, file.matches.len());
+//! }
+//! search.await??;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Paths are resolved against the current directory, which is the
+//! workspace root. Errors of single files don't stop a search, they go to
+//! the function set with [`set_log`].
+
+pub mod filter;
+pub mod gitignore;
+pub mod ignore;
+pub mod search;
+pub mod walk;
+#[cfg(any(test, feature = "slow-fs"))]
+pub mod slow_fs;
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+static LOG: OnceLock<fn(&str)> = OnceLock::new();
+
+/// Where messages about skipped files go, stderr until set. Has no
+/// effect after the first call.
+pub fn set_log(log: fn(&str)) {
+    let _ = LOG.set(log);
+}
+
+pub(crate) fn log(message: &str) {
+    match LOG.get() {
+        Some(log) => log(message),
+        None => eprintln!("{}", message),
+    }
+}
+
+/// Resolve a path against the current directory unless it is absolute
+pub(crate) fn absolute(path: &Path) -> PathBuf {
+    if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir()
+            .map(|dir| dir.join(path))
+            .unwrap_or_else(|_| path.to_path_buf())
+    }
+}
+
+/// Hook of blocking filesystem accesses, a no-op without `slow-fs`
+pub(crate) fn fs_delay() {
+    #[cfg(any(test, feature = "slow-fs"))]
+    slow_fs::delay();
+}
+
+/// Hook of async filesystem accesses, a no-op without `slow-fs`
+pub(crate) async fn fs_delay_async() {
+    #[cfg(any(test, feature = "slow-fs"))]
+    slow_fs::delay_async().await;
+}
@@ -0,0 +1,778 @@
+use std::path::Path;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+use tokio::sync::{mpsc};
+use anyhow::Result;
+use tokio::sync::Semaphore;
+use std::sync::Arc;
+
+use crate::filter::PathFilter;
+
+/// Lines up to this many characters are previewed whole
+const WHOLE_LINE: usize = 160;
+/// Characters of the line before and after a match in its preview
+const CONTEXT: usize = 50;
+/// Lines from this many characters on, minified code and logs, get a
+/// shorter context
+const LONG_LINE: usize = 2000;
+const LONG_LINE_CONTEXT: usize = 20;
+/// Consecutive lines merged into one result at most
+const MAX_MERGED_LINES: usize = 5;
+
+/// Characters around a match in the preview of a line of `len`
+/// characters, None for the whole line
+fn preview_context(len: usize) -> Option<usize> {
+    match len {
+        len if len <= WHOLE_LINE => None,
+        len if len < LONG_LINE => Some(CONTEXT),
+        _ => Some(LONG_LINE_CONTEXT),
+    }
+}
+
+/// Matches of `pattern` in one line, columns in characters. Short lines
+/// are the preview of their matches, longer ones are cut to some
+/// characters around each match, fewer the longer the line.
+pub fn line_search(
+    line_content: &str, pattern: &str, line_number: usize
+) -> Vec<SearchResult> {
+    let mut results = Vec::new();
+    let mut search_start = 0;
+    let chars: Vec<char> = line_content.chars().collect();
+    let pattern_len = pattern.chars().count();
+    let context = preview_context(chars.len());
+
+    // Search for all occurrences in the line
+    while let Some(byte_index) = line_content[search_start..].find(pattern) {
+        // Count characters correctly – Unicode taught me to be careful
+        let column = line_content[..search_start + byte_index].chars().count();
+
+        let (preview_column, preview_end) = match context {
+            Some(context) => (column.saturating_sub(context), (column + pattern_len + context).min(chars.len())),
+            None => (0, chars.len()),
+        };
+
+        results.push(SearchResult {
+            line: line_number,
+            column,
+            preview: chars[preview_column..preview_end].iter().collect(),
+            preview_column,
+            whole_line: context.is_none(),
+            ranges: Vec::new(),
+        });
+
+        // Move forward in the line, search for the next match
+        search_start += byte_index + pattern.len();
+    }
+
+    results
+}
+
+
+/// A match, `line` and `column` start at 0
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct SearchResult {
+    pub line: usize,
+    pub column: usize,
+    pub preview: String,
+    /// Column of the line where the preview starts
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub preview_column: usize,
+    /// The preview is the whole line, see `merge_matches`
+    #[serde(skip)]
+    pub whole_line: bool,
+    /// The matches of a merged result, the first one included
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ranges: Vec<MatchRange>,
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+/// A match of a merged result, `offset` in characters into its preview
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct MatchRange {
+    pub line: usize,
+    pub column: usize,
+    pub offset: usize,
+}
+
+/// A merged result being built
+struct Merged {
+    result: SearchResult,
+    lines: usize,
+    /// Characters of the preview before the last line
+    line_start: usize,
+    preview_len: usize,
+    /// Column of the line where the preview of the last line ends
+    end_column: usize,
+}
+
+impl Merged {
+    fn new(m: &SearchResult) -> Self {
+        let preview_len = m.preview.chars().count();
+        let mut result = m.clone();
+        result.ranges = vec![MatchRange { line: m.line, column: m.column, offset: m.column - m.preview_column }];
+        Self { result, lines: 1, line_start: 0, preview_len, end_column: m.preview_column + preview_len }
+    }
+
+    fn last_line(&self) -> usize {
+        self.result.line + self.lines - 1
+    }
+
+    /// Take `m` in when it shares the preview, or is on the next line and
+    /// both are previewed whole
+    fn add(&mut self, m: &SearchResult) -> bool {
+        let same_line = m.line == self.last_line();
+        if same_line && m.whole_line && self.result.whole_line {
+            // The line is already in the preview
+        } else if same_line && !m.whole_line && m.preview_column <= self.end_column && self.lines == 1 {
+            let skip = self.end_column - m.preview_column;
+            let tail: String = m.preview.chars().skip(skip).collect();
+            let tail_len = tail.chars().count();
+            self.result.preview.push_str(&tail);
+            self.preview_len += tail_len;
+            self.end_column += tail_len;
+        } else if m.line == self.last_line() + 1 && m.whole_line && self.result.whole_line && self.lines < MAX_MERGED_LINES {
+            self.result.preview.push('\n');
+            self.line_start = self.preview_len + 1;
+            self.result.preview.push_str(&m.preview);
+            self.preview_len = self.line_start + m.preview.chars().count();
+            self.lines += 1;
+        } else {
+            return false;
+        }
+        let offset = self.line_start + m.column - self.result.preview_column;
+        self.result.ranges.push(MatchRange { line: m.line, column: m.column, offset });
+        true
+    }
+
+    fn finish(mut self) -> SearchResult {
+        if self.result.ranges.len() == 1 {
+            self.result.ranges.clear();
+        }
+        self.result
+    }
+}
+
+/// Merge the matches of a file that share a line, or are on consecutive
+/// short lines, into one result with a preview for all of them and their
+/// `ranges`. `matches` are in the order of the file.
+pub fn merge_matches(matches: &[SearchResult]) -> Vec<SearchResult> {
+    let mut merged: Vec<SearchResult> = Vec::new();
+    let mut current: Option<Merged> = None;
+    for m in matches {
+        if let Some(open) = current.as_mut() && open.add(m) {
+            continue;
+        }
+        merged.extend(current.replace(Merged::new(m)).map(Merged::finish));
+    }
+    merged.extend(current.map(Merged::finish));
+    merged
+}
+
+/// Stream the matches of a file to `result_tx` line by line, stopping
+/// when `cancel_token` fires
+pub async fn file_search(
+    file_path: &str,
+    pattern: &str,
+    cancel_token: CancellationToken,
+    result_tx: mpsc::Sender<SearchResult>,
+) -> Result<()> {
+    let path = Path::new(file_path);
+    crate::fs_delay_async().await;
+    let file = tokio::fs::File::open(path).await?;
+    let reader = BufReader::new(file);
+
+    let mut lines = reader.lines();
+    let mut line_number = 0;
+
+    loop {
+        tokio::select! {
+            line = lines.next_line() => {
+                match line? {
+                    Some(content) => {
+                        if cancel_token.is_cancelled() { break }
+
+                        let line_results = line_search(&content, pattern, line_number);
+
+                        for result in line_results {
+                            if let Err(e) = result_tx.send(result).await {
+                                crate::log(&format!("Failed to send result: {}", e));
+                                break;
+                            }
+                        }
+
+                        line_number += 1;
+                    }
+                    // End of file reached
+                    None => { break }
+                }
+            }
+            _ = cancel_token.cancelled() => { break }
+        }
+    }
+
+    Ok(())
+}
+
+/// The matches of one file. Paths inside the current directory are
+/// relative to it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FileSearchResult {
+    pub file_path: String,
+    pub matches: Vec<SearchResult>,
+}
+
+/// Search the files below `dir_path` that are not ignored, 32 at a time,
+/// sending one result per file with matches. Returns once every file was
+/// searched or `cancel_token` fired; the walk is blocking.
+pub async fn dir_search(
+    dir_path: &Path,
+    pattern: &str,
+    cancel_token: CancellationToken,
+    result_tx: mpsc::Sender<FileSearchResult>,
+) -> Result<()> {
+    dir_search_filtered(dir_path, pattern, &PathFilter::default(), cancel_token, result_tx).await
+}
+
+/// Like [`dir_search`], searching only the files `filter` keeps. Fails
+/// before searching for an invalid glob.
+pub async fn dir_search_filtered(
+    dir_path: &Path,
+    pattern: &str,
+    filter: &PathFilter,
+    cancel_token: CancellationToken,
+    result_tx: mpsc::Sender<FileSearchResult>,
+) -> Result<()> {
+    let files = match filter.is_empty() {
+        true => crate::walk::collect_files_recursively(dir_path)?,
+        false => crate::walk::collect_files_filtered(dir_path, &filter.compile()?)?,
+    };
+    let semaphore = Arc::new(Semaphore::new(32));
+    let mut handles = Vec::new();
+
+    for file_path in files {
+        if cancel_token.is_cancelled() {
+            break;
+        }
+
+        let permit = semaphore.clone().acquire_owned().await.unwrap();
+        let path_buf = file_path.clone();
+        let pattern = pattern.to_string();
+        let cancel_token = cancel_token.clone();
+        let result_tx = result_tx.clone();
+
+        let handle = tokio::spawn(async move {
+            let _permit = permit;
+
+            let (search_result_tx, mut search_result_rx) = mpsc::channel(100);
+            let file_cancel_token = cancel_token.clone();
+
+            let file_path_str = path_buf.to_string_lossy().to_string();
+            let display_path = std::env::current_dir().ok()
+                .and_then(|dir| path_buf.strip_prefix(dir).ok().map(|p| p.to_string_lossy().to_string()))
+                .unwrap_or_else(|| file_path_str.clone());
+
+            tokio::select! {
+                res = file_search(&file_path_str, &pattern, file_cancel_token, search_result_tx) => {
+                    if let Err(err) = res {
+                        crate::log(&format!("Error searching in file {}: {}", file_path_str, err));
+                        return;
+                    }
+                }
+                _ = cancel_token.cancelled() => {
+                    return;
+                }
+            }
+
+            let mut matches = Vec::new();
+            while let Some(result) = search_result_rx.recv().await {
+                matches.push(result);
+            }
+
+            if !matches.is_empty()
+                && result_tx.send(FileSearchResult {
+                    file_path: display_path,
+                    matches,
+                }).await.is_err()
+            {
+                crate::log("Global receiver dropped. Skipping results");
+            }
+        });
+
+        handles.push(handle);
+    }
+
+    for handle in handles {
+        let _ = handle.await;
+    }
+
+    Ok(())
+}
+
+/// Search a document held in memory, None when nothing matches
+pub fn text_search(file_path: &str, text: &str, pattern: &str) -> Option<FileSearchResult> {
+    let matches: Vec<SearchResult> = text.lines()
+        .enumerate()
+        .flat_map(|(line_number, line)| line_search(line, pattern, line_number))
+        .collect();
+
+    (!matches.is_empty()).then(|| FileSearchResult { file_path: file_path.to_string(), matches })
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Matches of `symbol` in one line that are not part of a longer word:
+/// `id` finds `id` and `obj.id` but not `idle`. The ends of the symbol
+/// that are not word characters, like in `foo(`, match anywhere.
+pub fn symbol_line_search(line_content: &str, symbol: &str, line_number: usize) -> Vec<SearchResult> {
+    let starts_word = symbol.chars().next().is_some_and(is_word_char);
+    let ends_word = symbol.chars().last().is_some_and(is_word_char);
+    let symbol_len = symbol.chars().count();
+
+    let mut results = line_search(line_content, symbol, line_number);
+    if !starts_word && !ends_word {
+        return results;
+    }
+    let chars: Vec<char> = line_content.chars().collect();
+    results.retain(|result| {
+        let before = result.column.checked_sub(1).map(|i| chars[i]);
+        let after = chars.get(result.column + symbol_len).copied();
+        let bounded_before = !starts_word || before.is_none_or(|c| !is_word_char(c));
+        let bounded_after = !ends_word || after.is_none_or(|c| !is_word_char(c));
+        bounded_before && bounded_after
+    });
+    results
+}
+
+/// Usages of `symbol` in a document, see [`symbol_line_search`]
+pub fn symbol_search(file_path: &str, text: &str, symbol: &str) -> Option<FileSearchResult> {
+    let matches: Vec<SearchResult> = text.lines()
+        .enumerate()
+        .flat_map(|(line_number, line)| symbol_line_search(line, symbol, line_number))
+        .collect();
+
+    (!matches.is_empty()).then(|| FileSearchResult { file_path: file_path.to_string(), matches })
+}
+
+/// Order of search results inside a batching window. `Walk` keeps the
+/// file walk order and sends results as soon as they are found.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchOrder {
+    #[default]
+    Walk,
+    /// Pattern in the file name first, then in the path, shallow paths first
+    Relevance,
+    /// Recently modified files first
+    Recent,
+    /// Files with more matches first
+    Matches,
+}
+
+/// Window for collecting results before ranking and sending them, short
+/// enough to keep streaming but long enough to rank the first screen.
+const RANK_WINDOW: std::time::Duration = std::time::Duration::from_millis(100);
+
+impl SearchOrder {
+    pub fn window(&self) -> std::time::Duration {
+        match self {
+            SearchOrder::Walk => std::time::Duration::ZERO,
+            _ => RANK_WINDOW,
+        }
+    }
+}
+
+/// Wait for the next result and collect everything arriving within the
+/// window after it. Returns None once the search is finished.
+pub async fn next_batch(
+    result_rx: &mut mpsc::Receiver<FileSearchResult>,
+    window: std::time::Duration,
+) -> Option<Vec<FileSearchResult>> {
+    let first = result_rx.recv().await?;
+    let mut batch = vec![first];
+
+    let deadline = tokio::time::Instant::now() + window;
+    while let Ok(Some(result)) = tokio::time::timeout_at(deadline, result_rx.recv()).await {
+        batch.push(result);
+    }
+
+    Some(batch)
+}
+
+fn path_relevance(file_path: &str, pattern: &str) -> usize {
+    let pattern = pattern.to_lowercase();
+    let path = file_path.to_lowercase();
+    let name = Path::new(&path).file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    if name.contains(&pattern) { 0 }
+    else if path.contains(&pattern) { 1 }
+    else { 2 }
+}
+
+/// Sort results found within one window by `order`
+pub fn rank_results(results: &mut [FileSearchResult], order: SearchOrder, pattern: &str) {
+    match order {
+        SearchOrder::Walk => {}
+        SearchOrder::Relevance => results.sort_by_cached_key(|r| (
+            path_relevance(&r.file_path, pattern),
+            r.file_path.matches(std::path::MAIN_SEPARATOR).count(),
+            std::cmp::Reverse(r.matches.len()),
+        )),
+        SearchOrder::Recent => results.sort_by_cached_key(|r| std::cmp::Reverse(
+            std::fs::metadata(&r.file_path).and_then(|m| m.modified()).ok()
+        )),
+        SearchOrder::Matches => results.sort_by_key(|r| std::cmp::Reverse(r.matches.len())),
+    }
+}
+
+#[cfg(test)]
+mod search_tests {
+    use super::*;
+    
+    #[test]
+    fn test_line_search_simple() {
+        let line = "This is a test string where test appears twice: test.";
+        let pattern = "test";
+        let results = line_search(line, pattern, 0);
+
+        assert_eq!(results.len(), 3);
+
+        // First occurrence
+        assert_eq!(results[0].line, 0);
+        assert_eq!(results[0].column, 10);
+        assert!(results[0].preview.contains(pattern));
+
+        // Second occurrence
+        assert_eq!(results[1].column, 28);
+        assert!(results[1].preview.contains(pattern));
+
+        // Third occurrence
+        assert_eq!(results[2].column, 48);
+        assert!(results[2].preview.contains(pattern));
+    }
+    
+    #[test]
+    fn test_line_search_unicode() {
+        let line = "Пример строки с шаблон шаблоном и ещё текст.";
+        let pattern = "шаблон";
+        let results = line_search(line, pattern, 0);
+
+        assert_eq!(results.len(), 2);
+        
+        // First occurrence
+        assert_eq!(results[0].line, 0);
+        assert_eq!(results[0].column, 16);
+        assert!(results[0].preview.contains(pattern));
+
+        // Second occurrence
+        assert_eq!(results[1].column, 23);
+        assert!(results[1].preview.contains(pattern));
+    }
+    
+    #[test]
+    fn test_line_search_no_match() {
+        let line = "Nothing to see here.";
+        let pattern = "absent";
+        let results = line_search(line, pattern, 0);
+
+        assert!(results.is_empty());
+    }
+    
+    #[test]
+    fn test_line_search_long_preview_cutoff() {
+        let line = "A".repeat(100) + "pattern" + &"B".repeat(100);
+        let pattern = "pattern";
+        let results = line_search(&line, pattern, 0);
+    
+        assert_eq!(results.len(), 1);
+        let result = &results[0];
+    
+        assert_eq!(result.line, 0);
+        assert_eq!(result.column, 100); // 100 'A's before pattern
+        assert!(result.preview.contains(pattern));
+    
+        let expected_preview_len = 50 + pattern.len() + 50;
+        assert_eq!(result.preview.chars().count(), expected_preview_len);
+    
+        assert!(result.preview.starts_with(&"A".repeat(50)));
+        assert!(result.preview.ends_with(&"B".repeat(50)));
+    }
+
+    #[test]
+    fn test_adaptive_preview() {
+        let short = line_search("let a = b + b;", "b", 0);
+        assert!(short.iter().all(|r| r.preview == "let a = b + b;" && r.whole_line));
+
+        let minified = "x".repeat(3000) + "needle" + &"y".repeat(100);
+        let result = &line_search(&minified, "needle", 0)[0];
+        assert_eq!(result.preview, "x".repeat(20) + "needle" + &"y".repeat(20));
+        assert_eq!(result.preview_column, 2980);
+    }
+
+    #[test]
+    fn test_merge_matches() {
+        let text = "a b a\na\n\na";
+        let matches: Vec<SearchResult> = text.lines().enumerate()
+            .flat_map(|(i, line)| line_search(line, "a", i))
+            .collect();
+        let merged = merge_matches(&matches);
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].preview, "a b a\na");
+        let offsets: Vec<(usize, usize)> = merged[0].ranges.iter().map(|r| (r.line, r.offset)).collect();
+        assert_eq!(offsets, [(0, 0), (0, 4), (1, 6)]);
+        // A single match stays a plain result
+        assert!(merged[1].ranges.is_empty());
+
+        // Overlapping windows of a long line share one preview
+        let line = "x".repeat(200) + "ab" + &"x".repeat(10) + "ab" + &"x".repeat(200);
+        let merged = merge_matches(&line_search(&line, "ab", 0));
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].preview.chars().count(), 50 + 14 + 50);
+        let second = &merged[0].ranges[1];
+        assert_eq!(&merged[0].preview[second.offset..second.offset + 2], "ab");
+    }
+
+    #[tokio::test]
+    async fn test_search_in_file_with_cancel_named_tempfile() -> Result<()> {
+        let pattern = "search_term";
+    
+        let mut temp_file = tempfile::NamedTempFile::new()?;
+    
+        use std::io::Write;
+        writeln!(
+            temp_file,
+            "This is a test file.\n\
+            This line contains the search_term.\n\
+            This line does not.\n\
+            Another line with search_term.\n"
+        )?;
+    
+        let temp_file_path = temp_file.path().to_path_buf();
+    
+        let cancel = CancellationToken::new();
+        let (result_tx, mut result_rx) = mpsc::channel(10);
+    
+        let handle = tokio::spawn(async move {
+            file_search(
+                temp_file_path.to_string_lossy().as_ref(),
+                pattern,
+                cancel,
+                result_tx,
+            ).await.unwrap();
+        });
+    
+        let mut results = Vec::new();
+        while let Some(result) = result_rx.recv().await {
+            results.push(result);
+        }
+    
+        handle.await?;
+    
+        println!("Results: {:?}", results);
+    
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].line, 1);
+        assert!(results[0].preview.contains(pattern));
+        assert_eq!(results[1].line, 3);
+        assert!(results[1].preview.contains(pattern));
+        
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_search_in_file_with_cancel_cancelled() -> Result<()> {
+
+        let pattern = "search_term";
+        let mut temp_file = tempfile::NamedTempFile::new()?;
+    
+        use std::io::Write;
+        writeln!(
+            temp_file,
+            "This is a test file.\n\
+            This line contains the search_term.\n\
+            This line does not.\n\
+            Another line with search_term.\n"
+        )?;
+    
+        let temp_file_path = temp_file.path().to_path_buf();
+        
+        let cancel = CancellationToken::new();
+        let (result_tx, mut result_rx) = mpsc::channel(10);
+
+        let cancel_clone = cancel.clone();
+        
+        // Spawn the function in a task
+        let handle = tokio::spawn(async move {
+            file_search(
+                temp_file_path.to_string_lossy().as_ref(),
+                pattern,
+                cancel_clone,
+                result_tx,
+            ).await.unwrap();
+        });
+
+        // Send cancellation signal after a short delay
+        tokio::spawn(async move {
+            // sleep(Duration::from_millis(10)).await; // Adjust the delay as needed
+            cancel.cancel();
+        });
+
+        // Collect results until cancellation
+        let mut results = Vec::new();
+        while let Some(result) = result_rx.recv().await {
+            results.push(result);
+        }
+
+        println!("Results len: {}", results.len());
+        println!("Results: {:?}", results);
+
+        // Assert that processing stopped before completing
+        // We expect 0 results to be returned.
+        assert!(results.is_empty());
+
+        // Ensure the search task completes
+        handle.await?;
+        
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_batch_search_with_cancel() -> Result<()> {
+        use tempfile::TempDir;
+
+        // Create a temporary directory for the test
+        let temp_dir = TempDir::new()?;
+        let dir_path = temp_dir.path().to_path_buf(); // Clone the path to allow it to live longer
+
+        // Create test files inside the temp directory
+        let file_1 = dir_path.join("file1.txt");
+        let file_2 = dir_path.join("file2.txt");
+
+        // Write some content to the files
+        std::fs::write(&file_1, "hello world\nюникод не помеха search_term here\nbye world")?;
+        std::fs::write(&file_2, "nothing to match\nno search term\nstill nothing")?;
+
+        // Create the cancellation token
+        let cancel = CancellationToken::new();
+
+        // Channel to collect results
+        let (result_tx, mut result_rx) = tokio::sync::mpsc::channel::<FileSearchResult>(100);
+
+        let cancel_clone = cancel.clone();
+        // Send cancellation signal after a short delay
+        tokio::spawn(async move {
+            // Adjust the delay as needed
+            // tokio::time::sleep(tokio::time::Duration::from_millis(1000)).await;
+            // cancel.cancel();
+        });
+
+        // Run batch search with a cancellation token
+        let pattern = "search_term";
+        tokio::spawn(async move {
+            let search_result = dir_search(
+                &dir_path, pattern, cancel_clone, result_tx
+            ).await;
+
+            if let Err(err) = search_result {
+                eprintln!("search failed: {}", err);
+            }
+        });
+
+        // Collect results
+        let mut collected_results = Vec::new();
+        while let Some(file_result) = result_rx.recv().await {
+            println!("Results for file: {}", file_result.file_path);
+            for result in &file_result.matches {
+                println!("  Line {}:{} {}", result.line, result.column, result.preview);
+            }
+            collected_results.push(file_result);
+        }
+    
+        // Assertions
+    
+        // We expect only one file (file1.txt) to contain matches
+        assert_eq!(collected_results.len(), 1, "Expected one file with matches");
+    
+        let file1_results = &collected_results[0];
+        assert!(file1_results.file_path.ends_with("file1.txt"), "Expected matches in file1.txt");
+    
+        // We expect at least one match in that file
+        assert!(!file1_results.matches.is_empty(), "Expected at least one match");
+    
+        // Check that all matches contain the search pattern in their preview
+        for search_result in &file1_results.matches {
+            assert!(search_result.preview.contains(pattern), "Preview should contain the pattern");
+        }
+
+        Ok(())
+    }
+
+    #[cfg(test)]
+    fn file_result(path: &str, matches: usize) -> FileSearchResult {
+        FileSearchResult {
+            file_path: path.to_string(),
+            matches: (0..matches)
+                .map(|line| SearchResult { line, column: 0, ..Default::default() })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_text_search() {
+        let result = text_search("a.rs", "let x = 1;\nx += x;\n", "x").unwrap();
+        let found: Vec<(usize, usize)> = result.matches.iter().map(|m| (m.line, m.column)).collect();
+        assert_eq!(found, [(0, 4), (1, 0), (1, 5)]);
+        assert!(text_search("a.rs", "let y = 1;", "x").is_none());
+    }
+
+    #[test]
+    fn test_symbol_search() {
+        let columns = |line: &str, symbol: &str| -> Vec<usize> {
+            symbol_line_search(line, symbol, 0).iter().map(|m| m.column).collect()
+        };
+        assert_eq!(columns("id = obj.id + idle + _id", "id"), [0, 9]);
+        assert_eq!(columns("call(x); recall(y)", "call("), [0]);
+        assert_eq!(columns("a->b a->bc", "->"), [1, 6]);
+        assert_eq!(columns("имя = имя2", "имя"), [0]);
+
+        let result = symbol_search("a.rs", "let count = 1;\naccount += count;\n", "count").unwrap();
+        let found: Vec<(usize, usize)> = result.matches.iter().map(|m| (m.line, m.column)).collect();
+        assert_eq!(found, [(0, 4), (1, 11)]);
+    }
+
+    #[test]
+    fn test_rank_results() {
+        let mut results = vec![
+            file_result("src/deep/nested/other.rs", 5),
+            file_result("src/config.rs", 1),
+            file_result("docs/config/readme.md", 2),
+            file_result("main.rs", 3),
+        ];
+
+        rank_results(&mut results, SearchOrder::Relevance, "Config");
+        let paths: Vec<&str> = results.iter().map(|r| r.file_path.as_str()).collect();
+        assert_eq!(paths, ["src/config.rs", "docs/config/readme.md", "main.rs", "src/deep/nested/other.rs"]);
+
+        rank_results(&mut results, SearchOrder::Matches, "config");
+        assert_eq!(results[0].file_path, "src/deep/nested/other.rs");
+    }
+
+    #[tokio::test]
+    async fn test_next_batch() {
+        let (tx, mut rx) = mpsc::channel(10);
+        tx.send(file_result("a.rs", 1)).await.unwrap();
+        tx.send(file_result("b.rs", 1)).await.unwrap();
+        drop(tx);
+
+        let batch = next_batch(&mut rx, std::time::Duration::from_millis(10)).await.unwrap();
+        assert_eq!(batch.len(), 2);
+        assert!(next_batch(&mut rx, std::time::Duration::from_millis(10)).await.is_none());
+    }
+}
@@ -0,0 +1,46 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+// A slow filesystem for performance tests of search and the walk, here
+// and in the crates using this one through the `slow-fs` feature.
+
+/// Latency added to each filesystem access of the instrumented paths, in
+/// microseconds. Global, so only the ignored tests set it.
+static LATENCY_US: AtomicU64 = AtomicU64::new(0);
+
+/// Slows the instrumented filesystem accesses down while alive
+pub struct SlowFs;
+
+impl SlowFs {
+    pub fn new(latency: Duration) -> Self {
+        LATENCY_US.store(latency.as_micros() as u64, Ordering::Relaxed);
+        SlowFs
+    }
+}
+
+impl Drop for SlowFs {
+    fn drop(&mut self) {
+        LATENCY_US.store(0, Ordering::Relaxed);
+    }
+}
+
+fn latency() -> Option<Duration> {
+    match LATENCY_US.load(Ordering::Relaxed) {
+        0 => None,
+        us => Some(Duration::from_micros(us)),
+    }
+}
+
+/// Hook of blocking filesystem accesses
+pub fn delay() {
+    if let Some(latency) = latency() {
+        std::thread::sleep(latency);
+    }
+}
+
+/// Hook of async filesystem accesses
+pub async fn delay_async() {
+    if let Some(latency) = latency() {
+        tokio::time::sleep(latency).await;
+    }
+}
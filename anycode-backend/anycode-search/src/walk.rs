@@ -0,0 +1,123 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+use crate::filter::CompiledFilter;
+use crate::ignore::{is_ignored_entry, is_ignored_path};
+
+/// Files below `dir_path`, skipping the ignored ones and the directories
+/// they are in. Blocking, run it off the async runtime for large trees.
+pub fn collect_files_recursively(dir_path: &Path) -> Result<Vec<PathBuf>> {
+    collect_files_to_depth(dir_path, None)
+}
+
+/// Like [`collect_files_recursively`], going down at most `max_depth`
+/// directories: 0 keeps the files of `dir_path` itself
+pub fn collect_files_to_depth(dir_path: &Path, max_depth: Option<usize>) -> Result<Vec<PathBuf>> {
+    let mut collected_files = Vec::new();
+    collect_files_inner(dir_path, dir_path, max_depth, None, &mut collected_files)?;
+    Ok(collected_files)
+}
+
+/// Like [`collect_files_recursively`], keeping the files `filter` matches
+/// and skipping the directories it excludes
+pub fn collect_files_filtered(dir_path: &Path, filter: &CompiledFilter) -> Result<Vec<PathBuf>> {
+    let mut collected_files = Vec::new();
+    collect_files_inner(dir_path, dir_path, None, Some(filter), &mut collected_files)?;
+    Ok(collected_files)
+}
+
+fn collect_files_inner(
+    root: &Path,
+    dir_path: &Path,
+    depth: Option<usize>,
+    filter: Option<&CompiledFilter>,
+    collected: &mut Vec<PathBuf>,
+) -> Result<()> {
+    if is_ignored_path(dir_path) {
+        return Ok(());
+    }
+
+    crate::fs_delay();
+
+    for entry_result in std::fs::read_dir(dir_path)? {
+        let entry = entry_result?;
+        let path = entry.path();
+        let is_dir = path.is_dir();
+
+        if is_ignored_entry(&path, is_dir) {
+            continue;
+        }
+        let relative = path.strip_prefix(root).unwrap_or(&path);
+
+        if is_dir {
+            if filter.is_some_and(|f| !f.walks_dir(relative)) {
+                continue;
+            }
+            match depth {
+                Some(0) => {}
+                Some(depth) => collect_files_inner(root, &path, Some(depth - 1), filter, collected)?,
+                None => collect_files_inner(root, &path, None, filter, collected)?,
+            }
+        } else if filter.is_none_or(|f| f.matches_file(relative)) {
+            collected.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod walk_tests {
+    use super::*;
+
+    #[test]
+    fn test_collect_files_to_depth() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir_all(dir.path().join("a/b"))?;
+        std::fs::write(dir.path().join("top.rs"), "")?;
+        std::fs::write(dir.path().join("a/one.rs"), "")?;
+        std::fs::write(dir.path().join("a/b/two.rs"), "")?;
+
+        let count = |depth| collect_files_to_depth(dir.path(), depth).map(|files| files.len());
+        assert_eq!(count(Some(0))?, 1);
+        assert_eq!(count(Some(1))?, 2);
+        assert_eq!(count(None)?, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_collect_files_filtered() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir_all(dir.path().join("src/gen"))?;
+        std::fs::write(dir.path().join("src/app.ts"), "")?;
+        std::fs::write(dir.path().join("src/app.rs"), "")?;
+        std::fs::write(dir.path().join("src/gen/api.ts"), "")?;
+
+        let filter = crate::filter::PathFilter {
+            include: vec!["*.ts".to_string()],
+            exclude: vec!["gen".to_string()],
+        }.compile()?;
+        let files = collect_files_filtered(dir.path(), &filter)?;
+        assert_eq!(files, [dir.path().join("src/app.ts")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_respects_gitignore() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir_all(dir.path().join(".git"))?;
+        std::fs::create_dir_all(dir.path().join("target/debug"))?;
+        std::fs::create_dir_all(dir.path().join(".github"))?;
+        std::fs::write(dir.path().join(".gitignore"), "target/\n")?;
+        std::fs::write(dir.path().join("target/debug/app.d"), "")?;
+        std::fs::write(dir.path().join(".github/ci.yml"), "")?;
+        std::fs::write(dir.path().join("main.rs"), "")?;
+
+        let mut names: Vec<String> = collect_files_recursively(dir.path())?.iter()
+            .map(|p| p.strip_prefix(dir.path()).unwrap().to_string_lossy().replace('\\', "/"))
+            .collect();
+        names.sort();
+        assert_eq!(names, [".github/ci.yml", ".gitignore", "main.rs"]);
+        Ok(())
+    }
+}
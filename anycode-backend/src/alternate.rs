@@ -0,0 +1,124 @@
+use regex::Regex;
+use std::path::{Path, PathBuf};
+
+use crate::config::AlternateRule;
+
+// Counterparts of files, a source and its test, by the `alternates` rules
+// of the file's language in config.toml. A rule pairs two paths relative
+// to the workspace root where `{dir}` stands for any directories, none
+// included, and `{name}` for a part of the file name, e.g.
+// `src/{dir}{name}.rs` and `tests/{dir}{name}.rs`. A file matching the
+// test path of a rule goes to its source, else one matching the source
+// path goes to its test. Of the rules that match, the first whose
+// counterpart exists wins, else the first one.
+
+/// A file's counterpart
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alternate {
+    pub path: PathBuf,
+    pub exists: bool,
+    /// Whether the counterpart is the test
+    pub is_test: bool,
+    /// Template to create it from, for a test
+    pub template: Option<String>,
+}
+
+fn pattern_regex(pattern: &str) -> Option<Regex> {
+    let mut regex = String::from("^");
+    let mut rest = pattern;
+    while let Some(start) = rest.find('{') {
+        regex.push_str(&regex::escape(&rest[..start]));
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("{dir}") {
+            regex.push_str("(?P<dir>(?:.*/)?)");
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("{name}") {
+            regex.push_str("(?P<name>[^/]+)");
+            rest = after;
+        } else {
+            regex.push_str(r"\{");
+            rest = &rest[1..];
+        }
+    }
+    regex.push_str(&regex::escape(rest));
+    regex.push('$');
+    Regex::new(&regex).ok()
+}
+
+/// `path` matched against the pattern `from`, filled into `to`
+fn counterpart(path: &str, from: &str, to: &str) -> Option<String> {
+    let captures = pattern_regex(from)?.captures(path)?;
+    let get = |name| captures.name(name).map_or("", |m| m.as_str());
+    let other = to.replace("{dir}", get("dir")).replace("{name}", get("name"));
+    (other != path).then_some(other)
+}
+
+/// Counterpart of `path` in the workspace `root`, None when no rule
+/// matches or the file is outside the workspace
+pub fn resolve(root: &Path, path: &Path, rules: &[AlternateRule]) -> Option<Alternate> {
+    let relative = path.strip_prefix(root).ok()?.to_string_lossy().replace('\\', "/");
+    let alternates: Vec<Alternate> = rules.iter()
+        .filter_map(|rule| match counterpart(&relative, &rule.test, &rule.source) {
+            Some(source) => Some((source, false, None)),
+            None => counterpart(&relative, &rule.source, &rule.test).map(|test| (test, true, rule.template.clone())),
+        })
+        .map(|(other, is_test, template)| {
+            let path = root.join(other);
+            Alternate { exists: path.is_file(), path, is_test, template }
+        })
+        .collect();
+    alternates.iter().find(|a| a.exists).or(alternates.first()).cloned()
+}
+
+#[cfg(test)]
+mod alternate_tests {
+    use super::*;
+
+    fn rule(source: &str, test: &str) -> AlternateRule {
+        AlternateRule { source: source.to_string(), test: test.to_string(), template: None }
+    }
+
+    #[test]
+    fn test_counterpart() {
+        let (source, test) = ("src/{dir}{name}.rs", "tests/{dir}{name}.rs");
+        assert_eq!(counterpart("src/foo.rs", source, test).as_deref(), Some("tests/foo.rs"));
+        assert_eq!(counterpart("src/a/b/foo.rs", source, test).as_deref(), Some("tests/a/b/foo.rs"));
+        assert_eq!(counterpart("tests/foo.rs", test, source).as_deref(), Some("src/foo.rs"));
+        assert_eq!(counterpart("lib/foo.rs", source, test), None);
+
+        let (source, test) = ("src/main/java/{dir}{name}.java", "src/test/java/{dir}{name}Test.java");
+        assert_eq!(
+            counterpart("src/main/java/com/acme/Foo.java", source, test).as_deref(),
+            Some("src/test/java/com/acme/FooTest.java"),
+        );
+        assert_eq!(counterpart("foo_test.go", "{dir}{name}_test.go", "{dir}{name}.go").as_deref(), Some("foo.go"));
+        // Braces other than the variables are literal
+        assert_eq!(counterpart("{x}.txt", "{x}.{name}", "{name}.md").as_deref(), Some("txt.md"));
+    }
+
+    #[test]
+    fn test_resolve() -> anyhow::Result<()> {
+        let root = tempfile::tempdir()?;
+        std::fs::create_dir_all(root.path().join("pkg"))?;
+        std::fs::create_dir_all(root.path().join("tests"))?;
+        std::fs::write(root.path().join("pkg/util.py"), "")?;
+        std::fs::write(root.path().join("tests/test_util.py"), "")?;
+        let rules = [rule("{dir}{name}.py", "{dir}test_{name}.py"), rule("{dir}{name}.py", "tests/test_{name}.py")];
+
+        // The second rule's test exists
+        let alternate = resolve(root.path(), &root.path().join("pkg/util.py"), &rules).unwrap();
+        assert_eq!(alternate.path, root.path().join("tests/test_util.py"));
+        assert!(alternate.exists && alternate.is_test);
+
+        // A test is not taken for a source, none exists so the first wins
+        let alternate = resolve(root.path(), &root.path().join("tests/test_util.py"), &rules).unwrap();
+        assert_eq!(alternate.path, root.path().join("tests/util.py"));
+        assert!(!alternate.exists && !alternate.is_test);
+
+        let alternate = resolve(root.path(), &root.path().join("main.py"), &rules).unwrap();
+        assert_eq!(alternate.path, root.path().join("test_main.py"));
+        assert!(resolve(root.path(), Path::new("/elsewhere/main.py"), &rules).is_none());
+        assert!(resolve(root.path(), &root.path().join("main.rs"), &rules).is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,273 @@
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{header, HeaderValue, Request, StatusCode},
+    response::{sse::{Event, Sse}, IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::json;
+use socketioxide::SocketIo;
+use std::{convert::Infallible, sync::Arc};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::CancellationToken;
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
+use tracing::{error, info};
+
+use anycode_search::ignore::is_ignored_dir;
+
+use crate::app_state::AppState;
+use crate::config::{AuthConfig, Scope};
+use crate::file_probe::NotText;
+use crate::permissions::guard_routes;
+use crate::search::{next_batch, rank_results, PathFilter, SearchOrder};
+use crate::services;
+use crate::timing::EventTimer;
+
+/// REST mirror of the core socket operations for integrations that can't
+/// speak socket.io. Mounted under /api/v1 next to the socket.io layer, the
+/// routes need the scopes of the events they mirror, see
+/// `permissions::guard_routes`.
+#[derive(Clone)]
+struct ApiState {
+    app: AppState,
+    io: Arc<SocketIo>,
+}
+
+pub fn router(app: AppState, io: Arc<SocketIo>) -> Router {
+    let auth = app.config.auth.clone();
+    let read = Router::new()
+        .route("/file", get(open_file))
+        .route("/dir", get(list_dir))
+        .route("/search", get(search))
+        .route("/exports/{name}", get(download_export));
+    let write = Router::new()
+        .route("/file/save", post(save_file));
+
+    guard_routes(read, auth.clone(), &[Scope::FsRead])
+        .merge(guard_routes(write, auth, &[Scope::FsWrite]))
+        .with_state(ApiState { app, io })
+}
+
+/// `/healthz` for load balancers and uptime checks, served at the root
+pub fn health_router(app: AppState, io: Arc<SocketIo>) -> Router {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .with_state(ApiState { app, io })
+}
+
+/// `/raw/{path}`, workspace files as they are on disk with their mime
+/// type, for the previews of images, PDFs and other binaries. Paths are
+/// relative to the primary root or absolute inside a root, files in
+/// ignored directories are not served.
+pub fn raw_router(auth: Option<AuthConfig>) -> Router {
+    guard_routes(Router::new().route("/raw/{*path}", get(raw_file)), auth, &[Scope::FsRead])
+}
+
+async fn raw_file(Path(path): Path<String>, request: Request<Body>) -> Response {
+    info!("Received GET /raw/{}", path);
+    let _timer = EventTimer::start("api:raw");
+
+    let file = match crate::roots::resolve(&path) {
+        Ok(file) if file.is_file() && !file.parent().is_some_and(is_ignored_dir) => file,
+        Ok(_) => return error_response(StatusCode::NOT_FOUND, &path, anyhow::anyhow!("No file {}", path)),
+        Err(e) => return error_response(StatusCode::NOT_FOUND, &path, e),
+    };
+    match ServeFile::new(file).oneshot(request).await {
+        Ok(response) => {
+            let mut response = response.into_response();
+            // Served from the origin of the app, keep documents like SVG
+            // and HTML from running scripts
+            let headers = response.headers_mut();
+            headers.insert(header::CONTENT_SECURITY_POLICY, HeaderValue::from_static("sandbox"));
+            headers.insert(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+            response
+        }
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, &path, e.into()),
+    }
+}
+
+async fn healthz(State(state): State<ApiState>) -> Response {
+    let status = crate::status::collect(&state.app, &state.io).await;
+    Json(json!({ "status": "ok", "server": status })).into_response()
+}
+
+fn error_response(status: StatusCode, path: &str, e: anyhow::Error) -> Response {
+    error!("{}", e);
+    let body = json!({ "error": e.to_string(), "path": path, "success": false });
+    (status, Json(body)).into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct PathQuery {
+    path: String,
+}
+
+async fn open_file(State(state): State<ApiState>, Query(query): Query<PathQuery>) -> Response {
+    info!("Received GET /api/v1/file: {}", query.path);
+    let mut timer = EventTimer::start("api:file:open");
+
+    let file = match services::load_file(&state.app, &mut timer, &query.path, false).await {
+        Ok(f) => f,
+        Err(e) => match e.downcast_ref::<NotText>() {
+            Some(not_text) => {
+                let body = not_text.response(&query.path);
+                return (StatusCode::UNSUPPORTED_MEDIA_TYPE, Json(body)).into_response();
+            }
+            None => return error_response(StatusCode::NOT_FOUND, &query.path, e),
+        },
+    };
+
+    services::lsp_did_open(&state.app, &mut timer, &file).await;
+
+    Json(json!({
+        "content": file.content, "path": query.path, "read_only": file.read_only, "success": true
+    })).into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct SaveRequest {
+    path: String,
+    /// Replace the file content before saving, like file:set
+    text: Option<String>,
+    /// Overwrite even if the file changed on disk
+    force: Option<bool>,
+}
+
+async fn save_file(State(state): State<ApiState>, Json(request): Json<SaveRequest>) -> Response {
+    info!("Received POST /api/v1/file/save: {}", request.path);
+    let mut timer = EventTimer::start("api:file:save");
+
+    let saved = match &request.text {
+        Some(text) => services::set_file(&state.app, &mut timer, &request.path, text).await,
+        None => {
+            let force = request.force.unwrap_or(false);
+            services::save_file(&state.app, &mut timer, &request.path, force).await
+        }
+    };
+
+    let abs_path = match saved {
+        Ok(p) => p,
+        Err(e) if e.is::<services::SaveConflict>() => {
+            return error_response(StatusCode::CONFLICT, &request.path, e)
+        }
+        Err(e) => return error_response(StatusCode::BAD_REQUEST, &request.path, e),
+    };
+
+    if let Some(text) = request.text {
+        state.io.emit("file:changed", &(abs_path.clone(), text)).await.ok();
+    }
+
+    Json(json!({ "success": true, "file": abs_path })).into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct DirQuery {
+    path: String,
+    limit: Option<usize>,
+    cursor: Option<String>,
+}
+
+async fn list_dir(State(state): State<ApiState>, Query(query): Query<DirQuery>) -> Response {
+    info!("Received GET /api/v1/dir: {}", query.path);
+    let _timer = EventTimer::start("api:dir:list");
+
+    let limit = services::dir_limit(&state.app.config, query.limit);
+    match services::list_dir(&crate::roots::primary(), &query.path, limit, query.cursor.as_deref()) {
+        Ok(listing) => Json(listing).into_response(),
+        Err(e) => error_response(StatusCode::NOT_FOUND, &query.path, e),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchQuery {
+    pattern: String,
+    #[serde(default)]
+    order: SearchOrder,
+}
+
+/// Streams `result` events per file and a final `end` (or `error`) event.
+/// The search is cancelled when the client goes away.
+async fn search(Query(query): Query<SearchQuery>) -> Sse<ReceiverStream<Result<Event, Infallible>>> {
+    info!("Received GET /api/v1/search: {}", query.pattern);
+
+    let cancel = CancellationToken::new();
+    let order = query.order;
+    let pattern = query.pattern.clone();
+    let (mut result_rx, search) = services::start_search(
+        &crate::roots::primary(), query.pattern, PathFilter::default(), cancel.clone(),
+    );
+    let (event_tx, event_rx) = mpsc::channel::<Result<Event, Infallible>>(100);
+
+    let start = std::time::Instant::now();
+
+    tokio::spawn(async move {
+        let mut matches = 0;
+        while let Some(mut batch) = next_batch(&mut result_rx, order.window()).await {
+            rank_results(&mut batch, order, &pattern);
+            for file_result in batch {
+                matches += file_result.matches.len();
+                let event = Event::default().event("result").json_data(&file_result);
+                if let Ok(event) = event
+                    && event_tx.send(Ok(event)).await.is_err()
+                {
+                    cancel.cancel();
+                    return;
+                }
+            }
+        }
+
+        let last = match search.await {
+            Ok(Err(err)) => Event::default().event("error")
+                .json_data(json!({ "error": "Search failed", "message": err.to_string() })),
+            _ => Event::default().event("end")
+                .json_data(json!({ "elapsed": start.elapsed().as_millis(), "matches": matches })),
+        };
+        if let Ok(event) = last {
+            let _ = event_tx.send(Ok(event)).await;
+        }
+    });
+
+    Sse::new(ReceiverStream::new(event_rx))
+}
+
+/// Search exports written by search:export. Served with range support so
+/// interrupted downloads of large exports resume where they stopped.
+async fn download_export(Path(name): Path<String>, request: Request<Body>) -> Response {
+    info!("Received GET /api/v1/exports/{}", name);
+
+    let Some(file) = crate::search_export::export_file(&name) else {
+        return error_response(StatusCode::NOT_FOUND, &name, anyhow::anyhow!("No export {}", name));
+    };
+    match ServeFile::new(file).oneshot(request).await {
+        Ok(response) => response.into_response(),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, &name, e.into()),
+    }
+}
+
+#[cfg(test)]
+mod api_tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_raw_file() -> anyhow::Result<()> {
+        // Relative paths are taken from the primary root, the current directory
+        let dir = tempfile::Builder::new().prefix("raw").tempdir_in(crate::roots::primary())?;
+        std::fs::create_dir_all(dir.path().join("img"))?;
+        std::fs::write(dir.path().join("img/logo.png"), b"\x89PNG\r\n\x1a\n")?;
+        let name = dir.path().file_name().unwrap().to_string_lossy().to_string();
+        let get = |uri: String| async move {
+            raw_router(None).oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap()
+        };
+
+        let response = get(format!("/raw/{}/img/logo.png", name)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+        assert_eq!(get(format!("/raw/{}/img/missing.png", name)).await.status(), StatusCode::NOT_FOUND);
+        assert_eq!(get(format!("/raw/{}/../../etc/passwd", name)).await.status(), StatusCode::NOT_FOUND);
+        Ok(())
+    }
+}
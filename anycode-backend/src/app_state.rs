@@ -7,8 +7,13 @@ use socketioxide::{extract::SocketRef};
 use std::collections::HashSet;
 use tokio_util::sync::CancellationToken;
 use crate::terminal::Terminal;
+use crate::recording::Recording;
+use crate::scrollback::Scrollback;
+use crate::notifier::Notifier;
+use crate::recent::RecentFiles;
 use std::collections::hash_map::{HashMap, Entry};
 use anyhow::{Result, anyhow};
+use lsp_types::PublishDiagnosticsParams;
 
 
 #[derive(Clone)]
@@ -18,12 +23,24 @@ pub struct AppState {
     pub lsp_manager: Arc<Mutex<LspManager>>,
     pub socket2data: Arc<Mutex<HashMap<String, SocketData>>>,
     pub terminals: Arc<Mutex<HashMap<String, TerminalData>>>,
+    pub notifier: Arc<Notifier>,
+    /// Latest published diagnostics per document path, for clients that
+    /// reconnect after the LSP has already published them.
+    pub diagnostics: Arc<Mutex<HashMap<String, PublishDiagnosticsParams>>>,
+    pub recent: Arc<Mutex<RecentFiles>>,
 }
 
 #[derive(Clone, Default)]
 pub struct SocketData {
     pub opened_files: HashSet<String>,
     pub search_cancel: Option<CancellationToken>,
+    /// Asks the last workspace search for its next page, see search:more
+    pub search_more: Option<tokio::sync::mpsc::Sender<usize>>,
+    /// Root opened with `workspace:open`, the primary root when unset
+    pub root: Option<std::path::PathBuf>,
+    /// Scopes of the connection, for handlers whose requests need more
+    /// than their event does, see permissions.rs
+    pub scopes: Vec<crate::config::Scope>,
 }
 
 #[derive(Clone)]
@@ -31,6 +48,16 @@ pub struct TerminalData {
     pub terminal: Arc<Terminal>,
     pub sockets: Arc<Mutex<Vec<SocketRef>>>,
     pub buffer: Arc<Mutex<VecDeque<String>>>,
+    pub scrollback: Arc<Mutex<Scrollback>>,
+    pub recording: Arc<Mutex<Recording>>,
+}
+
+impl TerminalData {
+    /// Whether the socket is the one the terminal is attached to, the
+    /// last to start or reconnect to it. Only it types into the terminal.
+    pub async fn is_attached(&self, socket: &SocketRef) -> bool {
+        self.sockets.lock().await.iter().any(|s| s.id == socket.id)
+    }
 }
 
 
@@ -58,4 +85,19 @@ pub fn get_or_create_code<'a>(
             Ok(v.insert(c))
         }
     }
-}
\ No newline at end of file
+}
+/// State of a backend with the default config and no running servers
+#[cfg(test)]
+pub fn test_state() -> AppState {
+    let config = Config::default();
+    AppState {
+        lsp_manager: Arc::new(Mutex::new(LspManager::new(config.clone()))),
+        notifier: Arc::new(Notifier::new(None)),
+        config,
+        file2code: Arc::new(Mutex::new(HashMap::new())),
+        socket2data: Arc::new(Mutex::new(HashMap::new())),
+        terminals: Arc::new(Mutex::new(HashMap::new())),
+        diagnostics: Arc::new(Mutex::new(HashMap::new())),
+        recent: Arc::new(Mutex::new(RecentFiles::default())),
+    }
+}
@@ -0,0 +1,293 @@
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::io::AsyncReadExt;
+use tokio_util::sync::CancellationToken;
+
+use crate::store::WORKSPACE_DIR;
+
+// Onboarding of an empty workspace: `workspace:bootstrap` tells whether
+// the root is empty and runs what the empty state offers on the server,
+// cloning a git repository with its progress streamed, scaffolding a
+// project or opening another folder. Clones run the git command, so its
+// credential helpers and ssh keys apply, into a staging directory under
+// .anycode that is moved in place once complete. Running git is why a
+// clone needs the exec scope next to fs:write, see permissions.rs.
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Scaffold {
+    Rust,
+    Node,
+    Python,
+    Go,
+}
+
+impl Scaffold {
+    pub const ALL: [Scaffold; 4] = [Scaffold::Rust, Scaffold::Node, Scaffold::Python, Scaffold::Go];
+
+    /// Files of the project and their templates, `${package}` is the
+    /// project name as a package name
+    fn files(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Scaffold::Rust => &[
+                ("Cargo.toml", "[package]\nname = \"${package}\"\nversion = \"0.1.0\"\nedition = \"2024\"\n\n[dependencies]\n"),
+                ("src/main.rs", "fn main() {\n    println!(\"Hello, world!\");\n}\n"),
+                (".gitignore", "/target\n"),
+            ],
+            Scaffold::Node => &[
+                ("package.json", concat!(
+                    "{\n  \"name\": \"${package}\",\n  \"version\": \"0.1.0\",\n  \"type\": \"module\",\n",
+                    "  \"main\": \"index.js\",\n  \"scripts\": {\n    \"start\": \"node index.js\"\n  }\n}\n",
+                )),
+                ("index.js", "console.log(\"Hello, world!\");\n"),
+                (".gitignore", "node_modules/\n"),
+            ],
+            Scaffold::Python => &[
+                ("pyproject.toml", "[project]\nname = \"${package}\"\nversion = \"0.1.0\"\nrequires-python = \">=3.9\"\ndependencies = []\n"),
+                ("main.py", "def main():\n    print(\"Hello, world!\")\n\n\nif __name__ == \"__main__\":\n    main()\n"),
+                (".gitignore", "__pycache__/\n.venv/\n"),
+            ],
+            Scaffold::Go => &[
+                ("go.mod", "module ${package}\n\ngo 1.22\n"),
+                ("main.go", "package main\n\nimport \"fmt\"\n\nfunc main() {\n\tfmt.Println(\"Hello, world!\")\n}\n"),
+            ],
+        }
+    }
+}
+
+/// Whether `root` has nothing but the workspace state directory
+pub fn is_empty(root: &Path) -> bool {
+    std::fs::read_dir(root).is_ok_and(|mut entries| {
+        entries.all(|entry| entry.is_ok_and(|e| e.file_name() == WORKSPACE_DIR))
+    })
+}
+
+/// Where a project goes: the subdirectory `dir` of `root`, which must not
+/// exist yet, else `root` itself, which must be empty
+pub fn destination(root: &Path, dir: Option<&str>) -> Result<PathBuf> {
+    match dir.map(str::trim).filter(|d| !d.is_empty()) {
+        Some(dir) => {
+            let plain = Path::new(dir).file_name().is_some_and(|name| name == dir);
+            if !plain || dir == WORKSPACE_DIR {
+                bail!("{} is not a plain directory name", dir);
+            }
+            let dest = root.join(dir);
+            if dest.exists() {
+                bail!("{} already exists", dest.display());
+            }
+            Ok(dest)
+        }
+        None if is_empty(root) => Ok(root.to_path_buf()),
+        None => bail!("{} is not empty", root.display()),
+    }
+}
+
+/// Name of the directory as a package name: lowercase, other characters
+/// than letters, digits, `-` and `_` replaced by `-`
+fn package_name(dir: &Path) -> String {
+    let name: String = dir.file_name().unwrap_or_default().to_string_lossy()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c.to_ascii_lowercase() } else { '-' })
+        .collect();
+    match name.trim_matches('-') {
+        "" => "app".to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// Write the files of a `kind` project to `dest`, see `destination`.
+/// Returns the written files.
+pub async fn scaffold(dest: &Path, kind: Scaffold) -> Result<Vec<PathBuf>> {
+    let mut written = Vec::new();
+    for (name, template) in kind.files() {
+        let path = dest.join(name);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut vars: HashMap<String, String> = crate::template::variables(dest, &path, "").await;
+        vars.insert("package".to_string(), package_name(dest));
+        std::fs::OpenOptions::new().write(true).create_new(true).open(&path)
+            .and_then(|mut file| std::io::Write::write_all(&mut file, crate::template::render(template, &vars).as_bytes()))
+            .map_err(|e| anyhow!("Failed to write {}: {}", path.display(), e))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// A progress line of `git clone`
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct CloneProgress {
+    /// What git is doing, e.g. `Receiving objects`
+    pub stage: String,
+    pub percent: Option<u8>,
+    pub line: String,
+}
+
+fn parse_progress(line: &str) -> Option<CloneProgress> {
+    let text = line.strip_prefix("remote:").map(str::trim).unwrap_or(line);
+    let (stage, rest) = text.split_once(": ")?;
+    let percent = rest.split_once('%').and_then(|(percent, _)| percent.trim().parse().ok());
+    Some(CloneProgress { stage: stage.to_string(), percent, line: line.to_string() })
+}
+
+/// Clone `url` to `dest` of the workspace `root`, see `destination`.
+/// `on_progress` gets the progress lines of git. Nothing is left behind
+/// when the clone fails or `cancel` fires.
+pub async fn clone(
+    root: &Path, dest: &Path, url: &str, cancel: CancellationToken, on_progress: impl FnMut(CloneProgress),
+) -> Result<()> {
+    if url.trim().is_empty() || url.starts_with('-') {
+        bail!("Invalid repository url {}", url);
+    }
+    let staging = root.join(WORKSPACE_DIR).join(format!("clone-{}", chrono::Utc::now().timestamp_millis()));
+    std::fs::create_dir_all(root.join(WORKSPACE_DIR))?;
+
+    let result = match run_clone(url, &staging, cancel, on_progress).await {
+        Ok(()) => move_clone(&staging, dest),
+        Err(e) => Err(e),
+    };
+    if staging.exists() {
+        let _ = std::fs::remove_dir_all(&staging);
+    }
+    result
+}
+
+async fn run_clone(url: &str, staging: &Path, cancel: CancellationToken, mut on_progress: impl FnMut(CloneProgress)) -> Result<()> {
+    let mut child = tokio::process::Command::new("git")
+        .args(["clone", "--progress", "--"])
+        .arg(url)
+        .arg(staging)
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| anyhow!("Failed to run git: {}", e))?;
+    let mut stderr = child.stderr.take().ok_or_else(|| anyhow!("No output of git"))?;
+
+    // git redraws its progress with \r
+    let (mut pending, mut buf, mut last) = (Vec::new(), [0u8; 4096], String::new());
+    loop {
+        let read = tokio::select! {
+            read = stderr.read(&mut buf) => read?,
+            _ = cancel.cancelled() => {
+                let _ = child.kill().await;
+                bail!("Clone of {} cancelled", url);
+            }
+        };
+        if read == 0 {
+            break;
+        }
+        pending.extend_from_slice(&buf[..read]);
+        while let Some(end) = pending.iter().position(|b| *b == b'\r' || *b == b'\n') {
+            let line: Vec<u8> = pending.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line).trim().to_string();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(progress) = parse_progress(&line) {
+                on_progress(progress);
+            }
+            last = line;
+        }
+    }
+
+    let status = child.wait().await?;
+    if !status.success() {
+        bail!("git clone failed: {}", last);
+    }
+    Ok(())
+}
+
+/// Move a complete clone from the staging directory to `dest`, into the
+/// root when it is `dest` itself. Nothing is moved when a name of the
+/// clone is taken in `dest`, .anycode of a repository that has one too.
+fn move_clone(staging: &Path, dest: &Path) -> Result<()> {
+    if !dest.exists() {
+        std::fs::rename(staging, dest)?;
+        return Ok(());
+    }
+    let names = std::fs::read_dir(staging)?
+        .map(|entry| entry.map(|e| e.file_name()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    let taken: Vec<_> = names.iter()
+        .filter(|name| dest.join(name).symlink_metadata().is_ok())
+        .map(|name| name.to_string_lossy())
+        .collect();
+    if !taken.is_empty() {
+        bail!("The clone has {} that {} has already", taken.join(", "), dest.display());
+    }
+    for name in &names {
+        std::fs::rename(staging.join(name), dest.join(name))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod bootstrap_tests {
+    use super::*;
+
+    #[test]
+    fn test_destination() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        std::fs::create_dir(root.path().join(WORKSPACE_DIR))?;
+        assert!(is_empty(root.path()));
+        assert_eq!(destination(root.path(), None)?, root.path());
+
+        std::fs::write(root.path().join("notes.txt"), "")?;
+        assert!(!is_empty(root.path()));
+        assert!(destination(root.path(), None).is_err());
+        assert_eq!(destination(root.path(), Some("app"))?, root.path().join("app"));
+        assert!(destination(root.path(), Some("notes.txt")).is_err());
+        assert!(destination(root.path(), Some("../elsewhere")).is_err());
+        assert!(destination(root.path(), Some(WORKSPACE_DIR)).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_scaffold() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        let dest = root.path().join("My App");
+        let files = scaffold(&dest, Scaffold::Rust).await?;
+        assert_eq!(files.len(), 3);
+        let manifest = std::fs::read_to_string(dest.join("Cargo.toml"))?;
+        assert!(manifest.contains("name = \"my-app\""));
+        assert!(dest.join("src/main.rs").is_file());
+
+        // Existing files are not overwritten
+        assert!(scaffold(&dest, Scaffold::Rust).await.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_move_clone() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        let staging = root.path().join(WORKSPACE_DIR).join("clone-1");
+        std::fs::create_dir_all(staging.join(WORKSPACE_DIR))?;
+        std::fs::write(staging.join("README.md"), "")?;
+
+        // The repository's .anycode is in the way of the root's
+        assert!(move_clone(&staging, root.path()).is_err());
+        assert!(staging.join("README.md").is_file());
+        assert!(!root.path().join("README.md").exists());
+
+        std::fs::remove_dir(staging.join(WORKSPACE_DIR))?;
+        move_clone(&staging, root.path())?;
+        assert!(root.path().join("README.md").is_file());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_progress() {
+        let progress = parse_progress("Receiving objects:  45% (45/100), 1.20 MiB | 2.00 MiB/s").unwrap();
+        assert_eq!((progress.stage.as_str(), progress.percent), ("Receiving objects", Some(45)));
+        let remote = parse_progress("remote: Counting objects: 100% (10/10), done.").unwrap();
+        assert_eq!((remote.stage.as_str(), remote.percent), ("Counting objects", Some(100)));
+        assert_eq!(parse_progress("remote: Enumerating objects: 10, done.").unwrap().percent, None);
+        assert!(parse_progress("Cloning into 'x'...").is_none());
+    }
+}
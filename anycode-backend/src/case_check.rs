@@ -0,0 +1,115 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+// Names that differ only in case. Where the file system ignores case
+// (macOS, Windows) buffers of `Foo.rs` and `foo.rs` are one file edited
+// twice, which desyncs the language servers; elsewhere two such workspace
+// files can't both be checked out on those systems. The issues are
+// reported like diagnostics, with a severity and a message.
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CaseIssueKind {
+    /// Open buffers of the same file
+    Buffers,
+    /// Workspace files
+    Files,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct CaseIssue {
+    pub kind: CaseIssueKind,
+    pub severity: Severity,
+    pub message: String,
+    pub paths: Vec<String>,
+}
+
+/// Groups of the paths equal but for case, sorted
+pub fn collisions<'a>(paths: impl IntoIterator<Item = &'a str>) -> Vec<Vec<String>> {
+    let mut by_folded: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for path in paths {
+        by_folded.entry(path.to_lowercase()).or_default().push(path.to_string());
+    }
+    by_folded.into_values()
+        .filter_map(|mut group| {
+            group.sort();
+            group.dedup();
+            (group.len() > 1).then_some(group)
+        })
+        .collect()
+}
+
+/// Issues of the open buffers and the workspace files, on a file system
+/// that ignores case or not
+pub fn check(buffers: &[String], files: &[String], case_insensitive: bool) -> Vec<CaseIssue> {
+    let mut issues = Vec::new();
+    if case_insensitive {
+        for paths in collisions(buffers.iter().map(String::as_str)) {
+            issues.push(CaseIssue {
+                kind: CaseIssueKind::Buffers,
+                severity: Severity::Error,
+                message: format!(
+                    "{} is open {} times under names differing in case, edits and language servers may get out of sync",
+                    paths[0], paths.len(),
+                ),
+                paths,
+            });
+        }
+    }
+    for paths in collisions(files.iter().map(String::as_str)) {
+        issues.push(CaseIssue {
+            kind: CaseIssueKind::Files,
+            severity: Severity::Warning,
+            message: format!("{} differ only in case, only one of them can exist on macOS and Windows", paths.join(", ")),
+            paths,
+        });
+    }
+    issues
+}
+
+/// Log the issues and list them in the server output
+pub fn warn(issues: &[CaseIssue]) {
+    for issue in issues {
+        tracing::warn!("{}", issue.message);
+        crate::output::write("server", &issue.message);
+    }
+}
+
+#[cfg(test)]
+mod case_check_tests {
+    use super::*;
+
+    fn paths(paths: &[&str]) -> Vec<String> {
+        paths.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn test_collisions() {
+        let groups = collisions(["src/Foo.rs", "src/foo.rs", "src/bar.rs", "SRC/FOO.RS", "src/foo.rs"]);
+        assert_eq!(groups, [paths(&["SRC/FOO.RS", "src/Foo.rs", "src/foo.rs"])]);
+        assert!(collisions(["a.rs", "b.rs"]).is_empty());
+    }
+
+    #[test]
+    fn test_check() {
+        let buffers = paths(&["/w/src/Foo.rs", "/w/src/foo.rs"]);
+        let files = paths(&["README.md", "readme.md", "src/foo.rs"]);
+
+        let issues = check(&buffers, &files, true);
+        assert_eq!(issues.len(), 2);
+        assert_eq!((issues[0].kind, issues[0].severity), (CaseIssueKind::Buffers, Severity::Error));
+        assert_eq!(issues[1].paths, ["README.md", "readme.md"]);
+
+        // Buffers of distinct files where case matters
+        let issues = check(&buffers, &files, false);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].kind, CaseIssueKind::Files);
+    }
+}
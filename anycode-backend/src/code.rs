@@ -3,6 +3,7 @@ use std::fs;
 use std::fs::File;
 use std::io::{BufReader, BufWriter};
 use std::path::Path;
+use std::time::SystemTime;
 
 use crate::config::{Config};
 use crate::utils::{self};
@@ -41,14 +42,63 @@ pub struct Position {
 }
 
 
+/// Language id of a file, matching the `name` of the configured
+/// languages, see lang_rules for the workspace overrides
+pub fn lang_of(path: &str, conf: &Config) -> String {
+    crate::lang_rules::detect(path, conf).lang
+}
+
+/// What the buffer last saw on disk. Lets the watcher and save tell our
+/// own writes apart from an external writer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileStamp {
+    pub mtime: Option<SystemTime>,
+    pub len: u64,
+    pub inode: u64,
+    pub hash: crate::file_hash::Hash,
+}
+
+impl FileStamp {
+    fn new(meta: &fs::Metadata, hash: crate::file_hash::Hash) -> Self {
+        Self {
+            mtime: meta.modified().ok(),
+            len: meta.len(),
+            inode: inode(meta),
+            hash,
+        }
+    }
+
+    /// Stamp of the file as it is now, hashed by the file hash service
+    pub fn read(path: &str) -> std::io::Result<Self> {
+        let meta = fs::metadata(path)?;
+        Ok(Self::new(&meta, crate::file_hash::hash(Path::new(path))?))
+    }
+}
+
+#[cfg(unix)]
+pub fn inode(meta: &fs::Metadata) -> u64 {
+    std::os::unix::fs::MetadataExt::ino(meta)
+}
+
+#[cfg(not(unix))]
+pub fn inode(_meta: &fs::Metadata) -> u64 {
+    0
+}
+
 pub struct Code {
     pub file_name: String,
     pub abs_path: String,
     pub lang: String,
     pub text: ropey::Rope,
     pub changed: bool,
+    pub stamp: Option<FileStamp>,
+    /// Content of the last load or save, the base of the dirty diff
+    pub saved: Option<Rope>,
     pub undo_history: Vec<Change>,
     pub redo_history: Vec<Change>,
+    /// Moved on by every change of the text, see revision
+    pub version: u64,
+    pub revisions: crate::revision::Log,
 }
 
 impl Code {
@@ -58,9 +108,13 @@ impl Code {
             file_name: String::new(),
             abs_path: String::new(),
             changed: false,
+            stamp: None,
+            saved: None,
             lang: String::new(),
             undo_history: Vec::new(),
             redo_history: Vec::new(),
+            version: 0,
+            revisions: crate::revision::Log::default(),
         }
     }
 
@@ -71,29 +125,41 @@ impl Code {
     }
 
     pub fn from_file(path: &str, conf: &Config) -> std::io::Result<Self> {
-        let file = File::open(path)?;
-        let text = Rope::from_reader(BufReader::new(file))?;
+        Self::read(path, conf, false)
+    }
+
+    /// A file that is not UTF-8 converted as `file_probe::decode` does, it
+    /// is saved as UTF-8
+    pub fn from_file_converted(path: &str, conf: &Config) -> std::io::Result<Self> {
+        Self::read(path, conf, true)
+    }
+
+    fn read(path: &str, conf: &Config, convert: bool) -> std::io::Result<Self> {
+        let bytes = fs::read(path)?;
+        let text = match convert {
+            true => Rope::from_str(&crate::file_probe::decode(&bytes)),
+            false => Rope::from_reader(&bytes[..])?,
+        };
+        let meta = fs::metadata(path)?;
+        let stamp = Some(FileStamp::new(&meta, crate::file_hash::remember(Path::new(path), &meta, &bytes)));
         let abs_path = utils::abs_file(path)
             .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
         let file_name = utils::get_file_name(path);
 
-        let lang = detect_lang::from_path(path)
-            .map(|lang| lang.id().to_lowercase())
-            .unwrap_or_else(|| {
-                conf.language.iter()
-                    .find(|l| l.types.iter().any(|t| path.ends_with(t)))
-                    .map(|lang| lang.name.clone())
-                    .unwrap_or_else(|| "text".to_string())
-            });
+        let lang = lang_of(path, conf);
 
         Ok(Self {
+            saved: Some(text.clone()),
             text,
             file_name,
             abs_path,
             changed: false,
+            stamp,
             lang,
             undo_history: Vec::new(),
             redo_history: Vec::new(),
+            version: 0,
+            revisions: crate::revision::Log::default(),
         })
     }
 
@@ -102,6 +168,22 @@ impl Code {
         self.text = Rope::new();
         self.text.insert(0, text);
         self.changed = true;
+        self.replaced();
+    }
+
+    /// The edits of a change were applied
+    pub fn applied(&mut self, edits: &[crate::services::Edit]) {
+        self.version += 1;
+        self.revisions.push(edits);
+    }
+
+    /// The whole text was replaced, older versions can't be rebased
+    fn replaced(&mut self) {
+        self.version += 1;
+        self.revisions.clear();
+        if crate::collab::has_session(&self.abs_path) {
+            crate::collab::reset(&self.abs_path, &self.text.to_string(), self.version, None);
+        }
     }
 
     pub fn save_file(&mut self) -> std::io::Result<()> {
@@ -112,9 +194,42 @@ impl Code {
         let file = File::create(&self.abs_path)?;
         let saved = self.text.write_to(BufWriter::new(file));
         self.changed = false;
+        self.stamp = FileStamp::read(&self.abs_path).ok();
+        if saved.is_ok() {
+            self.saved = Some(self.text.clone());
+        }
         saved
     }
 
+    /// Line changes against the last loaded or saved content, a buffer
+    /// never loaded from disk compares with an empty file
+    pub fn dirty_diff(&self) -> Vec<crate::dirty_diff::LineChange> {
+        if !self.changed {
+            return Vec::new();
+        }
+        let saved = self.saved.as_ref().map(|s| s.to_string()).unwrap_or_default();
+        crate::dirty_diff::line_changes(&saved, &self.text.to_string())
+    }
+
+    /// True when the file on disk is not what this buffer last loaded or
+    /// saved. A touch without content change does not count.
+    pub fn disk_changed(&self) -> bool {
+        let Some(stamp) = &self.stamp else { return false };
+
+        let meta = match fs::metadata(&self.abs_path) {
+            Ok(meta) => meta,
+            Err(_) => return true,
+        };
+        if meta.modified().ok() == stamp.mtime && meta.len() == stamp.len && inode(&meta) == stamp.inode {
+            return false;
+        }
+
+        match FileStamp::read(&self.abs_path) {
+            Ok(disk) => disk.hash != stamp.hash,
+            Err(_) => true,
+        }
+    }
+
     pub fn set_file_name(&mut self, file_name: String) {
         self.file_name = file_name;
     }
@@ -334,6 +449,10 @@ impl Code {
         let last_col = self.line_len(last_row);
 
         self.replace_text(0, 0, last_row, last_col, &text.to_string());
+        self.replaced();
+        self.changed = false;
+        self.stamp = FileStamp::read(&self.abs_path).ok();
+        self.saved = Some(self.text.clone());
 
         Ok(())
     }
@@ -436,4 +555,27 @@ mod code_undo_tests {
         buffer.redo();
         assert_eq!(buffer.text.to_string(), "hello world!");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_disk_changed() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("stamp.txt");
+        std::fs::write(&path, "one")?;
+
+        let mut code = Code::from_file(path.to_str().unwrap(), &Config::default())?;
+        assert!(!code.disk_changed());
+
+        // Our own save is not an external change
+        code.set_text("two two");
+        code.save_file()?;
+        assert!(!code.disk_changed());
+
+        std::fs::write(&path, "external writer")?;
+        assert!(code.disk_changed());
+
+        code.reload()?;
+        assert!(!code.disk_changed());
+        assert_eq!(code.text.to_string(), "external writer");
+        Ok(())
+    }
+}
@@ -0,0 +1,560 @@
+use parking_lot::Mutex;
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use socketioxide::extract::SocketRef;
+
+use crate::revision::Span;
+use crate::services::{Edit, Operation};
+
+// Real-time collaborative editing of a buffer. Peers join a file with
+// `collab:join` and get its text and version, then send what they type
+// as operations made on a version (`collab:op`). The backend orders them:
+// an operation is transformed over the changes applied since its version,
+// applied to the buffer and sent to all peers with the new version, the
+// sender included as its confirmation, so all of them apply the same
+// operations in the same order and converge. Changes from outside the session (file:change, undo,
+// refactorings) reach the peers the same way, a replaced text (set,
+// reload) as `collab:reset`, so peers ignore `file:change` of the files
+// they joined. Operations are in the format of ot.js, in
+// UTF-16 units: a positive number retains, a negative one deletes, a
+// string inserts. Peers share their selection with `collab:cursor`, the
+// backend moves it with every later change.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Component {
+    Retain(usize),
+    Insert(String),
+    Delete(usize),
+}
+
+fn len16(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+/// `text` split after `units` UTF-16 units
+fn split16(text: &str, units: usize) -> (&str, &str) {
+    let mut count = 0;
+    for (i, c) in text.char_indices() {
+        if count >= units {
+            return text.split_at(i);
+        }
+        count += c.len_utf16();
+    }
+    (text, "")
+}
+
+impl Component {
+    fn len(&self) -> usize {
+        match self {
+            Component::Retain(n) | Component::Delete(n) => *n,
+            Component::Insert(text) => len16(text),
+        }
+    }
+
+    /// What is left after the first `n` units, None when nothing
+    fn rest(self, n: usize) -> Option<Component> {
+        match self {
+            Component::Retain(len) => (len > n).then(|| Component::Retain(len - n)),
+            Component::Delete(len) => (len > n).then(|| Component::Delete(len - n)),
+            Component::Insert(text) => {
+                let rest = split16(&text, n).1;
+                (!rest.is_empty()).then(|| Component::Insert(rest.to_string()))
+            }
+        }
+    }
+}
+
+/// An operation on the whole text, see the module comment
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(try_from = "Vec<Value>", into = "Vec<Value>")]
+pub struct Op(Vec<Component>);
+
+impl TryFrom<Vec<Value>> for Op {
+    type Error = String;
+
+    fn try_from(values: Vec<Value>) -> Result<Self, String> {
+        let mut op = Op::default();
+        for value in values {
+            match value {
+                Value::String(text) => op.insert(&text),
+                Value::Number(n) => match n.as_i64() {
+                    Some(n) if n > 0 => op.retain(n as usize),
+                    Some(n) if n < 0 => op.delete(n.unsigned_abs() as usize),
+                    _ => return Err(format!("Invalid component {}", n)),
+                },
+                other => return Err(format!("Invalid component {}", other)),
+            }
+        }
+        Ok(op)
+    }
+}
+
+impl From<Op> for Vec<Value> {
+    fn from(op: Op) -> Self {
+        op.0.into_iter()
+            .map(|c| match c {
+                Component::Retain(n) => Value::from(n),
+                Component::Insert(text) => Value::from(text),
+                Component::Delete(n) => Value::from(-(n as i64)),
+            })
+            .collect()
+    }
+}
+
+impl Op {
+    pub fn retain(&mut self, n: usize) {
+        if n == 0 {
+            return;
+        }
+        match self.0.last_mut() {
+            Some(Component::Retain(last)) => *last += n,
+            _ => self.0.push(Component::Retain(n)),
+        }
+    }
+
+    /// Inserts go before the deletes at the same position
+    pub fn insert(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        let at = match self.0.last() {
+            Some(Component::Delete(_)) => self.0.len() - 1,
+            _ => self.0.len(),
+        };
+        match at.checked_sub(1).and_then(|i| self.0.get_mut(i)) {
+            Some(Component::Insert(last)) => last.push_str(text),
+            _ => self.0.insert(at, Component::Insert(text.to_string())),
+        }
+    }
+
+    pub fn delete(&mut self, n: usize) {
+        if n == 0 {
+            return;
+        }
+        match self.0.last_mut() {
+            Some(Component::Delete(last)) => *last += n,
+            _ => self.0.push(Component::Delete(n)),
+        }
+    }
+
+    /// Length of the text the operation applies to
+    pub fn base_len(&self) -> usize {
+        self.0.iter()
+            .map(|c| match c {
+                Component::Retain(n) | Component::Delete(n) => *n,
+                Component::Insert(_) => 0,
+            })
+            .sum()
+    }
+
+    /// Length of the text after it
+    pub fn target_len(&self) -> usize {
+        self.0.iter()
+            .map(|c| match c {
+                Component::Retain(n) => *n,
+                Component::Insert(text) => len16(text),
+                Component::Delete(_) => 0,
+            })
+            .sum()
+    }
+
+    /// `self` then `other` as one operation, None when `other` does not
+    /// apply to the text after `self`
+    pub fn compose(&self, other: &Op) -> Option<Op> {
+        if self.target_len() != other.base_len() {
+            return None;
+        }
+        let mut out = Op::default();
+        let (mut first, mut second) = (self.0.iter().cloned(), other.0.iter().cloned());
+        let (mut a, mut b) = (first.next(), second.next());
+        loop {
+            match (a.take(), b.take()) {
+                (None, None) => break,
+                (Some(Component::Delete(n)), rest) => {
+                    out.delete(n);
+                    (a, b) = (first.next(), rest);
+                }
+                (rest, Some(Component::Insert(text))) => {
+                    out.insert(&text);
+                    (a, b) = (rest, second.next());
+                }
+                (Some(x), Some(y)) => {
+                    let n = x.len().min(y.len());
+                    match (&x, &y) {
+                        (Component::Retain(_), Component::Retain(_)) => out.retain(n),
+                        (Component::Insert(text), Component::Retain(_)) => out.insert(split16(text, n).0),
+                        (Component::Retain(_), Component::Delete(_)) => out.delete(n),
+                        // Inserted by the first, deleted by the second
+                        _ => {}
+                    }
+                    a = x.rest(n).or_else(|| first.next());
+                    b = y.rest(n).or_else(|| second.next());
+                }
+                _ => return None,
+            }
+        }
+        Some(out)
+    }
+
+    /// The edits of a change as one operation on a text of `len` units,
+    /// None when they don't fit in it
+    pub fn from_edits(edits: &[Edit], mut len: usize) -> Option<Op> {
+        let mut op = Op::default();
+        op.retain(len);
+        for edit in edits {
+            let span = Span::of(edit);
+            let end = span.start + span.removed;
+            if end > len {
+                return None;
+            }
+            let mut step = Op::default();
+            step.retain(span.start);
+            match edit.operation {
+                Operation::Insert => step.insert(&edit.text),
+                Operation::Remove => step.delete(span.removed),
+            }
+            step.retain(len - end);
+            len = len - span.removed + span.inserted;
+            op = op.compose(&step)?;
+        }
+        Some(op)
+    }
+}
+
+/// Lengths of a change an operation is moved over
+#[derive(Debug, Clone, Copy)]
+enum Piece {
+    Retain(usize),
+    Insert(usize),
+    Delete(usize),
+}
+
+impl Piece {
+    fn len(self) -> usize {
+        match self {
+            Piece::Retain(n) | Piece::Insert(n) | Piece::Delete(n) => n,
+        }
+    }
+
+    fn rest(self, n: usize) -> Option<Piece> {
+        match self {
+            Piece::Retain(len) => (len > n).then(|| Piece::Retain(len - n)),
+            Piece::Insert(len) => (len > n).then(|| Piece::Insert(len - n)),
+            Piece::Delete(len) => (len > n).then(|| Piece::Delete(len - n)),
+        }
+    }
+}
+
+/// `op` moved over one change made on the same text
+fn transform_one(op: &Op, span: &Span) -> Op {
+    let mut change = [Piece::Retain(span.start), Piece::Insert(span.inserted), Piece::Delete(span.removed)]
+        .into_iter()
+        .filter(|p| p.len() > 0);
+    let mut out = Op::default();
+    let mut components = op.0.iter().cloned();
+    let (mut a, mut b) = (components.next(), change.next());
+    loop {
+        if let Some(Component::Insert(text)) = &a {
+            out.insert(text);
+            a = components.next();
+            continue;
+        }
+        if let Some(Piece::Insert(n)) = b {
+            out.retain(n);
+            b = change.next();
+            continue;
+        }
+        let Some(x) = a.take() else { break };
+        // The change retains the text after it
+        let y = b.unwrap_or(Piece::Retain(usize::MAX));
+        let n = x.len().min(y.len());
+        match (&x, y) {
+            (Component::Retain(_), Piece::Retain(_)) => out.retain(n),
+            (Component::Delete(_), Piece::Retain(_)) => out.delete(n),
+            // Deleted by the change already
+            _ => {}
+        }
+        a = x.rest(n).or_else(|| components.next());
+        if b.is_some() {
+            b = y.rest(n).or_else(|| change.next());
+        }
+    }
+    out
+}
+
+/// `op` moved over the changes of `spans`, applied one after the other to
+/// the text it was made on. Its inserts go first at the same position,
+/// text deleted by both is deleted once.
+pub fn transform(op: &Op, spans: &[Span]) -> Op {
+    spans.iter().fold(op.clone(), |op, span| transform_one(&op, span))
+}
+
+/// A position moved over the changes of `spans`
+pub fn transform_index(mut index: usize, spans: &[Span]) -> usize {
+    for span in spans {
+        if span.start <= index {
+            index += span.inserted;
+        }
+        if span.start < index {
+            index -= span.removed.min(index - span.start);
+        }
+    }
+    index
+}
+
+/// The edits of `op` on `text`, applied one after the other. None when
+/// it was made on a text of another length.
+pub fn edits(op: &Op, text: &Rope) -> Option<Vec<Edit>> {
+    if op.base_len() != text.len_utf16_cu() {
+        return None;
+    }
+    let mut edits = Vec::new();
+    // Positions in the text before the operation and with the edits so far
+    let (mut old, mut new) = (0, 0);
+    for c in &op.0 {
+        match c {
+            Component::Retain(n) => {
+                old += n;
+                new += n;
+            }
+            Component::Insert(inserted) => {
+                edits.push(Edit { operation: Operation::Insert, start: new, text: inserted.clone() });
+                new += len16(inserted);
+            }
+            Component::Delete(n) => {
+                let (from, to) = (text.utf16_cu_to_char(old), text.utf16_cu_to_char(old + n));
+                edits.push(Edit { operation: Operation::Remove, start: new, text: text.slice(from..to).to_string() });
+                old += n;
+            }
+        }
+    }
+    Some(edits)
+}
+
+/// A peer's name and selection in UTF-16 offsets, the cursor at `head`
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Presence {
+    pub peer: String,
+    pub name: String,
+    pub anchor: usize,
+    pub head: usize,
+}
+
+/// What a peer gets when it joins
+#[derive(Debug, Serialize)]
+pub struct Joined {
+    pub file: String,
+    pub text: String,
+    pub version: u64,
+    /// The other peers
+    pub peers: Vec<Presence>,
+}
+
+struct Peer {
+    socket: SocketRef,
+    presence: Presence,
+}
+
+struct Session {
+    path: String,
+    peers: Vec<Peer>,
+}
+
+static SESSIONS: Mutex<Vec<Session>> = Mutex::new(Vec::new());
+
+fn emit_others(session: &Session, from: Option<&str>, event: &str, payload: &Value) {
+    for peer in session.peers.iter().filter(|p| Some(p.presence.peer.as_str()) != from && p.socket.connected()) {
+        let _ = peer.socket.emit(event, payload);
+    }
+}
+
+/// Add a peer to the session of `path`, the presence of the others. The
+/// caller holds file2code so no change falls between its snapshot of the
+/// text and the join.
+pub fn join(path: &str, socket: SocketRef, name: &str) -> Vec<Presence> {
+    let mut sessions = SESSIONS.lock();
+    let index = match sessions.iter().position(|s| s.path == path) {
+        Some(index) => index,
+        None => {
+            sessions.push(Session { path: path.to_string(), peers: Vec::new() });
+            sessions.len() - 1
+        }
+    };
+    let session = &mut sessions[index];
+    let id = socket.id.as_str().to_string();
+    session.peers.retain(|p| p.presence.peer != id);
+    let presence = Presence { peer: id.clone(), name: name.to_string(), anchor: 0, head: 0 };
+    emit_others(session, Some(&id), "collab:joined", &json!({ "file": path, "peer": id, "name": name }));
+    let others = session.peers.iter().map(|p| p.presence.clone()).collect();
+    session.peers.push(Peer { socket, presence });
+    others
+}
+
+/// Remove a peer from the session of `path`, false if it was not in it
+pub fn leave(path: &str, socket_id: &str) -> bool {
+    let mut sessions = SESSIONS.lock();
+    let Some(session) = sessions.iter_mut().find(|s| s.path == path) else { return false };
+    let count = session.peers.len();
+    session.peers.retain(|p| p.presence.peer != socket_id);
+    if session.peers.len() == count {
+        return false;
+    }
+    emit_others(session, None, "collab:left", &json!({ "file": path, "peer": socket_id }));
+    sessions.retain(|s| !s.peers.is_empty());
+    true
+}
+
+/// Remove a disconnected socket from every session
+pub fn leave_socket(socket_id: &str) {
+    let paths: Vec<String> = SESSIONS.lock().iter()
+        .filter(|s| s.peers.iter().any(|p| p.presence.peer == socket_id))
+        .map(|s| s.path.clone())
+        .collect();
+    for path in paths {
+        leave(&path, socket_id);
+    }
+}
+
+pub fn is_peer(path: &str, socket_id: &str) -> bool {
+    SESSIONS.lock().iter()
+        .filter(|s| s.path == path)
+        .any(|s| s.peers.iter().any(|p| p.presence.peer == socket_id))
+}
+
+/// Whether anyone edits `path` in a session
+pub fn has_session(path: &str) -> bool {
+    SESSIONS.lock().iter().any(|s| s.path == path)
+}
+
+/// Send a change applied to the buffer of `path` to its peers and move
+/// their selections over it. `from` is the peer it came from, which takes
+/// it as the confirmation of its operation. Called with file2code held,
+/// the peers get the changes in the order of the versions.
+pub fn forward(path: &str, version: u64, op: &Op, spans: &[Span], from: Option<&str>) {
+    let mut sessions = SESSIONS.lock();
+    let Some(session) = sessions.iter_mut().find(|s| s.path == path) else { return };
+    for peer in session.peers.iter_mut() {
+        peer.presence.anchor = transform_index(peer.presence.anchor, spans);
+        peer.presence.head = transform_index(peer.presence.head, spans);
+    }
+    let payload = json!({ "file": path, "op": op, "version": version, "peer": from });
+    emit_others(session, None, "collab:op", &payload);
+}
+
+/// Give the peers of `path`, or only `peer`, the whole text to start over
+/// from, after it was replaced or an operation of the peer could not be
+/// applied. Called with file2code held.
+pub fn reset(path: &str, text: &str, version: u64, peer: Option<&str>) {
+    let mut sessions = SESSIONS.lock();
+    let Some(session) = sessions.iter_mut().find(|s| s.path == path) else { return };
+    let len = len16(text);
+    for peer in session.peers.iter_mut() {
+        peer.presence.anchor = peer.presence.anchor.min(len);
+        peer.presence.head = peer.presence.head.min(len);
+    }
+    let payload = json!({ "file": path, "text": text, "version": version });
+    for reset in session.peers.iter().filter(|p| peer.is_none_or(|id| p.presence.peer == id)) {
+        let _ = reset.socket.emit("collab:reset", &payload);
+    }
+}
+
+/// Set the selection of a peer, already moved to the current version, and
+/// show it to the others. False if the socket is not in the session.
+pub fn cursor(path: &str, socket_id: &str, anchor: usize, head: usize) -> bool {
+    let mut sessions = SESSIONS.lock();
+    let Some(session) = sessions.iter_mut().find(|s| s.path == path) else { return false };
+    let Some(peer) = session.peers.iter_mut().find(|p| p.presence.peer == socket_id) else { return false };
+    peer.presence.anchor = anchor;
+    peer.presence.head = head;
+    let mut payload = json!(peer.presence);
+    payload["file"] = json!(path);
+    emit_others(session, Some(socket_id), "collab:cursor", &payload);
+    true
+}
+
+#[cfg(test)]
+mod collab_tests {
+    use super::*;
+
+    fn op(json: Value) -> Op {
+        serde_json::from_value(json).unwrap()
+    }
+
+    fn apply(op: &Op, text: &str) -> String {
+        let mut rope = Rope::from_str(text);
+        for edit in edits(op, &rope).unwrap() {
+            let start = rope.utf16_cu_to_char(edit.start);
+            match edit.operation {
+                Operation::Insert => rope.insert(start, &edit.text),
+                Operation::Remove => rope.remove(start..start + edit.text.chars().count()),
+            }
+        }
+        rope.to_string()
+    }
+
+    fn insert(start: usize, text: &str) -> Edit {
+        Edit { operation: Operation::Insert, start, text: text.to_string() }
+    }
+
+    #[test]
+    fn test_op_format() {
+        let replaced = op(json!([6, -5, "there"]));
+        // Normalized with the insert first
+        assert_eq!(replaced.0, [Component::Retain(6), Component::Insert("there".into()), Component::Delete(5)]);
+        assert_eq!(serde_json::to_value(&replaced).unwrap(), json!([6, "there", -5]));
+        assert_eq!((replaced.base_len(), replaced.target_len()), (11, 11));
+        assert!(serde_json::from_value::<Op>(json!([0])).is_err());
+        assert!(serde_json::from_value::<Op>(json!([true])).is_err());
+    }
+
+    #[test]
+    fn test_edits() {
+        let text = "h\u{e9}llo \u{1f600} world";
+        let changed = op(json!([1, -1, "e", 4, -2, "\u{2764}", 6]));
+        assert_eq!(apply(&changed, text), "hello \u{2764} world");
+        assert!(edits(&op(json!([3])), &Rope::from_str(text)).is_none());
+    }
+
+    #[test]
+    fn test_compose_and_from_edits() {
+        let a = op(json!([5, " world"]));
+        let b = op(json!([-1, "H", 10]));
+        assert_eq!(a.compose(&b).unwrap(), op(json!(["H", -1, 4, " world"])));
+        assert!(a.compose(&op(json!([3]))).is_none());
+
+        let removed = Edit { operation: Operation::Remove, start: 0, text: "he".into() };
+        let change = Op::from_edits(&[insert(5, "!"), removed], 5).unwrap();
+        assert_eq!(apply(&change, "hello"), "llo!");
+        assert!(Op::from_edits(&[insert(9, "!")], 5).is_none());
+    }
+
+    #[test]
+    fn test_transform_converges() {
+        let text = "hello world";
+        // Applied by the backend first: "!" typed at the end, then "hello"
+        // removed
+        let spans = [Span::of(&insert(11, "!")), Span { start: 0, removed: 5, inserted: 0 }];
+        let server = op(json!([11, "!"])).compose(&op(json!([-5, 7]))).unwrap();
+        assert_eq!(apply(&server, text), " world!");
+
+        // Made by a peer on the same text: "world" replaced, "hello" and
+        // the space after it removed, a prompt typed first
+        for (peer, expected) in [
+            (op(json!([6, "there", -5])), " there!"),
+            (op(json!([-6, 5])), "world!"),
+            (op(json!(["> ", 11])), ">  world!"),
+        ] {
+            let moved = transform(&peer, &spans);
+            assert_eq!(apply(&moved, &apply(&server, text)), expected);
+        }
+    }
+
+    #[test]
+    fn test_transform_index() {
+        let spans = [Span::of(&insert(2, "ab")), Span { start: 0, removed: 3, inserted: 0 }];
+        assert_eq!(transform_index(2, &spans), 1);
+        assert_eq!(transform_index(5, &spans), 4);
+        assert_eq!(transform_index(0, &spans), 0);
+    }
+}
@@ -0,0 +1,85 @@
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::sync::{Arc, OnceLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Output of run:command kept as read-only documents addressed by
+// `command-output://<id>/<command>`, served through the vfs registry.
+
+pub const SCHEME: &str = "command-output";
+/// Documents kept, the oldest are dropped first
+const MAX_DOCUMENTS: usize = 20;
+
+struct Document {
+    uri: String,
+    content: String,
+}
+
+static DOCUMENTS: OnceLock<Mutex<VecDeque<Document>>> = OnceLock::new();
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+fn documents() -> &'static Mutex<VecDeque<Document>> {
+    DOCUMENTS.get_or_init(|| Mutex::new(VecDeque::new()))
+}
+
+/// Uri of a new document, the command is kept readable for tab titles
+fn uri_for(id: u64, command: &str) -> String {
+    let name: String = command.chars()
+        .map(|c| if c.is_alphanumeric() || "-_.".contains(c) { c } else { '-' })
+        .take(60)
+        .collect();
+    format!("{}://{}/{}", SCHEME, id, name.trim_matches('-'))
+}
+
+/// Store the output of a command, returns its uri
+pub fn insert(command: &str, content: String) -> String {
+    let uri = uri_for(NEXT_ID.fetch_add(1, Ordering::Relaxed), command);
+    let mut documents = documents().lock();
+    documents.push_back(Document { uri: uri.clone(), content });
+    while documents.len() > MAX_DOCUMENTS {
+        documents.pop_front();
+    }
+    uri
+}
+
+pub fn get(uri: &str) -> Option<String> {
+    documents().lock().iter()
+        .find(|d| d.uri == uri)
+        .map(|d| d.content.clone())
+}
+
+struct CommandOutputProvider;
+
+impl crate::vfs::Provider for CommandOutputProvider {
+    fn scheme(&self) -> &str {
+        SCHEME
+    }
+
+    fn read(&self, uri: &str) -> Option<String> {
+        get(uri)
+    }
+
+    fn list(&self) -> Vec<String> {
+        documents().lock().iter().map(|d| d.uri.clone()).collect()
+    }
+}
+
+pub fn register() {
+    crate::vfs::register(Arc::new(CommandOutputProvider));
+}
+
+#[cfg(test)]
+mod command_output_tests {
+    use super::*;
+
+    #[test]
+    fn test_documents() {
+        assert_eq!(uri_for(3, "cargo build --release"), "command-output://3/cargo-build---release");
+
+        register();
+        let uri = insert("echo hi", "hi\n".to_string());
+        assert!(crate::vfs::is_virtual(&uri));
+        assert_eq!(get(&uri).as_deref(), Some("hi\n"));
+        assert_eq!(get("command-output://0/none"), None);
+    }
+}
@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use std::{fmt::format, path::Path};
+use std::{collections::HashMap, fmt::format, path::Path};
 
 use rust_embed::Embed;
 
@@ -18,6 +18,26 @@ pub struct Config {
     pub theme: String,
     pub language: Vec<Language>,
     pub terminal: Option<Terminal>,
+    pub background_workers: Option<usize>,
+    pub slow_event_ms: Option<u64>,
+    pub encryption: Option<EncryptionConfig>,
+    pub notify: Option<NotifyConfig>,
+    pub mcp: Option<McpConfig>,
+    pub lsp_warmup: Option<LspWarmupConfig>,
+    pub lsp_memory: Option<LspMemoryConfig>,
+    pub lsp_scheduler: Option<LspSchedulerConfig>,
+    pub lsp_restart: Option<LspRestartConfig>,
+    pub storage: Option<StorageConfig>,
+    pub exec: Option<ExecConfig>,
+    pub preload: Option<PreloadConfig>,
+    pub dir_list: Option<DirListConfig>,
+    /// Ports tried after ANYCODE_PORT when it is taken, 0 to fail instead
+    pub port_fallback: Option<u16>,
+    /// Background work at full speed or throttled, see power.rs
+    pub power_mode: Option<PowerMode>,
+    pub auth: Option<AuthConfig>,
+    pub save: Option<SaveConfig>,
+    pub open: Option<OpenConfig>,
 }
 
 impl Config {
@@ -26,6 +46,24 @@ impl Config {
             theme: "default".to_string(),
             language: vec![],
             terminal: None,
+            background_workers: None,
+            slow_event_ms: None,
+            encryption: None,
+            notify: None,
+            mcp: None,
+            lsp_warmup: None,
+            lsp_memory: None,
+            lsp_scheduler: None,
+            lsp_restart: None,
+            storage: None,
+            exec: None,
+            preload: None,
+            dir_list: None,
+            port_fallback: None,
+            power_mode: None,
+            auth: None,
+            save: None,
+            open: None,
         }
     }
 }
@@ -40,6 +78,21 @@ pub struct Language {
     pub executable: Option<bool>,
     pub exec: Option<String>,
     pub exectest: Option<String>,
+    /// Interpreter kept running for repl:eval (python or node)
+    pub repl: Option<String>,
+    /// Source and test file pairs for nav:alternate, see alternate.rs
+    #[serde(default)]
+    pub alternates: Vec<AlternateRule>,
+}
+
+/// Paths of a source and its test relative to the workspace, `{dir}` for
+/// any directories and `{name}` for a part of the file name
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct AlternateRule {
+    pub source: String,
+    pub test: String,
+    /// Template of a created test, with the variables of template.rs
+    pub template: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -137,6 +190,232 @@ pub fn read_assets_config() -> anyhow::Result<String> {
 #[derive(Debug, Deserialize, Clone)]
 pub struct Terminal {
     pub command: String,
+    #[serde(default)]
+    pub profiles: Vec<TerminalProfile>,
+    /// Output kept per terminal for terminal:scrollback, in bytes
+    pub scrollback_bytes: Option<usize>,
+    /// Markers of the command lines for bash and zsh, on when unset
+    pub shell_integration: Option<bool>,
+}
+
+/// Named terminal launch configuration, e.g. a WSL distribution
+/// (`wsl.exe -d Ubuntu`) or an ssh target (`ssh user@host`).
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TerminalProfile {
+    pub name: String,
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+}
+
+impl TerminalProfile {
+    /// Profile for a raw command line like `bash -l`
+    pub fn from_command_line(name: &str, command_line: &str) -> anyhow::Result<Self> {
+        let mut words = shell_words::split(command_line)?.into_iter();
+        let command = words.next().ok_or_else(|| anyhow::anyhow!("Empty terminal command"))?;
+        Ok(Self { name: name.to_string(), command, args: words.collect(), env: HashMap::new() })
+    }
+}
+
+/// Encryption of the state files written under ANYCODE_HOME (~/.anycode).
+/// The key comes from `key_command` (keychain helper) or the passphrase env var.
+#[derive(Debug, Deserialize, Clone)]
+pub struct EncryptionConfig {
+    pub enabled: bool,
+    pub passphrase_env: Option<String>,
+    pub key_command: Option<String>,
+}
+
+/// External notifications: a webhook receiving JSON and/or a command where
+/// `{event}` and `{message}` are substituted. `events` filters by name.
+#[derive(Debug, Deserialize, Clone)]
+pub struct NotifyConfig {
+    pub webhook: Option<String>,
+    pub command: Option<String>,
+    pub events: Option<Vec<String>>,
+    pub long_search_ms: Option<u64>,
+    pub diagnostics_threshold: Option<usize>,
+}
+
+/// MCP server exposing read_file and search to external agents over SSE.
+/// apply_edit and run_task are only served when allowed explicitly.
+#[derive(Debug, Deserialize, Clone)]
+pub struct McpConfig {
+    pub enabled: bool,
+    pub address: Option<String>,
+    pub allow_edit: Option<bool>,
+    pub allow_run: Option<bool>,
+}
+
+/// Policy of the one-off commands run for clients and agents. Without
+/// `allow` any program not in `deny` may run.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ExecConfig {
+    pub allow: Option<Vec<String>>,
+    pub deny: Option<Vec<String>>,
+    pub timeout_secs: Option<u64>,
+}
+
+/// Start language servers at startup for the languages found in the
+/// workspace, the most used first, instead of on the first request.
+#[derive(Debug, Deserialize, Clone)]
+pub struct LspWarmupConfig {
+    pub enabled: bool,
+    pub max_servers: Option<usize>,
+}
+
+/// Load the recent files of the workspace at startup, within
+/// `budget_mb` of file content, and open them in their language servers
+/// when `lsp` is set
+#[derive(Debug, Deserialize, Clone)]
+pub struct PreloadConfig {
+    pub budget_mb: Option<u64>,
+    pub max_files: Option<usize>,
+    #[serde(default)]
+    pub lsp: bool,
+}
+
+/// Largest file file:open reads without `force`, in bytes
+#[derive(Debug, Deserialize, Clone)]
+pub struct OpenConfig {
+    pub max_size: Option<u64>,
+}
+
+/// Entries per dir:list page, requests can ask for more with `limit`
+#[derive(Debug, Deserialize, Clone)]
+pub struct DirListConfig {
+    pub max_entries: Option<usize>,
+}
+
+/// Concurrent requests per language server by priority class, background
+/// requests run anyway after waiting `max_background_wait_ms`
+#[derive(Debug, Deserialize, Clone)]
+pub struct LspSchedulerConfig {
+    pub interactive: Option<usize>,
+    pub background: Option<usize>,
+    pub max_background_wait_ms: Option<u64>,
+}
+
+/// Restarts of crashed language servers, waiting `initial_delay_ms` and
+/// twice as long after every new crash up to `max_delay_ms`. A server
+/// crashing more than `max_retries` times in a row is left stopped, 0
+/// disables the restarts.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct LspRestartConfig {
+    pub max_retries: Option<u32>,
+    pub initial_delay_ms: Option<u64>,
+    pub max_delay_ms: Option<u64>,
+}
+
+/// Memory budget of each language server, checked every `interval_secs`
+#[derive(Debug, Deserialize, Clone)]
+pub struct LspMemoryConfig {
+    pub budget_mb: u64,
+    #[serde(default)]
+    pub action: LspMemoryAction,
+    pub interval_secs: Option<u64>,
+}
+
+/// Backend of the state storage, `path` defaults to state.db in the
+/// anycode home
+#[derive(Debug, Deserialize, Clone)]
+pub struct StorageConfig {
+    #[serde(default)]
+    pub backend: StorageBackend,
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    #[default]
+    Sqlite,
+    /// Nothing survives a restart, for throwaway sessions
+    Memory,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PowerMode {
+    #[default]
+    Performance,
+    /// Throttle the workspace scans and put off the rescans
+    Battery,
+}
+
+/// What to do with a server over its memory budget
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LspMemoryAction {
+    /// Only emit `lsp:memoryPressure`
+    #[default]
+    Warn,
+    /// Start a fresh server
+    Restart,
+    /// Stop the server and use word completion and text references
+    Fallback,
+}
+
+/// What a connection may do, see permissions.rs
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scope {
+    #[serde(rename = "fs:read")]
+    FsRead,
+    #[serde(rename = "fs:write")]
+    FsWrite,
+    #[serde(rename = "exec")]
+    Exec,
+    #[serde(rename = "lsp")]
+    Lsp,
+    #[serde(rename = "terminal")]
+    Terminal,
+    /// Server diagnostics and settings shared by every client
+    #[serde(rename = "admin")]
+    Admin,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 6] = [Scope::FsRead, Scope::FsWrite, Scope::Exec, Scope::Lsp, Scope::Terminal, Scope::Admin];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::FsRead => "fs:read",
+            Scope::FsWrite => "fs:write",
+            Scope::Exec => "exec",
+            Scope::Lsp => "lsp",
+            Scope::Terminal => "terminal",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+/// Scopes of the socket connections and REST requests. A client
+/// connecting with a `token` in its auth payload, or a bearer token, gets
+/// the scopes of the token, an unknown token is refused. Connections without a token get `default_scopes`, every scope
+/// when unset.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct AuthConfig {
+    pub default_scopes: Option<Vec<Scope>>,
+    #[serde(default)]
+    pub tokens: Vec<AuthToken>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct AuthToken {
+    pub token: String,
+    pub scopes: Vec<Scope>,
+}
+
+/// Normalizations of `file:save`, previewed or applied at the client's
+/// request, see text_audit.rs
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct SaveConfig {
+    pub trim_trailing_whitespace: Option<bool>,
+    pub insert_final_newline: Option<bool>,
+    /// Remove the blank lines at the end of the file
+    pub trim_final_newlines: Option<bool>,
 }
 
 #[cfg(test)]
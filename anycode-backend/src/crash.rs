@@ -0,0 +1,150 @@
+use serde::Serialize;
+use serde_json::Value;
+use socketioxide::socket::Sid;
+use std::cell::RefCell;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::mpsc;
+use tracing::error;
+
+// Panics of the socket handlers. Each handler runs in its own task and
+// holds an `EventTimer`, which reports the panic when it is dropped while
+// unwinding: a structured crash report is logged and sent as
+// `server:handlerError` to the socket that sent the event, so it stops
+// waiting for the ack, and to the admin subscribers. Only the handler's
+// task ends, the connection, the other handlers and the background tasks
+// go on. The buffers and the language servers are behind tokio locks and
+// the registries (terminal shares, the event log, the roots and such)
+// behind parking_lot ones, neither is poisoned by a panic while held.
+
+/// Payload strings longer than this are replaced by their length
+const MAX_PAYLOAD_STRING: usize = 200;
+/// Payload fields carrying file or terminal contents, never logged
+const CONTENT_KEYS: &[&str] = &["content", "text", "new_text", "newText", "input", "code", "replacement"];
+
+#[derive(Debug, Serialize, Clone)]
+pub struct CrashReport {
+    pub id: u64,
+    pub event: &'static str,
+    /// Socket the event came from, when the handler told its timer
+    #[serde(skip)]
+    pub socket: Option<Sid>,
+    pub message: String,
+    /// file:line:column of the panic
+    pub location: Option<String>,
+    /// The event payload with file contents redacted
+    pub payload: Option<Value>,
+    pub time: i64,
+}
+
+/// What the panic hook saw, picked up by the unwinding handler
+struct Panic {
+    message: String,
+    location: Option<String>,
+}
+
+thread_local! {
+    static LAST_PANIC: RefCell<Option<Panic>> = const { RefCell::new(None) };
+}
+
+static SENDER: OnceLock<mpsc::Sender<CrashReport>> = OnceLock::new();
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Install the panic hook, crash reports are sent to `sender`. The
+/// previous hook still prints the panic.
+pub fn init(sender: mpsc::Sender<CrashReport>) {
+    if SENDER.set(sender).is_err() {
+        return;
+    }
+
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let payload = info.payload();
+        let message = payload.downcast_ref::<&str>().map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "Box<dyn Any>".to_string());
+        let location = info.location().map(|l| format!("{}:{}:{}", l.file(), l.line(), l.column()));
+        LAST_PANIC.with(|last| *last.borrow_mut() = Some(Panic { message, location }));
+        previous(info);
+    }));
+}
+
+/// Drop file contents and long strings from an event payload
+pub fn redact(value: Value) -> Value {
+    match value {
+        Value::String(s) if s.chars().count() > MAX_PAYLOAD_STRING => {
+            Value::String(format!("<{} chars>", s.chars().count()))
+        }
+        Value::Array(items) => Value::Array(items.into_iter().map(redact).collect()),
+        Value::Object(fields) => Value::Object(fields.into_iter().map(|(key, value)| {
+            let value = match value {
+                Value::String(s) if CONTENT_KEYS.contains(&key.as_str()) => {
+                    Value::String(format!("<{} chars>", s.chars().count()))
+                }
+                value => redact(value),
+            };
+            (key, value)
+        }).collect()),
+        value => value,
+    }
+}
+
+/// Report the panic of the handler of `event`, called while unwinding
+pub fn report(event: &'static str, socket: Option<Sid>, payload: Option<Value>) {
+    let panic = LAST_PANIC.with(|last| last.borrow_mut().take());
+    let report = CrashReport {
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        event,
+        socket,
+        message: panic.as_ref().map_or_else(|| "unknown panic".to_string(), |p| p.message.clone()),
+        location: panic.and_then(|p| p.location),
+        payload,
+        time: chrono::Utc::now().timestamp_millis(),
+    };
+
+    error!("Handler {} panicked: {}", event, serde_json::to_string(&report).unwrap_or_default());
+    if let Some(sender) = SENDER.get() {
+        let _ = sender.try_send(report);
+    }
+}
+
+#[cfg(test)]
+mod crash_tests {
+    use super::*;
+    use crate::timing::EventTimer;
+    use serde_json::json;
+
+    #[test]
+    fn test_redact() {
+        let long = "x".repeat(MAX_PAYLOAD_STRING + 1);
+        let payload = json!({
+            "path": "/w/a.rs",
+            "content": "fn main() {}",
+            "edits": [{ "start": 3, "text": "secret" }],
+            "query": long,
+        });
+        assert_eq!(redact(payload), json!({
+            "path": "/w/a.rs",
+            "content": "<12 chars>",
+            "edits": [{ "start": 3, "text": "<6 chars>" }],
+            "query": format!("<{} chars>", MAX_PAYLOAD_STRING + 1),
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_handler_panic_is_reported() {
+        let (send, mut recv) = mpsc::channel(4);
+        init(send);
+
+        let handler = tokio::spawn(async {
+            let _timer = EventTimer::start("test:panic").with_payload(&json!({ "path": "/w/a.rs", "text": "abc" }));
+            panic!("handler bug");
+        });
+        assert!(handler.await.is_err());
+
+        let report = recv.recv().await.unwrap();
+        assert_eq!((report.event, report.message.as_str()), ("test:panic", "handler bug"));
+        assert!(report.location.is_some_and(|l| l.contains("crash.rs")));
+        assert_eq!(report.payload, Some(json!({ "path": "/w/a.rs", "text": "<3 chars>" })));
+    }
+}
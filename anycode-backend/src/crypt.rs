@@ -0,0 +1,90 @@
+use anyhow::{anyhow, Result};
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+/// Prefix of encrypted files, followed by a 12 byte nonce and the ciphertext
+const MAGIC: &[u8] = b"ANYCODE-ENC1";
+const NONCE_LEN: usize = 12;
+pub const SALT_LEN: usize = 16;
+
+pub struct Cipher {
+    cipher: ChaCha20Poly1305,
+}
+
+impl Cipher {
+    /// Derive the key from a passphrase with argon2
+    pub fn from_passphrase(passphrase: &str, salt: &[u8]) -> Result<Self> {
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|e| anyhow!("Failed to derive encryption key: {}", e))?;
+
+        Ok(Self { cipher: ChaCha20Poly1305::new(Key::from_slice(&key)) })
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self.cipher.encrypt(&nonce, plaintext)
+            .map_err(|_| anyhow!("Encryption failed"))?;
+
+        let mut out = Vec::with_capacity(MAGIC.len() + NONCE_LEN + ciphertext.len());
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&ciphertext);
+        Ok(out)
+    }
+
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        if !is_encrypted(data) || data.len() < MAGIC.len() + NONCE_LEN {
+            return Err(anyhow!("Data is not encrypted"));
+        }
+
+        let (nonce, ciphertext) = data[MAGIC.len()..].split_at(NONCE_LEN);
+        self.cipher.decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow!("Decryption failed, wrong passphrase or corrupted file"))
+    }
+}
+
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+pub fn generate_salt() -> [u8; SALT_LEN] {
+    let mut salt = [0u8; SALT_LEN];
+    // A nonce is just random bytes, reuse the AEAD rng instead of pulling in rand
+    let a = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let b = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    salt[..NONCE_LEN].copy_from_slice(&a);
+    salt[NONCE_LEN..].copy_from_slice(&b[..SALT_LEN - NONCE_LEN]);
+    salt
+}
+
+#[cfg(test)]
+mod crypt_tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_decrypt_roundtrip() -> Result<()> {
+        let salt = generate_salt();
+        let cipher = Cipher::from_passphrase("correct horse", &salt)?;
+
+        let data = "fn main() { println!(\"секрет\"); }".as_bytes();
+        let encrypted = cipher.encrypt(data)?;
+
+        assert!(is_encrypted(&encrypted));
+        assert_ne!(&encrypted[MAGIC.len()..], data);
+        assert_eq!(cipher.decrypt(&encrypted)?, data);
+        Ok(())
+    }
+
+    #[test]
+    fn test_wrong_passphrase_fails() -> Result<()> {
+        let salt = generate_salt();
+        let encrypted = Cipher::from_passphrase("one", &salt)?.encrypt(b"text")?;
+        let other = Cipher::from_passphrase("two", &salt)?;
+
+        assert!(other.decrypt(&encrypted).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,240 @@
+use anyhow::Result;
+use globset::{Glob, GlobMatcher};
+use lsp_types::{Diagnostic, DiagnosticSeverity, NumberOrString, PublishDiagnosticsParams};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::utils::relative_to_current_dir;
+
+// Workspace rules hiding diagnostics, in .anycode/diagnostics.toml. A rule
+// hides the diagnostics matching all of its fields, a missing field
+// matches anything:
+//
+//     [[rule]]
+//     source = "eslint"
+//     code = "no-console"
+//     path = "scripts/**"
+//
+//     [[rule]]
+//     severity = "hint"
+//
+// `path` is a glob over the path relative to the workspace. The store
+// keeps what the servers sent, the rules apply when diagnostics are sent
+// to the clients, so changing them shows or hides diagnostics at once.
+
+const RULES_FILE: &str = "diagnostics.toml";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+    Hint,
+}
+
+impl Severity {
+    fn of(severity: Option<DiagnosticSeverity>) -> Option<Self> {
+        match severity? {
+            DiagnosticSeverity::ERROR => Some(Self::Error),
+            DiagnosticSeverity::WARNING => Some(Self::Warning),
+            DiagnosticSeverity::INFORMATION => Some(Self::Info),
+            DiagnosticSeverity::HINT => Some(Self::Hint),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct DiagnosticRule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    /// Glob over the workspace relative path
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub severity: Option<Severity>,
+}
+
+impl DiagnosticRule {
+    fn is_empty(&self) -> bool {
+        self.source.is_none() && self.code.is_none() && self.path.is_none() && self.severity.is_none()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct DiagnosticRules {
+    #[serde(default, rename = "rule")]
+    pub rules: Vec<DiagnosticRule>,
+}
+
+/// Rules with their path globs compiled
+struct Compiled {
+    rules: DiagnosticRules,
+    globs: Vec<Option<GlobMatcher>>,
+}
+
+impl Compiled {
+    fn new(rules: DiagnosticRules) -> Result<Self> {
+        let globs = rules.rules.iter()
+            .map(|rule| rule.path.as_deref().map(|p| Ok(Glob::new(p)?.compile_matcher())).transpose())
+            .collect::<Result<_>>()?;
+        Ok(Self { rules, globs })
+    }
+
+    fn hides(&self, path: &Path, diagnostic: &Diagnostic) -> bool {
+        let code = diagnostic.code.as_ref().map(|code| match code {
+            NumberOrString::Number(n) => n.to_string(),
+            NumberOrString::String(s) => s.clone(),
+        });
+        let severity = Severity::of(diagnostic.severity);
+
+        self.rules.rules.iter().zip(&self.globs).any(|(rule, glob)| {
+            !rule.is_empty()
+                && rule.source.as_ref().is_none_or(|s| diagnostic.source.as_ref() == Some(s))
+                && rule.code.as_ref().is_none_or(|c| code.as_ref() == Some(c))
+                && rule.severity.is_none_or(|s| severity == Some(s))
+                && glob.as_ref().is_none_or(|g| g.is_match(path))
+        })
+    }
+}
+
+static RULES: RwLock<Option<Arc<Compiled>>> = RwLock::new(None);
+
+fn rules_file() -> PathBuf {
+    crate::store::workspace_dir().join(RULES_FILE)
+}
+
+fn load(path: &Path) -> Result<DiagnosticRules> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => Ok(toml::from_str(&text)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(DiagnosticRules::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn compiled() -> Arc<Compiled> {
+    if let Some(compiled) = RULES.read().as_ref() {
+        return compiled.clone();
+    }
+    let path = rules_file();
+    let compiled = load(&path).and_then(Compiled::new).unwrap_or_else(|e| {
+        tracing::error!("Invalid diagnostic rules in {}: {}", path.display(), e);
+        Compiled { rules: DiagnosticRules::default(), globs: Vec::new() }
+    });
+    let compiled = Arc::new(compiled);
+    *RULES.write() = Some(compiled.clone());
+    compiled
+}
+
+/// The workspace rules, loaded on first use
+pub fn rules() -> DiagnosticRules {
+    compiled().rules.clone()
+}
+
+/// Replace the rules and persist them to .anycode/diagnostics.toml
+pub fn set_rules(rules: DiagnosticRules) -> Result<DiagnosticRules> {
+    let compiled = Compiled::new(rules)?;
+    let path = rules_file();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, toml::to_string(&compiled.rules)?)?;
+
+    let rules = compiled.rules.clone();
+    *RULES.write() = Some(Arc::new(compiled));
+    Ok(rules)
+}
+
+fn retain(compiled: &Compiled, params: &PublishDiagnosticsParams) -> (PublishDiagnosticsParams, usize) {
+    let path = crate::paths::uri_to_path(params.uri.as_str()).map(PathBuf::from).unwrap_or_default();
+    let path = relative_to_current_dir(&path).unwrap_or(path);
+
+    let mut shown = params.clone();
+    shown.diagnostics.retain(|d| !compiled.hides(&path, d));
+    let hidden = params.diagnostics.len() - shown.diagnostics.len();
+    (shown, hidden)
+}
+
+/// The diagnostics the rules leave, with the number hidden
+pub fn apply(params: &PublishDiagnosticsParams) -> (PublishDiagnosticsParams, usize) {
+    retain(&compiled(), params)
+}
+
+/// `lsp:diagnostics` payload: the diagnostics left with their plain text
+/// and the number of hidden ones
+pub fn to_json(params: &PublishDiagnosticsParams) -> Value {
+    let (shown, hidden) = apply(params);
+    let mut json = crate::rich_text::diagnostics_json(&shown);
+    json["hidden"] = hidden.into();
+    json
+}
+
+#[cfg(test)]
+mod diagnostic_filter_tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Diagnostics of a file at `path` in the workspace
+    fn params(path: &str) -> PublishDiagnosticsParams {
+        let path = std::env::current_dir().unwrap().join(path);
+        let range = json!({ "start": { "line": 0, "character": 0 }, "end": { "line": 0, "character": 1 } });
+        serde_json::from_value(json!({
+            "uri": crate::paths::file_uri(&path.to_string_lossy()),
+            "diagnostics": [
+                { "range": range, "severity": 2, "source": "eslint", "code": "no-console", "message": "console" },
+                { "range": range, "severity": 1, "source": "eslint", "code": "no-undef", "message": "undef" },
+                { "range": range, "severity": 4, "source": "ts", "code": 6133, "message": "unused" },
+            ],
+        })).unwrap()
+    }
+
+    fn messages(params: &PublishDiagnosticsParams) -> Vec<&str> {
+        params.diagnostics.iter().map(|d| d.message.as_str()).collect()
+    }
+
+    #[test]
+    fn test_rules() -> Result<()> {
+        let rules: DiagnosticRules = toml::from_str(r#"
+            [[rule]]
+            source = "eslint"
+            code = "no-console"
+            path = "scripts/**"
+
+            [[rule]]
+            code = "6133"
+
+            # Matches everything, ignored
+            [[rule]]
+        "#)?;
+        let compiled = Compiled::new(rules)?;
+
+        let (shown, hidden) = retain(&compiled, &params("scripts/build.js"));
+        assert_eq!((messages(&shown), hidden), (vec!["undef"], 2));
+        let (shown, hidden) = retain(&compiled, &params("src/app.js"));
+        assert_eq!((messages(&shown), hidden), (vec!["console", "undef"], 1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_severity_and_roundtrip() -> Result<()> {
+        let rules = DiagnosticRules {
+            rules: vec![DiagnosticRule { severity: Some(Severity::Hint), ..Default::default() }],
+        };
+        let text = toml::to_string(&rules)?;
+        assert_eq!(text.trim(), "[[rule]]\nseverity = \"hint\"");
+        assert_eq!(toml::from_str::<DiagnosticRules>(&text)?, rules);
+
+        let (shown, hidden) = retain(&Compiled::new(rules)?, &params("a.ts"));
+        assert_eq!((messages(&shown), hidden), (vec!["console", "undef"], 1));
+
+        let invalid = DiagnosticRules { rules: vec![DiagnosticRule { path: Some("a/[".into()), ..Default::default() }] };
+        assert!(Compiled::new(invalid).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+use serde::Serialize;
+use std::collections::HashMap;
+
+// Line changes of a buffer against the content it was last loaded from or
+// saved to, for the pending-change markers of the editor gutter. Lines are
+// compared whole with a Myers diff after trimming the common head and
+// tail. Edits too far apart to diff cheaply show as one modified block.
+
+/// Edit distance beyond which the middle part is reported as modified
+const MAX_EDIT_DISTANCE: usize = 1000;
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LineChangeKind {
+    Added,
+    Modified,
+    Removed,
+}
+
+/// Lines `start..end` of the buffer, 0-based. Removed lines have an empty
+/// range at the line that follows them.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct LineChange {
+    pub kind: LineChangeKind,
+    pub start: usize,
+    pub end: usize,
+    /// Saved lines replaced or removed by the change
+    pub removed: usize,
+}
+
+/// Changes turning `saved` into `current`
+pub fn line_changes(saved: &str, current: &str) -> Vec<LineChange> {
+    let (old, new) = line_ids(saved, current);
+
+    let head = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let tail = old[head..].iter().rev().zip(new[head..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old_mid, new_mid) = (&old[head..old.len() - tail], &new[head..new.len() - tail]);
+
+    let mut matched: Vec<(usize, usize)> = match myers(old_mid, new_mid, MAX_EDIT_DISTANCE) {
+        Some(pairs) => pairs.into_iter().map(|(i, j)| (i + head, j + head)).collect(),
+        None => Vec::new(),
+    };
+    // Sentinels around the differing middle
+    matched.insert(0, (head.wrapping_sub(1), head.wrapping_sub(1)));
+    matched.push((old.len() - tail, new.len() - tail));
+
+    let mut changes = Vec::new();
+    for pair in matched.windows(2) {
+        let ((i0, j0), (i1, j1)) = (pair[0], pair[1]);
+        let (old_start, new_start) = (i0.wrapping_add(1), j0.wrapping_add(1));
+        let (removed, added) = (i1 - old_start, j1 - new_start);
+        let kind = match (removed, added) {
+            (0, 0) => continue,
+            (0, _) => LineChangeKind::Added,
+            (_, 0) => LineChangeKind::Removed,
+            _ => LineChangeKind::Modified,
+        };
+        changes.push(LineChange { kind, start: new_start, end: j1, removed });
+    }
+    changes
+}
+
+/// Lines of both texts as ids, equal lines share an id. An empty text
+/// has no lines.
+fn line_ids<'a>(saved: &'a str, current: &'a str) -> (Vec<u32>, Vec<u32>) {
+    let mut ids: HashMap<&'a str, u32> = HashMap::new();
+    let mut id = |line: &'a str| {
+        let next = ids.len() as u32;
+        *ids.entry(line).or_insert(next)
+    };
+    let lines = |text: &'a str| (!text.is_empty()).then(|| text.split('\n')).into_iter().flatten();
+    let old = lines(saved).map(&mut id).collect();
+    let new = lines(current).map(&mut id).collect();
+    (old, new)
+}
+
+/// Matching line pairs of a shortest edit script, None past `max_d` edits
+fn myers(a: &[u32], b: &[u32], max_d: usize) -> Option<Vec<(usize, usize)>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = n + m;
+    let offset = max + 1;
+    // Furthest x reached on each diagonal k = x - y
+    let mut v = vec![0isize; (2 * max + 3) as usize];
+    // v before each step d, for the diagonals -d - 1 to d + 1
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+
+    let mut end = None;
+    'search: for d in 0..=max.min(max_d as isize) {
+        trace.push(v[(offset - d - 1) as usize..=(offset + d + 1) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let idx = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) { v[idx + 1] } else { v[idx - 1] + 1 };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx] = x;
+            if x >= n && y >= m {
+                end = Some(d);
+                break 'search;
+            }
+        }
+    }
+
+    let mut pairs = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for d in (0..=end?).rev() {
+        let v = &trace[d as usize];
+        let at = |k: isize| v[(k + d + 1) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) { k + 1 } else { k - 1 };
+        let prev_x = at(prev_k);
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            pairs.push((x as usize, y as usize));
+        }
+        if d > 0 {
+            (x, y) = (prev_x, prev_y);
+        }
+    }
+    pairs.reverse();
+    Some(pairs)
+}
+
+#[cfg(test)]
+mod dirty_diff_tests {
+    use super::*;
+    use LineChangeKind::*;
+
+    fn change(kind: LineChangeKind, start: usize, end: usize, removed: usize) -> LineChange {
+        LineChange { kind, start, end, removed }
+    }
+
+    #[test]
+    fn test_unchanged() {
+        assert!(line_changes("a\nb\n", "a\nb\n").is_empty());
+        assert!(line_changes("", "").is_empty());
+    }
+
+    #[test]
+    fn test_line_changes() {
+        let saved = "one\ntwo\nthree\nfour\nfive\n";
+        assert_eq!(line_changes(saved, "one\ntwo\nnew\nthree\nfour\nfive\n"), vec![change(Added, 2, 3, 0)]);
+        assert_eq!(line_changes(saved, "one\nthree\nfour\nfive\n"), vec![change(Removed, 1, 1, 1)]);
+        assert_eq!(line_changes(saved, "one\nTWO\nthree\nfour\nfive\n"), vec![change(Modified, 1, 2, 1)]);
+        assert_eq!(
+            line_changes(saved, "zero\none\ntwo\nthree\n4\n5\n6\n"),
+            vec![change(Added, 0, 1, 0), change(Modified, 4, 7, 2)],
+        );
+        // Removed at the end of the file
+        assert_eq!(line_changes("a\nb", "a"), vec![change(Removed, 1, 1, 1)]);
+        assert_eq!(line_changes("", "x\ny"), vec![change(Added, 0, 2, 0)]);
+        assert_eq!(line_changes("x", ""), vec![change(Removed, 0, 0, 1)]);
+    }
+
+    #[test]
+    fn test_repeated_lines() {
+        let saved = "}\n}\nfn a() {\n}\n}\n";
+        assert_eq!(line_changes(saved, "}\n}\nfn a() {\n    b();\n}\n}\n"), vec![change(Added, 3, 4, 0)]);
+    }
+
+    #[test]
+    fn test_distant_edits() {
+        let saved: String = (0..5000).map(|i| format!("{}\n", i)).collect();
+        let current: String = (0..5000).map(|i| format!("{}\n", if i % 2 == 0 { i } else { i + 100_000 })).collect();
+        // Past the edit distance limit the middle is one block
+        assert_eq!(line_changes(&saved, &current), vec![change(Modified, 1, 5000, 4999)]);
+    }
+}
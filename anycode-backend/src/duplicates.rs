@@ -0,0 +1,102 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::utils::relative_to_current_dir;
+
+/// Files sharing size and blake3 hash
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct DuplicateGroup {
+    pub size: u64,
+    pub hash: String,
+    pub files: Vec<String>,
+}
+
+impl DuplicateGroup {
+    /// Bytes that removing all copies but one would free
+    pub fn wasted(&self) -> u64 {
+        self.size * (self.files.len() as u64 - 1)
+    }
+}
+
+fn display_path(path: &Path) -> String {
+    relative_to_current_dir(path)
+        .unwrap_or_else(|| path.to_path_buf())
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Groups of identical files, the most wasted space first. Only files
+/// sharing their size with another one are hashed, empty files are skipped.
+/// `progress` gets the hashed and total counts of the files to hash.
+pub fn find_duplicates(files: Vec<PathBuf>, mut progress: impl FnMut(usize, usize)) -> Vec<DuplicateGroup> {
+    let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    for file in files {
+        if let Ok(meta) = std::fs::metadata(&file) && meta.is_file() && meta.len() > 0 {
+            by_size.entry(meta.len()).or_default().push(file);
+        }
+    }
+
+    let candidates: Vec<(u64, PathBuf)> = by_size.into_iter()
+        .filter(|(_, files)| files.len() > 1)
+        .flat_map(|(size, files)| files.into_iter().map(move |f| (size, f)))
+        .collect();
+
+    let total = candidates.len();
+    let mut by_hash: HashMap<(u64, String), Vec<String>> = HashMap::new();
+    for (done, (size, file)) in candidates.into_iter().enumerate() {
+        crate::power::throttle();
+        if let Ok(hash) = crate::file_hash::hash(&file).map(|h| h.to_hex().to_string()) {
+            by_hash.entry((size, hash)).or_default().push(display_path(&file));
+        }
+        progress(done + 1, total);
+    }
+
+    let mut groups: Vec<DuplicateGroup> = by_hash.into_iter()
+        .filter(|(_, files)| files.len() > 1)
+        .map(|((size, hash), mut files)| {
+            files.sort();
+            DuplicateGroup { size, hash, files }
+        })
+        .collect();
+
+    groups.sort_by(|a, b| b.wasted().cmp(&a.wasted()).then_with(|| a.files.cmp(&b.files)));
+    groups
+}
+
+#[cfg(test)]
+mod duplicates_tests {
+    use super::*;
+
+    #[test]
+    fn test_find_duplicates() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let write = |name: &str, content: &str| -> anyhow::Result<PathBuf> {
+            let path = dir.path().join(name);
+            std::fs::write(&path, content)?;
+            Ok(path)
+        };
+
+        let files = vec![
+            write("a.txt", "same content")?,
+            write("b.txt", "same content")?,
+            // Same size, different content
+            write("c.txt", "other conten")?,
+            write("big1", &"x".repeat(100))?,
+            write("big2", &"x".repeat(100))?,
+            write("empty1", "")?,
+            write("empty2", "")?,
+            write("unique", "unique")?,
+        ];
+
+        let mut calls = Vec::new();
+        let groups = find_duplicates(files, |done, total| calls.push((done, total)));
+
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].size, 100);
+        assert_eq!(groups[1].files.len(), 2);
+        assert!(groups[1].files[0].ends_with("a.txt") && groups[1].files[1].ends_with("b.txt"));
+        assert_eq!(calls.last(), Some(&(5, 5)));
+        Ok(())
+    }
+}
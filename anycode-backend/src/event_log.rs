@@ -0,0 +1,125 @@
+use parking_lot::Mutex;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::sync::OnceLock;
+
+// Recent workspace events, numbered in the order they were broadcast.
+// Files created, renamed or removed, focus and ignore changes and terminal
+// starts are recorded next to their broadcast, so a client connecting late
+// or reconnecting replays what it missed with `events:since`. The
+// sequence number goes out as the last argument of the broadcast, clients
+// keep the highest one they saw to ask from. Buffer edits are not
+// recorded, clients read the buffers when they open them.
+
+/// Events kept, a client further behind reloads its state instead
+const MAX_EVENTS: usize = 1000;
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct LoggedEvent {
+    pub seq: u64,
+    pub event: String,
+    pub data: Value,
+    pub time: i64,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Since {
+    pub events: Vec<LoggedEvent>,
+    /// Sequence number of the last recorded event, 0 before the first
+    pub last_seq: u64,
+    /// False when events after the requested one were already dropped
+    pub complete: bool,
+}
+
+#[derive(Default)]
+struct Log {
+    events: VecDeque<LoggedEvent>,
+    last_seq: u64,
+    /// Sequence number of the last dropped event
+    dropped: u64,
+}
+
+impl Log {
+    fn record(&mut self, event: &str, data: Value, capacity: usize) -> u64 {
+        self.last_seq += 1;
+        self.events.push_back(LoggedEvent {
+            seq: self.last_seq,
+            event: event.to_string(),
+            data,
+            time: chrono::Utc::now().timestamp_millis(),
+        });
+        while self.events.len() > capacity {
+            if let Some(dropped) = self.events.pop_front() {
+                self.dropped = dropped.seq;
+            }
+        }
+        self.last_seq
+    }
+
+    fn since(&self, seq: u64) -> Since {
+        Since {
+            events: self.events.iter().filter(|e| e.seq > seq).cloned().collect(),
+            last_seq: self.last_seq,
+            complete: seq >= self.dropped,
+        }
+    }
+}
+
+static LOG: OnceLock<Mutex<Log>> = OnceLock::new();
+
+fn log() -> &'static Mutex<Log> {
+    LOG.get_or_init(|| Mutex::new(Log::default()))
+}
+
+/// Record a broadcast workspace event, returns its sequence number
+pub fn record(event: &str, data: &impl Serialize) -> u64 {
+    let data = serde_json::to_value(data).unwrap_or(Value::Null);
+    log().lock().record(event, data, MAX_EVENTS)
+}
+
+/// Sequence number of the last recorded event, 0 before the first
+pub fn last_seq() -> u64 {
+    log().lock().last_seq
+}
+
+/// The events recorded after `seq`, oldest first
+pub fn since(seq: u64) -> Since {
+    log().lock().since(seq)
+}
+
+#[cfg(test)]
+mod event_log_tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_since() {
+        let mut log = Log::default();
+        assert_eq!(log.since(0), Since { events: vec![], last_seq: 0, complete: true });
+
+        assert_eq!(log.record("file:created", json!("/w/a.rs"), 3), 1);
+        log.record("dir:created", json!("/w/src"), 3);
+        log.record("terminal:started", json!({ "name": "t1" }), 3);
+
+        let since = log.since(1);
+        let events: Vec<&str> = since.events.iter().map(|e| e.event.as_str()).collect();
+        assert_eq!(events, ["dir:created", "terminal:started"]);
+        assert_eq!((since.last_seq, since.complete), (3, true));
+        assert!(log.since(3).events.is_empty());
+    }
+
+    #[test]
+    fn test_dropped_events() {
+        let mut log = Log::default();
+        for i in 0..5 {
+            log.record("file:created", json!(i), 3);
+        }
+        // Events 1 and 2 are gone
+        let behind = log.since(1);
+        assert!(!behind.complete);
+        assert_eq!(behind.events.first().map(|e| e.seq), Some(3));
+        assert!(log.since(2).complete);
+        assert_eq!(log.since(2).events.len(), 3);
+    }
+}
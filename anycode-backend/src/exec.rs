@@ -0,0 +1,103 @@
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+use crate::config::ExecConfig;
+
+// One-off commands run on behalf of clients and agents (run:command, the
+// MCP run_task tool). They all go through the same policy: the optional
+// allow and deny lists of programs and a timeout.
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Serialize, Clone)]
+pub struct CommandOutput {
+    /// Exit code, None when killed by a signal
+    pub status: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    pub elapsed_ms: u128,
+}
+
+/// Program name without directory and `.exe`, what the lists match against
+pub fn program_name(program: &str) -> String {
+    let name = Path::new(program).file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| program.to_string());
+    name.strip_suffix(".exe").map(str::to_string).unwrap_or(name)
+}
+
+/// Whether the policy lets `program` run
+pub fn check(program: &str, policy: Option<&ExecConfig>) -> Result<()> {
+    let Some(policy) = policy else { return Ok(()) };
+    let name = program_name(program);
+
+    if policy.deny.iter().flatten().any(|p| *p == name) {
+        return Err(anyhow!("{} is denied by the exec policy", name));
+    }
+    if let Some(allow) = &policy.allow && !allow.contains(&name) {
+        return Err(anyhow!("{} is not allowed by the exec policy", name));
+    }
+    Ok(())
+}
+
+/// Run a command line in the workspace root, without a shell, and wait
+/// for it within the policy timeout (or `timeout` when given)
+pub async fn run(command_line: &str, policy: Option<&ExecConfig>, timeout: Option<Duration>) -> Result<CommandOutput> {
+    let words = shell_words::split(command_line)?;
+    let (program, args) = words.split_first()
+        .ok_or_else(|| anyhow!("Empty command"))?;
+    check(program, policy)?;
+
+    let timeout = timeout
+        .or_else(|| policy.and_then(|p| p.timeout_secs).map(Duration::from_secs))
+        .unwrap_or(DEFAULT_TIMEOUT);
+
+    let _task = crate::status::task_started();
+    let cancel = CancellationToken::new();
+    let _progress = crate::progress::start("task", command_line, Some(cancel.clone()));
+    let start = Instant::now();
+    let mut command = tokio::process::Command::new(program);
+    command.args(args)
+        .current_dir(std::env::current_dir()?)
+        .kill_on_drop(true);
+
+    let output = tokio::select! {
+        output = tokio::time::timeout(timeout, command.output()) => output
+            .map_err(|_| anyhow!("Command timed out after {}s", timeout.as_secs()))??,
+        _ = cancel.cancelled() => return Err(anyhow!("Command cancelled")),
+    };
+
+    crate::output::write("tasks", &format!("$ {}", command_line));
+    crate::output::write("tasks", &output.status.to_string());
+
+    Ok(CommandOutput {
+        status: output.status.code(),
+        stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+        stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        elapsed_ms: start.elapsed().as_millis(),
+    })
+}
+
+#[cfg(test)]
+mod exec_tests {
+    use super::*;
+
+    #[test]
+    fn test_policy() {
+        let policy = ExecConfig {
+            allow: Some(vec!["cargo".into(), "rg".into()]),
+            deny: Some(vec!["rg".into()]),
+            timeout_secs: None,
+        };
+
+        assert!(check("cargo", None).is_ok());
+        assert!(check("rm", None).is_ok());
+        assert!(check("/usr/bin/cargo", Some(&policy)).is_ok());
+        assert!(check("cargo.exe", Some(&policy)).is_ok());
+        assert!(check("rg", Some(&policy)).is_err());
+        assert!(check("rm", Some(&policy)).is_err());
+    }
+}
@@ -0,0 +1,143 @@
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+pub use blake3::Hash;
+
+// Content hashes of files, blake3 over a memory map, shared by the change
+// detection of open buffers, the duplicate finder and the caches keyed by
+// file content. Hashes are kept for the last files asked about, keyed by
+// path, mtime and size, so a file hashed by one of them is not read again
+// by the next until it changes. The watcher drops the entries of changed
+// paths for writers that keep the mtime and size.
+
+/// Files whose hash is kept
+const CAPACITY: usize = 4096;
+
+struct Entry {
+    mtime: Option<SystemTime>,
+    len: u64,
+    hash: Hash,
+    /// Tick of the last use, the least recent is evicted first
+    used: u64,
+}
+
+#[derive(Default)]
+struct Cache {
+    entries: HashMap<PathBuf, Entry>,
+    tick: u64,
+}
+
+impl Cache {
+    fn get(&mut self, path: &Path, meta: &std::fs::Metadata) -> Option<Hash> {
+        self.tick += 1;
+        let entry = self.entries.get_mut(path)?;
+        if entry.mtime != meta.modified().ok() || entry.len != meta.len() {
+            return None;
+        }
+        entry.used = self.tick;
+        Some(entry.hash)
+    }
+
+    fn insert(&mut self, path: PathBuf, meta: &std::fs::Metadata, hash: Hash) {
+        self.tick += 1;
+        if self.entries.len() >= CAPACITY && !self.entries.contains_key(&path) {
+            let oldest = self.entries.iter().min_by_key(|(_, e)| e.used).map(|(p, _)| p.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        let entry = Entry { mtime: meta.modified().ok(), len: meta.len(), hash, used: self.tick };
+        self.entries.insert(path, entry);
+    }
+}
+
+static CACHE: Mutex<Option<Cache>> = Mutex::new(None);
+
+fn with_cache<T>(f: impl FnOnce(&mut Cache) -> T) -> T {
+    f(CACHE.lock().get_or_insert_with(Cache::default))
+}
+
+/// Hash of the content of a file, from the cache while its mtime and size
+/// are the same. Large files are mapped instead of read.
+pub fn hash(path: &Path) -> std::io::Result<Hash> {
+    let path = crate::paths::absolute(path);
+    let meta = std::fs::metadata(&path)?;
+    if let Some(hash) = with_cache(|cache| cache.get(&path, &meta)) {
+        return Ok(hash);
+    }
+
+    let hash = blake3::Hasher::new().update_mmap(&path)?.finalize();
+    with_cache(|cache| cache.insert(path, &meta, hash));
+    Ok(hash)
+}
+
+/// Hash of `bytes` just read from `path`, cached for the next callers
+pub fn remember(path: &Path, meta: &std::fs::Metadata, bytes: &[u8]) -> Hash {
+    let hash = blake3::hash(bytes);
+    with_cache(|cache| cache.insert(crate::paths::absolute(path), meta, hash));
+    hash
+}
+
+/// Forget the hashes of `path` and the files under it
+pub fn invalidate(path: &Path) {
+    let path = crate::paths::absolute(path);
+    with_cache(|cache| cache.entries.retain(|p, _| !p.starts_with(&path)));
+}
+
+#[cfg(test)]
+mod file_hash_tests {
+    use super::*;
+
+    #[test]
+    fn test_hash() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let a = dir.path().join("a.txt");
+        let b = dir.path().join("b.txt");
+        std::fs::write(&a, "same")?;
+        std::fs::write(&b, "same")?;
+
+        assert_eq!(hash(&a)?, blake3::hash(b"same"));
+        assert_eq!(hash(&a)?, hash(&b)?);
+
+        std::fs::write(&a, "other content")?;
+        assert_eq!(hash(&a)?, blake3::hash(b"other content"));
+        assert!(hash(&dir.path().join("missing.txt")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalidate() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("a.txt");
+        std::fs::write(&path, "one")?;
+        let meta = std::fs::metadata(&path)?;
+
+        // A stale entry with the current mtime and size wins until dropped
+        with_cache(|cache| cache.insert(path.clone(), &meta, blake3::hash(b"two")));
+        assert_eq!(hash(&path)?, blake3::hash(b"two"));
+        invalidate(dir.path());
+        assert_eq!(hash(&path)?, blake3::hash(b"one"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_evicts_least_recent() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("a.txt");
+        std::fs::write(&path, "a")?;
+        let meta = std::fs::metadata(&path)?;
+
+        let mut cache = Cache::default();
+        for i in 0..CAPACITY {
+            cache.insert(PathBuf::from(format!("/f{}", i)), &meta, blake3::hash(b"a"));
+        }
+        assert!(cache.get(Path::new("/f0"), &meta).is_some());
+        cache.insert(path, &meta, blake3::hash(b"a"));
+        assert_eq!(cache.entries.len(), CAPACITY);
+        assert!(cache.entries.contains_key(Path::new("/f0")));
+        assert!(!cache.entries.contains_key(Path::new("/f1")));
+        Ok(())
+    }
+}
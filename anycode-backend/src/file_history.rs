@@ -0,0 +1,233 @@
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+
+use crate::dirty_diff::{self, LineChange};
+
+// Content of the workspace files before each write made through the
+// backend (saves, sets, creates, renames), numbered with the sequence of
+// the event log at the time. The workspace at an earlier point, a
+// sequence number or a time, is what the first write after it found on
+// disk, `workspace:diffSince` compares it to the files now. Changes of
+// other programs are not seen, files over `MAX_TEXT` or not UTF-8 are
+// only known as modified.
+
+/// Larger files are not kept
+const MAX_TEXT: u64 = 1024 * 1024;
+/// Kept content, the oldest writes are dropped beyond it
+const MAX_BYTES: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Content {
+    Missing,
+    Text(Arc<str>),
+    Unknown,
+}
+
+impl Content {
+    fn read(path: &Path) -> Self {
+        match std::fs::metadata(path) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Content::Missing,
+            Ok(meta) if meta.is_file() && meta.len() <= MAX_TEXT => match std::fs::read_to_string(path) {
+                Ok(text) => Content::Text(text.into()),
+                Err(_) => Content::Unknown,
+            },
+            _ => Content::Unknown,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Content::Text(text) => text.len(),
+            _ => 0,
+        }
+    }
+
+    fn text(&self) -> Option<String> {
+        match self {
+            Content::Text(text) => Some(text.to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// A point of the session, by event sequence number or time in ms
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Point {
+    Seq(u64),
+    Time(i64),
+}
+
+struct Write {
+    seq: u64,
+    time: i64,
+    path: String,
+    before: Content,
+}
+
+impl Write {
+    fn after(&self, point: Point) -> bool {
+        match point {
+            // The write came after the event it was numbered with
+            Point::Seq(seq) => self.seq >= seq,
+            Point::Time(time) => self.time >= time,
+        }
+    }
+}
+
+#[derive(Default)]
+struct History {
+    writes: VecDeque<Write>,
+    bytes: usize,
+    dropped: Option<Write>,
+}
+
+impl History {
+    fn record(&mut self, write: Write, max_bytes: usize) {
+        self.bytes += write.before.len();
+        self.writes.push_back(write);
+        while self.bytes > max_bytes && self.writes.len() > 1 {
+            if let Some(mut dropped) = self.writes.pop_front() {
+                self.bytes -= dropped.before.len();
+                dropped.before = Content::Unknown;
+                self.dropped = Some(dropped);
+            }
+        }
+    }
+
+    /// Content at `point` of the files written since, and whether writes
+    /// after it were dropped
+    fn before(&self, point: Point) -> (BTreeMap<String, Content>, bool) {
+        let mut before = BTreeMap::new();
+        for write in self.writes.iter().filter(|w| w.after(point)) {
+            before.entry(write.path.clone()).or_insert_with(|| write.before.clone());
+        }
+        let complete = !self.dropped.as_ref().is_some_and(|w| w.after(point));
+        (before, complete)
+    }
+}
+
+static HISTORY: OnceLock<Mutex<History>> = OnceLock::new();
+
+fn history() -> &'static Mutex<History> {
+    HISTORY.get_or_init(|| Mutex::new(History::default()))
+}
+
+/// Keep the content of the file at `path` before it is written
+pub fn record(path: &Path) {
+    let write = Write {
+        seq: crate::event_log::last_seq(),
+        time: chrono::Utc::now().timestamp_millis(),
+        path: path.to_string_lossy().to_string(),
+        before: Content::read(path),
+    };
+    history().lock().record(write, MAX_BYTES);
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Added,
+    Removed,
+    Modified,
+}
+
+fn status(before: &Content, now: &Content) -> Option<Status> {
+    match (before, now) {
+        (Content::Missing, Content::Missing) => None,
+        (Content::Missing, _) => Some(Status::Added),
+        (_, Content::Missing) => Some(Status::Removed),
+        (Content::Text(a), Content::Text(b)) if a == b => None,
+        _ => Some(Status::Modified),
+    }
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct FileChange {
+    pub path: String,
+    pub status: Status,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct ChangesSince {
+    pub files: Vec<FileChange>,
+    /// False when writes after the point were dropped, files may be missing
+    pub complete: bool,
+}
+
+/// Files added, removed or modified since `point`, by path
+pub fn changes_since(point: Point) -> ChangesSince {
+    let (before, complete) = history().lock().before(point);
+    let files = before.iter()
+        .filter_map(|(path, before)| {
+            let status = status(before, &Content::read(Path::new(path)))?;
+            Some(FileChange { path: path.clone(), status })
+        })
+        .collect();
+    ChangesSince { files, complete }
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct FileDiff {
+    pub path: String,
+    pub status: Status,
+    /// Null when the file was missing or its content is not known
+    pub before: Option<String>,
+    pub after: Option<String>,
+    /// Lines of `after` changed from `before`
+    pub changes: Vec<LineChange>,
+}
+
+/// Diff of one file since `point`, None when it is unchanged
+pub fn diff_since(point: Point, path: &str) -> Option<FileDiff> {
+    let before = history().lock().before(point).0.remove(path)?;
+    let now = Content::read(Path::new(path));
+    let status = status(&before, &now)?;
+    let (before, after) = (before.text(), now.text());
+    let changes = dirty_diff::line_changes(before.as_deref().unwrap_or_default(), after.as_deref().unwrap_or_default());
+    Some(FileDiff { path: path.to_string(), status, before, after, changes })
+}
+
+#[cfg(test)]
+mod file_history_tests {
+    use super::*;
+
+    fn write(seq: u64, path: &str, before: Content) -> Write {
+        Write { seq, time: seq as i64 * 1000, path: path.to_string(), before }
+    }
+
+    #[test]
+    fn test_before_point() {
+        let mut history = History::default();
+        history.record(write(1, "/w/a.rs", Content::Text("v1".into())), 100);
+        history.record(write(3, "/w/a.rs", Content::Text("v2".into())), 100);
+        history.record(write(3, "/w/b.rs", Content::Missing), 100);
+
+        let (before, complete) = history.before(Point::Seq(0));
+        assert!(complete);
+        assert_eq!(before["/w/a.rs"], Content::Text("v1".into()));
+        assert_eq!(before["/w/b.rs"], Content::Missing);
+
+        let (before, _) = history.before(Point::Time(2000));
+        assert_eq!(before["/w/a.rs"], Content::Text("v2".into()));
+        assert!(history.before(Point::Seq(4)).0.is_empty());
+
+        // The first write is dropped for room
+        history.record(write(5, "/w/c.rs", Content::Text("x".repeat(97).into())), 100);
+        assert!(!history.before(Point::Seq(0)).1);
+        assert!(history.before(Point::Seq(2)).1);
+    }
+
+    #[test]
+    fn test_status() {
+        let text = |s: &str| Content::Text(s.into());
+        assert_eq!(status(&Content::Missing, &text("a")), Some(Status::Added));
+        assert_eq!(status(&text("a"), &Content::Missing), Some(Status::Removed));
+        assert_eq!(status(&text("a"), &text("b")), Some(Status::Modified));
+        assert_eq!(status(&Content::Unknown, &text("b")), Some(Status::Modified));
+        assert_eq!(status(&text("a"), &text("a")), None);
+        assert_eq!(status(&Content::Missing, &Content::Missing), None);
+    }
+}
@@ -0,0 +1,175 @@
+use parking_lot::{Mutex, RwLock};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use crate::utils::relative_to_current_dir;
+use anycode_search::ignore::is_ignored_path;
+
+/// Minimum interval between progress reports of a scan
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Serialize, Clone, Default, PartialEq)]
+pub struct ScanProgress {
+    pub files: usize,
+    pub skipped_dirs: usize,
+    pub done: bool,
+}
+
+/// Workspace files for quick-open, filled by the startup scan and kept
+/// up to date by the watcher. Paths are relative to the workspace root.
+#[derive(Default)]
+struct Index {
+    files: RwLock<BTreeSet<String>>,
+    progress: Mutex<ScanProgress>,
+}
+
+static INDEX: OnceLock<Index> = OnceLock::new();
+
+fn index() -> &'static Index {
+    INDEX.get_or_init(Index::default)
+}
+
+fn key(path: &Path) -> String {
+    let path = crate::paths::absolute(path);
+    relative_to_current_dir(&path)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Enumerate the not ignored files below `root`. `on_progress` is called
+/// every PROGRESS_INTERVAL while walking and once at the end.
+pub fn walk(root: &Path, mut on_progress: impl FnMut(&ScanProgress)) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut progress = ScanProgress::default();
+    let mut last = Instant::now();
+    let mut dirs = vec![root.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        #[cfg(test)]
+        crate::fixtures::fs_delay();
+        crate::power::throttle();
+
+        let Ok(entries) = std::fs::read_dir(&dir) else { continue };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+
+            if is_ignored_path(&path) {
+                if is_dir {
+                    progress.skipped_dirs += 1;
+                }
+                continue;
+            }
+
+            if is_dir {
+                dirs.push(path);
+            } else {
+                files.push(path);
+                progress.files += 1;
+            }
+        }
+
+        if last.elapsed() >= PROGRESS_INTERVAL {
+            last = Instant::now();
+            on_progress(&progress);
+        }
+    }
+
+    progress.done = true;
+    on_progress(&progress);
+    files
+}
+
+/// Scan the workspace into the index, reporting progress as it goes
+pub fn prime(root: &Path, mut on_progress: impl FnMut(&ScanProgress)) {
+    let index = index();
+    let files = walk(root, |progress| {
+        *index.progress.lock() = progress.clone();
+        on_progress(progress);
+    });
+
+    let mut indexed = index.files.write();
+    indexed.extend(files.iter().map(|f| key(f)));
+}
+
+/// Walk the workspace again and replace the index, after the ignore rules
+/// changed. Put off while on battery.
+pub fn rescan(root: &Path) {
+    if crate::power::defer_rescan(root) {
+        return;
+    }
+    let files: BTreeSet<String> = walk(root, |_| {}).iter().map(|f| key(f)).collect();
+    *index().files.write() = files;
+}
+
+pub fn progress() -> ScanProgress {
+    index().progress.lock().clone()
+}
+
+pub fn files() -> Vec<String> {
+    index().files.read().iter().cloned().collect()
+}
+
+/// Run `f` over the indexed files without copying them
+pub fn with_files<R>(f: impl FnOnce(&BTreeSet<String>) -> R) -> R {
+    f(&index().files.read())
+}
+
+pub fn add(path: &Path) {
+    if path.is_file() && !is_ignored_path(path) {
+        index().files.write().insert(key(path));
+    }
+}
+
+pub fn remove(path: &Path) {
+    let key = key(path);
+    let mut files = index().files.write();
+    // A removed directory takes its files with it
+    let prefix = format!("{}{}", key, std::path::MAIN_SEPARATOR);
+    files.retain(|f| f != &key && !f.starts_with(&prefix));
+}
+
+/// Move a renamed file, or all the files of a renamed directory
+pub fn rename(from: &Path, to: &Path) {
+    let (from, to) = (key(from), key(to));
+    let mut files = index().files.write();
+    let prefix = format!("{}{}", from, std::path::MAIN_SEPARATOR);
+
+    let moved: Vec<String> = files.iter()
+        .filter(|f| **f == from || f.starts_with(&prefix))
+        .cloned()
+        .collect();
+    for file in moved {
+        files.remove(&file);
+        files.insert(format!("{}{}", to, &file[from.len()..]));
+    }
+}
+
+#[cfg(test)]
+mod file_index_tests {
+    use super::*;
+
+    #[test]
+    fn test_walk_counts_files_and_skipped_dirs() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir_all(dir.path().join("src/nested"))?;
+        std::fs::create_dir_all(dir.path().join(".git/objects"))?;
+        std::fs::write(dir.path().join("src/main.rs"), "")?;
+        std::fs::write(dir.path().join("src/nested/lib.rs"), "")?;
+        std::fs::write(dir.path().join(".git/objects/x"), "")?;
+        std::fs::write(dir.path().join("logo.png"), "")?;
+
+        let mut reports = Vec::new();
+        let files = walk(dir.path(), |p| reports.push(p.clone()));
+
+        assert_eq!(files.len(), 2);
+        let last = reports.last().unwrap();
+        assert_eq!((last.files, last.skipped_dirs, last.done), (2, 1, true));
+        Ok(())
+    }
+}
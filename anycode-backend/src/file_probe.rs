@@ -0,0 +1,187 @@
+use serde::Serialize;
+use std::io::Read;
+use std::path::Path;
+
+// Checks of a file before it is read into a buffer. Binary content is
+// told from the first bytes, a NUL byte or mostly control characters, and
+// files over the size limit are not read at all. Text that is not UTF-8,
+// UTF-16 with a BOM or else taken for Latin-1, opens converted with
+// `force`. The clients get what was found instead of the content, to show
+// a viewer or offer to open anyway.
+
+/// Size limit of file:open without `[open] max_size`
+pub const DEFAULT_MAX_SIZE: u64 = 16 * 1024 * 1024;
+/// Bytes looked at for binary content
+const SNIFF_LEN: usize = 8192;
+/// Share of control characters from which content counts as binary
+const MAX_CONTROL_RATIO: f64 = 0.1;
+
+/// A file that is not opened as text
+#[derive(Debug, Serialize, Clone)]
+pub struct NotText {
+    pub path: String,
+    pub size: u64,
+    pub binary: bool,
+    pub too_large: bool,
+    /// Encoding of text that is not UTF-8, "utf-16le", "utf-16be" or "latin-1"
+    pub encoding: Option<&'static str>,
+    /// Guessed from the extension
+    pub mime: String,
+}
+
+impl NotText {
+    /// Answer in place of the content, `path` as the client asked for it
+    pub fn response(&self, path: &str) -> serde_json::Value {
+        serde_json::json!({
+            "path": path, "size": self.size, "binary": self.binary,
+            "too_large": self.too_large, "encoding": self.encoding, "mime": self.mime, "success": true
+        })
+    }
+}
+
+impl std::fmt::Display for NotText {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.binary {
+            write!(f, "{} is a binary file ({})", self.path, self.mime)
+        } else if self.too_large {
+            write!(f, "{} is too large to open ({} bytes)", self.path, self.size)
+        } else {
+            write!(f, "{} is not UTF-8 ({}), open it anyway to convert it", self.path, self.encoding.unwrap_or_default())
+        }
+    }
+}
+
+impl std::error::Error for NotText {}
+
+/// A NUL byte or mostly control characters in the first bytes of a file,
+/// tabs, line breaks, form feeds and escapes aside. UTF-16 with a BOM is
+/// text.
+pub fn looks_binary(bytes: &[u8]) -> bool {
+    let head = &bytes[..bytes.len().min(SNIFF_LEN)];
+    if utf16_bom(head).is_some() {
+        return false;
+    }
+    if head.contains(&0) {
+        return true;
+    }
+    let control = head.iter()
+        .filter(|&&b| (b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | 0x0c | 0x1b)) || b == 0x7f)
+        .count();
+    control as f64 > head.len() as f64 * MAX_CONTROL_RATIO
+}
+
+/// Encoding of text that is not UTF-8, None for UTF-8. A character cut at
+/// the end of `bytes` is not counted when they are `cut` from a longer file.
+pub fn encoding(bytes: &[u8], cut: bool) -> Option<&'static str> {
+    if let Some(encoding) = utf16_bom(bytes) {
+        return Some(encoding);
+    }
+    match std::str::from_utf8(bytes) {
+        Err(e) if e.error_len().is_some() || !cut => Some("latin-1"),
+        _ => None,
+    }
+}
+
+/// The content of a file as text: UTF-8 as it is, UTF-16 after its BOM,
+/// anything else as Latin-1
+pub fn decode(bytes: &[u8]) -> String {
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return text.to_string();
+    }
+    let utf16 = |to_u16: fn([u8; 2]) -> u16| {
+        let units = bytes[2..].chunks(2).map(|pair| to_u16([pair[0], *pair.get(1).unwrap_or(&0)]));
+        char::decode_utf16(units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)).collect()
+    };
+    match utf16_bom(bytes) {
+        Some("utf-16le") => utf16(u16::from_le_bytes),
+        Some(_) => utf16(u16::from_be_bytes),
+        None => bytes.iter().map(|&b| b as char).collect(),
+    }
+}
+
+fn utf16_bom(bytes: &[u8]) -> Option<&'static str> {
+    match bytes {
+        [0xff, 0xfe, ..] => Some("utf-16le"),
+        [0xfe, 0xff, ..] => Some("utf-16be"),
+        _ => None,
+    }
+}
+
+/// Why the file at `path` can't be opened as text as it is, None when it
+/// can. Files over `max_size` are reported without being read.
+pub fn check(path: &Path, max_size: Option<u64>) -> std::io::Result<Option<NotText>> {
+    let size = std::fs::metadata(path)?.len();
+    let too_large = max_size.is_some_and(|max| size > max);
+
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    std::fs::File::open(path)?.take(SNIFF_LEN as u64).read_to_end(&mut head)?;
+    let binary = looks_binary(&head);
+    let encoding = if binary { None } else { encoding(&head, size > head.len() as u64) };
+
+    if !binary && !too_large && encoding.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(NotText {
+        path: path.to_string_lossy().to_string(),
+        size,
+        binary,
+        too_large,
+        encoding,
+        mime: mime_guess::from_path(path).first_or_octet_stream().to_string(),
+    }))
+}
+
+#[cfg(test)]
+mod file_probe_tests {
+    use super::*;
+
+    #[test]
+    fn test_looks_binary() {
+        assert!(!looks_binary(b"fn main() {}\n"));
+        assert!(!looks_binary("caf\u{e9} \u{1f600}".as_bytes()));
+        assert!(looks_binary(b"\x89PNG\r\n\x1a\n\x00\x00"));
+        assert!(looks_binary(b"\x01\x02\x03\x04abcdef"));
+        assert!(!looks_binary(b"caf\xe9 au lait"));
+        assert!(!looks_binary(b"\x1b[31mred\x1b[0m\r\n\x0c"));
+        assert!(!looks_binary(b"\xff\xfeh\x00i\x00"));
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(encoding("caf\u{e9}".as_bytes(), false), None);
+        // A character cut by the sniffed length
+        assert_eq!(encoding(&"\u{1f600}".as_bytes()[..2], true), None);
+        assert_eq!(encoding(b"caf\xe9", false), Some("latin-1"));
+        assert_eq!(encoding(b"\xfe\xff\x00h", false), Some("utf-16be"));
+
+        assert_eq!(decode(b"caf\xe9 au lait"), "caf\u{e9} au lait");
+        assert_eq!(decode(b"\xff\xfeh\x00\xe9\x00"), "h\u{e9}");
+        assert_eq!(decode(b"\xfe\xff\x00h\x00\xe9"), "h\u{e9}");
+        assert_eq!(decode("caf\u{e9}".as_bytes()), "caf\u{e9}");
+    }
+
+    #[test]
+    fn test_check() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let text = dir.path().join("notes.txt");
+        let image = dir.path().join("logo.png");
+        std::fs::write(&text, "hello\n")?;
+        std::fs::write(&image, b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR")?;
+
+        assert!(check(&text, None)?.is_none());
+        let large = check(&text, Some(3))?.unwrap();
+        assert!(large.too_large && !large.binary);
+        assert_eq!(large.size, 6);
+
+        let binary = check(&image, None)?.unwrap();
+        assert!(binary.binary && !binary.too_large);
+        assert_eq!(binary.mime, "image/png");
+
+        let latin1 = dir.path().join("menu.txt");
+        std::fs::write(&latin1, b"caf\xe9\n")?;
+        let converted = check(&latin1, None)?.unwrap();
+        assert!(!converted.binary && !converted.too_large);
+        assert_eq!(converted.encoding, Some("latin-1"));
+        Ok(())
+    }
+}
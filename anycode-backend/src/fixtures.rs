@@ -0,0 +1,221 @@
+// Synthetic repositories and a slow filesystem for performance tests of
+// search, the workspace walk and the watcher. The large scale tests are
+// ignored by default, run them with
+// `cargo test --release fixtures -- --ignored --nocapture`.
+// Also helpers the tests of several modules share.
+
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+/// Line every `needle_every`-th generated file contains once
+pub const NEEDLE: &str = "synthetic_needle_marker";
+
+/// Files of the huge repository, overridden with ANYCODE_BENCH_FILES
+const HUGE_FILES: usize = 100_000;
+
+#[derive(Debug, Clone)]
+pub struct RepoSpec {
+    pub files: usize,
+    /// Directory levels above the files
+    pub depth: usize,
+    /// Subdirectories per directory
+    pub fanout: usize,
+    /// Approximate size of each file in bytes
+    pub file_size: usize,
+    pub needle_every: usize,
+}
+
+impl Default for RepoSpec {
+    fn default() -> Self {
+        Self { files: 100, depth: 2, fanout: 4, file_size: 256, needle_every: 10 }
+    }
+}
+
+impl RepoSpec {
+    pub fn huge() -> Self {
+        let files = std::env::var("ANYCODE_BENCH_FILES").ok()
+            .and_then(|n| n.parse().ok())
+            .unwrap_or(HUGE_FILES);
+        Self { files, depth: 4, fanout: 10, file_size: 1024, needle_every: 100 }
+    }
+
+    /// Directory of the i-th file, relative to the root
+    fn dir_of(&self, i: usize) -> PathBuf {
+        let mut dir = PathBuf::new();
+        let mut n = i;
+        for level in 0..self.depth {
+            dir.push(format!("d{}_{}", level, n % self.fanout));
+            n /= self.fanout;
+        }
+        dir
+    }
+}
+
+#[derive(Debug)]
+pub struct SyntheticRepo {
+    pub files: Vec<PathBuf>,
+    /// Files containing NEEDLE
+    pub needles: usize,
+}
+
+/// Write the repository described by `spec` below `root`. The content only
+/// depends on the spec so runs are comparable.
+pub fn generate(root: &Path, spec: &RepoSpec) -> Result<SyntheticRepo> {
+    let mut files = Vec::with_capacity(spec.files);
+    let mut needles = 0;
+
+    for i in 0..spec.files {
+        let dir = root.join(spec.dir_of(i));
+        std::fs::create_dir_all(&dir)?;
+
+        let mut content = String::with_capacity(spec.file_size + 64);
+        if spec.needle_every > 0 && i % spec.needle_every == 0 {
+            content.push_str(NEEDLE);
+            content.push('\n');
+            needles += 1;
+        }
+        let mut line = 0;
+        while content.len() < spec.file_size {
+            content.push_str(&format!("fn item_{}_{}() {{ let value = {}; }}\n", i, line, i * line));
+            line += 1;
+        }
+
+        let path = dir.join(format!("file_{}.rs", i));
+        std::fs::write(&path, content)?;
+        files.push(path);
+    }
+
+    Ok(SyntheticRepo { files, needles })
+}
+
+/// `text` after `edits`, applied in order the way the editor does
+pub fn apply_edits(text: &str, edits: &[crate::services::Edit]) -> String {
+    use crate::services::Operation;
+    let mut code = crate::code::Code::from_str(text);
+    for edit in edits {
+        let start = code.utf16_to_char_offset(edit.start);
+        match edit.operation {
+            Operation::Insert => code.insert_text_at(&edit.text, start),
+            Operation::Remove => code.remove_text2(start, start + edit.text.chars().count()),
+        }
+    }
+    code.text.to_string()
+}
+
+/// Slows search, the walk and the workspace scan down while alive
+pub use anycode_search::slow_fs::SlowFs;
+pub use anycode_search::slow_fs::delay as fs_delay;
+
+#[cfg(test)]
+mod fixtures_tests {
+    use super::*;
+    use crate::search::{dir_search, FileSearchResult};
+    use std::time::{Duration, Instant};
+    use tokio::sync::mpsc;
+    use tokio_util::sync::CancellationToken;
+
+    async fn search(root: &Path, pattern: &str, cancel: CancellationToken) -> Vec<FileSearchResult> {
+        let (tx, mut rx) = mpsc::channel(1024);
+        let root = root.to_path_buf();
+        let pattern = pattern.to_string();
+        tokio::spawn(async move { dir_search(&root, &pattern, cancel, tx).await });
+
+        let mut results = Vec::new();
+        while let Some(result) = rx.recv().await {
+            results.push(result);
+        }
+        results
+    }
+
+    #[test]
+    fn test_generate_layout() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let spec = RepoSpec::default();
+        let repo = generate(dir.path(), &spec)?;
+
+        assert_eq!(repo.files.len(), 100);
+        assert_eq!(repo.needles, 10);
+        assert_eq!(crate::file_index::walk(dir.path(), |_| {}).len(), 100);
+
+        let first = repo.files[0].strip_prefix(dir.path())?;
+        assert_eq!(first.components().count(), spec.depth + 1);
+        assert!(std::fs::metadata(&repo.files[1])?.len() as usize >= spec.file_size);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn bench_search_huge_repo() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let repo = generate(dir.path(), &RepoSpec::huge())?;
+
+        let start = Instant::now();
+        let results = search(dir.path(), NEEDLE, CancellationToken::new()).await;
+        println!("search {} files: {:?}", repo.files.len(), start.elapsed());
+
+        assert_eq!(results.len(), repo.needles);
+        Ok(())
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_walk_huge_repo() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let repo = generate(dir.path(), &RepoSpec::huge())?;
+
+        let start = Instant::now();
+        let mut reports = 0;
+        let files = crate::file_index::walk(dir.path(), |_| reports += 1);
+        println!("walk {} files: {:?}, {} progress reports", files.len(), start.elapsed(), reports);
+
+        let start = Instant::now();
+        let listing = crate::services::list_dir(dir.path(), "", usize::MAX, None)?;
+        println!("list root: {:?}", start.elapsed());
+
+        assert_eq!(files.len(), repo.files.len());
+        assert_eq!(listing.dirs.len(), RepoSpec::huge().fanout);
+        Ok(())
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_watcher_rename_pairing() {
+        use crate::watcher::{Arrival, RenamePairer};
+
+        let files = RepoSpec::huge().files;
+        let mut pairer = RenamePairer::default();
+        let now = Instant::now();
+
+        let start = Instant::now();
+        for i in 0..files {
+            pairer.departed(Path::new(&format!("/w/a/{}.rs", i)), Some(i), None, now);
+        }
+        for i in 0..files {
+            let arrival = pairer.arrived(Path::new(&format!("/w/b/{}.rs", i)), Some(i), None);
+            assert_eq!(arrival, Arrival::Renamed(format!("/w/a/{}.rs", i).into()));
+        }
+        println!("pair {} renames: {:?}", files, start.elapsed());
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn bench_search_slow_fs_cancel() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        generate(dir.path(), &RepoSpec { files: 2000, ..RepoSpec::default() })?;
+        let _slow = SlowFs::new(Duration::from_millis(5));
+
+        let cancel = CancellationToken::new();
+        let canceller = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            canceller.cancel();
+        });
+
+        let start = Instant::now();
+        search(dir.path(), NEEDLE, cancel).await;
+        println!("cancelled slow search after {:?}", start.elapsed());
+
+        assert!(start.elapsed() < Duration::from_secs(2), "cancellation was not prompt");
+        Ok(())
+    }
+}
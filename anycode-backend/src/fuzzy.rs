@@ -0,0 +1,119 @@
+use serde::Serialize;
+
+/// Fuzzy match of a query against a candidate, quick-open style:
+/// query chars have to appear in order, case-insensitive.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct FuzzyMatch {
+    pub score: i64,
+    /// Char indices of the matched candidate chars, for highlighting
+    pub indices: Vec<usize>,
+}
+
+const MATCH: i64 = 16;
+const CONSECUTIVE: i64 = 32;
+const WORD_START: i64 = 24;
+const FIRST_CHAR: i64 = 16;
+const GAP: i64 = 1;
+
+fn is_separator(c: char) -> bool {
+    matches!(c, '/' | '\\' | '_' | '-' | '.' | ' ' | ':')
+}
+
+fn is_word_start(chars: &[char], i: usize) -> bool {
+    if i == 0 {
+        return true;
+    }
+    let prev = chars[i - 1];
+    is_separator(prev) || (prev.is_lowercase() && chars[i].is_uppercase())
+}
+
+/// Score a candidate, None when the query does not match. Matches at word
+/// starts (`fo` in `foo_bar`, `FB` in `FooBar`) and consecutive runs rank
+/// higher, gaps and long candidates lower.
+pub fn fuzzy_match(query: &str, candidate: &str) -> Option<FuzzyMatch> {
+    let query: Vec<char> = query.chars().filter(|c| !c.is_whitespace()).collect();
+    if query.is_empty() {
+        return Some(FuzzyMatch { score: 0, indices: Vec::new() });
+    }
+
+    let chars: Vec<char> = candidate.chars().collect();
+    let lower: Vec<char> = chars.iter().map(|c| c.to_lowercase().next().unwrap_or(*c)).collect();
+
+    // Greedy forward pass preferring word starts: for each query char take the
+    // next word start match if there is one before the next plain match of
+    // the following query char, otherwise the first match.
+    let mut indices = Vec::with_capacity(query.len());
+    let mut pos = 0;
+    for q in query.iter() {
+        let q = q.to_lowercase().next().unwrap_or(*q);
+        let first = (pos..lower.len()).find(|&i| lower[i] == q)?;
+
+        let consecutive = indices.last().is_some_and(|&last: &usize| last + 1 == first);
+        let chosen = if consecutive || is_word_start(&chars, first) {
+            first
+        } else {
+            (first..lower.len())
+                .find(|&i| lower[i] == q && is_word_start(&chars, i))
+                .unwrap_or(first)
+        };
+
+        indices.push(chosen);
+        pos = chosen + 1;
+    }
+
+    let mut score = 0;
+    for (n, &i) in indices.iter().enumerate() {
+        score += MATCH;
+        if i == 0 {
+            score += FIRST_CHAR;
+        }
+        if is_word_start(&chars, i) {
+            score += WORD_START;
+        }
+        if n > 0 {
+            let prev = indices[n - 1];
+            if prev + 1 == i {
+                score += CONSECUTIVE;
+            } else {
+                score -= GAP * (i - prev - 1) as i64;
+            }
+        }
+    }
+    score -= (chars.len() as i64 - indices.len() as i64) / 4;
+
+    Some(FuzzyMatch { score, indices })
+}
+
+#[cfg(test)]
+mod fuzzy_tests {
+    use super::*;
+
+    fn score(query: &str, candidate: &str) -> i64 {
+        fuzzy_match(query, candidate).map(|m| m.score).unwrap_or(i64::MIN)
+    }
+
+    #[test]
+    fn test_fuzzy_match_basic() {
+        assert!(fuzzy_match("abc", "a_b_c").is_some());
+        assert!(fuzzy_match("abc", "acb").is_none());
+        assert_eq!(fuzzy_match("MaIn", "src/main.rs").unwrap().indices, vec![4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn test_fuzzy_ranking() {
+        // Word starts beat scattered matches
+        assert!(score("fb", "foo_bar") > score("fb", "xfxxbx"));
+        // camelCase boundaries count as word starts
+        assert!(score("gd", "gotoDefinition") > score("gd", "guarded"));
+        // Shorter candidates win on equal matches
+        assert!(score("main", "main.rs") > score("main", "src/deep/path/main.rs"));
+        // Consecutive beats spread out
+        assert!(score("code", "code.rs") > score("code", "c_o_d_e.rs"));
+    }
+
+    #[test]
+    fn test_fuzzy_prefers_word_start_occurrence() {
+        let m = fuzzy_match("hs", "handlers/search_handler.rs").unwrap();
+        assert_eq!(m.indices, vec![0, 9]);
+    }
+}
@@ -0,0 +1,464 @@
+use anyhow::Result;
+use anyhow::anyhow;
+use git2::{BranchType, Delta, ErrorCode, Repository, Status, StatusEntry, StatusOptions};
+use parking_lot::Mutex;
+use serde::Serialize;
+use socketioxide::SocketIo;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anycode_search::gitignore;
+use anycode_search::ignore::is_ignored_dir;
+
+// Git status of the workspace files, for the markers of the file tree.
+// Clients ask with `git:status`; the watcher reports the files it sees
+// change, the index and refs under .git included, and the status of their
+// root is pushed again as `git:status` once the changes settle.
+
+/// Quiet time after a change before the status is pushed
+const PUSH_DELAY: Duration = Duration::from_millis(300);
+
+const STAGED: Status = Status::INDEX_NEW
+    .union(Status::INDEX_MODIFIED)
+    .union(Status::INDEX_DELETED)
+    .union(Status::INDEX_RENAMED)
+    .union(Status::INDEX_TYPECHANGE);
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FileStatus {
+    Modified,
+    Added,
+    Deleted,
+    Renamed,
+    Untracked,
+    Ignored,
+    Conflicted,
+}
+
+impl FileStatus {
+    fn of(status: Status) -> Option<Self> {
+        if status.is_conflicted() {
+            Some(Self::Conflicted)
+        } else if status.is_ignored() {
+            Some(Self::Ignored)
+        } else if status.is_wt_new() && !status.intersects(STAGED) {
+            Some(Self::Untracked)
+        } else if status.is_index_new() {
+            Some(Self::Added)
+        } else if status.intersects(Status::INDEX_DELETED | Status::WT_DELETED) {
+            Some(Self::Deleted)
+        } else if status.intersects(Status::INDEX_RENAMED | Status::WT_RENAMED) {
+            Some(Self::Renamed)
+        } else if status.intersects(Status::INDEX_MODIFIED | Status::WT_MODIFIED
+            | Status::INDEX_TYPECHANGE | Status::WT_TYPECHANGE)
+        {
+            Some(Self::Modified)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct FileEntry {
+    /// Absolute path, the new one of a rename. Ignored directories are
+    /// one entry.
+    pub path: PathBuf,
+    pub status: FileStatus,
+    /// The change is in the index
+    pub staged: bool,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct RepoStatus {
+    pub workdir: PathBuf,
+    /// Checked out branch, missing on a detached HEAD
+    pub branch: Option<String>,
+    pub files: Vec<FileEntry>,
+}
+
+fn entry_path(workdir: &Path, entry: &StatusEntry) -> Option<PathBuf> {
+    let delta = entry.head_to_index().or_else(|| entry.index_to_workdir());
+    let path = delta.as_ref().and_then(|d| d.new_file().path().map(Path::to_path_buf))
+        .or_else(|| entry.path().map(PathBuf::from))?;
+    // Collected again to drop the trailing slash of directories
+    Some(workdir.join(path).components().collect())
+}
+
+/// Name of the branch HEAD points to, also before its first commit
+fn branch(repo: &Repository) -> Option<String> {
+    let head = repo.find_reference("HEAD").ok()?;
+    head.symbolic_target()?.strip_prefix("refs/heads/").map(str::to_string)
+}
+
+fn discover(root: &Path) -> Result<Option<Repository>> {
+    match Repository::discover(root) {
+        Ok(repo) => Ok(Some(repo)),
+        Err(e) if e.code() == ErrorCode::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Status of the repository containing `root`, None outside of one
+pub fn status(root: &Path) -> Result<Option<RepoStatus>> {
+    let Some(repo) = discover(root)? else { return Ok(None) };
+    let Some(workdir) = repo.workdir().map(|dir| dir.components().collect::<PathBuf>()) else {
+        return Ok(None);
+    };
+
+    let mut options = StatusOptions::new();
+    options
+        .include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .include_ignored(true)
+        .recurse_ignored_dirs(false)
+        .renames_head_to_index(true)
+        .exclude_submodules(true);
+
+    let mut files: Vec<FileEntry> = repo.statuses(Some(&mut options))?.iter()
+        .filter_map(|entry| Some(FileEntry {
+            path: entry_path(&workdir, &entry)?,
+            status: FileStatus::of(entry.status())?,
+            staged: entry.status().intersects(STAGED),
+        }))
+        .collect();
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(Some(RepoStatus { branch: branch(&repo), workdir, files }))
+}
+
+/// Working directory of the repository containing `root`
+pub fn workdir(root: &Path) -> Option<PathBuf> {
+    let repo = Repository::discover(root).ok()?;
+    repo.workdir().map(|dir| dir.components().collect())
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Branch {
+    /// `main` for local branches, `origin/main` for remote ones
+    pub name: String,
+    pub remote: bool,
+    pub current: bool,
+    /// Remote branch a local one tracks
+    pub upstream: Option<String>,
+    /// Short id of the commit the branch points to
+    pub commit: String,
+    pub summary: Option<String>,
+}
+
+/// Local branches then remote ones, by name. None outside of a repository.
+pub fn branches(root: &Path) -> Result<Option<Vec<Branch>>> {
+    let Some(repo) = discover(root)? else { return Ok(None) };
+
+    let mut branches = Vec::new();
+    for branch in repo.branches(None)? {
+        let (branch, kind) = branch?;
+        let Some(name) = branch.name()?.map(str::to_string) else { continue };
+        // origin/HEAD only points to another remote branch
+        if kind == BranchType::Remote && name.ends_with("/HEAD") {
+            continue;
+        }
+        let upstream = branch.upstream().ok()
+            .and_then(|upstream| upstream.name().ok().flatten().map(str::to_string));
+        let commit = branch.get().peel_to_commit()?;
+        let id = commit.id().to_string();
+        branches.push(Branch {
+            name,
+            remote: kind == BranchType::Remote,
+            current: branch.is_head(),
+            upstream,
+            commit: id[..id.len().min(7)].to_string(),
+            summary: commit.summary().map(str::to_string),
+        });
+    }
+    branches.sort_by(|a, b| (a.remote, &a.name).cmp(&(b.remote, &b.name)));
+    Ok(Some(branches))
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Modified,
+    Deleted,
+}
+
+/// A file a checkout changed, the path is absolute
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct FileChange {
+    pub path: PathBuf,
+    pub kind: ChangeKind,
+}
+
+/// Switch the repository containing `root` to `branch`, or to a new one
+/// from HEAD with `create`. A remote branch like `origin/feature` is
+/// checked out as a local branch tracking it, or as the local branch of
+/// that name when there is one. Local changes in the way of the checkout
+/// fail it, like `git checkout` does, and a branch created for it is
+/// removed again. Returns the files the checkout changed.
+pub fn checkout(root: &Path, branch: &str, create: bool) -> Result<Vec<FileChange>> {
+    let repo = discover(root)?.ok_or_else(|| anyhow!("{} is not in a git repository", root.display()))?;
+    let workdir: PathBuf = repo.workdir()
+        .ok_or_else(|| anyhow!("The repository has no working directory"))?
+        .components().collect();
+    let old_tree = repo.head().ok().and_then(|head| head.peel_to_tree().ok());
+
+    // The branch to switch to, with the commit and upstream of a new one
+    let (name, new) = if create {
+        if repo.find_branch(branch, BranchType::Local).is_ok() {
+            return Err(anyhow!("A branch named {} already exists", branch));
+        }
+        (branch, Some((repo.head()?.peel_to_commit()?, None)))
+    } else if repo.find_branch(branch, BranchType::Local).is_ok() {
+        (branch, None)
+    } else if let Ok(remote) = repo.find_branch(branch, BranchType::Remote) {
+        let name = branch.split_once('/').map_or(branch, |(_, name)| name);
+        match repo.find_branch(name, BranchType::Local) {
+            Ok(_) => (name, None),
+            Err(_) => (name, Some((remote.get().peel_to_commit()?, Some(branch)))),
+        }
+    } else {
+        return Err(anyhow!("No branch {}", branch));
+    };
+
+    let commit = match &new {
+        Some((commit, _)) => commit.clone(),
+        None => repo.find_branch(name, BranchType::Local)?.get().peel_to_commit()?,
+    };
+    let tree = commit.tree()?;
+    repo.checkout_tree(tree.as_object(), Some(git2::build::CheckoutBuilder::new().safe()))?;
+
+    let mut local = match &new {
+        Some((commit, _)) => repo.branch(name, commit, false)?,
+        None => repo.find_branch(name, BranchType::Local)?,
+    };
+    let refname = local.get().name().ok_or_else(|| anyhow!("Branch name is not utf-8"))?.to_string();
+    let upstream = new.as_ref().and_then(|(_, upstream)| *upstream);
+    let switched = upstream.map_or(Ok(()), |upstream| local.set_upstream(Some(upstream)))
+        .and_then(|_| repo.set_head(&refname));
+    if let Err(e) = switched {
+        if new.is_some() {
+            local.delete().ok();
+        }
+        return Err(e.into());
+    }
+
+    let diff = repo.diff_tree_to_tree(old_tree.as_ref(), Some(&tree), None)?;
+    let changes = diff.deltas()
+        .filter_map(|delta| {
+            let kind = match delta.status() {
+                Delta::Added => ChangeKind::Added,
+                Delta::Deleted => ChangeKind::Deleted,
+                _ => ChangeKind::Modified,
+            };
+            let file = match kind {
+                ChangeKind::Deleted => delta.old_file(),
+                _ => delta.new_file(),
+            };
+            Some(FileChange { path: workdir.join(file.path()?), kind })
+        })
+        .collect();
+    Ok(changes)
+}
+
+/// Whether a change of `path` may change the status: files neither in the
+/// ignored directories nor ignored by the .gitignore files, and the index,
+/// HEAD and refs of a repository
+pub fn affects_status(path: &Path) -> bool {
+    let mut components = path.iter();
+    if !components.any(|c| c == ".git") {
+        return !is_ignored_dir(path) && !gitignore::is_ignored(path, path.is_dir());
+    }
+    let rest: Vec<_> = components.collect();
+    matches!(rest.as_slice(), [name] if *name == "index" || *name == "HEAD")
+        || rest.first().is_some_and(|c| *c == "refs")
+}
+
+/// Roots with a push scheduled
+static PENDING: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Push the status of the root of `path` once the changes settle
+pub fn changed(path: &Path, io: &Arc<SocketIo>) {
+    let root = crate::roots::root_of(path);
+    let mut pending = PENDING.lock();
+    if pending.contains(&root) {
+        return;
+    }
+    pending.push(root);
+    // Scheduled already by an earlier change
+    if pending.len() > 1 {
+        return;
+    }
+    drop(pending);
+
+    let io = io.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(PUSH_DELAY).await;
+        let roots = std::mem::take(&mut *PENDING.lock());
+        for root in roots {
+            let result = crate::pool::spawn(async move { (status(&root), root) }).await;
+            match result {
+                Ok((Ok(status), root)) => {
+                    let _ = io.emit("git:status", &serde_json::json!({ "root": root, "status": status })).await;
+                }
+                Ok((Err(e), root)) => tracing::error!("Failed to read git status of {}: {}", root.display(), e),
+                Err(e) => tracing::error!("Git status task failed: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod git_tests {
+    use super::*;
+
+    fn commit_all(repo: &Repository) -> Result<()> {
+        let mut index = repo.index()?;
+        index.add_all(["*"], git2::IndexAddOption::DEFAULT, None)?;
+        index.write()?;
+        let tree = repo.find_tree(index.write_tree()?)?;
+        let signature = git2::Signature::now("test", "test@example.com")?;
+        repo.commit(Some("HEAD"), &signature, &signature, "initial", &tree, &[])?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_status() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let repo = Repository::init(dir.path())?;
+        let workdir = repo.workdir().unwrap().to_path_buf();
+        std::fs::write(workdir.join(".gitignore"), "build/\n")?;
+        std::fs::write(workdir.join("kept.txt"), "a")?;
+        std::fs::write(workdir.join("edited.txt"), "a")?;
+        commit_all(&repo)?;
+
+        std::fs::write(workdir.join("edited.txt"), "b")?;
+        std::fs::write(workdir.join("staged.txt"), "a")?;
+        let mut index = repo.index()?;
+        index.add_path(Path::new("staged.txt"))?;
+        index.write()?;
+        std::fs::create_dir_all(workdir.join("new"))?;
+        std::fs::write(workdir.join("new/untracked.txt"), "a")?;
+        std::fs::create_dir_all(workdir.join("build"))?;
+        std::fs::write(workdir.join("build/out.o"), "a")?;
+
+        let status = status(&workdir.join("new"))?.unwrap();
+        assert_eq!(status.workdir, workdir);
+        assert!(status.branch.is_some());
+        let files: Vec<(String, FileStatus, bool)> = status.files.iter()
+            .map(|f| (f.path.strip_prefix(&workdir).unwrap().to_string_lossy().to_string(), f.status, f.staged))
+            .collect();
+        assert_eq!(files, [
+            ("build".to_string(), FileStatus::Ignored, false),
+            ("edited.txt".to_string(), FileStatus::Modified, false),
+            ("new/untracked.txt".to_string(), FileStatus::Untracked, false),
+            ("staged.txt".to_string(), FileStatus::Added, true),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_branches_and_checkout() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let repo = Repository::init(dir.path())?;
+        let workdir = repo.workdir().unwrap().components().collect::<PathBuf>();
+        std::fs::write(workdir.join("shared.txt"), "a")?;
+        commit_all(&repo)?;
+        let main = branch(&repo).unwrap();
+
+        checkout(&workdir, "feature", true)?;
+        std::fs::write(workdir.join("shared.txt"), "b")?;
+        std::fs::write(workdir.join("feature.txt"), "a")?;
+        let mut index = repo.index()?;
+        index.add_all(["*"], git2::IndexAddOption::DEFAULT, None)?;
+        index.write()?;
+        let tree = repo.find_tree(index.write_tree()?)?;
+        let signature = git2::Signature::now("test", "test@example.com")?;
+        let parent = repo.head()?.peel_to_commit()?;
+        repo.commit(Some("HEAD"), &signature, &signature, "feature", &tree, &[&parent])?;
+
+        let names: Vec<(String, bool)> = branches(&workdir)?.unwrap().into_iter().map(|b| (b.name, b.current)).collect();
+        assert_eq!(names, [("feature".to_string(), true), (main.clone(), false)]);
+
+        let changes = checkout(&workdir, &main, false)?;
+        assert_eq!(changes, [
+            FileChange { path: workdir.join("feature.txt"), kind: ChangeKind::Deleted },
+            FileChange { path: workdir.join("shared.txt"), kind: ChangeKind::Modified },
+        ]);
+        assert_eq!(std::fs::read_to_string(workdir.join("shared.txt"))?, "a");
+        assert!(!workdir.join("feature.txt").exists());
+        assert_eq!(branch(&repo).as_ref(), Some(&main));
+
+        assert!(checkout(&workdir, "missing", false).is_err());
+        assert!(checkout(&workdir, "feature", true).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_checkout_remote_branch() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let repo = Repository::init(dir.path())?;
+        let workdir = repo.workdir().unwrap().components().collect::<PathBuf>();
+        std::fs::write(workdir.join("a.txt"), "a")?;
+        commit_all(&repo)?;
+        let head = repo.head()?.peel_to_commit()?;
+        repo.remote("origin", "https://example.com/repo.git")?;
+        repo.reference("refs/remotes/origin/feature", head.id(), false, "fetch")?;
+
+        // Local changes in the way fail it without leaving a branch behind
+        std::fs::write(workdir.join("a.txt"), "changed")?;
+        let mut index = repo.index()?;
+        index.add_path(Path::new("a.txt"))?;
+        index.write()?;
+        let tree = repo.find_tree(index.write_tree()?)?;
+        let signature = git2::Signature::now("test", "test@example.com")?;
+        let diverged = repo.commit(None, &signature, &signature, "other", &tree, &[&head])?;
+        repo.reference("refs/remotes/origin/other", diverged, false, "fetch")?;
+        repo.checkout_head(Some(git2::build::CheckoutBuilder::new().force()))?;
+        std::fs::write(workdir.join("a.txt"), "uncommitted")?;
+        assert!(checkout(&workdir, "origin/other", false).is_err());
+        assert!(repo.find_branch("other", BranchType::Local).is_err());
+        repo.checkout_head(Some(git2::build::CheckoutBuilder::new().force()))?;
+
+        checkout(&workdir, "origin/feature", false)?;
+        let local = repo.find_branch("feature", BranchType::Local)?;
+        assert_eq!(local.upstream()?.name()?, Some("origin/feature"));
+        assert_eq!(branch(&repo).as_deref(), Some("feature"));
+
+        // With the local branch there already, it is the one checked out
+        let main = repo.branches(Some(BranchType::Local))?.flatten()
+            .map(|(b, _)| b.name().unwrap().unwrap().to_string())
+            .find(|name| name != "feature").unwrap();
+        checkout(&workdir, &main, false)?;
+        checkout(&workdir, "origin/feature", false)?;
+        assert_eq!(branch(&repo).as_deref(), Some("feature"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_not_a_repository() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        // The temp dir may sit inside a repository of the machine
+        let found = status(dir.path())?;
+        assert!(found.is_none_or(|s| !s.workdir.starts_with(dir.path())));
+        Ok(())
+    }
+
+    #[test]
+    fn test_affects_status() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join(".gitignore"), "node_modules/\n")?;
+        assert!(!affects_status(&dir.path().join("node_modules").join("x").join("index.js")));
+        assert!(affects_status(&dir.path().join(".gitignore")));
+
+        assert!(affects_status(Path::new("/w/src/main.rs")));
+        assert!(affects_status(Path::new("/w/.git/index")));
+        assert!(affects_status(Path::new("/w/.git/refs/heads/main")));
+        assert!(!affects_status(Path::new("/w/.git/index.lock")));
+        assert!(!affects_status(Path::new("/w/.git/objects/ab/cdef")));
+        assert!(!affects_status(Path::new("/w/.anycode/ignore")));
+        Ok(())
+    }
+}
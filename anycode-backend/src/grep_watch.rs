@@ -0,0 +1,273 @@
+use anyhow::Result;
+use parking_lot::Mutex;
+use serde::Serialize;
+use socketioxide::extract::SocketRef;
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::search::{collect_files_recursively, line_search, FileSearchResult, SearchResult};
+use anycode_search::ignore::is_ignored_path;
+
+// Live grep subscriptions, `watch:grep`. A subscription is a pattern over
+// some files and directories: they are searched once when it starts, then
+// every file the watcher reports changed in its scope is searched again
+// and the matches that appeared or went away are pushed to the socket as
+// `watch:grepMatches`. Followed files, like logs, are only searched from
+// where the last search stopped, until they shrink and start over.
+
+#[derive(Debug, Default, Clone)]
+struct FileMatches {
+    matches: Vec<SearchResult>,
+    /// End of the last complete line searched, followed files only
+    offset: u64,
+    /// Lines before `offset`
+    lines: usize,
+}
+
+struct Subscription {
+    id: u64,
+    socket: SocketRef,
+    pattern: String,
+    /// Absolute files and directories searched
+    paths: Vec<PathBuf>,
+    follow: bool,
+    files: HashMap<PathBuf, FileMatches>,
+}
+
+impl Subscription {
+    /// Files named in the scope are searched whatever their name, files
+    /// found in its directories unless they are ignored
+    fn covers(&self, path: &Path) -> bool {
+        self.paths.iter().any(|p| p == path || (path.starts_with(p) && !is_ignored_path(path)))
+    }
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct MatchChange {
+    pub id: u64,
+    pub file_path: PathBuf,
+    pub added: Vec<SearchResult>,
+    pub removed: Vec<SearchResult>,
+}
+
+static SUBSCRIPTIONS: Mutex<Vec<Subscription>> = Mutex::new(Vec::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+/// Held while files are searched again, so two changes of one file are
+/// compared with each other in order
+static REFRESH: Mutex<()> = Mutex::new(());
+
+/// Search `path`, from where `previous` stopped when following and the
+/// file did not shrink. A missing file has no matches.
+fn search_file(path: &Path, pattern: &str, follow: bool, previous: Option<&FileMatches>) -> Result<FileMatches> {
+    let mut file = match std::fs::File::open(path) {
+        Ok(file) if file.metadata()?.is_file() => file,
+        Ok(_) => return Ok(FileMatches::default()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(FileMatches::default()),
+        Err(e) => return Err(e.into()),
+    };
+    let len = file.metadata()?.len();
+    let mut found = match previous {
+        Some(previous) if follow && previous.offset <= len => previous.clone(),
+        _ => FileMatches::default(),
+    };
+
+    file.seek(SeekFrom::Start(found.offset))?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+    // A followed file is being written, its last line may not be complete
+    let searched = match bytes.iter().rposition(|b| *b == b'\n') {
+        Some(end) if follow => &bytes[..=end],
+        None if follow => &bytes[..0],
+        _ => &bytes[..],
+    };
+
+    let text = String::from_utf8_lossy(searched);
+    for (i, line) in text.lines().enumerate() {
+        found.matches.extend(line_search(line, pattern, found.lines + i));
+    }
+    if follow {
+        found.offset += searched.len() as u64;
+        found.lines += text.matches('\n').count();
+    }
+    Ok(found)
+}
+
+/// Matches of `new` missing from `old`, and of `old` missing from `new`
+fn diff(old: &[SearchResult], new: &[SearchResult]) -> (Vec<SearchResult>, Vec<SearchResult>) {
+    let added = new.iter().filter(|m| !old.contains(m)).cloned().collect();
+    let removed = old.iter().filter(|m| !new.contains(m)).cloned().collect();
+    (added, removed)
+}
+
+/// Files searched for `paths`: the files themselves and the files of the
+/// directories
+fn scope_files(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            files.extend(collect_files_recursively(path).unwrap_or_default());
+        } else {
+            files.push(path.clone());
+        }
+    }
+    files
+}
+
+/// Start a subscription of `socket`, returning its id and the matches
+/// found now. Walks the scope, call it off the main runtime.
+pub fn subscribe(socket: SocketRef, pattern: String, paths: Vec<PathBuf>, follow: bool) -> Result<(u64, Vec<FileSearchResult>)> {
+    let mut files = HashMap::new();
+    let mut results = Vec::new();
+    for path in scope_files(&paths) {
+        let found = search_file(&path, &pattern, follow, None)?;
+        if !found.matches.is_empty() {
+            results.push(FileSearchResult {
+                file_path: path.to_string_lossy().to_string(),
+                matches: found.matches.clone(),
+            });
+        }
+        files.insert(path, found);
+    }
+
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    SUBSCRIPTIONS.lock().push(Subscription { id, socket, pattern, paths, follow, files });
+    Ok((id, results))
+}
+
+/// Stop a subscription of the socket, false if it has none with the id
+pub fn unsubscribe(id: u64, socket_id: &str) -> bool {
+    let mut subscriptions = SUBSCRIPTIONS.lock();
+    let count = subscriptions.len();
+    subscriptions.retain(|s| !(s.id == id && s.socket.id.as_str() == socket_id));
+    subscriptions.len() != count
+}
+
+/// Stop the subscriptions of a disconnected socket
+pub fn unsubscribe_socket(socket_id: &str) {
+    SUBSCRIPTIONS.lock().retain(|s| s.socket.id.as_str() != socket_id);
+}
+
+/// Search `path` again for the subscriptions covering it, on the
+/// background pool. Called by the watcher for every changed path.
+pub fn changed(path: &Path) {
+    let path = crate::paths::absolute(path);
+    if !SUBSCRIPTIONS.lock().iter().any(|s| s.covers(&path)) {
+        return;
+    }
+    crate::pool::spawn(async move {
+        for (socket, change) in refresh(&path) {
+            let _ = socket.emit("watch:grepMatches", &change);
+        }
+    });
+}
+
+/// The changes of the matches of `path`, with the sockets to send them
+/// to. A path that is not a file anymore drops the files under it, for
+/// directories removed or renamed away.
+fn refresh(path: &Path) -> Vec<(SocketRef, MatchChange)> {
+    let _refresh = REFRESH.lock();
+    let searches: Vec<(u64, String, bool, Vec<PathBuf>)> = {
+        let subscriptions = SUBSCRIPTIONS.lock();
+        subscriptions.iter()
+            .filter(|s| s.covers(path))
+            .map(|s| {
+                let known = s.files.keys().filter(|f| f.starts_with(path)).cloned().collect();
+                (s.id, s.pattern.clone(), s.follow, known)
+            })
+            .collect()
+    };
+
+    let mut changes = Vec::new();
+    for (id, pattern, follow, known) in searches {
+        let is_file = path.is_file();
+        let files: Vec<PathBuf> = if is_file { vec![path.to_path_buf()] } else { known };
+        for file in files {
+            let previous = SUBSCRIPTIONS.lock().iter()
+                .find(|s| s.id == id)
+                .and_then(|s| s.files.get(&file).cloned());
+            let found = match search_file(&file, &pattern, follow, previous.as_ref()) {
+                Ok(found) => found,
+                Err(e) => {
+                    crate::output::write("search", &format!("Failed to search {}: {}", file.display(), e));
+                    continue;
+                }
+            };
+            let old = previous.map(|p| p.matches).unwrap_or_default();
+            let (added, removed) = diff(&old, &found.matches);
+
+            let mut subscriptions = SUBSCRIPTIONS.lock();
+            // Stopped while the file was searched
+            let Some(subscription) = subscriptions.iter_mut().find(|s| s.id == id) else { break };
+            if is_file {
+                subscription.files.insert(file.clone(), found);
+            } else {
+                subscription.files.remove(&file);
+            }
+            if !added.is_empty() || !removed.is_empty() {
+                changes.push((subscription.socket.clone(), MatchChange { id, file_path: file, added, removed }));
+            }
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod grep_watch_tests {
+    use super::*;
+    use std::io::Write;
+
+    fn lines(found: &FileMatches) -> Vec<usize> {
+        found.matches.iter().map(|m| m.line).collect()
+    }
+
+    #[test]
+    fn test_search_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("a.rs");
+        std::fs::write(&path, "// TODO one\nfn main() {}\n// TODO two")?;
+
+        let found = search_file(&path, "TODO", false, None)?;
+        assert_eq!(lines(&found), [0, 2]);
+        assert_eq!(found.offset, 0);
+
+        let missing = search_file(&dir.path().join("b.rs"), "TODO", false, None)?;
+        assert!(missing.matches.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_follow_appends() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("app.log");
+        std::fs::write(&path, "ERROR first\ninfo\nERROR par")?;
+
+        // The incomplete last line waits for its end
+        let first = search_file(&path, "ERROR", true, None)?;
+        assert_eq!(lines(&first), [0]);
+        assert_eq!(first.lines, 2);
+
+        std::fs::OpenOptions::new().append(true).open(&path)?.write_all(b"tial\nERROR third\n")?;
+        let second = search_file(&path, "ERROR", true, Some(&first))?;
+        assert_eq!(lines(&second), [0, 2, 3]);
+        assert_eq!(second.matches[1].preview, "ERROR partial");
+
+        // Truncated, like a rotated log
+        std::fs::write(&path, "ERROR new\n")?;
+        let third = search_file(&path, "ERROR", true, Some(&second))?;
+        assert_eq!(lines(&third), [0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff() {
+        let at = |line: usize, preview: &str| SearchResult { line, column: 0, preview: preview.to_string(), ..Default::default() };
+        let old = [at(0, "TODO a"), at(3, "TODO b")];
+        let new = [at(0, "TODO a"), at(4, "TODO c")];
+
+        let (added, removed) = diff(&old, &new);
+        assert_eq!(added, [at(4, "TODO c")]);
+        assert_eq!(removed, [at(3, "TODO b")]);
+    }
+}
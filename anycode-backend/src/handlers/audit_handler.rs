@@ -0,0 +1,119 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use socketioxide::extract::{AckSender, Data, SocketRef, State};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::{info, error};
+use crate::app_state::{get_or_create_code, AppState};
+use crate::error_ack;
+use crate::handlers::edit_handler::broadcast_changes;
+use crate::search::collect_files_recursively;
+use crate::services::{self, Change};
+use crate::text_audit::{audit, fix_edits, TextFormatFixes};
+use crate::timing::EventTimer;
+use crate::utils::abs_file;
+
+/// Minimum interval between `audit:textFormatProgress` events
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct TextFormatAuditRequest {
+    /// Directory to audit, the whole workspace when empty
+    #[serde(default)]
+    pub path: String,
+}
+
+/// Files with mixed line endings, byte order marks, other encodings than
+/// UTF-8 or trailing whitespace, scanned in the background. Progress is
+/// streamed as `audit:textFormatProgress {scanned, total}`.
+pub async fn handle_audit_text_format(
+    socket: SocketRef,
+    Data(request): Data<TextFormatAuditRequest>,
+    ack: AckSender,
+) {
+    info!("Received audit:textFormat: {:?}", request);
+    let _timer = EventTimer::start("audit:textFormat").with_socket(socket.id).with_payload(&request);
+
+    let root = match request.path.trim() {
+        "" => ".".to_string(),
+        path => path.to_string(),
+    };
+    let (progress_tx, mut progress_rx) = mpsc::channel::<(usize, usize)>(16);
+    let scan = crate::pool::spawn(async move {
+        let files = collect_files_recursively(Path::new(&root))?;
+        let progress = crate::progress::start("audit", "Auditing text format", None);
+        let mut last = Instant::now();
+        let reports = audit(files, |scanned, total| {
+            if scanned == total || last.elapsed() >= PROGRESS_INTERVAL {
+                last = Instant::now();
+                progress.update(Some((scanned * 100 / total.max(1)) as u8), Some(format!("{} files", scanned)));
+                let _ = progress_tx.try_send((scanned, total));
+            }
+        });
+        anyhow::Ok(reports)
+    });
+
+    while let Some((scanned, total)) = progress_rx.recv().await {
+        let _ = socket.emit("audit:textFormatProgress", &json!({ "scanned": scanned, "total": total }));
+    }
+
+    match scan.await {
+        Ok(Ok(files)) => { ack.send(&json!({ "files": files, "success": true })).ok(); }
+        Ok(Err(e)) => error_ack!(ack, &request.path, "Audit failed: {}", e),
+        Err(e) => error_ack!(ack, &request.path, "Audit failed: {}", e),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TextFormatFixRequest {
+    pub files: Vec<String>,
+    #[serde(flatten)]
+    pub fixes: TextFormatFixes,
+    /// Save the fixed files, otherwise they stay modified in the buffers
+    #[serde(default = "default_save")]
+    pub save: bool,
+}
+
+fn default_save() -> bool { true }
+
+/// Fix the reported issues of files through the batch edit api, every
+/// client gets the edits as `file:change`. Files that are not UTF-8 can
+/// not be opened and fail the request.
+pub async fn handle_audit_fix_text_format(
+    socket: SocketRef,
+    Data(request): Data<TextFormatFixRequest>,
+    ack: AckSender,
+    state: State<AppState>,
+) {
+    info!("Received audit:fixTextFormat: {:?}", request);
+    let mut timer = EventTimer::start("audit:fixTextFormat").with_socket(socket.id).with_payload(&request);
+
+    let mut changes = Vec::with_capacity(request.files.len());
+    {
+        let mut f2c = timer.lock("file2code", &state.file2code).await;
+        for file in &request.files {
+            let abs_path = match abs_file(file) {
+                Ok(p) => p,
+                Err(e) => error_ack!(ack, file, "Failed to resolve file: {:?}", e),
+            };
+            let code = match get_or_create_code(&mut f2c, &abs_path, &state.config) {
+                Ok(c) => c,
+                Err(e) => error_ack!(ack, file, "{:?}", e),
+            };
+
+            let edits = fix_edits(&code.text.to_string(), &request.fixes);
+            if !edits.is_empty() {
+                changes.push(Change { file: abs_path, edits, base: None, version: None });
+            }
+        }
+    }
+
+    let (files, changes) = match services::apply_batch(&state, &mut timer, &changes, request.save).await {
+        Ok(applied) => applied,
+        Err(e) => error_ack!(ack, "", "{}", e),
+    };
+    broadcast_changes(&socket, &changes).await;
+
+    ack.send(&json!({ "files": files, "success": true })).ok();
+}
@@ -0,0 +1,142 @@
+use serde_json::json;
+use socketioxide::extract::{AckSender, Data, SocketRef, State};
+use tracing::{error, info};
+use crate::app_state::AppState;
+use serde::{Deserialize, Serialize};
+use crate::utils::abs_file;
+use crate::timing::EventTimer;
+use crate::error_ack;
+use crate::services::{self, Stale};
+use crate::readonly::ReadOnly;
+use crate::collab::{self, Op};
+
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CollabJoinRequest {
+    pub file: String,
+    /// Shown to the other peers with the selection
+    #[serde(default)]
+    pub name: String,
+}
+
+/// Edit an open buffer together with the other peers of its session. The
+/// ack has the text and version to start from and the selections of the
+/// others, they get `collab:joined`.
+pub async fn handle_collab_join(
+    socket: SocketRef,
+    Data(request): Data<CollabJoinRequest>,
+    state: State<AppState>,
+    ack: AckSender,
+) {
+    info!("Received collab:join: {:?}", request);
+    let mut timer = EventTimer::start("collab:join").with_socket(socket.id).with_payload(&request);
+
+    let peer = socket.id.to_string();
+    let joined = match services::collab_join(&state, &mut timer, socket, &request.file, &request.name).await {
+        Ok(joined) => joined,
+        Err(e) => error_ack!(ack, &request.file, "{}", e),
+    };
+
+    if let Err(err) = ack.send(&json!({
+        "file": joined.file, "text": joined.text, "version": joined.version,
+        "peer": peer, "peers": joined.peers, "success": true
+    })) {
+        error!("Failed to send acknowledgment: {:?}", err);
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CollabLeaveRequest {
+    pub file: String,
+}
+
+/// Leave the session of a buffer, the other peers get `collab:left`
+pub async fn handle_collab_leave(
+    socket: SocketRef,
+    Data(request): Data<CollabLeaveRequest>,
+    ack: AckSender,
+) {
+    info!("Received collab:leave: {:?}", request);
+    let _timer = EventTimer::start("collab:leave").with_socket(socket.id).with_payload(&request);
+
+    let file = match abs_file(&request.file) {
+        Ok(file) => file,
+        Err(e) => error_ack!(ack, &request.file, "Failed to resolve file: {:?}", e),
+    };
+    let left = collab::leave(&file, socket.id.as_str());
+    ack.send(&json!({ "file": file, "left": left, "success": true })).ok();
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CollabOpRequest {
+    pub file: String,
+    /// Version the operation was made on
+    pub base: u64,
+    pub op: Op,
+}
+
+/// Apply an operation of a peer. All peers get it as `collab:op` in the
+/// order it was applied, the sender as the confirmation, and the clients
+/// outside the session as `file:change`. A peer the operation can't be
+/// moved for is refused and gets `collab:reset`.
+pub async fn handle_collab_op(
+    socket: SocketRef,
+    Data(request): Data<CollabOpRequest>,
+    state: State<AppState>,
+    ack: AckSender,
+) {
+    info!("Received collab:op: base={} file={}", request.base, request.file);
+    let mut timer = EventTimer::start("collab:op").with_socket(socket.id).with_payload(&request);
+
+    let applied = match services::apply_collab_op(
+        &state, &mut timer, socket.id.as_str(), &request.file, request.base, &request.op,
+    ).await {
+        Ok(applied) => applied,
+        Err(e) => {
+            if let Some(read_only) = e.downcast_ref::<ReadOnly>() {
+                error!("{}", e);
+                ack.send(&json!({
+                    "error": e.to_string(), "path": read_only.path, "read_only": read_only.reason, "success": false
+                })).ok();
+                return;
+            } else if let Some(stale) = e.downcast_ref::<Stale>() {
+                error!("{}", e);
+                ack.send(&json!({
+                    "error": e.to_string(), "path": stale.path, "stale": true, "version": stale.version, "success": false
+                })).ok();
+                return;
+            }
+            error_ack!(ack, &request.file, "{}", e);
+        }
+    };
+
+    socket.broadcast().emit("file:change", &applied).await.ok();
+    ack.send(&json!({ "file": applied.file, "version": applied.version, "success": true })).ok();
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CollabCursorRequest {
+    pub file: String,
+    /// Version the selection is on
+    pub base: u64,
+    /// Ends of the selection in UTF-16 units, equal for a caret
+    pub anchor: usize,
+    pub head: usize,
+}
+
+/// Share a peer's selection, the others get `collab:cursor`
+pub async fn handle_collab_cursor(
+    socket: SocketRef,
+    Data(request): Data<CollabCursorRequest>,
+    state: State<AppState>,
+    ack: AckSender,
+) {
+    let mut timer = EventTimer::start("collab:cursor").with_socket(socket.id).with_payload(&request);
+
+    if let Err(e) = services::collab_cursor(
+        &state, &mut timer, socket.id.as_str(), &request.file, request.base, request.anchor, request.head,
+    ).await {
+        error_ack!(ack, &request.file, "{}", e);
+    }
+    ack.send(&json!({ "success": true })).ok();
+}
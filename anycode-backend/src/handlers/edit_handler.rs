@@ -0,0 +1,92 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use socketioxide::extract::{AckSender, Data, SocketRef, State};
+use tracing::{info, error};
+use crate::app_state::{get_or_create_code, AppState};
+use crate::services::{self, Change};
+use crate::utils::abs_file;
+use crate::timing::EventTimer;
+use crate::words::word_at;
+use crate::error_ack;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WordAtRequest {
+    pub file: String,
+    /// UTF-16 offset, the same unit as file:change edits
+    pub offset: usize,
+}
+
+/// The identifier under the cursor, for double-click selection,
+/// search-word-under-cursor and references fallback.
+/// Returns `word: null` when the cursor is not on a word.
+pub async fn handle_word_at(
+    Data(request): Data<WordAtRequest>,
+    ack: AckSender,
+    state: State<AppState>,
+) {
+    info!("Received edit:wordAt: {:?}", request);
+    let mut timer = EventTimer::start("edit:wordAt").with_payload(&request);
+
+    let abs_path = match abs_file(&request.file) {
+        Ok(p) => p,
+        Err(e) => error_ack!(ack, &request.file, "Failed to resolve file: {:?}", e),
+    };
+
+    let mut f2c = timer.lock("file2code", &state.file2code).await;
+    let code = match get_or_create_code(&mut f2c, &abs_path, &state.config) {
+        Ok(c) => c,
+        Err(e) => error_ack!(ack, &abs_path, "{:?}", e),
+    };
+
+    let char_offset = code.utf16_to_char_offset(request.offset);
+    let word = word_at(&code.text, char_offset, &code.lang);
+
+    let response = match word {
+        Some(word) => json!({
+            "word": word.text,
+            "start": code.char_to_utf16_offset(word.start),
+            "end": code.char_to_utf16_offset(word.end),
+            "success": true,
+        }),
+        None => json!({ "word": null, "success": true }),
+    };
+
+    ack.send(&response).ok();
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BatchEditRequest {
+    pub changes: Vec<Change>,
+    /// Save every touched file after applying
+    #[serde(default)]
+    pub save: bool,
+}
+
+/// Apply edits to several files at once (refactorings, code actions).
+/// Every change is sent as `file:change` to all clients, the sender
+/// included, since it did not apply the edits itself.
+pub async fn handle_batch_edit(
+    socket: SocketRef,
+    Data(request): Data<BatchEditRequest>,
+    ack: AckSender,
+    state: State<AppState>,
+) {
+    info!("Received edit:batch: files={}", request.changes.len());
+    let mut timer = EventTimer::start("edit:batch").with_socket(socket.id).with_payload(&request);
+
+    let (files, changes) = match services::apply_batch(&state, &mut timer, &request.changes, request.save).await {
+        Ok(applied) => applied,
+        Err(e) => error_ack!(ack, "", "{}", e),
+    };
+
+    broadcast_changes(&socket, &changes).await;
+
+    ack.send(&json!({ "files": files, "success": true })).ok();
+}
+
+pub async fn broadcast_changes(socket: &SocketRef, changes: &[Change]) {
+    for change in changes {
+        socket.emit("file:change", change).ok();
+        socket.broadcast().emit("file:change", change).await.ok();
+    }
+}
@@ -0,0 +1,147 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use socketioxide::{extract::{AckSender, Data, SocketRef, State}, SocketIo};
+use std::path::{Path, PathBuf};
+use tracing::{info, error};
+use crate::app_state::AppState;
+use crate::error_ack;
+use crate::git::ChangeKind;
+use crate::services;
+use crate::timing::EventTimer;
+use crate::{event_log, file_index};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct GitStatusRequest {
+    /// Directory in the repository, the client's workspace root if missing
+    pub path: Option<String>,
+}
+
+/// Git status of the files of the repository, `status` is null outside of
+/// one. Changes are pushed as `git:status` with the same payload.
+pub async fn handle_git_status(
+    socket: SocketRef,
+    Data(request): Data<GitStatusRequest>,
+    ack: AckSender,
+    state: State<AppState>,
+) {
+    info!("Received git:status: {:?}", request);
+    let mut timer = EventTimer::start("git:status").with_socket(socket.id).with_payload(&request);
+
+    let root = repo_root(&state, &mut timer, &socket, request.path.as_deref()).await;
+    let status = match crate::pool::spawn({
+        let root = root.clone();
+        async move { crate::git::status(&root) }
+    }).await {
+        Ok(Ok(status)) => status,
+        Ok(Err(e)) => error_ack!(ack, &root, "Failed to read git status: {}", e),
+        Err(e) => error_ack!(ack, &root, "Failed to read git status: {}", e),
+    };
+
+    ack.send(&json!({ "root": root, "status": status, "success": true })).ok();
+}
+
+/// Local and remote branches of the repository, `branches` is null
+/// outside of one
+pub async fn handle_git_branches(
+    socket: SocketRef,
+    Data(request): Data<GitStatusRequest>,
+    ack: AckSender,
+    state: State<AppState>,
+) {
+    info!("Received git:branches: {:?}", request);
+    let mut timer = EventTimer::start("git:branches").with_socket(socket.id).with_payload(&request);
+
+    let root = repo_root(&state, &mut timer, &socket, request.path.as_deref()).await;
+    let branches = match crate::pool::spawn({
+        let root = root.clone();
+        async move { crate::git::branches(&root) }
+    }).await {
+        Ok(Ok(branches)) => branches,
+        Ok(Err(e)) => error_ack!(ack, &root, "Failed to list branches: {}", e),
+        Err(e) => error_ack!(ack, &root, "Failed to list branches: {}", e),
+    };
+
+    ack.send(&json!({ "root": root, "branches": branches, "success": true })).ok();
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GitCheckoutRequest {
+    /// Directory in the repository, the client's workspace root if missing
+    pub path: Option<String>,
+    /// Local branch, or remote one like `origin/main` to track
+    pub branch: String,
+    /// Create the branch from HEAD
+    #[serde(default)]
+    pub create: bool,
+}
+
+/// Switch branches. Refused while open buffers of the repository have
+/// unsaved edits. The open buffers of the changed files are reloaded and
+/// sent as `file:changed`, the clients get the changed files as
+/// `watcher:create`, `watcher:modify` and `watcher:remove`.
+pub async fn handle_git_checkout(
+    socket: SocketRef,
+    Data(request): Data<GitCheckoutRequest>,
+    ack: AckSender,
+    io: SocketIo,
+    state: State<AppState>,
+) {
+    info!("Received git:checkout: {:?}", request);
+    let mut timer = EventTimer::start("git:checkout").with_socket(socket.id).with_payload(&request);
+
+    let root = repo_root(&state, &mut timer, &socket, request.path.as_deref()).await;
+    let Some(workdir) = crate::git::workdir(&root) else {
+        error_ack!(ack, &root, "{} is not in a git repository", root.display());
+    };
+    let unsaved = services::unsaved_buffers(&state, &mut timer, &workdir).await;
+    if !unsaved.is_empty() {
+        error_ack!(ack, &root, "Save or discard the changes of {} first", unsaved.join(", "));
+    }
+
+    let changes = match crate::pool::spawn({
+        let root = root.clone();
+        let branch = request.branch.clone();
+        let create = request.create;
+        async move { crate::git::checkout(&root, &branch, create) }
+    }).await {
+        Ok(Ok(changes)) => changes,
+        Ok(Err(e)) => error_ack!(ack, &root, "Failed to check out {}: {}", request.branch, e),
+        Err(e) => error_ack!(ack, &root, "Failed to check out {}: {}", request.branch, e),
+    };
+
+    let paths: Vec<_> = changes.iter().map(|c| c.path.clone()).collect();
+    let reloaded = services::reload_buffers(&state, &mut timer, &paths).await;
+    for (path, text) in &reloaded {
+        io.emit("file:changed", &(path, text)).await.ok();
+    }
+
+    for change in &changes {
+        let event = match change.kind {
+            ChangeKind::Added => {
+                file_index::add(&change.path);
+                "watcher:create"
+            }
+            ChangeKind::Modified => "watcher:modify",
+            ChangeKind::Deleted => {
+                file_index::remove(&change.path);
+                "watcher:remove"
+            }
+        };
+        let (path, exists) = (&change.path, change.kind != ChangeKind::Deleted);
+        let seq = event_log::record(event, &(path, exists));
+        io.emit(event, &(path, exists, seq)).await.ok();
+    }
+
+    let reloaded: Vec<&String> = reloaded.iter().map(|(path, _)| path).collect();
+    ack.send(&json!({
+        "root": root, "branch": request.branch, "changes": changes, "reloaded": reloaded, "success": true
+    })).ok();
+}
+
+/// The requested directory, else the client's workspace root
+async fn repo_root(state: &AppState, timer: &mut EventTimer, socket: &SocketRef, path: Option<&str>) -> PathBuf {
+    match path {
+        Some(path) => crate::paths::absolute(Path::new(path)),
+        None => services::workspace_root(state, timer, socket.id.as_str()).await,
+    }
+}
@@ -0,0 +1,55 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use socketioxide::extract::{AckSender, Data, SocketRef};
+use tracing::{info, error};
+use crate::error_ack;
+use crate::file_index;
+use anycode_search::ignore::{self, IgnoreRules};
+use crate::timing::EventTimer;
+
+/// Ignore rules by layer and the effective ones the tree, search and the
+/// watcher use
+pub async fn handle_ignore_get(ack: AckSender) {
+    info!("Received ignore:get");
+    let _timer = EventTimer::start("ignore:get");
+
+    let layers = ignore::layers();
+    ack.send(&json!({ "layers": layers, "effective": *ignore::rules(), "success": true })).ok();
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IgnoreSetRequest {
+    /// Workspace directories to hide on top of the defaults and env
+    #[serde(default)]
+    pub dirs: Vec<String>,
+    /// Workspace file names or `*.ext` patterns
+    #[serde(default)]
+    pub files: Vec<String>,
+}
+
+/// Replace the workspace overrides in .anycode/ignore. The other clients
+/// get `ignore:changed` and reload their tree, the quick-open index is
+/// rebuilt in the background.
+pub async fn handle_ignore_set(
+    socket: SocketRef,
+    Data(request): Data<IgnoreSetRequest>,
+    ack: AckSender,
+) {
+    info!("Received ignore:set: {:?}", request);
+    let _timer = EventTimer::start("ignore:set").with_socket(socket.id).with_payload(&request);
+
+    let rules = IgnoreRules { dirs: request.dirs, files: request.files };
+    let layers = match ignore::set_workspace(rules) {
+        Ok(layers) => layers,
+        Err(e) => error_ack!(ack, "", "Failed to save ignore rules: {}", e),
+    };
+
+    let response = json!({ "layers": layers, "effective": *ignore::rules(), "success": true });
+    let seq = crate::event_log::record("ignore:changed", &response);
+    socket.broadcast().emit("ignore:changed", &(&response, seq)).await.ok();
+    ack.send(&response).ok();
+
+    crate::pool::spawn(async {
+        file_index::rescan(std::path::Path::new("."));
+    });
+}
@@ -1,16 +1,25 @@
 use serde_json::{self, json};
 use socketioxide::{extract::{AckSender, Data, SocketRef, State}};
 use tracing::{info, error};
-use crate::{app_state::{AppState, SocketData}, code::Code};
+use crate::app_state::AppState;
 use serde::{Deserialize, Serialize};
-use crate::utils::{abs_file, is_ignored_path};
-use crate::app_state::*;
+use crate::utils::abs_file;
+use crate::timing::EventTimer;
 use crate::error_ack;
+use crate::services::{self, AlreadyExists, BatchFile, Change, DirtyBuffer, LoadedFile, Stale};
+use crate::readonly::ReadOnly;
+use crate::handlers::edit_handler::broadcast_changes;
+use crate::text_audit::normalization_edits;
+use crate::file_probe::NotText;
 
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct FileOpenRequest {
     pub path: String,
+    /// Open a file over the size limit or not in UTF-8 anyway, binary files
+    /// never open
+    #[serde(default)]
+    pub force: bool,
 }
 
 pub async fn handle_file_open(
@@ -20,257 +29,367 @@ pub async fn handle_file_open(
     state: State<AppState>
 ) {
     info!("Received file:open: {:?}", request);
-
-    let abs_path = match abs_file(&request.path) {
-        Ok(p) => p,
-        Err(e) => error_ack!(ack, &request.path, "Failed to resolve file: {:?}", e),
-    };
-
-    let mut f2c = state.file2code.lock().await;
-    let code = match get_or_create_code(&mut f2c, &abs_path, &state.config) {
-        Ok(c) => c,
-        Err(e) => error_ack!(ack, &abs_path, "{:?}", e),
+    let mut timer = EventTimer::start("file:open").with_socket(socket.id).with_payload(&request);
+
+    let file = match services::load_file(&state, &mut timer, &request.path, request.force).await {
+        Ok(f) => f,
+        Err(e) => match e.downcast_ref::<NotText>() {
+            Some(not_text) => {
+                ack.send(&not_text.response(&request.path)).ok();
+                return;
+            }
+            None => error_ack!(ack, &request.path, "{}", e),
+        },
     };
-    
-    let content = code.text.to_string();
 
     ack.send(&json!({
-        "content": content, "path": request.path, "success": true 
+        "content": file.content, "path": request.path, "read_only": file.read_only,
+        "version": file.version, "success": true
     })).ok();
 
-    let mut lsp_manager = state.lsp_manager.lock().await;
-    if let Some(lsp) = lsp_manager.get(&code.lang).await {
-        lsp.did_open(&code.lang, &abs_path, &content);
-    } 
+    // Virtual documents are tracked as open for scoped search but never
+    // reach the language servers or the recent files
+    if !crate::vfs::is_virtual(&file.abs_path) {
+        services::lsp_did_open(&state, &mut timer, &file).await;
+        timer.lock("recent", &state.recent).await.touch(&file.abs_path);
+    }
+
+    services::track_opened(&state, &mut timer, socket.id.as_str(), [file.abs_path]).await;
+}
+
+/// Virtual documents of all registered providers, opened with file:open
+pub async fn handle_vfs_list(ack: AckSender) {
+    info!("Received vfs:list");
+    let _timer = EventTimer::start("vfs:list");
 
-    let sid = socket.id.as_str().to_string();
-    let mut sockets_data = state.socket2data.lock().await;
-    let data = sockets_data.entry(sid).or_insert_with(SocketData::default);
-    data.opened_files.insert(abs_path);
+    ack.send(&json!({ "documents": crate::vfs::list(), "success": true })).ok();
+}
+
+/// Files larger than this are returned as metadata only by file:openBatch,
+/// the client opens them one by one with file:open when actually needed.
+const BATCH_INLINE_LIMIT: u64 = 1024 * 1024;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FileOpenBatchRequest {
+    pub paths: Vec<String>,
+}
+
+pub async fn handle_file_open_batch(
+    socket: SocketRef,
+    Data(request): Data<FileOpenBatchRequest>,
+    ack: AckSender,
+    state: State<AppState>
+) {
+    info!("Received file:openBatch: {} files", request.paths.len());
+    let mut timer = EventTimer::start("file:openBatch").with_socket(socket.id).with_payload(&request);
+
+    let files = services::open_batch(&state, &mut timer, &request.paths, BATCH_INLINE_LIMIT).await;
+    let results: Vec<_> = files.iter().zip(&request.paths).map(|(file, path)| file.response(path)).collect();
+    ack.send(&json!({ "files": results, "success": true })).ok();
+
+    let opened: Vec<&LoadedFile> = files.iter()
+        .filter_map(|file| match file {
+            BatchFile::Loaded(loaded) => Some(loaded),
+            _ => None,
+        })
+        .collect();
+    services::lsp_did_open_all(&state, &mut timer, &opened).await;
+    let paths = opened.iter().map(|file| file.abs_path.clone()).collect::<Vec<_>>();
+    services::track_opened(&state, &mut timer, socket.id.as_str(), paths).await;
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DirOpenRequest {
     pub path: String,
+    /// Page size, overrides the configured limit
+    pub limit: Option<usize>,
+    /// Cursor of the previous page, to continue a truncated listing
+    pub cursor: Option<String>,
 }
 
 pub async fn handle_dir_list(
+    socket: SocketRef,
     Data(request): Data<DirOpenRequest>,
     ack: AckSender,
-    _state: State<AppState>
+    state: State<AppState>
 ) {
     info!("Received dir:list: {:?}", request);
+    let mut timer = EventTimer::start("dir:list").with_socket(socket.id).with_payload(&request);
 
-    let dir = match request.path.as_str().trim() {
-        "" | "." | "./" => crate::utils::current_dir(),
-        d => d.to_string(),
-    };
-
-    let abs_path = match abs_file(&dir) {
-        Ok(p) => p,
-        Err(e) => error_ack!(ack, &dir, "Failed to resolve directory: {:?}", e),
+    let root = services::workspace_root(&state, &mut timer, socket.id.as_str()).await;
+    let limit = services::dir_limit(&state.config, request.limit);
+    let listing = match services::list_dir(&root, &request.path, limit, request.cursor.as_deref()) {
+        Ok(l) => l,
+        Err(e) => error_ack!(ack, &request.path, "{}", e),
     };
 
-    let name = crate::utils::file_name(&dir);
-    let mut relative_path = crate::utils::relative_path(&dir);
-    if relative_path.is_empty() {
-        relative_path = ".".to_string();
+    if let Err(err) = ack.send(&listing) {
+        error!("Failed to send acknowledgment: {:?}", err);
     }
+}
 
-    let entries = match std::fs::read_dir(&dir) {
-        Ok(e) => e,
-        Err(e) => error_ack!(ack, &dir, "Failed to open directory: {:?}", e),
-    };
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FileCloseRequest {
+    pub file: String,
+}
 
-    let mut files = Vec::new();
-    let mut dirs = Vec::new();
+pub async fn handle_file_close(
+    socket: SocketRef,
+    Data(request): Data<FileCloseRequest>,
+    state: State<AppState>,
+    ack: AckSender,
+) {
+    info!("Received file:close: {:?}", request);
+    let mut timer = EventTimer::start("file:close").with_socket(socket.id).with_payload(&request);
 
-    for entry in entries.flatten() {
-        let path = entry.path();
+    if let Err(e) = services::close_file(&state, &mut timer, socket.id.as_str(), &request.file).await {
+        error_ack!(ack, &request.file, "{}", e);
+    }
+}
 
-        if is_ignored_path(&path) {
-            continue;
-        }
+/// Apply a client's edits. With `base`, a change made on an older version
+/// of the buffer is rebased over the changes since and the sender gets the
+/// buffer back as `file:set`, or is refused with `stale` when it can't be.
+/// The ack has the new version.
+pub async fn handle_change(
+    socket: SocketRef,
+    Data(change): Data<Change>,
+    state: State<AppState>,
+    ack: AckSender,
+) {
+    info!("Received file:change: edits={} file={}", change.edits.len(), change.file);
+    let mut timer = EventTimer::start("file:change").with_socket(socket.id).with_payload(&change);
 
-        if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-            if path.is_dir() {
-                dirs.push(name.to_string());
-            } else {
-                files.push(name.to_string());
+    let applied = match services::apply_edits(&state, &mut timer, &change).await {
+        Ok((_, applied)) => applied,
+        Err(e) => {
+            tracing::error!("{}", e);
+            if let Some(read_only) = e.downcast_ref::<ReadOnly>() {
+                ack.send(&json!({
+                    "error": e.to_string(), "path": read_only.path, "read_only": read_only.reason, "success": false
+                })).ok();
+            } else if let Some(stale) = e.downcast_ref::<Stale>() {
+                ack.send(&json!({
+                    "error": e.to_string(), "path": stale.path, "stale": true, "version": stale.version, "success": false
+                })).ok();
+                resync(&socket, &state, &mut timer, &change.file).await;
             }
+            return;
         }
-    }
+    };
 
-    dirs.sort();
-    files.sort();
+    // Broadcast as a single message for other clients if needed
+    socket.broadcast().emit("file:change", &applied).await.ok();
+    ack.send(&json!({ "file": change.file, "version": applied.version, "success": true })).ok();
 
-    let message = json!({
-        "files": files,
-        "dirs": dirs,
-        "name": name,
-        "fullpath": abs_path,
-        "relative_path": relative_path,
-    });
+    // The sender's text misses the changes its edits were rebased over
+    if change.base.is_some_and(|base| applied.version != Some(base + 1)) {
+        resync(&socket, &state, &mut timer, &change.file).await;
+    }
+}
 
-    if let Err(err) = ack.send(&message) {
-        error!("Failed to send acknowledgment: {:?}", err);
+/// Send a client the text and version of a buffer it diverged from
+async fn resync(socket: &SocketRef, state: &AppState, timer: &mut EventTimer, file: &str) {
+    if let Some((text, version)) = services::buffer_text(state, timer, file).await {
+        socket.emit("file:set", &json!({ "file": file, "text": text, "version": version })).ok();
     }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct FileCloseRequest {
+pub struct FileHistoryRequest {
     pub file: String,
 }
 
-pub async fn handle_file_close(
+/// Undo the last change of a buffer from the history kept by the backend,
+/// a group of changes as one. The ack has the change to apply, `change` is
+/// null when there is nothing to undo, the other clients get it as
+/// `file:change`.
+pub async fn handle_file_undo(
     socket: SocketRef,
-    Data(request): Data<FileCloseRequest>,
+    Data(request): Data<FileHistoryRequest>,
     state: State<AppState>,
     ack: AckSender,
 ) {
-    info!("Received file:close: {:?}", request);
+    info!("Received file:undo: {:?}", request);
+    let mut timer = EventTimer::start("file:undo").with_socket(socket.id).with_payload(&request);
+    history_step(socket, &request.file, &state, &mut timer, ack, false).await;
+}
 
-    let abs_path = match abs_file(&request.file) {
-        Ok(p) => p,
-        Err(e) => error_ack!(ack, &request, "Failed to resolve file: {:?}", e),
-    };
+/// Redo the last undone change of a buffer, see file:undo
+pub async fn handle_file_redo(
+    socket: SocketRef,
+    Data(request): Data<FileHistoryRequest>,
+    state: State<AppState>,
+    ack: AckSender,
+) {
+    info!("Received file:redo: {:?}", request);
+    let mut timer = EventTimer::start("file:redo").with_socket(socket.id).with_payload(&request);
+    history_step(socket, &request.file, &state, &mut timer, ack, true).await;
+}
 
-    let mut f2c = state.file2code.lock().await;
-    let code = match get_or_create_code(&mut f2c, &abs_path, &state.config) {
-        Ok(c) => c,
-        Err(e) => error_ack!(ack, &abs_path, "{:?}", e),
+async fn history_step(socket: SocketRef, file: &str, state: &AppState, timer: &mut EventTimer, ack: AckSender, redo: bool) {
+    let change = match services::undo_change(state, timer, file, redo).await {
+        Ok(change) => change,
+        Err(e) => match e.downcast_ref::<ReadOnly>() {
+            Some(read_only) => {
+                error!("{}", e);
+                ack.send(&json!({
+                    "error": e.to_string(), "path": read_only.path, "read_only": read_only.reason, "success": false
+                })).ok();
+                return;
+            }
+            None => error_ack!(ack, file, "{}", e),
+        },
     };
 
-    let mut lsp_manager = state.lsp_manager.lock().await;
-    if let Some(lsp) = lsp_manager.get(&code.lang).await {
-        lsp.did_close(&abs_path);
+    if let Some(change) = &change {
+        socket.broadcast().emit("file:change", change).await.ok();
     }
-
-    let sid = socket.id.as_str().to_string();
-    let mut sockets_data = state.socket2data.lock().await;
-    let data = sockets_data.entry(sid).or_insert_with(SocketData::default);
-    data.opened_files.remove(&abs_path);
+    ack.send(&json!({ "file": file, "change": change, "success": true })).ok();
 }
 
-
 #[derive(Debug, Serialize, Deserialize, Clone)]
-#[serde(rename_all = "lowercase")]
-pub enum Operation {
-    Insert,
-    Remove,
+pub struct MakeWritableRequest {
+    pub path: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct Edit {
-    pub operation: Operation,
-    pub start: usize,
-    pub text: String,
+/// Allow changes to a read-only document for the rest of the session,
+/// write-protected files also get their write permission back
+pub async fn handle_make_writable(Data(request): Data<MakeWritableRequest>, ack: AckSender) {
+    info!("Received file:makeWritable: {:?}", request);
+    let _timer = EventTimer::start("file:makeWritable").with_payload(&request);
+
+    let abs_path = match abs_file(&request.path) {
+        Ok(p) => p,
+        Err(e) => error_ack!(ack, &request.path, "Failed to resolve file: {:?}", e),
+    };
+
+    if let Err(e) = crate::readonly::make_writable(&abs_path) {
+        error_ack!(ack, &abs_path, "Failed to make {} writable: {}", abs_path, e);
+    }
+    ack.send(&json!({ "path": abs_path, "success": true })).ok();
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct Change {
-    pub file: String,
-    pub edits: Vec<Edit>,
+pub struct RestoreFromBufferRequest {
+    pub path: String,
 }
 
-pub async fn handle_change(
-    socket: SocketRef,
-    Data(change): Data<Change>,
+/// Write the buffer of a file deleted by another program back to disk,
+/// offered to the user with `file:deletedExternally`
+pub async fn handle_restore_from_buffer(
+    Data(request): Data<RestoreFromBufferRequest>,
     state: State<AppState>,
-    _ack: AckSender,
+    ack: AckSender,
 ) {
-    info!("Received file:change: edits={} file={}", change.edits.len(), change.file);
-
-    let abs_path = match abs_file(&change.file) {
-        Ok(p) => p,
-        Err(e) => {
-            tracing::error!("Failed to resolve file: {:?}", e);
-            return;
-        }
-    };
-
-    let mut f2c = state.file2code.lock().await;
-    let code = match get_or_create_code(&mut f2c, &abs_path, &state.config) {
-        Ok(c) => c,
-        Err(e) => {
-            tracing::error!("Failed to get code: {:?}", e);
-            return;
-        }
-    };
+    info!("Received file:restoreFromBuffer: {:?}", request);
+    let mut timer = EventTimer::start("file:restoreFromBuffer").with_payload(&request);
 
-    let mut lsp_manager = state.lsp_manager.lock().await;
+    match services::restore_from_buffer(&state, &mut timer, &request.path).await {
+        Ok(abs_path) => { ack.send(&json!({ "path": abs_path, "success": true })).ok(); }
+        Err(e) => error_ack!(ack, &request.path, "{}", e),
+    }
+}
 
-    for e in change.edits.iter() {
-        match e.operation {
-            Operation::Insert => {
-                let start_char = code.utf16_to_char_offset(e.start);
-                let (line, col_utf16) = code.char_to_position(start_char);
-                code.insert_text_at(&e.text, start_char);
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DirtyDiffRequest {
+    pub path: String,
+}
 
-                if let Some(lsp) = lsp_manager.get(&code.lang).await {
-                    lsp.did_change(line, col_utf16, line, col_utf16, &abs_path, &e.text).await;
-                }
-            }
-            Operation::Remove => {
-                let start_char = code.utf16_to_char_offset(e.start);
-                let end_char = code.utf16_to_char_offset(e.start + e.text.encode_utf16().count());
-                let (start_line, start_col_utf16) = code.char_to_position(start_char);
-                let (end_line, end_col_utf16) = code.char_to_position(end_char);
-
-                code.remove_text2(start_char, end_char);
-
-                if let Some(lsp) = lsp_manager.get(&code.lang).await {
-                    lsp.did_change(
-                        start_line, start_col_utf16,
-                        end_line, end_col_utf16,
-                        &abs_path, "",
-                    )
-                    .await;
-                }
-            }
-        }
-    }
+/// Lines added, modified and removed since the buffer was loaded or saved,
+/// for the gutter markers of pending changes. Files that are not open
+/// have no changes.
+pub async fn handle_dirty_diff(
+    Data(request): Data<DirtyDiffRequest>,
+    state: State<AppState>,
+    ack: AckSender,
+) {
+    info!("Received file:dirtyDiff: {:?}", request);
+    let mut timer = EventTimer::start("file:dirtyDiff").with_payload(&request);
 
-    // Broadcast as a single message for other clients if needed
-    socket.broadcast().emit("file:change", &change).await.ok();
+    let (abs_path, changed, ranges) = services::dirty_diff(&state, &mut timer, &request.path).await;
+    ack.send(&json!({ "path": abs_path, "changed": changed, "ranges": ranges, "success": true })).ok();
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct FileSaveRequest {
     pub path: String,
+    /// Overwrite even if the file changed on disk
+    pub force: Option<bool>,
+    /// What to do with the normalizations of the `[save]` hooks
+    #[serde(default)]
+    pub normalize: SaveNormalize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SaveNormalize {
+    /// Save as is, the ack lists the normalizations left
+    #[default]
+    Report,
+    /// Only list the normalizations, don't save
+    Preview,
+    /// Apply them as one `file:change` of their own, then save
+    Apply,
 }
 
+/// Save a buffer. The normalizations of the `[save]` hooks are never made
+/// silently: a plain save reports them in `normalizations`, `preview`
+/// lists them without saving and `apply` sends them to every client as a
+/// separate change, undoable on its own, before writing the file.
 pub async fn handle_file_save(
-    _socket: SocketRef,
+    socket: SocketRef,
     Data(request): Data<FileSaveRequest>,
     state: State<AppState>,
     ack: AckSender,
 ) {
     info!("Received file:save: {:?}", request.path);
+    let mut timer = EventTimer::start("file:save").with_socket(socket.id).with_payload(&request);
 
-    let abs_path = match abs_file(&request.path) {
-        Ok(p) => p,
-        Err(e) => error_ack!(ack, &request.path, "Failed to resolve file: {:?}", e),
+    let (file, pending) = match services::save_normalizations(&state, &mut timer, &request.path).await {
+        Ok(n) => n,
+        Err(e) => error_ack!(ack, &request.path, "{}", e),
     };
-
-    let mut f2c = state.file2code.lock().await;
-    let code = match get_or_create_code(&mut f2c, &abs_path, &state.config) {
-        Ok(c) => c,
-        Err(e) => error_ack!(ack, &abs_path, "{:?}", e),
+    match request.normalize {
+        SaveNormalize::Preview => {
+            ack.send(&json!({ "file": file, "normalizations": pending, "saved": false, "success": true })).ok();
+            return;
+        }
+        SaveNormalize::Apply if !pending.is_empty() => {
+            let change = Change { file: file.clone(), edits: normalization_edits(&pending), base: None, version: None };
+            let change = match services::apply_edits(&state, &mut timer, &change).await {
+                Ok((_, applied)) => applied,
+                Err(e) => error_ack!(ack, &request.path, "{}", e),
+            };
+            broadcast_changes(&socket, &[change]).await;
+        }
+        _ => {}
+    }
+    let (normalized, pending) = match request.normalize {
+        SaveNormalize::Apply => (pending, Vec::new()),
+        _ => (Vec::new(), pending),
     };
 
-    if let Err(e) = code.save_file() {
-        error_ack!(ack, &abs_path, "Failed to save file: {:?}", e);
-    }
+    let force = request.force.unwrap_or(false);
+    let abs_path = match services::save_file(&state, &mut timer, &request.path, force).await {
+        Ok(p) => p,
+        Err(e) if e.is::<services::SaveConflict>() => {
+            error!("{}", e);
+            let response = json!({
+                "error": e.to_string(), "path": request.path, "conflict": true, "success": false
+            });
+            ack.send(&response).ok();
+            return;
+        }
+        Err(e) => error_ack!(ack, &request.path, "{}", e),
+    };
 
     info!("File saved successfully: {}", abs_path);
 
-    let mut lsp_manager = state.lsp_manager.lock().await;
-    if let Some(lsp) = lsp_manager.get(&code.lang).await {
-        lsp.did_save(&abs_path, Some(&code.text.to_string()));
-    }
-
-    ack.send(&json!({ "success": true, "file": abs_path })).ok();
+    let response = json!({
+        "success": true, "file": abs_path, "normalized": normalized, "normalizations": pending
+    });
+    ack.send(&response).ok();
 }
 
 
@@ -288,30 +407,17 @@ pub async fn handle_file_set(
     ack: AckSender,
 ) {
     info!("Received file:set: {:?}", file_set_request);
+    let mut timer = EventTimer::start("file:set").with_socket(socket.id).with_payload(&file_set_request);
 
-    let abs_path = match abs_file(&file_set_request.file) {
+    let abs_path = match services::set_file(
+        &state, &mut timer, &file_set_request.file, &file_set_request.text
+    ).await {
         Ok(p) => p,
-        Err(e) => error_ack!(ack, &file_set_request.file, "Failed to resolve file: {:?}", e),
+        Err(e) => error_ack!(ack, &file_set_request.file, "{}", e),
     };
 
-    let mut f2c = state.file2code.lock().await;
-    let code = f2c.entry(abs_path.clone()).or_insert_with(|| Code::new());
-
-    code.set_file_name(abs_path.clone());
-    code.ensure_file_exists().ok();
-    code.set_text(&file_set_request.text);
-
-    if let Err(e) = code.save_file() {
-        error_ack!(ack, &abs_path, "Failed to set file: {:?}", e);
-    }
-
     info!("File set successfully: {}", abs_path);
 
-    let mut lsp_manager = state.lsp_manager.lock().await;
-    if let Some(lsp) = lsp_manager.get(&code.lang).await {
-        lsp.did_save(&abs_path, Some(&file_set_request.text));
-    }
-
     socket.broadcast().emit(
         "file:changed",
         &(abs_path.clone(), file_set_request.text.clone())
@@ -325,6 +431,12 @@ pub struct CreateRequest {
     pub parent_path: String,
     pub name: String,
     pub is_file: bool,
+    /// Truncate an existing file instead of failing with `exists`
+    #[serde(default)]
+    pub overwrite: bool,
+    /// Content of the new file, `${author}`, `${project}`, `${year}`,
+    /// `${license_header}` and the other template variables filled in
+    pub template: Option<String>,
 }
 
 pub async fn handle_create(
@@ -334,63 +446,112 @@ pub async fn handle_create(
     ack: AckSender,
 ) {
     info!("Received create: {:?}", request);
+    let mut timer = EventTimer::start("file:create").with_socket(socket.id).with_payload(&request);
     
-    let parent_path = &request.parent_path;
-    let name = &request.name;
-    let is_file = request.is_file;
-    
-    let full_path = if parent_path.is_empty() || parent_path == "." || parent_path == "./" {
-        name.clone()
-    } else {
-        format!("{}/{}", parent_path, name)
+    let created = services::create_entry(
+        &state, &mut timer, &request.parent_path, &request.name,
+        request.is_file, request.template.as_deref(), request.overwrite,
+    ).await;
+    let full_path = match created {
+        Ok(path) => path,
+        Err(e) => match (e.downcast_ref::<services::AlreadyExists>(), e.downcast_ref::<services::DirtyBuffer>()) {
+            (Some(AlreadyExists { path }), _) | (_, Some(DirtyBuffer { path })) => {
+                error!("{}", e);
+                let response = json!({
+                    "error": e.to_string(),
+                    "path": path,
+                    "exists": e.is::<services::AlreadyExists>(),
+                    "conflict": e.is::<services::DirtyBuffer>(),
+                    "success": false,
+                });
+                ack.send(&response).ok();
+                return;
+            }
+            _ => error_ack!(ack, &request.name, "{}", e),
+        },
     };
 
-    // For relative paths, we need to join with current directory
-    let full_path = if full_path.starts_with('/') {
-        full_path
+    if request.is_file {
+        info!("File created successfully: {}", full_path);
+        let seq = crate::event_log::record("file:created", &full_path);
+        socket.broadcast().emit("file:created", &(&full_path, seq)).await.ok();
+        ack.send(&json!({ "success": true, "file": full_path, "is_file": true })).ok();
     } else {
-        // Relative path, join with current directory
-        let current_dir = std::env::current_dir().unwrap_or_default();
-        current_dir.join(&full_path).to_string_lossy().to_string()
-    };
-
-    // Create parent directories if they don't exist
-    let path_buf = std::path::PathBuf::from(&full_path);
-    if let Some(parent) = path_buf.parent() {
-        if let Err(e) = std::fs::create_dir_all(parent) {
-            error_ack!(ack, &request.name, "Failed to create parent directories: {:?}", e);
-        }
+        info!("Directory created successfully: {}", full_path);
+        let seq = crate::event_log::record("dir:created", &full_path);
+        socket.broadcast().emit("dir:created", &(&full_path, seq)).await.ok();
+        ack.send(&json!({ "success": true, "dir": full_path, "is_file": false })).ok();
     }
+}
 
-    if is_file {
-        // Create empty file
-        match std::fs::File::create(&full_path) {
-            Ok(_) => {
-                info!("File created successfully: {}", full_path);
-                let mut f2c = state.file2code.lock().await;
-                let code = f2c.entry(full_path.clone()).or_insert_with(|| {
-                    Code::new()
-                });
-                code.set_file_name(full_path.clone());
-                
-                socket.broadcast().emit("file:created", &full_path).await.ok();
-                ack.send(&json!({ "success": true, "file": full_path, "is_file": true })).ok();
-            },
-            Err(e) => {
-                error_ack!(ack, &request.name, "Failed to create file: {:?}", e);
-            }
-        }
-    } else {
-        // Create directory
-        match std::fs::create_dir(&full_path) {
-            Ok(_) => {
-                info!("Directory created successfully: {}", full_path);
-                socket.broadcast().emit("dir:created", &full_path).await.ok();
-                ack.send(&json!({ "success": true, "dir": full_path, "is_file": false })).ok();
-            },
-            Err(e) => {
-                error_ack!(ack, &request.name, "Failed to create directory: {:?}", e);
-            }
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FileRenameRequest {
+    pub from: String,
+    pub to: String,
+    /// Replace an existing destination instead of failing with `exists`
+    #[serde(default)]
+    pub overwrite: bool,
+}
+
+/// Rename or move a file or directory. Open buffers keep their content and
+/// undo history under the new path, the other clients get `file:renamed`.
+pub async fn handle_file_rename(
+    socket: SocketRef,
+    Data(request): Data<FileRenameRequest>,
+    state: State<AppState>,
+    ack: AckSender,
+) {
+    info!("Received file:rename: {:?}", request);
+    let mut timer = EventTimer::start("file:rename").with_socket(socket.id).with_payload(&request);
+
+    let renamed = services::rename_file(&state, &mut timer, &request.from, &request.to, request.overwrite).await;
+    let (from, to, moved) = match renamed {
+        Ok(renamed) => renamed,
+        Err(e) if e.is::<services::AlreadyExists>() || e.is::<services::DirtyBuffer>() => {
+            error!("{}", e);
+            let response = json!({
+                "error": e.to_string(),
+                "path": request.to,
+                "exists": e.is::<services::AlreadyExists>(),
+                "conflict": e.is::<services::DirtyBuffer>(),
+                "success": false,
+            });
+            ack.send(&response).ok();
+            return;
         }
-    }
-}
\ No newline at end of file
+        Err(e) => error_ack!(ack, &request.from, "{}", e),
+    };
+
+    info!("Renamed {} -> {}", from, to);
+    let renamed = json!({ "from": from, "to": to, "moved": moved });
+    let seq = crate::event_log::record("file:renamed", &renamed);
+    socket.broadcast().emit("file:renamed", &(&renamed, seq)).await.ok();
+    ack.send(&json!({ "success": true, "from": from, "to": to, "moved": moved })).ok();
+}
+
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FilePeekRequest {
+    pub path: String,
+    pub line: usize,
+    /// Lines before and after `line`
+    pub context: Option<usize>,
+}
+
+/// Read-only window of a file for search and reference previews, does
+/// not open the file
+pub async fn handle_file_peek(
+    Data(request): Data<FilePeekRequest>,
+    ack: AckSender,
+    state: State<AppState>
+) {
+    info!("Received file:peek: {:?}", request);
+    let mut timer = EventTimer::start("file:peek").with_payload(&request);
+
+    let peek = match services::peek_file(&state, &mut timer, &request.path, request.line, request.context).await {
+        Ok(peek) => peek,
+        Err(e) => error_ack!(ack, &request.path, "{}", e),
+    };
+
+    ack.send(&json!({ "path": request.path, "peek": peek, "success": true })).ok();
+}
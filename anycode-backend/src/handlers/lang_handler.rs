@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use socketioxide::extract::{AckSender, Data, SocketRef, State};
+use tracing::{info, error};
+use crate::app_state::AppState;
+use crate::error_ack;
+use crate::lang_rules::{self, LangRule, LangRules};
+use crate::timing::EventTimer;
+
+/// Language rules of the workspace, see lang_rules.rs, with the types of
+/// the configured languages they take precedence over
+pub async fn handle_languages_rules(ack: AckSender, state: State<AppState>) {
+    info!("Received languages:rules");
+    let _timer = EventTimer::start("languages:rules");
+
+    let configured: Vec<_> = state.config.language.iter()
+        .map(|lang| json!({ "name": lang.name, "types": lang.types }))
+        .collect();
+    ack.send(&json!({ "rules": lang_rules::rules().rules, "configured": configured, "success": true })).ok();
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LanguagesSetRulesRequest {
+    /// In order, the first matching rule wins
+    pub rules: Vec<LangRule>,
+}
+
+/// Replace the rules, the other clients get them as
+/// `languages:rulesChanged`
+pub async fn handle_languages_set_rules(
+    socket: SocketRef,
+    Data(request): Data<LanguagesSetRulesRequest>,
+    ack: AckSender,
+) {
+    info!("Received languages:setRules: {:?}", request);
+    let _timer = EventTimer::start("languages:setRules").with_socket(socket.id).with_payload(&request);
+
+    let rules = match lang_rules::set_rules(LangRules { rules: request.rules }) {
+        Ok(rules) => rules,
+        Err(e) => error_ack!(ack, "", "Failed to save language rules: {}", e),
+    };
+
+    let response = json!({ "rules": rules.rules, "success": true });
+    socket.broadcast().emit("languages:rulesChanged", &response).await.ok();
+    ack.send(&response).ok();
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LanguagesDetectRequest {
+    pub paths: Vec<String>,
+}
+
+/// The effective language of each path and what decided it
+pub async fn handle_languages_detect(
+    Data(request): Data<LanguagesDetectRequest>,
+    ack: AckSender,
+    state: State<AppState>,
+) {
+    info!("Received languages:detect: {} paths", request.paths.len());
+    let _timer = EventTimer::start("languages:detect").with_payload(&request);
+
+    let languages: Vec<_> = request.paths.iter()
+        .map(|path| {
+            let detection = lang_rules::detect(path, &state.config);
+            json!({ "path": path, "lang": detection.lang, "source": detection.source, "rule": detection.rule })
+        })
+        .collect();
+    ack.send(&json!({ "languages": languages, "success": true })).ok();
+}
@@ -1,49 +1,109 @@
 use serde::{Deserialize, Serialize};
 use serde_json::{self, json};
-use socketioxide::{extract::{AckSender, Data, State}};
+use socketioxide::{extract::{AckSender, Data, SocketRef, State}};
 use tracing::{info, error};
+use crate::timing::EventTimer;
 use crate::app_state::AppState;
 use crate::app_state::*;
 use crate::error_ack;
-use crate::utils::abs_file;
+use crate::utils::{abs_file, relative_path};
+use crate::words::{self, word_at};
+use lsp_types::{CompletionItem, CompletionItemKind};
+use crate::services;
+use crate::lsp_requests;
+use crate::symbol_cache;
+use crate::semantic_tokens::{self, Answer};
+use crate::rename;
+use crate::rich_text;
+use crate::path_completion;
+use crate::handlers::edit_handler::broadcast_changes;
+
+/// Word completions offered when the language has no server
+const MAX_WORD_COMPLETIONS: usize = 50;
+/// Workspace symbols returned when the request sets no limit
+const MAX_WORKSPACE_SYMBOLS: usize = 100;
+
+/// Ack of a request cancelled with lsp:cancel or superseded by a newer
+/// one, the late result is dropped
+fn ack_cancelled(ack: AckSender, id: Option<u64>) {
+    ack.send(&json!({ "id": id, "cancelled": true, "success": false })).ok();
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CompletionRequest {
     pub file: String,
     pub row: usize,
     pub column: usize,
+    /// Client id of the request, for lsp:cancel
+    #[serde(default)]
+    pub id: Option<u64>,
 }
 
 pub async fn handle_completion(
+    socket: SocketRef,
     Data(request): Data<CompletionRequest>,
     ack: AckSender,
     state: State<AppState>
 ) {
     info!("handle_completion {:?}", request);
-    let CompletionRequest { file, row, column } = request;
+    let mut timer = EventTimer::start("lsp:completion").with_socket(socket.id).with_payload(&request);
+    let CompletionRequest { file, row, column, id } = request;
 
     let abs_path = match abs_file(&file) {
         Ok(p) => p,
         Err(e) => error_ack!(ack, &file, "Failed to resolve file: {:?}", e),
     };
 
-    let mut f2c = state.file2code.lock().await;
+    let mut f2c = timer.lock("file2code", &state.file2code).await;
     let code = match get_or_create_code(&mut f2c, &abs_path, &state.config) {
         Ok(c) => c,
         Err(e) => error_ack!(ack, &abs_path, "{:?}", e),
     };
 
-    let mut lsp_manager = state.lsp_manager.lock().await;
+    // Paths in strings complete from the file index, in every language
+    let mut result = path_completion::complete(&code.text, row, column, &relative_path(&abs_path));
 
-    let result = match lsp_manager.get(&code.lang).await {
-        Some(lsp) => {
-            lsp.completion(&abs_path, row, column).await
-                .ok().unwrap_or_else(|| Vec::new())
-        },
-        None => Vec::new()
+    let request = lsp_requests::begin(socket.id.as_str(), "completion", id);
+    let mut lsp_manager = timer.lock("lsp_manager", &state.lsp_manager).await;
+    // Superseded while waiting for the lock
+    if request.is_cancelled() {
+        return ack_cancelled(ack, id);
+    }
+
+    // The request runs without the manager lock, the scheduler of the
+    // server lets it pass background requests
+    let client = lsp_manager.get_for(&code.lang, &abs_path).await.map(|lsp| lsp.client());
+    drop(lsp_manager);
+    if let Some(lsp) = client {
+        drop(f2c);
+        match request.scope(lsp.completion(&abs_path, row, column)).await {
+            Err(e) if lsp_requests::is_cancelled(&e) => ack_cancelled(ack, id),
+            server => {
+                result.extend(server.unwrap_or_default());
+                ack.send(&rich_text::completions_json(&result)).ok();
+            }
+        }
+        return;
+    }
+
+    // No language server, complete the prefix under the cursor with the
+    // words of the document
+    let line_start = code.text.line_to_char(row.min(code.text.len_lines() - 1));
+    let offset = code.utf16_to_char_offset(code.char_to_utf16_offset(line_start) + column);
+    let words: Vec<CompletionItem> = match word_at(&code.text, offset, &code.lang) {
+        Some(word) if offset > word.start && result.is_empty() => {
+            let prefix = code.text.slice(word.start..offset).to_string();
+            words::completions(&code.text, &prefix, &code.lang, MAX_WORD_COMPLETIONS).into_iter()
+                .map(|label| CompletionItem {
+                    label, kind: Some(CompletionItemKind::TEXT), ..Default::default()
+                })
+                .collect()
+        }
+        _ => Vec::new(),
     };
+    result.extend(words);
 
-    ack.send(&result).ok();
+    ack.send(&rich_text::completions_json(&result)).ok();
 }
 
 
@@ -52,34 +112,48 @@ pub struct HoverRequest {
     pub file: String,
     pub row: usize,
     pub column: usize,
+    /// Client id of the request, for lsp:cancel
+    #[serde(default)]
+    pub id: Option<u64>,
 }
 
 pub async fn handle_hover(
+    socket: SocketRef,
     Data(request): Data<HoverRequest>,
     ack: AckSender,
     state: State<AppState>
 ) {
     info!("handle_completion {}", request.file);
-    let HoverRequest { file, row, column } = request;
+    let mut timer = EventTimer::start("lsp:hover").with_socket(socket.id).with_payload(&request);
+    let HoverRequest { file, row, column, id } = request;
 
     let abs_path = match abs_file(&file) {
         Ok(p) => p,
         Err(e) => error_ack!(ack, &file, "Failed to resolve file: {:?}", e),
     };
 
-    let mut f2c = state.file2code.lock().await;
+    let mut f2c = timer.lock("file2code", &state.file2code).await;
     let code = match get_or_create_code(&mut f2c, &abs_path, &state.config) {
         Ok(c) => c,
         Err(e) => error_ack!(ack, &abs_path, "{:?}", e),
     };
-    
-    let mut lsp_manager = state.lsp_manager.lock().await;
 
-    if let Some(lsp) = lsp_manager.get(&code.lang).await {
-        match lsp.hover(&abs_path, row, column).await {
+    let request = lsp_requests::begin(socket.id.as_str(), "hover", id);
+    let mut lsp_manager = timer.lock("lsp_manager", &state.lsp_manager).await;
+    if request.is_cancelled() {
+        return ack_cancelled(ack, id);
+    }
+
+    let client = lsp_manager.get_for(&code.lang, &abs_path).await.map(|lsp| lsp.client());
+    drop(lsp_manager);
+    drop(f2c);
+
+    if let Some(lsp) = client {
+        match request.scope(lsp.hover(&abs_path, row, column)).await {
             Ok(hover) => {
-                ack.send(&hover).ok();
+                ack.send(&rich_text::hover_json(&hover)).ok();
             }
+            Err(e) if lsp_requests::is_cancelled(&e) => ack_cancelled(ack, id),
             Err(e) => {
                 ack.send(&json!({ "error": format!("Hover request failed: {}", e) })).ok();
             }
@@ -87,39 +161,148 @@ pub async fn handle_hover(
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SignatureTrigger {
+    /// Asked for by the user, e.g. with a shortcut
+    Invoked,
+    /// A trigger or retrigger character was typed
+    TriggerCharacter,
+    /// The cursor moved or the text changed while the hints were open
+    ContentChange,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SignatureHelpRequest {
+    pub file: String,
+    pub row: usize,
+    pub column: usize,
+    #[serde(default)]
+    pub trigger: Option<SignatureTrigger>,
+    /// The typed character of a `trigger_character` trigger
+    #[serde(default)]
+    pub trigger_character: Option<String>,
+    /// Whether the hints are open already
+    #[serde(default)]
+    pub is_retrigger: bool,
+    /// The hints shown, the server keeps the chosen overload
+    #[serde(default)]
+    pub active_signature_help: Option<lsp_types::SignatureHelp>,
+    /// Client id of the request, for lsp:cancel
+    #[serde(default)]
+    pub id: Option<u64>,
+}
+
+/// Parameter hints at the position. The ack also carries the trigger and
+/// retrigger characters of the server, so the editor knows when to ask
+/// while typing; `signature_help` is null outside of a call.
+pub async fn handle_signature_help(
+    socket: SocketRef,
+    Data(request): Data<SignatureHelpRequest>,
+    ack: AckSender,
+    state: State<AppState>
+) {
+    info!("Received lsp:signature_help: {:?}", request);
+    let mut timer = EventTimer::start("lsp:signature_help").with_socket(socket.id).with_payload(&request);
+    let SignatureHelpRequest {
+        file, row, column, trigger, trigger_character, is_retrigger, active_signature_help, id,
+    } = request;
+
+    let abs_path = match abs_file(&file) {
+        Ok(p) => p,
+        Err(e) => error_ack!(ack, &file, "Failed to resolve file: {:?}", e),
+    };
+
+    let lang = {
+        let mut f2c = timer.lock("file2code", &state.file2code).await;
+        match get_or_create_code(&mut f2c, &abs_path, &state.config) {
+            Ok(c) => c.lang.clone(),
+            Err(e) => error_ack!(ack, &abs_path, "{:?}", e),
+        }
+    };
+
+    let request = lsp_requests::begin(socket.id.as_str(), "signature_help", id);
+    let mut lsp_manager = timer.lock("lsp_manager", &state.lsp_manager).await;
+    if request.is_cancelled() {
+        return ack_cancelled(ack, id);
+    }
+    let Some(lsp) = lsp_manager.get_for(&lang, &abs_path).await.map(|lsp| lsp.client()) else {
+        error_ack!(ack, &abs_path, "No language server for {}", lang);
+    };
+    drop(lsp_manager);
+
+    let context = trigger.map(|trigger| lsp_types::SignatureHelpContext {
+        trigger_kind: match trigger {
+            SignatureTrigger::Invoked => lsp_types::SignatureHelpTriggerKind::INVOKED,
+            SignatureTrigger::TriggerCharacter => lsp_types::SignatureHelpTriggerKind::TRIGGER_CHARACTER,
+            SignatureTrigger::ContentChange => lsp_types::SignatureHelpTriggerKind::CONTENT_CHANGE,
+        },
+        trigger_character,
+        is_retrigger,
+        active_signature_help,
+    });
+    let (trigger_characters, retrigger_characters) = lsp.signature_help_triggers();
+
+    match request.scope(lsp.signature_help(&abs_path, row, column, context)).await {
+        Ok(help) => {
+            ack.send(&json!({
+                "id": id,
+                "signature_help": help.as_ref().map(rich_text::signature_help_json),
+                "trigger_characters": trigger_characters,
+                "retrigger_characters": retrigger_characters,
+                "success": true,
+            })).ok();
+        }
+        Err(e) if lsp_requests::is_cancelled(&e) => ack_cancelled(ack, id),
+        Err(e) => error_ack!(ack, &abs_path, "Signature help failed: {}", e),
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DefinitionRequest {
     pub file: String,
     pub row: usize,
     pub column: usize,
+    /// Client id of the request, for lsp:cancel
+    #[serde(default)]
+    pub id: Option<u64>,
 }
 
 pub async fn handle_definition(
+    socket: SocketRef,
     Data(request): Data<DefinitionRequest>,
     ack: AckSender,
     state: State<AppState>
 ) {
     info!("handle_definition {}", request.file);
-    let DefinitionRequest { file, row, column } = request;
+    let mut timer = EventTimer::start("lsp:definition").with_socket(socket.id).with_payload(&request);
+    let DefinitionRequest { file, row, column, id } = request;
 
     let abs_path = match abs_file(&file) {
         Ok(p) => p,
         Err(e) => error_ack!(ack, &file, "Failed to resolve file: {:?}", e),
     };
 
-    let mut f2c = state.file2code.lock().await;
+    let mut f2c = timer.lock("file2code", &state.file2code).await;
     let code = match get_or_create_code(&mut f2c, &abs_path, &state.config) {
         Ok(c) => c,
         Err(e) => error_ack!(ack, &abs_path, "{:?}", e),
     };
 
-    let mut lsp_manager = state.lsp_manager.lock().await;
+    let request = lsp_requests::begin(socket.id.as_str(), "definition", id);
+    let mut lsp_manager = timer.lock("lsp_manager", &state.lsp_manager).await;
+    if request.is_cancelled() {
+        return ack_cancelled(ack, id);
+    }
     
-    let result = match lsp_manager.get(&code.lang).await {
-        Some(lsp) => {
-            lsp.definition(&abs_path, row, column).await
-                .ok().unwrap_or_else(|| Vec::new())
+    let client = lsp_manager.get_for(&code.lang, &abs_path).await.map(|lsp| lsp.client());
+    drop(lsp_manager);
+    drop(f2c);
+
+    let result = match client {
+        Some(lsp) => match request.scope(lsp.definition(&abs_path, row, column)).await {
+            Err(e) if lsp_requests::is_cancelled(&e) => return ack_cancelled(ack, id),
+            result => result.unwrap_or_default(),
         },
         None => Vec::new()
     };
@@ -128,39 +311,727 @@ pub async fn handle_definition(
 }
 
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RenameRequest {
+    pub file: String,
+    pub row: usize,
+    pub column: usize,
+    pub new_name: String,
+    /// Save the renamed files, otherwise they stay modified in the buffers
+    #[serde(default = "default_save")]
+    pub save: bool,
+    /// Client id of the request, for lsp:cancel
+    #[serde(default)]
+    pub id: Option<u64>,
+}
+
+fn default_save() -> bool { true }
+
+/// Rename the symbol at the position with the language server and apply
+/// the edits to every affected file through the batch edit api. All
+/// clients, the sender included, get the edits as `file:change`.
+pub async fn handle_rename(
+    socket: SocketRef,
+    Data(request): Data<RenameRequest>,
+    ack: AckSender,
+    state: State<AppState>
+) {
+    info!("handle_rename {:?}", request);
+    let mut timer = EventTimer::start("lsp:rename").with_socket(socket.id).with_payload(&request);
+    let RenameRequest { file, row, column, new_name, save, id } = request;
+
+    if new_name.trim().is_empty() {
+        error_ack!(ack, &file, "Nothing to rename");
+    }
+    let abs_path = match abs_file(&file) {
+        Ok(p) => p,
+        Err(e) => error_ack!(ack, &file, "Failed to resolve file: {:?}", e),
+    };
+
+    let lang = {
+        let mut f2c = timer.lock("file2code", &state.file2code).await;
+        match get_or_create_code(&mut f2c, &abs_path, &state.config) {
+            Ok(c) => c.lang.clone(),
+            Err(e) => error_ack!(ack, &abs_path, "{:?}", e),
+        }
+    };
+
+    let request = lsp_requests::begin(socket.id.as_str(), "rename", id);
+    let mut lsp_manager = timer.lock("lsp_manager", &state.lsp_manager).await;
+    if request.is_cancelled() {
+        return ack_cancelled(ack, id);
+    }
+    let Some(lsp) = lsp_manager.get_for(&lang, &abs_path).await.map(|lsp| lsp.client()) else {
+        error_ack!(ack, &abs_path, "No language server for {}, use rename:preview", lang);
+    };
+    // Applying the edits takes the lock again
+    drop(lsp_manager);
+    let edit = match request.scope(lsp.rename(&abs_path, row, column, &new_name)).await {
+        Ok(Some(edit)) => edit,
+        Ok(None) => error_ack!(ack, &abs_path, "Nothing to rename at {}:{}", row, column),
+        Err(e) if lsp_requests::is_cancelled(&e) => return ack_cancelled(ack, id),
+        Err(e) => error_ack!(ack, &abs_path, "Rename failed: {}", e),
+    };
+
+    let (files, changes) = match services::apply_workspace_edit(&state, &mut timer, edit, save).await {
+        Ok(applied) => applied,
+        Err(e) => error_ack!(ack, &abs_path, "{}", e),
+    };
+    broadcast_changes(&socket, &changes).await;
+
+    ack.send(&json!({ "id": id, "files": files, "success": true })).ok();
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FormatRequest {
+    pub file: String,
+    /// Write the formatted buffer to disk
+    #[serde(default)]
+    pub save: bool,
+    /// Client id of the request, for lsp:cancel
+    #[serde(default)]
+    pub id: Option<u64>,
+}
+
+/// Format a whole document with its language server
+pub async fn handle_format(
+    socket: SocketRef,
+    Data(request): Data<FormatRequest>,
+    ack: AckSender,
+    state: State<AppState>
+) {
+    info!("Received lsp:format: {:?}", request);
+    let timer = EventTimer::start("lsp:format").with_socket(socket.id).with_payload(&request);
+    let target = FormatTarget { file: request.file, range: None, save: request.save, id: request.id };
+    format(socket, ack, state, timer, target).await;
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FormatRangeRequest {
+    pub file: String,
+    pub start_row: usize,
+    pub start_column: usize,
+    pub end_row: usize,
+    pub end_column: usize,
+    #[serde(default)]
+    pub save: bool,
+    #[serde(default)]
+    pub id: Option<u64>,
+}
+
+/// Format a range of a document, e.g. the selection or a pasted block
+pub async fn handle_format_range(
+    socket: SocketRef,
+    Data(request): Data<FormatRangeRequest>,
+    ack: AckSender,
+    state: State<AppState>
+) {
+    info!("Received lsp:format_range: {:?}", request);
+    let timer = EventTimer::start("lsp:format_range").with_socket(socket.id).with_payload(&request);
+    let range = lsp_types::Range::new(
+        lsp_types::Position::new(request.start_row as u32, request.start_column as u32),
+        lsp_types::Position::new(request.end_row as u32, request.end_column as u32),
+    );
+    let target = FormatTarget { file: request.file, range: Some(range), save: request.save, id: request.id };
+    format(socket, ack, state, timer, target).await;
+}
+
+struct FormatTarget {
+    file: String,
+    /// The whole document when missing
+    range: Option<lsp_types::Range>,
+    save: bool,
+    id: Option<u64>,
+}
+
+/// Apply the formatting edits of the server to the buffer, like any other
+/// edit, and send them to the other clients. Formatting a buffer that
+/// changed while the server was at it fails, the edits no longer fit.
+async fn format(socket: SocketRef, ack: AckSender, state: State<AppState>, mut timer: EventTimer, target: FormatTarget) {
+    let FormatTarget { file, range, save, id } = target;
+    let abs_path = match abs_file(&file) {
+        Ok(p) => p,
+        Err(e) => error_ack!(ack, &file, "Failed to resolve file: {:?}", e),
+    };
+
+    let (lang, text) = {
+        let mut f2c = timer.lock("file2code", &state.file2code).await;
+        match get_or_create_code(&mut f2c, &abs_path, &state.config) {
+            Ok(c) => (c.lang.clone(), c.text.clone()),
+            Err(e) => error_ack!(ack, &abs_path, "{:?}", e),
+        }
+    };
+
+    let indent = state.config.language.iter().find(|l| l.name == lang).map(|l| &l.indent);
+    let options = lsp_types::FormattingOptions {
+        tab_size: indent.map_or(4, |i| i.width.max(1) as u32),
+        insert_spaces: indent.is_none_or(|i| i.unit != "\t"),
+        trim_trailing_whitespace: Some(true),
+        ..Default::default()
+    };
+
+    let request = lsp_requests::begin(socket.id.as_str(), "format", id);
+    let mut lsp_manager = timer.lock("lsp_manager", &state.lsp_manager).await;
+    if request.is_cancelled() {
+        return ack_cancelled(ack, id);
+    }
+    let Some(lsp) = lsp_manager.get_for(&lang, &abs_path).await.map(|lsp| lsp.client()) else {
+        error_ack!(ack, &abs_path, "No language server for {}", lang);
+    };
+    drop(lsp_manager);
+
+    let result = match range {
+        Some(range) => request.scope(lsp.range_formatting(&abs_path, range, options)).await,
+        None => request.scope(lsp.formatting(&abs_path, options)).await,
+    };
+    let edits = match result {
+        Ok(edits) => edits,
+        Err(e) if lsp_requests::is_cancelled(&e) => return ack_cancelled(ack, id),
+        Err(e) => error_ack!(ack, &abs_path, "Formatting failed: {}", e),
+    };
+
+    let changed = {
+        let f2c = timer.lock("file2code", &state.file2code).await;
+        f2c.get(&abs_path).is_none_or(|code| code.text != text)
+    };
+    if changed {
+        error_ack!(ack, &abs_path, "{} changed while formatting", abs_path);
+    }
+
+    let changes = vec![rename::text_edits_change(&abs_path, &text.to_string(), &edits)];
+    let (files, changes) = match services::apply_batch(&state, &mut timer, &changes, save).await {
+        Ok(applied) => applied,
+        Err(e) => error_ack!(ack, &abs_path, "{}", e),
+    };
+    broadcast_changes(&socket, &changes).await;
+
+    ack.send(&json!({ "id": id, "files": files, "edits": edits.len(), "success": true })).ok();
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CodeActionRequest {
+    pub file: String,
+    pub start_row: usize,
+    pub start_column: usize,
+    pub end_row: usize,
+    pub end_column: usize,
+    /// Kinds to ask for, e.g. `quickfix` or `source.organizeImports`
+    #[serde(default)]
+    pub only: Option<Vec<String>>,
+    /// Client id of the request, for lsp:cancel
+    #[serde(default)]
+    pub id: Option<u64>,
+}
+
+/// Code actions for a range. The diagnostics of the document overlapping
+/// the range go with the request, so the server offers their quick fixes.
+/// The actions are returned as the server sent them, for
+/// lsp:code_action_apply.
+pub async fn handle_code_action(
+    socket: SocketRef,
+    Data(request): Data<CodeActionRequest>,
+    ack: AckSender,
+    state: State<AppState>
+) {
+    info!("Received lsp:code_action: {:?}", request);
+    let mut timer = EventTimer::start("lsp:code_action").with_socket(socket.id).with_payload(&request);
+    let CodeActionRequest { file, start_row, start_column, end_row, end_column, only, id } = request;
+    let abs_path = match abs_file(&file) {
+        Ok(p) => p,
+        Err(e) => error_ack!(ack, &file, "Failed to resolve file: {:?}", e),
+    };
+
+    let lang = {
+        let mut f2c = timer.lock("file2code", &state.file2code).await;
+        match get_or_create_code(&mut f2c, &abs_path, &state.config) {
+            Ok(c) => c.lang.clone(),
+            Err(e) => error_ack!(ack, &abs_path, "{:?}", e),
+        }
+    };
+
+    let range = lsp_types::Range::new(
+        lsp_types::Position::new(start_row as u32, start_column as u32),
+        lsp_types::Position::new(end_row as u32, end_column as u32),
+    );
+    let diagnostics: Vec<lsp_types::Diagnostic> = {
+        let diagnostics = timer.lock("diagnostics", &state.diagnostics).await;
+        diagnostics.get(&abs_path)
+            .map(|published| published.diagnostics.iter()
+                .filter(|d| d.range.start <= range.end && range.start <= d.range.end)
+                .cloned()
+                .collect())
+            .unwrap_or_default()
+    };
+    let only = only.map(|kinds| kinds.into_iter().map(lsp_types::CodeActionKind::from).collect());
+
+    let request = lsp_requests::begin(socket.id.as_str(), "code_action", id);
+    let mut lsp_manager = timer.lock("lsp_manager", &state.lsp_manager).await;
+    if request.is_cancelled() {
+        return ack_cancelled(ack, id);
+    }
+    let Some(lsp) = lsp_manager.get_for(&lang, &abs_path).await.map(|lsp| lsp.client()) else {
+        error_ack!(ack, &abs_path, "No language server for {}", lang);
+    };
+    drop(lsp_manager);
+
+    match request.scope(lsp.code_actions(&abs_path, range, diagnostics, only)).await {
+        Ok(actions) => {
+            ack.send(&json!({ "id": id, "actions": actions, "success": true })).ok();
+        }
+        Err(e) if lsp_requests::is_cancelled(&e) => ack_cancelled(ack, id),
+        Err(e) => error_ack!(ack, &abs_path, "Code actions failed: {}", e),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CodeActionApplyRequest {
+    /// The document the action was listed for, picks the server
+    pub file: String,
+    /// An action or command from lsp:code_action, as it was sent
+    pub action: serde_json::Value,
+    #[serde(default)]
+    pub save: bool,
+    #[serde(default)]
+    pub id: Option<u64>,
+}
+
+/// Apply a code action: an action listed without its edit is resolved
+/// first, the edit is applied to the buffers of every file it touches and
+/// sent to all clients as `file:change`, then the action's command runs on
+/// the server. Edits the server applies while running the command arrive
+/// as `workspace/applyEdit` and are broadcast by main.
+pub async fn handle_code_action_apply(
+    socket: SocketRef,
+    Data(request): Data<CodeActionApplyRequest>,
+    ack: AckSender,
+    state: State<AppState>
+) {
+    info!("Received lsp:code_action_apply: {:?}", request);
+    let mut timer = EventTimer::start("lsp:code_action_apply").with_socket(socket.id).with_payload(&request);
+    let CodeActionApplyRequest { file, action, save, id } = request;
+    let abs_path = match abs_file(&file) {
+        Ok(p) => p,
+        Err(e) => error_ack!(ack, &file, "Failed to resolve file: {:?}", e),
+    };
+    let action = match serde_json::from_value::<lsp_types::CodeActionOrCommand>(action) {
+        Ok(action) => action,
+        Err(e) => error_ack!(ack, &abs_path, "Invalid code action: {}", e),
+    };
+
+    let lang = {
+        let mut f2c = timer.lock("file2code", &state.file2code).await;
+        match get_or_create_code(&mut f2c, &abs_path, &state.config) {
+            Ok(c) => c.lang.clone(),
+            Err(e) => error_ack!(ack, &abs_path, "{:?}", e),
+        }
+    };
+
+    let request = lsp_requests::begin(socket.id.as_str(), "code_action_apply", id);
+    let mut lsp_manager = timer.lock("lsp_manager", &state.lsp_manager).await;
+    if request.is_cancelled() {
+        return ack_cancelled(ack, id);
+    }
+    let Some(lsp) = lsp_manager.get_for(&lang, &abs_path).await.map(|lsp| lsp.client()) else {
+        error_ack!(ack, &abs_path, "No language server for {}", lang);
+    };
+    drop(lsp_manager);
+
+    let (edit, command) = match action {
+        lsp_types::CodeActionOrCommand::Command(command) => (None, Some(command)),
+        lsp_types::CodeActionOrCommand::CodeAction(action) => {
+            if let Some(reason) = &action.disabled {
+                error_ack!(ack, &abs_path, "{} is disabled: {}", action.title, reason.reason);
+            }
+            let action = match (&action.edit, &action.command) {
+                // Nothing to resolve for a command only action
+                (None, None) => match request.scope(lsp.resolve_code_action(action)).await {
+                    Ok(action) => action,
+                    Err(e) if lsp_requests::is_cancelled(&e) => return ack_cancelled(ack, id),
+                    Err(e) => error_ack!(ack, &abs_path, "Failed to resolve code action: {}", e),
+                },
+                _ => action,
+            };
+            (action.edit, action.command)
+        }
+    };
+
+    let files = match edit {
+        Some(edit) => match services::apply_workspace_edit(&state, &mut timer, edit, save).await {
+            Ok((files, changes)) => {
+                broadcast_changes(&socket, &changes).await;
+                files
+            }
+            Err(e) => error_ack!(ack, &abs_path, "{}", e),
+        },
+        None => Vec::new(),
+    };
+
+    if let Some(command) = command
+        && let Err(e) = lsp.execute_command(command).await
+    {
+        error_ack!(ack, &abs_path, "Code action command failed: {}", e);
+    }
+
+    ack.send(&json!({ "id": id, "files": files, "success": true })).ok();
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ReferencesRequest {
     pub file: String,
     pub row: usize,
     pub column: usize,
+    /// Client id of the request, for lsp:cancel
+    #[serde(default)]
+    pub id: Option<u64>,
 }
 
 pub async fn handle_references(
+    socket: SocketRef,
     Data(request): Data<ReferencesRequest>,
     ack: AckSender,
     state: State<AppState>
 ) {
     info!("handle_references {}", request.file);
-    let ReferencesRequest { file, row, column } = request;
+    let mut timer = EventTimer::start("lsp:references").with_socket(socket.id).with_payload(&request);
+    let ReferencesRequest { file, row, column, id } = request;
 
     let abs_path = match abs_file(&file) {
         Ok(p) => p,
         Err(e) => error_ack!(ack, &file, "Failed to resolve file: {:?}", e),
     };
 
-    let mut f2c = state.file2code.lock().await;
+    let mut f2c = timer.lock("file2code", &state.file2code).await;
     let code = match get_or_create_code(&mut f2c, &abs_path, &state.config) {
         Ok(c) => c,
         Err(e) => error_ack!(ack, &abs_path, "{:?}", e),
     };
 
-    let result = match state.lsp_manager.lock().await.get(&code.lang).await {
-        Some(lsp) => {
-            lsp.references(&abs_path, row, column).await
-                .ok().unwrap_or_else(|| Vec::new())
+    let request = lsp_requests::begin(socket.id.as_str(), "references", id);
+    let mut lsp_manager = timer.lock("lsp_manager", &state.lsp_manager).await;
+    if request.is_cancelled() {
+        return ack_cancelled(ack, id);
+    }
+    let client = lsp_manager.get_for(&code.lang, &abs_path).await.map(|lsp| lsp.client());
+    drop(lsp_manager);
+    if let Some(lsp) = client {
+        drop(f2c);
+        match request.scope(lsp.references(&abs_path, row, column)).await {
+            Err(e) if lsp_requests::is_cancelled(&e) => ack_cancelled(ack, id),
+            result => { ack.send(&json!({ "items": result.unwrap_or_default(), "kind": "lsp" })).ok(); }
+        }
+        return;
+    }
+
+    // No language server, fall back to a whole-word text search for the
+    // identifier under the cursor. `kind: text` tells the UI these are text
+    // matches, not semantic references.
+    let line_start = code.text.line_to_char(row.min(code.text.len_lines() - 1));
+    let offset = code.utf16_to_char_offset(code.char_to_utf16_offset(line_start) + column);
+    let Some(word) = word_at(&code.text, offset, &code.lang) else {
+        ack.send(&json!({ "items": [], "kind": "text" })).ok();
+        return;
+    };
+    let lang = code.lang.clone();
+    drop(f2c);
+
+    let items = match services::text_references(&word.text, &lang).await {
+        Ok(items) => items,
+        Err(e) => error_ack!(ack, &abs_path, "Text search failed: {}", e),
+    };
+
+    ack.send(&json!({ "items": items, "kind": "text", "word": word.text })).ok();
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LspCancelRequest {
+    pub id: u64,
+}
+
+/// Cancel an in-flight LSP request of this socket, any LSP request sent
+/// with an `id`, by that id
+pub async fn handle_lsp_cancel(socket: SocketRef, Data(request): Data<LspCancelRequest>, ack: AckSender) {
+    info!("Received lsp:cancel: {:?}", request);
+    let _timer = EventTimer::start("lsp:cancel").with_socket(socket.id).with_payload(&request);
+
+    let cancelled = lsp_requests::cancel(socket.id.as_str(), request.id);
+    ack.send(&json!({ "id": request.id, "cancelled": cancelled, "success": true })).ok();
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DocumentSymbolsRequest {
+    pub file: String,
+    /// Client id of the request, for lsp:cancel
+    #[serde(default)]
+    pub id: Option<u64>,
+}
+
+/// Symbols of a document as a tree, for the outline panel
+pub async fn handle_document_symbols(
+    socket: SocketRef,
+    Data(request): Data<DocumentSymbolsRequest>,
+    ack: AckSender,
+    state: State<AppState>
+) {
+    info!("Received lsp:document_symbols: {:?}", request);
+    let mut timer = EventTimer::start("lsp:document_symbols").with_socket(socket.id).with_payload(&request);
+    let DocumentSymbolsRequest { file, id } = request;
+    let abs_path = match abs_file(&file) {
+        Ok(p) => p,
+        Err(e) => error_ack!(ack, &file, "Failed to resolve file: {:?}", e),
+    };
+
+    let (lang, hash) = {
+        let mut f2c = timer.lock("file2code", &state.file2code).await;
+        match get_or_create_code(&mut f2c, &abs_path, &state.config) {
+            Ok(c) => (c.lang.clone(), blake3::hash(c.text.to_string().as_bytes()).to_hex().to_string()),
+            Err(e) => error_ack!(ack, &abs_path, "{:?}", e),
+        }
+    };
+
+    // The outline of the last session shows until the server answers
+    let cached = symbol_cache::document_symbols(&state.config, &abs_path, &hash);
+    if let Some((symbols, stale)) = &cached {
+        socket.emit("lsp:document_symbols_cached", &json!({
+            "id": id, "file": abs_path, "symbols": symbols, "stale": stale,
+        })).ok();
+    }
+
+    let request = lsp_requests::begin(socket.id.as_str(), "document_symbols", id);
+    let mut lsp_manager = timer.lock("lsp_manager", &state.lsp_manager).await;
+    if request.is_cancelled() {
+        return ack_cancelled(ack, id);
+    }
+    let Some(lsp) = lsp_manager.get_for(&lang, &abs_path).await.map(|lsp| lsp.client()) else {
+        error_ack!(ack, &abs_path, "No language server for {}", lang);
+    };
+    drop(lsp_manager);
+
+    match request.scope(lsp.outline(&abs_path)).await {
+        Ok(symbols) => {
+            symbol_cache::store_document(&state.config, &lang, &abs_path, &hash, &symbols);
+            ack.send(&json!({ "id": id, "file": abs_path, "symbols": symbols, "stale": false, "success": true })).ok();
+        }
+        Err(e) if lsp_requests::is_cancelled(&e) => ack_cancelled(ack, id),
+        Err(e) => match cached {
+            Some((symbols, stale)) => {
+                error!("Document symbols failed, answering from the cache: {}", e);
+                ack.send(&json!({ "id": id, "file": abs_path, "symbols": symbols, "stale": stale, "success": true })).ok();
+            }
+            None => error_ack!(ack, &abs_path, "Document symbols failed: {}", e),
         },
-        None => Vec::new()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SemanticTokensRequest {
+    pub file: String,
+    /// Result id of the tokens the client holds, the answer has the lines
+    /// changed since them
+    #[serde(default)]
+    pub previous_result_id: Option<String>,
+    #[serde(default)]
+    pub id: Option<u64>,
+}
+
+/// Semantic tokens of a document. The server is asked for a delta against
+/// its last answer when it supports them, the ack has the changed lines
+/// for a client holding `previous_result_id`, else all lines and the legend.
+pub async fn handle_semantic_tokens(
+    socket: SocketRef,
+    Data(request): Data<SemanticTokensRequest>,
+    ack: AckSender,
+    state: State<AppState>
+) {
+    info!("Received lsp:semantic_tokens: {:?}", request);
+    let mut timer = EventTimer::start("lsp:semantic_tokens").with_socket(socket.id).with_payload(&request);
+    let SemanticTokensRequest { file, previous_result_id, id } = request;
+    let abs_path = match abs_file(&file) {
+        Ok(p) => p,
+        Err(e) => error_ack!(ack, &file, "Failed to resolve file: {:?}", e),
+    };
+
+    let (lang, line_count) = {
+        let mut f2c = timer.lock("file2code", &state.file2code).await;
+        match get_or_create_code(&mut f2c, &abs_path, &state.config) {
+            Ok(c) => (c.lang.clone(), c.text.len_lines()),
+            Err(e) => error_ack!(ack, &abs_path, "{:?}", e),
+        }
+    };
+
+    let request = lsp_requests::begin(socket.id.as_str(), "semantic_tokens", id);
+    let mut lsp_manager = timer.lock("lsp_manager", &state.lsp_manager).await;
+    if request.is_cancelled() {
+        return ack_cancelled(ack, id);
+    }
+    let Some(lsp) = lsp_manager.get_for(&lang, &abs_path).await.map(|lsp| lsp.client()) else {
+        error_ack!(ack, &abs_path, "No language server for {}", lang);
+    };
+    drop(lsp_manager);
+
+    let Some((legend, supports_delta)) = lsp.semantic_tokens_legend() else {
+        error_ack!(ack, &abs_path, "The {} server has no semantic tokens", lang);
     };
 
-    ack.send(&json!({ "items": result })).ok();
+    let client_result_id = previous_result_id.as_deref();
+    let mut update = None;
+    if let Some(result_id) = semantic_tokens::result_id(&abs_path).filter(|_| supports_delta) {
+        match request.scope(lsp.semantic_tokens_delta(&abs_path, &result_id)).await {
+            Ok(result) => {
+                update = Answer::from_delta(result)
+                    .and_then(|answer| semantic_tokens::update(&abs_path, answer, line_count, client_result_id));
+            }
+            Err(e) if lsp_requests::is_cancelled(&e) => return ack_cancelled(ack, id),
+            // The server may have dropped the result id, all tokens are asked below
+            Err(e) => error!("Semantic tokens delta failed: {}", e),
+        }
+    }
+    let update = match update {
+        Some(update) => update,
+        None => match request.scope(lsp.semantic_tokens_full(&abs_path)).await {
+            Ok(result) => {
+                let answer = Answer::from_full(result);
+                match semantic_tokens::update(&abs_path, answer, line_count, client_result_id) {
+                    Some(update) => update,
+                    None => error_ack!(ack, &abs_path, "Semantic tokens failed"),
+                }
+            }
+            Err(e) if lsp_requests::is_cancelled(&e) => return ack_cancelled(ack, id),
+            Err(e) => error_ack!(ack, &abs_path, "Semantic tokens failed: {}", e),
+        },
+    };
+
+    ack.send(&json!({
+        "id": id, "file": abs_path, "legend": update.full.then_some(&legend),
+        "result_id": update.result_id, "full": update.full, "start": update.start,
+        "deleted": update.deleted, "lines": update.lines, "success": true,
+    })).ok();
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WorkspaceSymbolsRequest {
+    pub query: String,
+    /// Ask the servers of this language only, else all running servers
+    #[serde(default)]
+    pub lang: Option<String>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+    #[serde(default)]
+    pub id: Option<u64>,
+}
+
+/// Symbols of the workspace matching a query, for "Go to symbol". Only
+/// running servers are asked, a server knows the workspace once a
+/// document of its language was opened. Cached symbols come first as
+/// `lsp:workspace_symbols_cached`, the ack merges them with the answers
+/// and marks the ones no server confirmed as stale.
+pub async fn handle_workspace_symbols(
+    socket: SocketRef,
+    Data(request): Data<WorkspaceSymbolsRequest>,
+    ack: AckSender,
+    state: State<AppState>
+) {
+    info!("Received lsp:workspace_symbols: {:?}", request);
+    let mut timer = EventTimer::start("lsp:workspace_symbols").with_socket(socket.id).with_payload(&request);
+    let WorkspaceSymbolsRequest { query, lang, limit, id } = request;
+
+    let request = lsp_requests::begin(socket.id.as_str(), "workspace_symbols", id);
+    let clients: Vec<crate::lsp::LspClient> = timer.lock("lsp_manager", &state.lsp_manager).await
+        .running()
+        .map(|lsp| lsp.client())
+        .filter(|lsp| lang.as_ref().is_none_or(|lang| lsp.lang() == lang))
+        .collect();
+
+    let limit = limit.unwrap_or(MAX_WORKSPACE_SYMBOLS);
+
+    // Symbols of the last sessions first, servers may still be indexing
+    let cached = symbol_cache::workspace_symbols(&state.config, lang.as_deref(), &query);
+    if !cached.is_empty() {
+        let symbols = crate::outline::rank_with_cached(Vec::new(), cached.clone(), &query, limit);
+        socket.emit("lsp:workspace_symbols_cached", &json!({ "id": id, "symbols": symbols })).ok();
+    }
+
+    let mut symbols = Vec::new();
+    let mut failed = Vec::new();
+    for lsp in clients {
+        match request.scope(lsp.workspace_symbols(&query)).await {
+            Ok(found) => {
+                symbol_cache::store_workspace(&state.config, lsp.lang(), &found);
+                symbols.extend(found);
+            }
+            Err(e) if lsp_requests::is_cancelled(&e) => return ack_cancelled(ack, id),
+            Err(e) => {
+                error!("Workspace symbols of {} failed: {:?}", lsp.lang(), e);
+                failed.push(lsp.lang().to_string());
+            }
+        }
+    }
+
+    let symbols = crate::outline::rank_with_cached(symbols, cached, &query, limit);
+    ack.send(&json!({ "id": id, "symbols": symbols, "failed": failed, "success": true })).ok();
+}
+
+/// Current state of the language servers, changes are pushed as `lsp:status`
+pub async fn handle_lsp_status(ack: AckSender) {
+    info!("Received lsp:status");
+    let _timer = EventTimer::start("lsp:status");
+
+    ack.send(&json!({ "servers": crate::lsp_status::all(), "success": true })).ok();
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LspClearCacheRequest {
+    /// Language to clear, all of them when missing
+    pub lang: Option<String>,
+}
+
+/// Stop the affected language servers and remove their cache dirs under
+/// .anycode/lsp-cache and their cached symbols. The servers start again
+/// on the next request.
+pub async fn handle_lsp_clear_cache(
+    Data(request): Data<LspClearCacheRequest>,
+    ack: AckSender,
+    state: State<AppState>,
+) {
+    info!("Received lsp:clearCache: {:?}", request);
+    let mut timer = EventTimer::start("lsp:clearCache").with_payload(&request);
+
+    if let Some(lang) = &request.lang && !state.config.language.iter().any(|l| &l.name == lang) {
+        error_ack!(ack, "", "Unknown language {}", lang);
+    }
+
+    let mut lsp_manager = timer.lock("lsp_manager", &state.lsp_manager).await;
+    let langs = match &request.lang {
+        Some(lang) => vec![lang.clone()],
+        None => lsp_manager.running_langs(),
+    };
+    for lang in &langs {
+        lsp_manager.stop(lang).await;
+    }
+
+    symbol_cache::clear(&state.config, request.lang.as_deref());
+    let freed = match crate::lsp_cache::clear(request.lang.as_deref()) {
+        Ok(freed) => freed,
+        Err(e) => error_ack!(ack, "", "Failed to clear lsp cache: {}", e),
+    };
+
+    ack.send(&json!({ "freed": freed, "stopped": langs, "success": true })).ok();
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LspRestoreRequest {
+    pub lang: String,
+}
+
+/// Leave the fallback providers a memory budget switched the language to,
+/// the server starts again on the next request
+pub async fn handle_lsp_restore(
+    Data(request): Data<LspRestoreRequest>,
+    ack: AckSender,
+    state: State<AppState>,
+) {
+    info!("Received lsp:restore: {:?}", request);
+    let mut timer = EventTimer::start("lsp:restore").with_payload(&request);
+
+    let restored = timer.lock("lsp_manager", &state.lsp_manager).await.restore(&request.lang);
+    ack.send(&json!({ "lang": request.lang, "restored": restored, "success": true })).ok();
 }
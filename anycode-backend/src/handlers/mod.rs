@@ -1,9 +1,43 @@
+pub mod audit_handler;
+pub mod collab_handler;
+pub mod edit_handler;
+pub mod git_handler;
+pub mod ignore_handler;
 pub mod io_handler;
+pub mod lang_handler;
 pub mod lsp_handler;
+pub mod nav_handler;
+pub mod notify_handler;
+pub mod output_handler;
+pub mod palette_handler;
+pub mod problems_handler;
+pub mod rename_handler;
+pub mod repl_handler;
+pub mod run_handler;
 pub mod search_handler;
+pub mod server_handler;
+pub mod session_handler;
 pub mod terminal_handler;
+pub mod workspace_handler;
 
+// pub use audit_handler::*;
+// pub use collab_handler::*;
+// pub use edit_handler::*;
+// pub use git_handler::*;
+// pub use ignore_handler::*;
 // pub use io_handler::*;
+// pub use lang_handler::*;
 // pub use lsp_handler::*;
+// pub use nav_handler::*;
+// pub use notify_handler::*;
+// pub use output_handler::*;
+// pub use palette_handler::*;
+// pub use problems_handler::*;
+// pub use rename_handler::*;
+// pub use repl_handler::*;
+// pub use run_handler::*;
 // pub use search_handler::*;
-// pub use terminal_handler::*;
\ No newline at end of file
+// pub use server_handler::*;
+// pub use session_handler::*;
+// pub use terminal_handler::*;
+// pub use workspace_handler::*;
\ No newline at end of file
@@ -0,0 +1,60 @@
+use serde_json::json;
+use socketioxide::extract::{AckSender, Data, SocketRef, State};
+use tracing::{error, info};
+use crate::app_state::AppState;
+use serde::{Deserialize, Serialize};
+use crate::timing::EventTimer;
+use crate::error_ack;
+use crate::services;
+
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AlternateRequest {
+    pub file: String,
+}
+
+/// The counterpart of a file, its test or the source of a test, by the
+/// alternates rules of its language. `exists` is false when it was not
+/// written yet.
+pub async fn handle_nav_alternate(
+    socket: SocketRef,
+    Data(request): Data<AlternateRequest>,
+    state: State<AppState>,
+    ack: AckSender,
+) {
+    info!("Received nav:alternate: {:?}", request);
+    let mut timer = EventTimer::start("nav:alternate").with_socket(socket.id).with_payload(&request);
+    alternate(&socket, &state, &mut timer, &request.file, false, ack).await;
+}
+
+/// As nav:alternate, a missing counterpart is created, a test from the
+/// template of the rule
+pub async fn handle_nav_create_alternate(
+    socket: SocketRef,
+    Data(request): Data<AlternateRequest>,
+    state: State<AppState>,
+    ack: AckSender,
+) {
+    info!("Received nav:createAlternate: {:?}", request);
+    let mut timer = EventTimer::start("nav:createAlternate").with_socket(socket.id).with_payload(&request);
+    alternate(&socket, &state, &mut timer, &request.file, true, ack).await;
+}
+
+async fn alternate(socket: &SocketRef, state: &AppState, timer: &mut EventTimer, file: &str, create: bool, ack: AckSender) {
+    let root = services::workspace_root(state, timer, socket.id.as_str()).await;
+    let (alternate, created) = match services::alternate_file(state, timer, &root, file, create).await {
+        Ok(alternate) => alternate,
+        Err(e) => error_ack!(ack, file, "{}", e),
+    };
+
+    if let Err(err) = ack.send(&json!({
+        "file": file,
+        "alternate": alternate.path.to_string_lossy(),
+        "exists": alternate.exists,
+        "test": alternate.is_test,
+        "created": created,
+        "success": true,
+    })) {
+        error!("Failed to send acknowledgment: {:?}", err);
+    }
+}
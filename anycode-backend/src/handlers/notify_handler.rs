@@ -0,0 +1,32 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use socketioxide::extract::{AckSender, Data};
+use tracing::info;
+use crate::timing::EventTimer;
+
+/// Running operations of the progress center, changes are pushed as
+/// `notify:progress`
+pub async fn handle_notify_list(ack: AckSender) {
+    info!("Received notify:list");
+    let _timer = EventTimer::start("notify:list");
+
+    ack.send(&json!({ "items": crate::progress::list(), "success": true })).ok();
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NotifyCancelRequest {
+    pub id: u64,
+}
+
+pub async fn handle_notify_cancel(Data(request): Data<NotifyCancelRequest>, ack: AckSender) {
+    info!("Received notify:cancel: {:?}", request);
+    let _timer = EventTimer::start("notify:cancel").with_payload(&request);
+
+    if !crate::progress::cancel(request.id) {
+        ack.send(&json!({
+            "id": request.id, "error": "Unknown or not cancellable operation", "success": false
+        })).ok();
+        return;
+    }
+    ack.send(&json!({ "id": request.id, "success": true })).ok();
+}
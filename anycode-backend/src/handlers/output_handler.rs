@@ -0,0 +1,40 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use socketioxide::extract::{AckSender, Data, SocketRef};
+use tracing::info;
+use crate::output;
+use crate::timing::EventTimer;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OutputSubscribeRequest {
+    pub channel: String,
+}
+
+pub async fn handle_output_list(ack: AckSender) {
+    info!("Received output:list");
+    let _timer = EventTimer::start("output:list");
+
+    let _ = ack.send(&json!({ "channels": output::list(), "success": true }));
+}
+
+/// Join the channel room for live `output:line` events, the ack carries
+/// the lines written so far.
+pub async fn handle_output_subscribe(
+    socket: SocketRef,
+    Data(request): Data<OutputSubscribeRequest>,
+    ack: AckSender,
+) {
+    info!("Received output:subscribe: {}", request.channel);
+    let _timer = EventTimer::start("output:subscribe").with_socket(socket.id).with_payload(&request);
+
+    socket.join(output::room(&request.channel));
+    let lines = output::lines(&request.channel);
+    let _ = ack.send(&json!({ "channel": request.channel, "lines": lines, "success": true }));
+}
+
+pub async fn handle_output_unsubscribe(socket: SocketRef, Data(request): Data<OutputSubscribeRequest>) {
+    info!("Received output:unsubscribe: {}", request.channel);
+    let _timer = EventTimer::start("output:unsubscribe").with_socket(socket.id).with_payload(&request);
+
+    socket.leave(output::room(&request.channel));
+}
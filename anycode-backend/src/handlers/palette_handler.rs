@@ -0,0 +1,163 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use socketioxide::extract::{AckSender, Data, SocketRef, State};
+use tracing::{info, error};
+use crate::app_state::AppState;
+use crate::search::SearchScope;
+use crate::fuzzy::{fuzzy_match, FuzzyMatch};
+use crate::lsp::LspClient;
+use crate::timing::EventTimer;
+use crate::utils::relative_path;
+
+const DEFAULT_PALETTE_LIMIT: usize = 50;
+/// Workspace symbols are only queried for queries at least this long
+const MIN_SYMBOL_QUERY: usize = 2;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PaletteCommand {
+    pub id: String,
+    pub title: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PaletteQueryRequest {
+    pub query: String,
+    /// Frontend commands to match against, the backend does not know them
+    #[serde(default)]
+    pub commands: Vec<PaletteCommand>,
+    pub limit: Option<usize>,
+    /// `open` takes the symbols of the open documents only
+    #[serde(default)]
+    pub scope: SearchScope,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct PaletteItem {
+    pub kind: &'static str,
+    pub label: String,
+    pub detail: Option<String>,
+    #[serde(flatten)]
+    pub matched: FuzzyMatch,
+    pub data: Value,
+}
+
+fn push_match(
+    items: &mut Vec<PaletteItem>, query: &str, kind: &'static str,
+    label: String, detail: Option<String>, data: Value,
+) {
+    if let Some(matched) = fuzzy_match(query, &label) {
+        items.push(PaletteItem { kind, label, detail, matched, data });
+    }
+}
+
+/// One ranked list over commands, open files, recent files, workspace
+/// files and symbols, the command palette only renders it.
+pub async fn handle_palette_query(
+    socket: SocketRef,
+    Data(request): Data<PaletteQueryRequest>,
+    ack: AckSender,
+    state: State<AppState>,
+) {
+    info!("Received palette:query: {}", request.query);
+    let mut timer = EventTimer::start("palette:query").with_socket(socket.id).with_payload(&request);
+
+    let query = request.query.as_str();
+    let mut items = Vec::new();
+
+    for command in request.commands.iter() {
+        push_match(&mut items, query, "command", command.title.clone(), None, json!({ "id": command.id }));
+    }
+
+    let opened: Vec<String> = {
+        let sockets_data = timer.lock("socket2data", &state.socket2data).await;
+        sockets_data.get(socket.id.as_str())
+            .map(|d| d.opened_files.iter().cloned().collect())
+            .unwrap_or_default()
+    };
+
+    for path in opened.iter() {
+        push_match(&mut items, query, "file", relative_path(path), None, json!({ "path": path }));
+    }
+
+    let recent: Vec<String> = {
+        let recent = timer.lock("recent", &state.recent).await;
+        let root = crate::utils::current_dir();
+        recent.files()
+            .filter(|f| f.starts_with(&root) && !opened.contains(f))
+            .cloned()
+            .collect()
+    };
+
+    for path in recent.iter() {
+        push_match(&mut items, query, "recent", relative_path(path), None, json!({ "path": path }));
+    }
+
+    // Quick-open over the workspace files from the startup scan
+    if !query.is_empty() {
+        let listed: Vec<String> = opened.iter().chain(recent.iter()).map(|p| relative_path(p)).collect();
+        for path in crate::file_index::files() {
+            if !listed.contains(&path) {
+                let data = json!({ "path": path });
+                push_match(&mut items, query, "workspace", path, None, data);
+            }
+        }
+    }
+
+    if query.chars().count() >= MIN_SYMBOL_QUERY && request.scope == SearchScope::Open {
+        let documents: Vec<(String, String)> = {
+            let f2c = timer.lock("file2code", &state.file2code).await;
+            opened.iter()
+                .filter_map(|path| Some((path.clone(), f2c.get(path)?.lang.clone())))
+                .collect()
+        };
+
+        let mut clients = Vec::new();
+        {
+            let mut lsp_manager = timer.lock("lsp_manager", &state.lsp_manager).await;
+            for (path, lang) in documents {
+                let Some(lsp) = lsp_manager.get_for(&lang, &path).await else { continue };
+                clients.push((path, lsp.client()));
+            }
+        }
+        for (path, lsp) in clients {
+            let symbols = match lsp.document_symbols(&path).await {
+                Ok(symbols) => symbols,
+                Err(e) => {
+                    error!("Failed to get document symbols: {:?}", e);
+                    continue;
+                }
+            };
+
+            for symbol in symbols {
+                let data = json!({ "kind": symbol.kind, "location": symbol.location });
+                push_match(&mut items, query, "symbol", symbol.name, symbol.container_name, data);
+            }
+        }
+    } else if query.chars().count() >= MIN_SYMBOL_QUERY {
+        let clients: Vec<LspClient> = timer.lock("lsp_manager", &state.lsp_manager).await
+            .running()
+            .map(|lsp| lsp.client())
+            .collect();
+        for lsp in clients {
+            let symbols = match lsp.workspace_symbols(query).await {
+                Ok(symbols) => symbols,
+                Err(e) => {
+                    error!("Failed to get workspace symbols: {:?}", e);
+                    continue;
+                }
+            };
+            crate::symbol_cache::store_workspace(&state.config, lsp.lang(), &symbols);
+
+            for symbol in symbols {
+                let data = json!({ "kind": symbol.kind, "location": symbol.location });
+                push_match(&mut items, query, "symbol", symbol.name, symbol.container_name, data);
+            }
+        }
+    }
+
+    // Stable sort keeps the source order (commands, files, recent, workspace, symbols) for equal scores
+    items.sort_by_key(|item| std::cmp::Reverse(item.matched.score));
+    items.truncate(request.limit.unwrap_or(DEFAULT_PALETTE_LIMIT));
+
+    ack.send(&json!({ "items": items, "success": true })).ok();
+}
@@ -0,0 +1,83 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use socketioxide::extract::{AckSender, Data, SocketRef, State};
+use tracing::{info, error};
+use crate::app_state::AppState;
+use crate::diagnostic_filter::{self, DiagnosticRule, DiagnosticRules};
+use crate::error_ack;
+use crate::timing::EventTimer;
+
+/// Diagnostic rules of the workspace, see diagnostic_filter.rs
+pub async fn handle_problems_rules(ack: AckSender) {
+    info!("Received problems:rules");
+    let _timer = EventTimer::start("problems:rules");
+
+    ack.send(&json!({ "rules": diagnostic_filter::rules().rules, "success": true })).ok();
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProblemsSuppressRequest {
+    pub rule: DiagnosticRule,
+}
+
+/// Add a rule hiding the matching diagnostics, e.g. built from a problem
+/// with its source and code. An equal rule is not added twice.
+pub async fn handle_problems_suppress(
+    socket: SocketRef,
+    Data(request): Data<ProblemsSuppressRequest>,
+    ack: AckSender,
+    state: State<AppState>,
+) {
+    info!("Received problems:suppress: {:?}", request);
+    let mut timer = EventTimer::start("problems:suppress").with_socket(socket.id).with_payload(&request);
+
+    let mut rules = diagnostic_filter::rules();
+    if !rules.rules.contains(&request.rule) {
+        rules.rules.push(request.rule);
+    }
+    set_rules(&socket, ack, &state, &mut timer, rules).await;
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProblemsUnsuppressRequest {
+    /// Index of the rule in `problems:rules`
+    pub index: usize,
+}
+
+/// Remove a rule, its diagnostics show again
+pub async fn handle_problems_unsuppress(
+    socket: SocketRef,
+    Data(request): Data<ProblemsUnsuppressRequest>,
+    ack: AckSender,
+    state: State<AppState>,
+) {
+    info!("Received problems:unsuppress: {:?}", request);
+    let mut timer = EventTimer::start("problems:unsuppress").with_socket(socket.id).with_payload(&request);
+
+    let mut rules = diagnostic_filter::rules();
+    if request.index >= rules.rules.len() {
+        error_ack!(ack, "", "No diagnostic rule {}", request.index);
+    }
+    rules.rules.remove(request.index);
+    set_rules(&socket, ack, &state, &mut timer, rules).await;
+}
+
+/// Save the rules and send every client the diagnostics of the open
+/// documents filtered again, and the rules as `problems:rulesChanged`
+async fn set_rules(socket: &SocketRef, ack: AckSender, state: &AppState, timer: &mut EventTimer, rules: DiagnosticRules) {
+    let rules = match diagnostic_filter::set_rules(rules) {
+        Ok(rules) => rules,
+        Err(e) => error_ack!(ack, "", "Failed to save diagnostic rules: {}", e),
+    };
+
+    let published: Vec<_> = timer.lock("diagnostics", &state.diagnostics).await.values().cloned().collect();
+    for params in &published {
+        let payload = diagnostic_filter::to_json(params);
+        socket.emit("lsp:diagnostics", &payload).ok();
+        socket.broadcast().emit("lsp:diagnostics", &payload).await.ok();
+    }
+
+    let response = json!({ "rules": rules.rules, "success": true });
+    socket.broadcast().emit("problems:rulesChanged", &response).await.ok();
+    ack.send(&response).ok();
+}
@@ -0,0 +1,162 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use socketioxide::extract::{AckSender, Data, SocketRef, State};
+use std::collections::HashMap;
+use tracing::{info, error};
+use crate::app_state::{get_or_create_code, AppState};
+use crate::handlers::edit_handler::broadcast_changes;
+use crate::code::lang_of;
+use crate::rename::{find_occurrences, rename_edits, scope_files, FileOccurrences, MAX_OCCURRENCES};
+use crate::services::{self, Change};
+use crate::utils::{abs_file, relative_to_current_dir};
+use crate::timing::EventTimer;
+use crate::error_ack;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RenamePreviewRequest {
+    pub symbol: String,
+    /// File or directory to rename in, the whole workspace when empty
+    #[serde(default)]
+    pub scope: String,
+}
+
+/// Whole-word occurrences of a symbol grouped by file, for reviewing a
+/// textual rename before applying it. Open buffers are searched instead
+/// of the files on disk.
+pub async fn handle_rename_preview(
+    Data(request): Data<RenamePreviewRequest>,
+    ack: AckSender,
+    state: State<AppState>,
+) {
+    info!("Received rename:preview: {:?}", request);
+    let mut timer = EventTimer::start("rename:preview").with_payload(&request);
+
+    if request.symbol.trim().is_empty() {
+        error_ack!(ack, &request.scope, "Nothing to rename");
+    }
+
+    let files = match scope_files(&request.scope) {
+        Ok(files) => files,
+        Err(e) => error_ack!(ack, &request.scope, "{}", e),
+    };
+
+    let buffers: HashMap<String, String> = {
+        let f2c = timer.lock("file2code", &state.file2code).await;
+        f2c.iter().map(|(path, code)| (path.clone(), code.text.to_string())).collect()
+    };
+
+    let symbol = request.symbol.clone();
+    let config = state.config.clone();
+    let scan = crate::pool::spawn(async move {
+        let mut result = Vec::new();
+        let mut total = 0;
+
+        for path in files {
+            if total >= MAX_OCCURRENCES { break }
+
+            let abs_path = abs_file(&path.to_string_lossy()).unwrap_or_default();
+            let text = match buffers.get(&abs_path) {
+                Some(text) => text.clone(),
+                // Binary and unreadable files have nothing to rename
+                None => match std::fs::read_to_string(&path) {
+                    Ok(text) => text,
+                    Err(_) => continue,
+                },
+            };
+
+            let occurrences = find_occurrences(&text, &symbol, &lang_of(&path.to_string_lossy(), &config));
+            if occurrences.is_empty() { continue }
+            total += occurrences.len();
+
+            let file = relative_to_current_dir(&path)
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or(abs_path);
+            result.push(FileOccurrences { file, occurrences });
+        }
+
+        (result, total)
+    });
+
+    let (files, total) = match scan.await {
+        Ok(scan) => scan,
+        Err(e) => error_ack!(ack, &request.scope, "Rename preview failed: {}", e),
+    };
+
+    ack.send(&json!({
+        "files": files,
+        "total": total,
+        "truncated": total >= MAX_OCCURRENCES,
+        "success": true,
+    })).ok();
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RenameSelection {
+    pub file: String,
+    /// `start` of the selected occurrences from the preview
+    pub starts: Vec<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RenameApplyRequest {
+    pub symbol: String,
+    pub new_name: String,
+    pub files: Vec<RenameSelection>,
+    /// Save the renamed files, otherwise they stay modified in the buffers
+    #[serde(default = "default_save")]
+    pub save: bool,
+}
+
+fn default_save() -> bool { true }
+
+/// Apply the occurrences picked from a preview through the batch edit api.
+/// Fails without touching anything if a file changed since the preview.
+pub async fn handle_rename_apply(
+    socket: SocketRef,
+    Data(request): Data<RenameApplyRequest>,
+    ack: AckSender,
+    state: State<AppState>,
+) {
+    info!("Received rename:apply: {} -> {} files={}", request.symbol, request.new_name, request.files.len());
+    let mut timer = EventTimer::start("rename:apply").with_socket(socket.id).with_payload(&request);
+
+    if request.new_name.is_empty() || request.new_name == request.symbol {
+        error_ack!(ack, "", "Nothing to rename");
+    }
+
+    let mut changes = Vec::with_capacity(request.files.len());
+    {
+        let mut f2c = timer.lock("file2code", &state.file2code).await;
+        for selection in request.files.iter().filter(|s| !s.starts.is_empty()) {
+            let abs_path = match abs_file(&selection.file) {
+                Ok(p) => p,
+                Err(e) => error_ack!(ack, &selection.file, "Failed to resolve file: {:?}", e),
+            };
+            let code = match get_or_create_code(&mut f2c, &abs_path, &state.config) {
+                Ok(c) => c,
+                Err(e) => error_ack!(ack, &selection.file, "{:?}", e),
+            };
+
+            let current = find_occurrences(&code.text.to_string(), &request.symbol, &code.lang);
+            if !selection.starts.iter().all(|start| current.iter().any(|o| o.start == *start)) {
+                error_ack!(ack, &selection.file, "{} changed since the preview", selection.file);
+            }
+
+            changes.push(Change {
+                file: abs_path,
+                edits: rename_edits(&selection.starts, &request.symbol, &request.new_name),
+                base: None,
+                version: None,
+            });
+        }
+    }
+
+    let (files, changes) = match services::apply_batch(&state, &mut timer, &changes, request.save).await {
+        Ok(applied) => applied,
+        Err(e) => error_ack!(ack, "", "{}", e),
+    };
+
+    broadcast_changes(&socket, &changes).await;
+
+    ack.send(&json!({ "files": files, "success": true })).ok();
+}
@@ -0,0 +1,75 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use socketioxide::extract::{AckSender, Data, State};
+use std::time::Duration;
+use tracing::{info, error};
+use crate::app_state::AppState;
+use crate::error_ack;
+use crate::timing::EventTimer;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReplStartRequest {
+    pub lang: String,
+    /// Replace a running interpreter, its state is lost
+    #[serde(default)]
+    pub restart: bool,
+}
+
+pub async fn handle_repl_start(
+    Data(request): Data<ReplStartRequest>,
+    ack: AckSender,
+    state: State<AppState>,
+) {
+    info!("Received repl:start: {:?}", request);
+    let _timer = EventTimer::start("repl:start").with_payload(&request);
+
+    match crate::repl::start(&state.config, &request.lang, request.restart) {
+        Ok(repl) => { ack.send(&json!({ "repl": repl, "success": true })).ok(); }
+        Err(e) => error_ack!(ack, "", "Failed to start repl: {}", e),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReplEvalRequest {
+    pub lang: String,
+    pub code: String,
+    pub timeout_secs: Option<u64>,
+}
+
+/// Evaluate a selection in the interpreter of the language. Errors of the
+/// evaluated code are part of the result, failures of the interpreter
+/// itself are not.
+pub async fn handle_repl_eval(
+    Data(request): Data<ReplEvalRequest>,
+    ack: AckSender,
+    state: State<AppState>,
+) {
+    info!("Received repl:eval: {:?}", request);
+    let _timer = EventTimer::start("repl:eval").with_payload(&request);
+
+    let timeout = request.timeout_secs.map(Duration::from_secs);
+    match crate::repl::eval(&state.config, &request.lang, &request.code, timeout).await {
+        Ok(result) => { ack.send(&json!({ "lang": request.lang, "result": result, "success": true })).ok(); }
+        Err(e) => error_ack!(ack, "", "Failed to evaluate: {}", e),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReplStopRequest {
+    pub lang: String,
+}
+
+pub async fn handle_repl_stop(Data(request): Data<ReplStopRequest>, ack: AckSender) {
+    info!("Received repl:stop: {:?}", request);
+    let _timer = EventTimer::start("repl:stop").with_payload(&request);
+
+    let stopped = crate::repl::stop(&request.lang);
+    ack.send(&json!({ "lang": request.lang, "stopped": stopped, "success": true })).ok();
+}
+
+pub async fn handle_repl_list(ack: AckSender) {
+    info!("Received repl:list");
+    let _timer = EventTimer::start("repl:list");
+
+    ack.send(&json!({ "repls": crate::repl::list(), "success": true })).ok();
+}
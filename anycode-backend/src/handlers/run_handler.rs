@@ -0,0 +1,77 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use socketioxide::extract::{AckSender, Data, SocketRef, State};
+use tracing::{info, error};
+use crate::app_state::AppState;
+use crate::command_output;
+use crate::error_ack;
+use crate::services;
+use crate::timing::EventTimer;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RunCommandRequest {
+    pub command: String,
+}
+
+/// Run a one-off command through the exec policy and keep its stdout as a
+/// read-only `command-output://` document, opened with file:open
+pub async fn handle_run_command(
+    Data(request): Data<RunCommandRequest>,
+    ack: AckSender,
+    state: State<AppState>,
+) {
+    info!("Received run:command: {:?}", request);
+    let _timer = EventTimer::start("run:command").with_payload(&request);
+
+    let output = match crate::exec::run(&request.command, state.config.exec.as_ref(), None).await {
+        Ok(output) => output,
+        Err(e) => error_ack!(ack, "", "Failed to run {}: {}", request.command, e),
+    };
+
+    let uri = command_output::insert(&request.command, output.stdout.clone());
+    ack.send(&json!({
+        "uri": uri,
+        "command": request.command,
+        "content": output.stdout,
+        "stderr": output.stderr,
+        "status": output.status,
+        "elapsed_ms": output.elapsed_ms,
+        "success": true,
+    })).ok();
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RunSaveOutputRequest {
+    pub uri: String,
+    pub path: String,
+}
+
+/// Save a command output, or any other virtual document, as a regular file.
+/// Writes like file:create with overwrite, an open buffer of the file
+/// follows unless it has unsaved changes, then nothing is written.
+pub async fn handle_run_save_output(
+    socket: SocketRef,
+    Data(request): Data<RunSaveOutputRequest>,
+    ack: AckSender,
+    state: State<AppState>,
+) {
+    info!("Received run:saveOutput: {:?}", request);
+    let mut timer = EventTimer::start("run:saveOutput").with_socket(socket.id).with_payload(&request);
+
+    let content = match crate::vfs::read(&request.uri) {
+        Ok((content, _)) => content,
+        Err(e) => error_ack!(ack, &request.uri, "{}", e),
+    };
+
+    let path = crate::paths::absolute(std::path::Path::new(&request.path)).to_string_lossy().to_string();
+    if let Err(e) = crate::readonly::check_writable(&path) {
+        error_ack!(ack, &request.path, "{}", e);
+    }
+    if let Err(e) = services::create_file(&state, &mut timer, &path, &content, true).await {
+        error_ack!(ack, &request.path, "Failed to save command output: {}", e);
+    }
+
+    socket.emit("file:changed", &(path.clone(), content.clone())).ok();
+    socket.broadcast().emit("file:changed", &(path.clone(), content)).await.ok();
+    ack.send(&json!({ "file": path, "success": true })).ok();
+}
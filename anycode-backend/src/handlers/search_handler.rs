@@ -1,15 +1,50 @@
 use serde_json::{self, json};
-use socketioxide::{extract::{Data, SocketRef, State}};
+use socketioxide::{extract::{AckSender, Data, SocketRef, State}};
 use tokio_util::sync::CancellationToken;
-use tracing::info;
+use tracing::{info, error};
+use crate::error_ack;
+use crate::timing::EventTimer;
 use crate::{app_state::{AppState, SocketData}};
 use serde::{Deserialize, Serialize};
-use crate::search::{dir_search, FileSearchResult};
-use tokio::sync::mpsc;
+use crate::services;
+use crate::search::{collect_files_recursively, collect_files_to_depth, symbol_search, text_search, next_batch, rank_results, merge_matches, FileSearchResult, Pager, PathFilter, DEFAULT_MAX_RESULTS, SearchMode, SearchOrder, SearchScope};
+use crate::structural_search::{Preset, StructuralSearch};
+use crate::notifier::NotifyEvent;
+use crate::search_export::{self, ExportFormat, ExportWriter};
+use crate::replace::{ReplaceOptions, Replacer};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SearchRequest {
     pub pattern: String,
+    #[serde(default)]
+    pub order: SearchOrder,
+    #[serde(default)]
+    pub scope: SearchScope,
+    #[serde(default)]
+    pub mode: SearchMode,
+    /// Shape a structural search matches, `pattern` is then the name
+    pub preset: Option<Preset>,
+    /// Language of a structural search, required for queries
+    pub lang: Option<String>,
+    /// Matches of a page of a workspace search, the next page is asked
+    /// with `search:more`
+    pub max_results: Option<usize>,
+    /// Send matches on the same or consecutive lines as one result with
+    /// their `ranges` in a shared preview
+    #[serde(default)]
+    pub merge: bool,
+    /// `include` and `exclude` globs of the workspace files searched
+    #[serde(flatten)]
+    pub filter: PathFilter,
+}
+
+/// Send a result of a text search, merged when asked. The pages count
+/// the matches before merging.
+fn emit_result(socket: &SocketRef, mut file_result: FileSearchResult, merge: bool) {
+    if merge {
+        file_result.matches = merge_matches(&file_result.matches);
+    }
+    let _ = socket.emit("search:result", &file_result);
 }
 
 pub async fn handle_search(
@@ -18,9 +53,10 @@ pub async fn handle_search(
     state: State<AppState>
 ) {
     info!("Received handle_search {}", search_request.pattern);
+    let mut timer = EventTimer::start("search:start").with_socket(socket.id).with_payload(&search_request);
 
     let sid = socket.id.as_str();
-    let mut sockets_data = state.socket2data.lock().await;
+    let mut sockets_data = timer.lock("socket2data", &state.socket2data).await;
 
     // Get the socket data
     let data = sockets_data
@@ -36,39 +72,534 @@ pub async fn handle_search(
     let cancel = CancellationToken::new();
     // Save the cancel in the socket data
     data.search_cancel = Some(cancel.clone());
+    // Drops the pages left of the previous search
+    data.search_more = None;
+    let root = data.root.clone().unwrap_or_else(crate::roots::primary);
+
+    if search_request.mode == SearchMode::Structural {
+        let opened = (search_request.scope == SearchScope::Open)
+            .then(|| data.opened_files.iter().cloned().collect());
+        drop(sockets_data);
+        return structural_search(socket, &state, &mut timer, search_request, &root, opened, cancel).await;
+    }
+
+    if search_request.scope == SearchScope::Open {
+        let opened: Vec<String> = data.opened_files.iter().cloned().collect();
+        drop(sockets_data);
+
+        let start = std::time::Instant::now();
+        let pattern = search_request.pattern;
+        let mut results = services::search_documents(&state, &mut timer, &opened, &pattern).await;
+        rank_results(&mut results, search_request.order, &pattern);
+
+        let mut matches = 0;
+        for file_result in results {
+            matches += file_result.matches.len();
+            emit_result(&socket, file_result, search_request.merge);
+        }
+        let _ = socket.emit("search:end", &json!({
+            "elapsed": start.elapsed().as_millis(),
+            "matches": matches
+        }));
+        return;
+    }
+
+    let (more_tx, mut more_rx) = tokio::sync::mpsc::channel::<usize>(4);
+    data.search_more = Some(more_tx);
+    drop(sockets_data);
 
-    // Prepare search, get the current directory and create channel to collect results
-    let current_dir = std::env::current_dir().unwrap();
-    let (result_tx, mut result_rx) = mpsc::channel::<FileSearchResult>(1000);
     let socket_clone = socket.clone();
+    let notifier = state.notifier.clone();
+    let pattern = search_request.pattern.clone();
+    let order = search_request.order;
+    let merge = search_request.merge;
+    let max_results = search_request.max_results.unwrap_or(DEFAULT_MAX_RESULTS);
 
     let start = std::time::Instant::now();
 
-    // Start the search in the background
+    // Start the search on the background pool
+    let (mut result_rx, search) = services::start_search(&root, search_request.pattern, search_request.filter, cancel.clone());
     tokio::spawn(async move {
-        let search_result = dir_search(
-            &current_dir, &search_request.pattern, cancel, result_tx
-        ).await;
-
-        if let Err(err) = search_result {
+        if let Ok(Err(err)) = search.await {
             let _ = socket_clone.emit("search:error", &json!({
                 "error": "Search failed", "message": err.to_string()
             }));
         }
     });
 
-    // Collect results and send them to the socket
+    let progress = crate::progress::start("search", &format!("Searching {}", pattern), Some(cancel.clone()));
+
+    // Send the results a page at a time, `search:end` closes every page
+    // with the counts so far
+    tokio::spawn(async move {
+        let _progress = progress;
+        let mut pager = Pager::new(max_results);
+        let mut done = false;
+        let mut ended = false;
+
+        loop {
+            tokio::select! {
+                batch = next_batch(&mut result_rx, order.window()), if !done && !pager.is_full() => match batch {
+                    Some(mut batch) => {
+                        rank_results(&mut batch, order, &pattern);
+                        for file_result in batch {
+                            if let Some(file_result) = pager.push(file_result) {
+                                emit_result(&socket, file_result, merge);
+                            }
+                        }
+                    }
+                    // In cancel case, the results end automatically
+                    None => done = true,
+                },
+                more = more_rx.recv(), if ended => match more {
+                    Some(max_results) => {
+                        for file_result in pager.more(max_results) {
+                            emit_result(&socket, file_result, merge);
+                        }
+                        ended = false;
+                    }
+                    // Replaced by the next search
+                    None => break,
+                },
+                _ = cancel.cancelled(), if !done => done = true,
+            }
+
+            // Finished, or waiting for the next page with enough results
+            if (done || pager.is_full()) && !ended {
+                let _ = socket.emit("search:end", &json!({
+                    "elapsed": start.elapsed().as_millis(),
+                    "matches": pager.sent,
+                    "total_matches": pager.total_matches,
+                    "total_files": pager.total_files,
+                    // Counted up to now, the search goes on with the next page
+                    "estimated": !done,
+                    "has_more": pager.has_pending() || !done,
+                }));
+                ended = true;
+                if done && !pager.has_pending() {
+                    break;
+                }
+            }
+        }
+
+        if start.elapsed() >= notifier.long_search() {
+            notifier.notify(NotifyEvent::SearchCompleted {
+                pattern, matches: pager.total_matches, elapsed_ms: start.elapsed().as_millis(),
+            });
+        }
+    });
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SearchMoreRequest {
+    /// Matches of the page, the ones of the search when missing
+    pub max_results: Option<usize>,
+}
+
+/// Send the next page of the last workspace search, ended by another
+/// `search:end`. Fails when the search has no more results.
+pub async fn handle_search_more(
+    socket: SocketRef,
+    Data(request): Data<SearchMoreRequest>,
+    ack: AckSender,
+    state: State<AppState>,
+) {
+    info!("Received search:more: {:?}", request);
+    let mut timer = EventTimer::start("search:more").with_socket(socket.id).with_payload(&request);
+
+    let more = {
+        let sockets_data = timer.lock("socket2data", &state.socket2data).await;
+        sockets_data.get(socket.id.as_str()).and_then(|data| data.search_more.clone())
+    };
+    let Some(more) = more else {
+        error_ack!(ack, "", "No search to continue");
+    };
+    if more.send(request.max_results.unwrap_or(DEFAULT_MAX_RESULTS)).await.is_err() {
+        error_ack!(ack, "", "The search has no more results");
+    }
+    ack.send(&json!({ "success": true })).ok();
+}
+
+/// Search by syntax, in the buffers of the `opened` documents or in the
+/// workspace files on the background pool. Results are `search:result`
+/// events with the match ranges and captures, ended by `search:end`.
+async fn structural_search(
+    socket: SocketRef,
+    state: &AppState,
+    timer: &mut EventTimer,
+    request: SearchRequest,
+    root: &std::path::Path,
+    opened: Option<Vec<String>>,
+    cancel: CancellationToken,
+) {
+    let mut search = match StructuralSearch::new(&request.pattern, request.preset, request.lang.as_deref()) {
+        Ok(search) => search,
+        Err(e) => {
+            let _ = socket.emit("search:error", &json!({
+                "error": "Search failed", "message": e.to_string()
+            }));
+            return;
+        }
+    };
+    let start = std::time::Instant::now();
+
+    if let Some(opened) = opened {
+        let f2c = timer.lock("file2code", &state.file2code).await;
+        let mut matches = 0;
+        for path in opened {
+            let Some(code) = f2c.get(&path) else { continue };
+            if let Some(file_result) = search.search_text(&path, &code.text.to_string()) {
+                matches += file_result.matches.len();
+                let _ = socket.emit("search:result", &file_result);
+            }
+        }
+        let _ = socket.emit("search:end", &json!({
+            "elapsed": start.elapsed().as_millis(),
+            "matches": matches
+        }));
+        return;
+    }
+
+    let (result_tx, mut result_rx) = tokio::sync::mpsc::channel(64);
+    let progress = crate::progress::start("search", &format!("Searching {}", request.pattern), Some(cancel.clone()));
+    let root = root.to_path_buf();
+    let scan = crate::pool::spawn(async move {
+        let _progress = progress;
+        let files = collect_files_recursively(&root)?;
+        for file in &files {
+            if cancel.is_cancelled() {
+                break;
+            }
+            if let Some(file_result) = search.search_file(file) && result_tx.send(file_result).await.is_err() {
+                break;
+            }
+        }
+        anyhow::Ok(())
+    });
+
     tokio::spawn(async move {
         let mut matches = 0;
-        // In cancel case, the loop will be ended automatically
         while let Some(file_result) = result_rx.recv().await {
-            let _ = socket.emit("search:result", &file_result);
             matches += file_result.matches.len();
+            let _ = socket.emit("search:result", &file_result);
+        }
+        if let Ok(Err(err)) = scan.await {
+            let _ = socket.emit("search:error", &json!({
+                "error": "Search failed", "message": err.to_string()
+            }));
         }
-
         let _ = socket.emit("search:end", &json!({
             "elapsed": start.elapsed().as_millis(),
             "matches": matches
         }));
     });
 }
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SearchInPathRequest {
+    /// Directory to search, usually picked in the file tree
+    pub path: String,
+    pub pattern: String,
+    /// Directories to go down below `path`, unlimited when missing
+    pub depth: Option<usize>,
+    /// Only match the pattern as a whole symbol, see symbol_search
+    #[serde(default = "default_symbol")]
+    pub symbol: bool,
+}
+
+fn default_symbol() -> bool {
+    true
+}
+
+/// Find usages under one directory: its files are walked down to `depth`
+/// and searched directly, without the shared workspace search jobs. Like
+/// `search:start` it cancels the previous search of the socket. The ack
+/// carries the directory, `search:inPathResult` follows per file with its
+/// match count and `search:inPathEnd` with the totals.
+pub async fn handle_search_in_path(
+    socket: SocketRef,
+    Data(request): Data<SearchInPathRequest>,
+    ack: AckSender,
+    state: State<AppState>,
+) {
+    info!("Received search:inPath: {:?}", request);
+    let mut timer = EventTimer::start("search:inPath").with_socket(socket.id).with_payload(&request);
+
+    if request.pattern.is_empty() {
+        error_ack!(ack, &request.path, "Pattern is empty");
+    }
+    let dir = crate::paths::absolute(std::path::Path::new(&request.path));
+    if !dir.is_dir() {
+        error_ack!(ack, &request.path, "Not a directory: {}", request.path);
+    }
+
+    let cancel = CancellationToken::new();
+    {
+        let mut sockets_data = timer.lock("socket2data", &state.socket2data).await;
+        let data = sockets_data.entry(socket.id.to_string()).or_default();
+        if let Some(previous) = data.search_cancel.replace(cancel.clone()) {
+            previous.cancel();
+        }
+        data.search_more = None;
+    }
+    ack.send(&json!({ "path": dir, "success": true })).ok();
+
+    let progress = crate::progress::start("search", &format!("Searching {} in {}", request.pattern, request.path), Some(cancel.clone()));
+    crate::pool::spawn(async move {
+        let _progress = progress;
+        let start = std::time::Instant::now();
+        let (mut files, mut matches) = (0, 0);
+
+        let error = match collect_files_to_depth(&dir, request.depth) {
+            Ok(paths) => {
+                for path in paths {
+                    if cancel.is_cancelled() {
+                        break;
+                    }
+                    // Binary files are not valid text, nothing to find in them
+                    let Ok(text) = std::fs::read_to_string(&path) else { continue };
+                    let display = crate::utils::relative_to_current_dir(&path).unwrap_or(path);
+                    let display = display.to_string_lossy();
+                    let found = if request.symbol {
+                        symbol_search(&display, &text, &request.pattern)
+                    } else {
+                        text_search(&display, &text, &request.pattern)
+                    };
+                    let Some(found) = found else { continue };
+
+                    files += 1;
+                    matches += found.matches.len();
+                    let _ = socket.emit("search:inPathResult", &json!({
+                        "file_path": found.file_path,
+                        "count": found.matches.len(),
+                        "matches": found.matches,
+                    }));
+                }
+                None
+            }
+            Err(e) => Some(e.to_string()),
+        };
+
+        let _ = socket.emit("search:inPathEnd", &json!({
+            "path": dir,
+            "files": files,
+            "matches": matches,
+            "elapsed": start.elapsed().as_millis(),
+            "cancelled": cancel.is_cancelled(),
+            "error": error,
+        }));
+    });
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SearchExportRequest {
+    pub pattern: String,
+    #[serde(default)]
+    pub format: ExportFormat,
+    /// File in the workspace to write, a downloadable file in
+    /// .anycode/exports when missing
+    pub path: Option<String>,
+    #[serde(flatten)]
+    pub filter: PathFilter,
+}
+
+/// Minimum interval between `search:exportProgress` events
+const EXPORT_PROGRESS_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
+
+/// Write every result of a search to a file. The ack carries the target,
+/// `search:exportProgress` and a final `search:exportEnd` follow.
+pub async fn handle_search_export(
+    socket: SocketRef,
+    Data(request): Data<SearchExportRequest>,
+    ack: AckSender,
+    state: State<AppState>,
+) {
+    info!("Received search:export: {:?}", request);
+    let mut timer = EventTimer::start("search:export").with_socket(socket.id).with_payload(&request);
+    let root = services::workspace_root(&state, &mut timer, socket.id.as_str()).await;
+
+    let dest = match search_export::destination(request.path.as_deref(), request.format) {
+        Ok(dest) => dest,
+        Err(e) => error_ack!(ack, request.path.as_deref().unwrap_or_default(), "{}", e),
+    };
+    // Written next to the target and moved over it when complete
+    let partial = dest.with_extension(format!("{}.part", request.format.extension()));
+    let file = match dest.parent().map(std::fs::create_dir_all).transpose()
+        .and_then(|_| std::fs::File::create(&partial))
+    {
+        Ok(file) => file,
+        Err(e) => error_ack!(ack, &dest, "Failed to create {}: {}", partial.display(), e),
+    };
+    let mut writer = match ExportWriter::new(std::io::BufWriter::new(file), request.format) {
+        Ok(writer) => writer,
+        Err(e) => error_ack!(ack, &dest, "Failed to write {}: {}", partial.display(), e),
+    };
+
+    let download = search_export::download_url(&dest);
+    ack.send(&json!({ "path": dest, "download": download, "success": true })).ok();
+
+    let cancel = CancellationToken::new();
+    let progress = crate::progress::start("export", &format!("Exporting {}", request.pattern), Some(cancel.clone()));
+    let (mut result_rx, search) = services::start_search(&root, request.pattern, request.filter, cancel.clone());
+
+    tokio::spawn(async move {
+        let _progress = progress;
+        let start = std::time::Instant::now();
+        let mut last = start;
+        let mut files = 0;
+        let mut failed = None;
+
+        while let Some(file_result) = result_rx.recv().await {
+            if let Err(e) = writer.write(&file_result) {
+                cancel.cancel();
+                failed = Some(e.to_string());
+                break;
+            }
+            files += 1;
+            if last.elapsed() >= EXPORT_PROGRESS_INTERVAL {
+                last = std::time::Instant::now();
+                let _ = socket.emit("search:exportProgress", &json!({
+                    "path": dest, "files": files, "matches": writer.matches()
+                }));
+            }
+        }
+
+        let matches = writer.matches();
+        if let Ok(Err(e)) = search.await {
+            failed.get_or_insert(e.to_string());
+        }
+        if failed.is_none() && let Err(e) = writer.finish() {
+            failed = Some(e.to_string());
+        }
+        let cancelled = failed.is_none() && cancel.is_cancelled();
+        if failed.is_none() && !cancelled && let Err(e) = std::fs::rename(&partial, &dest) {
+            failed = Some(e.to_string());
+        }
+        if failed.is_some() || cancelled {
+            let _ = std::fs::remove_file(&partial);
+        }
+
+        let _ = socket.emit("search:exportEnd", &json!({
+            "path": dest,
+            "download": download,
+            "files": files,
+            "matches": matches,
+            "elapsed": start.elapsed().as_millis(),
+            "cancelled": cancelled,
+            "error": failed,
+            "success": failed.is_none() && !cancelled,
+        }));
+    });
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SearchReplaceRequest {
+    pub pattern: String,
+    pub replacement: String,
+    #[serde(flatten)]
+    pub options: ReplaceOptions,
+    /// Files to replace in, usually the ones of the search results
+    pub files: Vec<String>,
+}
+
+/// Replace all matches in the given files, open buffers included, and
+/// save them. Other clients get `file:changed` for each changed file.
+pub async fn handle_search_replace(
+    socket: SocketRef,
+    Data(request): Data<SearchReplaceRequest>,
+    ack: AckSender,
+    state: State<AppState>,
+) {
+    info!("Received search:replace: {} -> {} in {} files", request.pattern, request.replacement, request.files.len());
+    let mut timer = EventTimer::start("search:replace").with_socket(socket.id).with_payload(&request);
+
+    let replacer = match Replacer::new(&request.pattern, &request.replacement, request.options) {
+        Ok(replacer) => replacer,
+        Err(e) => error_ack!(ack, "", "{}", e),
+    };
+
+    let mut changed = Vec::new();
+    let mut failed = Vec::new();
+    for file in &request.files {
+        let loaded = match services::load_file(&state, &mut timer, file, false).await {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                failed.push(json!({ "path": file, "error": e.to_string() }));
+                continue;
+            }
+        };
+        let (text, replacements) = replacer.replace_all(&loaded.content);
+        if replacements == 0 {
+            continue;
+        }
+
+        match services::set_file(&state, &mut timer, &loaded.abs_path, &text).await {
+            Ok(abs_path) => {
+                socket.emit("file:changed", &(abs_path.clone(), text.clone())).ok();
+                socket.broadcast().emit("file:changed", &(abs_path.clone(), text)).await.ok();
+                changed.push(json!({ "path": abs_path, "replacements": replacements }));
+            }
+            Err(e) => failed.push(json!({ "path": file, "error": e.to_string() })),
+        }
+    }
+
+    ack.send(&json!({ "files": changed, "failed": failed, "success": failed.is_empty() })).ok();
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WatchGrepRequest {
+    pub pattern: String,
+    /// Files and directories to watch, the workspace root if empty
+    #[serde(default)]
+    pub paths: Vec<String>,
+    /// Only search what is appended to the files, for logs
+    #[serde(default)]
+    pub follow: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WatchGrepStopRequest {
+    pub id: u64,
+}
+
+/// Subscribe to the matches of a pattern, the ack carries the id and the
+/// matches found now. Later changes arrive as `watch:grepMatches` with
+/// the added and removed matches of a file.
+pub async fn handle_watch_grep(
+    socket: SocketRef,
+    Data(request): Data<WatchGrepRequest>,
+    ack: AckSender,
+    state: State<AppState>,
+) {
+    info!("Received watch:grep: {:?}", request);
+    let mut timer = EventTimer::start("watch:grep").with_socket(socket.id).with_payload(&request);
+
+    if request.pattern.is_empty() {
+        error_ack!(ack, "", "Pattern is empty");
+    }
+    let paths = if request.paths.is_empty() {
+        vec![services::workspace_root(&state, &mut timer, socket.id.as_str()).await]
+    } else {
+        request.paths.iter().map(|p| crate::paths::absolute(std::path::Path::new(p))).collect()
+    };
+
+    let subscribed = crate::pool::spawn({
+        let socket = socket.clone();
+        async move { crate::grep_watch::subscribe(socket, request.pattern, paths, request.follow) }
+    }).await;
+    let (id, results) = match subscribed {
+        Ok(Ok(subscribed)) => subscribed,
+        Ok(Err(e)) => error_ack!(ack, "", "Failed to watch the matches: {}", e),
+        Err(e) => error_ack!(ack, "", "Failed to watch the matches: {}", e),
+    };
+
+    ack.send(&json!({ "id": id, "results": results, "success": true })).ok();
+}
+
+pub async fn handle_watch_grep_stop(socket: SocketRef, Data(request): Data<WatchGrepStopRequest>, ack: AckSender) {
+    info!("Received watch:grepStop: {}", request.id);
+    let _timer = EventTimer::start("watch:grepStop").with_socket(socket.id).with_payload(&request);
+
+    let stopped = crate::grep_watch::unsubscribe(request.id, socket.id.as_str());
+    ack.send(&json!({ "id": request.id, "success": stopped })).ok();
+}
@@ -0,0 +1,104 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use socketioxide::{extract::{AckSender, Data, SocketRef, State}, SocketIo};
+use tracing::info;
+use crate::app_state::AppState;
+use crate::config::PowerMode;
+use crate::timing::EventTimer;
+
+/// Room that receives server diagnostics such as `server:slowEvent`.
+pub const ADMIN_ROOM: &str = "admin";
+
+pub async fn handle_admin_subscribe(socket: SocketRef) {
+    info!("Received admin:subscribe from {}", socket.id);
+    socket.join(ADMIN_ROOM);
+}
+
+/// Uptime, clients, open documents, terminals, language servers, tasks,
+/// memory and watcher backlog for the status panel
+pub async fn handle_server_status(ack: AckSender, io: SocketIo, state: State<AppState>) {
+    info!("Received server:status");
+    let _timer = EventTimer::start("server:status");
+
+    let status = crate::status::collect(&state, &io).await;
+    ack.send(&json!({ "status": status, "success": true })).ok();
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SetPowerModeRequest {
+    pub mode: PowerMode,
+}
+
+/// Throttle the background work on battery or let it run at full speed,
+/// the other clients get the new mode as `server:powerMode`
+pub async fn handle_set_power_mode(
+    socket: SocketRef,
+    Data(request): Data<SetPowerModeRequest>,
+    ack: AckSender,
+) {
+    info!("Received server:setPowerMode: {:?}", request);
+    let _timer = EventTimer::start("server:setPowerMode").with_socket(socket.id).with_payload(&request);
+
+    let changed = crate::power::set(request.mode);
+    if changed {
+        socket.broadcast().emit("server:powerMode", &json!({ "mode": request.mode })).await.ok();
+    }
+    ack.send(&json!({ "mode": request.mode, "changed": changed, "success": true })).ok();
+}
+
+/// Check the environment for the setup checklist: language servers and
+/// shells in PATH, pseudo terminals, the watch limit, writable state
+/// directories and the server port
+pub async fn handle_server_selftest(socket: SocketRef, ack: AckSender, state: State<AppState>) {
+    info!("Received server:selftest");
+    let mut timer = EventTimer::start("server:selftest").with_socket(socket.id);
+
+    let root = crate::services::workspace_root(&state, &mut timer, socket.id.as_str()).await;
+    let port = crate::status::url()
+        .and_then(|url| url.rsplit(':').next().and_then(|p| p.parse::<u16>().ok()));
+    let config = state.config.clone();
+    let report = crate::pool::spawn(async move { crate::selftest::run(&config, &root, port).await }).await;
+
+    match report {
+        Ok(report) => ack.send(&json!({ "report": report, "success": true })).ok(),
+        Err(e) => ack.send(&json!({ "error": e.to_string(), "success": false })).ok(),
+    };
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProfileAction {
+    Start,
+    Stop,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProfileRequest {
+    pub action: ProfileAction,
+}
+
+/// Start or stop recording a profile of the backend, see profile.rs. The
+/// ack of `stop` has the path of the trace file and its download url.
+pub async fn handle_server_profile(Data(request): Data<ProfileRequest>, ack: AckSender) {
+    info!("Received server:profile: {:?}", request);
+    let _timer = EventTimer::start("server:profile").with_payload(&request);
+
+    match request.action {
+        ProfileAction::Start => {
+            let started = crate::profile::start();
+            ack.send(&json!({ "recording": true, "started": started, "success": true })).ok();
+        }
+        ProfileAction::Stop => match crate::profile::stop().map(|p| crate::profile::write(&p, &crate::search_export::exports_dir())) {
+            Some(Ok(path)) => {
+                let url = crate::search_export::download_url(&path);
+                ack.send(&json!({ "recording": false, "path": path, "url": url, "success": true })).ok();
+            }
+            Some(Err(e)) => {
+                ack.send(&json!({ "error": format!("Failed to write the profile: {}", e), "success": false })).ok();
+            }
+            None => {
+                ack.send(&json!({ "error": "Not recording a profile", "success": false })).ok();
+            }
+        },
+    }
+}
@@ -0,0 +1,44 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use socketioxide::extract::{AckSender, Data, SocketRef, State};
+use tracing::info;
+use crate::app_state::{AppState, SocketData};
+use crate::timing::EventTimer;
+use crate::utils::abs_file;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SessionRestoreRequest {
+    /// Files the client still has open from before the reconnect
+    pub files: Vec<String>,
+}
+
+/// Re-register the open files of a reconnecting client and return the
+/// current diagnostics for them, so the gutter is not empty until the
+/// next LSP publish.
+pub async fn handle_session_restore(
+    socket: SocketRef,
+    Data(request): Data<SessionRestoreRequest>,
+    ack: AckSender,
+    state: State<AppState>,
+) {
+    info!("Received session:restore: {} files", request.files.len());
+    let mut timer = EventTimer::start("session:restore").with_socket(socket.id).with_payload(&request);
+
+    let files: Vec<String> = request.files.iter()
+        .filter_map(|f| abs_file(f).ok())
+        .collect();
+
+    let diagnostics: Vec<_> = {
+        let cache = timer.lock("diagnostics", &state.diagnostics).await;
+        files.iter()
+            .filter_map(|f| cache.get(f).map(crate::diagnostic_filter::to_json))
+            .collect()
+    };
+
+    let sid = socket.id.as_str().to_string();
+    let mut sockets_data = timer.lock("socket2data", &state.socket2data).await;
+    let data = sockets_data.entry(sid).or_insert_with(SocketData::default);
+    data.opened_files.extend(files.iter().cloned());
+
+    ack.send(&json!({ "files": files, "diagnostics": diagnostics, "success": true })).ok();
+}
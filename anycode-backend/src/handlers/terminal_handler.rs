@@ -1,12 +1,25 @@
 use serde_json::{self, json};
 use socketioxide::{extract::{AckSender, Data, SocketRef, State}};
 use tracing::info;
+use crate::timing::EventTimer;
 use crate::{app_state::{AppState,TerminalData}, terminal::Terminal};
+use crate::config::{Config, TerminalProfile};
+use crate::terminal::available_profiles;
+use crate::recording::{self, Recording};
+use crate::scrollback::{Scrollback, DEFAULT_SCROLLBACK_BYTES};
+use crate::terminal_history::{self, MarkerParser};
+use crate::shell_complete;
+use crate::terminal_share;
+use crate::notifier::NotifyEvent;
 use serde::{Deserialize, Serialize};
+use base64::Engine;
 use std::{collections::VecDeque, sync::Arc};
 use tokio::sync::{Mutex, mpsc};
 
 const MAX_TERMINAL_BUFFER: usize = 500;
+const DEFAULT_HISTORY_LIMIT: usize = 50;
+/// History key of terminals started without a profile
+const DEFAULT_PROFILE: &str = "default";
 
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -14,10 +27,34 @@ pub struct TerminalStartRequest {
     pub name: String,
     pub session: String,
     pub cmd: Option<String>,
+    /// Name of a profile from terminal:profiles, takes precedence over cmd
+    pub profile: Option<String>,
     pub rows: Option<u16>,
     pub cols: Option<u16>,
 }
 
+fn start_profile(config: &Config, request: &TerminalStartRequest) -> anyhow::Result<Option<TerminalProfile>> {
+    if let Some(name) = &request.profile {
+        return available_profiles(config).into_iter()
+            .find(|p| &p.name == name)
+            .map(Some)
+            .ok_or_else(|| anyhow::anyhow!("Unknown terminal profile {}", name));
+    }
+
+    match &request.cmd {
+        Some(cmd) => Ok(Some(TerminalProfile::from_command_line(&request.name, cmd)?)),
+        None => Ok(None),
+    }
+}
+
+pub async fn handle_terminal_profiles(ack: AckSender, state: State<AppState>) {
+    info!("Received terminal:profiles");
+    let _timer = EventTimer::start("terminal:profiles");
+
+    let profiles = available_profiles(&state.config);
+    let _ = ack.send(&json!({ "profiles": profiles, "success": true }));
+}
+
 pub async fn handle_terminal_start(
     socket: SocketRef,
     Data(terminal_start_request): Data<TerminalStartRequest>,
@@ -25,16 +62,17 @@ pub async fn handle_terminal_start(
     ack: AckSender
 ) {
     info!("Received handle_terminal {:?}", terminal_start_request);
+    let mut timer = EventTimer::start("terminal:start").with_socket(socket.id).with_payload(&terminal_start_request);
 
     let terminal_name = terminal_start_request.name.clone();
     let session_id = terminal_start_request.session.clone();
     let id = format!("{}-{}", session_id, terminal_name);
 
-    if state.terminals.lock().await.contains_key(&id) {
+    if timer.lock("terminals", &state.terminals).await.contains_key(&id) {
         // let _ = socket.emit("terminal:error", "Terminal already exists");
 
         let terminal_data_opt = {
-            let terminals = state.terminals.lock().await;
+            let terminals = timer.lock("terminals", &state.terminals).await;
             terminals.get(&id).cloned()
         };
     
@@ -53,17 +91,34 @@ pub async fn handle_terminal_start(
         return;
     }
 
+    let profile = match start_profile(&state.config, &terminal_start_request) {
+        Ok(profile) => profile,
+        Err(e) => {
+            let message = format!("Failed to create terminal: {}", e);
+            let _ = socket.emit("terminal:error", &message);
+            return;
+        }
+    };
+
     // Get terminal dimensions
     let rows = terminal_start_request.rows.unwrap_or(30);
     let cols = terminal_start_request.cols.unwrap_or(80);
 
+    let profile_name = profile.as_ref()
+        .map(|p| p.name.clone())
+        .unwrap_or_else(|| DEFAULT_PROFILE.to_string());
+    let profile = match state.config.terminal.as_ref().and_then(|t| t.shell_integration) {
+        Some(false) => profile,
+        _ => crate::shell_integration::apply(profile),
+    };
+
     // Create channel for terminal output
     let (output_tx, mut output_rx) = mpsc::channel::<String>(32);
 
     // Create terminal
     let term = Terminal::new(
         terminal_name.clone(), session_id.clone(),
-        rows, cols, None, None, output_tx,
+        rows, cols, profile, None, output_tx,
     ).await;
 
     let terminal = match term {
@@ -79,21 +134,36 @@ pub async fn handle_terminal_start(
     let sockets = Arc::new(Mutex::new(vec![socket.clone()]));
 
     let buffer = Arc::new(Mutex::new(VecDeque::with_capacity(MAX_TERMINAL_BUFFER)));
+    let scrollback_bytes = state.config.terminal.as_ref()
+        .and_then(|t| t.scrollback_bytes)
+        .unwrap_or(DEFAULT_SCROLLBACK_BYTES);
+    let scrollback = Arc::new(Mutex::new(Scrollback::new(scrollback_bytes)));
+    let recording = Arc::new(Mutex::new(Recording::new(cols, rows)));
 
     // Create terminal data for app state
     let terminal_data = TerminalData {
         terminal: Arc::new(terminal),
         sockets: sockets.clone(),
         buffer: buffer.clone(),
+        scrollback: scrollback.clone(),
+        recording: recording.clone(),
     };
 
     // Spawn task to handle terminal output
     let tname = terminal_name.clone();
     let sockets_clone = sockets.clone();
     let buffer_clone = buffer.clone();
+    let notifier = state.notifier.clone();
+    let share_key = id.clone();
     tokio::spawn(async move {
+        let mut markers = MarkerParser::default();
         while let Some(output) = output_rx.recv().await {
             let channel = format!("terminal:data:{}", tname);
+            recording.lock().await.output(&output);
+            scrollback.lock().await.push(&output);
+            for command in markers.feed(&output) {
+                terminal_history::record(&profile_name, &tname, &command);
+            }
             let mut needs_buffer = false;
 
             {
@@ -114,6 +184,8 @@ pub async fn handle_terminal_start(
                 }
             }
 
+            terminal_share::forward(&share_key, &output);
+
             if needs_buffer {
                 let mut buffer_guard = buffer_clone.lock().await;
                 buffer_guard.push_back(output.clone());
@@ -123,20 +195,40 @@ pub async fn handle_terminal_start(
             }
         }
         info!("Terminal output handler finished for {}", tname);
+        notifier.notify(NotifyEvent::TerminalExited { name: tname });
     });
 
     // Store terminal in app state
-    state.terminals.lock().await.insert(id, terminal_data);
+    timer.lock("terminals", &state.terminals).await.insert(id, terminal_data);
 
     info!("Terminal {} started successfully", terminal_name);
+    let started = json!({ "name": terminal_name, "session": session_id });
+    let seq = crate::event_log::record("terminal:started", &started);
+    socket.broadcast().emit("terminal:started", &(&started, seq)).await.ok();
 }
 
 
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum InputEncoding {
+    /// The input is the text itself
+    #[default]
+    Text,
+    /// The input is base64 of raw bytes, e.g. control sequences or partial
+    /// UTF-8 sequences
+    Base64,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TerminalInputRequest {
     pub name: String,
     pub input: String,
     pub session: String,
+    #[serde(default)]
+    pub encoding: InputEncoding,
+    /// Pasted text, sent bracketed when the program enabled bracketed paste
+    #[serde(default)]
+    pub paste: bool,
 }
 
 pub async fn handle_terminal_input(
@@ -145,18 +237,43 @@ pub async fn handle_terminal_input(
     state: State<AppState>
 ) {
     info!("Received handle_terminal_input {:?}", request);
+    let mut timer = EventTimer::start("terminal:input").with_socket(socket.id).with_payload(&request);
 
-    let TerminalInputRequest { name, input, session } = request;
+    let TerminalInputRequest { name, input, session, encoding, paste } = request;
     let id = format!("{}-{}", session, name);
+
+    if terminal_share::is_guest(&id, socket.id.as_str()) {
+        let _ = socket.emit("terminal:error", "Terminal is shared read-only");
+        return;
+    }
+
+    let input = match encoding {
+        InputEncoding::Text => input.into_bytes(),
+        InputEncoding::Base64 => match base64::engine::general_purpose::STANDARD.decode(&input) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                let _ = socket.emit("terminal:error", &format!("Invalid base64 input: {}", e));
+                return;
+            }
+        },
+    };
     
     let terminal_data_opt = {
-        let terminals = state.terminals.lock().await;
+        let terminals = timer.lock("terminals", &state.terminals).await;
         terminals.get(&id).cloned()
     };
 
     if let Some(terminal_data) = terminal_data_opt {
+        if !terminal_data.is_attached(&socket).await {
+            let _ = socket.emit("terminal:error", "Terminal is attached to another connection");
+            return;
+        }
+
         // Send input to terminal
-        let send_result = terminal_data.terminal.send_input(input).await;
+        let send_result = match paste {
+            true => terminal_data.terminal.paste(&String::from_utf8_lossy(&input)).await,
+            false => terminal_data.terminal.send_input(input).await,
+        };
 
         if let Err(e) = send_result {
             let e = format!("Failed to send input: {}", e);
@@ -182,21 +299,34 @@ pub async fn handle_terminal_resize(
     state: State<AppState>
 ) {
     info!("Received handle_terminal_resize {:?}", request);
+    let mut timer = EventTimer::start("terminal:resize").with_socket(socket.id).with_payload(&request);
     let TerminalResizeRequest { name, session, cols, rows } = request;
     let id = format!("{}-{}", session, name);
 
+    if terminal_share::is_guest(&id, socket.id.as_str()) {
+        let _ = socket.emit("terminal:error", "Terminal is shared read-only");
+        return;
+    }
+
     let terminal_data_opt = {
-        let terminals = state.terminals.lock().await;
+        let terminals = timer.lock("terminals", &state.terminals).await;
         terminals.get(&id).cloned()
     };
 
     if let Some(terminal_data) = terminal_data_opt {
+        if !terminal_data.is_attached(&socket).await {
+            let _ = socket.emit("terminal:error", "Terminal is attached to another connection");
+            return;
+        }
+
         // resize terminal
         let resize_result = terminal_data.terminal.resize(cols, rows).await;
 
         if let Err(e) = resize_result {
             let e = format!("Failed to resize terminal: {}", e);
             let _ = socket.emit("terminal:error", &e);
+        } else {
+            terminal_data.recording.lock().await.resize(cols, rows);
         }
     } else {
         let _ = socket.emit("terminal:error", "Terminal not found");
@@ -216,19 +346,32 @@ pub async fn handle_terminal_close(
     state: State<AppState>
 ) {
     info!("Received handle_terminal_close {:?}", request);
+    let mut timer = EventTimer::start("terminal:close").with_socket(socket.id).with_payload(&request);
     let TerminalCloseRequest { name, session } = request;
     let id = format!("{}-{}", session, name);
 
+    if terminal_share::is_guest(&id, socket.id.as_str()) {
+        let _ = socket.emit("terminal:error", "Terminal is shared read-only");
+        return;
+    }
+
     let terminal_data_opt = {
-        let mut terminals = state.terminals.lock().await;
+        let mut terminals = timer.lock("terminals", &state.terminals).await;
         terminals.remove(&id)
     };
 
+    if let Some((share_id, guests)) = terminal_share::revoke(&id) {
+        revoked(&share_id, &guests);
+    }
+
     if let Some(terminal_data) = terminal_data_opt {
         // kill terminal
         match terminal_data.terminal.kill().await {
             Ok(_) => {
                 info!("Terminal {} closed successfully", name);
+                let closed = json!({ "name": name, "session": session });
+                let seq = crate::event_log::record("terminal:closed", &closed);
+                socket.broadcast().emit("terminal:closed", &(&closed, seq)).await.ok();
             }
             Err(e) => {
                 let e = format!("Failed to kill terminal: {}", e);
@@ -254,11 +397,17 @@ pub async fn handle_terminal_reconnect(
     ack: AckSender
 ) {
     info!("Received handle_terminal_reconnect {:?}", request);
+    let mut timer = EventTimer::start("terminal:reconnect").with_socket(socket.id).with_payload(&request);
     let TerminalReconnectRequest { name, session } = request;
     let id = format!("{}-{}", session, name);
 
+    if terminal_share::is_guest(&id, socket.id.as_str()) {
+        let _ = ack.send(&json!({ "success": false, "error": "Terminal is shared read-only" }));
+        return;
+    }
+
     let terminal_data_opt = {
-        let terminals = state.terminals.lock().await;
+        let terminals = timer.lock("terminals", &state.terminals).await;
         terminals.get(&id).cloned()
     };
 
@@ -291,4 +440,272 @@ pub async fn handle_terminal_reconnect(
         let _ = ack.send(&json!({ "success": false, "error": "Terminal not found" }));
         info!("Terminal {} not found for reconnection", name);
     }
-}
\ No newline at end of file
+}
+
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TerminalScrollbackRequest {
+    pub name: String,
+    pub session: String,
+    /// Bytes to replay from the end, all that is kept when missing
+    pub bytes: Option<usize>,
+}
+
+/// Output of a terminal so far, for a panel attaching to it. `total` is
+/// the bytes it wrote since it started, `truncated` tells that earlier
+/// output is missing from `data`.
+pub async fn handle_terminal_scrollback(
+    Data(request): Data<TerminalScrollbackRequest>,
+    state: State<AppState>,
+    ack: AckSender
+) {
+    info!("Received terminal:scrollback {:?}", request);
+    let mut timer = EventTimer::start("terminal:scrollback").with_payload(&request);
+    let id = format!("{}-{}", request.session, request.name);
+
+    let terminal_data_opt = {
+        let terminals = timer.lock("terminals", &state.terminals).await;
+        terminals.get(&id).cloned()
+    };
+
+    let Some(terminal_data) = terminal_data_opt else {
+        let _ = ack.send(&json!({ "success": false, "error": "Terminal not found" }));
+        return;
+    };
+
+    let scrollback = timer.lock("scrollback", &terminal_data.scrollback).await;
+    let data = scrollback.tail(request.bytes);
+    let truncated = (data.len() as u64) < scrollback.total();
+    let _ = ack.send(&json!({
+        "name": request.name, "data": data, "total": scrollback.total(), "truncated": truncated, "success": true
+    }));
+}
+
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TerminalShareRequest {
+    pub name: String,
+    pub session: String,
+}
+
+/// Tell the guests of a share that it ended
+fn revoked(share_id: &str, guests: &[SocketRef]) {
+    for guest in guests {
+        let _ = guest.emit("terminal:shareRevoked", &json!({ "share_id": share_id }));
+    }
+}
+
+/// Share a terminal read-only, the ack carries the id guests join with
+pub async fn handle_terminal_share(
+    socket: SocketRef,
+    Data(request): Data<TerminalShareRequest>,
+    state: State<AppState>,
+    ack: AckSender
+) {
+    info!("Received terminal:share {:?}", request);
+    let mut timer = EventTimer::start("terminal:share").with_socket(socket.id).with_payload(&request);
+    let id = format!("{}-{}", request.session, request.name);
+
+    if !timer.lock("terminals", &state.terminals).await.contains_key(&id) {
+        let _ = ack.send(&json!({ "success": false, "error": "Terminal not found" }));
+        return;
+    }
+    if terminal_share::is_guest(&id, socket.id.as_str()) {
+        let _ = ack.send(&json!({ "success": false, "error": "Terminal is shared read-only" }));
+        return;
+    }
+
+    let share_id = terminal_share::share(&id, &request.name);
+    let _ = ack.send(&json!({ "share_id": share_id, "success": true }));
+}
+
+/// End the share of a terminal, its guests get `terminal:shareRevoked`
+pub async fn handle_terminal_revoke(
+    socket: SocketRef,
+    Data(request): Data<TerminalShareRequest>,
+    ack: AckSender
+) {
+    info!("Received terminal:revoke {:?}", request);
+    let _timer = EventTimer::start("terminal:revoke").with_socket(socket.id).with_payload(&request);
+    let id = format!("{}-{}", request.session, request.name);
+
+    if terminal_share::is_guest(&id, socket.id.as_str()) {
+        let _ = ack.send(&json!({ "success": false, "error": "Terminal is shared read-only" }));
+        return;
+    }
+    match terminal_share::revoke(&id) {
+        Some((share_id, guests)) => {
+            revoked(&share_id, &guests);
+            let _ = ack.send(&json!({ "share_id": share_id, "guests": guests.len(), "success": true }));
+        }
+        None => {
+            let _ = ack.send(&json!({ "success": false, "error": "Terminal is not shared" }));
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TerminalJoinRequest {
+    pub share_id: String,
+}
+
+/// Follow a shared terminal, its output arrives as
+/// `terminal:shared:<share_id>` until the guest leaves or the share ends
+pub async fn handle_terminal_join(
+    socket: SocketRef,
+    Data(request): Data<TerminalJoinRequest>,
+    ack: AckSender
+) {
+    info!("Received terminal:join {:?}", request);
+    let _timer = EventTimer::start("terminal:join").with_socket(socket.id).with_payload(&request);
+
+    match terminal_share::join(&request.share_id, socket.clone()) {
+        Some((_, name)) => {
+            let _ = ack.send(&json!({ "share_id": request.share_id, "name": name, "success": true }));
+        }
+        None => {
+            let _ = ack.send(&json!({ "success": false, "error": "Share not found" }));
+        }
+    }
+}
+
+pub async fn handle_terminal_leave(
+    socket: SocketRef,
+    Data(request): Data<TerminalJoinRequest>,
+    ack: AckSender
+) {
+    info!("Received terminal:leave {:?}", request);
+    let _timer = EventTimer::start("terminal:leave").with_socket(socket.id).with_payload(&request);
+
+    let left = terminal_share::leave(&request.share_id, socket.id.as_str());
+    let _ = ack.send(&json!({ "share_id": request.share_id, "success": left }));
+}
+
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TerminalRecordRequest {
+    pub name: String,
+    pub session: String,
+    pub enabled: bool,
+}
+
+/// Start or stop recording a terminal into an asciicast file under the
+/// workspace state dir. Acks the name of the started or finished cast.
+pub async fn handle_terminal_record(
+    Data(request): Data<TerminalRecordRequest>,
+    state: State<AppState>,
+    ack: AckSender
+) {
+    info!("Received terminal:record {:?}", request);
+    let mut timer = EventTimer::start("terminal:record").with_payload(&request);
+    let id = format!("{}-{}", request.session, request.name);
+
+    let terminal_data_opt = {
+        let terminals = timer.lock("terminals", &state.terminals).await;
+        terminals.get(&id).cloned()
+    };
+
+    let Some(terminal_data) = terminal_data_opt else {
+        let _ = ack.send(&json!({ "success": false, "error": "Terminal not found" }));
+        return;
+    };
+
+    let mut recording = timer.lock("recording", &terminal_data.recording).await;
+    let result = if request.enabled {
+        recording.start(&request.name).map(Some)
+    } else {
+        Ok(recording.stop())
+    };
+
+    let response = match result {
+        Ok(file) => json!({ "recording": recording.is_recording(), "file": file, "success": true }),
+        Err(e) => json!({ "success": false, "error": format!("Failed to record terminal: {}", e) }),
+    };
+    let _ = ack.send(&response);
+}
+
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TerminalReplaysRequest {
+    /// Name of a recording to fetch, lists the recordings when missing
+    pub name: Option<String>,
+}
+
+pub async fn handle_terminal_replays(
+    Data(request): Data<TerminalReplaysRequest>,
+    ack: AckSender
+) {
+    info!("Received terminal:replays {:?}", request);
+    let _timer = EventTimer::start("terminal:replays").with_payload(&request);
+
+    let response = match &request.name {
+        Some(name) => match recording::read(name) {
+            Ok(cast) => json!({ "name": name, "cast": cast, "success": true }),
+            Err(e) => json!({ "success": false, "error": format!("Failed to read recording: {}", e) }),
+        },
+        None => match recording::list() {
+            Ok(replays) => json!({ "replays": replays, "success": true }),
+            Err(e) => json!({ "success": false, "error": format!("Failed to list recordings: {}", e) }),
+        },
+    };
+    let _ = ack.send(&response);
+}
+
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TerminalHistoryRequest {
+    #[serde(default)]
+    pub query: String,
+    /// Only the history of this profile, all profiles when missing
+    pub profile: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// Fuzzy search of the command lines captured from the terminals, kept
+/// across terminal and backend restarts
+pub async fn handle_terminal_history(
+    Data(request): Data<TerminalHistoryRequest>,
+    ack: AckSender
+) {
+    info!("Received terminal:history {:?}", request);
+    let _timer = EventTimer::start("terminal:history").with_payload(&request);
+
+    let limit = request.limit.unwrap_or(DEFAULT_HISTORY_LIMIT);
+    let entries = terminal_history::search(request.profile.as_deref(), &request.query, limit);
+    let _ = ack.send(&json!({ "entries": entries, "success": true }));
+}
+
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TerminalCompleteRequest {
+    pub line: String,
+    /// Char offset in the line, the end when missing
+    pub cursor: Option<usize>,
+    /// Directory file names are completed in, the workspace root when missing
+    pub cwd: Option<String>,
+}
+
+/// Command and file name completions of a partial command line from the
+/// shell, for frontends that do not render a terminal
+pub async fn handle_terminal_complete(
+    Data(request): Data<TerminalCompleteRequest>,
+    ack: AckSender
+) {
+    info!("Received terminal:complete {:?}", request);
+    let _timer = EventTimer::start("terminal:complete").with_payload(&request);
+
+    let root = std::env::current_dir().unwrap_or_default();
+    let cwd = request.cwd.as_ref().map_or_else(|| root.clone(), |cwd| root.join(cwd));
+    let cursor = request.cursor.unwrap_or(usize::MAX);
+
+    let response = match shell_complete::complete(&request.line, cursor, &cwd).await {
+        Ok(completions) => json!({
+            "start": completions.start,
+            "end": completions.end,
+            "completions": completions.completions,
+            "success": true,
+        }),
+        Err(e) => json!({ "success": false, "error": format!("Failed to complete: {}", e) }),
+    };
+    let _ = ack.send(&response);
+}
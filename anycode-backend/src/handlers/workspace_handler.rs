@@ -0,0 +1,362 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use socketioxide::extract::{AckSender, Data, SocketRef, State};
+use tracing::{info, error};
+use crate::app_state::AppState;
+use crate::error_ack;
+use crate::timing::EventTimer;
+use anycode_search::ignore::{focus_excludes, set_focus_excludes};
+use crate::duplicates::find_duplicates;
+use crate::bootstrap::{self, Scaffold};
+use crate::file_history::{self, Point};
+use crate::search::collect_files_recursively;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// Minimum interval between `workspace:duplicatesProgress` events
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WorkspaceFocusRequest {
+    pub exclude: Option<Vec<String>>,
+}
+
+/// Exclude subtrees for the rest of the session. Search, dir:list and the
+/// watcher skip them the same way as statically ignored directories.
+/// Without `exclude` the current excludes are returned.
+pub async fn handle_workspace_focus(
+    socket: SocketRef,
+    Data(request): Data<WorkspaceFocusRequest>,
+    ack: AckSender,
+) {
+    info!("Received workspace:focus: {:?}", request);
+    let _timer = EventTimer::start("workspace:focus").with_socket(socket.id).with_payload(&request);
+
+    let excludes = match request.exclude {
+        Some(exclude) => {
+            let excludes = set_focus_excludes(&exclude);
+            let seq = crate::event_log::record("workspace:focus", &excludes);
+            socket.broadcast().emit("workspace:focus", &(&excludes, seq)).await.ok();
+            excludes
+        }
+        None => focus_excludes(),
+    };
+
+    ack.send(&json!({ "success": true, "exclude": excludes })).ok();
+}
+
+/// Groups of identical files in the workspace, hashed in the background.
+/// Progress is streamed as `workspace:duplicatesProgress {hashed, total}`.
+pub async fn handle_workspace_duplicates(socket: SocketRef, ack: AckSender) {
+    info!("Received workspace:duplicates");
+    let _timer = EventTimer::start("workspace:duplicates").with_socket(socket.id);
+
+    let (progress_tx, mut progress_rx) = mpsc::channel::<(usize, usize)>(16);
+    let scan = crate::pool::spawn(async move {
+        let files = collect_files_recursively(Path::new("."))?;
+        let mut last = Instant::now();
+        let groups = find_duplicates(files, |hashed, total| {
+            if hashed == total || last.elapsed() >= PROGRESS_INTERVAL {
+                last = Instant::now();
+                let _ = progress_tx.try_send((hashed, total));
+            }
+        });
+        anyhow::Ok(groups)
+    });
+
+    while let Some((hashed, total)) = progress_rx.recv().await {
+        let _ = socket.emit("workspace:duplicatesProgress", &json!({ "hashed": hashed, "total": total }));
+    }
+
+    let response = match scan.await {
+        Ok(Ok(groups)) => {
+            let wasted: u64 = groups.iter().map(|g| g.wasted()).sum();
+            json!({ "groups": groups, "wasted": wasted, "success": true })
+        }
+        Ok(Err(e)) => json!({ "error": e.to_string(), "success": false }),
+        Err(e) => json!({ "error": e.to_string(), "success": false }),
+    };
+    ack.send(&response).ok();
+}
+
+/// Names that differ only in case among the open buffers and the
+/// workspace files, with a severity and a message each like diagnostics
+pub async fn handle_workspace_case_check(ack: AckSender, state: State<AppState>) {
+    info!("Received workspace:caseCheck");
+    let mut timer = EventTimer::start("workspace:caseCheck");
+
+    let issues = crate::services::case_issues(&state, &mut timer).await;
+    ack.send(&json!({
+        "issues": issues, "case_insensitive": crate::paths::is_case_insensitive(), "success": true
+    })).ok();
+}
+
+/// Progress of the startup workspace scan, for clients connecting after
+/// some `workspace:scanProgress` events were already sent
+pub async fn handle_workspace_scan_status(ack: AckSender) {
+    info!("Received workspace:scanStatus");
+    let _timer = EventTimer::start("workspace:scanStatus");
+
+    ack.send(&json!({ "progress": crate::file_index::progress(), "success": true })).ok();
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FilesFindRequest {
+    #[serde(default)]
+    pub query: String,
+    pub limit: Option<usize>,
+}
+
+/// Fuzzy "Go to file" over the indexed workspace files, recently opened
+/// files first. `indexing` is set while the startup scan is running.
+pub async fn handle_files_find(
+    Data(request): Data<FilesFindRequest>,
+    ack: AckSender,
+    state: State<AppState>,
+) {
+    info!("Received files:find: {:?}", request);
+    let mut timer = EventTimer::start("files:find").with_payload(&request);
+
+    let recent: Vec<String> = timer.lock("recent", &state.recent).await
+        .files()
+        .map(|f| crate::utils::relative_path(f))
+        .collect();
+
+    let limit = request.limit.unwrap_or(crate::quick_open::DEFAULT_LIMIT);
+    let files = crate::file_index::with_files(|files| {
+        crate::quick_open::find(files, &request.query, &recent, limit)
+    });
+    let indexing = !crate::file_index::progress().done;
+    ack.send(&json!({ "files": files, "indexing": indexing, "success": true })).ok();
+}
+
+/// Workspace roots, the primary root first
+pub async fn handle_workspace_roots(ack: AckSender) {
+    info!("Received workspace:roots");
+    let _timer = EventTimer::start("workspace:roots");
+
+    ack.send(&json!({ "roots": crate::roots::list(), "success": true })).ok();
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WorkspaceRootRequest {
+    pub path: String,
+}
+
+/// Add a root, documents under it get language servers of their own
+pub async fn handle_workspace_add_root(Data(request): Data<WorkspaceRootRequest>, ack: AckSender) {
+    info!("Received workspace:addRoot: {:?}", request);
+    let _timer = EventTimer::start("workspace:addRoot").with_payload(&request);
+
+    let root = match crate::roots::add(&request.path) {
+        Ok(root) => root,
+        Err(e) => error_ack!(ack, &request.path, "Failed to add root: {}", e),
+    };
+    if let Err(e) = crate::watcher::watch_root(&root) {
+        error!("Failed to watch {}: {}", root.display(), e);
+    }
+    ack.send(&json!({ "root": root, "roots": crate::roots::list(), "success": true })).ok();
+}
+
+/// Open a root as the workspace of this connection: it is added as a root,
+/// and dir:list, search and search:export of the client resolve against it
+pub async fn handle_workspace_open(
+    socket: SocketRef,
+    Data(request): Data<WorkspaceRootRequest>,
+    ack: AckSender,
+    state: State<AppState>,
+) {
+    info!("Received workspace:open: {:?}", request);
+    let mut timer = EventTimer::start("workspace:open").with_socket(socket.id).with_payload(&request);
+
+    let root = match crate::services::open_workspace(&state, &mut timer, socket.id.as_str(), &request.path).await {
+        Ok(root) => root,
+        Err(e) => error_ack!(ack, &request.path, "Failed to open workspace: {}", e),
+    };
+    ack.send(&json!({ "root": root, "roots": crate::roots::list(), "success": true })).ok();
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum BootstrapRequest {
+    /// Whether the workspace is empty and the projects it can scaffold
+    Status,
+    /// Clone into the subdirectory `dir`, into the empty root without
+    Clone { url: String, dir: Option<String> },
+    Scaffold { kind: Scaffold, dir: Option<String> },
+    /// Open another folder as the workspace, like workspace:open
+    Open { path: String },
+}
+
+/// Onboarding of an empty workspace, see bootstrap.rs. The progress of a
+/// clone is streamed as `workspace:bootstrapProgress {url, stage, percent,
+/// line}` and shown in the progress center, where it can be cancelled.
+/// The other clients get `workspace:bootstrapped` when files were added.
+pub async fn handle_workspace_bootstrap(
+    socket: SocketRef,
+    Data(request): Data<BootstrapRequest>,
+    ack: AckSender,
+    state: State<AppState>,
+) {
+    info!("Received workspace:bootstrap: {:?}", request);
+    let mut timer = EventTimer::start("workspace:bootstrap").with_socket(socket.id).with_payload(&request);
+    let root = crate::services::workspace_root(&state, &mut timer, socket.id.as_str()).await;
+
+    let action = match &request {
+        BootstrapRequest::Open { .. } => Some("workspace:bootstrap:open"),
+        BootstrapRequest::Clone { .. } => Some("workspace:bootstrap:clone"),
+        _ => None,
+    };
+    if let Some(action) = action
+        && let Some(scope) = crate::services::missing_scope(&state, &mut timer, socket.id.as_str(), action).await
+    {
+        error_ack!(ack, &root, "Not allowed: {} needs the {} scope", action, scope.as_str());
+    }
+
+    let (path, files) = match request {
+        BootstrapRequest::Status => {
+            ack.send(&json!({
+                "root": root, "empty": bootstrap::is_empty(&root), "scaffolds": Scaffold::ALL, "success": true
+            })).ok();
+            return;
+        }
+        BootstrapRequest::Open { path } => {
+            let root = match crate::services::open_workspace(&state, &mut timer, socket.id.as_str(), &path).await {
+                Ok(root) => root,
+                Err(e) => error_ack!(ack, &path, "Failed to open workspace: {}", e),
+            };
+            ack.send(&json!({ "root": root, "roots": crate::roots::list(), "success": true })).ok();
+            return;
+        }
+        BootstrapRequest::Scaffold { kind, dir } => {
+            let dest = match bootstrap::destination(&root, dir.as_deref()) {
+                Ok(dest) => dest,
+                Err(e) => error_ack!(ack, &root, "{}", e),
+            };
+            match bootstrap::scaffold(&dest, kind).await {
+                Ok(files) => (dest, files),
+                Err(e) => error_ack!(ack, &dest, "Failed to scaffold the project: {}", e),
+            }
+        }
+        BootstrapRequest::Clone { url, dir } => {
+            let dest = match bootstrap::destination(&root, dir.as_deref()) {
+                Ok(dest) => dest,
+                Err(e) => error_ack!(ack, &root, "{}", e),
+            };
+            let cancel = CancellationToken::new();
+            let progress = crate::progress::start("clone", &format!("Cloning {}", url), Some(cancel.clone()));
+            let cloned = bootstrap::clone(&root, &dest, &url, cancel, |line| {
+                progress.update(line.percent, Some(line.stage.clone()));
+                let _ = socket.emit("workspace:bootstrapProgress", &json!({
+                    "url": url, "stage": line.stage, "percent": line.percent, "line": line.line
+                }));
+            }).await;
+            drop(progress);
+            if let Err(e) = cloned {
+                error_ack!(ack, &dest, "{}", e);
+            }
+            (dest, Vec::new())
+        }
+    };
+
+    let scan_root = root.clone();
+    crate::pool::spawn(async move { crate::file_index::rescan(&scan_root) });
+    let bootstrapped = json!({ "root": root, "path": path });
+    let seq = crate::event_log::record("workspace:bootstrapped", &bootstrapped);
+    socket.broadcast().emit("workspace:bootstrapped", &(&bootstrapped, seq)).await.ok();
+    ack.send(&json!({ "root": root, "path": path, "files": files, "success": true })).ok();
+}
+
+/// Remove a root and shut down the language servers started for it.
+/// Connections that opened it go back to the primary root.
+pub async fn handle_workspace_remove_root(
+    Data(request): Data<WorkspaceRootRequest>,
+    ack: AckSender,
+    state: State<AppState>,
+) {
+    info!("Received workspace:removeRoot: {:?}", request);
+    let mut timer = EventTimer::start("workspace:removeRoot").with_payload(&request);
+
+    let Some(root) = crate::roots::remove(&request.path) else {
+        error_ack!(ack, &request.path, "Not an added workspace root");
+    };
+    if let Err(e) = crate::watcher::unwatch_root(&root) {
+        error!("Failed to unwatch {}: {}", root.display(), e);
+    }
+
+    let mut sockets_data = timer.lock("socket2data", &state.socket2data).await;
+    for data in sockets_data.values_mut().filter(|data| data.root.as_ref() == Some(&root)) {
+        data.root = None;
+    }
+    drop(sockets_data);
+
+    let mut lsp_manager = timer.lock("lsp_manager", &state.lsp_manager).await;
+    let stopped = lsp_manager.stop_root(&root).await;
+    drop(lsp_manager);
+
+    ack.send(&json!({
+        "root": root, "roots": crate::roots::list(), "stopped": stopped, "success": true
+    })).ok();
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct EventsSinceRequest {
+    /// Last sequence number the client saw, 0 for every kept event
+    #[serde(default)]
+    pub seq: u64,
+}
+
+/// Workspace events broadcast after `seq`, for clients that connected
+/// late or reconnected. With `complete: false` some were already dropped
+/// and the client reloads its tree instead.
+pub async fn handle_events_since(Data(request): Data<EventsSinceRequest>, ack: AckSender) {
+    info!("Received events:since: {:?}", request);
+    let _timer = EventTimer::start("events:since").with_payload(&request);
+
+    let since = crate::event_log::since(request.seq);
+    ack.send(&json!({
+        "events": since.events,
+        "last_seq": since.last_seq,
+        "complete": since.complete,
+        "success": true,
+    })).ok();
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct DiffSinceRequest {
+    /// Sequence number of the workspace event of the point, see events:since
+    #[serde(default)]
+    pub seq: Option<u64>,
+    /// Or the time of the point, in milliseconds since the epoch
+    #[serde(default)]
+    pub time: Option<i64>,
+    /// Diff of this file instead of the list of changed files
+    #[serde(default)]
+    pub file: Option<String>,
+}
+
+/// Files added, removed and modified through the backend since a point of
+/// the session, the start of the session without `seq` and `time`. With
+/// `file` the ack has the content of the file at the point and now and
+/// the changed lines, `diff` is null when it is unchanged.
+pub async fn handle_diff_since(Data(request): Data<DiffSinceRequest>, ack: AckSender) {
+    info!("Received workspace:diffSince: {:?}", request);
+    let _timer = EventTimer::start("workspace:diffSince").with_payload(&request);
+
+    let point = match (request.seq, request.time) {
+        (Some(seq), _) => Point::Seq(seq),
+        (None, Some(time)) => Point::Time(time),
+        (None, None) => Point::Seq(0),
+    };
+
+    let Some(file) = &request.file else {
+        let changes = file_history::changes_since(point);
+        ack.send(&json!({ "files": changes.files, "complete": changes.complete, "success": true })).ok();
+        return;
+    };
+    let abs_path = crate::paths::absolute(Path::new(file)).to_string_lossy().to_string();
+    let diff = file_history::diff_since(point, &abs_path);
+    ack.send(&json!({ "path": abs_path, "diff": diff, "success": true })).ok();
+}
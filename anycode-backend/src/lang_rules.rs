@@ -0,0 +1,198 @@
+use anyhow::Result;
+use globset::{Glob, GlobMatcher};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::utils::relative_to_current_dir;
+
+// Workspace rules choosing the language of files, in
+// .anycode/languages.toml, for projects where the extension says too
+// little. The first rule whose glob matches the path relative to the
+// workspace wins over the detection by extension and the configured
+// types:
+//
+//     [[rule]]
+//     glob = "*.inc"
+//     language = "php"
+//
+//     [[rule]]
+//     glob = "scripts/*"
+//     language = "bash"
+
+const RULES_FILE: &str = "languages.toml";
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LangRule {
+    pub glob: String,
+    pub language: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct LangRules {
+    #[serde(default, rename = "rule")]
+    pub rules: Vec<LangRule>,
+}
+
+/// What decided the language of a file
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LangSource {
+    /// A workspace rule, see `rule`
+    Rule,
+    /// The extension, by detect-lang
+    Detected,
+    /// The `types` of a configured language
+    Config,
+    /// Nothing matched, plain text
+    Default,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Detection {
+    pub lang: String,
+    pub source: LangSource,
+    /// Index of the rule that matched
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule: Option<usize>,
+}
+
+/// Rules with their globs compiled
+struct Compiled {
+    rules: LangRules,
+    globs: Vec<GlobMatcher>,
+}
+
+impl Compiled {
+    fn new(rules: LangRules) -> Result<Self> {
+        let globs = rules.rules.iter()
+            .map(|rule| Ok(Glob::new(&rule.glob)?.compile_matcher()))
+            .collect::<Result<_>>()?;
+        Ok(Self { rules, globs })
+    }
+
+    fn matching(&self, path: &Path) -> Option<usize> {
+        self.globs.iter().position(|glob| glob.is_match(path))
+    }
+}
+
+static RULES: RwLock<Option<Arc<Compiled>>> = RwLock::new(None);
+
+fn rules_file() -> PathBuf {
+    crate::store::workspace_dir().join(RULES_FILE)
+}
+
+fn load(path: &Path) -> Result<LangRules> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => Ok(toml::from_str(&text)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(LangRules::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn compiled() -> Arc<Compiled> {
+    if let Some(compiled) = RULES.read().as_ref() {
+        return compiled.clone();
+    }
+    let path = rules_file();
+    let compiled = load(&path).and_then(Compiled::new).unwrap_or_else(|e| {
+        tracing::error!("Invalid language rules in {}: {}", path.display(), e);
+        Compiled { rules: LangRules::default(), globs: Vec::new() }
+    });
+    let compiled = Arc::new(compiled);
+    *RULES.write() = Some(compiled.clone());
+    compiled
+}
+
+/// The workspace rules, loaded on first use
+pub fn rules() -> LangRules {
+    compiled().rules.clone()
+}
+
+/// Replace the rules and persist them to .anycode/languages.toml. Files
+/// opened before keep their language until they are opened again.
+pub fn set_rules(rules: LangRules) -> Result<LangRules> {
+    let compiled = Compiled::new(rules)?;
+    let path = rules_file();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, toml::to_string(&compiled.rules)?)?;
+
+    let rules = compiled.rules.clone();
+    *RULES.write() = Some(Arc::new(compiled));
+    Ok(rules)
+}
+
+fn detect_with(compiled: &Compiled, path: &str, conf: &Config) -> Detection {
+    let absolute = crate::paths::absolute(Path::new(path));
+    let relative = relative_to_current_dir(&absolute).unwrap_or(absolute);
+    if let Some(index) = compiled.matching(&relative) {
+        let lang = compiled.rules.rules[index].language.clone();
+        return Detection { lang, source: LangSource::Rule, rule: Some(index) };
+    }
+
+    if let Some(lang) = detect_lang::from_path(path) {
+        return Detection { lang: lang.id().to_lowercase(), source: LangSource::Detected, rule: None };
+    }
+    match conf.language.iter().find(|l| l.types.iter().any(|t| path.ends_with(t))) {
+        Some(lang) => Detection { lang: lang.name.clone(), source: LangSource::Config, rule: None },
+        None => Detection { lang: "text".to_string(), source: LangSource::Default, rule: None },
+    }
+}
+
+/// Language of a file and what decided it: the workspace rules, then the
+/// extension, then the configured types
+pub fn detect(path: &str, conf: &Config) -> Detection {
+    detect_with(&compiled(), path, conf)
+}
+
+#[cfg(test)]
+mod lang_rules_tests {
+    use super::*;
+
+    fn config() -> Config {
+        let mut config = Config::default();
+        config.language.push(toml::from_str(r##"
+            name = "nix"
+            types = [".nix"]
+            comment = "#"
+            indent = { width = 2, unit = " " }
+        "##).unwrap());
+        config
+    }
+
+    #[test]
+    fn test_detect() -> Result<()> {
+        let rules: LangRules = toml::from_str(r#"
+            [[rule]]
+            glob = "*.inc"
+            language = "php"
+
+            [[rule]]
+            glob = "legacy/**/*.js"
+            language = "text"
+        "#)?;
+        let compiled = Compiled::new(rules)?;
+        let config = config();
+
+        let detect = |path: &str| {
+            let detection = detect_with(&compiled, path, &config);
+            (detection.lang, detection.source, detection.rule)
+        };
+        assert_eq!(detect("src/header.inc"), ("php".to_string(), LangSource::Rule, Some(0)));
+        assert_eq!(detect("legacy/old/app.js"), ("text".to_string(), LangSource::Rule, Some(1)));
+        assert_eq!(detect("src/app.js"), ("javascript".to_string(), LangSource::Detected, None));
+        assert_eq!(detect("flake.nix"), ("nix".to_string(), LangSource::Config, None));
+        assert_eq!(detect("notes.unknown"), ("text".to_string(), LangSource::Default, None));
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_glob() {
+        let rules = LangRules { rules: vec![LangRule { glob: "a/[".into(), language: "php".into() }] };
+        assert!(Compiled::new(rules).is_err());
+    }
+}
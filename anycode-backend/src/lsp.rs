@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
@@ -17,34 +18,59 @@ use lsp_types::*;
 use lsp_types::notification::*;
 
 use crate::config::Config;
+use crate::lsp_status::{self, LspState};
+use crate::lsp_cache;
+use crate::lsp_scheduler::{self, Scheduler};
+use crate::roots;
 
 pub struct Lsp {
-    lang: String,
+    client: LspClient,
     kill_send: Option<mpsc::Sender<()>>,
-    stdin_send: Option<mpsc::Sender<String>>,
-    next_id: AtomicUsize,
     versions: HashMap<String, AtomicUsize>,
-    pending: Arc<Mutex<HashMap<usize, mpsc::Sender<String>>>>,
-    ready: AtomicBool,
     opened: HashSet<String>,
+    pid: Option<u32>,
+}
+
+/// The request side of a server. Handlers clone it out of the manager and
+/// send requests without holding the manager lock, the scheduler of the
+/// server decides which ones run.
+#[derive(Clone)]
+pub struct LspClient {
+    lang: String,
+    stdin_send: Option<mpsc::Sender<String>>,
+    next_id: Arc<AtomicUsize>,
+    pending: Arc<Mutex<HashMap<usize, mpsc::Sender<String>>>>,
+    ready: Arc<AtomicBool>,
+    scheduler: Arc<Scheduler>,
+    /// What the server answered to initialize
+    capabilities: Arc<std::sync::OnceLock<ServerCapabilities>>,
 }
 
 impl Lsp {
-    pub fn new() -> Self {
+    pub fn new(scheduler: Scheduler) -> Self {
         Self {
-            lang: String::new(),
+            client: LspClient {
+                lang: String::new(),
+                stdin_send: None,
+                next_id: Arc::new(AtomicUsize::new(1)),
+                pending: Arc::new(Mutex::new(HashMap::new())),
+                ready: Arc::new(AtomicBool::new(false)),
+                scheduler: Arc::new(scheduler),
+                capabilities: Arc::new(std::sync::OnceLock::new()),
+            },
             kill_send: None,
-            stdin_send: None,
-            next_id: AtomicUsize::new(1),
             versions: HashMap::new(),
-            pending: Arc::new(Mutex::new(HashMap::new())),
-            ready: AtomicBool::new(false),
             opened: HashSet::new(),
+            pid: None,
         }
     }
 
+    pub fn client(&self) -> LspClient {
+        self.client.clone()
+    }
+
     pub fn start(
-        &mut self, lang: &str, cmd: &str,
+        &mut self, lang: &str, cmd: &str, root: &Path,
         diagnostic_updates: Option<mpsc::Sender<PublishDiagnosticsParams>>
     ) -> io::Result<()> {
 
@@ -52,24 +78,42 @@ impl Lsp {
         let cmd = s[0];
         let args = &s[1..];
 
-        self.lang = lang.to_string();
+        self.client.lang = lang.to_string();
 
         let (kill_send, mut kill_recv) = mpsc::channel::<()>(1);
         self.kill_send = Some(kill_send);
 
         let (stdin_send, mut stdin_recv) = mpsc::channel::<String>(1);
-        self.stdin_send = Some(stdin_send);
+        self.client.stdin_send = Some(stdin_send);
+
+        let cache_dir = lsp_cache::cache_dir(lang);
+        if let Err(e) = std::fs::create_dir_all(&cache_dir) {
+            error!("Failed to create lsp cache dir {}: {}", cache_dir.display(), e);
+        }
 
         // spawn lsp process
         let mut child = Command::new(cmd)
             .args(args)
+            .envs(lsp_cache::env(&cache_dir))
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .spawn()?;
+        self.pid = child.id();
 
         let mut stdin = child.stdin.take().unwrap();
         let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+        let output_channel = format!("lsp:{}", lang);
+
+        // reading from child stderr into the output channel
+        let channel = output_channel.clone();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                crate::output::write(&channel, &line);
+            }
+        });
 
         // reading from channel and write to child stdin
         tokio::spawn(async move {
@@ -82,7 +126,8 @@ impl Lsp {
             }
         });
 
-        let pending = self.pending.clone();
+        let pending = self.client.pending.clone();
+        let replies = self.client.stdin_send.clone();
 
         // reading from child stdout
         tokio::spawn(async move {
@@ -117,6 +162,13 @@ impl Lsp {
 
                 let parsed_json: Value = serde_json::from_str(msg).unwrap();
 
+                if parsed_json.get("method").is_some() && parsed_json.get("id").is_some() {
+                    if let Some(replies) = &replies {
+                        server_request(parsed_json, replies.clone());
+                    }
+                    continue;
+                }
+
                 if let Some(id) = parsed_json["id"].as_u64() { // response
                     let id = id as usize;
                     if let Some(sender) = pending.lock().await.get(&id) {
@@ -126,6 +178,11 @@ impl Lsp {
                 }
 
                 match parsed_json.get("method").and_then(|v| v.as_str()) {
+                    Some("window/logMessage") | Some("window/showMessage") => {
+                        if let Some(message) = parsed_json["params"]["message"].as_str() {
+                            crate::output::write(&output_channel, message);
+                        }
+                    }
                     Some("textDocument/publishDiagnostics") => { // diagnostics
                         let v = parsed_json["params"].clone();
                         if let Ok(params) = serde_json::from_value::<lsp_types::PublishDiagnosticsParams>(v) {
@@ -141,10 +198,18 @@ impl Lsp {
         });
 
         // wait for child end or kill
+        let exit = LspExit { lang: lang.to_string(), root: root.to_path_buf(), pid: self.pid, status: String::new() };
         tokio::spawn(async move {
             tokio::select! {
-                _ = child.wait() => {
+                status = child.wait() => {
                     debug!("lsp process wait done");
+                    let status = match status {
+                        Ok(status) => status.to_string(),
+                        Err(e) => e.to_string(),
+                    };
+                    if let Some(exits) = EXITS.get() {
+                        let _ = exits.send(LspExit { status, ..exit }).await;
+                    }
                 }
                 _ = kill_recv.recv() => {
                     child.kill().await.expect("kill failed");
@@ -158,10 +223,202 @@ impl Lsp {
 
     pub async fn stop(&mut self) {
         if let Some(kill_send) = self.kill_send.take() {
-            kill_send.send(()).await.expect("Failed to send kill signal");
+            // The process may already be gone
+            let _ = kill_send.send(()).await;
+        }
+    }
+
+    pub async fn init(&mut self, dir: &str) {
+        let id = 0;
+        let (tx, rx) = mpsc::channel::<String>(1);
+        self.add_pending(id, tx).await;
+        let options = lsp_cache::initialization_options(&self.lang, &lsp_cache::cache_dir(&self.lang));
+        let message = lsp_messages::initialize(dir, options);
+        self.send_async(message);
+        let response = self.wait(5, rx).await;
+        self.remove_pending(id).await;
+        let capabilities = response
+            .and_then(|r| serde_json::from_str::<Value>(&r).ok())
+            .and_then(|r| serde_json::from_value::<InitializeResult>(r["result"].clone()).ok())
+            .map(|r| r.capabilities);
+        if let Some(capabilities) = capabilities {
+            let _ = self.capabilities.set(capabilities);
         }
+        self.initialized();
+        self.ready.store(true, Ordering::SeqCst)
     }
 
+    /// Process id of the server, for the memory monitor
+    pub fn pid(&self) -> Option<u32> {
+        self.pid
+    }
+
+    pub fn initialized(&mut self) {
+        let params = InitializedParams {};
+        self.send_notification::<Initialized>(params);
+    }
+
+    pub fn did_open(&mut self, lang: &str, path: &str, text: &str) {
+        self.opened.insert(path.to_string());
+
+        let params = DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: crate::paths::file_uri(path).parse().unwrap(),
+                language_id: lang.to_string(),
+                version: 0,
+                text: text.to_string(),
+            },
+        };
+        self.send_notification::<DidOpenTextDocument>(params);
+    }
+
+    pub fn did_close(&mut self, path: &str) {
+        if !self.opened.remove(path) {
+            return;
+        }
+        crate::semantic_tokens::forget(path);
+        let params = DidCloseTextDocumentParams {
+            text_document: TextDocumentIdentifier {
+                uri: crate::paths::file_uri(path).parse().unwrap()
+            },
+        };
+        self.send_notification::<DidCloseTextDocument>(params);
+    }
+
+    /// Follow a document opened in the server to its new path. Servers
+    /// learn about renames as a close of the old uri and an open of the new.
+    pub fn rename_document(&mut self, from: &str, to: &str, text: &str) {
+        if !self.opened.contains(from) {
+            return;
+        }
+        self.did_close(from);
+        self.versions.remove(from);
+        let lang = self.lang.clone();
+        self.did_open(&lang, to, text);
+    }
+
+    pub fn did_save(&mut self, path: &str, text: Option<&str>) {
+        let params = DidSaveTextDocumentParams {
+            text_document: TextDocumentIdentifier {
+                uri: crate::paths::file_uri(path).parse().unwrap()
+            },
+            text: text.map(|s| s.to_string()),
+        };
+        self.send_notification::<DidSaveTextDocument>(params);
+    }
+
+    fn get_next_version(&mut self, path: &str) -> usize {
+        let version = self.versions.entry(path.to_string())
+            .or_insert_with(|| AtomicUsize::new(0));
+
+        version.fetch_add(1, Ordering::SeqCst)
+    }
+
+    pub async fn did_change(
+        &mut self,
+        start_line: usize, start_column: usize,
+        end_line: usize, end_column: usize,
+        path: &str, text: &str,
+    ) {
+        let params = DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier {
+                uri: crate::paths::file_uri(path).parse().unwrap(),
+                version: self.get_next_version(path) as i32,
+            },
+            content_changes: vec![
+                TextDocumentContentChangeEvent {
+                    range: Some(Range {
+                        start: Position::new(start_line as u32, start_column as u32),
+                        end: Position::new(end_line as u32, end_column as u32),
+                    }),
+                    range_length: None,
+                    text: text.to_string(),
+                }
+            ],
+        };
+
+        self.send_notification::<DidChangeTextDocument>(params);
+    }
+}
+
+impl std::ops::Deref for Lsp {
+    type Target = LspClient;
+
+    fn deref(&self) -> &LspClient {
+        &self.client
+    }
+}
+
+/// A `workspace/applyEdit` of a server, `reply` tells whether it was applied
+pub struct ApplyEdit {
+    pub edit: WorkspaceEdit,
+    pub reply: tokio::sync::oneshot::Sender<bool>,
+}
+
+static APPLY_EDITS: std::sync::OnceLock<mpsc::Sender<ApplyEdit>> = std::sync::OnceLock::new();
+
+/// Set where the edits servers ask to apply are sent, unset they are refused
+pub fn set_apply_edits(sender: mpsc::Sender<ApplyEdit>) {
+    let _ = APPLY_EDITS.set(sender);
+}
+
+/// A server process that exited without being stopped, or a running one
+/// to replace, see lsp_restart::restart
+#[derive(Debug, Clone)]
+pub struct LspExit {
+    pub lang: String,
+    pub root: PathBuf,
+    pub pid: Option<u32>,
+    /// Exit status, or why waiting for it failed
+    pub status: String,
+}
+
+static EXITS: std::sync::OnceLock<mpsc::Sender<LspExit>> = std::sync::OnceLock::new();
+
+/// Set where the exits of crashed servers are sent, see lsp_restart
+pub fn set_exits(sender: mpsc::Sender<LspExit>) {
+    let _ = EXITS.set(sender);
+}
+
+/// Answer a request of the server, the reply is written to its stdin
+fn server_request(message: Value, replies: mpsc::Sender<String>) {
+    let id = message["id"].clone();
+    let method = message["method"].as_str().unwrap_or_default().to_string();
+    let params = message["params"].clone();
+
+    tokio::spawn(async move {
+        let result = match method.as_str() {
+            "workspace/applyEdit" => {
+                let applied = match (APPLY_EDITS.get(), serde_json::from_value::<ApplyWorkspaceEditParams>(params)) {
+                    (Some(sender), Ok(params)) => {
+                        let (reply, applied) = tokio::sync::oneshot::channel();
+                        let _ = sender.send(ApplyEdit { edit: params.edit, reply }).await;
+                        applied.await.unwrap_or(false)
+                    }
+                    _ => false,
+                };
+                Ok(serde_json::json!({ "applied": applied }))
+            }
+            // No settings, one null per requested item
+            "workspace/configuration" => {
+                let items = params["items"].as_array().map_or(0, |items| items.len());
+                Ok(Value::Array(vec![Value::Null; items]))
+            }
+            "window/workDoneProgress/create" | "client/registerCapability" | "client/unregisterCapability" => {
+                Ok(Value::Null)
+            }
+            _ => Err(serde_json::json!({ "code": -32601, "message": format!("Unhandled method {}", method) })),
+        };
+
+        let reply = match result {
+            Ok(result) => serde_json::json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(error) => serde_json::json!({ "jsonrpc": "2.0", "id": id, "error": error }),
+        };
+        let _ = replies.send(reply.to_string()).await;
+    });
+}
+
+impl LspClient {
     fn send_async(&self, message: String) {
         if let Some(stdin_send) = &self.stdin_send {
             let stdin_send = stdin_send.clone();
@@ -173,16 +430,16 @@ impl Lsp {
         }
     }
 
-    pub async fn add_pending(&mut self, id: usize, sender: mpsc::Sender<String>) {
+    pub async fn add_pending(&self, id: usize, sender: mpsc::Sender<String>) {
         self.pending.lock().await.insert(id, sender);
     }
 
-    pub async fn remove_pending(&mut self, id: usize) {
+    pub async fn remove_pending(&self, id: usize) {
         self.pending.lock().await.remove(&id);
     }
 
     pub async fn wait(
-        &mut self, timeout: usize, mut rx: mpsc::Receiver<String>
+        &self, timeout: usize, mut rx: mpsc::Receiver<String>
     ) -> Option<String> {
         let timeout = time::sleep(Duration::from_secs(timeout as u64));
         tokio::pin!(timeout);
@@ -193,18 +450,6 @@ impl Lsp {
         }
     }
 
-    pub async fn init(&mut self, dir: &str) {
-        let id = 0;
-        let (tx, rx) = mpsc::channel::<String>(1);
-        self.add_pending(id, tx).await;
-        let message = lsp_messages::initialize(dir);
-        self.send_async(message);
-        self.wait(5, rx).await;
-        self.remove_pending(id).await;
-        self.initialized();
-        self.ready.store(true, Ordering::SeqCst)
-    }
-
     pub fn send_notification<N>(&self, params: N::Params)
     where
         N: lsp_types::notification::Notification,
@@ -220,7 +465,7 @@ impl Lsp {
     }
 
     pub async fn send_request<R>(
-        &mut self, params: R::Params
+        &self, params: R::Params
     ) -> anyhow::Result<R::Result>
     where
         R: lsp_types::request::Request,
@@ -232,6 +477,7 @@ impl Lsp {
         }
 
         let id = self.get_next_id();
+        let _span = crate::profile::span("lsp", R::METHOD);
 
         let msg = serde_json::json!({
             "jsonrpc": "2.0", "id": id,
@@ -239,12 +485,29 @@ impl Lsp {
             "params": serde_json::to_value(params)?,
         });
 
-        let (tx, rx) = mpsc::channel::<String>(1);
+        // Requests of a client may be cancelled while waiting, see lsp_requests
+        let cancel = crate::lsp_requests::current().unwrap_or_default();
+        let _permit = tokio::select! {
+            permit = self.scheduler.acquire(lsp_scheduler::priority_of(R::METHOD)) => permit,
+            _ = cancel.cancelled() => return Err(crate::lsp_requests::RequestCancelled.into()),
+        };
+
+        let (tx, mut rx) = mpsc::channel::<String>(1);
         self.add_pending(id, tx).await;
         self.send_async(msg.to_string());
-        let response = self.wait(3, rx).await;
+
+        let response = tokio::select! {
+            response = rx.recv() => response,
+            _ = time::sleep(Duration::from_secs(3)) => None,
+            _ = cancel.cancelled() => None,
+        };
         self.remove_pending(id).await;
 
+        if cancel.is_cancelled() {
+            self.send_notification::<Cancel>(CancelParams { id: NumberOrString::Number(id as i32) });
+            return Err(crate::lsp_requests::RequestCancelled.into());
+        }
+
         let response_str = response.ok_or_else(||
             anyhow::anyhow!("no response for request {}", R::METHOD))?;
 
@@ -262,96 +525,26 @@ impl Lsp {
         Ok(parsed)
     }
 
-    pub fn is_ready(&mut self) -> bool {
-        self.ready.load(Ordering::SeqCst)
+    pub fn lang(&self) -> &str {
+        &self.lang
     }
 
-    pub fn initialized(&mut self) {
-        let params = InitializedParams {};
-        self.send_notification::<Initialized>(params);
-    }
-
-    pub fn did_open(&mut self, lang: &str, path: &str, text: &str) {
-        self.opened.insert(path.to_string());
-
-        let params = DidOpenTextDocumentParams {
-            text_document: TextDocumentItem {
-                uri: format!("file://{}", path).parse().unwrap(),
-                language_id: lang.to_string(),
-                version: 0,
-                text: text.to_string(),
-            },
-        };
-        self.send_notification::<DidOpenTextDocument>(params);
-    }
-
-    pub fn did_close(&mut self, path: &str) {
-        if !self.opened.remove(path) {
-            return;
-        }
-        let params = DidCloseTextDocumentParams {
-            text_document: TextDocumentIdentifier {
-                uri: format!("file://{}", path).parse().unwrap()
-            },
-        };
-        self.send_notification::<DidCloseTextDocument>(params);
-    }
-
-    pub fn did_save(&mut self, path: &str, text: Option<&str>) {
-        let params = DidSaveTextDocumentParams {
-            text_document: TextDocumentIdentifier {
-                uri: format!("file://{}", path).parse().unwrap()
-            },
-            text: text.map(|s| s.to_string()),
-        };
-        self.send_notification::<DidSaveTextDocument>(params);
-    }
-
-    fn get_next_version(&mut self, path: &str) -> usize {
-        let version = self.versions.entry(path.to_string())
-            .or_insert_with(|| AtomicUsize::new(0));
-
-        version.fetch_add(1, Ordering::SeqCst)
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst)
     }
 
-    fn get_next_id(&mut self, ) -> usize {
+    fn get_next_id(&self) -> usize {
         self.next_id.fetch_add(1, Ordering::SeqCst)
     }
 
-    pub async fn did_change(
-        &mut self,
-        start_line: usize, start_column: usize,
-        end_line: usize, end_column: usize,
-        path: &str, text: &str,
-    ) {
-        let params = DidChangeTextDocumentParams {
-            text_document: VersionedTextDocumentIdentifier {
-                uri: format!("file://{}", path).parse().unwrap(),
-                version: self.get_next_version(path) as i32,
-            },
-            content_changes: vec![
-                TextDocumentContentChangeEvent {
-                    range: Some(Range {
-                        start: Position::new(start_line as u32, start_column as u32),
-                        end: Position::new(end_line as u32, end_column as u32),
-                    }),
-                    range_length: None,
-                    text: text.to_string(),
-                }
-            ],
-        };
-
-        self.send_notification::<DidChangeTextDocument>(params);
-    }
-
     pub async fn completion(
-        &mut self, path: &str, line: usize, character: usize
+        &self, path: &str, line: usize, character: usize
     ) -> anyhow::Result<Vec<CompletionItem>> {
 
         let params = CompletionParams {
             text_document_position: TextDocumentPositionParams {
                 text_document: TextDocumentIdentifier {
-                    uri: format!("file://{}", path).parse().unwrap(),
+                    uri: crate::paths::file_uri(path).parse().unwrap(),
                 },
                 position: Position::new(line as u32, character as u32),
             },
@@ -377,12 +570,12 @@ impl Lsp {
     }
 
     pub async fn definition(
-        &mut self, path: &str, line: usize, character: usize,
+        &self, path: &str, line: usize, character: usize,
     ) -> anyhow::Result<Vec<Location>> {
         let params = lsp_types::GotoDefinitionParams {
             text_document_position_params: TextDocumentPositionParams {
                 text_document: TextDocumentIdentifier {
-                    uri: format!("file://{}", path).parse()?,
+                    uri: crate::paths::file_uri(path).parse()?,
                 },
                 position: Position::new(line as u32, character as u32),
             },
@@ -409,12 +602,12 @@ impl Lsp {
     }
 
     pub async fn references(
-        &mut self, path: &str, line: usize, character: usize,
+        &self, path: &str, line: usize, character: usize,
     ) -> anyhow::Result<Vec<Location>> {
         let params = ReferenceParams {
             text_document_position: TextDocumentPositionParams {
                 text_document: TextDocumentIdentifier {
-                    uri: format!("file://{}", path).parse()?,
+                    uri: crate::paths::file_uri(path).parse()?,
                 },
                 position: Position::new(line as u32, character as u32),
             },
@@ -433,14 +626,249 @@ impl Lsp {
         Ok(response)
     }
 
+    /// Edits of renaming the symbol at the position, None when the server
+    /// finds nothing to rename there
+    pub async fn rename(
+        &self, path: &str, line: usize, character: usize, new_name: &str,
+    ) -> anyhow::Result<Option<WorkspaceEdit>> {
+        let params = RenameParams {
+            text_document_position: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier {
+                    uri: crate::paths::file_uri(path).parse()?,
+                },
+                position: Position::new(line as u32, character as u32),
+            },
+            new_name: new_name.to_string(),
+            work_done_progress_params: Default::default(),
+        };
+
+        self.send_request::<lsp_types::request::Rename>(params).await
+    }
+
+    pub async fn formatting(&self, path: &str, options: FormattingOptions) -> anyhow::Result<Vec<TextEdit>> {
+        let params = DocumentFormattingParams {
+            text_document: TextDocumentIdentifier {
+                uri: crate::paths::file_uri(path).parse()?,
+            },
+            options,
+            work_done_progress_params: Default::default(),
+        };
+
+        let edits = self.send_request::<lsp_types::request::Formatting>(params).await?;
+        Ok(edits.unwrap_or_default())
+    }
+
+    pub async fn range_formatting(
+        &self, path: &str, range: Range, options: FormattingOptions,
+    ) -> anyhow::Result<Vec<TextEdit>> {
+        let params = DocumentRangeFormattingParams {
+            text_document: TextDocumentIdentifier {
+                uri: crate::paths::file_uri(path).parse()?,
+            },
+            range,
+            options,
+            work_done_progress_params: Default::default(),
+        };
+
+        let edits = self.send_request::<lsp_types::request::RangeFormatting>(params).await?;
+        Ok(edits.unwrap_or_default())
+    }
+
+    /// Code actions for a range, `diagnostics` are the ones of the range
+    pub async fn code_actions(
+        &self, path: &str, range: Range, diagnostics: Vec<Diagnostic>, only: Option<Vec<CodeActionKind>>,
+    ) -> anyhow::Result<Vec<CodeActionOrCommand>> {
+        let params = CodeActionParams {
+            text_document: TextDocumentIdentifier {
+                uri: crate::paths::file_uri(path).parse()?,
+            },
+            range,
+            context: CodeActionContext { diagnostics, only, trigger_kind: Some(CodeActionTriggerKind::INVOKED) },
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+
+        let actions = self.send_request::<lsp_types::request::CodeActionRequest>(params).await?;
+        Ok(actions.unwrap_or_default())
+    }
+
+    /// Fill in the edit of a code action listed without it
+    pub async fn resolve_code_action(&self, action: CodeAction) -> anyhow::Result<CodeAction> {
+        self.send_request::<lsp_types::request::CodeActionResolveRequest>(action).await
+    }
+
+    /// Run a server command, the server applies its edits with
+    /// `workspace/applyEdit`
+    pub async fn execute_command(&self, command: lsp_types::Command) -> anyhow::Result<Option<Value>> {
+        let params = ExecuteCommandParams {
+            command: command.command,
+            arguments: command.arguments.unwrap_or_default(),
+            work_done_progress_params: Default::default(),
+        };
+
+        self.send_request::<lsp_types::request::ExecuteCommand>(params).await
+    }
+
+    pub async fn workspace_symbols(&self, query: &str) -> anyhow::Result<Vec<WorkspaceSymbol>> {
+        let params = WorkspaceSymbolParams {
+            query: query.to_string(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+
+        let response = self
+            .send_request::<lsp_types::request::WorkspaceSymbolRequest>(params)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Workspace symbols returned None"))?;
+
+        let symbols = match response {
+            WorkspaceSymbolResponse::Nested(symbols) => symbols,
+            WorkspaceSymbolResponse::Flat(symbols) => symbols.into_iter()
+                .map(|s| WorkspaceSymbol {
+                    name: s.name,
+                    kind: s.kind,
+                    tags: s.tags,
+                    container_name: s.container_name,
+                    location: OneOf::Left(s.location),
+                    data: None,
+                })
+                .collect(),
+        };
+
+        Ok(symbols)
+    }
+
+    async fn document_symbol_response(&self, uri: &Uri) -> anyhow::Result<DocumentSymbolResponse> {
+        let params = DocumentSymbolParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+
+        self.send_request::<lsp_types::request::DocumentSymbolRequest>(params)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Document symbols returned None"))
+    }
+
+    /// Symbols of one document as a tree, for the outline
+    pub async fn outline(&self, path: &str) -> anyhow::Result<Vec<crate::outline::OutlineSymbol>> {
+        let uri: Uri = crate::paths::file_uri(path).parse()?;
+        Ok(match self.document_symbol_response(&uri).await? {
+            DocumentSymbolResponse::Flat(flat) => crate::outline::from_flat(flat),
+            DocumentSymbolResponse::Nested(nested) => crate::outline::from_nested(nested),
+        })
+    }
+
+    /// Symbols of one document, flattened into the shape of workspace
+    /// symbols with the parent symbol as container
+    pub async fn document_symbols(&self, path: &str) -> anyhow::Result<Vec<WorkspaceSymbol>> {
+        let uri: Uri = crate::paths::file_uri(path).parse()?;
+        let response = self.document_symbol_response(&uri).await?;
+
+        let mut symbols = Vec::new();
+        match response {
+            DocumentSymbolResponse::Flat(flat) => {
+                for s in flat {
+                    symbols.push(WorkspaceSymbol {
+                        name: s.name,
+                        kind: s.kind,
+                        tags: s.tags,
+                        container_name: s.container_name,
+                        location: OneOf::Left(s.location),
+                        data: None,
+                    });
+                }
+            }
+            DocumentSymbolResponse::Nested(nested) => {
+                let mut stack: Vec<(DocumentSymbol, Option<String>)> =
+                    nested.into_iter().map(|s| (s, None)).collect();
+                while let Some((s, container_name)) = stack.pop() {
+                    for child in s.children.into_iter().flatten() {
+                        stack.push((child, Some(s.name.clone())));
+                    }
+                    symbols.push(WorkspaceSymbol {
+                        name: s.name,
+                        kind: s.kind,
+                        tags: s.tags,
+                        container_name,
+                        location: OneOf::Left(Location { uri: uri.clone(), range: s.selection_range }),
+                        data: None,
+                    });
+                }
+            }
+        }
+
+        Ok(symbols)
+    }
+
+    /// Characters that open (trigger) and update (retrigger) parameter
+    /// hints, as the server announced them
+    pub fn signature_help_triggers(&self) -> (Vec<String>, Vec<String>) {
+        let provider = self.capabilities.get().and_then(|c| c.signature_help_provider.as_ref());
+        (
+            provider.and_then(|p| p.trigger_characters.clone()).unwrap_or_default(),
+            provider.and_then(|p| p.retrigger_characters.clone()).unwrap_or_default(),
+        )
+    }
+
+    /// Legend of the server's semantic tokens and whether it answers
+    /// deltas, None when it has no semantic tokens
+    pub fn semantic_tokens_legend(&self) -> Option<(SemanticTokensLegend, bool)> {
+        let options = match self.capabilities.get()?.semantic_tokens_provider.as_ref()? {
+            SemanticTokensServerCapabilities::SemanticTokensOptions(o) => o,
+            SemanticTokensServerCapabilities::SemanticTokensRegistrationOptions(r) => &r.semantic_tokens_options,
+        };
+        let delta = matches!(options.full, Some(SemanticTokensFullOptions::Delta { delta: Some(true) }));
+        Some((options.legend.clone(), delta))
+    }
+
+    pub async fn semantic_tokens_full(&self, path: &str) -> anyhow::Result<Option<SemanticTokensResult>> {
+        let params = SemanticTokensParams {
+            text_document: TextDocumentIdentifier { uri: crate::paths::file_uri(path).parse()? },
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        self.send_request::<lsp_types::request::SemanticTokensFullRequest>(params).await
+    }
+
+    /// Tokens changed since the answer with `previous_result_id`
+    pub async fn semantic_tokens_delta(
+        &self, path: &str, previous_result_id: &str,
+    ) -> anyhow::Result<Option<SemanticTokensFullDeltaResult>> {
+        let params = SemanticTokensDeltaParams {
+            text_document: TextDocumentIdentifier { uri: crate::paths::file_uri(path).parse()? },
+            previous_result_id: previous_result_id.to_string(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        self.send_request::<lsp_types::request::SemanticTokensFullDeltaRequest>(params).await
+    }
+
+    pub async fn signature_help(
+        &self, path: &str, line: usize, character: usize, context: Option<SignatureHelpContext>,
+    ) -> anyhow::Result<Option<SignatureHelp>> {
+        let params = SignatureHelpParams {
+            context,
+            text_document_position_params: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier {
+                    uri: crate::paths::file_uri(path).parse()?,
+                },
+                position: Position::new(line as u32, character as u32),
+            },
+            work_done_progress_params: Default::default(),
+        };
+
+        self.send_request::<lsp_types::request::SignatureHelpRequest>(params).await
+    }
+
     pub async fn hover(
-        &mut self, path: &str, line: usize, character: usize,
+        &self, path: &str, line: usize, character: usize,
     ) -> anyhow::Result<Hover> {
 
         let params = HoverParams {
             text_document_position_params: TextDocumentPositionParams {
                 text_document: TextDocumentIdentifier {
-                    uri: format!("file://{}", path).parse()?,
+                    uri: crate::paths::file_uri(path).parse()?,
                 },
                 position: Position::new(line as u32, character as u32),
             },
@@ -466,12 +894,12 @@ mod tests {
     async fn test_lsp_minimal() -> anyhow::Result<()> {
         let lang = "python";
 
-        let mut lsp = Lsp::new();
-        lsp.start(lang, "pyright-langserver --stdio", None)?;
-
         let dir = std::env::current_dir().unwrap()
             .to_string_lossy().into_owned();
 
+        let mut lsp = Lsp::new(Scheduler::new(None));
+        lsp.start(lang, "pyright-langserver --stdio", Path::new(&dir), None)?;
+
         lsp.init(&dir).await;
 
         let content = r#"for i in range(10000): print(i)"#;
@@ -524,8 +952,8 @@ pub mod lsp_messages {
         pub error: Option<Value>,
     }
 
-    pub fn initialize(dir: &str) -> String {
-        let uri: Uri = format!("file://{}", dir).parse().unwrap();
+    pub fn initialize(dir: &str, initialization_options: Option<Value>) -> String {
+        let uri: Uri = crate::paths::file_uri(dir).parse().unwrap();
 
         let workspace_folders = Some(vec![
             WorkspaceFolder {
@@ -546,6 +974,10 @@ pub mod lsp_messages {
                     ..Default::default()
                 }),
                 synchronization: Some(Default::default()),
+                document_symbol: Some(lsp_types::DocumentSymbolClientCapabilities {
+                    hierarchical_document_symbol_support: Some(true),
+                    ..Default::default()
+                }),
                 signature_help: Some(lsp_types::SignatureHelpClientCapabilities {
                     signature_information: Some(lsp_types::SignatureInformationSettings {
                         documentation_format: Some(vec![lsp_types::MarkupKind::PlainText]),
@@ -562,6 +994,52 @@ pub mod lsp_messages {
                     }),
                     ..Default::default()
                 }),
+                code_action: Some(lsp_types::CodeActionClientCapabilities {
+                    code_action_literal_support: Some(lsp_types::CodeActionLiteralSupport {
+                        code_action_kind: lsp_types::CodeActionKindLiteralSupport {
+                            value_set: [
+                                CodeActionKind::EMPTY,
+                                CodeActionKind::QUICKFIX,
+                                CodeActionKind::REFACTOR,
+                                CodeActionKind::REFACTOR_EXTRACT,
+                                CodeActionKind::REFACTOR_INLINE,
+                                CodeActionKind::REFACTOR_REWRITE,
+                                CodeActionKind::SOURCE,
+                                CodeActionKind::SOURCE_ORGANIZE_IMPORTS,
+                            ].iter().map(|kind| kind.as_str().to_string()).collect(),
+                        },
+                    }),
+                    is_preferred_support: Some(true),
+                    disabled_support: Some(true),
+                    data_support: Some(true),
+                    // Servers may leave the edit out of the list, see resolve_code_action
+                    resolve_support: Some(lsp_types::CodeActionCapabilityResolveSupport {
+                        properties: vec!["edit".to_string()],
+                    }),
+                    ..Default::default()
+                }),
+                formatting: Some(lsp_types::DocumentFormattingClientCapabilities {
+                    dynamic_registration: Some(false),
+                }),
+                range_formatting: Some(lsp_types::DocumentRangeFormattingClientCapabilities {
+                    dynamic_registration: Some(false),
+                }),
+                rename: Some(lsp_types::RenameClientCapabilities {
+                    prepare_support: Some(false),
+                    ..Default::default()
+                }),
+                semantic_tokens: Some(lsp_types::SemanticTokensClientCapabilities {
+                    requests: lsp_types::SemanticTokensClientCapabilitiesRequests {
+                        range: Some(false),
+                        full: Some(lsp_types::SemanticTokensFullOptions::Delta { delta: Some(true) }),
+                    },
+                    token_types: crate::semantic_tokens::TOKEN_TYPES.to_vec(),
+                    token_modifiers: crate::semantic_tokens::TOKEN_MODIFIERS.to_vec(),
+                    formats: vec![lsp_types::TokenFormat::RELATIVE],
+                    overlapping_token_support: Some(false),
+                    multiline_token_support: Some(false),
+                    ..Default::default()
+                }),
                 publish_diagnostics: Some(lsp_types::PublishDiagnosticsClientCapabilities {
                     related_information: Some(false),
                     version_support: Some(false),
@@ -571,6 +1049,18 @@ pub mod lsp_messages {
                 }),
                 ..Default::default()
             }),
+            workspace: Some(lsp_types::WorkspaceClientCapabilities {
+                // Text edits only, file creates and renames are not applied
+                workspace_edit: Some(lsp_types::WorkspaceEditClientCapabilities {
+                    document_changes: Some(true),
+                    ..Default::default()
+                }),
+                apply_edit: Some(true),
+                execute_command: Some(lsp_types::DynamicRegistrationClientCapabilities {
+                    dynamic_registration: Some(false),
+                }),
+                ..Default::default()
+            }),
             ..Default::default()
         };
 
@@ -580,6 +1070,7 @@ pub mod lsp_messages {
             root_uri: Some(uri),
             capabilities,
             workspace_folders,
+            initialization_options,
             client_info: Some(ClientInfo {
                 name: "anycode".to_string(),
                 version: Some("1.0.0".to_string()),
@@ -600,16 +1091,20 @@ pub mod lsp_messages {
 
 pub struct LspManager {
     config: Config,
-    lang2lsp: HashMap<String,Lsp>,
+    /// Servers by language and workspace root
+    servers: HashMap<(String, PathBuf), Lsp>,
     diagnostics_sender: Option<mpsc::Sender<PublishDiagnosticsParams>>,
+    /// Languages switched to the fallback providers, no server is started
+    degraded: HashSet<String>,
 }
 
 impl LspManager {
     pub fn new(config: Config) -> Self {
         Self {
             config,
-            lang2lsp: HashMap::new(),
+            servers: HashMap::new(),
             diagnostics_sender: None,
+            degraded: HashSet::new(),
         }
     }
 
@@ -617,39 +1112,205 @@ impl LspManager {
         self.diagnostics_sender = Some(d);
     }
 
+    /// Server of the language for the primary workspace root
     pub async fn get(&mut self, lang: &str) -> Option<&mut Lsp> {
+        self.get_in(lang, roots::primary()).await
+    }
+
+    /// Server of the language for the root containing the document
+    pub async fn get_for(&mut self, lang: &str, path: &str) -> Option<&mut Lsp> {
+        self.get_in(lang, roots::root_of(Path::new(path))).await
+    }
+
+    async fn get_in(&mut self, lang: &str, root: PathBuf) -> Option<&mut Lsp> {
+        if self.degraded.contains(lang) {
+            return None;
+        }
 
         let lang_conf = self.config.language.iter().find(|lang_conf| lang_conf.name == lang)?;
         let cmd = lang_conf.clone().lsp?.join(" ");
 
-        if !self.lang2lsp.contains_key(lang) {
-           self.init_new(lang.to_string(), &cmd).await;
+        let key = (lang.to_string(), root);
+        if !self.servers.contains_key(&key) {
+           self.init_new(lang.to_string(), &cmd, &key.1).await;
+        }
+
+        self.servers.get_mut(&key)
+    }
+
+    /// Language servers that are already running, none are started
+    pub fn running(&mut self) -> impl Iterator<Item = &mut Lsp> {
+        self.servers.values_mut()
+    }
+
+    /// The running server of the language for the root containing the
+    /// document, none is started
+    pub fn running_for(&mut self, lang: &str, path: &str) -> Option<&mut Lsp> {
+        let key = (lang.to_string(), roots::root_of(Path::new(path)));
+        self.servers.get_mut(&key)
+    }
+
+    /// Stop the servers of a language in all roots, the next `get` starts
+    /// a new one
+    pub async fn stop(&mut self, lang: &str) -> bool {
+        let stopped = self.remove_where(|l, _| l == lang).await;
+        for (lang, root) in &stopped {
+            lsp_status::set(lang, root, LspState::Stopped, None);
+        }
+        !stopped.is_empty()
+    }
+
+    /// Stop the servers of a removed workspace root, returns their languages
+    pub async fn stop_root(&mut self, root: &Path) -> Vec<String> {
+        let stopped = self.remove_where(|_, r| r == root).await;
+        for (lang, root) in &stopped {
+            lsp_status::set(lang, root, LspState::Stopped, None);
+        }
+        lsp_status::forget_root(root);
+        stopped.into_iter().map(|(lang, _)| lang).collect()
+    }
+
+    async fn remove_where(&mut self, matches: impl Fn(&str, &Path) -> bool) -> Vec<(String, PathBuf)> {
+        let keys: Vec<(String, PathBuf)> = self.servers.keys()
+            .filter(|(lang, root)| matches(lang, root))
+            .cloned()
+            .collect();
+
+        let mut stopped = Vec::new();
+        for key in keys {
+            if let Some(mut lsp) = self.servers.remove(&key) {
+                lsp.stop().await;
+                stopped.push(key);
+            }
+        }
+        stopped
+    }
+
+    /// Stop the servers and answer the requests of the language with the
+    /// fallback providers (word completion, text references) until restored
+    pub async fn degrade(&mut self, lang: &str) {
+        self.remove_where(|l, _| l == lang).await;
+        self.degraded.insert(lang.to_string());
+        for root in roots::list() {
+            lsp_status::set(lang, &root, LspState::Degraded, None);
+        }
+    }
+
+    /// Let the language start its server again, returns false if it was
+    /// not degraded
+    pub fn restore(&mut self, lang: &str) -> bool {
+        let restored = self.degraded.remove(lang);
+        if restored {
+            for root in roots::list() {
+                lsp_status::set(lang, &root, LspState::Stopped, None);
+            }
+        }
+        restored
+    }
+
+    /// The server an exit is about, if it was not stopped or replaced
+    /// since: the running one still has the pid of the exited process
+    fn exited(&self, exit: &LspExit) -> Option<(String, PathBuf)> {
+        let key = (exit.lang.clone(), exit.root.clone());
+        let lsp = self.servers.get(&key)?;
+        (lsp.pid() == exit.pid).then_some(key)
+    }
+
+    /// The start of a server in place of the crashed one of an exit, run
+    /// without holding the manager. None when it was stopped or replaced
+    /// meanwhile or its language is degraded.
+    pub fn restart(&self, exit: &LspExit) -> Option<Launch> {
+        self.exited(exit)?;
+        if self.degraded.contains(&exit.lang) {
+            return None;
+        }
+        let lang_conf = self.config.language.iter().find(|lang_conf| lang_conf.name == exit.lang)?;
+        let cmd = lang_conf.clone().lsp?.join(" ");
+        Some(self.launch(exit.lang.clone(), &cmd, &exit.root))
+    }
+
+    /// Put the restarted server of an exit in place of the crashed one,
+    /// which is stopped in case it still runs. A server stopped or replaced
+    /// while the new one started is not brought back, the new one is
+    /// stopped instead.
+    pub async fn replace(&mut self, exit: &LspExit, mut lsp: Lsp) -> Option<&mut Lsp> {
+        let Some(key) = self.exited(exit) else {
+            lsp.stop().await;
+            return None;
+        };
+        if let Some(mut old) = self.servers.insert(key.clone(), lsp) {
+            old.stop().await;
+        }
+        self.servers.get_mut(&key)
+    }
+
+    /// Drop the crashed server of an exit without replacing it, the next
+    /// `get` starts a new one
+    pub fn forget(&mut self, exit: &LspExit) {
+        if let Some(key) = self.exited(exit) {
+            self.servers.remove(&key);
+        }
+    }
+
+    /// Language, root and pid of the running servers
+    pub fn running_pids(&self) -> Vec<(String, PathBuf, u32)> {
+        self.servers.iter()
+            .filter_map(|((lang, root), lsp)| Some((lang.clone(), root.clone(), lsp.pid()?)))
+            .collect()
+    }
+
+    pub fn running_langs(&self) -> Vec<String> {
+        let mut langs: Vec<String> = self.servers.keys().map(|(lang, _)| lang.clone()).collect();
+        langs.sort();
+        langs.dedup();
+        langs
+    }
+
+    pub async fn init_new(&mut self, lang: String, lsp_cmd: &str, root: &Path) {
+        if let Some(lsp) = self.launch(lang.clone(), lsp_cmd, root).start().await {
+            self.servers.insert((lang, root.to_path_buf()), lsp);
         }
+    }
 
-        self.lang2lsp.get_mut(lang)
+    fn launch(&self, lang: String, cmd: &str, root: &Path) -> Launch {
+        Launch {
+            lsp: Lsp::new(Scheduler::new(self.config.lsp_scheduler.as_ref())),
+            lang,
+            cmd: cmd.to_string(),
+            root: root.to_path_buf(),
+            diagnostics: self.diagnostics_sender.clone(),
+        }
     }
+}
+
+/// A server about to be started
+pub struct Launch {
+    lsp: Lsp,
+    lang: String,
+    cmd: String,
+    root: PathBuf,
+    diagnostics: Option<mpsc::Sender<PublishDiagnosticsParams>>,
+}
 
-    pub async fn init_new(&mut self, lang: String, lsp_cmd: &str) {
-        let mut lsp = Lsp::new();
-        let diagnostic_send = self.diagnostics_sender.as_mut().map(|s|s.clone());
-        let result = lsp.start(&lang, &lsp_cmd, diagnostic_send);
+impl Launch {
+    /// Start and initialize the server, None when it failed to start
+    pub async fn start(self) -> Option<Lsp> {
+        let Launch { mut lsp, lang, cmd, root, diagnostics } = self;
+        lsp_status::set(&lang, &root, LspState::Starting, None);
 
-        match result {
+        match lsp.start(&lang, &cmd, &root, diagnostics) {
             Ok(_) => {
-                info!("lsp process started {}", &lsp_cmd);
+                info!("lsp process started {} for {}", &cmd, root.display());
             },
             Err(e) => {
-                error!("error starting lsp process {}: {}", &lsp_cmd, e.to_string());
-                // panic!("error starting lsp process {}", e.to_string());
-                return;
+                error!("error starting lsp process {}: {}", &cmd, e.to_string());
+                lsp_status::set(&lang, &root, LspState::Failed, Some(e.to_string()));
+                return None;
             },
         }
 
-        let dir = std::env::current_dir().unwrap()
-            .to_string_lossy().into_owned();
-
-        lsp.init(&dir).await;
-
-        self.lang2lsp.insert(lang, lsp);
+        lsp.init(&root.to_string_lossy()).await;
+        lsp_status::set(&lang, &root, LspState::Ready, None);
+        Some(lsp)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,101 @@
+use anyhow::{bail, Result};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+
+// Stable per-workspace cache directories for language servers under
+// .anycode/lsp-cache/<lang>, so indexes and build artifacts survive
+// restarts and can be dropped from the UI.
+
+const LSP_CACHE_DIR: &str = "lsp-cache";
+
+pub fn root() -> PathBuf {
+    crate::store::workspace_dir().join(LSP_CACHE_DIR)
+}
+
+pub fn cache_dir(lang: &str) -> PathBuf {
+    root().join(lang)
+}
+
+/// Environment of the server process. Servers using the platform cache
+/// dir (gopls, typescript) follow XDG_CACHE_HOME.
+pub fn env(dir: &Path) -> Vec<(&'static str, PathBuf)> {
+    vec![("XDG_CACHE_HOME", dir.join("xdg"))]
+}
+
+/// initializationOptions pointing servers with their own setting at the
+/// cache dir, rust-analyzer checks into a separate target dir instead of
+/// fighting over ./target with the user's cargo builds.
+pub fn initialization_options(lang: &str, dir: &Path) -> Option<Value> {
+    match lang {
+        "rust" => Some(json!({ "cargo": { "targetDir": dir.join("target") } })),
+        _ => None,
+    }
+}
+
+fn dir_size(path: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(path) else { return 0 };
+    entries.flatten()
+        .map(|entry| match entry.file_type() {
+            Ok(t) if t.is_dir() => dir_size(&entry.path()),
+            _ => entry.metadata().map(|m| m.len()).unwrap_or(0),
+        })
+        .sum()
+}
+
+/// Remove the cache of one language or all of them, returns the freed bytes.
+/// The servers using it have to be stopped first.
+pub fn clear(lang: Option<&str>) -> Result<u64> {
+    clear_in(&root(), lang)
+}
+
+fn clear_in(root: &Path, lang: Option<&str>) -> Result<u64> {
+    let dir = match lang {
+        // Only the cache dirs themselves, never a path out of the cache
+        Some(lang) if Path::new(lang).file_name().is_none_or(|name| name != lang) => {
+            bail!("{} is not a language name", lang)
+        }
+        Some(lang) => root.join(lang),
+        None => root.to_path_buf(),
+    };
+    if !dir.exists() {
+        return Ok(0);
+    }
+
+    let size = dir_size(&dir);
+    std::fs::remove_dir_all(&dir)?;
+    Ok(size)
+}
+
+#[cfg(test)]
+mod lsp_cache_tests {
+    use super::*;
+
+    #[test]
+    fn test_clear_cache() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        std::fs::create_dir_all(root.path().join("rust/target"))?;
+        std::fs::write(root.path().join("rust/target/a"), [0u8; 100])?;
+        std::fs::create_dir_all(root.path().join("go"))?;
+        std::fs::write(root.path().join("go/b"), [0u8; 10])?;
+
+        assert_eq!(clear_in(root.path(), Some("python"))?, 0);
+        assert!(clear_in(root.path(), Some("../..")).is_err());
+        assert!(clear_in(root.path(), Some("rust/target")).is_err());
+        assert!(clear_in(root.path(), Some("")).is_err());
+        assert_eq!(clear_in(root.path(), Some("rust"))?, 100);
+        assert!(!root.path().join("rust").exists());
+        assert!(root.path().join("go").exists());
+
+        assert_eq!(clear_in(root.path(), None)?, 10);
+        assert!(!root.path().exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_initialization_options() {
+        let dir = Path::new("/w/.anycode/lsp-cache/rust");
+        let options = initialization_options("rust", dir).unwrap();
+        assert_eq!(options["cargo"]["targetDir"], json!(dir.join("target")));
+        assert!(initialization_options("python", dir).is_none());
+    }
+}
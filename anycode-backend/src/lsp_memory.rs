@@ -0,0 +1,137 @@
+use serde::Serialize;
+use socketioxide::SocketIo;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use sysinfo::{ProcessRefreshKind, ProcessesToUpdate, System};
+use tracing::info;
+
+use crate::app_state::AppState;
+use crate::config::{LspMemoryAction, LspMemoryConfig};
+use crate::lsp::LspExit;
+
+const DEFAULT_INTERVAL_SECS: u64 = 30;
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct MemoryPressure {
+    pub lang: String,
+    /// Resident memory of the server and its child processes in bytes
+    pub rss: u64,
+    pub budget: u64,
+    pub action: LspMemoryAction,
+}
+
+/// Memory of a process and all its descendants. `processes` are
+/// (pid, parent, memory) of every process of the machine.
+pub fn tree_memory(processes: &[(u32, Option<u32>, u64)], root: u32) -> u64 {
+    let mut children: HashMap<u32, Vec<(u32, u64)>> = HashMap::new();
+    let mut memory = 0;
+    for &(pid, parent, mem) in processes {
+        if pid == root {
+            memory = mem;
+        }
+        if let Some(parent) = parent {
+            children.entry(parent).or_default().push((pid, mem));
+        }
+    }
+
+    let mut stack = vec![root];
+    let mut seen = HashSet::from([root]);
+    while let Some(pid) = stack.pop() {
+        for &(child, mem) in children.get(&pid).into_iter().flatten() {
+            if seen.insert(child) {
+                memory += mem;
+                stack.push(child);
+            }
+        }
+    }
+    memory
+}
+
+fn process_table() -> Vec<(u32, Option<u32>, u64)> {
+    let mut system = System::new();
+    system.refresh_processes_specifics(
+        ProcessesToUpdate::All, true, ProcessRefreshKind::nothing().with_memory(),
+    );
+    system.processes().iter()
+        .map(|(pid, p)| (pid.as_u32(), p.parent().map(|p| p.as_u32()), p.memory()))
+        .collect()
+}
+
+/// Check the language servers against the budget every interval. Servers
+/// over it get one `lsp:memoryPressure` and the configured action, a
+/// warned server is warned again only after going back under the budget.
+pub async fn monitor(config: LspMemoryConfig, state: AppState, io: Arc<SocketIo>) {
+    let budget = config.budget_mb * 1024 * 1024;
+    let interval = Duration::from_secs(config.interval_secs.unwrap_or(DEFAULT_INTERVAL_SECS));
+    let mut warned: HashSet<(String, PathBuf)> = HashSet::new();
+
+    loop {
+        tokio::time::sleep(interval).await;
+
+        let servers = state.lsp_manager.lock().await.running_pids();
+        if servers.is_empty() {
+            warned.clear();
+            continue;
+        }
+
+        let processes = match crate::pool::spawn(async { process_table() }).await {
+            Ok(processes) => processes,
+            Err(_) => continue,
+        };
+
+        for (lang, root, pid) in servers {
+            let rss = tree_memory(&processes, pid);
+            let key = (lang.clone(), root.clone());
+            if rss <= budget {
+                warned.remove(&key);
+                continue;
+            }
+            if !warned.insert(key.clone()) {
+                continue;
+            }
+
+            let pressure = MemoryPressure { lang: lang.clone(), rss, budget, action: config.action };
+            info!("Language server over its memory budget: {:?}", pressure);
+            crate::output::write("lsp", &format!(
+                "{} uses {} MB, budget {} MB: {:?}", lang, rss / 1024 / 1024, config.budget_mb, config.action
+            ));
+            let _ = io.emit("lsp:memoryPressure", &pressure).await;
+
+            match config.action {
+                LspMemoryAction::Warn => {}
+                LspMemoryAction::Restart => {
+                    // Replaced like a crashed server, started without the locks
+                    let exit = LspExit { lang, root, pid: Some(pid), status: "Over its memory budget".to_string() };
+                    crate::lsp_restart::restart(&state, &exit).await;
+                    warned.remove(&key);
+                }
+                LspMemoryAction::Fallback => {
+                    state.lsp_manager.lock().await.degrade(&lang).await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod lsp_memory_tests {
+    use super::*;
+
+    #[test]
+    fn test_tree_memory() {
+        let processes = [
+            (1, None, 10),
+            (100, Some(1), 500),
+            // Children and grandchildren of the server
+            (101, Some(100), 200),
+            (102, Some(101), 50),
+            (200, Some(1), 1000),
+        ];
+
+        assert_eq!(tree_memory(&processes, 100), 750);
+        assert_eq!(tree_memory(&processes, 102), 50);
+        assert_eq!(tree_memory(&processes, 999), 0);
+    }
+}
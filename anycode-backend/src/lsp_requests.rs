@@ -0,0 +1,150 @@
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio_util::sync::CancellationToken;
+
+// Language server requests of the clients that can be cancelled. Clients
+// tag a request with their own id, `lsp:cancel` with that id or a newer
+// request of the same kind from the same socket cancels it. The token
+// reaches `Lsp::send_request` through a task local, which sends
+// `$/cancelRequest` to the server and drops the late answer.
+
+tokio::task_local! {
+    static CANCEL: CancellationToken;
+}
+
+/// Error of a request cancelled by the client or superseded
+#[derive(Debug)]
+pub struct RequestCancelled;
+
+impl std::fmt::Display for RequestCancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Request cancelled")
+    }
+}
+
+impl std::error::Error for RequestCancelled {}
+
+struct Entry {
+    seq: u64,
+    kind: &'static str,
+    id: u64,
+    token: CancellationToken,
+}
+
+static REQUESTS: OnceLock<Mutex<HashMap<String, Vec<Entry>>>> = OnceLock::new();
+static NEXT_SEQ: AtomicU64 = AtomicU64::new(1);
+
+fn requests() -> &'static Mutex<HashMap<String, Vec<Entry>>> {
+    REQUESTS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// A request in flight, unregistered when dropped
+pub struct Request {
+    socket: String,
+    seq: u64,
+    token: CancellationToken,
+}
+
+impl Request {
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Run the language server call with the token of the request
+    pub async fn scope<F: Future>(&self, future: F) -> F::Output {
+        CANCEL.scope(self.token.clone(), future).await
+    }
+}
+
+impl Drop for Request {
+    fn drop(&mut self) {
+        let mut requests = requests().lock();
+        if let Some(entries) = requests.get_mut(&self.socket) {
+            entries.retain(|e| e.seq != self.seq);
+            if entries.is_empty() {
+                requests.remove(&self.socket);
+            }
+        }
+    }
+}
+
+/// Register a request of a socket. Requests without a client id can not
+/// be cancelled and do not supersede anything.
+pub fn begin(socket: &str, kind: &'static str, id: Option<u64>) -> Request {
+    let seq = NEXT_SEQ.fetch_add(1, Ordering::Relaxed);
+    let token = CancellationToken::new();
+    let request = Request { socket: socket.to_string(), seq, token: token.clone() };
+
+    let Some(id) = id else { return request };
+    let mut requests = requests().lock();
+    let entries = requests.entry(socket.to_string()).or_default();
+    for entry in entries.iter().filter(|e| e.kind == kind) {
+        entry.token.cancel();
+    }
+    entries.push(Entry { seq, kind, id, token });
+    request
+}
+
+/// Cancel a request of the socket by its client id
+pub fn cancel(socket: &str, id: u64) -> bool {
+    let requests = requests().lock();
+    let Some(entries) = requests.get(socket) else { return false };
+    let mut found = false;
+    for entry in entries.iter().filter(|e| e.id == id) {
+        entry.token.cancel();
+        found = true;
+    }
+    found
+}
+
+/// Cancel everything of a disconnected socket
+pub fn cancel_socket(socket: &str) {
+    if let Some(entries) = requests().lock().get(socket) {
+        for entry in entries {
+            entry.token.cancel();
+        }
+    }
+}
+
+/// Token of the request the current task runs for, if any
+pub fn current() -> Option<CancellationToken> {
+    CANCEL.try_with(|token| token.clone()).ok()
+}
+
+pub fn is_cancelled(error: &anyhow::Error) -> bool {
+    error.is::<RequestCancelled>()
+}
+
+#[cfg(test)]
+mod lsp_requests_tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cancel_and_supersede() {
+        let first = begin("s1", "completion", Some(1));
+        let hover = begin("s1", "hover", Some(2));
+        let other_socket = begin("s2", "completion", Some(1));
+        let untagged = begin("s1", "completion", None);
+
+        assert!(first.scope(async { current().is_some() }).await);
+        assert!(current().is_none());
+
+        // A newer completion supersedes the older one of the same socket
+        let second = begin("s1", "completion", Some(3));
+        assert!(first.is_cancelled());
+        assert!(!hover.is_cancelled() && !other_socket.is_cancelled() && !untagged.is_cancelled());
+
+        assert!(cancel("s1", 2));
+        assert!(hover.is_cancelled());
+        assert!(!cancel("s1", 42));
+
+        drop(second);
+        assert!(!cancel("s1", 3));
+
+        cancel_socket("s2");
+        assert!(other_socket.is_cancelled());
+    }
+}
@@ -0,0 +1,156 @@
+use serde::Serialize;
+use socketioxide::SocketIo;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::info;
+
+use crate::app_state::AppState;
+use crate::config::LspRestartConfig;
+use crate::lsp::LspExit;
+use crate::lsp_status::{self, LspState};
+
+// Restarts of crashed language servers. The manager sends the exits of
+// servers that were not stopped, each one is restarted after a delay
+// doubling with every crash in a row, the documents open in it are opened
+// again in the new server and the clients get `lsp:server_restarted`.
+
+const DEFAULT_MAX_RETRIES: u32 = 5;
+const DEFAULT_INITIAL_DELAY_MS: u64 = 500;
+const DEFAULT_MAX_DELAY_MS: u64 = 30_000;
+/// A server running this long since its last restart starts over at the
+/// first delay when it crashes again
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ServerRestarted {
+    pub lang: String,
+    pub root: PathBuf,
+    /// Crashes in a row, 1 for the first
+    pub attempt: u32,
+    pub pid: Option<u32>,
+    /// Documents opened again in the new server
+    pub reopened: usize,
+    /// How the previous server exited
+    pub status: String,
+}
+
+/// Delay before the restart `attempt`, starting at 1
+pub fn backoff(attempt: u32, initial: Duration, max: Duration) -> Duration {
+    let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+    initial.saturating_mul(factor).min(max)
+}
+
+struct Crashes {
+    count: u32,
+    last: Instant,
+}
+
+/// Count a crash of the server, the count starts over when it was stable
+fn next_attempt(crashes: &mut HashMap<(String, PathBuf), Crashes>, key: (String, PathBuf), now: Instant) -> u32 {
+    let entry = crashes.entry(key).or_insert(Crashes { count: 0, last: now });
+    if now.duration_since(entry.last) >= STABLE_AFTER {
+        entry.count = 0;
+    }
+    entry.count += 1;
+    entry.last = now;
+    entry.count
+}
+
+/// Restart the servers whose exits arrive on `exits` until the channel
+/// closes
+pub async fn supervise(
+    config: Option<LspRestartConfig>,
+    mut exits: mpsc::Receiver<LspExit>,
+    state: AppState,
+    io: Arc<SocketIo>,
+) {
+    let config = config.unwrap_or_default();
+    let max_retries = config.max_retries.unwrap_or(DEFAULT_MAX_RETRIES);
+    let initial = Duration::from_millis(config.initial_delay_ms.unwrap_or(DEFAULT_INITIAL_DELAY_MS));
+    let max = Duration::from_millis(config.max_delay_ms.unwrap_or(DEFAULT_MAX_DELAY_MS));
+    let mut crashes = HashMap::new();
+
+    while let Some(exit) = exits.recv().await {
+        info!("Language server exited: {:?}", exit);
+        crate::output::write("lsp", &format!("{} server for {} exited: {}", exit.lang, exit.root.display(), exit.status));
+
+        let attempt = next_attempt(&mut crashes, (exit.lang.clone(), exit.root.clone()), Instant::now());
+        if attempt > max_retries {
+            state.lsp_manager.lock().await.forget(&exit);
+            let error = format!("Exited {} times in a row, last: {}", attempt, exit.status);
+            crate::output::write("lsp", &format!("{} server is not restarted: {}", exit.lang, error));
+            lsp_status::set(&exit.lang, &exit.root, LspState::Failed, Some(error));
+            continue;
+        }
+
+        let delay = backoff(attempt, initial, max);
+        lsp_status::set(&exit.lang, &exit.root, LspState::Failed, Some(format!(
+            "Exited: {}, restarting in {} ms", exit.status, delay.as_millis()
+        )));
+
+        let state = state.clone();
+        let io = io.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            let Some((pid, reopened)) = restart(&state, &exit).await else { return };
+            let restarted = ServerRestarted {
+                lang: exit.lang, root: exit.root, attempt, pid, reopened, status: exit.status,
+            };
+            let _ = io.emit("lsp:server_restarted", &restarted).await;
+        });
+    }
+}
+
+/// Start a new server in place of the exited one, or the running one of
+/// the exit's pid, and open the documents of its language and root in it,
+/// the pid of the new server and the count of documents. The server starts
+/// without the buffers and the manager locked, they are taken to swap it in.
+pub async fn restart(state: &AppState, exit: &LspExit) -> Option<(Option<u32>, usize)> {
+    let launch = state.lsp_manager.lock().await.restart(exit)?;
+    let started = launch.start().await?;
+
+    let f2c = state.file2code.lock().await;
+    let mut lsp_manager = state.lsp_manager.lock().await;
+    let lsp = lsp_manager.replace(exit, started).await?;
+
+    let mut reopened = 0;
+    for (path, code) in f2c.iter().filter(|(_, code)| code.lang == exit.lang) {
+        if crate::roots::root_of(Path::new(path)) == exit.root {
+            lsp.did_open(&exit.lang, path, &code.text.to_string());
+            reopened += 1;
+        }
+    }
+    Some((lsp.pid(), reopened))
+}
+
+#[cfg(test)]
+mod lsp_restart_tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let initial = Duration::from_millis(500);
+        let max = Duration::from_secs(3);
+
+        let delays: Vec<u128> = (1..=5).map(|attempt| backoff(attempt, initial, max).as_millis()).collect();
+        assert_eq!(delays, [500, 1000, 2000, 3000, 3000]);
+        assert_eq!(backoff(100, initial, max), max);
+    }
+
+    #[test]
+    fn test_next_attempt() {
+        let mut crashes = HashMap::new();
+        let key = || ("rust".to_string(), PathBuf::from("/work"));
+        let start = Instant::now();
+
+        assert_eq!(next_attempt(&mut crashes, key(), start), 1);
+        assert_eq!(next_attempt(&mut crashes, key(), start + Duration::from_secs(1)), 2);
+        assert_eq!(next_attempt(&mut crashes, ("go".to_string(), PathBuf::from("/work")), start), 1);
+
+        // Stable for a while, counted as a first crash again
+        assert_eq!(next_attempt(&mut crashes, key(), start + Duration::from_secs(120)), 1);
+    }
+}
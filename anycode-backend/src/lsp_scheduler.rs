@@ -0,0 +1,264 @@
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+use crate::config::LspSchedulerConfig;
+
+// Admission of the requests sent to one language server. Interactive
+// requests (completion, hover, navigation) run up to their own limit and
+// are never held back by background ones. Background requests (symbols,
+// code lens, semantic tokens) wait while interactive ones are queued or in
+// flight, except after waiting `max_background_wait`, so they still make
+// progress while the user keeps typing.
+
+const DEFAULT_INTERACTIVE: usize = 4;
+const DEFAULT_BACKGROUND: usize = 2;
+const DEFAULT_MAX_BACKGROUND_WAIT: Duration = Duration::from_millis(1500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    Interactive,
+    Background,
+}
+
+/// Priority class of a request method
+pub fn priority_of(method: &str) -> Priority {
+    match method {
+        "textDocument/completion"
+        | "completionItem/resolve"
+        | "textDocument/hover"
+        | "textDocument/signatureHelp"
+        | "textDocument/definition"
+        | "textDocument/declaration"
+        | "textDocument/typeDefinition"
+        | "textDocument/implementation"
+        | "textDocument/references"
+        | "textDocument/documentHighlight"
+        | "textDocument/prepareRename"
+        | "textDocument/rename"
+        | "textDocument/formatting"
+        | "textDocument/rangeFormatting"
+        | "textDocument/codeAction"
+        | "codeAction/resolve"
+        | "workspace/executeCommand" => Priority::Interactive,
+        _ => Priority::Background,
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    interactive: usize,
+    background: usize,
+    waiting_interactive: usize,
+}
+
+#[derive(Debug)]
+pub struct Scheduler {
+    interactive_limit: usize,
+    background_limit: usize,
+    max_background_wait: Duration,
+    counts: Mutex<Counts>,
+    released: Notify,
+}
+
+impl Scheduler {
+    pub fn new(config: Option<&LspSchedulerConfig>) -> Self {
+        let interactive = config.and_then(|c| c.interactive).unwrap_or(DEFAULT_INTERACTIVE);
+        let background = config.and_then(|c| c.background).unwrap_or(DEFAULT_BACKGROUND);
+        let max_background_wait = config.and_then(|c| c.max_background_wait_ms)
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_MAX_BACKGROUND_WAIT);
+
+        Self {
+            interactive_limit: interactive.max(1),
+            background_limit: background.max(1),
+            max_background_wait,
+            counts: Mutex::new(Counts::default()),
+            released: Notify::new(),
+        }
+    }
+
+    /// Wait for a slot of the class, released when the permit is dropped
+    pub async fn acquire(self: &Arc<Self>, priority: Priority) -> Permit {
+        let _waiting = (priority == Priority::Interactive).then(|| Waiting::new(self));
+        let deadline = Instant::now() + self.max_background_wait;
+
+        loop {
+            let released = self.released.notified();
+            tokio::pin!(released);
+            // Registered before checking, a release in between is not lost
+            released.as_mut().enable();
+
+            let overdue = Instant::now() >= deadline;
+            if self.try_admit(priority, overdue) {
+                return Permit { scheduler: self.clone(), priority };
+            }
+
+            // Once overdue only a free slot lets the request in
+            match priority {
+                Priority::Interactive => released.await,
+                Priority::Background if overdue => released.await,
+                Priority::Background => tokio::select! {
+                    _ = released => {}
+                    _ = tokio::time::sleep_until(deadline) => {}
+                },
+            }
+        }
+    }
+
+    fn try_admit(&self, priority: Priority, overdue: bool) -> bool {
+        let mut counts = self.counts.lock();
+        let admitted = match priority {
+            Priority::Interactive => counts.interactive < self.interactive_limit,
+            Priority::Background => {
+                let yields = counts.interactive > 0 || counts.waiting_interactive > 0;
+                counts.background < self.background_limit && (!yields || overdue)
+            }
+        };
+        if admitted {
+            match priority {
+                Priority::Interactive => counts.interactive += 1,
+                Priority::Background => counts.background += 1,
+            }
+        }
+        admitted
+    }
+
+    fn release(&self, priority: Priority) {
+        {
+            let mut counts = self.counts.lock();
+            match priority {
+                Priority::Interactive => counts.interactive -= 1,
+                Priority::Background => counts.background -= 1,
+            }
+        }
+        self.released.notify_waiters();
+    }
+}
+
+/// A running request, its slot is freed when dropped
+pub struct Permit {
+    scheduler: Arc<Scheduler>,
+    priority: Priority,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.scheduler.release(self.priority);
+    }
+}
+
+/// An interactive request in the queue, background requests yield to it.
+/// Also counts down when the waiting request is cancelled.
+struct Waiting<'a>(&'a Scheduler);
+
+impl<'a> Waiting<'a> {
+    fn new(scheduler: &'a Scheduler) -> Self {
+        scheduler.counts.lock().waiting_interactive += 1;
+        Self(scheduler)
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.counts.lock().waiting_interactive -= 1;
+        self.0.released.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod lsp_scheduler_tests {
+    use super::*;
+
+    fn scheduler(interactive: usize, background: usize, wait_ms: u64) -> Arc<Scheduler> {
+        Arc::new(Scheduler::new(Some(&LspSchedulerConfig {
+            interactive: Some(interactive),
+            background: Some(background),
+            max_background_wait_ms: Some(wait_ms),
+        })))
+    }
+
+    async fn admitted(scheduler: &Arc<Scheduler>, priority: Priority) -> Option<Permit> {
+        tokio::time::timeout(Duration::from_millis(50), scheduler.acquire(priority)).await.ok()
+    }
+
+    #[test]
+    fn test_priority_of() {
+        assert_eq!(priority_of("textDocument/completion"), Priority::Interactive);
+        assert_eq!(priority_of("textDocument/hover"), Priority::Interactive);
+        assert_eq!(priority_of("textDocument/formatting"), Priority::Interactive);
+        assert_eq!(priority_of("textDocument/codeAction"), Priority::Interactive);
+        assert_eq!(priority_of("textDocument/codeLens"), Priority::Background);
+        assert_eq!(priority_of("textDocument/semanticTokens/full"), Priority::Background);
+        assert_eq!(priority_of("workspace/symbol"), Priority::Background);
+    }
+
+    #[tokio::test]
+    async fn test_limits() {
+        let scheduler = scheduler(2, 1, 60_000);
+        let first = admitted(&scheduler, Priority::Interactive).await;
+        let second = admitted(&scheduler, Priority::Interactive).await;
+        assert!(first.is_some() && second.is_some());
+        assert!(admitted(&scheduler, Priority::Interactive).await.is_none());
+
+        drop(first);
+        assert!(admitted(&scheduler, Priority::Interactive).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_background_yields_to_interactive() {
+        let scheduler = scheduler(1, 2, 60_000);
+        let interactive = admitted(&scheduler, Priority::Interactive).await;
+        assert!(admitted(&scheduler, Priority::Background).await.is_none());
+
+        // A waiting interactive request keeps the background ones queued
+        let queued = tokio::spawn({
+            let scheduler = scheduler.clone();
+            async move { scheduler.acquire(Priority::Interactive).await; }
+        });
+        tokio::task::yield_now().await;
+        drop(interactive);
+        queued.await.unwrap();
+
+        let background = admitted(&scheduler, Priority::Background).await;
+        assert!(background.is_some());
+        // Interactive requests do not wait for background ones
+        assert!(admitted(&scheduler, Priority::Interactive).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_background_progress() {
+        let scheduler = scheduler(1, 1, 20);
+        let _interactive = admitted(&scheduler, Priority::Interactive).await;
+        // Runs once it waited long enough, even with interactive load
+        let background = tokio::time::timeout(Duration::from_secs(1), scheduler.acquire(Priority::Background)).await;
+        assert!(background.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_overdue_waits_for_slot() {
+        let scheduler = scheduler(1, 1, 10);
+        let background = admitted(&scheduler, Priority::Background).await;
+        let overdue = tokio::spawn({
+            let scheduler = scheduler.clone();
+            async move { scheduler.acquire(Priority::Background).await; }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!overdue.is_finished());
+
+        drop(background);
+        tokio::time::timeout(Duration::from_secs(1), overdue).await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_waiter() {
+        let scheduler = scheduler(1, 1, 60_000);
+        let interactive = admitted(&scheduler, Priority::Interactive).await;
+        assert!(admitted(&scheduler, Priority::Interactive).await.is_none());
+        drop(interactive);
+        // The timed out waiter no longer holds back background requests
+        assert!(admitted(&scheduler, Priority::Background).await.is_some());
+    }
+}
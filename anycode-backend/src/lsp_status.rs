@@ -0,0 +1,170 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use tokio::sync::{mpsc, Mutex};
+use tracing::info;
+
+use crate::code::lang_of;
+use crate::config::{Config, LspWarmupConfig};
+use crate::lsp::LspManager;
+
+const DEFAULT_WARMUP_SERVERS: usize = 3;
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LspState {
+    Starting,
+    Ready,
+    Failed,
+    Stopped,
+    /// Running on the fallback providers, see LspManager::degrade
+    Degraded,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct LspStatus {
+    pub lang: String,
+    /// Workspace root of the server, see roots.rs
+    pub root: PathBuf,
+    pub state: LspState,
+    pub error: Option<String>,
+}
+
+#[derive(Default)]
+struct Registry {
+    /// By language and workspace root, like the servers
+    statuses: parking_lot::Mutex<HashMap<(String, PathBuf), LspStatus>>,
+    sender: OnceLock<mpsc::Sender<LspStatus>>,
+}
+
+static REGISTRY: OnceLock<Registry> = OnceLock::new();
+
+fn registry() -> &'static Registry {
+    REGISTRY.get_or_init(Registry::default)
+}
+
+/// Set the channel status changes are forwarded to, main emits them as
+/// `lsp:status` to all clients.
+pub fn init(sender: mpsc::Sender<LspStatus>) {
+    let _ = registry().sender.set(sender);
+}
+
+pub fn set(lang: &str, root: &Path, state: LspState, error: Option<String>) {
+    let status = LspStatus { lang: lang.to_string(), root: root.to_path_buf(), state, error };
+    let registry = registry();
+
+    registry.statuses.lock().insert((lang.to_string(), root.to_path_buf()), status.clone());
+    if let Some(sender) = registry.sender.get() {
+        let _ = sender.try_send(status);
+    }
+}
+
+pub fn all() -> Vec<LspStatus> {
+    let statuses = registry().statuses.lock();
+    let mut all: Vec<LspStatus> = statuses.values().cloned().collect();
+    all.sort_by(|a, b| a.lang.cmp(&b.lang).then_with(|| a.root.cmp(&b.root)));
+    all
+}
+
+/// Drop the statuses of a removed workspace root
+pub fn forget_root(root: &Path) {
+    registry().statuses.lock().retain(|(_, r), _| r != root);
+}
+
+/// Languages with a configured server found among the files, the ones
+/// with the most files first
+pub fn detect_languages(files: &[PathBuf], config: &Config) -> Vec<String> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for file in files {
+        let lang = lang_of(&file.to_string_lossy(), config);
+        let has_server = config.language.iter()
+            .any(|l| l.name == lang && l.lsp.is_some());
+        if has_server {
+            *counts.entry(lang).or_default() += 1;
+        }
+    }
+
+    let mut langs: Vec<(String, usize)> = counts.into_iter().collect();
+    langs.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    langs.into_iter().map(|(lang, _)| lang).collect()
+}
+
+/// Start the servers for the languages of the workspace so the first
+/// completion doesn't wait for the process startup
+pub async fn warm_up(warmup: LspWarmupConfig, config: Config, lsp_manager: Arc<Mutex<LspManager>>) {
+    let scan = crate::pool::spawn(async {
+        crate::search::collect_files_recursively(Path::new("."))
+    });
+
+    let files = match scan.await {
+        Ok(Ok(files)) => files,
+        Ok(Err(e)) => {
+            crate::output::write("lsp", &format!("Warm-up scan failed: {}", e));
+            return;
+        }
+        Err(_) => return,
+    };
+
+    let max_servers = warmup.max_servers.unwrap_or(DEFAULT_WARMUP_SERVERS);
+    let langs: Vec<String> = detect_languages(&files, &config).into_iter().take(max_servers).collect();
+    info!("Warming up language servers: {:?}", langs);
+
+    for lang in langs {
+        lsp_manager.lock().await.get(&lang).await;
+    }
+}
+
+#[cfg(test)]
+mod lsp_status_tests {
+    use super::*;
+    use crate::config::{IndentConfig, Language};
+
+    fn language(name: &str, ext: &str, lsp: Option<&str>) -> Language {
+        Language {
+            name: name.to_string(),
+            types: vec![ext.to_string()],
+            comment: "//".to_string(),
+            lsp: lsp.map(|cmd| vec![cmd.to_string()]),
+            indent: IndentConfig { width: 4, unit: " ".to_string() },
+            executable: None,
+            exec: None,
+            exectest: None,
+            repl: None,
+            alternates: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_detect_languages() {
+        let mut config = Config::default();
+        config.language = vec![
+            language("rust", "rs", Some("rust-analyzer")),
+            language("python", "py", Some("pyright-langserver")),
+            language("toml", "toml", None),
+        ];
+
+        let files: Vec<PathBuf> = ["a.py", "b.rs", "c.rs", "Cargo.toml", "README.md"]
+            .iter().map(PathBuf::from).collect();
+
+        assert_eq!(detect_languages(&files, &config), ["rust", "python"]);
+    }
+
+    #[test]
+    fn test_status_registry() {
+        let (primary, added) = (Path::new("/w"), Path::new("/lib"));
+        set("test-lang", primary, LspState::Starting, None);
+        set("test-lang", primary, LspState::Ready, None);
+        set("test-lang", added, LspState::Failed, Some("not found".to_string()));
+
+        let status = |root: &Path| all().into_iter().find(|s| s.lang == "test-lang" && s.root == root);
+        assert_eq!(status(primary).unwrap().state, LspState::Ready);
+        let failed = status(added).unwrap();
+        assert_eq!(failed.state, LspState::Failed);
+        assert_eq!(failed.error.as_deref(), Some("not found"));
+
+        forget_root(added);
+        assert!(status(added).is_none());
+        assert!(status(primary).is_some());
+    }
+}
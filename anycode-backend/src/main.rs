@@ -5,28 +5,27 @@ use axum::{
 };
 use serde_json::{json, Value};
 use socketioxide::{
-    extract::{AckSender, Data, SocketRef, State},
+    extract::{AckSender, Data, SocketRef, State, TryData},
     SocketIo,
 };
 use tower::ServiceBuilder;
 use tower_http::{cors::CorsLayer, services::ServeDir};
 use tracing::info;
 use tracing_subscriber::FmtSubscriber;
+use tracing_subscriber::prelude::*;
 use anyhow::Result;
 
 mod code;
-use code::Code;
 
 mod config;
 use config::Config;
 
 mod utils;
-use utils::is_ignored_dir;
 
 mod lsp;
 use lsp::LspManager;
 
-use std::{path::PathBuf, sync::Arc};
+use std::sync::Arc;
 use tokio::sync::{mpsc::Receiver, Mutex};
 use std::collections::HashMap;
 use tokio::sync::mpsc;
@@ -40,49 +39,322 @@ use handlers::{
     search_handler::*, 
     lsp_handler::*, 
     terminal_handler::*,
+    server_handler::*,
+    workspace_handler::*,
+    edit_handler::*,
+    session_handler::*,
+    output_handler::*,
+    palette_handler::*,
+    problems_handler::*,
+    rename_handler::*,
+    ignore_handler::*,
+    run_handler::*,
+    notify_handler::*,
+    repl_handler::*,
+    audit_handler::*,
+    git_handler::*,
+    lang_handler::*,
+    collab_handler::*,
+    nav_handler::*,
 };
 
 mod search;
+mod search_jobs;
 mod terminal;
+mod pool;
+mod timing;
+mod profile;
+use timing::{EventTimer, SlowEvent};
+mod crash;
+use crash::CrashReport;
+mod crypt;
+mod store;
+mod storage;
+mod notifier;
+use notifier::Notifier;
+mod mcp;
+mod services;
+mod api;
+mod words;
+mod paths;
+mod case_check;
+mod output;
+use output::OutputLine;
+mod fuzzy;
+mod recent;
+use recent::RecentFiles;
+mod watcher;
+mod rename;
+mod recording;
+mod terminal_history;
+mod terminal_share;
+mod scrollback;
+mod shell_integration;
+mod shell_complete;
+mod status;
+mod lsp_status;
+mod lsp_cache;
+mod lsp_memory;
+mod lsp_restart;
+mod lsp_requests;
+mod lsp_scheduler;
+mod progress;
+mod readonly;
+use progress::ProgressItem;
+mod duplicates;
+mod file_index;
+mod quick_open;
+mod path_completion;
+mod outline;
+mod symbol_cache;
+mod semantic_tokens;
+mod power;
+mod rich_text;
+mod diagnostic_filter;
+mod lang_rules;
+mod template;
+mod bootstrap;
+mod alternate;
+mod exec;
+mod command_output;
+mod vfs;
+mod preload;
+mod net;
+mod roots;
+mod repl;
+mod search_export;
+mod structural_search;
+mod replace;
+mod text_audit;
+mod dirty_diff;
+mod git;
+mod grep_watch;
+mod file_hash;
+mod file_history;
+mod file_probe;
+mod undo_store;
+mod revision;
+mod collab;
+mod selftest;
+mod event_log;
+mod permissions;
+use permissions::AuthData;
+#[cfg(test)]
+mod protocol_tests;
+#[cfg(test)]
+mod fixtures;
+use file_index::ScanProgress;
+use lsp_status::LspStatus;
 
 use lsp_types::PublishDiagnosticsParams;
-use notify::{recommended_watcher, Event, RecursiveMode, Watcher};
 
-async fn on_connect(socket: SocketRef, _state: State<AppState>) {
+async fn on_connect(socket: SocketRef, TryData(auth): TryData<AuthData>, state: State<AppState>) {
     info!("Socket.IO connected: {:?} {:?}", socket.ns(), socket.id);
 
-    socket.on("file:open", handle_file_open);
-    socket.on("dir:list", handle_dir_list);
-    socket.on("file:change", handle_change);
-    socket.on("file:save", handle_file_save);
-    socket.on("file:set", handle_file_set);
-    socket.on("file:create", handle_create);
-    socket.on("file:close", handle_file_close);
-
-    socket.on("lsp:completion", handle_completion);
-    socket.on("lsp:definition", handle_definition);
-    socket.on("lsp:references", handle_references);
-    socket.on("lsp:hover", handle_hover);
-
-    socket.on("search:start", handle_search);
-
-    socket.on("terminal:start", handle_terminal_start);
-    socket.on("terminal:input", handle_terminal_input);
-    socket.on("terminal:resize", handle_terminal_resize);
-    socket.on("terminal:close", handle_terminal_close);
-    socket.on("terminal:reconnect", handle_terminal_reconnect);
+    let token = auth.ok().and_then(|auth| auth.token);
+    let scopes = match permissions::authorize(state.config.auth.as_ref(), token.as_deref()) {
+        Ok(scopes) => scopes,
+        Err(e) => {
+            info!("Socket.IO refused: {} {}", socket.id, e);
+            socket.emit("server:unauthorized", &json!({ "error": e })).ok();
+            socket.disconnect().ok();
+            return;
+        }
+    };
+    socket.emit("auth:scopes", &scopes).ok();
+    state.socket2data.lock().await.entry(socket.id.to_string()).or_default().scopes = scopes.clone();
+    let guard = permissions::Guard::new(&socket, scopes);
+
+    guard.on("file:open", handle_file_open);
+    guard.on("file:openBatch", handle_file_open_batch);
+    guard.on("dir:list", handle_dir_list);
+    guard.on("file:change", handle_change);
+    guard.on("file:undo", handle_file_undo);
+    guard.on("file:redo", handle_file_redo);
+    guard.on("file:save", handle_file_save);
+    guard.on("file:set", handle_file_set);
+    guard.on("file:create", handle_create);
+    guard.on("file:close", handle_file_close);
+    guard.on("file:makeWritable", handle_make_writable);
+    guard.on("file:restoreFromBuffer", handle_restore_from_buffer);
+    guard.on("file:peek", handle_file_peek);
+    guard.on("file:rename", handle_file_rename);
+    guard.on("nav:alternate", handle_nav_alternate);
+    guard.on("nav:createAlternate", handle_nav_create_alternate);
+    guard.on("collab:join", handle_collab_join);
+    guard.on("collab:leave", handle_collab_leave);
+    guard.on("collab:op", handle_collab_op);
+    guard.on("collab:cursor", handle_collab_cursor);
+    guard.on("file:dirtyDiff", handle_dirty_diff);
+    guard.on("vfs:list", handle_vfs_list);
+
+    guard.on("edit:wordAt", handle_word_at);
+    guard.on("edit:batch", handle_batch_edit);
+    guard.on("rename:preview", handle_rename_preview);
+    guard.on("rename:apply", handle_rename_apply);
+
+    guard.on("lsp:completion", handle_completion);
+    guard.on("lsp:definition", handle_definition);
+    guard.on("lsp:references", handle_references);
+    guard.on("lsp:rename", handle_rename);
+    guard.on("lsp:status", handle_lsp_status);
+    guard.on("lsp:clearCache", handle_lsp_clear_cache);
+    guard.on("lsp:restore", handle_lsp_restore);
+    guard.on("lsp:hover", handle_hover);
+    guard.on("lsp:signature_help", handle_signature_help);
+    guard.on("lsp:format", handle_format);
+    guard.on("lsp:format_range", handle_format_range);
+    guard.on("lsp:code_action", handle_code_action);
+    guard.on("lsp:code_action_apply", handle_code_action_apply);
+    guard.on("lsp:document_symbols", handle_document_symbols);
+    guard.on("lsp:semantic_tokens", handle_semantic_tokens);
+    guard.on("lsp:workspace_symbols", handle_workspace_symbols);
+    guard.on("lsp:cancel", handle_lsp_cancel);
+
+    guard.on("search:start", handle_search);
+    guard.on("search:more", handle_search_more);
+    guard.on("search:inPath", handle_search_in_path);
+    guard.on("search:export", handle_search_export);
+    guard.on("search:replace", handle_search_replace);
+    guard.on("watch:grep", handle_watch_grep);
+    guard.on("watch:grepStop", handle_watch_grep_stop);
+
+    guard.on("terminal:profiles", handle_terminal_profiles);
+    guard.on("terminal:start", handle_terminal_start);
+    guard.on("terminal:input", handle_terminal_input);
+    guard.on("terminal:resize", handle_terminal_resize);
+    guard.on("terminal:close", handle_terminal_close);
+    guard.on("terminal:reconnect", handle_terminal_reconnect);
+    guard.on("terminal:record", handle_terminal_record);
+    guard.on("terminal:replays", handle_terminal_replays);
+    guard.on("terminal:history", handle_terminal_history);
+    guard.on("terminal:scrollback", handle_terminal_scrollback);
+    guard.on("terminal:complete", handle_terminal_complete);
+    guard.on("terminal:share", handle_terminal_share);
+    guard.on("terminal:revoke", handle_terminal_revoke);
+    guard.on("terminal:join", handle_terminal_join);
+    guard.on("terminal:leave", handle_terminal_leave);
+
+    guard.on("admin:subscribe", handle_admin_subscribe);
+    guard.on("server:status", handle_server_status);
+    guard.on("server:setPowerMode", handle_set_power_mode);
+    guard.on("server:selftest", handle_server_selftest);
+    guard.on("server:profile", handle_server_profile);
+
+    guard.on("workspace:focus", handle_workspace_focus);
+    guard.on("workspace:duplicates", handle_workspace_duplicates);
+    guard.on("workspace:scanStatus", handle_workspace_scan_status);
+    guard.on("workspace:caseCheck", handle_workspace_case_check);
+    guard.on("workspace:roots", handle_workspace_roots);
+    guard.on("workspace:addRoot", handle_workspace_add_root);
+    guard.on("workspace:removeRoot", handle_workspace_remove_root);
+    guard.on("workspace:open", handle_workspace_open);
+    guard.on("workspace:bootstrap", handle_workspace_bootstrap);
+    guard.on("events:since", handle_events_since);
+    guard.on("workspace:diffSince", handle_diff_since);
+    guard.on("files:find", handle_files_find);
+
+    guard.on("ignore:get", handle_ignore_get);
+    guard.on("ignore:set", handle_ignore_set);
+
+    guard.on("run:command", handle_run_command);
+    guard.on("run:saveOutput", handle_run_save_output);
+
+    guard.on("session:restore", handle_session_restore);
+
+    guard.on("palette:query", handle_palette_query);
+
+    guard.on("problems:rules", handle_problems_rules);
+    guard.on("problems:suppress", handle_problems_suppress);
+    guard.on("problems:unsuppress", handle_problems_unsuppress);
+
+    guard.on("languages:rules", handle_languages_rules);
+    guard.on("languages:setRules", handle_languages_set_rules);
+    guard.on("languages:detect", handle_languages_detect);
+
+    guard.on("git:status", handle_git_status);
+    guard.on("git:branches", handle_git_branches);
+    guard.on("git:checkout", handle_git_checkout);
+
+    guard.on("output:list", handle_output_list);
+    guard.on("output:subscribe", handle_output_subscribe);
+    guard.on("output:unsubscribe", handle_output_unsubscribe);
+
+    guard.on("notify:list", handle_notify_list);
+    guard.on("notify:cancel", handle_notify_cancel);
+
+    guard.on("repl:start", handle_repl_start);
+    guard.on("repl:eval", handle_repl_eval);
+    guard.on("repl:stop", handle_repl_stop);
+    guard.on("repl:list", handle_repl_list);
+
+    guard.on("audit:textFormat", handle_audit_text_format);
+    guard.on("audit:fixTextFormat", handle_audit_fix_text_format);
     
     socket.on_disconnect(on_disconnect)
 }
 
-async fn on_disconnect(socket: SocketRef, _state: State<AppState>) {
+async fn on_disconnect(socket: SocketRef, state: State<AppState>) {
     info!("Socket.IO disconnected: {}", socket.id);
+    lsp_requests::cancel_socket(socket.id.as_str());
+    grep_watch::unsubscribe_socket(socket.id.as_str());
+    terminal_share::leave_socket(socket.id.as_str());
+    collab::leave_socket(socket.id.as_str());
+
+    let histories: Vec<_> = state.file2code.lock().await.iter()
+        .filter_map(|(path, code)| undo_store::snapshot(code).map(|h| (path.clone(), h)))
+        .collect();
+    pool::spawn(async move {
+        for (path, history) in histories {
+            undo_store::store(&path, &history);
+        }
+    });
 }
 
 
-fn build_app_state() -> (AppState, Receiver<PublishDiagnosticsParams>) {
+/// Receivers of the background registries, main forwards them to the clients
+struct AppChannels {
+    diagnostics: Receiver<PublishDiagnosticsParams>,
+    slow_events: Receiver<SlowEvent>,
+    crash_reports: Receiver<CrashReport>,
+    output_lines: Receiver<OutputLine>,
+    lsp_statuses: Receiver<LspStatus>,
+    progress_items: Receiver<ProgressItem>,
+    apply_edits: Receiver<lsp::ApplyEdit>,
+    lsp_exits: Receiver<lsp::LspExit>,
+}
+
+fn build_app_state() -> (AppState, AppChannels) {
 
     let config = crate::config::get();
+    status::init();
+    pool::init(config.background_workers);
+    store::init(&config);
+    storage::init(&config);
+    power::init(&config);
+    command_output::register();
+
+    let (slow_event_send, slow_event_recv) = mpsc::channel::<SlowEvent>(32);
+    timing::init(config.slow_event_ms, slow_event_send);
+
+    let (crash_send, crash_recv) = mpsc::channel::<CrashReport>(32);
+    crash::init(crash_send);
+
+    let (output_send, output_recv) = mpsc::channel::<OutputLine>(256);
+    output::init(output_send);
+    anycode_search::set_log(|message| output::write("search", message));
+
+    let (lsp_status_send, lsp_status_recv) = mpsc::channel::<LspStatus>(32);
+    lsp_status::init(lsp_status_send);
+
+    let (progress_send, progress_recv) = mpsc::channel::<ProgressItem>(64);
+    progress::init(progress_send);
+
+    let (apply_edit_send, apply_edit_recv) = mpsc::channel::<lsp::ApplyEdit>(8);
+    lsp::set_apply_edits(apply_edit_send);
+
+    let (lsp_exit_send, lsp_exit_recv) = mpsc::channel::<lsp::LspExit>(8);
+    lsp::set_exits(lsp_exit_send);
 
     let (diagnostic_send,  diagnostic_recv) = mpsc::channel::<PublishDiagnosticsParams>(1);
     let mut lsp_manager = LspManager::new(config.clone());
@@ -93,44 +365,48 @@ fn build_app_state() -> (AppState, Receiver<PublishDiagnosticsParams>) {
     let file2code = Arc::new(Mutex::new(HashMap::new()));
     let socket2data = Arc::new(Mutex::new(HashMap::new()));
     let terminals = Arc::new(Mutex::new(HashMap::new())); 
+    let notifier = Arc::new(Notifier::new(config.notify.clone()));
+    let diagnostics = Arc::new(Mutex::new(HashMap::new()));
+    let recent = Arc::new(Mutex::new(RecentFiles::load()));
 
     let state = AppState { 
-        config, file2code, lsp_manager, socket2data, terminals 
+        config, file2code, lsp_manager, socket2data, terminals, notifier, diagnostics, recent
+    };
+
+    let channels = AppChannels {
+        diagnostics: diagnostic_recv,
+        slow_events: slow_event_recv,
+        crash_reports: crash_recv,
+        output_lines: output_recv,
+        lsp_statuses: lsp_status_recv,
+        progress_items: progress_recv,
+        apply_edits: apply_edit_recv,
+        lsp_exits: lsp_exit_recv,
     };
 
-    (state, diagnostic_recv)
+    (state, channels)
 }
 
-async fn handle_watch_event(
-    path: &PathBuf, 
-    event: &notify::Event, 
-    socket: &Arc<SocketIo>,
-    file2code: &Arc<Mutex<HashMap<String, Code>>>
-) {
-    println!("watch event: {:?}", event);
-    
-    match event.kind {
-        notify::EventKind::Create(_) => {
-            let _ = socket.emit("watcher:create", &(path, path.is_file())).await;
-        },
-        notify::EventKind::Remove(_) => {
-            let _ = socket.emit("watcher:remove", &(path, path.is_file())).await; 
-        },
-        notify::EventKind::Modify(notify::event::ModifyKind::Data(_)) => {
-            let _ = socket.emit("watcher:modify", &(path, path.is_file())).await; 
-
-            let mut f2c = file2code.lock().await;
-            match f2c.get_mut(path.to_str().unwrap()) {
-                Some(file) => {
-                    let _ = file.reload();
-                },
-                None => {},
-            };
-        },
-        _ => {
+/// Socket.io and REST routes over the state, the background tasks feeding
+/// the clients are started by main
+fn build_app(state: AppState) -> (Router, Arc<SocketIo>) {
+    let api_state = state.clone();
+    let auth = state.config.auth.clone();
+    let (layer, io) = SocketIo::builder().with_state(state).build_layer();
+    let cors = ServiceBuilder::new().layer(CorsLayer::permissive()).layer(layer);
 
-        }
-    }
+    let io = Arc::new(io);
+    io.ns("/", on_connect);
+
+    let app = axum::Router::new()
+        .fallback(static_handler)
+        .with_state(io.clone())
+        .nest("/api/v1", api::router(api_state.clone(), io.clone()))
+        .merge(api::health_router(api_state, io.clone()))
+        .merge(api::raw_router(auth))
+        .layer(cors);
+
+    (app, io)
 }
 
 static INDEX_HTML: &str = "index.html";
@@ -172,24 +448,53 @@ async fn not_found() -> Response {
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(tracing_subscriber::EnvFilter::new("info"))
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::EnvFilter::new("info"))
+        .with(tracing_subscriber::fmt::layer())
+        .with(profile::ProfileLayer)
         .init();
+    if std::env::args().any(|arg| arg == "--profile") {
+        profile::start();
+    }
 
-    let (state, mut diagnostics_channel) = build_app_state();
-    // let file2code = state.file2code.clone();
+    if std::env::args().any(|arg| arg == "--doctor") {
+        let root = std::env::current_dir()?;
+        let report = selftest::run(&crate::config::get(), &root, None).await;
+        print!("{}", selftest::format(&report));
+        std::process::exit(if report.ok { 0 } else { 1 });
+    }
 
-    let (layer, io) = SocketIo::builder().with_state(state).build_layer();
-    let cors = ServiceBuilder::new().layer(CorsLayer::permissive()).layer(layer);
+    let (state, channels) = build_app_state();
+    let AppChannels {
+        diagnostics: mut diagnostics_channel, mut slow_events, mut crash_reports, mut output_lines,
+        mut lsp_statuses, mut progress_items, mut apply_edits, lsp_exits,
+    } = channels;
+    let notifier = state.notifier.clone();
+    let diagnostics = state.diagnostics.clone();
+    let mcp_state = state.clone();
+    let api_state = state.clone();
+    let port_fallback = state.config.port_fallback;
 
-    let io = Arc::new(io);
+    let (app, io) = build_app(state);
 
     // Spawn a task to handle diagnostics
     let socket = io.clone();
     tokio::spawn(async move {
         while let Some(diagnostic_message) = diagnostics_channel.recv().await {
             // log2::debug!("diagnostic_message_json {}", diagnostic_message_json);
-            let send_result = socket.emit("lsp:diagnostics", &diagnostic_message).await;
+            let (shown, _) = diagnostic_filter::apply(&diagnostic_message);
+            notifier.observe_diagnostics(diagnostic_message.uri.as_str(), shown.diagnostics.len());
+            {
+                let mut cache = diagnostics.lock().await;
+                let uri = diagnostic_message.uri.as_str();
+                let path = paths::uri_to_path(uri).unwrap_or_else(|| uri.to_string());
+                if diagnostic_message.diagnostics.is_empty() {
+                    cache.remove(&path);
+                } else {
+                    cache.insert(path, diagnostic_message.clone());
+                }
+            }
+            let send_result = socket.emit("lsp:diagnostics", &diagnostic_filter::to_json(&diagnostic_message)).await;
             match send_result {
                 Ok(_) => {},
                 Err(e) => {
@@ -199,45 +504,139 @@ async fn main() -> Result<()> {
         }
     });
 
+    // Spawn a task to apply the edits servers send with workspace/applyEdit,
+    // e.g. while executing a code action command
+    let socket = io.clone();
+    let edit_state = api_state.clone();
+    tokio::spawn(async move {
+        while let Some(lsp::ApplyEdit { edit, reply }) = apply_edits.recv().await {
+            let mut timer = EventTimer::start("workspace/applyEdit");
+            let applied = match services::apply_workspace_edit(&edit_state, &mut timer, edit, false).await {
+                Ok((_, changes)) => {
+                    for change in &changes {
+                        let _ = socket.emit("file:change", change).await;
+                    }
+                    true
+                }
+                Err(e) => {
+                    tracing::error!("Failed to apply workspace edit: {}", e);
+                    false
+                }
+            };
+            let _ = reply.send(applied);
+        }
+    });
+
+    // Spawn a task to report slow events to admin subscribers
+    let socket = io.clone();
+    tokio::spawn(async move {
+        while let Some(slow_event) = slow_events.recv().await {
+            let _ = socket.to(ADMIN_ROOM).emit("server:slowEvent", &slow_event).await;
+        }
+    });
+
+    // Spawn a task to tell the sender of the event about panicked handlers,
+    // it fails its requests of the event instead of waiting for an ack.
+    // Admin subscribers see every crash.
+    let socket = io.clone();
+    tokio::spawn(async move {
+        while let Some(report) = crash_reports.recv().await {
+            if let Some(sender) = report.socket.and_then(|sid| socket.get_socket(sid))
+                && !sender.rooms().contains(&ADMIN_ROOM.into())
+            {
+                let _ = sender.emit("server:handlerError", &report);
+            }
+            let _ = socket.to(ADMIN_ROOM).emit("server:handlerError", &report).await;
+        }
+    });
+
+    if let Some(mcp_config) = mcp_state.config.mcp.clone()
+        && mcp_config.enabled
+        && let Err(e) = mcp::start(&mcp_config, mcp_state, io.clone()).await
+    {
+        tracing::error!("Failed to start MCP server: {}", e);
+    }
 
-    // let (watch_tx, mut watch_rx) = mpsc::channel::<notify::Result<Event>>(32);
-    // let mut watcher = recommended_watcher(move |res| {
-    //     let _ = watch_tx.blocking_send(res);
-    // })?;
-
-    // let dir = std::path::Path::new(".");
-    // watcher.watch(dir, RecursiveMode::Recursive)?;
-
-    // // Spawn a task to watch files and dirs changes and send events to the socket
-    // let socket = io.clone();
-    // tokio::spawn(async move {
-    //     while let Some(res) = watch_rx.recv().await {
-    //         match res {
-    //             Ok(event) => {
-    //                 for path in &event.paths {
-    //                     if is_ignored_dir(path) { continue }
-    //                     else { 
-    //                         handle_watch_event(path, &event, &socket, &file2code).await
-    //                     }
-    //                 }
-    //             },
-    //             Err(e) => eprintln!("watch error: {:?}", e)
-    //         }
-    //     }
-    // });
+    // Enumerate the workspace in the background so quick-open is ready and
+    // the clients can show the progress on big repositories
+    let (scan_send, mut scan_progress) = mpsc::channel::<ScanProgress>(16);
+    pool::spawn(async move {
+        let scan = progress::start("scan", "Scanning workspace", None);
+        file_index::prime(std::path::Path::new("."), |progress| {
+            scan.update(None, Some(format!("{} files", progress.files)));
+            let _ = scan_send.try_send(progress.clone());
+        });
+        let files = file_index::files();
+        case_check::warn(&case_check::check(&[], &files, paths::is_case_insensitive()));
+    });
 
-    io.ns("/", on_connect);
+    let socket = io.clone();
+    tokio::spawn(async move {
+        while let Some(progress) = scan_progress.recv().await {
+            let _ = socket.emit("workspace:scanProgress", &progress).await;
+        }
+    });
 
-    let app = axum::Router::new()
-        .fallback(static_handler)
-        .with_state(io.clone())
-        .layer(cors);
+    // Spawn a task to push the progress center changes to the clients
+    let socket = io.clone();
+    tokio::spawn(async move {
+        while let Some(item) = progress_items.recv().await {
+            let _ = socket.emit("notify:progress", &item).await;
+        }
+    });
+
+    // Spawn a task to push language server readiness to the clients
+    let socket = io.clone();
+    tokio::spawn(async move {
+        while let Some(status) = lsp_statuses.recv().await {
+            let _ = socket.emit("lsp:status", &status).await;
+        }
+    });
+
+    if let Some(warmup) = api_state.config.lsp_warmup.clone()
+        && warmup.enabled
+    {
+        let config = api_state.config.clone();
+        tokio::spawn(lsp_status::warm_up(warmup, config, api_state.lsp_manager.clone()));
+    }
+
+    if let Some(config) = api_state.config.preload.clone() {
+        tokio::spawn(preload::warm(config, api_state.clone()));
+    }
+
+    tokio::spawn(lsp_restart::supervise(
+        api_state.config.lsp_restart.clone(), lsp_exits, api_state.clone(), io.clone(),
+    ));
+
+    if let Some(memory) = api_state.config.lsp_memory.clone() {
+        tokio::spawn(lsp_memory::monitor(memory, api_state.clone(), io.clone()));
+    }
+
+    // Spawn a task to forward output channel lines to their subscribers
+    let socket = io.clone();
+    tokio::spawn(async move {
+        while let Some(line) = output_lines.recv().await {
+            let room = output::room(&line.channel);
+            let _ = socket.to(room).emit("output:line", &line).await;
+        }
+    });
+
+    if let Err(e) = watcher::start(io.clone(), api_state.clone()) {
+        tracing::error!("Failed to watch workspace: {}", e);
+    }
 
-    let port = std::env::var("ANYCODE_PORT").unwrap_or("3000".to_string());
-    let url = format!("0.0.0.0:{}", port);
-    let listener = tokio::net::TcpListener::bind(url).await?;
+    let listener = net::bind(port_fallback).await?;
+    let url = format!("http://localhost:{}", listener.local_addr()?.port());
 
-    println!("Starting anycode at http://localhost:{}", port);
+    println!("Starting anycode at {}", url);
+    status::set_url(&url);
+    output::write("server", &format!("Listening at {}", url));
+
+    if std::env::args().any(|arg| arg == "--open")
+        && let Err(e) = net::open_browser(&url)
+    {
+        tracing::error!("Failed to open the browser: {}", e);
+    }
 
     axum::serve(listener, app)
         .with_graceful_shutdown(async {
@@ -245,5 +644,15 @@ async fn main() -> Result<()> {
         })
         .await?;
 
+    recent::flush();
+
+    // A profile still recording is kept for the bug report
+    if let Some(recorded) = profile::stop() {
+        match profile::write(&recorded, &search_export::exports_dir()) {
+            Ok(path) => println!("Profile written to {}", path.display()),
+            Err(e) => tracing::error!("Failed to write the profile: {}", e),
+        }
+    }
+
     Ok(())
 }
@@ -0,0 +1,330 @@
+use anyhow::{anyhow, Result};
+use rmcp::model::{
+    CallToolRequestParam, CallToolResult, Content, Implementation, ListToolsResult,
+    PaginatedRequestParam, ServerCapabilities, ServerInfo, Tool,
+};
+use rmcp::service::{RequestContext, RoleServer};
+use rmcp::transport::sse_server::{SseServer, SseServerConfig};
+use rmcp::{Error as McpError, ServerHandler};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use socketioxide::SocketIo;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+use crate::app_state::{get_or_create_code, AppState};
+use crate::code::Code;
+use crate::config::McpConfig;
+use crate::search::PathFilter;
+use crate::services;
+use anycode_search::ignore::is_ignored_path;
+
+const DEFAULT_MCP_ADDRESS: &str = "127.0.0.1:3001";
+const MAX_SEARCH_FILES: usize = 200;
+const MAX_TASK_OUTPUT: usize = 64 * 1024;
+const TASK_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Workspace tools served over MCP. They go through the same ignore rules and
+/// the same shared file2code / LSP state as the socket handlers, so an agent
+/// edit shows up in the open editors like an edit from another client.
+#[derive(Clone)]
+pub struct WorkspaceTools {
+    state: AppState,
+    io: Arc<SocketIo>,
+    allow_edit: bool,
+    allow_run: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReadFileArgs {
+    path: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchArgs {
+    pattern: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApplyEditArgs {
+    path: String,
+    old_text: Option<String>,
+    new_text: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RunTaskArgs {
+    command: String,
+}
+
+pub async fn start(config: &McpConfig, state: AppState, io: Arc<SocketIo>) -> Result<CancellationToken> {
+    let address = config.address.as_deref().unwrap_or(DEFAULT_MCP_ADDRESS);
+
+    let server = SseServer::serve_with_config(SseServerConfig {
+        bind: address.parse()?,
+        sse_path: "/sse".to_string(),
+        post_path: "/message".to_string(),
+        ct: CancellationToken::new(),
+    })
+    .await?;
+
+    let tools = WorkspaceTools {
+        state,
+        io,
+        allow_edit: config.allow_edit.unwrap_or(false),
+        allow_run: config.allow_run.unwrap_or(false),
+    };
+
+    info!("MCP server listening on http://{}/sse", address);
+    Ok(server.with_service(move || tools.clone()))
+}
+
+/// Resolve a tool path inside the workspace roots, see `roots::resolve`,
+/// `new` for a file that may not exist yet. Ignored paths are rejected.
+fn workspace_path(path: &str, new: bool) -> Result<PathBuf> {
+    let resolved = match new {
+        true => crate::roots::resolve_new(path)?,
+        false => crate::roots::resolve(path)?,
+    };
+    if is_ignored_path(&resolved) {
+        return Err(anyhow!("{} is ignored", path));
+    }
+    Ok(resolved)
+}
+
+fn schema(value: Value) -> Arc<Map<String, Value>> {
+    match value {
+        Value::Object(map) => Arc::new(map),
+        _ => Arc::new(Map::new()),
+    }
+}
+
+fn parse_args<T: serde::de::DeserializeOwned>(args: Option<Map<String, Value>>) -> Result<T, McpError> {
+    let value = Value::Object(args.unwrap_or_default());
+    serde_json::from_value(value).map_err(|e| McpError::invalid_params(e.to_string(), None))
+}
+
+impl WorkspaceTools {
+    fn tools(&self) -> Vec<Tool> {
+        let mut tools = vec![
+            Tool::new(
+                "read_file",
+                "Read a workspace file, unsaved editor changes included",
+                schema(json!({
+                    "type": "object",
+                    "properties": { "path": { "type": "string" } },
+                    "required": ["path"],
+                })),
+            ),
+            Tool::new(
+                "search",
+                "Search the workspace for a text pattern",
+                schema(json!({
+                    "type": "object",
+                    "properties": { "pattern": { "type": "string" } },
+                    "required": ["pattern"],
+                })),
+            ),
+        ];
+
+        if self.allow_edit {
+            tools.push(Tool::new(
+                "apply_edit",
+                "Replace old_text with new_text in a file and save it. \
+                 Without old_text the whole file content is replaced.",
+                schema(json!({
+                    "type": "object",
+                    "properties": {
+                        "path": { "type": "string" },
+                        "old_text": { "type": "string" },
+                        "new_text": { "type": "string" },
+                    },
+                    "required": ["path", "new_text"],
+                })),
+            ));
+        }
+
+        if self.allow_run {
+            tools.push(Tool::new(
+                "run_task",
+                "Run a command in the workspace root and return its output",
+                schema(json!({
+                    "type": "object",
+                    "properties": { "command": { "type": "string" } },
+                    "required": ["command"],
+                })),
+            ));
+        }
+
+        tools
+    }
+
+    async fn read_file(&self, args: ReadFileArgs) -> Result<String> {
+        let path = workspace_path(&args.path, false)?;
+        let path = path.to_string_lossy().to_string();
+
+        let f2c = self.state.file2code.lock().await;
+        match f2c.get(&path) {
+            Some(code) => Ok(code.text.to_string()),
+            None => Ok(tokio::fs::read_to_string(&path).await?),
+        }
+    }
+
+    async fn search(&self, args: SearchArgs) -> Result<String> {
+        let cancel = CancellationToken::new();
+        let (mut result_rx, search) = services::start_search(
+            &crate::roots::primary(), args.pattern, PathFilter::default(), cancel.clone(),
+        );
+
+        let mut results = Vec::new();
+        while let Some(result) = result_rx.recv().await {
+            results.push(result);
+            if results.len() >= MAX_SEARCH_FILES {
+                cancel.cancel();
+                break;
+            }
+        }
+
+        search.await??;
+        Ok(serde_json::to_string(&results)?)
+    }
+
+    async fn apply_edit(&self, args: ApplyEditArgs) -> Result<String> {
+        let path = workspace_path(&args.path, true)?;
+        let path = path.to_string_lossy().to_string();
+
+        let mut f2c = self.state.file2code.lock().await;
+        let code = match get_or_create_code(&mut f2c, &path, &self.state.config) {
+            Ok(code) => code,
+            Err(_) if args.old_text.is_none() => {
+                f2c.entry(path.clone()).or_insert_with(Code::new)
+            }
+            Err(e) => return Err(e),
+        };
+
+        let text = match &args.old_text {
+            None => args.new_text.clone(),
+            Some(old_text) => {
+                let current = code.text.to_string();
+                match current.matches(old_text.as_str()).count() {
+                    0 => return Err(anyhow!("old_text not found in {}", args.path)),
+                    1 => current.replacen(old_text.as_str(), &args.new_text, 1),
+                    n => return Err(anyhow!("old_text matches {} times in {}", n, args.path)),
+                }
+            }
+        };
+
+        code.set_file_name(path.clone());
+        code.ensure_file_exists()?;
+        code.set_text(&text);
+        code.save_file()?;
+
+        let mut lsp_manager = self.state.lsp_manager.lock().await;
+        if let Some(lsp) = lsp_manager.get_for(&code.lang, &path).await {
+            lsp.did_save(&path, Some(&text));
+        }
+
+        let _ = self.io.emit("file:changed", &(path.clone(), text)).await;
+
+        Ok(format!("Saved {}", path))
+    }
+
+    async fn run_task(&self, args: RunTaskArgs) -> Result<String> {
+        let output = crate::exec::run(
+            &args.command, self.state.config.exec.as_ref(), Some(TASK_TIMEOUT),
+        ).await?;
+
+        let mut text = output.stdout;
+        text.push_str(&output.stderr);
+        crate::output::write("tasks", &text);
+
+        let status = match output.status {
+            Some(code) => format!("exit status: {}", code),
+            None => "killed by signal".to_string(),
+        };
+        if text.len() > MAX_TASK_OUTPUT {
+            let mut cut = text.len() - MAX_TASK_OUTPUT;
+            while !text.is_char_boundary(cut) {
+                cut += 1;
+            }
+            text = text[cut..].to_string();
+        }
+
+        Ok(format!("{}\n{}", status, text))
+    }
+}
+
+impl ServerHandler for WorkspaceTools {
+    fn get_info(&self) -> ServerInfo {
+        ServerInfo {
+            capabilities: ServerCapabilities::builder().enable_tools().build(),
+            server_info: Implementation {
+                name: "anycode".to_string(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+            },
+            instructions: Some("Tools operating on the workspace opened in anycode".to_string()),
+            ..Default::default()
+        }
+    }
+
+    async fn list_tools(
+        &self,
+        _request: PaginatedRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListToolsResult, McpError> {
+        Ok(ListToolsResult { tools: self.tools(), next_cursor: None })
+    }
+
+    async fn call_tool(
+        &self,
+        request: CallToolRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        info!("MCP call_tool: {}", request.name);
+
+        let result = match request.name.as_ref() {
+            "read_file" => self.read_file(parse_args(request.arguments)?).await,
+            "search" => self.search(parse_args(request.arguments)?).await,
+            "apply_edit" if self.allow_edit => self.apply_edit(parse_args(request.arguments)?).await,
+            "run_task" if self.allow_run => self.run_task(parse_args(request.arguments)?).await,
+            name => return Err(McpError::invalid_params(format!("Unknown tool {}", name), None)),
+        };
+
+        match result {
+            Ok(text) => Ok(CallToolResult::success(vec![Content::text(text)])),
+            Err(e) => {
+                error!("MCP tool {} failed: {}", request.name, e);
+                Ok(CallToolResult::error(vec![Content::text(e.to_string())]))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod mcp_tests {
+    use super::*;
+
+    #[test]
+    fn test_workspace_path_rules() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        let outside = tempfile::tempdir()?;
+        std::fs::create_dir(root.path().join("src"))?;
+        std::fs::write(root.path().join("src").join("main.rs"), "fn main() {}")?;
+        // An added root, not the directory the server runs in
+        crate::roots::add(&root.path().to_string_lossy())?;
+        let path = |relative: &str| root.path().join(relative).to_string_lossy().to_string();
+
+        assert!(workspace_path(&path("src/main.rs"), false).is_ok());
+        assert!(workspace_path(&path("src/not_created_yet.rs"), false).is_err());
+        assert!(workspace_path(&path("src/not_created_yet.rs"), true).is_ok());
+
+        assert!(workspace_path(&path("src/../../outside.txt"), true).is_err());
+        assert!(workspace_path(&outside.path().join("new.txt").to_string_lossy(), true).is_err());
+        assert!(workspace_path("/etc/hosts", false).is_err());
+        assert!(workspace_path(&path("src/secret.pem"), true).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+use anyhow::{anyhow, Result};
+use tokio::net::TcpListener;
+use tracing::info;
+
+const DEFAULT_PORT: u16 = 3000;
+/// Ports tried after ANYCODE_PORT when it is taken, unless configured
+const DEFAULT_PORT_FALLBACK: u16 = 10;
+
+/// Ports to try in order: the requested one, then up to `fallback` more
+pub fn candidate_ports(port: u16, fallback: u16) -> impl Iterator<Item = u16> {
+    (0..=fallback).map_while(move |i| port.checked_add(i))
+}
+
+/// Bind ANYCODE_PORT (3000 by default) or the next free port within the
+/// configured fallback range
+pub async fn bind(port_fallback: Option<u16>) -> Result<TcpListener> {
+    let port = match std::env::var("ANYCODE_PORT") {
+        Ok(port) => port.trim().parse::<u16>()
+            .map_err(|_| anyhow!("Invalid ANYCODE_PORT {}", port))?,
+        Err(_) => DEFAULT_PORT,
+    };
+    let fallback = port_fallback.unwrap_or(DEFAULT_PORT_FALLBACK);
+
+    let mut last_error = None;
+    for candidate in candidate_ports(port, fallback) {
+        match TcpListener::bind(("0.0.0.0", candidate)).await {
+            Ok(listener) => {
+                if candidate != port {
+                    info!("Port {} is taken, using {}", port, candidate);
+                }
+                return Ok(listener);
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(anyhow!(
+        "No free port in {}..={}: {}",
+        port, port.saturating_add(fallback),
+        last_error.map(|e| e.to_string()).unwrap_or_default()
+    ))
+}
+
+/// Open the url in the default browser of the machine running the server
+pub fn open_browser(url: &str) -> Result<()> {
+    let mut command = if cfg!(target_os = "windows") {
+        let mut command = std::process::Command::new("cmd");
+        command.args(["/C", "start", "", url]);
+        command
+    } else if cfg!(target_os = "macos") {
+        let mut command = std::process::Command::new("open");
+        command.arg(url);
+        command
+    } else {
+        let mut command = std::process::Command::new("xdg-open");
+        command.arg(url);
+        command
+    };
+
+    command.spawn()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod net_tests {
+    use super::*;
+
+    #[test]
+    fn test_candidate_ports() {
+        assert_eq!(candidate_ports(3000, 2).collect::<Vec<_>>(), [3000, 3001, 3002]);
+        assert_eq!(candidate_ports(3000, 0).collect::<Vec<_>>(), [3000]);
+        assert_eq!(candidate_ports(65534, 5).collect::<Vec<_>>(), [65534, 65535]);
+    }
+}
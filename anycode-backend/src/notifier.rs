@@ -0,0 +1,186 @@
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::{error, info};
+
+use crate::config::NotifyConfig;
+
+const DEFAULT_LONG_SEARCH_MS: u64 = 5000;
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum NotifyEvent {
+    SearchCompleted { pattern: String, matches: usize, elapsed_ms: u128 },
+    DiagnosticsThreshold { count: usize, threshold: usize },
+    TerminalExited { name: String },
+}
+
+impl NotifyEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            NotifyEvent::SearchCompleted { .. } => "search_completed",
+            NotifyEvent::DiagnosticsThreshold { .. } => "diagnostics_threshold",
+            NotifyEvent::TerminalExited { .. } => "terminal_exited",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            NotifyEvent::SearchCompleted { pattern, matches, elapsed_ms } =>
+                format!("Search for '{}' finished: {} matches in {}ms", pattern, matches, elapsed_ms),
+            NotifyEvent::DiagnosticsThreshold { count, threshold } =>
+                format!("Diagnostics count {} crossed the threshold of {}", count, threshold),
+            NotifyEvent::TerminalExited { name } =>
+                format!("Terminal {} finished", name),
+        }
+    }
+}
+
+/// Posts selected events to a webhook and/or runs a command, so long running
+/// operations can end up as Slack or desktop notifications.
+pub struct Notifier {
+    config: Option<NotifyConfig>,
+    diagnostics: Mutex<DiagnosticsCount>,
+}
+
+#[derive(Default)]
+struct DiagnosticsCount {
+    per_file: HashMap<String, usize>,
+    above: bool,
+}
+
+impl Notifier {
+    pub fn new(config: Option<NotifyConfig>) -> Self {
+        Self { config, diagnostics: Mutex::new(DiagnosticsCount::default()) }
+    }
+
+    fn wants(&self, event: &NotifyEvent) -> bool {
+        match &self.config {
+            None => false,
+            Some(conf) => match &conf.events {
+                None => true,
+                Some(events) => events.iter().any(|e| e == event.name()),
+            },
+        }
+    }
+
+    pub fn long_search(&self) -> Duration {
+        let ms = self.config.as_ref()
+            .and_then(|c| c.long_search_ms)
+            .unwrap_or(DEFAULT_LONG_SEARCH_MS);
+        Duration::from_millis(ms)
+    }
+
+    /// Update the diagnostics count of a file, returns the total when it
+    /// has just crossed the configured threshold upwards.
+    fn diagnostics_crossed(&self, uri: &str, count: usize) -> Option<(usize, usize)> {
+        let threshold = self.config.as_ref()?.diagnostics_threshold?;
+        let mut diagnostics = self.diagnostics.lock();
+
+        if count == 0 {
+            diagnostics.per_file.remove(uri);
+        } else {
+            diagnostics.per_file.insert(uri.to_string(), count);
+        }
+
+        let total: usize = diagnostics.per_file.values().sum();
+        let above = total >= threshold;
+        let crossed = above && !diagnostics.above;
+        diagnostics.above = above;
+
+        crossed.then_some((total, threshold))
+    }
+
+    pub fn observe_diagnostics(&self, uri: &str, count: usize) {
+        if let Some((count, threshold)) = self.diagnostics_crossed(uri, count) {
+            self.notify(NotifyEvent::DiagnosticsThreshold { count, threshold });
+        }
+    }
+
+    pub fn notify(&self, event: NotifyEvent) {
+        if !self.wants(&event) {
+            return;
+        }
+        let Some(conf) = self.config.clone() else { return };
+
+        info!("Notify {}: {}", event.name(), event.message());
+
+        tokio::spawn(async move {
+            if let Some(url) = &conf.webhook {
+                let payload = serde_json::json!({
+                    "source": "anycode",
+                    "text": event.message(),
+                    "data": &event,
+                });
+                let client = reqwest::Client::new();
+                if let Err(e) = client.post(url).json(&payload).send().await {
+                    error!("Failed to post notification to webhook: {}", e);
+                }
+            }
+
+            if let Some(cmd) = &conf.command
+                && let Err(e) = run_command(cmd, &event).await
+            {
+                error!("Failed to run notification command: {}", e);
+            }
+        });
+    }
+}
+
+async fn run_command(cmd: &str, event: &NotifyEvent) -> anyhow::Result<()> {
+    let args = shell_words::split(cmd)?;
+    let Some((program, args)) = args.split_first() else {
+        return Err(anyhow::anyhow!("Empty notification command"));
+    };
+
+    let message = event.message();
+    let args: Vec<String> = args.iter()
+        .map(|a| a.replace("{event}", event.name()).replace("{message}", &message))
+        .collect();
+
+    tokio::process::Command::new(program)
+        .args(args)
+        .status()
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod notifier_tests {
+    use super::*;
+
+    fn config(events: Option<Vec<String>>, threshold: Option<usize>) -> NotifyConfig {
+        NotifyConfig {
+            webhook: None,
+            command: None,
+            events,
+            long_search_ms: None,
+            diagnostics_threshold: threshold,
+        }
+    }
+
+    #[test]
+    fn test_event_filter() {
+        let notifier = Notifier::new(Some(config(Some(vec!["terminal_exited".into()]), None)));
+        assert!(notifier.wants(&NotifyEvent::TerminalExited { name: "build".into() }));
+        assert!(!notifier.wants(&NotifyEvent::DiagnosticsThreshold { count: 1, threshold: 1 }));
+
+        let disabled = Notifier::new(None);
+        assert!(!disabled.wants(&NotifyEvent::TerminalExited { name: "build".into() }));
+    }
+
+    #[test]
+    fn test_diagnostics_threshold_crossing() {
+        let notifier = Notifier::new(Some(config(None, Some(10))));
+
+        assert_eq!(notifier.diagnostics_crossed("a.rs", 6), None);
+        assert_eq!(notifier.diagnostics_crossed("b.rs", 5), Some((11, 10)));
+        // Still above, no repeated notification
+        assert_eq!(notifier.diagnostics_crossed("b.rs", 7), None);
+        // Drops below and crosses again
+        assert_eq!(notifier.diagnostics_crossed("a.rs", 0), None);
+        assert_eq!(notifier.diagnostics_crossed("a.rs", 3), Some((10, 10)));
+    }
+}
@@ -0,0 +1,199 @@
+use lsp_types::{DocumentSymbol, Range, SymbolInformation, SymbolKind, WorkspaceSymbol};
+use serde::{Deserialize, Serialize};
+
+use crate::fuzzy::fuzzy_match;
+
+// Symbols of a document as a tree for the outline panel, and workspace
+// symbols as a flat list for "Go to symbol". Servers answer document
+// symbols either nested or flat; a flat answer is nested by the ranges of
+// the symbols, a symbol becomes the child of the innermost one around it.
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct OutlineSymbol {
+    pub name: String,
+    pub detail: Option<String>,
+    pub kind: SymbolKind,
+    /// The whole symbol, e.g. a function with its body
+    pub range: Range,
+    /// The name of the symbol, where "go to" puts the cursor
+    pub selection_range: Range,
+    pub children: Vec<OutlineSymbol>,
+}
+
+/// A workspace symbol with the location as a path
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct SymbolEntry {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub container_name: Option<String>,
+    pub path: String,
+    /// Missing when the server only knows the file
+    pub range: Option<Range>,
+    pub score: i64,
+    /// From the symbol cache, the server did not confirm it yet
+    pub stale: bool,
+}
+
+fn contains(outer: &Range, inner: &Range) -> bool {
+    outer.start <= inner.start && inner.end <= outer.end
+}
+
+/// Outline of a nested answer, children sorted by position
+pub fn from_nested(symbols: Vec<DocumentSymbol>) -> Vec<OutlineSymbol> {
+    let mut outline: Vec<OutlineSymbol> = symbols.into_iter()
+        .map(|s| OutlineSymbol {
+            name: s.name,
+            detail: s.detail,
+            kind: s.kind,
+            range: s.range,
+            selection_range: s.selection_range,
+            children: from_nested(s.children.unwrap_or_default()),
+        })
+        .collect();
+    outline.sort_by_key(|s| s.range.start);
+    outline
+}
+
+/// Outline of a flat answer, nested by range containment
+pub fn from_flat(symbols: Vec<SymbolInformation>) -> Vec<OutlineSymbol> {
+    let mut symbols: Vec<OutlineSymbol> = symbols.into_iter()
+        .map(|s| OutlineSymbol {
+            name: s.name,
+            detail: None,
+            kind: s.kind,
+            range: s.location.range,
+            selection_range: s.location.range,
+            children: Vec::new(),
+        })
+        .collect();
+    // Outer symbols first: by start, the longer one first on the same start
+    symbols.sort_by(|a, b| a.range.start.cmp(&b.range.start).then(b.range.end.cmp(&a.range.end)));
+
+    // The open symbols around the current position, innermost last
+    let mut stack: Vec<OutlineSymbol> = Vec::new();
+    let mut outline = Vec::new();
+    for symbol in symbols {
+        while let Some(top) = stack.last() && !contains(&top.range, &symbol.range) {
+            close(&mut stack, &mut outline);
+        }
+        stack.push(symbol);
+    }
+    while !stack.is_empty() {
+        close(&mut stack, &mut outline);
+    }
+    outline
+}
+
+/// Move the innermost open symbol into its parent or the outline
+fn close(stack: &mut Vec<OutlineSymbol>, outline: &mut Vec<OutlineSymbol>) {
+    let Some(symbol) = stack.pop() else { return };
+    match stack.last_mut() {
+        Some(parent) => parent.children.push(symbol),
+        None => outline.push(symbol),
+    }
+}
+
+/// Rank the symbols servers answered with the cached ones they did not,
+/// the cached ones marked stale. Ranked by how well their name matches the
+/// query, servers differ in how they filter.
+pub fn rank_with_cached(fresh: Vec<WorkspaceSymbol>, cached: Vec<WorkspaceSymbol>, query: &str, limit: usize) -> Vec<SymbolEntry> {
+    let keys: std::collections::HashSet<_> = fresh.iter().map(crate::symbol_cache::key).collect();
+    let cached = cached.into_iter().filter(|s| !keys.contains(&crate::symbol_cache::key(s)));
+    rank_symbols(fresh.into_iter().map(|s| (s, false)).chain(cached.map(|s| (s, true))), query, limit)
+}
+
+fn rank_symbols(symbols: impl Iterator<Item = (WorkspaceSymbol, bool)>, query: &str, limit: usize) -> Vec<SymbolEntry> {
+    let mut entries: Vec<SymbolEntry> = symbols
+        .filter_map(|(s, stale)| {
+            let (uri, range) = match s.location {
+                lsp_types::OneOf::Left(location) => (location.uri, Some(location.range)),
+                lsp_types::OneOf::Right(location) => (location.uri, None),
+            };
+            let path = crate::paths::uri_to_path(uri.as_str())?;
+            let score = fuzzy_match(query, &s.name).map_or(0, |m| m.score);
+            Some(SymbolEntry { name: s.name, kind: s.kind, container_name: s.container_name, path, range, score, stale })
+        })
+        .collect();
+
+    entries.sort_by(|a, b| b.score.cmp(&a.score)
+        .then(a.name.len().cmp(&b.name.len()))
+        .then(a.path.cmp(&b.path))
+        .then(a.range.map(|r| r.start).cmp(&b.range.map(|r| r.start))));
+    entries.truncate(limit);
+    entries
+}
+
+#[cfg(test)]
+mod outline_tests {
+    use super::*;
+    use lsp_types::Position;
+    use serde_json::json;
+
+    fn range(start: u32, end: u32) -> serde_json::Value {
+        json!({ "start": { "line": start, "character": 0 }, "end": { "line": end, "character": 0 } })
+    }
+
+    fn names(outline: &[OutlineSymbol]) -> Vec<&str> {
+        outline.iter().map(|s| s.name.as_str()).collect()
+    }
+
+    fn info(name: &str, kind: SymbolKind, start: u32, end: u32) -> SymbolInformation {
+        serde_json::from_value(json!({
+            "name": name,
+            "kind": kind,
+            "location": { "uri": "file:///w/a.rs", "range": range(start, end) },
+        })).unwrap()
+    }
+
+    #[test]
+    fn test_from_flat() {
+        let outline = from_flat(vec![
+            info("helper", SymbolKind::FUNCTION, 20, 25),
+            info("new", SymbolKind::METHOD, 3, 6),
+            info("Parser", SymbolKind::STRUCT, 0, 10),
+            info("parse", SymbolKind::METHOD, 7, 9),
+            info("pos", SymbolKind::VARIABLE, 8, 8),
+        ]);
+        assert_eq!(names(&outline), ["Parser", "helper"]);
+        assert_eq!(names(&outline[0].children), ["new", "parse"]);
+        assert_eq!(names(&outline[0].children[1].children), ["pos"]);
+        assert_eq!(outline[1].range.start, Position::new(20, 0));
+    }
+
+    #[test]
+    fn test_from_nested() {
+        let symbol = |name: &str, start: u32, end: u32, children: serde_json::Value| json!({
+            "name": name,
+            "kind": SymbolKind::FUNCTION,
+            "range": range(start, end),
+            "selectionRange": range(start, start),
+            "children": children,
+        });
+        let nested: Vec<DocumentSymbol> = serde_json::from_value(json!([
+            symbol("b", 10, 12, json!([])),
+            symbol("a", 0, 8, json!([symbol("a2", 5, 6, json!([])), symbol("a1", 1, 2, json!([]))])),
+        ])).unwrap();
+
+        let outline = from_nested(nested);
+        assert_eq!(names(&outline), ["a", "b"]);
+        assert_eq!(names(&outline[0].children), ["a1", "a2"]);
+    }
+
+    #[test]
+    fn test_rank_workspace_symbols() {
+        let symbol = |name: &str| -> WorkspaceSymbol {
+            serde_json::from_value(json!({
+                "name": name,
+                "kind": SymbolKind::FUNCTION,
+                "location": { "uri": "file:///w/a.rs", "range": range(1, 2) },
+            })).unwrap()
+        };
+        let ranked = rank_with_cached(vec![symbol("reparse_all"), symbol("parse"), symbol("unrelated")], vec![], "parse", 2);
+        assert_eq!(ranked.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(), ["parse", "reparse_all"]);
+        assert_eq!(ranked[0].path, "/w/a.rs");
+
+        let ranked = rank_with_cached(vec![symbol("parse")], vec![symbol("parse"), symbol("parser")], "parse", 10);
+        let found: Vec<(&str, bool)> = ranked.iter().map(|s| (s.name.as_str(), s.stale)).collect();
+        assert_eq!(found, [("parse", false), ("parser", true)]);
+    }
+}
@@ -0,0 +1,112 @@
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::OnceLock;
+use tokio::sync::mpsc;
+
+/// Lines kept per channel for clients subscribing later
+const MAX_CHANNEL_LINES: usize = 1000;
+
+#[derive(Debug, Serialize, Clone)]
+pub struct OutputLine {
+    pub channel: String,
+    pub time: i64,
+    pub text: String,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ChannelInfo {
+    pub name: String,
+    pub lines: usize,
+}
+
+#[derive(Default)]
+struct Registry {
+    channels: Mutex<HashMap<String, VecDeque<OutputLine>>>,
+    sender: OnceLock<mpsc::Sender<OutputLine>>,
+}
+
+static REGISTRY: OnceLock<Registry> = OnceLock::new();
+
+fn registry() -> &'static Registry {
+    REGISTRY.get_or_init(Registry::default)
+}
+
+/// Set the channel new lines are forwarded to, main emits them to the
+/// sockets subscribed to the output channel.
+pub fn init(sender: mpsc::Sender<OutputLine>) {
+    let _ = registry().sender.set(sender);
+}
+
+/// Socket.io room of an output channel
+pub fn room(channel: &str) -> String {
+    format!("output:{}", channel)
+}
+
+/// Append text to a labeled output channel, like the Output panel of an
+/// editor. LSP servers, tasks, the watcher and the indexer write here
+/// instead of stderr. Multi-line text is split into lines.
+pub fn write(channel: &str, text: &str) {
+    let registry = registry();
+    let time = chrono::Utc::now().timestamp_millis();
+
+    let mut channels = registry.channels.lock();
+    let lines = channels.entry(channel.to_string()).or_default();
+
+    for text in text.lines().filter(|l| !l.trim().is_empty()) {
+        let line = OutputLine { channel: channel.to_string(), time, text: text.to_string() };
+
+        if lines.len() == MAX_CHANNEL_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(line.clone());
+
+        if let Some(sender) = registry.sender.get() {
+            let _ = sender.try_send(line);
+        }
+    }
+}
+
+pub fn list() -> Vec<ChannelInfo> {
+    let channels = registry().channels.lock();
+    let mut list: Vec<ChannelInfo> = channels.iter()
+        .map(|(name, lines)| ChannelInfo { name: name.clone(), lines: lines.len() })
+        .collect();
+    list.sort_by(|a, b| a.name.cmp(&b.name));
+    list
+}
+
+pub fn lines(channel: &str) -> Vec<OutputLine> {
+    let channels = registry().channels.lock();
+    channels.get(channel)
+        .map(|lines| lines.iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod output_tests {
+    use super::*;
+
+    #[test]
+    fn test_write_and_list() {
+        write("test:output", "first\nsecond\n\n");
+        write("test:output", "third");
+
+        let lines = lines("test:output");
+        let texts: Vec<&str> = lines.iter().map(|l| l.text.as_str()).collect();
+        assert_eq!(texts, ["first", "second", "third"]);
+
+        assert!(list().iter().any(|c| c.name == "test:output" && c.lines == 3));
+    }
+
+    #[test]
+    fn test_channel_is_bounded() {
+        for i in 0..MAX_CHANNEL_LINES + 10 {
+            write("test:bounded", &i.to_string());
+        }
+
+        let lines = lines("test:bounded");
+        assert_eq!(lines.len(), MAX_CHANNEL_LINES);
+        assert_eq!(lines[0].text, "10");
+    }
+}
@@ -0,0 +1,184 @@
+use lsp_types::{CompletionItem, CompletionItemKind, CompletionTextEdit, Position, Range, TextEdit};
+use ropey::Rope;
+use std::collections::{BTreeMap, BTreeSet};
+
+// Completion of workspace paths inside strings, for every language and
+// without a language server: import paths, config values, asset names.
+// A string counts as a path once it starts with `.` or has a `/` in it.
+// `./` and `../` are resolved from the directory of the edited file, other
+// paths from the workspace root. The entries come from the file index,
+// one level at a time, directories ending with `/`.
+
+pub const MAX_PATH_COMPLETIONS: usize = 50;
+
+/// The path typed in a string up to the cursor
+#[derive(Debug, PartialEq)]
+pub struct PathPrefix {
+    pub text: String,
+    /// UTF-16 column of the last component, replaced by the completion
+    pub name_start: usize,
+}
+
+impl PathPrefix {
+    fn name(&self) -> &str {
+        self.text.rfind('/').map_or(self.text.as_str(), |i| &self.text[i + 1..])
+    }
+
+    fn dir(&self) -> &str {
+        self.text.rfind('/').map_or("", |i| &self.text[..i])
+    }
+}
+
+/// The path before `column` (UTF-16) when the cursor is in a string that
+/// looks like one
+pub fn path_prefix(line: &str, column: usize) -> Option<PathPrefix> {
+    let mut units = 0;
+    let before: String = line.chars()
+        .take_while(|c| {
+            units += c.len_utf16();
+            units <= column
+        })
+        .collect();
+
+    let quote_at = before.rfind(['"', '\'', '`'])?;
+    let quote = before[quote_at..].chars().next()?;
+    // An even count means the last quote closed a string
+    if before.matches(quote).count() % 2 == 0 {
+        return None;
+    }
+    let text = &before[quote_at + 1..];
+    if text.is_empty() || text.contains(char::is_whitespace) || !(text.starts_with('.') || text.contains('/')) {
+        return None;
+    }
+
+    let prefix = PathPrefix { text: text.to_string(), name_start: 0 };
+    let name_start = column - prefix.name().encode_utf16().count();
+    Some(PathPrefix { name_start, ..prefix })
+}
+
+/// Workspace directory the prefix lists, None above the root. `file` is
+/// the edited file relative to the root.
+fn base_dir(prefix: &PathPrefix, file: &str) -> Option<String> {
+    let relative = prefix.text.starts_with("./") || prefix.text.starts_with("../");
+    let mut parts: Vec<&str> = match relative {
+        true => file.split('/').collect(),
+        false => Vec::new(),
+    };
+    if relative {
+        parts.pop();
+    }
+    for part in prefix.dir().split('/') {
+        match part {
+            "" | "." => {}
+            ".." => { parts.pop()?; }
+            part => parts.push(part),
+        }
+    }
+    Some(parts.join("/"))
+}
+
+/// Entries of the prefix's directory among `files` starting with the
+/// typed name, dot files only when the name starts with a dot
+pub fn completions(
+    files: &BTreeSet<String>,
+    prefix: &PathPrefix,
+    file: &str,
+    row: usize,
+    column: usize,
+    limit: usize,
+) -> Vec<CompletionItem> {
+    let Some(dir) = base_dir(prefix, file) else { return Vec::new() };
+    let start = match dir.is_empty() {
+        true => String::new(),
+        false => format!("{}/", dir),
+    };
+    let name = prefix.name().to_lowercase();
+
+    let mut entries: BTreeMap<&str, bool> = BTreeMap::new();
+    for path in files.range(start.clone()..).take_while(|p| p.starts_with(&start)) {
+        let rest = &path[start.len()..];
+        let (entry, is_dir) = match rest.find('/') {
+            Some(i) => (&rest[..i], true),
+            None => (rest, false),
+        };
+        if (entry.starts_with('.') && !name.starts_with('.')) || !entry.to_lowercase().starts_with(&name) {
+            continue;
+        }
+        entries.insert(entry, is_dir);
+        if entries.len() >= limit {
+            break;
+        }
+    }
+
+    let row = row as u32;
+    let range = Range::new(Position::new(row, prefix.name_start as u32), Position::new(row, column as u32));
+    entries.into_iter()
+        .map(|(entry, is_dir)| {
+            let label = match is_dir {
+                true => format!("{}/", entry),
+                false => entry.to_string(),
+            };
+            CompletionItem {
+                kind: Some(if is_dir { CompletionItemKind::FOLDER } else { CompletionItemKind::FILE }),
+                detail: Some(format!("{}{}", start, entry)),
+                filter_text: Some(entry.to_string()),
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit { range, new_text: label.clone() })),
+                label,
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+/// Path completions at a position of a document, from the file index
+pub fn complete(text: &Rope, row: usize, column: usize, file: &str) -> Vec<CompletionItem> {
+    if row >= text.len_lines() {
+        return Vec::new();
+    }
+    let line = text.line(row).to_string();
+    let Some(prefix) = path_prefix(line.trim_end_matches(['\n', '\r']), column) else { return Vec::new() };
+    let file = file.replace('\\', "/");
+    crate::file_index::with_files(|files| completions(files, &prefix, &file, row, column, MAX_PATH_COMPLETIONS))
+}
+
+#[cfg(test)]
+mod path_completion_tests {
+    use super::*;
+
+    #[test]
+    fn test_path_prefix() {
+        let prefix = path_prefix("import x from './comp/But", 25).unwrap();
+        assert_eq!(prefix.text, "./comp/But");
+        assert_eq!(prefix.name_start, 22);
+
+        assert_eq!(path_prefix("load(\"assets/", 13).unwrap().name_start, 13);
+        assert!(path_prefix("let s = \"hello world", 20).is_none());
+        assert!(path_prefix("let s = \"word", 13).is_none());
+        assert!(path_prefix("\"./a\" + ./b", 11).is_none());
+        assert!(path_prefix("no strings/here", 15).is_none());
+    }
+
+    #[test]
+    fn test_completions() {
+        let files: BTreeSet<String> = [
+            "src/main.rs", "src/handlers/io.rs", "src/handlers/lsp.rs", "src/.hidden", "README.md",
+        ].iter().map(|s| s.to_string()).collect();
+        let labels = |text: &str, file: &str| -> Vec<String> {
+            let prefix = path_prefix(&format!("\"{}", text), text.len() + 1).unwrap();
+            completions(&files, &prefix, file, 0, text.len() + 1, 10).into_iter().map(|c| c.label).collect()
+        };
+
+        assert_eq!(labels("src/", "README.md"), ["handlers/", "main.rs"]);
+        assert_eq!(labels("./h", "src/main.rs"), ["handlers/"]);
+        assert_eq!(labels("../handlers/L", "src/handlers/io.rs"), ["lsp.rs"]);
+        assert_eq!(labels("./.", "src/main.rs"), [".hidden"]);
+        assert_eq!(labels("./R", "main.rs"), ["README.md"]);
+        assert!(labels("../../x", "src/main.rs").is_empty());
+
+        let prefix = path_prefix("\"src/ma", 7).unwrap();
+        let item = completions(&files, &prefix, "a.rs", 3, 7, 10).remove(0);
+        let Some(CompletionTextEdit::Edit(edit)) = item.text_edit else { panic!("no text edit") };
+        assert_eq!(edit.range, Range::new(Position::new(3, 5), Position::new(3, 7)));
+        assert_eq!(edit.new_text, "main.rs");
+    }
+}
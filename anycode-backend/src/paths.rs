@@ -0,0 +1,269 @@
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+
+// Path helpers that work the same on unix and Windows hosts. Anything that
+// builds, resolves or converts paths should go through here instead of
+// formatting strings with `/`.
+
+/// Join a child name to a parent directory. An empty parent or `.` means
+/// the workspace root (the current directory).
+pub fn join_child(parent: &str, name: &str) -> PathBuf {
+    match parent.trim() {
+        "" | "." | "./" | ".\\" => PathBuf::from(name),
+        parent => Path::new(parent).join(name),
+    }
+}
+
+/// Resolve a path against the current directory unless it is already
+/// absolute (`/x` on unix, `C:\x` or `\\server\share\x` on Windows).
+pub fn absolute(path: &Path) -> PathBuf {
+    if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir()
+            .map(|dir| dir.join(path))
+            .unwrap_or_else(|_| path.to_path_buf())
+    }
+}
+
+/// Drop the `\\?\` verbatim prefix `canonicalize` produces on Windows,
+/// editors, LSP servers and users expect plain `C:\x` paths.
+pub fn strip_verbatim(path: &str) -> String {
+    if let Some(unc) = path.strip_prefix(r"\\?\UNC\") {
+        format!(r"\\{}", unc)
+    } else if let Some(rest) = path.strip_prefix(r"\\?\") {
+        rest.to_string()
+    } else {
+        path.to_string()
+    }
+}
+
+/// `file://` uri of an absolute path as LSP servers expect it:
+/// `/a/b` -> `file:///a/b`, `C:\a\b` -> `file:///C:/a/b`,
+/// `\\server\share\a` -> `file://server/share/a`
+pub fn file_uri(path: &str) -> String {
+    let path = strip_verbatim(path);
+
+    if let Some(unc) = path.strip_prefix(r"\\") {
+        return format!("file://{}", encode_uri_path(&unc.replace('\\', "/")));
+    }
+
+    let path = path.replace('\\', "/");
+    if has_drive_letter(&path) {
+        format!("file:///{}", encode_uri_path(&path))
+    } else {
+        format!("file://{}", encode_uri_path(&path))
+    }
+}
+
+/// Inverse of `file_uri`, returns None for non file uris
+pub fn uri_to_path(uri: &str) -> Option<String> {
+    let rest = uri.strip_prefix("file://")?;
+    let decoded = decode_uri_path(rest);
+
+    // file:///C:/a -> C:/a, file://server/share -> //server/share
+    let path = match decoded.strip_prefix('/') {
+        Some(local) if has_drive_letter(local) => local.to_string(),
+        Some(_) => decoded.clone(),
+        None => format!("//{}", decoded),
+    };
+
+    if cfg!(windows) {
+        Some(path.replace('/', "\\"))
+    } else {
+        Some(path)
+    }
+}
+
+fn has_drive_letter(path: &str) -> bool {
+    let bytes = path.as_bytes();
+    bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':'
+}
+
+/// Resolved paths kept by `canonical_case`, cleared when full
+const MAX_CASES: usize = 10_000;
+
+static CASES: Mutex<Option<HashMap<PathBuf, PathBuf>>> = Mutex::new(None);
+
+/// Whether the file system of the workspace ignores case, as macOS and
+/// Windows do by default. Probed once on the current directory.
+pub fn is_case_insensitive() -> bool {
+    static INSENSITIVE: OnceLock<bool> = OnceLock::new();
+    *INSENSITIVE.get_or_init(|| std::env::current_dir().is_ok_and(|dir| probe_case_insensitive(&dir)))
+}
+
+/// Look `dir` up with its letters in the other case
+fn probe_case_insensitive(dir: &Path) -> bool {
+    let path = dir.to_string_lossy();
+    let swapped: String = path.chars()
+        .map(|c| if c.is_ascii_lowercase() { c.to_ascii_uppercase() } else { c.to_ascii_lowercase() })
+        .collect();
+    swapped != path && same_file(dir, Path::new(&swapped))
+}
+
+#[cfg(unix)]
+fn same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (std::fs::metadata(a), std::fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn same_file(a: &Path, b: &Path) -> bool {
+    a.exists() && b.exists()
+}
+
+/// `path` with the names as they are written on disk, so a file is known
+/// under one name on the file systems that ignore case. Names not found
+/// are kept as given.
+pub fn canonical_case(path: &Path) -> PathBuf {
+    if let Some(cached) = CASES.lock().as_ref().and_then(|cases| cases.get(path)) {
+        return cached.clone();
+    }
+    let resolved = resolve_case(path);
+    let mut cases = CASES.lock();
+    let cases = cases.get_or_insert_with(HashMap::new);
+    if cases.len() >= MAX_CASES {
+        cases.clear();
+    }
+    cases.insert(path.to_path_buf(), resolved.clone());
+    resolved
+}
+
+fn resolve_case(path: &Path) -> PathBuf {
+    let mut resolved = PathBuf::new();
+    for component in path.components() {
+        let Component::Normal(name) = component else {
+            resolved.push(component);
+            continue;
+        };
+        // An exact match wins over one that differs in case
+        let on_disk = std::fs::read_dir(&resolved).ok().and_then(|entries| {
+            let names: Vec<OsString> = entries.flatten().map(|e| e.file_name()).collect();
+            if names.iter().any(|n| n == name) {
+                return None;
+            }
+            let folded = name.to_string_lossy().to_lowercase();
+            names.into_iter().find(|n| n.to_string_lossy().to_lowercase() == folded)
+        });
+        resolved.push(on_disk.as_deref().unwrap_or(name));
+    }
+    resolved
+}
+
+/// Drop the names resolved so far, a file renamed, created or removed by
+/// file:rename or outside, as the watcher sees it, may change their case
+pub fn forget_cases() {
+    if let Some(cases) = CASES.lock().as_mut() {
+        cases.clear();
+    }
+}
+
+fn encode_uri_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for c in path.chars() {
+        match c {
+            ' ' => encoded.push_str("%20"),
+            '#' => encoded.push_str("%23"),
+            '?' => encoded.push_str("%3F"),
+            '%' => encoded.push_str("%25"),
+            _ => encoded.push(c),
+        }
+    }
+    encoded
+}
+
+fn decode_uri_path(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len()
+            && let Ok(hex) = std::str::from_utf8(&bytes[i + 1..i + 3])
+            && let Ok(byte) = u8::from_str_radix(hex, 16)
+        {
+            decoded.push(byte);
+            i += 3;
+            continue;
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+#[cfg(test)]
+mod paths_tests {
+    use super::*;
+
+    #[test]
+    fn test_join_child() {
+        assert_eq!(join_child("", "a.rs"), PathBuf::from("a.rs"));
+        assert_eq!(join_child("./", "a.rs"), PathBuf::from("a.rs"));
+        assert_eq!(join_child("src", "a.rs"), Path::new("src").join("a.rs"));
+    }
+
+    #[test]
+    fn test_file_uri() {
+        assert_eq!(file_uri("/home/me/a b.rs"), "file:///home/me/a%20b.rs");
+        assert_eq!(file_uri(r"C:\Users\me\main.rs"), "file:///C:/Users/me/main.rs");
+        assert_eq!(file_uri(r"\\?\C:\Users\me\main.rs"), "file:///C:/Users/me/main.rs");
+        assert_eq!(file_uri(r"\\server\share\main.rs"), "file://server/share/main.rs");
+        assert_eq!(file_uri(r"\\?\UNC\server\share\main.rs"), "file://server/share/main.rs");
+    }
+
+    #[test]
+    fn test_strip_verbatim() {
+        assert_eq!(strip_verbatim(r"\\?\D:\work"), r"D:\work");
+        assert_eq!(strip_verbatim(r"\\?\UNC\srv\x"), r"\\srv\x");
+        assert_eq!(strip_verbatim("/tmp/x"), "/tmp/x");
+    }
+
+    #[test]
+    fn test_canonical_case() -> std::io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("Src").join("Foo.rs");
+        std::fs::create_dir(dir.path().join("Src"))?;
+        std::fs::write(&file, "")?;
+
+        assert_eq!(canonical_case(&dir.path().join("src").join("FOO.rs")), file);
+        assert_eq!(resolve_case(&file), file);
+        // Missing names are kept
+        assert_eq!(resolve_case(&dir.path().join("src").join("new.rs")), dir.path().join("Src").join("new.rs"));
+
+        // Created under another case after it was resolved
+        let created = dir.path().join("Src").join("Bar.rs");
+        assert_eq!(canonical_case(&dir.path().join("src").join("bar.rs")), dir.path().join("Src").join("bar.rs"));
+        std::fs::write(&created, "")?;
+        forget_cases();
+        assert_eq!(canonical_case(&dir.path().join("src").join("bar.rs")), created);
+        Ok(())
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn test_uri_to_path_unix() {
+        assert_eq!(uri_to_path("file:///home/me/a%20b.rs").as_deref(), Some("/home/me/a b.rs"));
+        assert_eq!(uri_to_path("https://example.com"), None);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_uri_to_path_windows() {
+        assert_eq!(uri_to_path("file:///C:/Users/me/main.rs").as_deref(), Some(r"C:\Users\me\main.rs"));
+        assert_eq!(uri_to_path("file://server/share/a.rs").as_deref(), Some(r"\\server\share\a.rs"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_absolute_windows() {
+        assert!(absolute(Path::new(r"C:\x\y")).is_absolute());
+        assert!(absolute(Path::new(r"\\server\share\y")).is_absolute());
+        assert!(absolute(Path::new(r"x\y")).is_absolute());
+    }
+}
@@ -0,0 +1,190 @@
+use axum::extract::Request;
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::{Json, Router};
+use serde::Deserialize;
+use serde_json::json;
+use socketioxide::adapter::LocalAdapter;
+use socketioxide::extract::{AckSender, SocketRef};
+use socketioxide::handler::MessageHandler;
+
+use crate::config::{AuthConfig, Scope};
+
+// Scopes of the connections, given at connect time from the token of the
+// auth payload or the defaults of the config. Handlers are registered
+// through a `Guard`, events the connection has no scope for get an error
+// ack and `server:forbidden` instead of reaching their handler. The REST
+// routes are guarded the same way with the bearer token of the request,
+// see `guard_routes`.
+
+/// Auth payload of a connection
+#[derive(Debug, Deserialize, Default)]
+pub struct AuthData {
+    pub token: Option<String>,
+}
+
+/// Scopes of a connection with `token`, an error for an unknown token
+pub fn authorize(config: Option<&AuthConfig>, token: Option<&str>) -> Result<Vec<Scope>, String> {
+    let Some(config) = config else { return Ok(Scope::ALL.to_vec()) };
+    match token {
+        Some(token) => config.tokens.iter()
+            .find(|t| t.token == token)
+            .map(|t| t.scopes.clone())
+            .ok_or_else(|| "Unknown token".to_string()),
+        None => Ok(config.default_scopes.clone().unwrap_or_else(|| Scope::ALL.to_vec())),
+    }
+}
+
+/// Scopes an event needs
+pub fn required(event: &str) -> &'static [Scope] {
+    use Scope::*;
+    match event {
+        "lsp:code_action_apply" | "lsp:rename" => &[Lsp, FsWrite],
+        _ if event.starts_with("lsp:") => &[Lsp],
+        _ if event.starts_with("terminal:") => &[Terminal],
+        _ if event.starts_with("repl:") => &[Exec],
+        "run:command" => &[Exec],
+        // Roots widen what every connection can read and write
+        "admin:subscribe" | "server:profile" | "server:setPowerMode"
+        | "workspace:addRoot" | "workspace:removeRoot" | "workspace:open" | "workspace:bootstrap:open" => &[Admin],
+        "workspace:bootstrap:clone" => &[FsWrite, Exec],
+        "file:change" | "file:undo" | "file:redo" | "file:save" | "file:set" | "file:create" | "file:makeWritable"
+        | "file:restoreFromBuffer" | "file:rename" | "nav:createAlternate" | "collab:op" | "edit:batch" | "rename:apply"
+        | "search:replace" | "search:export" | "git:checkout" | "workspace:bootstrap" | "ignore:set"
+        | "languages:setRules" | "problems:suppress" | "problems:unsuppress"
+        | "audit:fixTextFormat" | "run:saveOutput" => &[FsWrite],
+        _ if event.starts_with("server:") || event.starts_with("output:")
+            || event.starts_with("notify:") => &[],
+        _ => &[FsRead],
+    }
+}
+
+/// The first scope of `event` missing from `scopes`
+pub fn missing(scopes: &[Scope], event: &str) -> Option<Scope> {
+    required(event).iter().find(|s| !scopes.contains(s)).copied()
+}
+
+/// Registers the handlers of a connection with its scopes
+pub struct Guard<'a> {
+    socket: &'a SocketRef,
+    scopes: Vec<Scope>,
+}
+
+impl<'a> Guard<'a> {
+    pub fn new(socket: &'a SocketRef, scopes: Vec<Scope>) -> Self {
+        Self { socket, scopes }
+    }
+
+    /// Register `handler` for `event`, or a refusal when a scope is missing
+    pub fn on<H, T>(&self, event: &'static str, handler: H)
+    where
+        H: MessageHandler<LocalAdapter, T>,
+        T: Send + Sync + 'static,
+    {
+        let Some(scope) = missing(&self.scopes, event) else {
+            self.socket.on(event, handler);
+            return;
+        };
+        let error = format!("Not allowed: {} needs the {} scope", event, scope.as_str());
+        self.socket.on(event, move |socket: SocketRef, ack: AckSender| {
+            let error = error.clone();
+            async move {
+                ack.send(&json!({ "error": error, "success": false })).ok();
+                socket.emit("server:forbidden", &json!({ "event": event, "scope": scope })).ok();
+            }
+        });
+    }
+}
+
+/// Token of a REST request, from `Authorization: Bearer <token>`
+fn bearer(request: &Request) -> Option<&str> {
+    request.headers().get(header::AUTHORIZATION)?
+        .to_str().ok()?
+        .strip_prefix("Bearer ")
+}
+
+/// Refuse the requests to the routes of `router` without `scopes`: 401
+/// for an unknown token, 403 for a missing scope. Requests without a token
+/// get the default scopes, like connections.
+pub fn guard_routes<S>(router: Router<S>, auth: Option<AuthConfig>, scopes: &'static [Scope]) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    router.route_layer(axum::middleware::from_fn(move |request: Request, next: Next| {
+        let granted = authorize(auth.as_ref(), bearer(&request));
+        async move {
+            let (status, error) = match granted {
+                Err(e) => (StatusCode::UNAUTHORIZED, e),
+                Ok(granted) => match scopes.iter().find(|s| !granted.contains(s)) {
+                    Some(scope) => (StatusCode::FORBIDDEN, format!("Not allowed: needs the {} scope", scope.as_str())),
+                    None => return next.run(request).await,
+                },
+            };
+            (status, Json(json!({ "error": error, "success": false }))).into_response()
+        }
+    }))
+}
+
+#[cfg(test)]
+mod permissions_tests {
+    use super::*;
+    use crate::config::AuthToken;
+
+    #[test]
+    fn test_required() {
+        assert_eq!(required("file:open"), [Scope::FsRead]);
+        assert_eq!(required("file:save"), [Scope::FsWrite]);
+        assert_eq!(required("lsp:hover"), [Scope::Lsp]);
+        assert_eq!(required("terminal:input"), [Scope::Terminal]);
+        assert_eq!(required("repl:eval"), [Scope::Exec]);
+        assert!(required("server:status").is_empty());
+        assert_eq!(required("admin:subscribe"), [Scope::Admin]);
+        assert_eq!(required("server:profile"), [Scope::Admin]);
+        assert_eq!(required("workspace:addRoot"), [Scope::Admin]);
+        assert_eq!(required("workspace:bootstrap"), [Scope::FsWrite]);
+        assert_eq!(required("workspace:bootstrap:clone"), [Scope::FsWrite, Scope::Exec]);
+
+        let lsp = [Scope::Lsp];
+        assert_eq!(missing(&lsp, "lsp:completion"), None);
+        assert_eq!(missing(&lsp, "lsp:code_action_apply"), Some(Scope::FsWrite));
+    }
+
+    #[test]
+    fn test_authorize() {
+        assert_eq!(authorize(None, Some("any")).unwrap(), Scope::ALL);
+
+        let config = AuthConfig {
+            default_scopes: Some(vec![Scope::FsRead]),
+            tokens: vec![AuthToken { token: "secret".to_string(), scopes: vec![Scope::FsRead, Scope::Lsp] }],
+        };
+        assert_eq!(authorize(Some(&config), None).unwrap(), [Scope::FsRead]);
+        assert_eq!(authorize(Some(&config), Some("secret")).unwrap(), [Scope::FsRead, Scope::Lsp]);
+        assert!(authorize(Some(&config), Some("guess")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_guard_routes() {
+        use axum::body::Body;
+        use tower::ServiceExt;
+
+        let config = AuthConfig {
+            default_scopes: Some(vec![Scope::FsRead]),
+            tokens: vec![AuthToken { token: "writer".to_string(), scopes: vec![Scope::FsRead, Scope::FsWrite] }],
+        };
+        let router = Router::new().route("/save", axum::routing::post(|| async { "saved" }));
+        let router = guard_routes(router, Some(config), &[Scope::FsWrite]);
+        let status = |token: Option<&str>| {
+            let mut request = Request::post("/save");
+            if let Some(token) = token {
+                request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+            }
+            let router = router.clone();
+            async move { router.oneshot(request.body(Body::empty()).unwrap()).await.unwrap().status() }
+        };
+
+        assert_eq!(status(None).await, StatusCode::FORBIDDEN);
+        assert_eq!(status(Some("guess")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(Some("writer")).await, StatusCode::OK);
+    }
+}
@@ -0,0 +1,70 @@
+use std::future::Future;
+use std::sync::OnceLock;
+use tokio::runtime::{Builder, Runtime};
+use tokio::task::JoinHandle;
+
+/// Dedicated runtime for heavy background work (search, indexing, hashing).
+/// It runs on its own small set of threads, so a large directory walk can't
+/// starve the runtime that serves typing, LSP and terminal traffic.
+static POOL: OnceLock<Runtime> = OnceLock::new();
+
+fn default_workers() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| (n.get() / 2).max(1))
+        .unwrap_or(1)
+}
+
+fn build(workers: usize) -> Runtime {
+    Builder::new_multi_thread()
+        .worker_threads(workers.max(1))
+        .thread_name("anycode-bg")
+        .enable_all()
+        .build()
+        .expect("Failed to build background runtime")
+}
+
+/// Initialize the background pool with the given number of workers.
+/// Has no effect if the pool was already started.
+pub fn init(workers: Option<usize>) {
+    let workers = workers.unwrap_or_else(default_workers);
+    POOL.get_or_init(|| build(workers));
+}
+
+pub fn runtime() -> &'static Runtime {
+    POOL.get_or_init(|| build(default_workers()))
+}
+
+/// Spawn a future on the background pool. Tasks spawned from inside it
+/// with `tokio::spawn` stay on the pool as well.
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    runtime().spawn(future)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_spawn_runs_on_background_threads() {
+        let name = spawn(async {
+            std::thread::current().name().map(|n| n.to_string())
+        }).await.unwrap();
+
+        assert_eq!(name.as_deref(), Some("anycode-bg"));
+    }
+
+    #[tokio::test]
+    async fn test_nested_spawn_stays_on_pool() {
+        let name = spawn(async {
+            tokio::spawn(async {
+                std::thread::current().name().map(|n| n.to_string())
+            }).await.unwrap()
+        }).await.unwrap();
+
+        assert_eq!(name.as_deref(), Some("anycode-bg"));
+    }
+}
@@ -0,0 +1,92 @@
+use parking_lot::Mutex;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tracing::info;
+
+use crate::config::{Config, PowerMode};
+
+// Power mode of the background work, set by `power_mode` in the config
+// and `server:setPowerMode`. On battery the workspace scan and duplicate
+// hashing pause between steps so they stay off the fans, and rescans of
+// the file index are put off until the mode is back to performance.
+// Requests of the clients run at full speed in both modes.
+
+/// Pause of a background step (a directory walked, a file hashed) on battery
+const BATTERY_STEP_PAUSE: Duration = Duration::from_millis(5);
+
+static BATTERY: AtomicBool = AtomicBool::new(false);
+/// Roots of the rescans put off on battery
+static DEFERRED_RESCANS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+pub fn init(config: &Config) {
+    set(config.power_mode.unwrap_or_default());
+}
+
+pub fn mode() -> PowerMode {
+    if BATTERY.load(Ordering::Relaxed) { PowerMode::Battery } else { PowerMode::Performance }
+}
+
+/// Switch the mode, back on performance the deferred rescans run. Returns
+/// whether the mode changed.
+pub fn set(mode: PowerMode) -> bool {
+    let battery = mode == PowerMode::Battery;
+    if BATTERY.swap(battery, Ordering::Relaxed) == battery {
+        return false;
+    }
+    info!("Power mode {:?}", mode);
+
+    if !battery {
+        let roots = std::mem::take(&mut *DEFERRED_RESCANS.lock());
+        if !roots.is_empty() {
+            crate::pool::spawn(async move {
+                for root in roots {
+                    crate::file_index::rescan(&root);
+                }
+            });
+        }
+    }
+    true
+}
+
+/// Called between the steps of background work, pauses on battery
+pub fn throttle() {
+    if BATTERY.load(Ordering::Relaxed) {
+        std::thread::sleep(BATTERY_STEP_PAUSE);
+    }
+}
+
+/// Put off a rescan of `root` while on battery. Returns false in
+/// performance mode, the caller rescans now.
+pub fn defer_rescan(root: &std::path::Path) -> bool {
+    if !BATTERY.load(Ordering::Relaxed) {
+        return false;
+    }
+    let mut deferred = DEFERRED_RESCANS.lock();
+    if !deferred.iter().any(|r| r == root) {
+        deferred.push(root.to_path_buf());
+    }
+    true
+}
+
+#[cfg(test)]
+mod power_tests {
+    use super::*;
+
+    #[test]
+    fn test_power_mode() {
+        assert!(set(PowerMode::Battery));
+        assert!(!set(PowerMode::Battery));
+        assert_eq!(mode(), PowerMode::Battery);
+
+        let root = std::path::Path::new("/w");
+        assert!(defer_rescan(root));
+        assert!(defer_rescan(root));
+        // Taken out, rescanning would replace the index other tests use
+        assert_eq!(std::mem::take(&mut *DEFERRED_RESCANS.lock()), [root.to_path_buf()]);
+
+        assert!(set(PowerMode::Performance));
+        assert!(!defer_rescan(root));
+        assert!(DEFERRED_RESCANS.lock().is_empty());
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::hash_map::Entry;
+use tracing::info;
+
+use crate::app_state::AppState;
+use crate::code::Code;
+use crate::config::PreloadConfig;
+
+// Warm buffers for the files the user works with. At startup the recent
+// and frequently opened files of the workspace are loaded into file2code
+// in the background, within a memory budget, so switching to them needs
+// no disk access, which matters on slow network filesystems.
+
+const DEFAULT_BUDGET_MB: u64 = 64;
+const DEFAULT_MAX_FILES: usize = 20;
+
+/// Candidates (path, size) to load in order while they fit the budget, a
+/// file too large for what is left is skipped rather than ending the list
+pub fn select(candidates: &[(String, u64)], budget: u64, max_files: usize) -> Vec<String> {
+    let mut used = 0;
+    let mut selected = Vec::new();
+    for (path, size) in candidates {
+        if selected.len() >= max_files {
+            break;
+        }
+        if used + size > budget {
+            continue;
+        }
+        used += size;
+        selected.push(path.clone());
+    }
+    selected
+}
+
+/// Load the ranked recent files of the workspace into file2code, and open
+/// them in their language servers when `lsp` is set
+pub async fn warm(preload: PreloadConfig, state: AppState) {
+    let budget = preload.budget_mb.unwrap_or(DEFAULT_BUDGET_MB) * 1024 * 1024;
+    let max_files = preload.max_files.unwrap_or(DEFAULT_MAX_FILES);
+
+    let ranked: Vec<String> = {
+        let recent = state.recent.lock().await;
+        let root = crate::utils::current_dir();
+        recent.ranked().into_iter().filter(|f| f.starts_with(&root)).collect()
+    };
+
+    let config = state.config.clone();
+    let loaded = crate::pool::spawn(async move {
+        let candidates: Vec<(String, u64)> = ranked.into_iter()
+            .filter_map(|path| {
+                let size = std::fs::metadata(&path).ok().filter(|m| m.is_file())?.len();
+                Some((path, size))
+            })
+            .collect();
+
+        select(&candidates, budget, max_files).into_iter()
+            .filter_map(|path| Code::from_file(&path, &config).ok().map(|code| (path, code)))
+            .collect::<Vec<(String, Code)>>()
+    }).await;
+
+    let Ok(loaded) = loaded else { return };
+    let mut opened = Vec::new();
+    {
+        let mut f2c = state.file2code.lock().await;
+        for (path, code) in loaded {
+            // A client may have opened the file meanwhile, its buffer wins
+            if let Entry::Vacant(entry) = f2c.entry(path.clone()) {
+                opened.push((path, code.lang.clone(), code.text.to_string()));
+                entry.insert(code);
+            }
+        }
+    }
+
+    info!("Preloaded {} files", opened.len());
+    crate::output::write("preload", &format!("Preloaded {} files", opened.len()));
+
+    if preload.lsp {
+        let mut lsp_manager = state.lsp_manager.lock().await;
+        for (path, lang, text) in opened {
+            if let Some(lsp) = lsp_manager.get_for(&lang, &path).await {
+                lsp.did_open(&lang, &path, &text);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod preload_tests {
+    use super::*;
+
+    #[test]
+    fn test_select_within_budget() {
+        let candidates: Vec<(String, u64)> = [("a", 40), ("huge", 500), ("b", 50), ("c", 20), ("d", 5)]
+            .into_iter().map(|(p, s)| (p.to_string(), s)).collect();
+
+        assert_eq!(select(&candidates, 100, 10), ["a", "b", "d"]);
+        assert_eq!(select(&candidates, 1000, 2), ["a", "huge"]);
+        assert!(select(&candidates, 0, 10).is_empty());
+    }
+}
@@ -0,0 +1,221 @@
+use anyhow::Result;
+use parking_lot::Mutex;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Span, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+// Opt-in profiling: while recording (`--profile` or `server:profile`),
+// the tracing spans of handlers, lock waits, LSP requests and searches
+// are kept as Chrome trace events, written on stop to the exports dir for
+// download and opened in Perfetto or chrome://tracing. A span shows on
+// the track of its root, a handler with its lock waits nested under it.
+// Spans are only created while recording, see `span`.
+
+/// Events kept per recording, later ones are counted as dropped
+const MAX_EVENTS: usize = 500_000;
+
+static RECORDING: AtomicBool = AtomicBool::new(false);
+static TRACE: Mutex<Option<Trace>> = Mutex::new(None);
+
+/// A complete event of the Chrome trace format, times in µs
+#[derive(Debug, Serialize, Clone)]
+pub struct TraceEvent {
+    pub name: String,
+    pub cat: String,
+    pub ph: &'static str,
+    pub ts: u128,
+    pub dur: u128,
+    pub pid: u32,
+    pub tid: u64,
+    pub args: Map<String, Value>,
+}
+
+struct Trace {
+    started: Instant,
+    started_at: chrono::DateTime<chrono::Local>,
+    events: Vec<TraceEvent>,
+    dropped: usize,
+}
+
+/// A finished recording
+pub struct Profile {
+    pub started_at: chrono::DateTime<chrono::Local>,
+    pub events: Vec<TraceEvent>,
+    pub dropped: usize,
+}
+
+pub fn is_recording() -> bool {
+    RECORDING.load(Ordering::Relaxed)
+}
+
+/// Start recording, false when already recording
+pub fn start() -> bool {
+    let mut trace = TRACE.lock();
+    if trace.is_some() {
+        return false;
+    }
+    *trace = Some(Trace { started: Instant::now(), started_at: chrono::Local::now(), events: Vec::new(), dropped: 0 });
+    RECORDING.store(true, Ordering::Relaxed);
+    true
+}
+
+/// Stop recording, None when not recording
+pub fn stop() -> Option<Profile> {
+    RECORDING.store(false, Ordering::Relaxed);
+    let trace = TRACE.lock().take()?;
+    Some(Profile { started_at: trace.started_at, events: trace.events, dropped: trace.dropped })
+}
+
+/// A span of `category` labelled `label`, nothing when not recording
+pub fn span(category: &'static str, label: &str) -> Span {
+    if !is_recording() {
+        return Span::none();
+    }
+    tracing::info_span!("profile", category, label)
+}
+
+/// A span nested in `parent`, nothing when not recording
+pub fn child(parent: &Span, category: &'static str, label: &str) -> Span {
+    if !is_recording() || parent.is_none() {
+        return Span::none();
+    }
+    tracing::info_span!(parent: parent, "profile", category, label)
+}
+
+/// Write a recording as `profile-<time>.json` to `dir`, returns its path
+pub fn write(profile: &Profile, dir: &Path) -> Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(format!("profile-{}.json", profile.started_at.format("%Y%m%d-%H%M%S%3f")));
+    let trace = json!({
+        "traceEvents": profile.events,
+        "displayTimeUnit": "ms",
+        "otherData": { "started": profile.started_at.to_rfc3339(), "dropped": profile.dropped },
+    });
+    std::fs::write(&path, serde_json::to_vec(&trace)?)?;
+    Ok(path)
+}
+
+struct Timing {
+    start: Instant,
+    fields: Map<String, Value>,
+}
+
+struct Fields<'a>(&'a mut Map<String, Value>);
+
+impl Visit for Fields<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0.insert(field.name().to_string(), format!("{:?}", value).into());
+    }
+}
+
+/// Keeps the spans closed while recording
+pub struct ProfileLayer;
+
+impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for ProfileLayer {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if !is_recording() {
+            return;
+        }
+        let Some(span) = ctx.span(id) else { return };
+        let mut fields = Map::new();
+        attrs.record(&mut Fields(&mut fields));
+        span.extensions_mut().insert(Timing { start: Instant::now(), fields });
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        if let Some(timing) = span.extensions_mut().get_mut::<Timing>() {
+            values.record(&mut Fields(&mut timing.fields));
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else { return };
+        let Some(mut timing) = span.extensions_mut().remove::<Timing>() else { return };
+        let tid = span.scope().from_root().next().map_or(id.into_u64(), |root| root.id().into_u64());
+        let mut take = |name: &str| match timing.fields.remove(name) {
+            Some(Value::String(value)) => Some(value),
+            _ => None,
+        };
+        let cat = take("category").unwrap_or_else(|| span.name().to_string());
+        let name = take("label").unwrap_or_else(|| span.name().to_string());
+
+        let mut trace = TRACE.lock();
+        let Some(trace) = trace.as_mut() else { return };
+        if trace.events.len() >= MAX_EVENTS {
+            trace.dropped += 1;
+            return;
+        }
+        trace.events.push(TraceEvent {
+            name,
+            cat,
+            ph: "X",
+            ts: timing.start.saturating_duration_since(trace.started).as_micros(),
+            dur: timing.start.elapsed().as_micros(),
+            pid: std::process::id(),
+            tid,
+            args: timing.fields,
+        });
+    }
+}
+
+#[cfg(test)]
+mod profile_tests {
+    use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn test_record_and_write() -> Result<()> {
+        let subscriber = tracing_subscriber::registry().with(ProfileLayer);
+        // Not recording, nothing is created
+        assert!(span("handler", "file:open").is_none());
+
+        tracing::subscriber::with_default(subscriber, || {
+            assert!(start());
+            assert!(!start());
+            let handler = span("handler", "file:save");
+            drop(child(&handler, "lock", "file2code"));
+            drop(handler);
+            drop(span("lsp", "textDocument/hover"));
+        });
+
+        let profile = stop().unwrap();
+        assert!(stop().is_none());
+        let names: Vec<(&str, &str)> = profile.events.iter().map(|e| (e.cat.as_str(), e.name.as_str())).collect();
+        assert_eq!(names, [("lock", "file2code"), ("handler", "file:save"), ("lsp", "textDocument/hover")]);
+        // The lock wait is on the track of its handler
+        assert_eq!(profile.events[0].tid, profile.events[1].tid);
+        assert_ne!(profile.events[1].tid, profile.events[2].tid);
+        assert!(profile.events[0].ts >= profile.events[1].ts);
+
+        let dir = tempfile::tempdir()?;
+        let path = write(&profile, dir.path())?;
+        let trace: Value = serde_json::from_slice(&std::fs::read(path)?)?;
+        assert_eq!(trace["traceEvents"].as_array().map(Vec::len), Some(3));
+        assert_eq!(trace["traceEvents"][0]["ph"], "X");
+        Ok(())
+    }
+}
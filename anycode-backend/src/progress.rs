@@ -0,0 +1,150 @@
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+// Progress of long running operations (searches, the workspace scan, task
+// runs) in one place, so the clients render a single progress UI. An
+// operation registers an item and updates it through the returned handle,
+// dropping the handle finishes the item. Changes are pushed to the clients
+// as `notify:progress`, `notify:list` returns the running items and
+// `notify:cancel` fires the token of a cancellable one.
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct ProgressItem {
+    pub id: u64,
+    /// What is running: search, scan, task
+    pub kind: String,
+    pub title: String,
+    pub message: Option<String>,
+    /// 0 to 100, None while the total is unknown
+    pub percent: Option<u8>,
+    pub cancellable: bool,
+    pub done: bool,
+}
+
+struct Item {
+    item: ProgressItem,
+    cancel: Option<CancellationToken>,
+}
+
+#[derive(Default)]
+struct Registry {
+    items: Mutex<BTreeMap<u64, Item>>,
+    sender: OnceLock<mpsc::Sender<ProgressItem>>,
+}
+
+static REGISTRY: OnceLock<Registry> = OnceLock::new();
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+fn registry() -> &'static Registry {
+    REGISTRY.get_or_init(Registry::default)
+}
+
+/// Set the channel changes are forwarded to, main emits them to the clients
+pub fn init(sender: mpsc::Sender<ProgressItem>) {
+    let _ = registry().sender.set(sender);
+}
+
+fn publish(item: &ProgressItem) {
+    if let Some(sender) = registry().sender.get() {
+        let _ = sender.try_send(item.clone());
+    }
+}
+
+/// Handle of a running item, finishes it when dropped
+pub struct Progress {
+    id: u64,
+}
+
+impl Progress {
+    /// Set the percent and message, only changes are pushed
+    pub fn update(&self, percent: Option<u8>, message: Option<String>) {
+        let mut items = registry().items.lock();
+        let Some(Item { item, .. }) = items.get_mut(&self.id) else { return };
+
+        let percent = percent.map(|p| p.min(100));
+        if item.percent == percent && item.message == message {
+            return;
+        }
+        item.percent = percent;
+        item.message = message;
+        publish(item);
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        let removed = registry().items.lock().remove(&self.id);
+        if let Some(Item { mut item, .. }) = removed {
+            item.done = true;
+            publish(&item);
+        }
+    }
+}
+
+/// Register a running operation. With `cancel` the clients may cancel it.
+pub fn start(kind: &str, title: &str, cancel: Option<CancellationToken>) -> Progress {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let item = ProgressItem {
+        id,
+        kind: kind.to_string(),
+        title: title.to_string(),
+        message: None,
+        percent: None,
+        cancellable: cancel.is_some(),
+        done: false,
+    };
+    publish(&item);
+    registry().items.lock().insert(id, Item { item, cancel });
+    Progress { id }
+}
+
+/// Running items, oldest first
+pub fn list() -> Vec<ProgressItem> {
+    registry().items.lock().values().map(|i| i.item.clone()).collect()
+}
+
+/// Cancel a running item, false when it is unknown or not cancellable
+pub fn cancel(id: u64) -> bool {
+    let items = registry().items.lock();
+    match items.get(&id).and_then(|i| i.cancel.as_ref()) {
+        Some(token) => {
+            token.cancel();
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod progress_tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_items() {
+        let token = CancellationToken::new();
+        let search = start("search", "Searching foo", Some(token.clone()));
+        let scan = start("scan", "Scanning workspace", None);
+
+        scan.update(Some(150), Some("10 files".to_string()));
+        let listed: Vec<ProgressItem> = list().into_iter()
+            .filter(|i| i.id == search.id || i.id == scan.id)
+            .collect();
+        assert_eq!(listed.len(), 2);
+        assert!(listed[0].cancellable && !listed[1].cancellable);
+        assert_eq!(listed[1].percent, Some(100));
+
+        assert!(!cancel(scan.id));
+        assert!(cancel(search.id));
+        assert!(token.is_cancelled());
+
+        let id = search.id;
+        drop(search);
+        assert!(!list().iter().any(|i| i.id == id));
+        assert!(!cancel(id));
+    }
+}
@@ -0,0 +1,236 @@
+// End-to-end tests of the socket.io protocol. Each test serves the app on an
+// ephemeral port and drives it with a minimal socket.io v5 client over the
+// websocket transport, so regressions across handler modules show up as
+// failing flows rather than only as unit test failures.
+
+use anyhow::{anyhow, Result};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+async fn serve() -> Result<SocketAddr> {
+    let (app, _io) = crate::build_app(crate::app_state::test_state());
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    Ok(addr)
+}
+
+enum Packet {
+    Event(String, Value),
+    Ack(u64, Value),
+}
+
+/// Parse the socket.io part of an engine.io message packet of the default
+/// namespace: `2[event, data]`, `2<id>[event, data]` or `3<id>[data]`
+fn parse_packet(text: &str) -> Option<Packet> {
+    let (kind, rest) = text.split_at_checked(1)?;
+    let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+    let (id, payload) = rest.split_at(digits);
+    let payload: Vec<Value> = serde_json::from_str(payload).ok()?;
+
+    match kind {
+        "2" => {
+            let name = payload.first()?.as_str()?.to_string();
+            Some(Packet::Event(name, payload.get(1).cloned().unwrap_or(Value::Null)))
+        }
+        "3" => Some(Packet::Ack(id.parse().ok()?, payload.into_iter().next().unwrap_or(Value::Null))),
+        _ => None,
+    }
+}
+
+struct TestClient {
+    ws: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    next_id: u64,
+    events: VecDeque<(String, Value)>,
+}
+
+impl TestClient {
+    async fn connect(addr: SocketAddr) -> Result<Self> {
+        let url = format!("ws://{}/socket.io/?EIO=4&transport=websocket", addr);
+        let (ws, _) = connect_async(url).await?;
+        let mut client = Self { ws, next_id: 0, events: VecDeque::new() };
+
+        // Engine.io open, then connect to the default namespace
+        let open = client.read_text().await?;
+        if !open.starts_with('0') {
+            return Err(anyhow!("expected an open packet, got {}", open));
+        }
+        client.ws.send(Message::text("40")).await?;
+        loop {
+            let text = client.read_text().await?;
+            if text.starts_with("40") {
+                return Ok(client);
+            }
+        }
+    }
+
+    /// Next engine.io message, answering pings on the way
+    async fn read_text(&mut self) -> Result<String> {
+        loop {
+            let message = timeout(TIMEOUT, self.ws.next()).await
+                .map_err(|_| anyhow!("timed out waiting for the server"))?
+                .ok_or_else(|| anyhow!("connection closed"))??;
+
+            let Message::Text(text) = message else { continue };
+            if text.as_str() == "2" {
+                self.ws.send(Message::text("3")).await?;
+                continue;
+            }
+            return Ok(text.to_string());
+        }
+    }
+
+    async fn read_packet(&mut self) -> Result<Packet> {
+        loop {
+            let text = self.read_text().await?;
+            if let Some(message) = text.strip_prefix('4')
+                && let Some(packet) = parse_packet(message)
+            {
+                return Ok(packet);
+            }
+        }
+    }
+
+    async fn emit(&mut self, event: &str, data: Value) -> Result<()> {
+        let packet = format!("42{}", json!([event, data]));
+        self.ws.send(Message::text(packet)).await?;
+        Ok(())
+    }
+
+    /// Emit with an ack and wait for it, events received meanwhile are kept
+    async fn call(&mut self, event: &str, data: Value) -> Result<Value> {
+        self.next_id += 1;
+        let id = self.next_id;
+        let packet = format!("42{}{}", id, json!([event, data]));
+        self.ws.send(Message::text(packet)).await?;
+
+        loop {
+            match self.read_packet().await? {
+                Packet::Ack(ack_id, data) if ack_id == id => return Ok(data),
+                Packet::Ack(..) => {}
+                Packet::Event(name, data) => self.events.push_back((name, data)),
+            }
+        }
+    }
+
+    /// Next event of that name, other events received meanwhile are kept
+    async fn event(&mut self, name: &str) -> Result<Value> {
+        if let Some(i) = self.events.iter().position(|(n, _)| n == name) {
+            return Ok(self.events.remove(i).unwrap().1);
+        }
+
+        loop {
+            match self.read_packet().await? {
+                Packet::Event(n, data) if n == name => return Ok(data),
+                Packet::Event(n, data) => self.events.push_back((n, data)),
+                Packet::Ack(..) => {}
+            }
+        }
+    }
+}
+
+#[test]
+fn test_parse_packet() {
+    let Some(Packet::Event(name, data)) = parse_packet(r#"2["search:end",{"matches":1}]"#) else { panic!() };
+    assert_eq!((name.as_str(), data), ("search:end", json!({ "matches": 1 })));
+
+    let Some(Packet::Ack(id, data)) = parse_packet(r#"312[{"success":true}]"#) else { panic!() };
+    assert_eq!((id, data), (12, json!({ "success": true })));
+
+    assert!(parse_packet("1").is_none());
+}
+
+#[tokio::test]
+async fn test_file_open_change_save() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("main.txt");
+    std::fs::write(&path, "hello\n")?;
+    let path = path.to_string_lossy().to_string();
+
+    let mut client = TestClient::connect(serve().await?).await?;
+
+    let opened = client.call("file:open", json!({ "path": path })).await?;
+    assert_eq!(opened["success"], true);
+    assert_eq!(opened["content"], "hello\n");
+    // The temporary directory is outside the workspace root
+    assert_eq!(opened["read_only"], "outside");
+    let writable = client.call("file:makeWritable", json!({ "path": path })).await?;
+    assert_eq!(writable["success"], true);
+
+    client.emit("file:change", json!({
+        "file": path,
+        "edits": [{ "operation": "insert", "start": 5, "text": " world" }]
+    })).await?;
+
+    // Events of a socket are handled concurrently, save until the change is in
+    for _ in 0..50 {
+        let saved = client.call("file:save", json!({ "path": path })).await?;
+        assert_eq!(saved["success"], true);
+        if std::fs::read_to_string(&path)? == "hello world\n" {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    Err(anyhow!("change was not saved: {:?}", std::fs::read_to_string(&path)?))
+}
+
+#[tokio::test]
+async fn test_search_cancelled_by_next_search() -> Result<()> {
+    let workspace = tempfile::tempdir()?;
+    let marker = "protocol-search-marker";
+    std::fs::write(workspace.path().join("marker.txt"), format!("{}\n", marker))?;
+
+    let mut client = TestClient::connect(serve().await?).await?;
+    let opened = client.call("workspace:open", json!({ "path": workspace.path() })).await?;
+    assert_eq!(opened["success"], true, "{}", opened);
+
+    // The first search is cancelled by the second, both still end
+    client.emit("search:start", json!({ "pattern": "fn" })).await?;
+    client.emit("search:start", json!({ "pattern": marker })).await?;
+
+    let first = client.event("search:end").await?;
+    let second = client.event("search:end").await?;
+    assert!(
+        first["matches"] == 1 || second["matches"] == 1,
+        "marker search did not complete: {} {}", first, second
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_terminal_echo() -> Result<()> {
+    let addr = serve().await?;
+    let mut client = TestClient::connect(addr).await?;
+    let terminal = json!({ "name": "e2e", "session": "protocol", "cmd": "bash" });
+
+    client.emit("terminal:start", terminal.clone()).await?;
+    // The first output (the prompt) tells the terminal is up
+    client.event("terminal:data:e2e").await?;
+    client.emit("terminal:input", json!({
+        "name": "e2e", "session": "protocol", "input": "echo protocol-$((6 * 7))\n"
+    })).await?;
+
+    let mut output = String::new();
+    while !output.contains("protocol-42") {
+        let data = client.event("terminal:data:e2e").await?;
+        output.push_str(data.as_str().unwrap_or_default());
+    }
+
+    // Another connection knowing the session can't type into it
+    let mut other = TestClient::connect(addr).await?;
+    other.emit("terminal:input", json!({ "name": "e2e", "session": "protocol", "input": "exit\n" })).await?;
+    let error = other.event("terminal:error").await?;
+    assert_eq!(error, "Terminal is attached to another connection");
+
+    client.emit("terminal:close", terminal).await?;
+    Ok(())
+}
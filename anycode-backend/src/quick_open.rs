@@ -0,0 +1,126 @@
+use serde::Serialize;
+
+use crate::fuzzy::fuzzy_match;
+
+// "Go to file" over the workspace file index, so clients do not need the
+// whole file list. Queries are split on whitespace into terms that all
+// have to match, fzf style. A term without a path separator is matched
+// against the file name first, so `main` ranks `src/main.rs` above
+// `src/domain/input.rs`, a term with one against the whole relative
+// path. Recently opened files rank higher.
+
+pub const DEFAULT_LIMIT: usize = 50;
+/// Bonus of a term matching within the file name
+const FILE_NAME_BONUS: i64 = 64;
+/// Bonus of the most recently opened file, less for the older ones
+const RECENT_BONUS: i64 = 48;
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct FileMatch {
+    pub path: String,
+    pub score: i64,
+    /// Char indices of the matched path chars, for highlighting
+    pub indices: Vec<usize>,
+}
+
+/// Score of a relative path for the query terms, None unless all match
+fn score_path(terms: &[&str], path: &str) -> Option<(i64, Vec<usize>)> {
+    let name_start = path.rfind(['/', '\\']).map_or(0, |i| path[..=i].chars().count());
+    let name: String = path.chars().skip(name_start).collect();
+
+    let mut score = 0;
+    let mut indices = Vec::new();
+    for term in terms {
+        let in_name = (!term.contains(['/', '\\']))
+            .then(|| fuzzy_match(term, &name))
+            .flatten();
+        match in_name {
+            Some(m) => {
+                score += m.score + FILE_NAME_BONUS;
+                indices.extend(m.indices.iter().map(|i| i + name_start));
+            }
+            None => {
+                let m = fuzzy_match(term, path)?;
+                score += m.score;
+                indices.extend(m.indices);
+            }
+        }
+    }
+    indices.sort_unstable();
+    indices.dedup();
+    Some((score, indices))
+}
+
+/// The best `limit` files for the query. `recent` are relative paths, most
+/// recent first, and what an empty query returns.
+pub fn find<'a>(files: impl IntoIterator<Item = &'a String>, query: &str, recent: &[String], limit: usize) -> Vec<FileMatch> {
+    let terms: Vec<&str> = query.split_whitespace().collect();
+    let recent_bonus = |path: &str| {
+        recent.iter().position(|r| r == path)
+            .map_or(0, |rank| RECENT_BONUS * (recent.len() - rank) as i64 / recent.len() as i64)
+    };
+
+    if terms.is_empty() {
+        let files: Vec<&String> = files.into_iter().collect();
+        return recent.iter()
+            .filter(|r| files.contains(r))
+            .take(limit)
+            .map(|path| FileMatch { path: path.clone(), score: recent_bonus(path), indices: Vec::new() })
+            .collect();
+    }
+
+    let mut matches: Vec<FileMatch> = files.into_iter()
+        .filter_map(|path| {
+            let (score, indices) = score_path(&terms, path)?;
+            Some(FileMatch { path: path.clone(), score: score + recent_bonus(path), indices })
+        })
+        .collect();
+
+    matches.sort_by(|a, b| b.score.cmp(&a.score)
+        .then(a.path.len().cmp(&b.path.len()))
+        .then(a.path.cmp(&b.path)));
+    matches.truncate(limit);
+    matches
+}
+
+#[cfg(test)]
+mod quick_open_tests {
+    use super::*;
+
+    fn paths(matches: &[FileMatch]) -> Vec<&str> {
+        matches.iter().map(|m| m.path.as_str()).collect()
+    }
+
+    fn files() -> Vec<String> {
+        ["src/domain/input.rs", "src/main.rs", "src/handlers/io_handler.rs", "src/handlers/search_handler.rs", "README.md"]
+            .into_iter().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_file_name_first() {
+        let files = files();
+        let found = find(&files, "main", &[], 10);
+        assert_eq!(paths(&found), ["src/main.rs", "src/domain/input.rs"]);
+        // Indices point into the whole path
+        assert_eq!(found[0].indices, vec![4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn test_terms() {
+        let files = files();
+        // Every term has to match, a separator matches the path
+        assert_eq!(paths(&find(&files, "handlers/ search", &[], 10)), ["src/handlers/search_handler.rs"]);
+        assert_eq!(paths(&find(&files, "hand io", &[], 10)), ["src/handlers/io_handler.rs"]);
+        assert!(find(&files, "main zzz", &[], 10).is_empty());
+    }
+
+    #[test]
+    fn test_recent() {
+        let files = files();
+        let recent = vec!["src/handlers/search_handler.rs".to_string(), "gone.rs".to_string()];
+        assert_eq!(paths(&find(&files, "handler", &recent, 10))[0], "src/handlers/search_handler.rs");
+        // An empty query lists the recent files still in the workspace
+        assert_eq!(paths(&find(&files, " ", &recent, 10)), ["src/handlers/search_handler.rs"]);
+        assert_eq!(find(&files, "rs", &[], 2).len(), 2);
+    }
+}
@@ -0,0 +1,178 @@
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::OnceLock;
+
+// Documents the editor should not modify: write-protected on disk, inside
+// ignored or generated directories (target/, node_modules/), or outside
+// the workspace roots. file:open reports the reason and file:change is
+// rejected until the client asks for file:makeWritable.
+
+/// Build output and dependency directories, read-only even when the
+/// ignore rules do not list them
+const GENERATED_DIRS: &[&str] = &["target", "node_modules", "dist"];
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadOnlyReason {
+    /// No write permission on disk
+    Protected,
+    /// Inside an ignored directory, usually build output or dependencies
+    Generated,
+    /// Outside the workspace roots
+    Outside,
+}
+
+/// The document is read-only, the error of rejected changes
+#[derive(Debug)]
+pub struct ReadOnly {
+    pub path: String,
+    pub reason: ReadOnlyReason,
+}
+
+impl std::fmt::Display for ReadOnly {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} is read-only ({:?})", self.path, self.reason)
+    }
+}
+
+impl std::error::Error for ReadOnly {}
+
+#[derive(Default)]
+struct State {
+    /// Reasons found when the documents were opened
+    reasons: HashMap<String, Option<ReadOnlyReason>>,
+    /// Documents made writable by the user for this session
+    writable: HashSet<String>,
+}
+
+static STATE: OnceLock<Mutex<State>> = OnceLock::new();
+
+fn state() -> &'static Mutex<State> {
+    STATE.get_or_init(|| Mutex::new(State::default()))
+}
+
+/// Why a file is read-only, None when it may be edited
+pub fn classify(path: &Path, protected: bool, root: &Path) -> Option<ReadOnlyReason> {
+    if protected {
+        return Some(ReadOnlyReason::Protected);
+    }
+    let Ok(relative) = path.strip_prefix(root) else {
+        return Some(ReadOnlyReason::Outside);
+    };
+    let parent = relative.parent().unwrap_or(Path::new(""));
+    let generated = parent.iter().any(|dir| GENERATED_DIRS.iter().any(|g| dir == *g));
+    if generated || anycode_search::ignore::is_ignored_dir(parent) {
+        return Some(ReadOnlyReason::Generated);
+    }
+    None
+}
+
+fn detect(path: &str) -> Option<ReadOnlyReason> {
+    let protected = std::fs::metadata(path).is_ok_and(|m| m.permissions().readonly());
+    classify(Path::new(path), protected, &crate::roots::root_of(Path::new(path)))
+}
+
+/// Check the document again, called when it is opened
+pub fn refresh(path: &str) -> Option<ReadOnlyReason> {
+    let mut state = state().lock();
+    let reason = match state.writable.contains(path) {
+        true => None,
+        false => detect(path),
+    };
+    state.reasons.insert(path.to_string(), reason);
+    reason
+}
+
+/// The reason of a document, checked on first use
+pub fn reason(path: &str) -> Option<ReadOnlyReason> {
+    let cached = state().lock().reasons.get(path).copied();
+    match cached {
+        Some(reason) => reason,
+        None => refresh(path),
+    }
+}
+
+/// Fail with `ReadOnly` for documents that may not be changed
+pub fn check_writable(path: &str) -> anyhow::Result<()> {
+    match reason(path) {
+        Some(reason) => Err(ReadOnly { path: path.to_string(), reason }.into()),
+        None => Ok(()),
+    }
+}
+
+/// Lift the restriction for the rest of the session, write-protected
+/// files also get their write permission back on disk
+pub fn make_writable(path: &str) -> anyhow::Result<()> {
+    if detect(path) == Some(ReadOnlyReason::Protected) {
+        let mut permissions = std::fs::metadata(path)?.permissions();
+        // The owner's write bit only, not write access for everyone
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            permissions.set_mode(permissions.mode() | 0o200);
+        }
+        #[cfg(not(unix))]
+        permissions.set_readonly(false);
+        std::fs::set_permissions(path, permissions)?;
+    }
+
+    let mut state = state().lock();
+    state.writable.insert(path.to_string());
+    state.reasons.insert(path.to_string(), None);
+    Ok(())
+}
+
+#[cfg(test)]
+mod readonly_tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let root = Path::new("/w");
+        assert_eq!(classify(Path::new("/w/src/main.rs"), false, root), None);
+        assert_eq!(classify(Path::new("/w/src/main.rs"), true, root), Some(ReadOnlyReason::Protected));
+        assert_eq!(classify(Path::new("/w/target/debug/build.rs"), false, root), Some(ReadOnlyReason::Generated));
+        assert_eq!(classify(Path::new("/w/node_modules/a/index.js"), false, root), Some(ReadOnlyReason::Generated));
+        assert_eq!(classify(Path::new("/w/.git/config"), false, root), Some(ReadOnlyReason::Generated));
+        assert_eq!(classify(Path::new("/other/lib.rs"), false, root), Some(ReadOnlyReason::Outside));
+    }
+
+    #[test]
+    fn test_detect_in_added_root() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, "x")?;
+        let path = path.to_string_lossy().to_string();
+        assert_eq!(detect(&path), Some(ReadOnlyReason::Outside));
+
+        crate::roots::add(&dir.path().to_string_lossy())?;
+        assert_eq!(detect(&path), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_make_writable() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("locked.txt");
+        std::fs::write(&path, "x")?;
+        let mut permissions = std::fs::metadata(&path)?.permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(&path, permissions)?;
+        let path = path.to_string_lossy().to_string();
+
+        assert!(check_writable(&path).is_err());
+        make_writable(&path)?;
+        assert!(check_writable(&path).is_ok());
+        assert_eq!(refresh(&path), None);
+        assert!(!std::fs::metadata(&path)?.permissions().readonly());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            // Writable by the owner only
+            assert_eq!(std::fs::metadata(&path)?.permissions().mode() & 0o022, 0);
+        }
+        Ok(())
+    }
+}
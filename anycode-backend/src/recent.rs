@@ -0,0 +1,136 @@
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tracing::error;
+
+use crate::{storage, store};
+
+const NAMESPACE: &str = "recent";
+const KEY: &str = "files";
+/// State file of older versions, imported when the storage has no entry
+const LEGACY_FILE: &str = "recent.json";
+const MAX_RECENT: usize = 50;
+/// Opens are written to the storage together, this long after the first
+const SAVE_DELAY: Duration = Duration::from_secs(2);
+
+/// Opens not written yet, in order
+static PENDING: Mutex<Vec<String>> = Mutex::new(Vec::new());
+static SAVE_PENDING: AtomicBool = AtomicBool::new(false);
+/// The files as last written, with the opens of other backends
+static SAVED: Mutex<Option<RecentFiles>> = Mutex::new(None);
+
+/// Recently opened files, most recent first, persisted in the state storage
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RecentFiles {
+    files: VecDeque<String>,
+    /// How often each of the files was opened
+    #[serde(default)]
+    opens: HashMap<String, u32>,
+}
+
+impl RecentFiles {
+    pub fn load() -> Self {
+        let storage = storage::get();
+        match storage.get_json(NAMESPACE, KEY) {
+            Ok(Some(recent)) => recent,
+            Ok(None) => {
+                let recent: Self = store::read_json(LEGACY_FILE).unwrap_or_default();
+                if !recent.files.is_empty() && let Err(e) = storage.put_json(NAMESPACE, KEY, &recent) {
+                    error!("Failed to import {}: {}", LEGACY_FILE, e);
+                }
+                recent
+            }
+            Err(e) => {
+                error!("Failed to load recent files: {}", e);
+                Self::default()
+            }
+        }
+    }
+
+    /// Record an open. The opens are written after `SAVE_DELAY`, merged
+    /// into what other backends stored meanwhile.
+    pub fn touch(&mut self, path: &str) {
+        let mut pending = PENDING.lock();
+        if let Some(saved) = SAVED.lock().take() {
+            *self = saved;
+            pending.iter().for_each(|p| self.add(p));
+        }
+        self.add(path);
+        pending.push(path.to_string());
+        drop(pending);
+        schedule_save();
+    }
+
+    fn add(&mut self, path: &str) {
+        self.files.retain(|f| f != path);
+        self.files.push_front(path.to_string());
+        self.files.truncate(MAX_RECENT);
+        *self.opens.entry(path.to_string()).or_default() += 1;
+        let files = &self.files;
+        self.opens.retain(|f, _| files.contains(f));
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &String> {
+        self.files.iter()
+    }
+
+    /// Files by frecency: opens weighted down by how long ago the file
+    /// was last opened, so a file opened often stays ahead of one opened
+    /// once a moment ago
+    pub fn ranked(&self) -> Vec<String> {
+        let score = |index: usize, path: &String| {
+            let opens = self.opens.get(path).copied().unwrap_or(1) as usize;
+            opens * MAX_RECENT / (index + 1)
+        };
+        let mut ranked: Vec<(usize, &String)> = self.files.iter().enumerate()
+            .map(|(index, path)| (score(index, path), path))
+            .collect();
+        ranked.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+        ranked.into_iter().map(|(_, path)| path.clone()).collect()
+    }
+}
+
+/// Write the pending opens to the storage, at once
+pub fn flush() {
+    let mut pending = PENDING.lock();
+    if pending.is_empty() {
+        return;
+    }
+    let opens = std::mem::take(&mut *pending);
+    let merged = storage::get().update_json(NAMESPACE, KEY, |recent: &mut RecentFiles| {
+        opens.iter().for_each(|p| recent.add(p));
+    });
+    match merged {
+        Ok(recent) => *SAVED.lock() = Some(recent),
+        Err(e) => error!("Failed to save recent files: {}", e),
+    }
+}
+
+fn schedule_save() {
+    if SAVE_PENDING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    crate::pool::spawn(async {
+        tokio::time::sleep(SAVE_DELAY).await;
+        SAVE_PENDING.store(false, Ordering::SeqCst);
+        flush();
+    });
+}
+
+#[cfg(test)]
+mod recent_tests {
+    use super::*;
+
+    #[test]
+    fn test_ranked() {
+        let recent = RecentFiles {
+            files: ["/w/new.rs", "/w/other.rs", "/w/often.rs", "/w/old.rs"]
+                .into_iter().map(String::from).collect(),
+            opens: HashMap::from([("/w/often.rs".to_string(), 6)]),
+        };
+
+        assert_eq!(recent.ranked(), ["/w/often.rs", "/w/new.rs", "/w/other.rs", "/w/old.rs"]);
+    }
+}
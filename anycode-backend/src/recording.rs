@@ -0,0 +1,212 @@
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use serde_json::json;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+// Terminal session recordings in the asciicast v2 format, playable with
+// `asciinema play` or the web player: a json header line followed by one
+// `[seconds, code, data]` line per output chunk ("o") or resize ("r").
+
+const RECORDINGS_DIR: &str = "recordings";
+const CAST_EXT: &str = "cast";
+
+pub fn recordings_dir() -> PathBuf {
+    crate::store::workspace_dir().join(RECORDINGS_DIR)
+}
+
+struct CastWriter {
+    file: BufWriter<File>,
+    started: Instant,
+}
+
+impl CastWriter {
+    fn create(path: &Path, title: &str, cols: u16, rows: u16) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut file = BufWriter::new(File::create(path)?);
+        let header = json!({
+            "version": 2,
+            "width": cols,
+            "height": rows,
+            "timestamp": chrono::Utc::now().timestamp(),
+            "title": title,
+        });
+        writeln!(file, "{}", header)?;
+        file.flush()?;
+
+        Ok(Self { file, started: Instant::now() })
+    }
+
+    fn event(&mut self, code: &str, data: &str) -> Result<()> {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        writeln!(self.file, "{}", json!([elapsed, code, data]))?;
+        // Keep the cast playable if the server goes away mid-session
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// Recording state of one terminal. The size is tracked all the time so a
+/// recording started mid-session gets the right header.
+pub struct Recording {
+    cols: u16,
+    rows: u16,
+    cast: Option<(String, CastWriter)>,
+}
+
+impl Recording {
+    pub fn new(cols: u16, rows: u16) -> Self {
+        Self { cols, rows, cast: None }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.cast.is_some()
+    }
+
+    /// Start recording into a new cast file, returns its name
+    pub fn start(&mut self, terminal: &str) -> Result<String> {
+        self.start_in(&recordings_dir(), terminal)
+    }
+
+    fn start_in(&mut self, dir: &Path, terminal: &str) -> Result<String> {
+        if let Some((name, _)) = &self.cast {
+            return Ok(name.clone());
+        }
+
+        let safe: String = terminal.chars()
+            .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+            .collect();
+        let name = format!("{}-{}.{}", safe, chrono::Local::now().format("%Y%m%d-%H%M%S"), CAST_EXT);
+
+        let writer = CastWriter::create(&dir.join(&name), terminal, self.cols, self.rows)?;
+        self.cast = Some((name.clone(), writer));
+        Ok(name)
+    }
+
+    /// Stop recording, returns the name of the finished cast
+    pub fn stop(&mut self) -> Option<String> {
+        self.cast.take().map(|(name, _)| name)
+    }
+
+    pub fn output(&mut self, data: &str) {
+        if let Some((name, writer)) = &mut self.cast
+            && let Err(e) = writer.event("o", data)
+        {
+            crate::output::write("terminal", &format!("Recording {} stopped: {}", name, e));
+            self.cast = None;
+        }
+    }
+
+    pub fn resize(&mut self, cols: u16, rows: u16) {
+        self.cols = cols;
+        self.rows = rows;
+        if let Some((_, writer)) = &mut self.cast {
+            let _ = writer.event("r", &format!("{}x{}", cols, rows));
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ReplayInfo {
+    pub name: String,
+    pub size: u64,
+    /// Unix time in millis
+    pub modified: i64,
+}
+
+/// Recorded casts of the workspace, newest first
+pub fn list() -> Result<Vec<ReplayInfo>> {
+    list_in(&recordings_dir())
+}
+
+fn list_in(dir: &Path) -> Result<Vec<ReplayInfo>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut replays: Vec<ReplayInfo> = entries.flatten()
+        .filter(|e| e.path().extension().is_some_and(|ext| ext == CAST_EXT))
+        .filter_map(|e| {
+            let meta = e.metadata().ok()?;
+            let modified = meta.modified().ok()
+                .map(|t| chrono::DateTime::<chrono::Utc>::from(t).timestamp_millis())
+                .unwrap_or(0);
+            Some(ReplayInfo { name: e.file_name().to_string_lossy().to_string(), size: meta.len(), modified })
+        })
+        .collect();
+
+    replays.sort_by_key(|r| std::cmp::Reverse(r.modified));
+    Ok(replays)
+}
+
+/// Content of a recorded cast, by the name from `list`
+pub fn read(name: &str) -> Result<String> {
+    read_in(&recordings_dir(), name)
+}
+
+fn read_in(dir: &Path, name: &str) -> Result<String> {
+    let path = Path::new(name);
+    let plain_name = path.components().count() == 1 && path.file_name().is_some();
+    if !plain_name || path.extension().is_none_or(|ext| ext != CAST_EXT) {
+        return Err(anyhow!("Invalid recording name {}", name));
+    }
+    Ok(std::fs::read_to_string(dir.join(name))?)
+}
+
+#[cfg(test)]
+mod recording_tests {
+    use super::*;
+
+    #[test]
+    fn test_recording_writes_asciicast() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut recording = Recording::new(80, 24);
+
+        recording.output("not recorded");
+        let name = recording.start_in(dir.path(), "build 1")?;
+        assert!(name.starts_with("build_1-") && name.ends_with(".cast"));
+
+        recording.output("hello\r\n");
+        recording.resize(100, 30);
+        assert_eq!(recording.stop(), Some(name.clone()));
+        recording.output("after stop");
+
+        let cast = read_in(dir.path(), &name)?;
+        let lines: Vec<serde_json::Value> = cast.lines()
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?;
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["version"], 2);
+        assert_eq!(lines[0]["width"], 80);
+        assert_eq!(lines[1][1], "o");
+        assert_eq!(lines[1][2], "hello\r\n");
+        assert_eq!(lines[2][2], "100x30");
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_and_read_replays() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        assert!(list_in(&dir.path().join("missing"))?.is_empty());
+
+        std::fs::write(dir.path().join("a.cast"), "{}")?;
+        std::fs::write(dir.path().join("notes.txt"), "")?;
+
+        let replays = list_in(dir.path())?;
+        assert_eq!(replays.len(), 1);
+        assert_eq!(replays[0].name, "a.cast");
+
+        assert!(read_in(dir.path(), "a.cast").is_ok());
+        assert!(read_in(dir.path(), "../a.cast").is_err());
+        assert!(read_in(dir.path(), "notes.txt").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,267 @@
+use anyhow::{anyhow, Result};
+use lsp_types::{DocumentChangeOperation, DocumentChanges, OneOf, TextEdit, WorkspaceEdit};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::search::collect_files_recursively;
+use crate::services::{Change, Edit, Operation};
+use crate::words::is_word_char;
+
+// Textual rename for languages without a rename-capable server, config
+// keys and YAML: whole-word occurrences of a symbol are previewed per file,
+// the client picks the ones to change and they go through the batch edit api.
+// Servers that can rename answer lsp:rename with a WorkspaceEdit, turned
+// into the same batch edits here.
+
+/// Stop collecting once this many occurrences were found, a preview larger
+/// than that is not reviewable anyway
+pub const MAX_OCCURRENCES: usize = 5000;
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Occurrence {
+    pub line: usize,
+    /// Column in chars
+    pub column: usize,
+    /// UTF-16 offset in the document, the unit of file:change edits
+    pub start: usize,
+    pub preview: String,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct FileOccurrences {
+    pub file: String,
+    pub occurrences: Vec<Occurrence>,
+}
+
+/// Files a rename runs over: the whole workspace for an empty scope, the
+/// file itself or every not ignored file below a directory.
+pub fn scope_files(scope: &str) -> Result<Vec<PathBuf>> {
+    let scope = match scope.trim() {
+        "" => Path::new("."),
+        s => Path::new(s),
+    };
+
+    if scope.is_file() {
+        Ok(vec![scope.to_path_buf()])
+    } else if scope.is_dir() {
+        collect_files_recursively(scope)
+    } else {
+        Err(anyhow!("Scope not found: {}", scope.display()))
+    }
+}
+
+/// Occurrences of `symbol` not glued to other identifier chars of `lang`,
+/// `max` is not found in `max_size` or `xmax`.
+pub fn find_occurrences(text: &str, symbol: &str, lang: &str) -> Vec<Occurrence> {
+    let mut occurrences = Vec::new();
+    if symbol.is_empty() {
+        return occurrences;
+    }
+
+    let mut line_start_utf16 = 0;
+    for (line_idx, line) in text.split_inclusive('\n').enumerate() {
+        for (byte_idx, _) in line.match_indices(symbol) {
+            let before = line[..byte_idx].chars().next_back();
+            let after = line[byte_idx + symbol.len()..].chars().next();
+            let glued = |c: Option<char>| c.is_some_and(|c| is_word_char(c, lang));
+            if glued(before) || glued(after) {
+                continue;
+            }
+
+            occurrences.push(Occurrence {
+                line: line_idx,
+                column: line[..byte_idx].chars().count(),
+                start: line_start_utf16 + line[..byte_idx].encode_utf16().count(),
+                preview: line.trim_end().to_string(),
+            });
+        }
+        line_start_utf16 += line.encode_utf16().count();
+    }
+
+    occurrences
+}
+
+/// Edits replacing the occurrences at the given UTF-16 offsets. They are
+/// ordered from the end of the document, so every offset stays valid
+/// while the previous edits are applied.
+pub fn rename_edits(starts: &[usize], symbol: &str, new_name: &str) -> Vec<Edit> {
+    let mut starts = starts.to_vec();
+    starts.sort_unstable_by(|a, b| b.cmp(a));
+    starts.dedup();
+
+    starts.into_iter()
+        .flat_map(|start| [
+            Edit { operation: Operation::Remove, start, text: symbol.to_string() },
+            Edit { operation: Operation::Insert, start, text: new_name.to_string() },
+        ])
+        .collect()
+}
+
+/// Text edits of a workspace edit by file path. File creates, renames and
+/// deletes are not supported and left out.
+pub fn workspace_edit_files(edit: WorkspaceEdit) -> Vec<(String, Vec<TextEdit>)> {
+    let mut files: Vec<(String, Vec<TextEdit>)> = Vec::new();
+    let mut add = |uri: &lsp_types::Uri, edits: Vec<TextEdit>| {
+        let Some(path) = crate::paths::uri_to_path(uri.as_str()) else { return };
+        match files.iter_mut().find(|(p, _)| *p == path) {
+            Some((_, existing)) => existing.extend(edits),
+            None => files.push((path, edits)),
+        }
+    };
+
+    let document_edits = match edit.document_changes {
+        Some(DocumentChanges::Edits(edits)) => edits,
+        Some(DocumentChanges::Operations(operations)) => operations.into_iter()
+            .filter_map(|op| match op {
+                DocumentChangeOperation::Edit(edit) => Some(edit),
+                DocumentChangeOperation::Op(_) => None,
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+    for document in document_edits {
+        let edits = document.edits.into_iter()
+            .map(|edit| match edit {
+                OneOf::Left(edit) => edit,
+                OneOf::Right(annotated) => annotated.text_edit,
+            })
+            .collect();
+        add(&document.text_document.uri, edits);
+    }
+
+    for (uri, edits) in edit.changes.unwrap_or_default() {
+        add(&uri, edits);
+    }
+    files
+}
+
+/// Text edits of a file as file:change edits against its current text.
+/// LSP positions count UTF-16 units like the edits do. The edits are
+/// applied from the end of the document, inserts at the same position
+/// keep the order the server sent them in.
+pub fn text_edits_change(path: &str, text: &str, edits: &[TextEdit]) -> Change {
+    let units: Vec<u16> = text.encode_utf16().collect();
+    let mut line_starts = vec![0];
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        offset += line.encode_utf16().count();
+        line_starts.push(offset);
+    }
+    let to_offset = |position: lsp_types::Position| {
+        let line = (position.line as usize).min(line_starts.len() - 1);
+        (line_starts[line] + position.character as usize).min(units.len())
+    };
+
+    let mut ordered: Vec<(usize, usize, usize, &str)> = edits.iter().enumerate()
+        .map(|(i, edit)| (to_offset(edit.range.start), to_offset(edit.range.end), i, edit.new_text.as_str()))
+        .collect();
+    ordered.sort_by_key(|&(start, _, i, _)| std::cmp::Reverse((start, i)));
+
+    let mut change_edits = Vec::new();
+    for (start, end, _, new_text) in ordered {
+        if end > start {
+            let removed = String::from_utf16_lossy(&units[start..end]);
+            change_edits.push(Edit { operation: Operation::Remove, start, text: removed });
+        }
+        if !new_text.is_empty() {
+            change_edits.push(Edit { operation: Operation::Insert, start, text: new_text.to_string() });
+        }
+    }
+    Change { file: path.to_string(), edits: change_edits, base: None, version: None }
+}
+
+/// Changes of a server rename, `texts` holds the current text of each file
+pub fn workspace_edit_changes(edit: WorkspaceEdit, texts: &HashMap<String, String>) -> Result<Vec<Change>> {
+    workspace_edit_files(edit).into_iter()
+        .map(|(path, edits)| {
+            let text = texts.get(&path).ok_or_else(|| anyhow!("No text of {}", path))?;
+            Ok(text_edits_change(&path, text, &edits))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod rename_tests {
+    use super::*;
+    use crate::fixtures::apply_edits;
+
+    #[test]
+    fn test_find_occurrences_whole_words() {
+        let text = "max = 1\nmax_size: max\nxmax, max!\n";
+        let found = find_occurrences(text, "max", "text");
+
+        let positions: Vec<(usize, usize)> = found.iter().map(|o| (o.line, o.column)).collect();
+        assert_eq!(positions, [(0, 0), (1, 10), (2, 6)]);
+        assert_eq!(found[1].preview, "max_size: max");
+        assert_eq!(found[2].start, "max = 1\nmax_size: max\nxmax, ".encode_utf16().count());
+    }
+
+    #[test]
+    fn test_find_occurrences_lang_rules() {
+        // `-` is part of identifiers in css, not in rust
+        let text = "color: var(--main-color); main";
+        assert_eq!(find_occurrences(text, "main", "css").len(), 1);
+        assert_eq!(find_occurrences(text, "main", "rust").len(), 2);
+    }
+
+    #[test]
+    fn test_find_occurrences_utf16_offsets() {
+        let text = "😀 key\r\nключ key";
+        let starts: Vec<usize> = find_occurrences(text, "key", "text").iter().map(|o| o.start).collect();
+        assert_eq!(starts, [3, 13]);
+    }
+
+    #[test]
+    fn test_rename_edits_apply_from_the_end() {
+        let mut code = crate::code::Code::from_str("a b a");
+        for edit in rename_edits(&[0, 4], "a", "long") {
+            let start = code.utf16_to_char_offset(edit.start);
+            match edit.operation {
+                Operation::Insert => code.insert_text_at(&edit.text, start),
+                Operation::Remove => code.remove_text2(start, start + edit.text.chars().count()),
+            }
+        }
+        assert_eq!(code.text.to_string(), "long b long");
+    }
+
+    #[test]
+    fn test_workspace_edit_changes() -> Result<()> {
+        use lsp_types::{Position, Range, TextDocumentEdit, OptionalVersionedTextDocumentIdentifier};
+        let edit = |line, from, to, text: &str| TextEdit {
+            range: Range::new(Position::new(line, from), Position::new(line, to)),
+            new_text: text.to_string(),
+        };
+        let uri = |path: &str| -> lsp_types::Uri { crate::paths::file_uri(path).parse().unwrap() };
+
+        let a = "fn 😀() { x }\nlet y = x;\n";
+        let b = "use x;\n";
+        let workspace_edit = WorkspaceEdit {
+            changes: Some(HashMap::from([(uri("/w/b.rs"), vec![edit(0, 4, 5, "value")])])),
+            document_changes: Some(DocumentChanges::Edits(vec![TextDocumentEdit {
+                text_document: OptionalVersionedTextDocumentIdentifier { uri: uri("/w/a.rs"), version: None },
+                edits: vec![
+                    // Columns after the emoji count two UTF-16 units for it
+                    OneOf::Left(edit(0, 10, 11, "value")),
+                    OneOf::Left(edit(1, 8, 9, "value")),
+                    // Inserts at the same position keep their order
+                    OneOf::Left(edit(1, 0, 0, "// a\n")),
+                    OneOf::Left(edit(1, 0, 0, "// b\n")),
+                ],
+            }])),
+            change_annotations: None,
+        };
+
+        let files = workspace_edit_files(workspace_edit.clone());
+        let texts: HashMap<String, String> = files.iter()
+            .map(|(path, _)| (path.clone(), if path.ends_with("a.rs") { a } else { b }.to_string()))
+            .collect();
+        let changes = workspace_edit_changes(workspace_edit, &texts)?;
+        assert_eq!(changes.len(), 2);
+
+        assert_eq!(apply_edits(a, &changes[0].edits), "fn 😀() { value }\n// a\n// b\nlet y = value;\n");
+        assert_eq!(apply_edits(b, &changes[1].edits), "use value;\n");
+        assert!(workspace_edit_changes(WorkspaceEdit::default(), &HashMap::new())?.is_empty());
+        Ok(())
+    }
+}
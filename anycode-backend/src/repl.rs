@@ -0,0 +1,299 @@
+use anyhow::{anyhow, Result};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+
+use crate::config::Config;
+
+// Interpreters kept running per language for inline evaluation, apart
+// from the terminals. Each one runs a small driver that reads one JSON
+// request per line on stdin, evaluates the code in a namespace that
+// lives as long as the process and answers with one JSON line: the
+// captured output, the value of the last expression and the error.
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+const PYTHON_DRIVER: &str = r#"
+import ast, io, json, sys, traceback
+ns = {"__name__": "__repl__"}
+out = sys.stdout
+for line in sys.stdin:
+    code = json.loads(line)["code"]
+    buf = io.StringIO()
+    sys.stdout = sys.stderr = buf
+    value = error = None
+    try:
+        tree = ast.parse(code, "<repl>", "exec")
+        last = None
+        if tree.body and isinstance(tree.body[-1], ast.Expr):
+            last = ast.Expression(tree.body.pop().value)
+        exec(compile(tree, "<repl>", "exec"), ns)
+        if last is not None:
+            result = eval(compile(last, "<repl>", "eval"), ns)
+            if result is not None:
+                value = repr(result)
+    except BaseException:
+        error = traceback.format_exc()
+    finally:
+        sys.stdout, sys.stderr = out, sys.__stderr__
+    out.write(json.dumps({"output": buf.getvalue(), "value": value, "error": error}) + "\n")
+    out.flush()
+"#;
+
+const NODE_DRIVER: &str = r#"
+const vm = require('vm'), util = require('util'), readline = require('readline');
+globalThis.require = require;
+const stdout = process.stdout.write.bind(process.stdout);
+const stderr = process.stderr.write.bind(process.stderr);
+let queue = Promise.resolve();
+readline.createInterface({ input: process.stdin }).on('line', line => {
+  queue = queue.then(() => evaluate(JSON.parse(line).code));
+});
+async function evaluate(code) {
+  let output = '', value = null, error = null;
+  process.stdout.write = process.stderr.write = chunk => { output += chunk; return true; };
+  try {
+    let result = vm.runInThisContext(code, { filename: '<repl>' });
+    if (result && typeof result.then === 'function') result = await result;
+    if (result !== undefined) value = util.inspect(result);
+  } catch (e) {
+    error = e && e.stack ? e.stack : String(e);
+  } finally {
+    process.stdout.write = stdout;
+    process.stderr.write = stderr;
+  }
+  stdout(JSON.stringify({ output, value, error }) + '\n');
+}
+"#;
+
+/// Flag and driver script of an interpreter, by its program name
+fn driver(program: &str) -> Option<(&'static str, &'static str)> {
+    let name = crate::exec::program_name(program);
+    if name.starts_with("python") {
+        Some(("-c", PYTHON_DRIVER))
+    } else if name == "node" {
+        Some(("-e", NODE_DRIVER))
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ReplInfo {
+    pub lang: String,
+    pub program: String,
+    pub pid: Option<u32>,
+}
+
+/// Result of an evaluation, what the editor shows inline
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct EvalResult {
+    /// What the code printed, stdout and stderr
+    pub output: String,
+    /// Representation of the value of the last expression
+    pub value: Option<String>,
+    /// The exception and its traceback
+    pub error: Option<String>,
+    #[serde(default)]
+    pub elapsed_ms: u128,
+}
+
+struct Interpreter {
+    lang: String,
+    _child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+}
+
+impl Interpreter {
+    async fn eval(&mut self, code: &str) -> Result<EvalResult> {
+        let request = json!({ "code": code }).to_string() + "\n";
+        self.stdin.write_all(request.as_bytes()).await?;
+        self.stdin.flush().await?;
+
+        let line = self.stdout.next_line().await?
+            .ok_or_else(|| anyhow!("The {} interpreter exited", self.lang))?;
+        Ok(serde_json::from_str(&line)?)
+    }
+}
+
+type Shared = Arc<tokio::sync::Mutex<Interpreter>>;
+
+#[derive(Clone)]
+struct Entry {
+    info: ReplInfo,
+    interpreter: Shared,
+}
+
+static REPLS: OnceLock<Mutex<HashMap<String, Entry>>> = OnceLock::new();
+
+fn repls() -> &'static Mutex<HashMap<String, Entry>> {
+    REPLS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Interpreter program configured for a language
+fn program<'a>(config: &'a Config, lang: &str) -> Result<&'a str> {
+    let language = config.language.iter().find(|l| l.name == lang)
+        .ok_or_else(|| anyhow!("Unknown language {}", lang))?;
+    language.repl.as_deref()
+        .ok_or_else(|| anyhow!("No repl configured for {}", lang))
+}
+
+fn spawn(lang: &str, program: &str) -> Result<Entry> {
+    let (flag, script) = driver(program)
+        .ok_or_else(|| anyhow!("{} is not a supported interpreter", program))?;
+
+    let mut child = Command::new(program)
+        .arg(flag)
+        .arg(script)
+        .current_dir(std::env::current_dir()?)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| anyhow!("Failed to start {}: {}", program, e))?;
+
+    let stdin = child.stdin.take().ok_or_else(|| anyhow!("No stdin"))?;
+    let stdout = child.stdout.take().ok_or_else(|| anyhow!("No stdout"))?;
+    let info = ReplInfo { lang: lang.to_string(), program: program.to_string(), pid: child.id() };
+    crate::output::write("repl", &format!("Started {} for {}", program, lang));
+
+    let interpreter = Interpreter {
+        lang: lang.to_string(),
+        _child: child,
+        stdin,
+        stdout: BufReader::new(stdout).lines(),
+    };
+    Ok(Entry { info, interpreter: Arc::new(tokio::sync::Mutex::new(interpreter)) })
+}
+
+/// Start the interpreter of a language, or return the running one. With
+/// `restart` a running interpreter is replaced and its state is lost.
+pub fn start(config: &Config, lang: &str, restart: bool) -> Result<ReplInfo> {
+    let program = program(config, lang)?;
+    crate::exec::check(program, config.exec.as_ref())?;
+
+    let mut repls = repls().lock();
+    if !restart && let Some(entry) = repls.get(lang) {
+        return Ok(entry.info.clone());
+    }
+
+    let entry = spawn(lang, program)?;
+    let info = entry.info.clone();
+    repls.insert(lang.to_string(), entry);
+    Ok(info)
+}
+
+/// Evaluate code in the interpreter of the language, starting it when
+/// needed. An evaluation running past the timeout stops the interpreter.
+pub async fn eval(config: &Config, lang: &str, code: &str, timeout: Option<Duration>) -> Result<EvalResult> {
+    start(config, lang, false)?;
+    let repl = repls().lock().get(lang).map(|e| e.interpreter.clone())
+        .ok_or_else(|| anyhow!("The {} interpreter is not running", lang))?;
+
+    let timeout = timeout.unwrap_or(DEFAULT_TIMEOUT);
+    let started = Instant::now();
+    let mut interpreter = repl.lock().await;
+    let result = match tokio::time::timeout(timeout, interpreter.eval(code)).await {
+        Ok(result) => result,
+        Err(_) => Err(anyhow!("Evaluation timed out after {}s, the interpreter was stopped", timeout.as_secs())),
+    };
+    drop(interpreter);
+
+    match result {
+        Ok(mut result) => {
+            result.elapsed_ms = started.elapsed().as_millis();
+            Ok(result)
+        }
+        Err(e) => {
+            // The interpreter is gone or busy forever, the next eval starts a new one
+            remove(lang, &repl);
+            Err(e)
+        }
+    }
+}
+
+fn remove(lang: &str, repl: &Shared) {
+    let mut repls = repls().lock();
+    if repls.get(lang).is_some_and(|e| Arc::ptr_eq(&e.interpreter, repl)) {
+        repls.remove(lang);
+    }
+}
+
+/// Stop the interpreter of a language, false when none was running
+pub fn stop(lang: &str) -> bool {
+    repls().lock().remove(lang).is_some()
+}
+
+/// Running interpreters
+pub fn list() -> Vec<ReplInfo> {
+    repls().lock().values().map(|e| e.info.clone()).collect()
+}
+
+#[cfg(test)]
+mod repl_tests {
+    use super::*;
+    use crate::config::{IndentConfig, Language};
+
+    fn config(program: &str) -> Config {
+        let mut config = Config::default();
+        config.language.push(Language {
+            name: "python".to_string(),
+            types: vec!["py".to_string()],
+            comment: "#".to_string(),
+            lsp: None,
+            indent: IndentConfig { width: 4, unit: " ".to_string() },
+            executable: None,
+            exec: None,
+            exectest: None,
+            repl: Some(program.to_string()),
+            alternates: Vec::new(),
+        });
+        config
+    }
+
+    #[test]
+    fn test_driver() {
+        assert_eq!(driver("/usr/bin/python3").map(|d| d.0), Some("-c"));
+        assert_eq!(driver("node").map(|d| d.0), Some("-e"));
+        assert!(driver("ruby").is_none());
+        assert!(start(&config("ruby"), "python", false).is_err());
+        assert!(start(&config("python3"), "go", false).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_python_eval() -> Result<()> {
+        if std::process::Command::new("python3").arg("--version").output().is_err() {
+            return Ok(());
+        }
+        let config = config("python3");
+
+        let result = eval(&config, "python", "x = 20\nprint('hi')\nx + 1", None).await?;
+        assert_eq!(result.output, "hi\n");
+        assert_eq!(result.value.as_deref(), Some("21"));
+        assert!(result.error.is_none());
+
+        // State persists between evaluations
+        let result = eval(&config, "python", "x * 2", None).await?;
+        assert_eq!(result.value.as_deref(), Some("40"));
+
+        let result = eval(&config, "python", "1 / 0", None).await?;
+        assert!(result.error.is_some_and(|e| e.contains("ZeroDivisionError")));
+
+        let timeout = Some(Duration::from_millis(200));
+        assert!(eval(&config, "python", "import time; time.sleep(5)", timeout).await.is_err());
+        let result = eval(&config, "python", "'x' in globals()", None).await?;
+        assert_eq!(result.value.as_deref(), Some("False"));
+
+        assert!(stop("python"));
+        assert!(!stop("python"));
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+use anyhow::{anyhow, Result};
+use regex::{Captures, Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+// Replacement engine of search:replace. The pattern is literal text
+// unless `regex` is set, then the replacement may refer to capture groups
+// with `$1` or `${name}` (`$$` for a dollar). With `smart_case` a pattern
+// without uppercase letters matches any case, and each replacement takes
+// the capitalization of the text it replaces (foo -> bar, Foo -> Bar,
+// FOO -> BAR).
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub struct ReplaceOptions {
+    #[serde(default)]
+    pub regex: bool,
+    #[serde(default)]
+    pub smart_case: bool,
+}
+
+/// Capitalization of a matched text, by its cased letters
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CasePattern {
+    Lower,
+    Upper,
+    /// First letter uppercase, the others lowercase
+    Title,
+    /// Mixed, or no cased letters at all
+    Other,
+}
+
+fn case_pattern(text: &str) -> CasePattern {
+    let mut letters = text.chars().filter(|c| c.is_lowercase() || c.is_uppercase());
+    let Some(first) = letters.next() else { return CasePattern::Other };
+    let (mut lower, mut upper) = (0, 0);
+    for c in letters {
+        if c.is_uppercase() { upper += 1 } else { lower += 1 }
+    }
+
+    match (first.is_uppercase(), lower, upper) {
+        (false, _, 0) => CasePattern::Lower,
+        // A single capital is a title, not a shout
+        (true, 0, 0) => CasePattern::Title,
+        (true, 0, _) => CasePattern::Upper,
+        (true, _, 0) => CasePattern::Title,
+        _ => CasePattern::Other,
+    }
+}
+
+/// Apply the capitalization of `matched` to the replacement
+fn apply_case(matched: &str, replacement: &str) -> String {
+    match case_pattern(matched) {
+        CasePattern::Lower => replacement.to_lowercase(),
+        CasePattern::Upper => replacement.to_uppercase(),
+        CasePattern::Title => {
+            let mut chars = replacement.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        }
+        CasePattern::Other => replacement.to_string(),
+    }
+}
+
+pub struct Replacer {
+    regex: Regex,
+    replacement: String,
+    options: ReplaceOptions,
+}
+
+impl Replacer {
+    pub fn new(pattern: &str, replacement: &str, options: ReplaceOptions) -> Result<Self> {
+        if pattern.is_empty() {
+            return Err(anyhow!("Empty pattern"));
+        }
+        let source = match options.regex {
+            true => pattern.to_string(),
+            false => regex::escape(pattern),
+        };
+        let ignore_case = options.smart_case && !pattern.chars().any(char::is_uppercase);
+        let regex = RegexBuilder::new(&source)
+            .case_insensitive(ignore_case)
+            .multi_line(true)
+            .build()
+            .map_err(|e| anyhow!("Invalid pattern: {}", e))?;
+
+        Ok(Self { regex, replacement: replacement.to_string(), options })
+    }
+
+    fn expand(&self, caps: &Captures) -> String {
+        let mut replaced = String::new();
+        match self.options.regex {
+            true => caps.expand(&self.replacement, &mut replaced),
+            false => replaced.push_str(&self.replacement),
+        }
+        match self.options.smart_case {
+            true => apply_case(&caps[0], &replaced),
+            false => replaced,
+        }
+    }
+
+    /// Replace every match, returns the new text and the number of matches
+    pub fn replace_all(&self, text: &str) -> (String, usize) {
+        let mut count = 0;
+        let replaced = self.regex.replace_all(text, |caps: &Captures| {
+            count += 1;
+            self.expand(caps)
+        });
+        (replaced.into_owned(), count)
+    }
+}
+
+#[cfg(test)]
+mod replace_tests {
+    use super::*;
+
+    fn replace(pattern: &str, replacement: &str, regex: bool, smart_case: bool, text: &str) -> (String, usize) {
+        Replacer::new(pattern, replacement, ReplaceOptions { regex, smart_case })
+            .unwrap()
+            .replace_all(text)
+    }
+
+    #[test]
+    fn test_literal() {
+        // Regex syntax in a literal pattern and replacement is plain text
+        assert_eq!(replace("a.b", "$1 (x)", false, false, "a.b axb a.b"), ("$1 (x) axb $1 (x)".to_string(), 2));
+        assert_eq!(replace("[é]", "ü", false, false, "[é] é"), ("ü é".to_string(), 1));
+        assert_eq!(replace("foo", "bar", false, false, "Foo foo"), ("Foo bar".to_string(), 1));
+        assert!(Replacer::new("", "x", ReplaceOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_capture_groups() {
+        let (text, count) = replace(r"(\w+)@(\w+)", "$2 at ${1}", true, false, "joe@mail ünï@ßtr");
+        assert_eq!((text.as_str(), count), ("mail at joe ßtr at ünï", 2));
+
+        let (text, _) = replace(r"(?P<key>\w+)=(?P<value>\d+)", "${value}:$key $$", true, false, "größe=42");
+        assert_eq!(text, "42:größe $");
+
+        assert_eq!(replace("^- ", "* ", true, false, "- a\n- b").0, "* a\n* b");
+        assert!(Replacer::new("(", "", ReplaceOptions { regex: true, smart_case: false }).is_err());
+    }
+
+    #[test]
+    fn test_smart_case() {
+        let (text, count) = replace("color", "colour", false, true, "color Color COLOR cOlOr");
+        assert_eq!((text.as_str(), count), ("colour Colour COLOUR colour", 4));
+
+        // An uppercase letter in the pattern makes the match case sensitive
+        assert_eq!(replace("Color", "hue", false, true, "color Color").0, "color Hue");
+        // A single capital is a title
+        assert_eq!(replace("x", "why", false, true, "x X").0, "why Why");
+        // Matches without letters take the replacement as typed
+        assert_eq!(replace("42", "forty-Two", false, true, "42").0, "forty-Two");
+    }
+
+    #[test]
+    fn test_smart_case_unicode() {
+        assert_eq!(replace("straße", "weg", false, true, "straße Straße STRASSE").0, "weg Weg STRASSE");
+        assert_eq!(replace("weg", "straße", false, true, "weg Weg WEG").0, "straße Straße STRASSE");
+        assert_eq!(replace("улица", "проспект", false, true, "улица Улица УЛИЦА").0, "проспект Проспект ПРОСПЕКТ");
+        // Final sigma when lowering
+        assert_eq!(replace("δρομος", "ΟΔΟΣ", false, true, "δρομος").0, "οδο\u{3c2}");
+        // Case-insensitive matching covers non-ASCII letters
+        assert_eq!(replace("élan", "vigueur", false, true, "ÉLAN Élan").0, "VIGUEUR Vigueur");
+        // Replacing keeps the surrounding multi-byte text intact
+        assert_eq!(replace("b", "c", false, true, "日本b語B😀").0, "日本c語C😀");
+        // Title case of a replacement starting with a multi-char uppercase
+        assert_eq!(replace("x", "ßa", false, true, "X").0, "SSa");
+    }
+
+    #[test]
+    fn test_smart_case_with_groups() {
+        let options = ReplaceOptions { regex: true, smart_case: true };
+        let replacer = Replacer::new(r"get_(\w+)", "fetch_$1", options).unwrap();
+        // Mixed case keeps the replacement as expanded
+        assert_eq!(replacer.replace_all("get_name GET_NAME Get_item Get_Item").0, "fetch_name FETCH_NAME Fetch_item fetch_Item");
+    }
+}
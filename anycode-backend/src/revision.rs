@@ -0,0 +1,149 @@
+use std::collections::VecDeque;
+
+use crate::services::{Edit, Operation};
+
+// Versions of the buffers for concurrent edits. Every change applied to a
+// buffer moves its version on and clients send the version their edits
+// were made on as `base`. A change made on an older version is rebased
+// over the changes applied since when it touches other text, and refused
+// when they overlap or are no longer logged, the client is then resynced
+// with `file:set`. Replacing the whole text (set, reload) can't be
+// rebased over and clears the log.
+
+/// Changes logged per buffer, older bases are refused
+const MAX_LOGGED: usize = 200;
+
+/// Text an edit replaces, in UTF-16 units
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub removed: usize,
+    pub inserted: usize,
+}
+
+impl Span {
+    pub fn of(edit: &Edit) -> Self {
+        let len = edit.text.encode_utf16().count();
+        match edit.operation {
+            Operation::Insert => Span { start: edit.start, removed: 0, inserted: len },
+            Operation::Remove => Span { start: edit.start, removed: len, inserted: 0 },
+        }
+    }
+
+    fn end(&self) -> usize {
+        self.start + self.removed
+    }
+}
+
+/// Move `a` over `b` and `b` over `a`, both made on the same text. None
+/// when they touch the same text, at the same position `a` comes first.
+fn transform(a: &mut Span, b: &mut Span) -> Option<()> {
+    if a.end() <= b.start {
+        b.start = b.start - a.removed + a.inserted;
+    } else if b.end() <= a.start {
+        a.start = a.start - b.removed + b.inserted;
+    } else {
+        return None;
+    }
+    Some(())
+}
+
+/// The changes that led to the last versions of a buffer
+#[derive(Debug, Default, Clone)]
+pub struct Log {
+    changes: VecDeque<Vec<Span>>,
+}
+
+impl Log {
+    /// Log the edits of the change to the next version
+    pub fn push(&mut self, edits: &[Edit]) {
+        self.changes.push_back(edits.iter().map(Span::of).collect());
+        if self.changes.len() > MAX_LOGGED {
+            self.changes.pop_front();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.changes.clear();
+    }
+
+    /// Spans of the changes after version `base` in order, the buffer
+    /// being at `version`. None when the log does not go back to `base`.
+    pub fn since(&self, base: u64, version: u64) -> Option<Vec<Span>> {
+        let since = usize::try_from(version.checked_sub(base)?).ok()?;
+        if since > self.changes.len() {
+            return None;
+        }
+        Some(self.changes.iter().skip(self.changes.len() - since).flatten().copied().collect())
+    }
+
+    /// Edits made on version `base` moved over the changes since, the
+    /// buffer being at `version`. None when they overlap one of them or
+    /// the log does not go back to `base`.
+    pub fn rebase(&self, base: u64, version: u64, edits: &[Edit]) -> Option<Vec<Edit>> {
+        let mut applied = self.since(base, version)?;
+
+        // Each edit is moved over the changes, which then follow it for
+        // the next edit
+        let mut rebased = Vec::with_capacity(edits.len());
+        for edit in edits {
+            let mut span = Span::of(edit);
+            for other in applied.iter_mut() {
+                transform(&mut span, other)?;
+            }
+            rebased.push(Edit { start: span.start, ..edit.clone() });
+        }
+        Some(rebased)
+    }
+}
+
+#[cfg(test)]
+mod revision_tests {
+    use super::*;
+
+    fn insert(start: usize, text: &str) -> Edit {
+        Edit { operation: Operation::Insert, start, text: text.to_string() }
+    }
+
+    fn remove(start: usize, text: &str) -> Edit {
+        Edit { operation: Operation::Remove, start, text: text.to_string() }
+    }
+
+    fn starts(edits: Option<Vec<Edit>>) -> Option<Vec<usize>> {
+        edits.map(|edits| edits.iter().map(|e| e.start).collect())
+    }
+
+    #[test]
+    fn test_rebase() {
+        // "hello world" at version 0, then another client typed at both ends
+        let mut log = Log::default();
+        log.push(&[insert(0, ">> ")]);
+        log.push(&[insert(14, "!")]);
+
+        // Made on version 0: "world" removed, "there" typed in its place
+        let edits = [remove(6, "world"), insert(6, "there")];
+        assert_eq!(starts(log.rebase(0, 2, &edits)), Some(vec![9, 9]));
+        assert_eq!(starts(log.rebase(1, 2, &edits)), Some(vec![6, 6]));
+        assert_eq!(starts(log.rebase(2, 2, &edits)), Some(vec![6, 6]));
+
+        // Inserts at the same position go before the logged one
+        assert_eq!(starts(log.rebase(0, 2, &[insert(0, "# ")])), Some(vec![0]));
+        // An insert after an earlier removal
+        let mut log = Log::default();
+        log.push(&[remove(2, "ll")]);
+        assert_eq!(starts(log.rebase(0, 1, &[insert(4, "!")])), Some(vec![2]));
+    }
+
+    #[test]
+    fn test_rebase_refused() {
+        let mut log = Log::default();
+        log.push(&[remove(6, "world")]);
+        assert!(log.rebase(0, 1, &[insert(8, "x")]).is_none());
+        assert!(log.rebase(0, 1, &[remove(4, "o w")]).is_none());
+        // Not logged, or a version the buffer never had
+        assert!(log.rebase(0, 2, &[insert(0, "x")]).is_none());
+        assert!(log.rebase(3, 1, &[insert(0, "x")]).is_none());
+        log.clear();
+        assert!(log.rebase(0, 1, &[insert(0, "x")]).is_none());
+    }
+}
@@ -0,0 +1,392 @@
+use lsp_types::{
+    CompletionItem, Diagnostic, DiagnosticSeverity, Documentation, Hover, HoverContents, MarkedString,
+    MarkupContent, MarkupKind, NumberOrString, ParameterLabel, PublishDiagnosticsParams, SignatureHelp,
+};
+use serde::Serialize;
+use serde_json::Value;
+
+// Plain text of the markdown and HTML-ish strings language servers send in
+// hovers, completion docs and diagnostics, for screen readers. Responses
+// keep the server's fields and gain a `plain` one next to them. Structure
+// survives as markers on their own line or at the line start:
+// `[heading 2]`, `[code rust]` ... `[end code]`, `[item]`, `[item 3]`,
+// `[quote]`, `[separator]`, `[image]`, and diagnostics start with their
+// severity, `[error]`. Links read as `text (url)`, emphasis is dropped.
+
+/// HTML tags servers put in docs, other `<...>` is text (`Vec<String>`)
+const HTML_TAGS: &[&str] = &[
+    "a", "b", "br", "code", "details", "div", "em", "h1", "h2", "h3", "h4", "h5", "h6", "hr", "i", "img",
+    "li", "ol", "p", "pre", "span", "strong", "sub", "summary", "sup", "table", "td", "th", "tr", "ul",
+];
+
+/// Plain text of a markdown string
+pub fn markdown_to_plain(text: &str) -> String {
+    let mut lines = Vec::new();
+    let mut fence: Option<&str> = None;
+
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if let Some(marker) = fence {
+            if trimmed.starts_with(marker) {
+                lines.push("[end code]".to_string());
+                fence = None;
+            } else {
+                lines.push(line.to_string());
+            }
+            continue;
+        }
+
+        if let Some(marker) = ["```", "~~~"].into_iter().find(|m| trimmed.starts_with(m)) {
+            let lang = trimmed.trim_start_matches(marker).trim();
+            lines.push(if lang.is_empty() { "[code]".to_string() } else { format!("[code {}]", lang) });
+            fence = Some(marker);
+            continue;
+        }
+
+        let html = strip_html(line);
+        for line in html.split('\n') {
+            lines.push(block_line(line));
+        }
+    }
+    if fence.is_some() {
+        lines.push("[end code]".to_string());
+    }
+
+    collapse_blank_lines(&lines)
+}
+
+/// A line outside code blocks with its block marker
+fn block_line(line: &str) -> String {
+    let indent = &line[..line.len() - line.trim_start().len()];
+    let trimmed = line.trim();
+
+    let hashes = trimmed.chars().take_while(|c| *c == '#').count();
+    if (1..=6).contains(&hashes) && trimmed[hashes..].starts_with(' ') {
+        return format!("[heading {}] {}", hashes, inline(trimmed[hashes..].trim()));
+    }
+    let compact: Vec<char> = trimmed.chars().filter(|c| *c != ' ').collect();
+    if compact.len() >= 3 && ['-', '*', '_'].iter().any(|m| compact.iter().all(|c| c == m)) {
+        return "[separator]".to_string();
+    }
+    if let Some(quote) = trimmed.strip_prefix('>') {
+        return format!("[quote] {}", inline(quote.trim()));
+    }
+    if let Some(item) = ["- ", "* ", "+ "].iter().find_map(|m| trimmed.strip_prefix(m)) {
+        return format!("{}[item] {}", indent, inline(item));
+    }
+    let digits = trimmed.chars().take_while(|c| c.is_ascii_digit()).count();
+    if digits > 0 && (trimmed[digits..].starts_with(". ") || trimmed[digits..].starts_with(") ")) {
+        return format!("{}[item {}] {}", indent, &trimmed[..digits], inline(&trimmed[digits + 2..]));
+    }
+    format!("{}{}", indent, inline(trimmed))
+}
+
+/// Inline markdown of a line: code spans verbatim, links as `text (url)`,
+/// emphasis markers and escapes dropped
+fn inline(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\\' if chars.get(i + 1).is_some_and(|n| n.is_ascii_punctuation()) => {
+                out.push(chars[i + 1]);
+                i += 2;
+            }
+            '`' => {
+                let ticks = chars[i..].iter().take_while(|c| **c == '`').count();
+                let rest: String = chars[i + ticks..].iter().collect();
+                match rest.find(&"`".repeat(ticks)) {
+                    Some(end) => {
+                        out.push_str(rest[..end].trim());
+                        i += ticks + rest[..end].chars().count() + ticks;
+                    }
+                    None => {
+                        out.extend(&chars[i..i + ticks]);
+                        i += ticks;
+                    }
+                }
+            }
+            '!' | '[' => {
+                let image = c == '!';
+                let start = if image { i + 1 } else { i };
+                match (chars.get(start) == Some(&'[')).then(|| link_at(&chars, start)).flatten() {
+                    Some((label, url, end)) => {
+                        let label = inline(&label);
+                        match (image, url.is_empty()) {
+                            (true, _) => out.push_str(&format!("[image] {}", label)),
+                            (false, true) => out.push_str(&label),
+                            (false, false) => out.push_str(&format!("{} ({})", label, url)),
+                        }
+                        i = end;
+                    }
+                    None => {
+                        out.push(c);
+                        i += 1;
+                    }
+                }
+            }
+            '*' | '_' => {
+                let run = chars[i..].iter().take_while(|r| **r == c).count();
+                let before = i.checked_sub(1).map(|p| chars[p]);
+                let after = chars.get(i + run).copied();
+                // `snake_case` and `a * b` keep their chars
+                let intraword = before.is_some_and(char::is_alphanumeric) && after.is_some_and(char::is_alphanumeric);
+                let spaced = before.is_none_or(char::is_whitespace) && after.is_none_or(char::is_whitespace);
+                if intraword || spaced {
+                    out.extend(&chars[i..i + run]);
+                }
+                i += run;
+            }
+            c => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+/// `[label](url)` starting at `start`, with the index after it
+fn link_at(chars: &[char], start: usize) -> Option<(String, String, usize)> {
+    let close = start + chars[start..].iter().position(|c| *c == ']')?;
+    if chars.get(close + 1) != Some(&'(') {
+        return None;
+    }
+    let end = close + 1 + chars[close + 1..].iter().position(|c| *c == ')')?;
+    let label: String = chars[start + 1..close].iter().collect();
+    let url: String = chars[close + 2..end].iter().collect();
+    Some((label, url.split_whitespace().next().unwrap_or_default().to_string(), end + 1))
+}
+
+/// Drop the known HTML tags of a line, line breaks and paragraphs become
+/// newlines, entities are decoded
+fn strip_html(line: &str) -> String {
+    let mut out = String::new();
+    let mut rest = line;
+    while let Some(start) = rest.find('<') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let tag = after.find('>').map(|end| &after[..end]).filter(|tag| {
+            let name = tag.trim_start_matches('/').split([' ', '/']).next().unwrap_or_default();
+            HTML_TAGS.contains(&name.to_ascii_lowercase().as_str())
+        });
+        match tag {
+            Some(tag) => {
+                let name = tag.trim_start_matches('/').split([' ', '/']).next().unwrap_or_default().to_ascii_lowercase();
+                match name.as_str() {
+                    "br" | "p" | "div" | "tr" => out.push('\n'),
+                    "li" if !tag.starts_with('/') => out.push_str("\n- "),
+                    "hr" => out.push_str("\n---\n"),
+                    _ => {}
+                }
+                rest = &after[tag.len() + 1..];
+            }
+            None => {
+                out.push('<');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+
+    [("&nbsp;", " "), ("&lt;", "<"), ("&gt;", ">"), ("&quot;", "\""), ("&#39;", "'"), ("&amp;", "&")]
+        .iter()
+        .fold(out, |text, (entity, c)| text.replace(entity, c))
+}
+
+/// Join the lines with at most one blank line in a row, trimmed
+fn collapse_blank_lines(lines: &[String]) -> String {
+    let mut out: Vec<&str> = Vec::new();
+    for line in lines {
+        let line = line.trim_end();
+        if line.is_empty() && out.last().is_none_or(|l| l.is_empty()) {
+            continue;
+        }
+        out.push(line);
+    }
+    while out.last().is_some_and(|l| l.is_empty()) {
+        out.pop();
+    }
+    out.join("\n")
+}
+
+fn markup_plain(markup: &MarkupContent) -> String {
+    match markup.kind {
+        MarkupKind::PlainText => markup.value.trim().to_string(),
+        MarkupKind::Markdown => markdown_to_plain(&markup.value),
+    }
+}
+
+fn marked_plain(marked: &MarkedString) -> String {
+    match marked {
+        MarkedString::String(text) => markdown_to_plain(text),
+        MarkedString::LanguageString(code) => format!("[code {}]\n{}\n[end code]", code.language, code.value.trim_end()),
+    }
+}
+
+pub fn hover_plain(hover: &Hover) -> String {
+    match &hover.contents {
+        HoverContents::Scalar(marked) => marked_plain(marked),
+        HoverContents::Array(items) => items.iter().map(marked_plain).collect::<Vec<_>>().join("\n\n"),
+        HoverContents::Markup(markup) => markup_plain(markup),
+    }
+}
+
+/// Detail and documentation of a completion item, None without both
+pub fn completion_plain(item: &CompletionItem) -> Option<String> {
+    let documentation = item.documentation.as_ref().map(documentation_plain);
+    let parts: Vec<String> = item.detail.iter().cloned().chain(documentation)
+        .filter(|part| !part.is_empty())
+        .collect();
+    (!parts.is_empty()).then(|| parts.join("\n"))
+}
+
+fn documentation_plain(doc: &Documentation) -> String {
+    match doc {
+        Documentation::String(text) => text.trim().to_string(),
+        Documentation::MarkupContent(markup) => markup_plain(markup),
+    }
+}
+
+/// The active signature with its active parameter marked:
+/// `[signature 1 of 2] fn add(a: i32, [parameter] b: i32)`, then the docs
+pub fn signature_help_plain(help: &SignatureHelp) -> Option<String> {
+    let index = help.active_signature.unwrap_or(0) as usize;
+    let signature = help.signatures.get(index).or(help.signatures.first())?;
+    let active = signature.active_parameter.or(help.active_parameter);
+    let parameter = active.and_then(|a| signature.parameters.as_ref()?.get(a as usize));
+
+    let mut label = signature.label.clone();
+    let span = parameter.and_then(|p| match &p.label {
+        ParameterLabel::Simple(name) => label.find(name.as_str()).map(|start| (start, start + name.len())),
+        // UTF-16 offsets, taken as char offsets
+        ParameterLabel::LabelOffsets([start, end]) => {
+            let byte = |offset: u32| label.char_indices().nth(offset as usize).map(|(i, _)| i);
+            Some((byte(*start)?, byte(*end).unwrap_or(label.len())))
+        }
+    });
+    if let Some((start, _)) = span {
+        label.insert_str(start, "[parameter] ");
+    }
+
+    let mut parts = vec![format!("[signature {} of {}] {}", index + 1, help.signatures.len(), label)];
+    parts.extend(parameter.and_then(|p| p.documentation.as_ref()).map(documentation_plain));
+    parts.extend(signature.documentation.as_ref().map(documentation_plain));
+    Some(parts.into_iter().filter(|p| !p.is_empty()).collect::<Vec<_>>().join("\n"))
+}
+
+/// `[error] rustc E0308: message`
+pub fn diagnostic_plain(diagnostic: &Diagnostic) -> String {
+    let severity = match diagnostic.severity {
+        Some(DiagnosticSeverity::ERROR) => "error",
+        Some(DiagnosticSeverity::WARNING) => "warning",
+        Some(DiagnosticSeverity::INFORMATION) => "info",
+        Some(DiagnosticSeverity::HINT) => "hint",
+        _ => "diagnostic",
+    };
+    let code = diagnostic.code.as_ref().map(|code| match code {
+        NumberOrString::Number(n) => n.to_string(),
+        NumberOrString::String(s) => s.clone(),
+    });
+    let origin: Vec<String> = diagnostic.source.iter().cloned().chain(code).collect();
+    let message = markdown_to_plain(&diagnostic.message);
+    match origin.is_empty() {
+        true => format!("[{}] {}", severity, message),
+        false => format!("[{}] {}: {}", severity, origin.join(" "), message),
+    }
+}
+
+/// The value as JSON with `plain` added, when the value is an object
+fn with_plain<T: Serialize>(value: &T, plain: Option<String>) -> Value {
+    let mut json = serde_json::to_value(value).unwrap_or_default();
+    if let (Some(fields), Some(plain)) = (json.as_object_mut(), plain) {
+        fields.insert("plain".to_string(), Value::String(plain));
+    }
+    json
+}
+
+pub fn hover_json(hover: &Hover) -> Value {
+    with_plain(hover, Some(hover_plain(hover)))
+}
+
+pub fn completions_json(items: &[CompletionItem]) -> Value {
+    Value::Array(items.iter().map(|item| with_plain(item, completion_plain(item))).collect())
+}
+
+pub fn signature_help_json(help: &SignatureHelp) -> Value {
+    with_plain(help, signature_help_plain(help))
+}
+
+pub fn diagnostics_json(params: &PublishDiagnosticsParams) -> Value {
+    let mut json = serde_json::to_value(params).unwrap_or_default();
+    json["diagnostics"] = Value::Array(params.diagnostics.iter()
+        .map(|d| with_plain(d, Some(diagnostic_plain(d))))
+        .collect());
+    json
+}
+
+#[cfg(test)]
+mod rich_text_tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_markdown_blocks() {
+        let markdown = "# Parser\n\nParses **tokens**.\n\n```rust\nfn parse(input: &str) -> Vec<Token>\n```\n\n---\n- first `item`\n2. second\n> note";
+        assert_eq!(markdown_to_plain(markdown), "[heading 1] Parser\n\nParses tokens.\n\n[code rust]\nfn parse(input: &str) -> Vec<Token>\n[end code]\n\n[separator]\n[item] first item\n[item 2] second\n[quote] note");
+    }
+
+    #[test]
+    fn test_inline() {
+        assert_eq!(inline("See [docs](https://docs.rs \"title\") and ![logo](a.png)"), "See docs (https://docs.rs) and [image] logo");
+        // Identifiers and arithmetic keep their underscores and stars
+        assert_eq!(inline("call `snake_case` or my_var, _emph_ a * b"), "call snake_case or my_var, emph a * b");
+        assert_eq!(inline("escaped \\*star\\* and ``a ` b``"), "escaped *star* and a ` b");
+    }
+
+    #[test]
+    fn test_html() {
+        assert_eq!(markdown_to_plain("Returns <code>Vec&lt;u8&gt;</code><br>or Vec<String>"), "Returns Vec<u8>\nor Vec<String>");
+        assert_eq!(markdown_to_plain("<ul><li>one</li><li>two</li></ul>"), "[item] one\n[item] two");
+    }
+
+    #[test]
+    fn test_responses() {
+        let hover: Hover = serde_json::from_value(json!({
+            "contents": [{ "language": "rust", "value": "fn main()" }, "Entry *point*"],
+        })).unwrap();
+        assert_eq!(hover_json(&hover)["plain"], "[code rust]\nfn main()\n[end code]\n\nEntry point");
+
+        let params: PublishDiagnosticsParams = serde_json::from_value(json!({
+            "uri": "file:///w/a.rs",
+            "diagnostics": [{
+                "range": { "start": { "line": 0, "character": 0 }, "end": { "line": 0, "character": 1 } },
+                "severity": 1, "code": "E0308", "source": "rustc", "message": "mismatched types",
+            }],
+        })).unwrap();
+        assert_eq!(diagnostics_json(&params)["diagnostics"][0]["plain"], "[error] rustc E0308: mismatched types");
+
+        let item: CompletionItem = serde_json::from_value(json!({
+            "label": "len", "detail": "fn len(&self) -> usize",
+            "documentation": { "kind": "markdown", "value": "Returns the **length**." },
+        })).unwrap();
+        assert_eq!(completions_json(&[item])[0]["plain"], "fn len(&self) -> usize\nReturns the length.");
+    }
+
+    #[test]
+    fn test_signature_help() {
+        let help: SignatureHelp = serde_json::from_value(json!({
+            "signatures": [{
+                "label": "fn add(a: i32, b: i32) -> i32",
+                "documentation": "Adds two numbers",
+                "parameters": [{ "label": [7, 13] }, { "label": "b: i32", "documentation": { "kind": "markdown", "value": "The *second* one" } }],
+            }],
+            "activeSignature": 0,
+            "activeParameter": 1,
+        })).unwrap();
+        assert_eq!(signature_help_plain(&help).unwrap(), "[signature 1 of 1] fn add(a: i32, [parameter] b: i32) -> i32\nThe second one\nAdds two numbers");
+        assert!(signature_help_plain(&SignatureHelp { signatures: vec![], active_signature: None, active_parameter: None }).is_none());
+    }
+}
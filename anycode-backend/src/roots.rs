@@ -0,0 +1,162 @@
+use anyhow::{anyhow, Result};
+use parking_lot::RwLock;
+use std::path::{Path, PathBuf};
+
+// Workspace roots. The directory the server runs in is the primary root,
+// clients add more for multi-root workspaces. Each root gets language
+// servers of its own with the root as their workspace folder, documents
+// go to the servers of the innermost root containing them.
+
+static EXTRA_ROOTS: RwLock<Vec<PathBuf>> = RwLock::new(Vec::new());
+
+pub fn primary() -> PathBuf {
+    std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."))
+}
+
+/// The primary root followed by the added ones
+pub fn list() -> Vec<PathBuf> {
+    let mut roots = vec![primary()];
+    roots.extend(EXTRA_ROOTS.read().iter().cloned());
+    roots
+}
+
+/// Add a directory as a root, returns its absolute path
+pub fn add(path: &str) -> Result<PathBuf> {
+    let root = crate::paths::absolute(Path::new(path));
+    if !root.is_dir() {
+        return Err(anyhow!("{} is not a directory", root.display()));
+    }
+
+    let mut roots = EXTRA_ROOTS.write();
+    if root != primary() && !roots.contains(&root) {
+        roots.push(root.clone());
+    }
+    Ok(root)
+}
+
+/// Remove an added root, the primary root can not be removed. Returns the
+/// removed root.
+pub fn remove(path: &str) -> Option<PathBuf> {
+    let root = crate::paths::absolute(Path::new(path));
+    let mut roots = EXTRA_ROOTS.write();
+    let index = roots.iter().position(|r| *r == root)?;
+    Some(roots.remove(index))
+}
+
+/// Root of a document: the innermost root containing it, the first root
+/// for documents outside all of them
+fn root_in(roots: &[PathBuf], path: &Path) -> PathBuf {
+    roots.iter()
+        .filter(|root| path.starts_with(root))
+        .max_by_key(|root| root.components().count())
+        .or(roots.first())
+        .cloned()
+        .unwrap_or_default()
+}
+
+pub fn root_of(path: &Path) -> PathBuf {
+    root_in(&list(), path)
+}
+
+/// Canonical path of an existing file inside `roots`, symlinks and `..`
+/// resolved first so neither leads out of them. Relative paths are taken
+/// from the first root. With `new` the file may not exist yet, its parent
+/// directory is resolved instead.
+fn resolve_in(roots: &[PathBuf], path: &str, new: bool) -> Result<PathBuf> {
+    let first = roots.first().ok_or_else(|| anyhow!("No workspace root"))?;
+    let joined = first.join(path);
+    let resolved = match joined.canonicalize() {
+        Ok(resolved) => resolved,
+        Err(e) if new && !joined.exists() => {
+            let name = joined.file_name().ok_or_else(|| anyhow!("Invalid path {}", path))?;
+            let parent = joined.parent().ok_or_else(|| anyhow!("Invalid path {}", path))?;
+            parent.canonicalize().map_err(|_| anyhow!("{}: {}", path, e))?.join(name)
+        }
+        Err(e) => return Err(anyhow!("{}: {}", path, e)),
+    };
+    let inside = roots.iter()
+        .filter_map(|root| root.canonicalize().ok())
+        .any(|root| resolved.starts_with(root));
+    if !inside {
+        return Err(anyhow!("{} is outside of the workspace", path));
+    }
+    Ok(resolved)
+}
+
+pub fn resolve(path: &str) -> Result<PathBuf> {
+    resolve_in(&list(), path, false)
+}
+
+/// Like `resolve` for a file that may not exist yet
+pub fn resolve_new(path: &str) -> Result<PathBuf> {
+    resolve_in(&list(), path, true)
+}
+
+#[cfg(test)]
+mod roots_tests {
+    use super::*;
+
+    #[test]
+    fn test_root_of_document() {
+        let roots = [PathBuf::from("/w"), PathBuf::from("/w/crates/cli"), PathBuf::from("/lib")];
+
+        assert_eq!(root_in(&roots, Path::new("/w/src/main.rs")), Path::new("/w"));
+        assert_eq!(root_in(&roots, Path::new("/w/crates/cli/src/main.rs")), Path::new("/w/crates/cli"));
+        assert_eq!(root_in(&roots, Path::new("/lib/lib.rs")), Path::new("/lib"));
+        // Prefixes are matched by component, not by string
+        assert_eq!(root_in(&roots, Path::new("/library/x.rs")), Path::new("/w"));
+    }
+
+    #[test]
+    fn test_add_and_remove() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().to_string_lossy().to_string();
+
+        let root = add(&path)?;
+        add(&path)?;
+        assert_eq!(list().iter().filter(|r| **r == root).count(), 1);
+        assert!(add(&dir.path().join("missing").to_string_lossy()).is_err());
+
+        assert_eq!(remove(&path), Some(root.clone()));
+        assert_eq!(remove(&path), None);
+        assert!(remove(&primary().to_string_lossy()).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_usable_after_panic() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let panicked = std::panic::catch_unwind(|| {
+            let _roots = EXTRA_ROOTS.write();
+            panic!("handler bug");
+        });
+        assert!(panicked.is_err());
+
+        let root = add(&dir.path().to_string_lossy())?;
+        assert!(list().contains(&root));
+        remove(&dir.path().to_string_lossy());
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_in() -> Result<()> {
+        let workspace = tempfile::tempdir()?;
+        let outside = tempfile::tempdir()?;
+        std::fs::create_dir_all(workspace.path().join("img"))?;
+        std::fs::write(workspace.path().join("img/logo.png"), "png")?;
+        std::fs::write(outside.path().join("secret.txt"), "secret")?;
+        let roots = [workspace.path().to_path_buf()];
+
+        let logo = resolve_in(&roots, "img/logo.png", false)?;
+        assert_eq!(logo, workspace.path().join("img/logo.png").canonicalize()?);
+        assert!(resolve_in(&roots, "img/missing.png", false).is_err());
+        assert_eq!(resolve_in(&roots, "img/missing.png", true)?, logo.with_file_name("missing.png"));
+        assert!(resolve_in(&roots, "assets/missing.png", true).is_err());
+
+        let escape = format!("img/../../{}/secret.txt", outside.path().file_name().unwrap().to_string_lossy());
+        assert!(resolve_in(&roots, &escape, false).is_err());
+        assert!(resolve_in(&roots, &outside.path().join("new.txt").to_string_lossy(), true).is_err());
+        assert!(resolve_in(&roots, &outside.path().join("secret.txt").to_string_lossy(), false).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::VecDeque;
+
+// Recent output of a terminal, recorded whether a client is attached or
+// not, so a panel opened later replays what already happened with
+// `terminal:scrollback`. Bounded by `[terminal] scrollback_bytes`, the
+// oldest output is dropped first. The kept output starts at a char
+// boundary, not necessarily at the start of an escape sequence.
+
+pub const DEFAULT_SCROLLBACK_BYTES: usize = 1024 * 1024;
+
+#[derive(Debug)]
+pub struct Scrollback {
+    chunks: VecDeque<String>,
+    bytes: usize,
+    max_bytes: usize,
+    /// Bytes of output so far, dropped ones included
+    total: u64,
+}
+
+/// The last `max` bytes of `text` or fewer, from a char boundary
+fn tail_of(text: &str, max: usize) -> &str {
+    let mut start = text.len().saturating_sub(max);
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    &text[start..]
+}
+
+impl Scrollback {
+    pub fn new(max_bytes: usize) -> Self {
+        Self { chunks: VecDeque::new(), bytes: 0, max_bytes, total: 0 }
+    }
+
+    pub fn push(&mut self, output: &str) {
+        self.total += output.len() as u64;
+        let output = tail_of(output, self.max_bytes);
+        self.bytes += output.len();
+        self.chunks.push_back(output.to_string());
+        while self.bytes > self.max_bytes && let Some(oldest) = self.chunks.pop_front() {
+            self.bytes -= oldest.len();
+        }
+    }
+
+    /// The kept output, its last `max` bytes when given
+    pub fn tail(&self, max: Option<usize>) -> String {
+        let max = max.unwrap_or(usize::MAX).min(self.bytes);
+        let mut kept = 0;
+        let mut chunks = Vec::new();
+        for chunk in self.chunks.iter().rev() {
+            let tail = tail_of(chunk, max - kept);
+            kept += tail.len();
+            chunks.push(tail);
+            if tail.len() < chunk.len() {
+                break;
+            }
+        }
+        chunks.into_iter().rev().collect()
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+}
+
+#[cfg(test)]
+mod scrollback_tests {
+    use super::*;
+
+    #[test]
+    fn test_ring() {
+        let mut scrollback = Scrollback::new(10);
+        scrollback.push("$ ls\r\n");
+        scrollback.push("a.rs\r\n");
+        // The first chunk is dropped for room
+        assert_eq!(scrollback.tail(None), "a.rs\r\n");
+        assert_eq!(scrollback.total(), 12);
+
+        scrollback.push("b");
+        assert_eq!(scrollback.tail(None), "a.rs\r\nb");
+        assert_eq!(scrollback.tail(Some(3)), "\r\nb");
+
+        // A chunk over the size keeps its end, from a char boundary
+        scrollback.push("0123456789é");
+        assert_eq!(scrollback.tail(None), "23456789é");
+        assert_eq!(scrollback.tail(Some(1)), "");
+    }
+}
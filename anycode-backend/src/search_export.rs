@@ -0,0 +1,189 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+
+use crate::search::FileSearchResult;
+
+// Full result sets of a search written to a file, without the limits of
+// what the UI shows, for post-processing with other tools. Exports go to
+// a path in the workspace or to .anycode/exports, served for download at
+// /api/v1/exports/<name> with range requests so large downloads resume.
+// Lines and columns are 1-based in every format, like grep.
+
+const EXPORTS_DIR: &str = "exports";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// An array of `{path, line, column, preview}` objects
+    #[default]
+    Json,
+    Csv,
+    /// `path:line:column:preview` lines
+    Grep,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Grep => "txt",
+        }
+    }
+}
+
+/// Writes results as they arrive, one file at a time
+pub struct ExportWriter<W: Write> {
+    out: W,
+    format: ExportFormat,
+    matches: usize,
+}
+
+impl<W: Write> ExportWriter<W> {
+    pub fn new(mut out: W, format: ExportFormat) -> Result<Self> {
+        match format {
+            ExportFormat::Json => out.write_all(b"[")?,
+            ExportFormat::Csv => out.write_all(b"path,line,column,preview\n")?,
+            ExportFormat::Grep => {}
+        }
+        Ok(Self { out, format, matches: 0 })
+    }
+
+    pub fn write(&mut self, result: &FileSearchResult) -> Result<()> {
+        let path = &result.file_path;
+        for m in &result.matches {
+            let (line, column) = (m.line + 1, m.column + 1);
+            match self.format {
+                ExportFormat::Json => {
+                    let separator = if self.matches == 0 { "\n" } else { ",\n" };
+                    let item = serde_json::json!({
+                        "path": path, "line": line, "column": column, "preview": m.preview
+                    });
+                    write!(self.out, "{}{}", separator, item)?;
+                }
+                ExportFormat::Csv => writeln!(
+                    self.out, "{},{},{},{}", csv_field(path), line, column, csv_field(&m.preview)
+                )?,
+                ExportFormat::Grep => writeln!(self.out, "{}:{}:{}:{}", path, line, column, m.preview)?,
+            }
+            self.matches += 1;
+        }
+        Ok(())
+    }
+
+    pub fn matches(&self) -> usize {
+        self.matches
+    }
+
+    pub fn finish(mut self) -> Result<W> {
+        if self.format == ExportFormat::Json {
+            self.out.write_all(b"\n]\n")?;
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub fn exports_dir() -> PathBuf {
+    crate::store::workspace_dir().join(EXPORTS_DIR)
+}
+
+/// Where an export is written: the requested path inside a workspace
+/// root, or a new file in the exports dir. `..` is refused, the file does
+/// not exist yet to be resolved.
+pub fn destination(path: Option<&str>, format: ExportFormat) -> Result<PathBuf> {
+    let Some(path) = path else {
+        let name = format!("search-{}.{}", chrono::Local::now().format("%Y%m%d-%H%M%S%3f"), format.extension());
+        return Ok(exports_dir().join(name));
+    };
+
+    let path = Path::new(path);
+    if path.components().any(|c| c == Component::ParentDir) {
+        return Err(anyhow!("{} leads out of the workspace", path.display()));
+    }
+    let dest = crate::paths::absolute(path);
+    if !crate::roots::list().iter().any(|root| dest.starts_with(root)) {
+        return Err(anyhow!("{} is outside the workspace", dest.display()));
+    }
+    Ok(dest)
+}
+
+/// Download URL of an export in the exports dir
+pub fn download_url(dest: &Path) -> Option<String> {
+    let name = dest.file_name()?.to_string_lossy();
+    (dest.parent()? == exports_dir()).then(|| format!("/api/v1/exports/{}", name))
+}
+
+/// File of a download request, None for names that are not plain file
+/// names in the exports dir
+pub fn export_file(name: &str) -> Option<PathBuf> {
+    let plain = !name.is_empty() && Path::new(name).file_name().is_some_and(|n| n == name);
+    plain.then(|| exports_dir().join(name)).filter(|p| p.is_file())
+}
+
+#[cfg(test)]
+mod search_export_tests {
+    use super::*;
+    use crate::search::SearchResult;
+
+    fn results() -> Vec<FileSearchResult> {
+        let m = |line, column, preview: &str| SearchResult { line, column, preview: preview.to_string(), ..Default::default() };
+        vec![
+            FileSearchResult { file_path: "src/a.rs".to_string(), matches: vec![m(0, 4, "let foo = 1;"), m(9, 0, "foo(\"x, y\")")] },
+            FileSearchResult { file_path: "src/é.rs".to_string(), matches: vec![m(2, 1, " foo")] },
+        ]
+    }
+
+    fn export(format: ExportFormat) -> Result<String> {
+        let mut writer = ExportWriter::new(Vec::new(), format)?;
+        for result in results() {
+            writer.write(&result)?;
+        }
+        assert_eq!(writer.matches(), 3);
+        Ok(String::from_utf8(writer.finish()?)?)
+    }
+
+    #[test]
+    fn test_export_formats() -> Result<()> {
+        let json: serde_json::Value = serde_json::from_str(&export(ExportFormat::Json)?)?;
+        assert_eq!(json.as_array().map(|a| a.len()), Some(3));
+        assert_eq!(json[1]["preview"], "foo(\"x, y\")");
+        assert_eq!((json[2]["line"].as_u64(), json[2]["column"].as_u64()), (Some(3), Some(2)));
+
+        assert_eq!(export(ExportFormat::Csv)?, concat!(
+            "path,line,column,preview\n",
+            "src/a.rs,1,5,let foo = 1;\n",
+            "src/a.rs,10,1,\"foo(\"\"x, y\"\")\"\n",
+            "src/é.rs,3,2, foo\n",
+        ));
+        assert_eq!(export(ExportFormat::Grep)?, "src/a.rs:1:5:let foo = 1;\nsrc/a.rs:10:1:foo(\"x, y\")\nsrc/é.rs:3:2: foo\n");
+
+        let empty = ExportWriter::new(Vec::new(), ExportFormat::Json)?.finish()?;
+        assert!(serde_json::from_slice::<Vec<serde_json::Value>>(&empty)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_paths() -> Result<()> {
+        let artifact = destination(None, ExportFormat::Csv)?;
+        assert!(download_url(&artifact).is_some_and(|url| url.ends_with(".csv")));
+        assert!(download_url(&destination(Some("out/results.txt"), ExportFormat::Grep)?).is_none());
+        assert!(destination(Some("/elsewhere/results.txt"), ExportFormat::Grep).is_err());
+        assert!(destination(Some("../../results.txt"), ExportFormat::Grep).is_err());
+        assert!(destination(Some("out/../../results.txt"), ExportFormat::Grep).is_err());
+
+        assert!(export_file("../config.toml").is_none());
+        assert!(export_file("").is_none());
+        Ok(())
+    }
+}
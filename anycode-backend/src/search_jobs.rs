@@ -0,0 +1,262 @@
+use anyhow::{anyhow, Result};
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::search::{dir_search_filtered, FileSearchResult, PathFilter};
+
+// Workspace searches shared between subscribers. Several clients running
+// the same query (tabs of one user) join the walk already in flight and
+// get the results found so far replayed. Finished searches are cached
+// briefly so repeating a query is instant, any change the watcher sees
+// drops the cache.
+
+/// How long finished results are served from the cache
+const CACHE_TTL: Duration = Duration::from_secs(5);
+/// Finished queries kept in the cache
+const CACHE_SIZE: usize = 4;
+/// Larger results are not cached
+const MAX_CACHED_RESULTS: usize = 10_000;
+
+const CHANNEL_SIZE: usize = 1000;
+
+type Done = Option<Result<(), String>>;
+
+struct Subscriber {
+    id: u64,
+    tx: mpsc::Sender<FileSearchResult>,
+}
+
+struct Job {
+    id: u64,
+    results: Vec<FileSearchResult>,
+    subscribers: Vec<Subscriber>,
+    cancel: CancellationToken,
+    done: watch::Sender<Done>,
+}
+
+/// Searched directory, pattern and include/exclude globs
+type Key = (PathBuf, String, PathFilter);
+
+struct Cached {
+    key: Key,
+    results: Arc<Vec<FileSearchResult>>,
+    at: Instant,
+}
+
+#[derive(Default)]
+struct Jobs {
+    running: HashMap<Key, Job>,
+    cache: VecDeque<Cached>,
+}
+
+static JOBS: OnceLock<Mutex<Jobs>> = OnceLock::new();
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+fn jobs() -> &'static Mutex<Jobs> {
+    JOBS.get_or_init(|| Mutex::new(Jobs::default()))
+}
+
+fn next_id() -> u64 {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Drop the cached results, called when files change
+pub fn invalidate() {
+    jobs().lock().cache.clear();
+}
+
+/// Subscribe to a search of `dir`, starting it unless the same pattern
+/// and filter are already searched or cached there. The receiver closes when the
+/// search ends or `cancel` fires, the handle resolves with the search
+/// error if any. The walk stops once all its subscribers cancelled.
+pub fn subscribe(
+    dir: &Path,
+    pattern: String,
+    filter: PathFilter,
+    cancel: CancellationToken,
+) -> (mpsc::Receiver<FileSearchResult>, JoinHandle<Result<()>>) {
+    let key: Key = (dir.to_path_buf(), pattern, filter);
+    let (tx, rx) = mpsc::channel::<FileSearchResult>(CHANNEL_SIZE);
+    let mut jobs = jobs().lock();
+
+    jobs.cache.retain(|c| c.at.elapsed() < CACHE_TTL);
+    if let Some(cached) = jobs.cache.iter().find(|c| c.key == key) {
+        let results = cached.results.clone();
+        let handle = tokio::spawn(async move {
+            for result in results.iter() {
+                tokio::select! {
+                    sent = tx.send(result.clone()) => if sent.is_err() { break },
+                    _ = cancel.cancelled() => break,
+                }
+            }
+            Ok(())
+        });
+        return (rx, handle);
+    }
+
+    if !jobs.running.contains_key(&key) {
+        let job = start_job(key.clone());
+        jobs.running.insert(key.clone(), job);
+    }
+    let job = jobs.running.get_mut(&key).unwrap();
+
+    let id = next_id();
+    let job_id = job.id;
+    let replay = job.results.clone();
+    job.subscribers.push(Subscriber { id, tx: tx.clone() });
+    let mut done = job.done.subscribe();
+    drop(jobs);
+
+    let handle = tokio::spawn(async move {
+        for result in replay {
+            tokio::select! {
+                sent = tx.send(result) => if sent.is_err() { break },
+                _ = cancel.cancelled() => break,
+            }
+        }
+
+        tokio::select! {
+            finished = done.wait_for(|d| d.is_some()) => match finished.as_deref() {
+                Ok(Some(Err(e))) => Err(anyhow!("{}", e)),
+                _ => Ok(()),
+            },
+            _ = cancel.cancelled() => {
+                unsubscribe(&key, job_id, id);
+                Ok(())
+            }
+        }
+    });
+
+    (rx, handle)
+}
+
+fn start_job(key: Key) -> Job {
+    let id = next_id();
+    let cancel = CancellationToken::new();
+    let (done, _) = watch::channel(None);
+
+    let search_cancel = cancel.clone();
+    crate::pool::spawn(async move {
+        let _span = crate::profile::span("search", &key.1);
+        let (result_tx, mut result_rx) = mpsc::channel::<FileSearchResult>(CHANNEL_SIZE);
+
+        let search = dir_search_filtered(&key.0, &key.1, &key.2, search_cancel.clone(), result_tx);
+
+        let forward = async {
+            while let Some(result) = result_rx.recv().await {
+                let subscribers: Vec<mpsc::Sender<FileSearchResult>> = {
+                    let mut jobs = jobs().lock();
+                    let Some(job) = jobs.running.get_mut(&key).filter(|j| j.id == id) else { break };
+                    job.results.push(result.clone());
+                    job.subscribers.iter().map(|s| s.tx.clone()).collect()
+                };
+                for tx in subscribers {
+                    let _ = tx.send(result.clone()).await;
+                }
+            }
+        };
+
+        let (result, _) = tokio::join!(search, forward);
+        finish(&key, id, result, search_cancel.is_cancelled());
+    });
+
+    Job { id, results: Vec::new(), subscribers: Vec::new(), cancel, done }
+}
+
+fn finish(key: &Key, id: u64, result: Result<()>, cancelled: bool) {
+    let mut jobs = jobs().lock();
+    if jobs.running.get(key).is_none_or(|j| j.id != id) {
+        return;
+    }
+    let job = jobs.running.remove(key).unwrap();
+
+    if result.is_ok() && !cancelled && job.results.len() <= MAX_CACHED_RESULTS {
+        jobs.cache.push_front(Cached {
+            key: key.clone(),
+            results: Arc::new(job.results),
+            at: Instant::now(),
+        });
+        jobs.cache.truncate(CACHE_SIZE);
+    }
+
+    job.done.send_replace(Some(result.map_err(|e| e.to_string())));
+}
+
+/// Leave a job, the last subscriber leaving stops the walk
+fn unsubscribe(key: &Key, job_id: u64, id: u64) {
+    let mut jobs = jobs().lock();
+    let Some(job) = jobs.running.get_mut(key).filter(|j| j.id == job_id) else { return };
+
+    job.subscribers.retain(|s| s.id != id);
+    if job.subscribers.is_empty() {
+        job.cancel.cancel();
+        jobs.running.remove(key);
+    }
+}
+
+#[cfg(test)]
+mod search_jobs_tests {
+    use super::*;
+
+    async fn collect(mut rx: mpsc::Receiver<FileSearchResult>) -> Vec<String> {
+        let mut files = Vec::new();
+        while let Some(result) = rx.recv().await {
+            files.push(result.file_path);
+        }
+        files.sort();
+        files
+    }
+
+    fn running(dir: &Path) -> usize {
+        jobs().lock().running.keys().filter(|(d, _, _)| d == dir).count()
+    }
+
+    #[tokio::test]
+    async fn test_subscribers_share_one_search() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        for i in 0..200 {
+            std::fs::write(dir.path().join(format!("{}.txt", i)), "shared_term\n")?;
+        }
+        let pattern = "shared_term".to_string();
+
+        let (rx1, handle1) = subscribe(dir.path(), pattern.clone(), PathFilter::default(), CancellationToken::new());
+        let (rx2, handle2) = subscribe(dir.path(), pattern.clone(), PathFilter::default(), CancellationToken::new());
+        assert_eq!(running(dir.path()), 1);
+
+        let (files1, files2) = tokio::join!(collect(rx1), collect(rx2));
+        handle1.await??;
+        handle2.await??;
+        assert_eq!(files1.len(), 200);
+        assert_eq!(files1, files2);
+
+        // Served from the cache, even after the files changed
+        std::fs::write(dir.path().join("new.txt"), "shared_term\n")?;
+        let (rx3, _) = subscribe(dir.path(), pattern.clone(), PathFilter::default(), CancellationToken::new());
+        assert_eq!(running(dir.path()), 0);
+        assert_eq!(collect(rx3).await.len(), 200);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_last_cancel_stops_the_search() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("a.txt"), "cancelled_term\n")?;
+
+        let cancel = CancellationToken::new();
+        let term = "cancelled_term".to_string();
+        let (rx, handle) = subscribe(dir.path(), term, PathFilter::default(), cancel.clone());
+        cancel.cancel();
+        handle.await??;
+        collect(rx).await;
+
+        assert_eq!(running(dir.path()), 0);
+        Ok(())
+    }
+}
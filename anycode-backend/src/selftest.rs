@@ -0,0 +1,235 @@
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+
+// Checks of the machine the server runs on, for the setup checklist of
+// the frontend (`server:selftest`) and `anycode --doctor`. Every check
+// reports what it found and, when something is off, how to fix it.
+
+/// Directories counted at most for the watcher check
+const MAX_COUNTED_DIRS: usize = 1_000_000;
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Ok,
+    /// Works, but a feature is missing or may stop working
+    Warning,
+    Failed,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Check {
+    /// Group of the checklist: lsp, terminal, watcher, storage or network
+    pub category: &'static str,
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn new(category: &'static str, name: &str, status: CheckStatus, detail: String) -> Self {
+        Self { category, name: name.to_string(), status, detail, hint: None }
+    }
+
+    fn hint(mut self, hint: impl Into<String>) -> Self {
+        if self.status != CheckStatus::Ok {
+            self.hint = Some(hint.into());
+        }
+        self
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Report {
+    pub checks: Vec<Check>,
+    /// No check failed
+    pub ok: bool,
+}
+
+/// Full path of `program` as PATH finds it, or the program itself when it
+/// is a path to an existing file
+pub fn find_in_path(program: &str) -> Option<PathBuf> {
+    let path = Path::new(program);
+    if path.components().count() > 1 {
+        return path.is_file().then(|| path.to_path_buf());
+    }
+    let extensions: &[&str] = if cfg!(target_os = "windows") { &["", ".exe", ".cmd", ".bat"] } else { &[""] };
+    let dirs = std::env::var_os("PATH")?;
+    std::env::split_paths(&dirs)
+        .flat_map(|dir| extensions.iter().map(move |ext| dir.join(format!("{}{}", program, ext))))
+        .find(|candidate| candidate.is_file())
+}
+
+fn lsp_checks(config: &Config) -> Vec<Check> {
+    config.language.iter()
+        .filter_map(|lang| Some((lang, lang.lsp.as_ref()?.first()?)))
+        .map(|(lang, program)| match find_in_path(program) {
+            Some(found) => Check::new("lsp", &lang.name, CheckStatus::Ok, found.display().to_string()),
+            None => Check::new("lsp", &lang.name, CheckStatus::Warning, format!("{} is not in PATH", program))
+                .hint(format!("Install {} or change the lsp command of {} in config.toml", program, lang.name)),
+        })
+        .collect()
+}
+
+fn shell_checks(config: &Config) -> Vec<Check> {
+    let shell = crate::terminal::Terminal::default_shell();
+    let mut checks = vec![match find_in_path(&shell) {
+        Some(found) => Check::new("terminal", "default shell", CheckStatus::Ok, found.display().to_string()),
+        None => Check::new("terminal", "default shell", CheckStatus::Failed, format!("{} is not found", shell))
+            .hint("Set SHELL to an installed shell"),
+    }];
+
+    let profiles = config.terminal.as_ref().map(|t| t.profiles.as_slice()).unwrap_or_default();
+    checks.extend(profiles.iter().map(|profile| match find_in_path(&profile.command) {
+        Some(found) => Check::new("terminal", &profile.name, CheckStatus::Ok, found.display().to_string()),
+        None => Check::new("terminal", &profile.name, CheckStatus::Warning, format!("{} is not in PATH", profile.command))
+            .hint(format!("Install {} or remove the profile from config.toml", profile.command)),
+    }));
+    checks
+}
+
+fn pty_check() -> Check {
+    let size = portable_pty::PtySize { rows: 24, cols: 80, pixel_width: 0, pixel_height: 0 };
+    match portable_pty::native_pty_system().openpty(size) {
+        Ok(_) => Check::new("terminal", "pty", CheckStatus::Ok, "A pseudo terminal can be opened".to_string()),
+        Err(e) => Check::new("terminal", "pty", CheckStatus::Failed, format!("Failed to open a pseudo terminal: {}", e))
+            .hint("Mount /dev/pts in containers, or run the server outside of the sandbox"),
+    }
+}
+
+/// Directories under `root`, each one takes a watch, counted up to `limit`
+fn count_dirs(root: &Path, limit: usize) -> usize {
+    let mut count = 0;
+    let mut stack = vec![root.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        count += 1;
+        if count >= limit {
+            break;
+        }
+        let Ok(entries) = std::fs::read_dir(&dir) else { continue };
+        stack.extend(entries.flatten()
+            .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+            .map(|e| e.path()));
+    }
+    count
+}
+
+fn watcher_check(root: &Path) -> Check {
+    let Ok(limit) = std::fs::read_to_string("/proc/sys/fs/inotify/max_user_watches") else {
+        return Check::new("watcher", "watch limit", CheckStatus::Ok, "No per-user watch limit".to_string());
+    };
+    let Ok(limit) = limit.trim().parse::<usize>() else {
+        return Check::new("watcher", "watch limit", CheckStatus::Warning, format!("Unreadable limit {}", limit.trim()));
+    };
+
+    let dirs = count_dirs(root, MAX_COUNTED_DIRS.min(limit + 1));
+    let detail = format!("{} directories to watch, max_user_watches is {}", dirs, limit);
+    let status = if dirs > limit {
+        CheckStatus::Failed
+    } else if dirs * 2 > limit {
+        CheckStatus::Warning
+    } else {
+        CheckStatus::Ok
+    };
+    Check::new("watcher", "watch limit", status, detail)
+        .hint("Raise fs.inotify.max_user_watches with sysctl, or open a smaller folder")
+}
+
+fn write_check(name: &str, dir: &Path) -> Check {
+    let written = std::fs::create_dir_all(dir).and_then(|_| tempfile::NamedTempFile::new_in(dir));
+    match written {
+        Ok(_) => Check::new("storage", name, CheckStatus::Ok, format!("{} is writable", dir.display())),
+        Err(e) => Check::new("storage", name, CheckStatus::Failed, format!("Can't write to {}: {}", dir.display(), e))
+            .hint(format!("Fix the permissions of {}", dir.display())),
+    }
+}
+
+/// The port of a running server accepts connections, else a port to
+/// listen at is free
+async fn port_check(config: &Config, listening: Option<u16>) -> Check {
+    if let Some(port) = listening {
+        return match tokio::net::TcpStream::connect(("127.0.0.1", port)).await {
+            Ok(_) => Check::new("network", "port", CheckStatus::Ok, format!("Port {} accepts connections", port)),
+            Err(e) => Check::new("network", "port", CheckStatus::Warning, format!("Port {} is not reachable: {}", port, e))
+                .hint("Allow local connections to the port in the firewall"),
+        };
+    }
+    match crate::net::bind(config.port_fallback).await {
+        Ok(listener) => {
+            let port = listener.local_addr().map(|a| a.port()).unwrap_or_default();
+            Check::new("network", "port", CheckStatus::Ok, format!("Port {} is free", port))
+        }
+        Err(e) => Check::new("network", "port", CheckStatus::Failed, e.to_string())
+            .hint("Set ANYCODE_PORT to a free port or raise port_fallback"),
+    }
+}
+
+/// Run every check. `listening` is the port of the running server, the
+/// doctor runs before one is started.
+pub async fn run(config: &Config, root: &Path, listening: Option<u16>) -> Report {
+    let mut checks = lsp_checks(config);
+    checks.extend(shell_checks(config));
+    checks.push(pty_check());
+    checks.push(watcher_check(root));
+    checks.push(write_check("state", &crate::store::home_dir()));
+    checks.push(write_check("workspace", &root.join(crate::store::WORKSPACE_DIR)));
+    checks.push(port_check(config, listening).await);
+
+    let ok = checks.iter().all(|c| c.status != CheckStatus::Failed);
+    Report { checks, ok }
+}
+
+/// The report as the lines `anycode --doctor` prints
+pub fn format(report: &Report) -> String {
+    let mut out = String::new();
+    for check in &report.checks {
+        let mark = match check.status {
+            CheckStatus::Ok => "ok",
+            CheckStatus::Warning => "warn",
+            CheckStatus::Failed => "FAIL",
+        };
+        out.push_str(&format!("[{:>4}] {} {}: {}\n", mark, check.category, check.name, check.detail));
+        if let Some(hint) = &check.hint {
+            out.push_str(&format!("       {}\n", hint));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod selftest_tests {
+    use super::*;
+
+    #[test]
+    fn test_find_in_path() {
+        assert!(find_in_path("cargo").is_some());
+        assert!(find_in_path("anycode-no-such-program").is_none());
+        assert!(find_in_path("/no/such/program").is_none());
+    }
+
+    #[test]
+    fn test_count_dirs() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir_all(dir.path().join("a/b"))?;
+        std::fs::create_dir_all(dir.path().join("c"))?;
+        std::fs::write(dir.path().join("a/file.txt"), "")?;
+
+        assert_eq!(count_dirs(dir.path(), 100), 4);
+        assert_eq!(count_dirs(dir.path(), 2), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_hint_only_when_off() {
+        let ok = Check::new("lsp", "rust", CheckStatus::Ok, String::new()).hint("install");
+        let missing = Check::new("lsp", "rust", CheckStatus::Warning, String::new()).hint("install");
+        assert!(ok.hint.is_none());
+        assert_eq!(missing.hint.as_deref(), Some("install"));
+
+        let report = Report { checks: vec![ok, missing], ok: true };
+        assert_eq!(format(&report), "[  ok] lsp rust: \n[warn] lsp rust: \n       install\n");
+    }
+}
@@ -0,0 +1,231 @@
+use lsp_types::{
+    SemanticToken, SemanticTokenModifier, SemanticTokenType, SemanticTokens, SemanticTokensDelta,
+    SemanticTokensEdit, SemanticTokensFullDeltaResult, SemanticTokensResult,
+};
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::HashMap;
+
+// Semantic tokens of the open documents as the servers sent them last,
+// with their result id. The next request asks the server for a delta
+// against it, the edits are applied here and the clients get the lines
+// whose tokens changed instead of the whole document. Tokens of a line
+// are sent as `[start, length, type, modifiers]`, start and length in
+// UTF-16 units, type and modifiers as indices into the legend.
+
+pub const TOKEN_TYPES: &[SemanticTokenType] = &[
+    SemanticTokenType::NAMESPACE, SemanticTokenType::TYPE, SemanticTokenType::CLASS,
+    SemanticTokenType::ENUM, SemanticTokenType::INTERFACE, SemanticTokenType::STRUCT,
+    SemanticTokenType::TYPE_PARAMETER, SemanticTokenType::PARAMETER, SemanticTokenType::VARIABLE,
+    SemanticTokenType::PROPERTY, SemanticTokenType::ENUM_MEMBER, SemanticTokenType::EVENT,
+    SemanticTokenType::FUNCTION, SemanticTokenType::METHOD, SemanticTokenType::MACRO,
+    SemanticTokenType::KEYWORD, SemanticTokenType::MODIFIER, SemanticTokenType::COMMENT,
+    SemanticTokenType::STRING, SemanticTokenType::NUMBER, SemanticTokenType::REGEXP,
+    SemanticTokenType::OPERATOR, SemanticTokenType::DECORATOR,
+];
+
+pub const TOKEN_MODIFIERS: &[SemanticTokenModifier] = &[
+    SemanticTokenModifier::DECLARATION, SemanticTokenModifier::DEFINITION,
+    SemanticTokenModifier::READONLY, SemanticTokenModifier::STATIC,
+    SemanticTokenModifier::DEPRECATED, SemanticTokenModifier::ABSTRACT,
+    SemanticTokenModifier::ASYNC, SemanticTokenModifier::MODIFICATION,
+    SemanticTokenModifier::DOCUMENTATION, SemanticTokenModifier::DEFAULT_LIBRARY,
+];
+
+/// A token of a line: start, length, type, modifier bits
+pub type LineToken = [u32; 4];
+
+/// An answer of the server
+pub enum Answer {
+    Full(SemanticTokens),
+    Delta(SemanticTokensDelta),
+}
+
+impl Answer {
+    pub fn from_full(result: Option<SemanticTokensResult>) -> Self {
+        match result {
+            Some(SemanticTokensResult::Tokens(tokens)) => Answer::Full(tokens),
+            Some(SemanticTokensResult::Partial(partial)) => Answer::Full(SemanticTokens { result_id: None, data: partial.data }),
+            None => Answer::Full(SemanticTokens::default()),
+        }
+    }
+
+    /// None when the server had nothing to say, ask for all tokens then
+    pub fn from_delta(result: Option<SemanticTokensFullDeltaResult>) -> Option<Self> {
+        match result? {
+            SemanticTokensFullDeltaResult::Tokens(tokens) => Some(Answer::Full(tokens)),
+            SemanticTokensFullDeltaResult::TokensDelta(delta) => Some(Answer::Delta(delta)),
+            SemanticTokensFullDeltaResult::PartialTokensDelta { edits } => {
+                Some(Answer::Delta(SemanticTokensDelta { result_id: None, edits }))
+            }
+        }
+    }
+}
+
+/// Lines `start..start + deleted` of the client's tokens replaced by `lines`,
+/// all the lines of the document when `full`
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Update {
+    pub result_id: Option<String>,
+    pub full: bool,
+    pub start: usize,
+    pub deleted: usize,
+    pub lines: Vec<Vec<LineToken>>,
+}
+
+struct Document {
+    result_id: Option<String>,
+    /// The server's encoding, the deltas index into it
+    data: Vec<u32>,
+    lines: Vec<Vec<LineToken>>,
+}
+
+static DOCUMENTS: Mutex<Option<HashMap<String, Document>>> = Mutex::new(None);
+
+fn flatten(tokens: &[SemanticToken]) -> Vec<u32> {
+    tokens.iter()
+        .flat_map(|t| [t.delta_line, t.delta_start, t.length, t.token_type, t.token_modifiers_bitset])
+        .collect()
+}
+
+/// Apply the edits of a delta, their indices point into the data before
+/// any of them
+fn apply_edits(data: &mut Vec<u32>, mut edits: Vec<SemanticTokensEdit>) {
+    edits.sort_by_key(|e| std::cmp::Reverse(e.start));
+    for edit in edits {
+        let start = (edit.start as usize).min(data.len());
+        let end = (start + edit.delete_count as usize).min(data.len());
+        data.splice(start..end, flatten(edit.data.as_deref().unwrap_or_default()));
+    }
+}
+
+/// Absolute tokens per line, at least `line_count` lines
+fn lines(data: &[u32], line_count: usize) -> Vec<Vec<LineToken>> {
+    let mut lines = vec![Vec::new(); line_count];
+    let (mut line, mut start) = (0usize, 0u32);
+    for token in data.chunks_exact(5) {
+        if token[0] > 0 {
+            line += token[0] as usize;
+            start = 0;
+        }
+        start += token[1];
+        if line >= lines.len() {
+            lines.resize(line + 1, Vec::new());
+        }
+        lines[line].push([start, token[2], token[3], token[4]]);
+    }
+    lines
+}
+
+/// The lines between the common start and end of `old` and `new`
+fn diff(old: &[Vec<LineToken>], new: &[Vec<LineToken>]) -> (usize, usize, Vec<Vec<LineToken>>) {
+    let start = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let end = old[start..].iter().rev()
+        .zip(new[start..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    (start, old.len() - start - end, new[start..new.len() - end].to_vec())
+}
+
+/// Result id to ask a delta against
+pub fn result_id(path: &str) -> Option<String> {
+    DOCUMENTS.lock().as_ref()?.get(path)?.result_id.clone()
+}
+
+/// Store the answer for `path` and what changed for a client holding the
+/// tokens of `client_result_id`. None for a delta without the tokens it
+/// applies to.
+pub fn update(path: &str, answer: Answer, line_count: usize, client_result_id: Option<&str>) -> Option<Update> {
+    let mut documents = DOCUMENTS.lock();
+    let documents = documents.get_or_insert_with(HashMap::new);
+    let previous = documents.remove(path);
+
+    let (result_id, data) = match answer {
+        Answer::Full(tokens) => (tokens.result_id, flatten(&tokens.data)),
+        Answer::Delta(delta) => {
+            let mut data = previous.as_ref()?.data.clone();
+            apply_edits(&mut data, delta.edits);
+            (delta.result_id, data)
+        }
+    };
+    let new_lines = lines(&data, line_count);
+
+    let update = match previous {
+        Some(previous) if client_result_id.is_some() && previous.result_id.as_deref() == client_result_id => {
+            let (start, deleted, lines) = diff(&previous.lines, &new_lines);
+            Update { result_id: result_id.clone(), full: false, start, deleted, lines }
+        }
+        _ => Update { result_id: result_id.clone(), full: true, start: 0, deleted: 0, lines: new_lines.clone() },
+    };
+    documents.insert(path.to_string(), Document { result_id, data, lines: new_lines });
+    Some(update)
+}
+
+/// Drop the tokens of a closed document
+pub fn forget(path: &str) {
+    if let Some(documents) = DOCUMENTS.lock().as_mut() {
+        documents.remove(path);
+    }
+}
+
+#[cfg(test)]
+mod semantic_tokens_tests {
+    use super::*;
+
+    fn token(delta_line: u32, delta_start: u32, length: u32, token_type: u32) -> SemanticToken {
+        SemanticToken { delta_line, delta_start, length, token_type, token_modifiers_bitset: 0 }
+    }
+
+    #[test]
+    fn test_apply_edits_and_lines() {
+        let tokens = [token(0, 0, 2, 15), token(0, 3, 4, 12), token(2, 4, 1, 8)];
+        let mut data = flatten(&tokens);
+        assert_eq!(lines(&data, 4), vec![vec![[0, 2, 15, 0], [3, 4, 12, 0]], vec![], vec![[4, 1, 8, 0]], vec![]]);
+
+        // The function token grows to 6
+        apply_edits(&mut data, vec![
+            SemanticTokensEdit { start: 5, delete_count: 5, data: Some(vec![token(0, 3, 6, 12)]) },
+        ]);
+        assert_eq!(lines(&data, 3)[0], [[0, 2, 15, 0], [3, 6, 12, 0]]);
+
+        apply_edits(&mut data, vec![
+            SemanticTokensEdit { start: 10, delete_count: 5, data: None },
+            SemanticTokensEdit { start: 0, delete_count: 5, data: None },
+        ]);
+        assert_eq!(data, [0, 3, 6, 12, 0]);
+    }
+
+    #[test]
+    fn test_update() {
+        let path = "/semantic_tokens_tests/main.rs";
+        let full = |result_id: &str, tokens: Vec<SemanticToken>| {
+            Answer::Full(SemanticTokens { result_id: Some(result_id.to_string()), data: tokens })
+        };
+
+        let first = update(path, full("1", vec![token(0, 0, 2, 15), token(1, 0, 3, 8)]), 3, None).unwrap();
+        assert!(first.full);
+        assert_eq!(first.lines.len(), 3);
+        assert_eq!(result_id(path).as_deref(), Some("1"));
+
+        // A line inserted at the top
+        let delta = Answer::Delta(SemanticTokensDelta {
+            result_id: Some("2".to_string()),
+            edits: vec![SemanticTokensEdit {
+                start: 0, delete_count: 5, data: Some(vec![token(0, 0, 6, 17), token(1, 0, 2, 15)]),
+            }],
+        });
+        let changed = update(path, delta, 4, Some("1")).unwrap();
+        assert_eq!(changed, Update {
+            result_id: Some("2".to_string()), full: false, start: 0, deleted: 0, lines: vec![vec![[0, 6, 17, 0]]],
+        });
+
+        // A client holding other tokens gets all of them
+        let other = update(path, full("3", vec![token(0, 0, 6, 17)]), 4, Some("1")).unwrap();
+        assert!(other.full);
+
+        forget(path);
+        assert!(result_id(path).is_none());
+        let orphan = Answer::Delta(SemanticTokensDelta { result_id: None, edits: Vec::new() });
+        assert!(update(path, orphan, 4, Some("3")).is_none());
+    }
+}
@@ -0,0 +1,1447 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use socketioxide::extract::SocketRef;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::app_state::{get_or_create_code, AppState, SocketData};
+use crate::code::Code;
+use crate::collab::{Joined, Op};
+use crate::file_probe::NotText;
+use crate::readonly::ReadOnlyReason;
+use crate::revision::Span;
+use crate::search::{text_search, FileSearchResult, PathFilter};
+use crate::timing::EventTimer;
+use crate::utils::abs_file;
+use anycode_search::ignore::is_ignored_entry;
+
+// Operations shared by the socket handlers and the REST api. They return
+// plain results, the callers decide how to ack, respond and broadcast.
+// Handlers parse the request, call one operation and turn its result or
+// its typed error (SaveConflict, AlreadyExists, DirtyBuffer, NotOpen,
+// NotText, ReadOnly) into the ack, so the operations are tested here
+// without a socket.
+
+pub struct LoadedFile {
+    pub abs_path: String,
+    pub lang: String,
+    pub content: String,
+    pub read_only: Option<ReadOnlyReason>,
+    /// Version of the buffer, the base of the client's first change
+    pub version: u64,
+}
+
+/// Load a file into file2code (or take the already opened buffer). Virtual
+/// documents are served read-only by their provider, without a buffer.
+/// Fails with `NotText` for binary files and, unless `force`, files over
+/// the `[open]` size limit or not in UTF-8, which open converted.
+pub async fn load_file(state: &AppState, timer: &mut EventTimer, path: &str, force: bool) -> Result<LoadedFile> {
+    if crate::vfs::is_virtual(path) {
+        let (content, lang) = crate::vfs::read(path)?;
+        return Ok(LoadedFile { abs_path: path.to_string(), lang, content, read_only: None, version: 0 });
+    }
+
+    let abs_path = abs_file(path)
+        .map_err(|e| anyhow!("Failed to resolve file: {:?}", e))?;
+
+    let mut f2c = timer.lock("file2code", &state.file2code).await;
+    let loaded = !f2c.contains_key(&abs_path);
+    if loaded {
+        let max_size = (!force).then_some(max_open_size(&state.config));
+        if let Ok(Some(not_text)) = crate::file_probe::check(std::path::Path::new(&abs_path), max_size) {
+            if !force || not_text.binary {
+                return Err(not_text.into());
+            }
+            f2c.insert(abs_path.clone(), Code::from_file_converted(&abs_path, &state.config)?);
+        }
+    }
+    let code = get_or_create_code(&mut f2c, &abs_path, &state.config)?;
+    if loaded {
+        crate::undo_store::restore(code);
+    }
+
+    Ok(LoadedFile {
+        lang: code.lang.clone(),
+        content: code.text.to_string(),
+        read_only: crate::readonly::refresh(&abs_path),
+        version: code.version,
+        abs_path,
+    })
+}
+
+pub fn max_open_size(config: &crate::config::Config) -> u64 {
+    config.open.as_ref()
+        .and_then(|open| open.max_size)
+        .unwrap_or(crate::file_probe::DEFAULT_MAX_SIZE)
+}
+
+pub async fn lsp_did_open(state: &AppState, timer: &mut EventTimer, file: &LoadedFile) {
+    lsp_did_open_all(state, timer, &[file]).await;
+}
+
+/// Open loaded files in their language servers, in a single pass over the
+/// LSP manager. Virtual documents are skipped.
+pub async fn lsp_did_open_all(state: &AppState, timer: &mut EventTimer, files: &[&LoadedFile]) {
+    let mut lsp_manager = timer.lock("lsp_manager", &state.lsp_manager).await;
+    for file in files.iter().filter(|f| !crate::vfs::is_virtual(&f.abs_path)) {
+        if let Some(lsp) = lsp_manager.get_for(&file.lang, &file.abs_path).await {
+            lsp.did_open(&file.lang, &file.abs_path, &file.content);
+        }
+    }
+}
+
+/// Record files as opened by the client `socket_id`
+pub async fn track_opened(state: &AppState, timer: &mut EventTimer, socket_id: &str, paths: impl IntoIterator<Item = String>) {
+    let mut sockets_data = timer.lock("socket2data", &state.socket2data).await;
+    let data = sockets_data.entry(socket_id.to_string()).or_insert_with(SocketData::default);
+    data.opened_files.extend(paths);
+}
+
+/// One file of `open_batch`
+pub enum BatchFile {
+    Loaded(LoadedFile),
+    NotText(NotText),
+    Failed(String),
+}
+
+impl BatchFile {
+    /// Entry of the file:openBatch ack, `path` as the client asked for it
+    pub fn response(&self, path: &str) -> serde_json::Value {
+        match self {
+            BatchFile::Loaded(file) => serde_json::json!({
+                "content": file.content, "path": path, "read_only": file.read_only,
+                "version": file.version, "success": true
+            }),
+            BatchFile::NotText(not_text) => not_text.response(path),
+            BatchFile::Failed(error) => serde_json::json!({ "error": error, "path": path, "success": false }),
+        }
+    }
+}
+
+/// Load several files, one entry per path in order. Files that are not
+/// open yet are read in parallel without holding file2code, binary ones
+/// and the ones over `inline_limit` are reported but not loaded.
+pub async fn open_batch(state: &AppState, timer: &mut EventTimer, paths: &[String], inline_limit: u64) -> Vec<BatchFile> {
+    let mut results: Vec<Option<BatchFile>> = paths.iter().map(|_| None).collect();
+    let mut to_load = Vec::new();
+    {
+        let f2c = timer.lock("file2code", &state.file2code).await;
+        for (i, path) in paths.iter().enumerate() {
+            let abs_path = match abs_file(path) {
+                Ok(p) => p,
+                Err(e) => {
+                    results[i] = Some(BatchFile::Failed(format!("Failed to resolve file: {:?}", e)));
+                    continue;
+                }
+            };
+            if f2c.contains_key(&abs_path) {
+                to_load.push((i, abs_path, None));
+                continue;
+            }
+            if let Ok(Some(not_text)) = crate::file_probe::check(std::path::Path::new(&abs_path), Some(inline_limit)) {
+                results[i] = Some(BatchFile::NotText(not_text));
+                continue;
+            }
+            to_load.push((i, abs_path, Some(state.config.clone())));
+        }
+    }
+
+    let mut tasks = tokio::task::JoinSet::new();
+    for (i, abs_path, config) in to_load {
+        tasks.spawn_blocking(move || {
+            let code = config.map(|c| Code::from_file(&abs_path, &c));
+            (i, abs_path, code)
+        });
+    }
+    let mut loaded = Vec::new();
+    while let Some(joined) = tasks.join_next().await {
+        if let Ok(result) = joined {
+            loaded.push(result);
+        }
+    }
+
+    let mut f2c = timer.lock("file2code", &state.file2code).await;
+    for (i, abs_path, code) in loaded {
+        let code = match code {
+            Some(Ok(mut c)) => {
+                crate::undo_store::restore(&mut c);
+                f2c.entry(abs_path.clone()).or_insert(c)
+            }
+            Some(Err(e)) => {
+                results[i] = Some(BatchFile::Failed(format!("Failed to load file {}: {:?}", abs_path, e)));
+                continue;
+            }
+            None => match f2c.get_mut(&abs_path) {
+                Some(c) => c,
+                None => continue,
+            },
+        };
+        results[i] = Some(BatchFile::Loaded(LoadedFile {
+            lang: code.lang.clone(),
+            content: code.text.to_string(),
+            read_only: crate::readonly::refresh(&abs_path),
+            version: code.version,
+            abs_path,
+        }));
+    }
+
+    results.into_iter()
+        .map(|r| r.unwrap_or_else(|| BatchFile::Failed("File was closed while loading".to_string())))
+        .collect()
+}
+
+/// Close a document of the client `socket_id`: its language server closes
+/// it and it leaves the client's opened files. Virtual documents only
+/// leave the opened files. Returns the absolute path.
+pub async fn close_file(state: &AppState, timer: &mut EventTimer, socket_id: &str, path: &str) -> Result<String> {
+    let abs_path = match crate::vfs::is_virtual(path) {
+        true => path.to_string(),
+        false => {
+            let abs_path = abs_file(path)
+                .map_err(|e| anyhow!("Failed to resolve file: {:?}", e))?;
+            let mut f2c = timer.lock("file2code", &state.file2code).await;
+            let code = get_or_create_code(&mut f2c, &abs_path, &state.config)?;
+            let mut lsp_manager = timer.lock("lsp_manager", &state.lsp_manager).await;
+            if let Some(lsp) = lsp_manager.get_for(&code.lang, &abs_path).await {
+                lsp.did_close(&abs_path);
+            }
+            abs_path
+        }
+    };
+
+    let mut sockets_data = timer.lock("socket2data", &state.socket2data).await;
+    if let Some(data) = sockets_data.get_mut(socket_id) {
+        data.opened_files.remove(&abs_path);
+    }
+    Ok(abs_path)
+}
+
+/// Whether the buffer of `path` has unsaved changes and the lines changed
+/// since it was loaded or saved, with the absolute path. Files that are
+/// not open have no changes.
+pub async fn dirty_diff(
+    state: &AppState, timer: &mut EventTimer, path: &str,
+) -> (String, bool, Vec<crate::dirty_diff::LineChange>) {
+    let abs_path = crate::paths::absolute(std::path::Path::new(path)).to_string_lossy().to_string();
+    let f2c = timer.lock("file2code", &state.file2code).await;
+    let (changed, ranges) = match f2c.get(&abs_path) {
+        Some(code) => (code.changed, code.dirty_diff()),
+        None => (false, Vec::new()),
+    };
+    (abs_path, changed, ranges)
+}
+
+#[derive(Debug, Serialize)]
+pub struct DirListing {
+    pub files: Vec<String>,
+    pub dirs: Vec<String>,
+    pub name: String,
+    pub fullpath: String,
+    pub relative_path: String,
+    /// Entries of the directory, over all pages
+    pub total: usize,
+    /// More entries follow, fetch them with `cursor`
+    pub truncated: bool,
+    pub cursor: Option<String>,
+}
+
+/// Entries returned by one dir:list page when neither the config nor the
+/// request say otherwise
+pub const DEFAULT_DIR_LIMIT: usize = 5000;
+
+/// Page size of a listing: the request's own limit, else the configured one
+pub fn dir_limit(config: &crate::config::Config, requested: Option<usize>) -> usize {
+    requested
+        .or_else(|| config.dir_list.as_ref().and_then(|d| d.max_entries))
+        .unwrap_or(DEFAULT_DIR_LIMIT)
+        .max(1)
+}
+
+/// Position of an entry in the listing order (directories, then files,
+/// each by name), used as the paging cursor. Names rather than offsets
+/// keep the pages consistent when entries come and go in between.
+fn entry_cursor(is_dir: bool, name: &str) -> String {
+    format!("{}:{}", if is_dir { "d" } else { "f" }, name)
+}
+
+/// List a directory of `root`, relative paths resolved against it, at
+/// most `limit` entries after `cursor`
+pub fn list_dir(root: &std::path::Path, path: &str, limit: usize, cursor: Option<&str>) -> Result<DirListing> {
+    let dir = match path.trim() {
+        "" | "." | "./" => root.to_path_buf(),
+        d => root.join(d),
+    };
+    let dir = dir.to_string_lossy().to_string();
+
+    let fullpath = abs_file(&dir)
+        .map_err(|e| anyhow!("Failed to resolve directory: {:?}", e))?;
+
+    let name = crate::utils::file_name(&dir);
+    let mut relative_path = pathdiff::diff_paths(&dir, root)
+        .map_or_else(|| dir.clone(), |p| p.to_string_lossy().into_owned());
+    if relative_path.is_empty() {
+        relative_path = ".".to_string();
+    }
+
+    let entries = std::fs::read_dir(&dir)
+        .map_err(|e| anyhow!("Failed to open directory: {:?}", e))?;
+
+    let mut files = Vec::new();
+    let mut dirs = Vec::new();
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let is_dir = path.is_dir();
+
+        if is_ignored_entry(&path, is_dir) {
+            continue;
+        }
+
+        if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+            if is_dir {
+                dirs.push(name.to_string());
+            } else {
+                files.push(name.to_string());
+            }
+        }
+    }
+
+    dirs.sort();
+    files.sort();
+    let total = dirs.len() + files.len();
+
+    let mut page: Vec<(bool, String)> = dirs.into_iter().map(|d| (true, d))
+        .chain(files.into_iter().map(|f| (false, f)))
+        .filter(|(is_dir, name)| cursor.is_none_or(|c| entry_cursor(*is_dir, name).as_str() > c))
+        .collect();
+
+    let truncated = page.len() > limit;
+    page.truncate(limit);
+    let cursor = match truncated {
+        true => page.last().map(|(is_dir, name)| entry_cursor(*is_dir, name)),
+        false => None,
+    };
+
+    let (dirs, files): (Vec<_>, Vec<_>) = page.into_iter().partition(|(is_dir, _)| *is_dir);
+    let dirs = dirs.into_iter().map(|(_, name)| name).collect();
+    let files = files.into_iter().map(|(_, name)| name).collect();
+
+    Ok(DirListing { files, dirs, name, fullpath, relative_path, total, truncated, cursor })
+}
+
+/// The file changed on disk since the buffer was loaded or saved, saving
+/// would overwrite the external change
+#[derive(Debug)]
+pub struct SaveConflict {
+    pub path: String,
+}
+
+impl std::fmt::Display for SaveConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} was changed on disk by another program", self.path)
+    }
+}
+
+impl std::error::Error for SaveConflict {}
+
+/// Save the file2code buffer to disk, returns the absolute path.
+/// Fails with `SaveConflict` when the file changed on disk, unless `force`.
+pub async fn save_file(state: &AppState, timer: &mut EventTimer, path: &str, force: bool) -> Result<String> {
+    let abs_path = abs_file(path)
+        .map_err(|e| anyhow!("Failed to resolve file: {:?}", e))?;
+
+    let mut f2c = timer.lock("file2code", &state.file2code).await;
+    let code = get_or_create_code(&mut f2c, &abs_path, &state.config)?;
+
+    if !force && code.changed && code.disk_changed() {
+        return Err(SaveConflict { path: abs_path }.into());
+    }
+
+    if code.changed {
+        crate::file_history::record(std::path::Path::new(&abs_path));
+    }
+    code.save_file()
+        .map_err(|e| anyhow!("Failed to save file: {:?}", e))?;
+    if let Some(history) = crate::undo_store::snapshot(code) {
+        crate::undo_store::store(&abs_path, &history);
+    }
+
+    let mut lsp_manager = timer.lock("lsp_manager", &state.lsp_manager).await;
+    if let Some(lsp) = lsp_manager.get_for(&code.lang, &abs_path).await {
+        lsp.did_save(&abs_path, Some(&code.text.to_string()));
+    }
+
+    Ok(abs_path)
+}
+
+/// Normalizations the `[save]` hooks would make to a buffer, with the
+/// absolute path. Empty without hooks.
+pub async fn save_normalizations(
+    state: &AppState,
+    timer: &mut EventTimer,
+    path: &str,
+) -> Result<(String, Vec<crate::text_audit::Normalization>)> {
+    let abs_path = abs_file(path)
+        .map_err(|e| anyhow!("Failed to resolve file: {:?}", e))?;
+    let Some(save) = state.config.save.as_ref() else { return Ok((abs_path, Vec::new())) };
+
+    let mut f2c = timer.lock("file2code", &state.file2code).await;
+    let code = get_or_create_code(&mut f2c, &abs_path, &state.config)?;
+    let normalizations = crate::text_audit::normalizations(&code.text.to_string(), save);
+    Ok((abs_path, normalizations))
+}
+
+/// Operation on a buffer that is not open
+#[derive(Debug)]
+pub struct NotOpen {
+    pub path: String,
+}
+
+impl std::fmt::Display for NotOpen {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} is not open", self.path)
+    }
+}
+
+impl std::error::Error for NotOpen {}
+
+/// Write an open buffer back to its file after the file was deleted by
+/// another program, returns the absolute path. Fails with `NotOpen`
+/// without a buffer.
+pub async fn restore_from_buffer(state: &AppState, timer: &mut EventTimer, path: &str) -> Result<String> {
+    let abs_path = crate::paths::absolute(std::path::Path::new(path)).to_string_lossy().to_string();
+
+    let mut f2c = timer.lock("file2code", &state.file2code).await;
+    let Some(code) = f2c.get_mut(&abs_path) else {
+        return Err(NotOpen { path: abs_path }.into());
+    };
+    if std::path::Path::new(&abs_path).exists() {
+        return Err(anyhow!("{} exists on disk", abs_path));
+    }
+
+    if let Some(parent) = std::path::Path::new(&abs_path).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    code.changed = true;
+    crate::file_history::record(std::path::Path::new(&abs_path));
+    code.save_file()
+        .map_err(|e| anyhow!("Failed to restore file: {:?}", e))?;
+
+    let mut lsp_manager = timer.lock("lsp_manager", &state.lsp_manager).await;
+    if let Some(lsp) = lsp_manager.get_for(&code.lang, &abs_path).await {
+        lsp.did_save(&abs_path, Some(&code.text.to_string()));
+    }
+
+    Ok(abs_path)
+}
+
+/// Replace the whole content of a file and save it, returns the absolute path.
+/// The caller broadcasts `file:changed` to the other clients.
+pub async fn set_file(state: &AppState, timer: &mut EventTimer, path: &str, text: &str) -> Result<String> {
+    let abs_path = abs_file(path)
+        .map_err(|e| anyhow!("Failed to resolve file: {:?}", e))?;
+    crate::readonly::check_writable(&abs_path)?;
+
+    let mut f2c = timer.lock("file2code", &state.file2code).await;
+    let code = f2c.entry(abs_path.clone()).or_insert_with(Code::new);
+
+    crate::file_history::record(std::path::Path::new(&abs_path));
+    code.set_file_name(abs_path.clone());
+    code.ensure_file_exists().ok();
+    code.set_text(text);
+
+    code.save_file()
+        .map_err(|e| anyhow!("Failed to set file: {:?}", e))?;
+
+    let mut lsp_manager = timer.lock("lsp_manager", &state.lsp_manager).await;
+    if let Some(lsp) = lsp_manager.get_for(&code.lang, &abs_path).await {
+        lsp.did_save(&abs_path, Some(text));
+    }
+
+    Ok(abs_path)
+}
+
+/// Create refused because the path exists and overwrite was not asked for
+#[derive(Debug)]
+pub struct AlreadyExists {
+    pub path: String,
+}
+
+impl std::fmt::Display for AlreadyExists {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} already exists", self.path)
+    }
+}
+
+impl std::error::Error for AlreadyExists {}
+
+/// Overwrite refused because the file is open with unsaved changes
+#[derive(Debug)]
+pub struct DirtyBuffer {
+    pub path: String,
+}
+
+impl std::fmt::Display for DirtyBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} has unsaved changes", self.path)
+    }
+}
+
+impl std::error::Error for DirtyBuffer {}
+
+/// Create a file holding `content`. Fails with `AlreadyExists` unless `overwrite`, the
+/// check and the create are one atomic open so concurrent creates can't
+/// clobber each other. Overwriting an open buffer with unsaved changes
+/// fails with `DirtyBuffer`.
+pub async fn create_file(state: &AppState, timer: &mut EventTimer, path: &str, content: &str, overwrite: bool) -> Result<()> {
+    let mut f2c = timer.lock("file2code", &state.file2code).await;
+    if overwrite && f2c.get(path).is_some_and(|code| code.changed) {
+        return Err(DirtyBuffer { path: path.to_string() }.into());
+    }
+
+    crate::file_history::record(std::path::Path::new(path));
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true);
+    if overwrite {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+
+    let mut file = match options.open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            return Err(AlreadyExists { path: path.to_string() }.into());
+        }
+        Err(e) => return Err(anyhow!("Failed to create file: {:?}", e)),
+    };
+    std::io::Write::write_all(&mut file, content.as_bytes())?;
+
+    match f2c.get_mut(path) {
+        // The open buffer follows the truncated file
+        Some(code) => code.reload()?,
+        None if !content.is_empty() => {
+            f2c.insert(path.to_string(), Code::from_file(path, &state.config)?);
+        }
+        None => {
+            let mut code = Code::new();
+            code.set_file_name(path.to_string());
+            f2c.insert(path.to_string(), code);
+        }
+    }
+
+    Ok(())
+}
+
+/// Create the file or directory `name` in `parent_path`, with its missing
+/// parents. A file gets `template` rendered with the template variables,
+/// see `create_file` for `overwrite` and the errors. Returns the absolute
+/// path.
+pub async fn create_entry(
+    state: &AppState,
+    timer: &mut EventTimer,
+    parent_path: &str,
+    name: &str,
+    is_file: bool,
+    template: Option<&str>,
+    overwrite: bool,
+) -> Result<String> {
+    let path_buf = crate::paths::absolute(&crate::paths::join_child(parent_path, name));
+    let full_path = path_buf.to_string_lossy().to_string();
+
+    if let Some(parent) = path_buf.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| anyhow!("Failed to create parent directories: {:?}", e))?;
+    }
+    if !is_file {
+        create_dir(&full_path)?;
+        return Ok(full_path);
+    }
+
+    let content = match template {
+        Some(template) => {
+            let lang = crate::code::lang_of(&full_path, &state.config);
+            let comment = state.config.language.iter()
+                .find(|l| l.name == lang)
+                .map(|l| l.comment.as_str())
+                .unwrap_or_default();
+            let vars = crate::template::variables(&std::env::current_dir().unwrap_or_default(), &path_buf, comment).await;
+            crate::template::render(template, &vars)
+        }
+        None => String::new(),
+    };
+    create_file(state, timer, &full_path, &content, overwrite).await?;
+    Ok(full_path)
+}
+
+/// Names that differ only in case among the open buffers and the
+/// workspace files, see case_check
+pub async fn case_issues(state: &AppState, timer: &mut EventTimer) -> Vec<crate::case_check::CaseIssue> {
+    let buffers: Vec<String> = timer.lock("file2code", &state.file2code).await.keys().cloned().collect();
+    let files = crate::file_index::files();
+    crate::case_check::check(&buffers, &files, crate::paths::is_case_insensitive())
+}
+
+/// Counterpart of the file `path` in the workspace `root` by the
+/// alternates rules of its language, see alternate.rs. With `create` a
+/// missing one is created, a test from the rule's template. Returns it and
+/// whether it was created.
+pub async fn alternate_file(
+    state: &AppState, timer: &mut EventTimer, root: &std::path::Path, path: &str, create: bool,
+) -> Result<(crate::alternate::Alternate, bool)> {
+    let abs_path = abs_file(path)
+        .map_err(|e| anyhow!("Failed to resolve file: {:?}", e))?;
+    let lang = crate::code::lang_of(&abs_path, &state.config);
+    let rules = state.config.language.iter()
+        .find(|l| l.name == lang)
+        .map(|l| l.alternates.as_slice())
+        .unwrap_or_default();
+    let root = crate::paths::absolute(root);
+    let alternate = crate::alternate::resolve(&root, std::path::Path::new(&abs_path), rules)
+        .ok_or_else(|| anyhow!("No alternate file for {}", abs_path))?;
+    if alternate.exists || !create {
+        return Ok((alternate, false));
+    }
+
+    let parent = alternate.path.parent().unwrap_or(&root).to_string_lossy().to_string();
+    let name = alternate.path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    create_entry(state, timer, &parent, &name, true, alternate.template.as_deref(), false).await?;
+    Ok((crate::alternate::Alternate { exists: true, ..alternate }, true))
+}
+
+/// Create a directory, fails with `AlreadyExists` if the path exists
+pub fn create_dir(path: &str) -> Result<()> {
+    match std::fs::create_dir(path) {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            Err(AlreadyExists { path: path.to_string() }.into())
+        }
+        Err(e) => Err(anyhow!("Failed to create directory: {:?}", e)),
+    }
+}
+
+/// Rename or move a file or directory, the open buffers go along with
+/// their undo history. Fails with `AlreadyExists` if the destination exists
+/// unless `overwrite`, and with `DirtyBuffer` when overwriting a buffer with
+/// unsaved changes. Returns the absolute paths and the moved buffers.
+pub async fn rename_file(
+    state: &AppState, timer: &mut EventTimer, from: &str, to: &str, overwrite: bool,
+) -> Result<(String, String, Vec<String>)> {
+    let from_path = crate::paths::absolute(std::path::Path::new(from));
+    let to_path = crate::paths::absolute(std::path::Path::new(to));
+    let (from_abs, to_abs) = (from_path.to_string_lossy().to_string(), to_path.to_string_lossy().to_string());
+
+    if !from_path.exists() {
+        return Err(anyhow!("{} does not exist", from_abs));
+    }
+    if from_abs == to_abs {
+        return Ok((from_abs, to_abs, Vec::new()));
+    }
+    if to_path.starts_with(&from_path) {
+        return Err(anyhow!("Can't move {} into itself", from_abs));
+    }
+    // Changing only the case finds the file itself at the destination
+    let case_only = crate::paths::is_case_insensitive() && from_abs.to_lowercase() == to_abs.to_lowercase();
+    {
+        let mut f2c = timer.lock("file2code", &state.file2code).await;
+        if to_path.exists() && !case_only {
+            if !overwrite {
+                return Err(AlreadyExists { path: to_abs }.into());
+            }
+            if f2c.get(&to_abs).is_some_and(|code| code.changed) {
+                return Err(DirtyBuffer { path: to_abs }.into());
+            }
+        }
+
+        if let Some(parent) = to_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        if from_path.is_file() {
+            crate::file_history::record(&from_path);
+            crate::file_history::record(&to_path);
+        }
+        std::fs::rename(&from_path, &to_path)
+            .map_err(|e| anyhow!("Failed to rename {}: {:?}", from_abs, e))?;
+        crate::paths::forget_cases();
+
+        // The overwritten buffer is replaced by the moved one
+        if let Some(code) = f2c.remove(&to_abs) {
+            let mut lsp_manager = timer.lock("lsp_manager", &state.lsp_manager).await;
+            if let Some(lsp) = lsp_manager.running_for(&code.lang, &to_abs) {
+                lsp.did_close(&to_abs);
+            }
+        }
+    }
+
+    crate::file_index::rename(&from_path, &to_path);
+    let moved = move_buffers(state, timer, &from_abs, &to_abs).await;
+    Ok((from_abs, to_abs, moved))
+}
+
+/// Move the open buffers of a renamed file, or of the files in a renamed
+/// directory, their LSP documents and the opened files of the clients.
+/// Returns the new paths of the moved buffers.
+pub async fn move_buffers(state: &AppState, timer: &mut EventTimer, from: &str, to: &str) -> Vec<String> {
+    let prefix = format!("{}{}", from, std::path::MAIN_SEPARATOR);
+    let moved_path = |path: &str| format!("{}{}", to, &path[from.len()..]);
+
+    let mut f2c = timer.lock("file2code", &state.file2code).await;
+    let moved: Vec<String> = f2c.keys()
+        .filter(|k| **k == from || k.starts_with(&prefix))
+        .cloned()
+        .collect();
+    if moved.is_empty() {
+        return Vec::new();
+    }
+
+    let mut lsp_manager = timer.lock("lsp_manager", &state.lsp_manager).await;
+    for old in &moved {
+        let Some(mut code) = f2c.remove(old) else { continue };
+        let new = moved_path(old);
+        code.abs_path = new.clone();
+        code.file_name = crate::utils::get_file_name(&new);
+
+        let text = code.text.to_string();
+        if let Some(lsp) = lsp_manager.running_for(&code.lang, old) {
+            lsp.rename_document(old, &new, &text);
+        }
+        f2c.insert(new, code);
+    }
+    drop(lsp_manager);
+
+    let mut socket2data = timer.lock("socket2data", &state.socket2data).await;
+    for data in socket2data.values_mut() {
+        for old in &moved {
+            if data.opened_files.remove(old) {
+                data.opened_files.insert(moved_path(old));
+            }
+        }
+    }
+
+    moved.iter().map(|old| moved_path(old)).collect()
+}
+
+/// Open buffers under `dir` with unsaved edits
+pub async fn unsaved_buffers(state: &AppState, timer: &mut EventTimer, dir: &std::path::Path) -> Vec<String> {
+    let f2c = timer.lock("file2code", &state.file2code).await;
+    let mut unsaved: Vec<String> = f2c.iter()
+        .filter(|(path, code)| code.changed && std::path::Path::new(path.as_str()).starts_with(dir))
+        .map(|(path, _)| path.clone())
+        .collect();
+    unsaved.sort();
+    unsaved
+}
+
+/// Load the open buffers of files changed on disk again, e.g. by a git
+/// checkout, and resync their LSP documents. Returns the reloaded paths
+/// with their new text.
+pub async fn reload_buffers(state: &AppState, timer: &mut EventTimer, paths: &[std::path::PathBuf]) -> Vec<(String, String)> {
+    let mut f2c = timer.lock("file2code", &state.file2code).await;
+    let mut lsp_manager = timer.lock("lsp_manager", &state.lsp_manager).await;
+    let mut reloaded = Vec::new();
+    for path in paths.iter().filter(|p| p.is_file()) {
+        let key = path.to_string_lossy().to_string();
+        let Some(code) = f2c.get_mut(&key) else { continue };
+        if let Err(e) = code.reload() {
+            crate::output::write("server", &format!("reload failed {}: {}", key, e));
+            continue;
+        }
+        let text = code.text.to_string();
+        if let Some(lsp) = lsp_manager.running_for(&code.lang, &key) {
+            lsp.did_save(&key, Some(&text));
+        }
+        reloaded.push((key, text));
+    }
+    reloaded
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Insert,
+    Remove,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Edit {
+    pub operation: Operation,
+    /// UTF-16 offset at the time the edit is applied, edits of a change
+    /// are applied in order
+    pub start: usize,
+    pub text: String,
+}
+
+/// Edits of one file, the payload of `file:change`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Change {
+    pub file: String,
+    pub edits: Vec<Edit>,
+    /// Version of the buffer the edits were made on, see revision. Changes
+    /// without one apply to the buffer as it is.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base: Option<u64>,
+    /// Version of the buffer after the change, set by the backend
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u64>,
+}
+
+/// Change refused because it was made on an older version of the buffer
+/// and can't be rebased over the changes since
+#[derive(Debug)]
+pub struct Stale {
+    pub path: String,
+    pub base: u64,
+    pub version: u64,
+}
+
+impl std::fmt::Display for Stale {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} changed since version {}, it is at version {}", self.path, self.base, self.version)
+    }
+}
+
+impl std::error::Error for Stale {}
+
+/// Apply the edits of a change to the file2code buffer and forward them to
+/// the LSP. A change made on an older version is rebased over the changes
+/// since. Returns the absolute path and the change as applied, with the
+/// new version, for the caller to broadcast as `file:change`. Fails with
+/// `ReadOnly` for read-only documents and `Stale` when the change can't be
+/// rebased.
+pub async fn apply_edits(state: &AppState, timer: &mut EventTimer, change: &Change) -> Result<(String, Change)> {
+    let abs_path = abs_file(&change.file)
+        .map_err(|e| anyhow!("Failed to resolve file: {:?}", e))?;
+    crate::readonly::check_writable(&abs_path)?;
+
+    let mut f2c = timer.lock("file2code", &state.file2code).await;
+    let code = get_or_create_code(&mut f2c, &abs_path, &state.config)?;
+
+    let edits = match change.base {
+        Some(base) if base != code.version => code.revisions.rebase(base, code.version, &change.edits)
+            .ok_or_else(|| Stale { path: abs_path.clone(), base, version: code.version })?,
+        _ => change.edits.clone(),
+    };
+
+    let len = code.text.len_utf16_cu();
+    let mut lsp_manager = timer.lock("lsp_manager", &state.lsp_manager).await;
+    edit_code(code, &mut lsp_manager, &abs_path, &edits).await;
+    next_version(&abs_path, code, &edits, len);
+
+    let applied = Change { file: change.file.clone(), edits, base: None, version: Some(code.version) };
+    Ok((abs_path, applied))
+}
+
+/// Apply edits to a buffer one after the other and forward them to its
+/// language server
+async fn edit_code(code: &mut Code, lsp_manager: &mut crate::lsp::LspManager, abs_path: &str, edits: &[Edit]) {
+    for e in edits.iter() {
+        match e.operation {
+            Operation::Insert => {
+                let start_char = code.utf16_to_char_offset(e.start);
+                let (line, col_utf16) = code.char_to_position(start_char);
+                code.insert_text_at(&e.text, start_char);
+
+                if let Some(lsp) = lsp_manager.get_for(&code.lang, abs_path).await {
+                    lsp.did_change(line, col_utf16, line, col_utf16, abs_path, &e.text).await;
+                }
+            }
+            Operation::Remove => {
+                let start_char = code.utf16_to_char_offset(e.start);
+                let end_char = code.utf16_to_char_offset(e.start + e.text.encode_utf16().count());
+                let (start_line, start_col_utf16) = code.char_to_position(start_char);
+                let (end_line, end_col_utf16) = code.char_to_position(end_char);
+
+                code.remove_text2(start_char, end_char);
+
+                if let Some(lsp) = lsp_manager.get_for(&code.lang, abs_path).await {
+                    lsp.did_change(
+                        start_line, start_col_utf16,
+                        end_line, end_col_utf16,
+                        abs_path, "",
+                    )
+                    .await;
+                }
+            }
+        }
+    }
+}
+
+/// Move a buffer on to the version after `edits`, made on a text of `len`
+/// UTF-16 units, and send them to its collab peers
+fn next_version(abs_path: &str, code: &mut Code, edits: &[Edit], len: usize) {
+    code.applied(edits);
+    if !crate::collab::has_session(abs_path) {
+        return;
+    }
+    let spans: Vec<Span> = edits.iter().map(Span::of).collect();
+    match Op::from_edits(edits, len) {
+        Some(op) => crate::collab::forward(abs_path, code.version, &op, &spans, None),
+        None => crate::collab::reset(abs_path, &code.text.to_string(), code.version, None),
+    }
+}
+
+/// Text and version of an open buffer, for resyncing a client with
+/// `file:set`. None without a buffer.
+pub async fn buffer_text(state: &AppState, timer: &mut EventTimer, path: &str) -> Option<(String, u64)> {
+    let abs_path = abs_file(path).ok()?;
+    let f2c = timer.lock("file2code", &state.file2code).await;
+    f2c.get(&abs_path).map(|code| (code.text.to_string(), code.version))
+}
+
+/// Undo (or with `redo`, redo) the last change of a buffer, a group of
+/// changes at once, and forward it to the LSP. Returns the change as the
+/// payload of `file:change` for the other clients, None when the history
+/// is empty. Fails with `NotOpen` without a buffer and with `ReadOnly` for
+/// read-only documents.
+pub async fn undo_change(state: &AppState, timer: &mut EventTimer, path: &str, redo: bool) -> Result<Option<Change>> {
+    let abs_path = abs_file(path)
+        .map_err(|e| anyhow!("Failed to resolve file: {:?}", e))?;
+    crate::readonly::check_writable(&abs_path)?;
+
+    let mut f2c = timer.lock("file2code", &state.file2code).await;
+    let Some(code) = f2c.get_mut(&abs_path) else {
+        return Err(NotOpen { path: abs_path }.into());
+    };
+    // The changes are replayed on the text before them for their positions
+    let mut text = code.text.clone();
+    let len = text.len_utf16_cu();
+    let Some(applied) = (match redo {
+        true => code.redo(),
+        false => code.undo(),
+    }) else {
+        return Ok(None);
+    };
+
+    let mut lsp_manager = timer.lock("lsp_manager", &state.lsp_manager).await;
+    let mut edits = Vec::with_capacity(applied.changes.len());
+    for change in applied.changes {
+        let inserted = matches!(
+            (&change.operation, redo),
+            (crate::code::Operation::Insert, true) | (crate::code::Operation::Remove, false)
+        );
+        let start = change.start.min(text.len_chars());
+        let end = match inserted {
+            true => start,
+            false => (start + change.text.chars().count()).min(text.len_chars()),
+        };
+        let (start_line, start_col) = position(&text, start);
+        let (end_line, end_col) = position(&text, end);
+        let utf16_start = text.char_to_utf16_cu(start);
+        let new_text = match inserted {
+            true => {
+                text.insert(start, &change.text);
+                change.text.as_str()
+            }
+            false => {
+                text.remove(start..end);
+                ""
+            }
+        };
+
+        if let Some(lsp) = lsp_manager.get_for(&code.lang, &abs_path).await {
+            lsp.did_change(start_line, start_col, end_line, end_col, &abs_path, new_text).await;
+        }
+        edits.push(Edit {
+            operation: if inserted { Operation::Insert } else { Operation::Remove },
+            start: utf16_start,
+            text: change.text,
+        });
+    }
+
+    next_version(&abs_path, code, &edits, len);
+    Ok(Some(Change { file: abs_path, edits, base: None, version: Some(code.version) }))
+}
+
+/// Join the collab session of an open buffer with `socket`, under the
+/// buffer's lock so no change falls between the text sent and the join.
+/// Fails with `NotOpen` without a buffer.
+pub async fn collab_join(state: &AppState, timer: &mut EventTimer, socket: SocketRef, path: &str, name: &str) -> Result<Joined> {
+    let abs_path = abs_file(path)
+        .map_err(|e| anyhow!("Failed to resolve file: {:?}", e))?;
+    let f2c = timer.lock("file2code", &state.file2code).await;
+    let Some(code) = f2c.get(&abs_path) else {
+        return Err(NotOpen { path: abs_path }.into());
+    };
+    let peers = crate::collab::join(&abs_path, socket, name);
+    Ok(Joined { text: code.text.to_string(), version: code.version, peers, file: abs_path })
+}
+
+/// Apply an operation a collab peer made on version `base`: it is moved
+/// over the changes since, applied and sent to the peers. Returns the
+/// change as applied for the clients outside the session. Fails with
+/// `NotOpen` without a buffer, `ReadOnly` for read-only documents and
+/// `Stale` when the changes since `base` are no longer logged, the peer
+/// then gets `collab:reset`.
+pub async fn apply_collab_op(
+    state: &AppState, timer: &mut EventTimer, socket_id: &str, path: &str, base: u64, op: &Op,
+) -> Result<Change> {
+    let abs_path = abs_file(path)
+        .map_err(|e| anyhow!("Failed to resolve file: {:?}", e))?;
+    crate::readonly::check_writable(&abs_path)?;
+
+    if !crate::collab::is_peer(&abs_path, socket_id) {
+        return Err(anyhow!("Not in the session of {}", abs_path));
+    }
+
+    let mut f2c = timer.lock("file2code", &state.file2code).await;
+    let Some(code) = f2c.get_mut(&abs_path) else {
+        return Err(NotOpen { path: abs_path }.into());
+    };
+    // The peer starts over from the text as it is
+    let Some(since) = code.revisions.since(base, code.version) else {
+        crate::collab::reset(&abs_path, &code.text.to_string(), code.version, Some(socket_id));
+        return Err(Stale { path: abs_path, base, version: code.version }.into());
+    };
+    let op = crate::collab::transform(op, &since);
+    let Some(edits) = crate::collab::edits(&op, &code.text) else {
+        crate::collab::reset(&abs_path, &code.text.to_string(), code.version, Some(socket_id));
+        return Err(anyhow!("Operation does not apply to version {} of {}", base, abs_path));
+    };
+
+    let mut lsp_manager = timer.lock("lsp_manager", &state.lsp_manager).await;
+    edit_code(code, &mut lsp_manager, &abs_path, &edits).await;
+    code.applied(&edits);
+    let spans: Vec<Span> = edits.iter().map(Span::of).collect();
+    crate::collab::forward(&abs_path, code.version, &op, &spans, Some(socket_id));
+
+    Ok(Change { file: abs_path, edits, base: None, version: Some(code.version) })
+}
+
+/// Set the selection of a collab peer, made on version `base`, moved to
+/// the current version. Fails with `NotOpen` without a buffer and `Stale`
+/// when the changes since `base` are no longer logged.
+pub async fn collab_cursor(
+    state: &AppState, timer: &mut EventTimer, socket_id: &str, path: &str, base: u64, anchor: usize, head: usize,
+) -> Result<()> {
+    let abs_path = abs_file(path)
+        .map_err(|e| anyhow!("Failed to resolve file: {:?}", e))?;
+    let f2c = timer.lock("file2code", &state.file2code).await;
+    let Some(code) = f2c.get(&abs_path) else {
+        return Err(NotOpen { path: abs_path }.into());
+    };
+    let since = code.revisions.since(base, code.version)
+        .ok_or_else(|| Stale { path: abs_path.clone(), base, version: code.version })?;
+    let (anchor, head) = (crate::collab::transform_index(anchor, &since), crate::collab::transform_index(head, &since));
+    if !crate::collab::cursor(&abs_path, socket_id, anchor, head) {
+        return Err(anyhow!("Not in the session of {}", abs_path));
+    }
+    Ok(())
+}
+
+/// Line and UTF-16 column of a char offset
+fn position(text: &ropey::Rope, char_offset: usize) -> (usize, usize) {
+    let line = text.char_to_line(char_offset);
+    let line_start = text.char_to_utf16_cu(text.line_to_char(line));
+    (line, text.char_to_utf16_cu(char_offset) - line_start)
+}
+
+/// Apply changes to several files at once, for refactorings like rename.
+/// Changes are applied file by file; with `save` every touched buffer is
+/// written to disk. Returns the absolute paths of the changed files and
+/// the changes as applied, for broadcasting.
+pub async fn apply_batch(
+    state: &AppState,
+    timer: &mut EventTimer,
+    changes: &[Change],
+    save: bool,
+) -> Result<(Vec<String>, Vec<Change>)> {
+    let mut files = Vec::with_capacity(changes.len());
+    let mut applied = Vec::with_capacity(changes.len());
+    for change in changes {
+        let (abs_path, change) = apply_edits(state, timer, change).await?;
+        if save {
+            save_file(state, timer, &abs_path, true).await?;
+        }
+        files.push(abs_path);
+        applied.push(change);
+    }
+    Ok((files, applied))
+}
+
+/// Apply the text edits of a language server's workspace edit to the
+/// buffers of the files it touches. Returns the changed files and the
+/// changes applied, for broadcasting.
+pub async fn apply_workspace_edit(
+    state: &AppState,
+    timer: &mut EventTimer,
+    edit: lsp_types::WorkspaceEdit,
+    save: bool,
+) -> Result<(Vec<String>, Vec<Change>)> {
+    let texts = {
+        let mut f2c = timer.lock("file2code", &state.file2code).await;
+        let mut texts = std::collections::HashMap::new();
+        for (path, _) in crate::rename::workspace_edit_files(edit.clone()) {
+            let code = get_or_create_code(&mut f2c, &path, &state.config)?;
+            texts.insert(path, code.text.to_string());
+        }
+        texts
+    };
+    let changes = crate::rename::workspace_edit_changes(edit, &texts)?;
+    apply_batch(state, timer, &changes, save).await
+}
+
+/// Add `path` as a root and make it the workspace of the connection
+/// `socket_id`, see workspace:open. Returns the root.
+pub async fn open_workspace(state: &AppState, timer: &mut EventTimer, socket_id: &str, path: &str) -> Result<std::path::PathBuf> {
+    let root = crate::roots::add(path)?;
+    if let Err(e) = crate::watcher::watch_root(&root) {
+        tracing::error!("Failed to watch {}: {}", root.display(), e);
+    }
+    let mut sockets_data = timer.lock("socket2data", &state.socket2data).await;
+    sockets_data.entry(socket_id.to_string()).or_default().root = Some(root.clone());
+    Ok(root)
+}
+
+/// Root of the workspace the client opened, the primary root until it
+/// sends `workspace:open`
+pub async fn workspace_root(state: &AppState, timer: &mut EventTimer, socket_id: &str) -> std::path::PathBuf {
+    let sockets = timer.lock("socket2data", &state.socket2data).await;
+    sockets.get(socket_id).and_then(|data| data.root.clone()).unwrap_or_else(crate::roots::primary)
+}
+
+/// The first scope `event` needs that the connection lacks, for requests
+/// needing more than the event they came with
+pub async fn missing_scope(state: &AppState, timer: &mut EventTimer, socket_id: &str, event: &str) -> Option<crate::config::Scope> {
+    let sockets = timer.lock("socket2data", &state.socket2data).await;
+    let scopes = sockets.get(socket_id).map(|data| data.scopes.as_slice()).unwrap_or_default();
+    crate::permissions::missing(scopes, event)
+}
+
+/// Start a search of the files of `root` that `filter` keeps on the
+/// background pool, or join the same one already running (see
+/// search_jobs). Results arrive on the receiver, the handle resolves with
+/// the search error if any, an invalid glob included.
+pub fn start_search(
+    root: &std::path::Path,
+    pattern: String,
+    filter: PathFilter,
+    cancel: CancellationToken,
+) -> (mpsc::Receiver<FileSearchResult>, JoinHandle<Result<()>>) {
+    crate::search_jobs::subscribe(root, pattern, filter, cancel)
+}
+
+/// Search the given documents in their buffers, unsaved edits included,
+/// in their provider for virtual documents, or on disk. Nothing runs in
+/// the background, the open documents are few.
+pub async fn search_documents(
+    state: &AppState, timer: &mut EventTimer, paths: &[String], pattern: &str,
+) -> Vec<FileSearchResult> {
+    let buffers: Vec<(String, Option<String>)> = {
+        let f2c = timer.lock("file2code", &state.file2code).await;
+        paths.iter().map(|path| (path.clone(), f2c.get(path).map(|code| code.text.to_string()))).collect()
+    };
+
+    let mut results = Vec::new();
+    for (path, text) in buffers {
+        let text = match text {
+            Some(text) => text,
+            None if crate::vfs::is_virtual(&path) => match crate::vfs::read(&path) {
+                Ok((text, _)) => text,
+                Err(_) => continue,
+            },
+            None => match tokio::fs::read_to_string(&path).await {
+                Ok(text) => text,
+                Err(_) => continue,
+            },
+        };
+        let display_path = match crate::vfs::is_virtual(&path) {
+            true => path.clone(),
+            false => crate::utils::relative_path(&path),
+        };
+        if let Some(result) = text_search(&display_path, &text, pattern) {
+            results.push(result);
+        }
+    }
+    results
+}
+
+/// Lines around the peeked line when the request does not say
+const DEFAULT_PEEK_CONTEXT: usize = 10;
+/// Upper bound of lines on each side of the peeked line
+const MAX_PEEK_CONTEXT: usize = 200;
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Peek {
+    /// Line number of the first returned line
+    pub start: usize,
+    pub lines: Vec<String>,
+    pub lang: String,
+}
+
+/// Lines `line - context ..= line + context` of a reader, without reading
+/// past the window
+fn read_window(reader: impl std::io::BufRead, line: usize, context: usize) -> std::io::Result<(usize, Vec<String>)> {
+    let start = line.saturating_sub(context);
+    let lines = reader.lines()
+        .skip(start)
+        .take((line - start).saturating_add(context).saturating_add(1))
+        .collect::<std::io::Result<Vec<String>>>()?;
+    Ok((start, lines))
+}
+
+/// A window of a file around `line` for previews. Uses the open buffer when
+/// there is one, otherwise reads the file without creating a buffer, so it
+/// does not count as opening the file and the LSP is not told about it.
+pub async fn peek_file(
+    state: &AppState, timer: &mut EventTimer, path: &str, line: usize, context: Option<usize>,
+) -> Result<Peek> {
+    let abs_path = abs_file(path)
+        .map_err(|e| anyhow!("Failed to resolve file: {:?}", e))?;
+    let context = context.unwrap_or(DEFAULT_PEEK_CONTEXT).min(MAX_PEEK_CONTEXT);
+
+    {
+        let f2c = timer.lock("file2code", &state.file2code).await;
+        if let Some(code) = f2c.get(&abs_path) {
+            let last = code.text.len_lines().saturating_sub(1);
+            let start = line.saturating_sub(context).min(last);
+            let end = line.saturating_add(context).min(last);
+            let lines = (start..=end)
+                .map(|i| code.text.line(i).to_string().trim_end_matches(['\n', '\r']).to_string())
+                .collect();
+            return Ok(Peek { start, lines, lang: code.lang.clone() });
+        }
+    }
+
+    let file = std::fs::File::open(&abs_path)
+        .map_err(|e| anyhow!("Failed to open file: {}", e))?;
+    let (start, lines) = read_window(std::io::BufReader::new(file), line, context)
+        .map_err(|e| anyhow!("Failed to read file: {}", e))?;
+    let lang = crate::code::lang_of(&abs_path, &state.config);
+
+    Ok(Peek { start, lines, lang })
+}
+
+/// Upper bound of text matches returned by `text_references`
+const MAX_TEXT_REFERENCES: usize = 1000;
+
+/// References for languages without a language server: whole-word matches
+/// of `word` across the workspace. The search engine finds the candidate
+/// lines, the word boundaries and UTF-16 columns are checked on the file.
+pub async fn text_references(word: &str, lang: &str) -> Result<Vec<lsp_types::Location>> {
+    let (mut result_rx, search) = start_search(
+        &crate::roots::primary(), word.to_string(), PathFilter::default(), CancellationToken::new(),
+    );
+
+    let mut locations = Vec::new();
+    while let Some(file_result) = result_rx.recv().await {
+        let Ok(text) = std::fs::read_to_string(&file_result.file_path) else { continue };
+        let lines: Vec<&str> = text.lines().collect();
+        let uri: lsp_types::Uri = match crate::paths::file_uri(&abs_file(&file_result.file_path)?).parse() {
+            Ok(uri) => uri,
+            Err(_) => continue,
+        };
+
+        let mut line_numbers: Vec<usize> = file_result.matches.iter().map(|m| m.line).collect();
+        line_numbers.dedup();
+
+        for line in line_numbers {
+            let Some(line_text) = lines.get(line) else { continue };
+            for occurrence in crate::rename::find_occurrences(line_text, word, lang) {
+                let start = lsp_types::Position::new(line as u32, occurrence.start as u32);
+                let end = lsp_types::Position::new(line as u32, (occurrence.start + word.encode_utf16().count()) as u32);
+                locations.push(lsp_types::Location { uri: uri.clone(), range: lsp_types::Range { start, end } });
+            }
+        }
+
+        if locations.len() >= MAX_TEXT_REFERENCES {
+            locations.truncate(MAX_TEXT_REFERENCES);
+            break;
+        }
+    }
+
+    drop(result_rx);
+    search.abort();
+    Ok(locations)
+}
+
+#[cfg(test)]
+mod services_tests {
+    use super::*;
+    use crate::app_state::test_state;
+
+    /// A temporary directory added as a workspace root, files outside the
+    /// roots are read-only
+    fn workspace() -> Result<tempfile::TempDir> {
+        let dir = tempfile::tempdir()?;
+        crate::roots::add(&dir.path().to_string_lossy())?;
+        Ok(dir)
+    }
+
+    fn insert(file: &str, start: usize, text: &str) -> Change {
+        let edits = vec![Edit { operation: Operation::Insert, start, text: text.to_string() }];
+        Change { file: file.to_string(), edits, base: None, version: None }
+    }
+
+    #[tokio::test]
+    async fn test_open_edit_save_close() -> Result<()> {
+        let dir = workspace()?;
+        let path = dir.path().join("notes.txt").to_string_lossy().to_string();
+        std::fs::write(&path, "hello\n")?;
+        let (state, mut timer) = (test_state(), EventTimer::start("test"));
+
+        let file = load_file(&state, &mut timer, &path, false).await?;
+        assert_eq!(file.content, "hello\n");
+        track_opened(&state, &mut timer, "s1", [file.abs_path.clone()]).await;
+
+        apply_edits(&state, &mut timer, &insert(&path, 5, " world")).await?;
+        let (_, changed, ranges) = dirty_diff(&state, &mut timer, &file.abs_path).await;
+        assert!(changed && !ranges.is_empty());
+
+        // Another writer got to the file first
+        std::fs::write(&path, "other\n")?;
+        let conflict = save_file(&state, &mut timer, &path, false).await.unwrap_err();
+        assert!(conflict.is::<SaveConflict>());
+        save_file(&state, &mut timer, &path, true).await?;
+        assert_eq!(std::fs::read_to_string(&path)?, "hello world\n");
+
+        close_file(&state, &mut timer, "s1", &path).await?;
+        assert!(state.socket2data.lock().await["s1"].opened_files.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_open_batch() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let text = dir.path().join("a.rs").to_string_lossy().to_string();
+        let large = dir.path().join("b.rs").to_string_lossy().to_string();
+        std::fs::write(&text, "fn a() {}\n")?;
+        std::fs::write(&large, "x".repeat(64))?;
+        let missing = dir.path().join("c.rs").to_string_lossy().to_string();
+        let (state, mut timer) = (test_state(), EventTimer::start("test"));
+
+        let files = open_batch(&state, &mut timer, &[text, large, missing], 32).await;
+        assert!(matches!(&files[0], BatchFile::Loaded(f) if f.content == "fn a() {}\n"));
+        assert!(matches!(&files[1], BatchFile::NotText(n) if n.too_large));
+        assert!(matches!(&files[2], BatchFile::Failed(_)));
+        assert_eq!(state.file2code.lock().await.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_open_converted() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("menu.txt").to_string_lossy().to_string();
+        std::fs::write(&path, b"caf\xe9\n")?;
+        let (state, mut timer) = (test_state(), EventTimer::start("test"));
+
+        let Err(not_utf8) = load_file(&state, &mut timer, &path, false).await else { panic!("opened as UTF-8") };
+        assert_eq!(not_utf8.downcast_ref::<NotText>().unwrap().encoding, Some("latin-1"));
+        let file = load_file(&state, &mut timer, &path, true).await?;
+        assert_eq!(file.content, "caf\u{e9}\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_and_rename_errors() -> Result<()> {
+        let dir = workspace()?;
+        let root = dir.path().to_string_lossy().to_string();
+        let (state, mut timer) = (test_state(), EventTimer::start("test"));
+
+        let created = create_entry(&state, &mut timer, &root, "src/main.rs", true, None, false).await?;
+        assert!(std::path::Path::new(&created).is_file());
+        let exists = create_entry(&state, &mut timer, &root, "src/main.rs", true, None, false).await.unwrap_err();
+        assert!(exists.is::<AlreadyExists>());
+
+        apply_edits(&state, &mut timer, &insert(&created, 0, "fn main() {}")).await?;
+        let dirty = create_entry(&state, &mut timer, &root, "src/main.rs", true, None, true).await.unwrap_err();
+        assert!(dirty.is::<DirtyBuffer>());
+
+        let to = dir.path().join("lib.rs").to_string_lossy().to_string();
+        let (_, to, moved) = rename_file(&state, &mut timer, &created, &to, false).await?;
+        assert_eq!(moved, [to.as_str()]);
+        assert!(state.file2code.lock().await[&to].changed);
+
+        let not_open = restore_from_buffer(&state, &mut timer, &created).await.unwrap_err();
+        assert!(not_open.is::<NotOpen>());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_undo_redo() -> Result<()> {
+        let dir = workspace()?;
+        let path = dir.path().join("a.txt").to_string_lossy().to_string();
+        std::fs::write(&path, "h\u{e9}llo\n")?;
+        let (state, mut timer) = (test_state(), EventTimer::start("test"));
+        load_file(&state, &mut timer, &path, false).await?;
+        apply_edits(&state, &mut timer, &insert(&path, 5, " w\u{f6}rld")).await?;
+
+        let undone = undo_change(&state, &mut timer, &path, false).await?.unwrap();
+        assert!(matches!(undone.edits[..], [Edit { operation: Operation::Remove, start: 5, .. }]));
+        assert_eq!(state.file2code.lock().await[&undone.file].text.to_string(), "h\u{e9}llo\n");
+
+        let redone = undo_change(&state, &mut timer, &path, true).await?.unwrap();
+        assert!(matches!(&redone.edits[..], [Edit { operation: Operation::Insert, start: 5, text }] if text == " w\u{f6}rld"));
+        assert!(undo_change(&state, &mut timer, &path, true).await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_edits() -> Result<()> {
+        let dir = workspace()?;
+        let path = dir.path().join("a.txt").to_string_lossy().to_string();
+        std::fs::write(&path, "hello world\n")?;
+        let (state, mut timer) = (test_state(), EventTimer::start("test"));
+        let file = load_file(&state, &mut timer, &path, false).await?;
+        let on = |base: u64, mut change: Change| { change.base = Some(base); change };
+
+        let (_, first) = apply_edits(&state, &mut timer, &on(file.version, insert(&path, 0, ">> "))).await?;
+        assert_eq!(first.version, Some(file.version + 1));
+        // Made on the text before the first change
+        let (_, second) = apply_edits(&state, &mut timer, &on(file.version, insert(&path, 11, "!"))).await?;
+        assert_eq!(second.edits[0].start, 14);
+        assert_eq!(buffer_text(&state, &mut timer, &path).await.unwrap().0, ">> hello world!\n");
+
+        let mut removed = on(second.version.unwrap(), insert(&path, 9, "world"));
+        removed.edits[0].operation = Operation::Remove;
+        apply_edits(&state, &mut timer, &removed).await?;
+        let stale = apply_edits(&state, &mut timer, &on(second.version.unwrap(), insert(&path, 11, "x"))).await.unwrap_err();
+        assert!(stale.is::<Stale>());
+        assert_eq!(buffer_text(&state, &mut timer, &path).await.unwrap(), (">> hello !\n".to_string(), file.version + 3));
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_dir_pages() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        for name in ["b", "a"] {
+            std::fs::create_dir(dir.path().join(name))?;
+        }
+        for name in ["y.txt", "x.txt", "z.txt"] {
+            std::fs::write(dir.path().join(name), "")?;
+        }
+        let path = dir.path().to_string_lossy().to_string();
+
+        let first = list_dir(dir.path(), &path, 3, None)?;
+        assert_eq!(first.dirs, ["a", "b"]);
+        assert_eq!(first.files, ["x.txt"]);
+        assert_eq!(first.total, 5);
+        assert!(first.truncated);
+
+        // A file added before the cursor does not shift the next page
+        std::fs::write(dir.path().join("w.txt"), "")?;
+        let second = list_dir(dir.path(), &path, 3, first.cursor.as_deref())?;
+        assert!(second.dirs.is_empty());
+        assert_eq!(second.files, ["y.txt", "z.txt"]);
+        assert!(!second.truncated && second.cursor.is_none());
+
+        // Relative paths resolve against the root
+        let sub = list_dir(dir.path(), "a", 3, None)?;
+        assert_eq!((sub.name.as_str(), sub.relative_path.as_str(), sub.total), ("a", "a", 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_window() -> std::io::Result<()> {
+        let text = "0\n1\n2\n3\n4\n5\n";
+        assert_eq!(read_window(text.as_bytes(), 3, 1)?, (2, vec!["2".into(), "3".into(), "4".into()]));
+        assert_eq!(read_window(text.as_bytes(), 0, 2)?, (0, vec!["0".into(), "1".into(), "2".into()]));
+        // Near and past the end only the existing lines come back
+        assert_eq!(read_window(text.as_bytes(), 5, 2)?.1, ["3", "4", "5"]);
+        assert!(read_window(text.as_bytes(), 20, 2)?.1.is_empty());
+        // Out of range requests don't overflow
+        assert!(read_window(text.as_bytes(), usize::MAX, 2)?.1.is_empty());
+        assert_eq!(read_window(text.as_bytes(), 1, usize::MAX)?.1.len(), 6);
+        Ok(())
+    }
+}
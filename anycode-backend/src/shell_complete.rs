@@ -0,0 +1,198 @@
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::path::Path;
+use std::time::Duration;
+
+// Completion of a partial command line for frontends without a terminal
+// emulator. The word under the cursor is completed by bash's `compgen`:
+// command names in command position (line start, after `|`, `;`, `&` or
+// `(`), files and directories elsewhere. bash runs without rc files, so
+// aliases and functions of the user's shell are not offered.
+
+const COMPLETE_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_COMPLETIONS: usize = 200;
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CompletionKind {
+    Command,
+    File,
+    Directory,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct ShellCompletion {
+    /// Replacement of the word, escaped for the shell
+    pub text: String,
+    pub kind: CompletionKind,
+}
+
+/// Completions replacing the chars `start..end` of the line
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct ShellCompletions {
+    pub start: usize,
+    pub end: usize,
+    pub completions: Vec<ShellCompletion>,
+}
+
+/// The word ending at the cursor
+#[derive(Debug, PartialEq)]
+struct Word {
+    /// Char offset of the word start
+    start: usize,
+    /// The word without its escapes
+    text: String,
+    command_position: bool,
+}
+
+/// Find the word before the char offset `cursor`. Backslash escapes and
+/// quotes keep spaces in a word, the quotes themselves are dropped.
+fn word_at(line: &str, cursor: usize) -> Word {
+    let mut word = Word { start: 0, text: String::new(), command_position: true };
+    let mut quote = None;
+    let mut escaped = false;
+    // Whether a word started since the last separator, empty quotes count
+    let mut in_word = false;
+
+    for (i, c) in line.chars().take(cursor).enumerate() {
+        if escaped {
+            word.text.push(c);
+            escaped = false;
+            continue;
+        }
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.text.push(c),
+            (None, '\\') => {
+                escaped = true;
+                in_word = true;
+            }
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, '|' | ';' | '&' | '(') => {
+                word = Word { start: i + 1, text: String::new(), command_position: true };
+                in_word = false;
+            }
+            (None, c) if c.is_whitespace() => {
+                // Arguments follow the command name
+                let command_position = word.command_position && !in_word;
+                word = Word { start: i + 1, text: String::new(), command_position };
+                in_word = false;
+            }
+            (None, c) => {
+                word.text.push(c);
+                in_word = true;
+            }
+        }
+    }
+    word
+}
+
+/// Escape the shell specials of a completed word
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        if c.is_whitespace() || "\\'\"$`&|;()<>*?[]!#~{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Completions of the line at the char offset `cursor`, paths relative to `cwd`
+pub async fn complete(line: &str, cursor: usize, cwd: &Path) -> Result<ShellCompletions> {
+    if cfg!(target_os = "windows") {
+        return Err(anyhow!("Shell completion needs bash"));
+    }
+
+    let cursor = cursor.min(line.chars().count());
+    let word = word_at(line, cursor);
+    let script = if word.command_position { "compgen -c -- \"$1\"" } else { "compgen -f -- \"$1\"" };
+
+    // The word is an argument of the script, never part of it
+    let output = tokio::process::Command::new("bash")
+        .args(["--noprofile", "--norc", "-c", script, "compgen"])
+        .arg(&word.text)
+        .current_dir(cwd)
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(COMPLETE_TIMEOUT, output).await
+        .map_err(|_| anyhow!("Shell completion timed out"))??;
+
+    let mut names: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|l| !l.is_empty())
+        .map(str::to_string)
+        .collect();
+    names.sort();
+    names.dedup();
+    names.truncate(MAX_COMPLETIONS);
+
+    let completions = names.into_iter().map(|name| {
+        if word.command_position {
+            return ShellCompletion { text: escape(&name), kind: CompletionKind::Command };
+        }
+        let is_dir = cwd.join(&name).is_dir();
+        ShellCompletion {
+            text: if is_dir { format!("{}/", escape(&name)) } else { escape(&name) },
+            kind: if is_dir { CompletionKind::Directory } else { CompletionKind::File },
+        }
+    }).collect();
+
+    Ok(ShellCompletions { start: word.start, end: cursor, completions })
+}
+
+#[cfg(test)]
+mod shell_complete_tests {
+    use super::*;
+
+    fn word(start: usize, text: &str, command_position: bool) -> Word {
+        Word { start, text: text.to_string(), command_position }
+    }
+
+    #[test]
+    fn test_word_at() {
+        assert_eq!(word_at("", 0), word(0, "", true));
+        assert_eq!(word_at("car", 3), word(0, "car", true));
+        assert_eq!(word_at("cargo b", 7), word(6, "b", false));
+        assert_eq!(word_at("cargo ", 6), word(6, "", false));
+        assert_eq!(word_at("ls src | gr", 11), word(9, "gr", true));
+        assert_eq!(word_at("cd a && mak", 11), word(8, "mak", true));
+        // Escaped and quoted spaces stay in the word
+        assert_eq!(word_at("cat my\\ fi", 10), word(4, "my fi", false));
+        assert_eq!(word_at("cat 'my fi", 10), word(4, "my fi", false));
+        // Only the text before the cursor counts
+        assert_eq!(word_at("git status", 3), word(0, "git", true));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("my file (1).txt"), "my\\ file\\ \\(1\\).txt");
+        assert_eq!(escape("plain"), "plain");
+    }
+
+    #[tokio::test]
+    async fn test_complete() -> Result<()> {
+        if cfg!(target_os = "windows") {
+            return Ok(());
+        }
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir(dir.path().join("src dir"))?;
+        std::fs::write(dir.path().join("script.sh"), "")?;
+        std::fs::write(dir.path().join("other.txt"), "")?;
+
+        let files = complete("cat s", 5, dir.path()).await?;
+        assert_eq!((files.start, files.end), (4, 5));
+        assert_eq!(files.completions, vec![
+            ShellCompletion { text: "script.sh".to_string(), kind: CompletionKind::File },
+            ShellCompletion { text: "src\\ dir/".to_string(), kind: CompletionKind::Directory },
+        ]);
+
+        let commands = complete("ech", 3, dir.path()).await?;
+        assert!(commands.completions.iter().any(|c| c.text == "echo" && c.kind == CompletionKind::Command));
+        Ok(())
+    }
+}
@@ -0,0 +1,153 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::config::TerminalProfile;
+use crate::terminal::Terminal;
+
+// Shell integration of the terminals: bash and zsh start with startup
+// files of ours that run the user's own and then mark the prompt and the
+// command lines with OSC 133/633 for terminal_history.rs. bash gets
+// `--rcfile`, zsh a ZDOTDIR whose files hand over to the user's. Shells
+// started with other arguments, a login bash or a script, are left alone.
+// Off with `[terminal] shell_integration = false`.
+
+const DIR: &str = "shell-integration";
+
+const BASHRC: &str = r##"# anycode shell integration, see shell_integration.rs
+[ -f ~/.bashrc ] && . ~/.bashrc
+if [[ $PS1 != *'133;B'* ]]; then
+    PS1='\[\e]133;A\a\]'"$PS1"'\[\e]133;B\a\]'
+    PS0='\e]133;C\a'"$PS0"
+fi
+"##;
+
+const ZSHENV: &str = r##"# anycode shell integration, see shell_integration.rs
+ANYCODE_ZDOTDIR=$ZDOTDIR
+ZDOTDIR=${ANYCODE_USER_ZDOTDIR:-$HOME}
+[[ -f $ZDOTDIR/.zshenv ]] && . $ZDOTDIR/.zshenv
+ANYCODE_USER_ZDOTDIR=$ZDOTDIR
+ZDOTDIR=$ANYCODE_ZDOTDIR
+"##;
+
+const ZPROFILE: &str = r##"# anycode shell integration, see shell_integration.rs
+ZDOTDIR=$ANYCODE_USER_ZDOTDIR
+[[ -f $ZDOTDIR/.zprofile ]] && . $ZDOTDIR/.zprofile
+ANYCODE_USER_ZDOTDIR=$ZDOTDIR
+ZDOTDIR=$ANYCODE_ZDOTDIR
+"##;
+
+// The user's files from here on, .zlogin included
+const ZSHRC: &str = r##"# anycode shell integration, see shell_integration.rs
+ZDOTDIR=$ANYCODE_USER_ZDOTDIR
+[[ -f $ZDOTDIR/.zshrc ]] && . $ZDOTDIR/.zshrc
+
+__anycode_precmd() { print -n '\e]133;A\a' }
+__anycode_preexec() {
+    local cmd=${1//\\/\\\\}
+    cmd=${cmd//;/\\x3b}
+    cmd=${cmd//$'\n'/\\x0a}
+    print -rn -- $'\e]633;E;'"$cmd"$'\a'
+    print -n '\e]133;C\a'
+}
+autoload -Uz add-zsh-hook
+add-zsh-hook precmd __anycode_precmd
+add-zsh-hook preexec __anycode_preexec
+"##;
+
+/// `profile`, the default shell without one, set up for the markers when
+/// it is bash or zsh
+pub fn apply(profile: Option<TerminalProfile>) -> Option<TerminalProfile> {
+    if cfg!(windows) {
+        return profile;
+    }
+    Some(apply_in(&crate::store::home_dir().join(DIR), profile))
+}
+
+fn apply_in(dir: &Path, profile: Option<TerminalProfile>) -> TerminalProfile {
+    let mut profile = profile.unwrap_or_else(|| TerminalProfile {
+        name: String::new(),
+        command: Terminal::default_shell(),
+        args: Vec::new(),
+        env: HashMap::new(),
+    });
+    let shell = Path::new(&profile.command).file_name().unwrap_or_default().to_string_lossy().to_string();
+    let only = |allowed: &[&str]| profile.args.iter().all(|arg| allowed.contains(&arg.as_str()));
+
+    let applied = match shell.as_str() {
+        "bash" if only(&["-i"]) => bash(dir, &mut profile),
+        "zsh" if only(&["-i", "-l", "--login"]) => zsh(dir, &mut profile),
+        _ => Ok(()),
+    };
+    if let Err(e) = applied {
+        tracing::warn!("No shell integration for {}: {}", profile.command, e);
+    }
+    profile
+}
+
+fn bash(dir: &Path, profile: &mut TerminalProfile) -> Result<()> {
+    let rcfile = dir.join("bashrc");
+    write(&rcfile, BASHRC)?;
+    profile.args.splice(0..0, ["--rcfile".to_string(), rcfile.to_string_lossy().to_string()]);
+    Ok(())
+}
+
+fn zsh(dir: &Path, profile: &mut TerminalProfile) -> Result<()> {
+    let zdotdir = dir.join("zsh");
+    for (name, content) in [(".zshenv", ZSHENV), (".zprofile", ZPROFILE), (".zshrc", ZSHRC)] {
+        write(&zdotdir.join(name), content)?;
+    }
+    let user = profile.env.get("ZDOTDIR").cloned().or_else(|| std::env::var("ZDOTDIR").ok());
+    if let Some(user) = user {
+        profile.env.insert("ANYCODE_USER_ZDOTDIR".to_string(), user);
+    }
+    profile.env.insert("ZDOTDIR".to_string(), zdotdir.to_string_lossy().to_string());
+    Ok(())
+}
+
+/// Write a startup file unless it is up to date
+fn write(path: &Path, content: &str) -> Result<()> {
+    if std::fs::read(path).is_ok_and(|current| current == content.as_bytes()) {
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, content)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod shell_integration_tests {
+    use super::*;
+
+    fn profile(command: &str, args: &[&str]) -> Option<TerminalProfile> {
+        Some(TerminalProfile {
+            name: "p".to_string(),
+            command: command.to_string(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+            env: HashMap::new(),
+        })
+    }
+
+    #[test]
+    fn test_apply() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let rcfile = dir.path().join("bashrc").to_string_lossy().to_string();
+
+        let bash = apply_in(dir.path(), profile("/bin/bash", &["-i"]));
+        assert_eq!(bash.args, ["--rcfile", rcfile.as_str(), "-i"]);
+        assert!(std::fs::read_to_string(&rcfile)?.contains("133;B"));
+
+        let zsh = apply_in(dir.path(), profile("zsh", &["-l"]));
+        assert_eq!(zsh.args, ["-l"]);
+        assert_eq!(zsh.env["ZDOTDIR"], dir.path().join("zsh").to_string_lossy());
+        assert!(dir.path().join("zsh").join(".zshrc").is_file());
+
+        // Scripts, login bash and other shells are left alone
+        assert_eq!(apply_in(dir.path(), profile("bash", &["-c", "make"])).args, ["-c", "make"]);
+        assert_eq!(apply_in(dir.path(), profile("bash", &["-l"])).args, ["-l"]);
+        assert!(apply_in(dir.path(), profile("ssh", &[])).env.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+use serde::Serialize;
+use socketioxide::SocketIo;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use sysinfo::{ProcessRefreshKind, ProcessesToUpdate, System};
+
+use crate::app_state::AppState;
+
+static STARTED: OnceLock<Instant> = OnceLock::new();
+static RUNNING_TASKS: AtomicUsize = AtomicUsize::new(0);
+static URL: OnceLock<String> = OnceLock::new();
+
+/// Remember the server start for the uptime, called once from main
+pub fn init() {
+    STARTED.get_or_init(Instant::now);
+}
+
+/// The url the server ended up listening at, after the port fallback
+pub fn set_url(url: &str) {
+    let _ = URL.set(url.to_string());
+}
+
+pub fn url() -> Option<String> {
+    URL.get().cloned()
+}
+
+pub fn uptime_secs() -> u64 {
+    STARTED.get_or_init(Instant::now).elapsed().as_secs()
+}
+
+/// Counts a running task (run_task, build commands) until dropped
+pub struct TaskGuard(());
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        RUNNING_TASKS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub fn task_started() -> TaskGuard {
+    RUNNING_TASKS.fetch_add(1, Ordering::Relaxed);
+    TaskGuard(())
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ServerStatus {
+    pub url: Option<String>,
+    pub uptime_secs: u64,
+    pub clients: usize,
+    pub open_documents: usize,
+    pub terminals: usize,
+    pub lsp: Vec<String>,
+    pub tasks: usize,
+    /// Resident memory of the backend process in bytes
+    pub memory: u64,
+    /// Watcher events received but not handled yet
+    pub watcher_backlog: usize,
+    pub power_mode: crate::config::PowerMode,
+}
+
+fn process_memory() -> u64 {
+    let Ok(pid) = sysinfo::get_current_pid() else { return 0 };
+
+    let mut system = System::new();
+    system.refresh_processes_specifics(
+        ProcessesToUpdate::Some(&[pid]), true, ProcessRefreshKind::nothing().with_memory(),
+    );
+    system.process(pid).map(|p| p.memory()).unwrap_or(0)
+}
+
+pub async fn collect(state: &AppState, io: &SocketIo) -> ServerStatus {
+    let open_documents = state.file2code.lock().await.len();
+    let terminals = state.terminals.lock().await.len();
+
+    let mut lsp: Vec<String> = {
+        let mut lsp_manager = state.lsp_manager.lock().await;
+        lsp_manager.running().map(|l| l.lang().to_string()).collect()
+    };
+    lsp.sort();
+
+    ServerStatus {
+        url: url(),
+        uptime_secs: uptime_secs(),
+        clients: io.sockets().len(),
+        open_documents,
+        terminals,
+        lsp,
+        tasks: RUNNING_TASKS.load(Ordering::Relaxed),
+        memory: process_memory(),
+        watcher_backlog: crate::watcher::backlog(),
+        power_mode: crate::power::mode(),
+    }
+}
+
+#[cfg(test)]
+mod status_tests {
+    use super::*;
+
+    #[test]
+    fn test_task_guard_counts_running_tasks() {
+        let before = RUNNING_TASKS.load(Ordering::Relaxed);
+        let guard = task_started();
+        assert_eq!(RUNNING_TASKS.load(Ordering::Relaxed), before + 1);
+        drop(guard);
+        assert_eq!(RUNNING_TASKS.load(Ordering::Relaxed), before);
+    }
+
+    #[test]
+    fn test_process_memory() {
+        assert!(process_memory() > 0);
+    }
+}
@@ -0,0 +1,295 @@
+use anyhow::{anyhow, Result};
+use parking_lot::Mutex;
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::{error, info};
+
+use crate::config::{Config, StorageBackend};
+use crate::store;
+
+// Session state shared by the backends of a user: recent files, terminal
+// history and whatever joins them later. Values are bytes under a
+// namespace and a key, JSON through the helpers, encrypted like the state
+// files when encryption is enabled. The default backend is a SQLite
+// database in the anycode home in WAL mode, so several backends write to
+// it without losing updates. Another store (e.g. a remote one for several
+// machines) implements `Storage`.
+
+const DEFAULT_DB_FILE: &str = "state.db";
+/// How long a write waits for another process holding the database
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Schema changes in order, `PRAGMA user_version` counts the applied ones
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE entries (
+        namespace TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value BLOB NOT NULL,
+        updated_at INTEGER NOT NULL,
+        PRIMARY KEY (namespace, key)
+    )",
+];
+
+pub trait Storage: Send + Sync {
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>>;
+    fn put(&self, namespace: &str, key: &str, value: &[u8]) -> Result<()>;
+    /// Replace a value with `f` of the current one, atomically against
+    /// other writers
+    fn update(&self, namespace: &str, key: &str, f: &mut dyn FnMut(Option<Vec<u8>>) -> Result<Vec<u8>>) -> Result<()>;
+}
+
+impl dyn Storage + '_ {
+    pub fn get_json<T: DeserializeOwned>(&self, namespace: &str, key: &str) -> Result<Option<T>> {
+        let Some(data) = self.get(namespace, key)? else { return Ok(None) };
+        let data = store::unseal(&entry_name(namespace, key), data)?;
+        Ok(Some(serde_json::from_slice(&data)?))
+    }
+
+    pub fn put_json<T: Serialize>(&self, namespace: &str, key: &str, value: &T) -> Result<()> {
+        let data = store::seal(&entry_name(namespace, key), &serde_json::to_vec(value)?)?;
+        self.put(namespace, key, &data)
+    }
+
+    /// Apply `f` to the stored value, or to the default one, and return
+    /// the value written
+    pub fn update_json<T>(&self, namespace: &str, key: &str, f: impl FnOnce(&mut T)) -> Result<T>
+    where
+        T: Serialize + DeserializeOwned + Default,
+    {
+        let name = entry_name(namespace, key);
+        let mut f = Some(f);
+        let mut updated = None;
+        self.update(namespace, key, &mut |current| {
+            let mut value: T = match current {
+                Some(data) => serde_json::from_slice(&store::unseal(&name, data)?)?,
+                None => T::default(),
+            };
+            // The closure is called once, `f` is taken out of the Option
+            if let Some(f) = f.take() {
+                f(&mut value);
+            }
+            let data = store::seal(&name, &serde_json::to_vec(&value)?)?;
+            updated = Some(value);
+            Ok(data)
+        })?;
+        updated.ok_or_else(|| anyhow!("{} was not updated", name))
+    }
+}
+
+fn entry_name(namespace: &str, key: &str) -> String {
+    format!("{}/{}", namespace, key)
+}
+
+pub struct SqliteStorage {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut conn = Connection::open(path)?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        migrate(&mut conn)?;
+        Ok(Self { conn: Mutex::new(conn) })
+    }
+}
+
+fn migrate(conn: &mut Connection) -> Result<()> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let version: usize = tx.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version > MIGRATIONS.len() {
+        return Err(anyhow!("State database version {} is newer than this anycode", version));
+    }
+    for migration in &MIGRATIONS[version..] {
+        tx.execute_batch(migration)?;
+    }
+    tx.pragma_update(None, "user_version", MIGRATIONS.len())?;
+    tx.commit()?;
+    Ok(())
+}
+
+impl Storage for SqliteStorage {
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>> {
+        let conn = self.conn.lock();
+        let value = conn.query_row(
+            "SELECT value FROM entries WHERE namespace = ?1 AND key = ?2",
+            params![namespace, key],
+            |row| row.get(0),
+        ).optional()?;
+        Ok(value)
+    }
+
+    fn put(&self, namespace: &str, key: &str, value: &[u8]) -> Result<()> {
+        let conn = self.conn.lock();
+        put_entry(&conn, namespace, key, value)
+    }
+
+    fn update(&self, namespace: &str, key: &str, f: &mut dyn FnMut(Option<Vec<u8>>) -> Result<Vec<u8>>) -> Result<()> {
+        let mut conn = self.conn.lock();
+        // Immediate takes the write lock before reading, no other process
+        // writes between the read and the write
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let current = tx.query_row(
+            "SELECT value FROM entries WHERE namespace = ?1 AND key = ?2",
+            params![namespace, key],
+            |row| row.get(0),
+        ).optional()?;
+        put_entry(&tx, namespace, key, &f(current)?)?;
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+fn put_entry(conn: &Connection, namespace: &str, key: &str, value: &[u8]) -> Result<()> {
+    conn.execute(
+        "INSERT INTO entries (namespace, key, value, updated_at) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT (namespace, key) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at",
+        params![namespace, key, value, chrono::Utc::now().timestamp_millis()],
+    )?;
+    Ok(())
+}
+
+/// Entries kept in memory only, the backend of tests and the fallback when
+/// the database cannot be opened
+#[derive(Default)]
+pub struct MemoryStorage {
+    entries: Mutex<HashMap<(String, String), Vec<u8>>>,
+}
+
+impl Storage for MemoryStorage {
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>> {
+        let entries = self.entries.lock();
+        Ok(entries.get(&(namespace.to_string(), key.to_string())).cloned())
+    }
+
+    fn put(&self, namespace: &str, key: &str, value: &[u8]) -> Result<()> {
+        let mut entries = self.entries.lock();
+        entries.insert((namespace.to_string(), key.to_string()), value.to_vec());
+        Ok(())
+    }
+
+    fn update(&self, namespace: &str, key: &str, f: &mut dyn FnMut(Option<Vec<u8>>) -> Result<Vec<u8>>) -> Result<()> {
+        let mut entries = self.entries.lock();
+        let entry = (namespace.to_string(), key.to_string());
+        let value = f(entries.get(&entry).cloned())?;
+        entries.insert(entry, value);
+        Ok(())
+    }
+}
+
+static STORAGE: OnceLock<Box<dyn Storage>> = OnceLock::new();
+
+pub fn init(config: &Config) {
+    let conf = config.storage.as_ref();
+    let storage: Box<dyn Storage> = match conf.map(|c| c.backend).unwrap_or_default() {
+        StorageBackend::Memory => Box::new(MemoryStorage::default()),
+        StorageBackend::Sqlite => {
+            let path = conf.and_then(|c| c.path.as_ref())
+                .map(PathBuf::from)
+                .unwrap_or_else(|| store::home_dir().join(DEFAULT_DB_FILE));
+            match SqliteStorage::open(&path) {
+                Ok(storage) => {
+                    info!("State storage at {}", path.display());
+                    Box::new(storage)
+                }
+                Err(e) => {
+                    error!("Failed to open state storage {}, state is not persisted: {}", path.display(), e);
+                    Box::new(MemoryStorage::default())
+                }
+            }
+        }
+    };
+
+    let _ = STORAGE.set(storage);
+}
+
+/// The configured storage, in memory until `init`
+pub fn get() -> &'static dyn Storage {
+    STORAGE.get_or_init(|| Box::new(MemoryStorage::default())).as_ref()
+}
+
+#[cfg(test)]
+mod storage_tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+    struct Counter {
+        count: u32,
+    }
+
+    fn check_storage(storage: &dyn Storage) -> Result<()> {
+        assert_eq!(storage.get("recent", "files")?, None);
+        storage.put("recent", "files", b"one")?;
+        storage.put("recent", "files", b"two")?;
+        storage.put("history", "/w", b"ls")?;
+        assert_eq!(storage.get("recent", "files")?, Some(b"two".to_vec()));
+        assert_eq!(storage.get("history", "/w")?, Some(b"ls".to_vec()));
+        assert_eq!(storage.get("history", "/other")?, None);
+
+        let counter = storage.update_json("counters", "a", |c: &mut Counter| c.count += 1)?;
+        assert_eq!(counter, Counter { count: 1 });
+        storage.update_json("counters", "a", |c: &mut Counter| c.count += 1)?;
+        assert_eq!(storage.get_json::<Counter>("counters", "a")?, Some(Counter { count: 2 }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory_storage() -> Result<()> {
+        check_storage(&MemoryStorage::default())
+    }
+
+    #[test]
+    fn test_sqlite_storage() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(DEFAULT_DB_FILE);
+        check_storage(&SqliteStorage::open(&path)?)?;
+
+        // Reopening keeps the data and applies no migration twice
+        let storage: &dyn Storage = &SqliteStorage::open(&path)?;
+        assert_eq!(storage.get_json::<Counter>("counters", "a")?, Some(Counter { count: 2 }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_newer_database() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(DEFAULT_DB_FILE);
+        Connection::open(&path)?.pragma_update(None, "user_version", MIGRATIONS.len() + 1)?;
+        assert!(SqliteStorage::open(&path).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_updates() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(DEFAULT_DB_FILE);
+        SqliteStorage::open(&path)?;
+
+        // One connection per thread, as separate backends would have
+        let threads: Vec<_> = (0..4).map(|_| {
+            let path = path.clone();
+            std::thread::spawn(move || -> Result<()> {
+                let storage: &dyn Storage = &SqliteStorage::open(&path)?;
+                for _ in 0..25 {
+                    storage.update_json("counters", "shared", |c: &mut Counter| c.count += 1)?;
+                }
+                Ok(())
+            })
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap()?;
+        }
+
+        let storage: &dyn Storage = &SqliteStorage::open(&path)?;
+        assert_eq!(storage.get_json::<Counter>("counters", "shared")?, Some(Counter { count: 100 }));
+        Ok(())
+    }
+}
@@ -0,0 +1,161 @@
+use anyhow::{anyhow, Result};
+use serde::de::DeserializeOwned;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tracing::{error, info};
+
+use crate::config::{Config, EncryptionConfig};
+use crate::crypt::{self, Cipher};
+
+const SALT_FILE: &str = "encryption.salt";
+const DEFAULT_PASSPHRASE_ENV: &str = "ANYCODE_PASSPHRASE";
+
+enum Encryption {
+    Disabled,
+    Enabled(Cipher),
+    /// Enabled in config but no key could be obtained, writes are refused
+    /// instead of silently falling back to plaintext.
+    Unavailable,
+}
+
+static ENCRYPTION: OnceLock<Encryption> = OnceLock::new();
+
+/// Directory for backend state files: ANYCODE_HOME or ~/.anycode
+pub fn home_dir() -> PathBuf {
+    match std::env::var("ANYCODE_HOME") {
+        Ok(home) => PathBuf::from(home),
+        Err(_) => dirs::home_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join(".anycode"),
+    }
+}
+
+/// Name of the per-workspace state directory, kept out of walks and search
+pub const WORKSPACE_DIR: &str = ".anycode";
+
+/// Directory for state belonging to the current workspace (recordings,
+/// caches), as opposed to the user wide `home_dir`
+pub fn workspace_dir() -> PathBuf {
+    std::env::current_dir()
+        .unwrap_or_else(|_| PathBuf::from("."))
+        .join(WORKSPACE_DIR)
+}
+
+pub fn init(config: &Config) {
+    let encryption = match &config.encryption {
+        Some(conf) if conf.enabled => match load_cipher(conf) {
+            Ok(cipher) => {
+                info!("State files encryption enabled");
+                Encryption::Enabled(cipher)
+            }
+            Err(e) => {
+                error!("State files encryption is enabled but unavailable: {}", e);
+                Encryption::Unavailable
+            }
+        },
+        _ => Encryption::Disabled,
+    };
+
+    let _ = ENCRYPTION.set(encryption);
+}
+
+fn encryption() -> &'static Encryption {
+    ENCRYPTION.get_or_init(|| Encryption::Disabled)
+}
+
+fn load_cipher(conf: &EncryptionConfig) -> Result<Cipher> {
+    let passphrase = match &conf.key_command {
+        Some(cmd) => key_from_command(cmd)?,
+        None => {
+            let var = conf.passphrase_env.as_deref().unwrap_or(DEFAULT_PASSPHRASE_ENV);
+            std::env::var(var).map_err(|_| anyhow!("{} is not set", var))?
+        }
+    };
+
+    if passphrase.is_empty() {
+        return Err(anyhow!("Empty passphrase"));
+    }
+
+    Cipher::from_passphrase(&passphrase, &load_or_create_salt()?)
+}
+
+/// Read the key from a keychain helper, e.g. `secret-tool lookup service anycode`
+/// or `security find-generic-password -s anycode -w`
+fn key_from_command(cmd: &str) -> Result<String> {
+    let args = shell_words::split(cmd)?;
+    let (program, args) = args.split_first()
+        .ok_or_else(|| anyhow!("Empty key_command"))?;
+
+    let output = std::process::Command::new(program).args(args).output()?;
+    if !output.status.success() {
+        return Err(anyhow!("key_command exited with {}", output.status));
+    }
+
+    Ok(String::from_utf8(output.stdout)?.trim_end().to_string())
+}
+
+/// The salt of the key, created on first use. A salt file that can't be
+/// read is an error rather than replaced, another salt would make the
+/// encrypted files unreadable.
+fn load_or_create_salt() -> Result<Vec<u8>> {
+    let path = home_dir().join(SALT_FILE);
+    match std::fs::read(&path) {
+        Ok(salt) if salt.len() == crypt::SALT_LEN => return Ok(salt),
+        Ok(salt) => return Err(anyhow!(
+            "{} is corrupt ({} bytes instead of {}), restore it or remove it to start over",
+            path.display(), salt.len(), crypt::SALT_LEN,
+        )),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(anyhow!("Failed to read {}: {}", path.display(), e)),
+    }
+
+    info!("Creating the encryption salt {}", path.display());
+    let salt = crypt::generate_salt();
+    write_atomic(&path, &salt)?;
+    Ok(salt.to_vec())
+}
+
+fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, data)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// State data as it is stored, encrypted when enabled. `name` is only
+/// used in errors.
+pub fn seal(name: &str, data: &[u8]) -> Result<Vec<u8>> {
+    match encryption() {
+        Encryption::Disabled => Ok(data.to_vec()),
+        Encryption::Enabled(cipher) => cipher.encrypt(data),
+        Encryption::Unavailable => {
+            Err(anyhow!("Refusing to write {} unencrypted, no encryption key", name))
+        }
+    }
+}
+
+/// Stored state data back in plaintext. Data written before encryption was
+/// enabled is still readable.
+pub fn unseal(name: &str, data: Vec<u8>) -> Result<Vec<u8>> {
+    if !crypt::is_encrypted(&data) {
+        return Ok(data);
+    }
+
+    match encryption() {
+        Encryption::Enabled(cipher) => cipher.decrypt(&data),
+        _ => Err(anyhow!("{} is encrypted but no encryption key is configured", name)),
+    }
+}
+
+/// Read a state file relative to the anycode home
+pub fn read(name: &str) -> Result<Vec<u8>> {
+    unseal(name, std::fs::read(home_dir().join(name))?)
+}
+
+pub fn read_json<T: DeserializeOwned>(name: &str) -> Result<T> {
+    Ok(serde_json::from_slice(&read(name)?)?)
+}
+
@@ -0,0 +1,401 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use streaming_iterator::StreamingIterator;
+use tree_sitter::{Language, Parser, Query, QueryCursor};
+
+use crate::utils::relative_to_current_dir;
+
+// Search by the shape of the code instead of its text. A search is a
+// tree-sitter query of one language, written against the node names of its
+// grammar, or a preset (function definitions, calls, type definitions) of
+// every supported language with the search pattern as the name to match.
+// A capture named `match` marks the range of a result, otherwise the whole
+// matched pattern is the result. The other captures are returned with
+// their text, for refactoring tools.
+
+/// Larger files are skipped, they are not hand-written sources
+const MAX_FILE_SIZE: u64 = 4 * 1024 * 1024;
+const MAX_PREVIEW_CHARS: usize = 200;
+const MATCH_CAPTURE: &str = "match";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Preset {
+    /// Function and method definitions
+    Function,
+    /// Function, method and macro calls
+    Call,
+    /// Classes, structs, enums, traits, interfaces and type aliases
+    Type,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Grammar {
+    Rust,
+    Python,
+    JavaScript,
+    TypeScript,
+    Tsx,
+    Go,
+}
+
+impl Grammar {
+    const ALL: [Grammar; 6] = [
+        Grammar::Rust, Grammar::Python, Grammar::JavaScript, Grammar::TypeScript, Grammar::Tsx, Grammar::Go,
+    ];
+
+    fn of(path: &Path) -> Option<Grammar> {
+        let grammar = match path.extension()?.to_str()? {
+            "rs" => Grammar::Rust,
+            "py" | "pyi" => Grammar::Python,
+            "js" | "jsx" | "mjs" | "cjs" => Grammar::JavaScript,
+            "ts" | "mts" | "cts" => Grammar::TypeScript,
+            "tsx" => Grammar::Tsx,
+            "go" => Grammar::Go,
+            _ => return None,
+        };
+        Some(grammar)
+    }
+
+    /// Language name, as in the config
+    fn lang(&self) -> &'static str {
+        match self {
+            Grammar::Rust => "rust",
+            Grammar::Python => "python",
+            Grammar::JavaScript => "javascript",
+            Grammar::TypeScript | Grammar::Tsx => "typescript",
+            Grammar::Go => "go",
+        }
+    }
+
+    fn language(&self) -> Language {
+        match self {
+            Grammar::Rust => tree_sitter_rust::LANGUAGE.into(),
+            Grammar::Python => tree_sitter_python::LANGUAGE.into(),
+            Grammar::JavaScript => tree_sitter_javascript::LANGUAGE.into(),
+            Grammar::TypeScript => tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+            Grammar::Tsx => tree_sitter_typescript::LANGUAGE_TSX.into(),
+            Grammar::Go => tree_sitter_go::LANGUAGE.into(),
+        }
+    }
+
+    /// Query of a preset, capturing the name as `@name`
+    fn preset(&self, preset: Preset) -> &'static str {
+        match (self, preset) {
+            (Grammar::Rust, Preset::Function) => "(function_item name: (identifier) @name) @match",
+            (Grammar::Rust, Preset::Call) => r#"[
+                (call_expression function: [
+                    (identifier) @name
+                    (field_expression field: (field_identifier) @name)
+                    (scoped_identifier name: (identifier) @name)
+                ])
+                (macro_invocation macro: (identifier) @name)
+            ] @match"#,
+            (Grammar::Rust, Preset::Type) => r#"[
+                (struct_item name: (type_identifier) @name)
+                (enum_item name: (type_identifier) @name)
+                (trait_item name: (type_identifier) @name)
+                (type_item name: (type_identifier) @name)
+            ] @match"#,
+
+            (Grammar::Python, Preset::Function) => "(function_definition name: (identifier) @name) @match",
+            (Grammar::Python, Preset::Call) => r#"(call function: [
+                (identifier) @name
+                (attribute attribute: (identifier) @name)
+            ]) @match"#,
+            (Grammar::Python, Preset::Type) => "(class_definition name: (identifier) @name) @match",
+
+            (Grammar::JavaScript | Grammar::TypeScript | Grammar::Tsx, Preset::Function) => r#"[
+                (function_declaration name: (identifier) @name)
+                (generator_function_declaration name: (identifier) @name)
+                (method_definition name: (property_identifier) @name)
+                (variable_declarator name: (identifier) @name value: [(arrow_function) (function_expression)])
+            ] @match"#,
+            (Grammar::JavaScript | Grammar::TypeScript | Grammar::Tsx, Preset::Call) => r#"(call_expression function: [
+                (identifier) @name
+                (member_expression property: (property_identifier) @name)
+            ]) @match"#,
+            (Grammar::JavaScript, Preset::Type) => "(class_declaration name: (identifier) @name) @match",
+            (Grammar::TypeScript | Grammar::Tsx, Preset::Type) => r#"[
+                (class_declaration name: (type_identifier) @name)
+                (interface_declaration name: (type_identifier) @name)
+                (type_alias_declaration name: (type_identifier) @name)
+                (enum_declaration name: (identifier) @name)
+            ] @match"#,
+
+            (Grammar::Go, Preset::Function) => r#"[
+                (function_declaration name: (identifier) @name)
+                (method_declaration name: (field_identifier) @name)
+            ] @match"#,
+            (Grammar::Go, Preset::Call) => r#"(call_expression function: [
+                (identifier) @name
+                (selector_expression field: (field_identifier) @name)
+            ]) @match"#,
+            (Grammar::Go, Preset::Type) => "(type_spec name: (type_identifier) @name) @match",
+        }
+    }
+}
+
+/// Languages structural searches support
+pub fn languages() -> Vec<&'static str> {
+    let mut langs: Vec<&'static str> = Grammar::ALL.iter().map(|g| g.lang()).collect();
+    langs.dedup();
+    langs
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Capture {
+    pub name: String,
+    pub text: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A matched node, positions are 0-based lines and char columns like the
+/// text search results
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct StructuralMatch {
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+    /// First line of the match
+    pub preview: String,
+    pub captures: Vec<Capture>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct StructuralFileResult {
+    pub file_path: String,
+    pub lang: &'static str,
+    pub matches: Vec<StructuralMatch>,
+}
+
+/// A search compiled for each searched grammar
+pub struct StructuralSearch {
+    pattern: String,
+    preset: Option<Preset>,
+    lang: Option<String>,
+    queries: HashMap<Grammar, Query>,
+    parsers: HashMap<Grammar, Parser>,
+}
+
+impl StructuralSearch {
+    /// A search of `pattern`, a tree-sitter query, or the name a preset
+    /// matches (any name when empty). Queries need a language, presets
+    /// search all languages unless one is given.
+    pub fn new(pattern: &str, preset: Option<Preset>, lang: Option<&str>) -> Result<Self> {
+        let lang = lang.map(|l| l.to_lowercase()).filter(|l| !l.is_empty());
+        if let Some(lang) = &lang && !languages().contains(&lang.as_str()) {
+            return Err(anyhow!("No structural search for {}, supported: {}", lang, languages().join(", ")));
+        }
+        if preset.is_none() && lang.is_none() {
+            return Err(anyhow!("A structural query needs a language"));
+        }
+        if preset.is_none() && pattern.trim().is_empty() {
+            return Err(anyhow!("Empty query"));
+        }
+
+        let mut search = Self {
+            pattern: pattern.to_string(),
+            preset,
+            lang,
+            queries: HashMap::new(),
+            parsers: HashMap::new(),
+        };
+        // Query errors are reported now rather than once per file
+        for grammar in Grammar::ALL {
+            if search.searches(grammar) {
+                search.compile(grammar)?;
+            }
+        }
+        Ok(search)
+    }
+
+    fn searches(&self, grammar: Grammar) -> bool {
+        self.lang.as_deref().is_none_or(|lang| lang == grammar.lang())
+    }
+
+    fn compile(&mut self, grammar: Grammar) -> Result<()> {
+        let source = match self.preset {
+            Some(preset) if self.pattern.is_empty() => grammar.preset(preset).to_string(),
+            Some(preset) => format!("({} (#eq? @name \"{}\"))", grammar.preset(preset), escape(&self.pattern)),
+            None => self.pattern.clone(),
+        };
+        let query = Query::new(&grammar.language(), &source)
+            .map_err(|e| anyhow!("Invalid {} query: {}", grammar.lang(), e))?;
+        self.queries.insert(grammar, query);
+        Ok(())
+    }
+
+    /// Whether files of the path are searched
+    fn accepts(&self, path: &Path) -> bool {
+        Grammar::of(path).is_some_and(|grammar| self.queries.contains_key(&grammar))
+    }
+
+    /// Matches of a document, None when nothing matches or the language
+    /// is not searched
+    pub fn search_text(&mut self, file_path: &str, text: &str) -> Option<StructuralFileResult> {
+        let grammar = Grammar::of(Path::new(file_path))?;
+        let query = self.queries.get(&grammar)?;
+
+        let parser = self.parsers.entry(grammar).or_insert_with(|| {
+            let mut parser = Parser::new();
+            parser.set_language(&grammar.language()).ok();
+            parser
+        });
+        let tree = parser.parse(text, None)?;
+
+        let match_index = query.capture_index_for_name(MATCH_CAPTURE);
+        let names = query.capture_names();
+        let mut matches: Vec<StructuralMatch> = Vec::new();
+        let mut cursor = QueryCursor::new();
+        let mut found = cursor.matches(query, tree.root_node(), text.as_bytes());
+        while let Some(m) = found.next() {
+            let range = match m.captures.iter().find(|c| Some(c.index) == match_index) {
+                Some(c) => c.node.byte_range(),
+                None => {
+                    let start = m.captures.iter().map(|c| c.node.start_byte()).min();
+                    let end = m.captures.iter().map(|c| c.node.end_byte()).max();
+                    let (Some(start), Some(end)) = (start, end) else { continue };
+                    start..end
+                }
+            };
+
+            let (line, column) = position(text, range.start);
+            let (end_line, end_column) = position(text, range.end);
+            if matches.last().is_some_and(|last| (last.line, last.column, last.end_line, last.end_column) == (line, column, end_line, end_column)) {
+                continue;
+            }
+
+            let captures = m.captures.iter()
+                .filter(|c| Some(c.index) != match_index)
+                .map(|c| {
+                    let (line, column) = position(text, c.node.start_byte());
+                    Capture {
+                        name: names[c.index as usize].to_string(),
+                        text: text[c.node.byte_range()].to_string(),
+                        line,
+                        column,
+                    }
+                })
+                .collect();
+            let line_start = text[..range.start].rfind('\n').map_or(0, |i| i + 1);
+            let preview = text[line_start..].lines().next().unwrap_or("")
+                .chars().take(MAX_PREVIEW_CHARS).collect();
+
+            matches.push(StructuralMatch { line, column, end_line, end_column, preview, captures });
+        }
+
+        (!matches.is_empty()).then(|| StructuralFileResult {
+            file_path: file_path.to_string(),
+            lang: grammar.lang(),
+            matches,
+        })
+    }
+
+    /// Matches of a file on disk
+    pub fn search_file(&mut self, path: &Path) -> Option<StructuralFileResult> {
+        if !self.accepts(path) || std::fs::metadata(path).map_or(true, |m| m.len() > MAX_FILE_SIZE) {
+            return None;
+        }
+        let text = std::fs::read_to_string(path).ok()?;
+        let display_path = relative_to_current_dir(path).unwrap_or_else(|| path.to_path_buf());
+        self.search_text(&display_path.to_string_lossy(), &text)
+    }
+}
+
+/// Line and char column of a byte offset
+fn position(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (before.matches('\n').count(), before[line_start..].chars().count())
+}
+
+/// A name as a string literal of a query predicate
+fn escape(name: &str) -> String {
+    name.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod structural_search_tests {
+    use super::*;
+
+    const RUST: &str = "struct Größe;\n\nfn parse(x: &str) -> u32 {\n    let n = helper(x);\n    println!(\"{}\", n);\n    n.max(1)\n}\n\nfn helper(x: &str) -> u32 { x.len() as u32 }\n";
+
+    #[test]
+    fn test_presets_compile() {
+        for preset in [Preset::Function, Preset::Call, Preset::Type] {
+            for lang in languages() {
+                assert!(StructuralSearch::new("name", Some(preset), Some(lang)).is_ok(), "{:?} {}", preset, lang);
+            }
+        }
+    }
+
+    #[test]
+    fn test_function_named() {
+        let mut search = StructuralSearch::new("helper", Some(Preset::Function), None).unwrap();
+        let result = search.search_text("src/lib.rs", RUST).unwrap();
+        assert_eq!(result.lang, "rust");
+        assert_eq!(result.matches.len(), 1);
+        let m = &result.matches[0];
+        assert_eq!((m.line, m.column, m.end_line), (8, 0, 8));
+        assert_eq!(m.captures, vec![Capture { name: "name".to_string(), text: "helper".to_string(), line: 8, column: 3 }]);
+
+        // Calls of the function are not definitions
+        let mut all = StructuralSearch::new("", Some(Preset::Function), Some("rust")).unwrap();
+        assert_eq!(all.search_text("src/lib.rs", RUST).unwrap().matches.len(), 2);
+    }
+
+    #[test]
+    fn test_calls() {
+        let mut search = StructuralSearch::new("helper", Some(Preset::Call), None).unwrap();
+        let m = search.search_text("a.rs", RUST).unwrap().matches;
+        assert_eq!((m.len(), m[0].line, m[0].column, m[0].preview.as_str()), (1, 3, 12, "    let n = helper(x);"));
+
+        let mut methods = StructuralSearch::new("max", Some(Preset::Call), None).unwrap();
+        assert_eq!(methods.search_text("a.rs", RUST).unwrap().matches[0].line, 5);
+
+        let python = "import os\nos.path.join('a', 'b')\njoin(x)\n";
+        let mut join = StructuralSearch::new("join", Some(Preset::Call), None).unwrap();
+        assert_eq!(join.search_text("a.py", python).unwrap().matches.len(), 2);
+        // Words in strings and comments are not calls
+        assert!(join.search_text("b.py", "# join(x)\ns = 'join(y)'\n").is_none());
+    }
+
+    #[test]
+    fn test_types_and_languages() {
+        let mut search = StructuralSearch::new("Größe", Some(Preset::Type), None).unwrap();
+        let m = &search.search_text("a.rs", RUST).unwrap().matches[0];
+        assert_eq!((m.end_line, m.end_column), (0, 13));
+
+        let ts = "interface Props { a: number }\nclass View {}\nconst render = () => 1;\n";
+        let mut types = StructuralSearch::new("", Some(Preset::Type), Some("typescript")).unwrap();
+        assert_eq!(types.search_text("a.tsx", ts).unwrap().matches.len(), 2);
+        assert!(types.search_text("a.rs", RUST).is_none());
+        let mut functions = StructuralSearch::new("render", Some(Preset::Function), None).unwrap();
+        assert_eq!(functions.search_text("a.ts", ts).unwrap().matches[0].line, 2);
+        assert_eq!(functions.search_text("b.js", "function render() {}\n").unwrap().lang, "javascript");
+
+        let go = "package main\nfunc (s *S) Run() {}\nfunc main() { s.Run() }\n";
+        let mut run = StructuralSearch::new("Run", Some(Preset::Function), None).unwrap();
+        assert_eq!(run.search_text("main.go", go).unwrap().matches[0].line, 1);
+    }
+
+    #[test]
+    fn test_raw_query() {
+        let query = "(let_declaration pattern: (identifier) @var value: (call_expression function: (identifier) @callee))";
+        let mut search = StructuralSearch::new(query, None, Some("rust")).unwrap();
+        let m = &search.search_text("a.rs", RUST).unwrap().matches[0];
+        // Without a match capture the result spans all captures
+        assert_eq!((m.line, m.column, m.end_column), (3, 8, 18));
+        let names: Vec<&str> = m.captures.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["var", "callee"]);
+
+        assert!(StructuralSearch::new(query, None, None).is_err());
+        assert!(StructuralSearch::new("(not_a_node)", None, Some("rust")).is_err());
+        assert!(StructuralSearch::new("", Some(Preset::Call), Some("cobol")).is_err());
+    }
+}
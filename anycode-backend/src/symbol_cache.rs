@@ -0,0 +1,278 @@
+use anyhow::Result;
+use lsp_types::{OneOf, Position, WorkspaceSymbol};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::fuzzy::fuzzy_match;
+use crate::outline::OutlineSymbol;
+
+// Symbols the language servers answered, kept in .anycode/symbols.json so
+// "Go to symbol" and the outline have something to show right after the
+// workspace is opened again, while the servers are still indexing.
+// Workspace symbols are merged per language across queries, document
+// symbols are kept with the hash of the content they describe. The
+// symbols of a language are dropped when its server command changes.
+
+const SYMBOLS_FILE: &str = "symbols.json";
+const MAX_WORKSPACE_SYMBOLS: usize = 20_000;
+const MAX_DOCUMENTS: usize = 1000;
+/// Stores within this delay are written to disk together
+const SAVE_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct DocumentSymbols {
+    lang: String,
+    /// blake3 of the content, hex
+    hash: String,
+    symbols: Vec<OutlineSymbol>,
+    /// Order of the stores, the oldest document is dropped first
+    stored: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+struct Symbols {
+    /// Command of the server each language was answered by
+    servers: HashMap<String, String>,
+    workspace: HashMap<String, Vec<WorkspaceSymbol>>,
+    documents: HashMap<String, DocumentSymbols>,
+    stores: u64,
+}
+
+impl Symbols {
+    /// Drop the languages answered by another server than the configured one
+    fn retain_configured(&mut self, config: &Config) {
+        let stale: Vec<String> = self.servers.iter()
+            .filter(|(lang, command)| server_command(config, lang).as_deref() != Some(command.as_str()))
+            .map(|(lang, _)| lang.clone())
+            .collect();
+        for lang in stale {
+            self.servers.remove(&lang);
+            self.workspace.remove(&lang);
+            self.documents.retain(|_, d| d.lang != lang);
+        }
+    }
+
+    fn answered_by(&mut self, config: &Config, lang: &str) -> bool {
+        let Some(command) = server_command(config, lang) else { return false };
+        self.servers.insert(lang.to_string(), command);
+        true
+    }
+}
+
+static SYMBOLS: Mutex<Option<Symbols>> = Mutex::new(None);
+static SAVE_PENDING: AtomicBool = AtomicBool::new(false);
+
+fn symbols_file() -> PathBuf {
+    crate::store::workspace_dir().join(SYMBOLS_FILE)
+}
+
+fn server_command(config: &Config, lang: &str) -> Option<String> {
+    let lang_conf = config.language.iter().find(|l| l.name == lang)?;
+    Some(lang_conf.lsp.as_ref()?.join(" "))
+}
+
+fn load() -> Result<Symbols> {
+    let data = match std::fs::read(symbols_file()) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Symbols::default()),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(serde_json::from_slice(&crate::store::unseal(SYMBOLS_FILE, data)?)?)
+}
+
+fn save() -> Result<()> {
+    let data = {
+        let symbols = SYMBOLS.lock();
+        let Some(symbols) = symbols.as_ref() else { return Ok(()) };
+        serde_json::to_vec(symbols)?
+    };
+    let path = symbols_file();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, crate::store::seal(SYMBOLS_FILE, &data)?)?;
+    std::fs::rename(&tmp, &path)?;
+    Ok(())
+}
+
+fn schedule_save() {
+    if SAVE_PENDING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    crate::pool::spawn(async {
+        tokio::time::sleep(SAVE_DELAY).await;
+        SAVE_PENDING.store(false, Ordering::SeqCst);
+        if let Err(e) = save() {
+            crate::output::write("lsp", &format!("Failed to save the symbol cache: {}", e));
+        }
+    });
+}
+
+fn with_symbols<T>(config: &Config, f: impl FnOnce(&mut Symbols) -> T) -> T {
+    let mut symbols = SYMBOLS.lock();
+    let symbols = symbols.get_or_insert_with(|| {
+        load().unwrap_or_else(|e| {
+            crate::output::write("lsp", &format!("Ignoring the symbol cache: {}", e));
+            Symbols::default()
+        })
+    });
+    symbols.retain_configured(config);
+    f(symbols)
+}
+
+/// Identity of a workspace symbol: name, uri and start
+pub fn key(symbol: &WorkspaceSymbol) -> (String, String, Option<Position>) {
+    let (uri, start) = match &symbol.location {
+        OneOf::Left(location) => (location.uri.as_str(), Some(location.range.start)),
+        OneOf::Right(location) => (location.uri.as_str(), None),
+    };
+    (symbol.name.clone(), uri.to_string(), start)
+}
+
+/// Merge `fresh` into `cached`: the fresh symbols first, then the cached
+/// ones they don't replace, up to `limit`
+fn merge(fresh: &[WorkspaceSymbol], cached: Vec<WorkspaceSymbol>, limit: usize) -> Vec<WorkspaceSymbol> {
+    let keys: HashSet<_> = fresh.iter().map(key).collect();
+    let mut merged = fresh.to_vec();
+    merged.extend(cached.into_iter().filter(|s| !keys.contains(&key(s))));
+    merged.truncate(limit);
+    merged
+}
+
+/// Cached workspace symbols matching `query`, of one language or all
+pub fn workspace_symbols(config: &Config, lang: Option<&str>, query: &str) -> Vec<WorkspaceSymbol> {
+    with_symbols(config, |symbols| {
+        symbols.workspace.iter()
+            .filter(|(l, _)| lang.is_none_or(|lang| lang == l.as_str()))
+            .flat_map(|(_, symbols)| symbols.iter())
+            .filter(|s| query.is_empty() || fuzzy_match(query, &s.name).is_some())
+            .cloned()
+            .collect()
+    })
+}
+
+/// Keep the workspace symbols a server of `lang` answered
+pub fn store_workspace(config: &Config, lang: &str, fresh: &[WorkspaceSymbol]) {
+    if fresh.is_empty() {
+        return;
+    }
+    with_symbols(config, |symbols| {
+        if !symbols.answered_by(config, lang) {
+            return;
+        }
+        let cached = symbols.workspace.remove(lang).unwrap_or_default();
+        symbols.workspace.insert(lang.to_string(), merge(fresh, cached, MAX_WORKSPACE_SYMBOLS));
+    });
+    schedule_save();
+}
+
+/// Cached outline of a document, with whether it was made for other
+/// content than `hash`
+pub fn document_symbols(config: &Config, path: &str, hash: &str) -> Option<(Vec<OutlineSymbol>, bool)> {
+    with_symbols(config, |symbols| {
+        let document = symbols.documents.get(path)?;
+        Some((document.symbols.clone(), document.hash != hash))
+    })
+}
+
+/// Keep the outline a server of `lang` answered for the content `hash`
+pub fn store_document(config: &Config, lang: &str, path: &str, hash: &str, outline: &[OutlineSymbol]) {
+    with_symbols(config, |symbols| {
+        if !symbols.answered_by(config, lang) {
+            return;
+        }
+        symbols.stores += 1;
+        let document = DocumentSymbols {
+            lang: lang.to_string(), hash: hash.to_string(), symbols: outline.to_vec(), stored: symbols.stores,
+        };
+        symbols.documents.insert(path.to_string(), document);
+        if symbols.documents.len() > MAX_DOCUMENTS {
+            let oldest = symbols.documents.iter().min_by_key(|(_, d)| d.stored).map(|(p, _)| p.clone());
+            if let Some(oldest) = oldest {
+                symbols.documents.remove(&oldest);
+            }
+        }
+    });
+    schedule_save();
+}
+
+/// Forget the symbols of a language, of all of them when None
+pub fn clear(config: &Config, lang: Option<&str>) {
+    with_symbols(config, |symbols| match lang {
+        Some(lang) => {
+            symbols.servers.remove(lang);
+            symbols.workspace.remove(lang);
+            symbols.documents.retain(|_, d| d.lang != lang);
+        }
+        None => *symbols = Symbols::default(),
+    });
+    schedule_save();
+}
+
+#[cfg(test)]
+mod symbol_cache_tests {
+    use super::*;
+    use crate::config::{IndentConfig, Language};
+    use serde_json::json;
+
+    fn symbol(name: &str, line: u32) -> WorkspaceSymbol {
+        serde_json::from_value(json!({
+            "name": name,
+            "kind": 12,
+            "location": {
+                "uri": "file:///src/lib.rs",
+                "range": { "start": { "line": line, "character": 0 }, "end": { "line": line, "character": 5 } }
+            }
+        })).unwrap()
+    }
+
+    fn config(command: &str) -> Config {
+        let mut config = Config::default();
+        config.language.push(Language {
+            name: "rust".to_string(),
+            types: vec!["rs".to_string()],
+            comment: "//".to_string(),
+            lsp: Some(vec![command.to_string()]),
+            indent: IndentConfig { width: 4, unit: " ".to_string() },
+            executable: None,
+            exec: None,
+            exectest: None,
+            repl: None,
+            alternates: Vec::new(),
+        });
+        config
+    }
+
+    #[test]
+    fn test_merge() {
+        let cached = vec![symbol("parse", 1), symbol("old", 5)];
+        let fresh = vec![symbol("parse", 1), symbol("new", 9)];
+
+        let merged = merge(&fresh, cached, 10);
+        let names: Vec<&str> = merged.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["parse", "new", "old"]);
+        assert_eq!(merge(&fresh, Vec::new(), 1).len(), 1);
+    }
+
+    #[test]
+    fn test_changed_server_drops_symbols() {
+        let mut symbols = Symbols::default();
+        let config = config("rust-analyzer");
+        assert!(symbols.answered_by(&config, "rust"));
+        assert!(!symbols.answered_by(&config, "cobol"));
+        symbols.workspace.insert("rust".to_string(), vec![symbol("parse", 1)]);
+
+        symbols.retain_configured(&config);
+        assert_eq!(symbols.workspace["rust"].len(), 1);
+
+        symbols.retain_configured(&self::config("ra-multiplex"));
+        assert!(symbols.workspace.is_empty());
+        assert!(symbols.servers.is_empty());
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+// Variables of the templates new files are created from, written as
+// `${name}`: `author` and `email` from the git config of the workspace,
+// `project` from its manifest (Cargo.toml, package.json, pyproject.toml
+// or go.mod, else the directory name), `year`, `date`, `file_name` and
+// `file_stem` of the new file, and `license_header`, the workspace's
+// .anycode/license-header.txt as line comments of the file's language.
+// Unknown variables are left as written.
+
+const LICENSE_HEADER_FILE: &str = "license-header.txt";
+
+/// Replace the `${name}` variables of a template
+pub fn render(template: &str, vars: &HashMap<String, String>) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        match after.find('}').and_then(|end| Some((vars.get(&after[..end])?, end))) {
+            Some((value, end)) => {
+                out.push_str(value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                out.push_str("${");
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Variables for a file created at `path` in the workspace `root`, whose
+/// language comments lines with `comment` (empty for plain text)
+pub async fn variables(root: &Path, path: &Path, comment: &str) -> HashMap<String, String> {
+    let now = chrono::Local::now();
+    let mut vars = HashMap::from([
+        ("year".to_string(), now.format("%Y").to_string()),
+        ("date".to_string(), now.format("%Y-%m-%d").to_string()),
+        ("project".to_string(), project_name(root)),
+    ]);
+    if let Some(name) = path.file_name() {
+        vars.insert("file_name".to_string(), name.to_string_lossy().to_string());
+    }
+    if let Some(stem) = path.file_stem() {
+        vars.insert("file_stem".to_string(), stem.to_string_lossy().to_string());
+    }
+    for (var, key) in [("author", "user.name"), ("email", "user.email")] {
+        if let Some(value) = git_config(root, key).await {
+            vars.insert(var.to_string(), value);
+        }
+    }
+
+    let header = std::fs::read_to_string(root.join(crate::store::WORKSPACE_DIR).join(LICENSE_HEADER_FILE))
+        .map(|text| comment_lines(&render(&text, &vars), comment))
+        .unwrap_or_default();
+    vars.insert("license_header".to_string(), header);
+    vars
+}
+
+async fn git_config(root: &Path, key: &str) -> Option<String> {
+    let output = tokio::process::Command::new("git")
+        .args(["config", "--get", key])
+        .current_dir(root)
+        .output()
+        .await
+        .ok()?;
+    let value = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (output.status.success() && !value.is_empty()).then_some(value)
+}
+
+/// Name of the project in `root`, from the first manifest that has one
+fn project_name(root: &Path) -> String {
+    let read = |name: &str| std::fs::read_to_string(root.join(name)).ok();
+    let toml_name = |text: String, tables: &[&[&str]]| {
+        let value: toml::Value = toml::from_str(&text).ok()?;
+        tables.iter().find_map(|table| {
+            let table = table.iter().try_fold(&value, |v, key| v.get(key))?;
+            table.get("name")?.as_str().map(str::to_string)
+        })
+    };
+
+    read("Cargo.toml").and_then(|text| toml_name(text, &[&["package"], &["workspace", "package"]]))
+        .or_else(|| {
+            let json: serde_json::Value = serde_json::from_str(&read("package.json")?).ok()?;
+            json.get("name")?.as_str().map(str::to_string)
+        })
+        .or_else(|| read("pyproject.toml").and_then(|text| toml_name(text, &[&["project"], &["tool", "poetry"]])))
+        .or_else(|| {
+            let go_mod = read("go.mod")?;
+            let module = go_mod.lines().find_map(|l| l.trim().strip_prefix("module "))?;
+            module.trim().rsplit('/').next().map(str::to_string)
+        })
+        .unwrap_or_else(|| {
+            root.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default()
+        })
+}
+
+/// Text as line comments, with a trailing newline. Without a comment
+/// token the text is kept as is.
+fn comment_lines(text: &str, comment: &str) -> String {
+    if comment.is_empty() {
+        return format!("{}\n", text.trim_end());
+    }
+    text.trim_end().lines()
+        .map(|line| match line.trim_end() {
+            "" => format!("{}\n", comment),
+            line => format!("{} {}\n", comment, line),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod template_tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let vars = HashMap::from([
+            ("author".to_string(), "Ada".to_string()),
+            ("year".to_string(), "2026".to_string()),
+        ]);
+        assert_eq!(render("// (c) ${year} ${author}\n", &vars), "// (c) 2026 Ada\n");
+        // Unknown and unterminated variables stay as written
+        assert_eq!(render("${unknown} ${author} ${year", &vars), "${unknown} Ada ${year");
+    }
+
+    #[test]
+    fn test_project_name() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("go.mod"), "module github.com/acme/tool\n\ngo 1.22\n")?;
+        assert_eq!(project_name(dir.path()), "tool");
+
+        std::fs::write(dir.path().join("package.json"), r#"{ "name": "web-app" }"#)?;
+        assert_eq!(project_name(dir.path()), "web-app");
+
+        std::fs::write(dir.path().join("Cargo.toml"), "[package]\nname = \"anycode\"\nversion = \"0.1.0\"\n")?;
+        assert_eq!(project_name(dir.path()), "anycode");
+
+        // A virtual workspace manifest has no name, the next manifest wins
+        std::fs::write(dir.path().join("Cargo.toml"), "[workspace]\nmembers = [\"a\"]\n")?;
+        assert_eq!(project_name(dir.path()), "web-app");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_license_header() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let state_dir = dir.path().join(crate::store::WORKSPACE_DIR);
+        std::fs::create_dir(&state_dir)?;
+        std::fs::write(state_dir.join(LICENSE_HEADER_FILE), "Copyright ${year} ${project}\n\nMIT License\n")?;
+
+        let vars = variables(dir.path(), &dir.path().join("src/lib.rs"), "//").await;
+        let project = project_name(dir.path());
+        assert_eq!(vars["license_header"], format!("// Copyright {} {}\n//\n// MIT License\n", vars["year"], project));
+        assert_eq!((vars["file_name"].as_str(), vars["file_stem"].as_str()), ("lib.rs", "lib"));
+        Ok(())
+    }
+}
@@ -3,12 +3,23 @@ use tokio::sync::mpsc;
 use std::io::{Read, Write};
 use anyhow::Result;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::config::{Config, TerminalProfile};
+
+const PASTE_START: &str = "\x1b[200~";
+const PASTE_END: &str = "\x1b[201~";
+/// Modes set by the program in the terminal, `?2004` is bracketed paste
+const BRACKETED_PASTE_ON: &str = "\x1b[?2004h";
+const BRACKETED_PASTE_OFF: &str = "\x1b[?2004l";
 
 pub struct Terminal {
     name: String,
     session_id: String,
-    pty_input_tx: mpsc::Sender<String>,
+    pty_input_tx: mpsc::Sender<Vec<u8>>,
     pty_resize_tx: mpsc::Sender<(u16, u16)>,
+    /// The program asked for pastes between markers
+    bracketed_paste: Arc<AtomicBool>,
     kill_tx: mpsc::Sender<()>,
 }
 
@@ -18,7 +29,7 @@ impl Terminal {
         session_id: String,
         rows: u16,
         cols: u16,
-        cmd: Option<String>,
+        profile: Option<TerminalProfile>,
         cwd: Option<PathBuf>,
         on_output_tx: mpsc::Sender<String>,
     ) -> anyhow::Result<Self> {
@@ -31,8 +42,17 @@ impl Terminal {
         };
 
         let pair = pty_system.openpty(pty_size)?;
-        let command_str = cmd.unwrap_or_else(Self::default_shell);
-        let mut cmd_builder = CommandBuilder::new(command_str);
+        let mut cmd_builder = match profile {
+            Some(profile) => {
+                let mut builder = CommandBuilder::new(profile.command);
+                builder.args(profile.args);
+                for (key, value) in profile.env {
+                    builder.env(key, value);
+                }
+                builder
+            }
+            None => CommandBuilder::new(Self::default_shell()),
+        };
 
         let working_dir = cwd.unwrap_or_else(|| Self::get_current_dir());
         cmd_builder.cwd(working_dir);
@@ -43,11 +63,12 @@ impl Terminal {
         let reader = pair.master.try_clone_reader()?;
 
         let (pty_output_tx, pty_output_rx) = mpsc::channel::<String>(32);
-        let (pty_input_tx, pty_input_rx) = mpsc::channel::<String>(32);
+        let (pty_input_tx, pty_input_rx) = mpsc::channel::<Vec<u8>>(32);
         let (pty_resize_tx, pty_resize_rx) = mpsc::channel::<(u16, u16)>(32);
         let (kill_tx, kill_rx) = mpsc::channel::<()>(1);
+        let bracketed_paste = Arc::new(AtomicBool::new(false));
 
-        Self::spawn_pty_reader(reader, pty_output_tx);
+        Self::spawn_pty_reader(reader, pty_output_tx, bracketed_paste.clone());
         Self::forward_output(pty_output_rx, on_output_tx);
         Self::spawn_terminal_task(child, writer, pair, pty_input_rx, pty_resize_rx, kill_rx);
 
@@ -56,11 +77,12 @@ impl Terminal {
             session_id,
             pty_input_tx,
             pty_resize_tx,
+            bracketed_paste,
             kill_tx,
         })
     }
 
-    fn default_shell() -> String {
+    pub fn default_shell() -> String {
         if cfg!(target_os = "windows") {
             return "cmd.exe".to_string();
         }
@@ -88,16 +110,21 @@ impl Terminal {
     fn spawn_pty_reader(
         mut reader: Box<dyn Read + Send>,
         pty_output_tx: mpsc::Sender<String>,
+        bracketed_paste: Arc<AtomicBool>,
     ) {
         tokio::task::spawn_blocking(move || {
             tracing::info!("PTY reader started");
             let mut buf = [0u8; 1024];
+            let mut decoder = OutputDecoder::default();
             loop {
                 match reader.read(&mut buf) {
                     Ok(0) => break,
                     Ok(n) => {
-                        let s = String::from_utf8_lossy(&buf[..n]).to_string();
-                        let _ = pty_output_tx.blocking_send(s);
+                        let s = decoder.decode(&buf[..n]);
+                        bracketed_paste.store(decoder.bracketed_paste, Ordering::Relaxed);
+                        if !s.is_empty() {
+                            let _ = pty_output_tx.blocking_send(s);
+                        }
                     }
                     Err(e) => {
                         tracing::warn!("PTY read error: {:?}", e);
@@ -121,18 +148,19 @@ impl Terminal {
     }
 
     fn spawn_terminal_task(
-        mut child: Box<dyn Child + Send>,
+        child: Box<dyn Child + Send>,
         mut writer: Box<dyn Write + Send>,
         pair: PtyPair,
-        mut input_rx: mpsc::Receiver<String>,
+        mut input_rx: mpsc::Receiver<Vec<u8>>,
         mut resize_rx: mpsc::Receiver<(u16, u16)>,
         mut kill_rx: mpsc::Receiver<()>,
     ) {
         tokio::spawn(async move {
+            let _shell = Shell(child);
             loop {
                 tokio::select! {
                     Some(input) = input_rx.recv() => {
-                        if let Err(e) = write!(writer, "{}", input) {
+                        if let Err(e) = writer.write_all(&input).and_then(|_| writer.flush()) {
                             tracing::error!("PTY write error: {:?}", e);
                         }
                     }
@@ -144,21 +172,25 @@ impl Terminal {
                             pixel_height: 0,
                         });
                     }
-                    Some(_) = kill_rx.recv() => {
-                        let _ = child.kill();
-                        break;
-                    }
+                    Some(_) = kill_rx.recv() => break,
                     else => break,
                 }
             }
         });
     }
 
-    pub async fn send_input(&self, input: String) -> Result<()> {
+    /// Write raw bytes to the PTY, as typed
+    pub async fn send_input(&self, input: Vec<u8>) -> Result<()> {
         self.pty_input_tx.send(input).await?;
         Ok(())
     }
 
+    /// Write pasted text, bracketed when the program enabled it
+    pub async fn paste(&self, text: &str) -> Result<()> {
+        let bracketed = self.bracketed_paste.load(Ordering::Relaxed);
+        self.send_input(paste_bytes(text, bracketed)).await
+    }
+
     pub async fn resize(&self, cols: u16, rows: u16) -> Result<()> {
         self.pty_resize_tx.send((cols, rows)).await?;
         Ok(())
@@ -170,6 +202,169 @@ impl Terminal {
     }
 }
 
+/// The program of a terminal, killed when its task ends, also when the
+/// runtime drops the task. The pty reader then reads the end of the output
+/// and stops instead of blocking the runtime shutdown.
+struct Shell(Box<dyn Child + Send>);
+
+impl Drop for Shell {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+    }
+}
+
+/// Bytes of a paste: between the bracketed paste markers when the program
+/// enabled them, line breaks as carriage returns like xterm sends. End
+/// markers in the text are dropped, they would end the paste early and
+/// run the rest as typed.
+fn paste_bytes(text: &str, bracketed: bool) -> Vec<u8> {
+    let text = text.replace(PASTE_END, "").replace("\r\n", "\r").replace('\n', "\r");
+    if bracketed {
+        format!("{}{}{}", PASTE_START, text, PASTE_END).into_bytes()
+    } else {
+        text.into_bytes()
+    }
+}
+
+/// Turns the PTY output into text. A UTF-8 sequence split between two
+/// reads is kept until its end arrives, and the bracketed paste mode
+/// switches are followed, even when split too.
+#[derive(Default)]
+struct OutputDecoder {
+    pending: Vec<u8>,
+    /// End of the previous output, where a mode switch may have started
+    tail: String,
+    bracketed_paste: bool,
+}
+
+impl OutputDecoder {
+    fn decode(&mut self, chunk: &[u8]) -> String {
+        let mut bytes = std::mem::take(&mut self.pending);
+        bytes.extend_from_slice(chunk);
+
+        let mut text = String::new();
+        let mut rest = &bytes[..];
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(valid) => {
+                    text.push_str(valid);
+                    break;
+                }
+                Err(e) => {
+                    let (valid, after) = rest.split_at(e.valid_up_to());
+                    text.push_str(std::str::from_utf8(valid).unwrap_or_default());
+                    match e.error_len() {
+                        Some(len) => {
+                            text.push(char::REPLACEMENT_CHARACTER);
+                            rest = &after[len..];
+                        }
+                        // Incomplete sequence at the end of the read
+                        None => {
+                            self.pending = after.to_vec();
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        self.track_modes(&text);
+        text
+    }
+
+    fn track_modes(&mut self, text: &str) {
+        let window = format!("{}{}", self.tail, text);
+        let on = window.rfind(BRACKETED_PASTE_ON);
+        let off = window.rfind(BRACKETED_PASTE_OFF);
+        match (on, off) {
+            (Some(on), Some(off)) => self.bracketed_paste = on > off,
+            (Some(_), None) => self.bracketed_paste = true,
+            (None, Some(_)) => self.bracketed_paste = false,
+            (None, None) => {}
+        }
+
+        let keep = BRACKETED_PASTE_ON.len() - 1;
+        let start = window.char_indices().rev().nth(keep - 1).map_or(0, |(i, _)| i);
+        self.tail = window[start..].to_string();
+    }
+}
+
+/// Profiles from the config plus the detected WSL distributions and
+/// the hosts of ~/.ssh/config
+pub fn available_profiles(config: &Config) -> Vec<TerminalProfile> {
+    let mut profiles = config.terminal.as_ref()
+        .map(|t| t.profiles.clone())
+        .unwrap_or_default();
+
+    let detected = wsl_profiles().into_iter().chain(ssh_profiles());
+    for profile in detected {
+        if !profiles.iter().any(|p| p.name == profile.name) {
+            profiles.push(profile);
+        }
+    }
+
+    profiles
+}
+
+fn wsl_profiles() -> Vec<TerminalProfile> {
+    if !cfg!(target_os = "windows") {
+        return Vec::new();
+    }
+
+    let output = match std::process::Command::new("wsl.exe").args(["-l", "-q"]).output() {
+        Ok(output) if output.status.success() => output.stdout,
+        _ => return Vec::new(),
+    };
+
+    // wsl.exe prints UTF-16LE
+    let utf16: Vec<u16> = output.chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+
+    String::from_utf16_lossy(&utf16)
+        .lines()
+        .map(|l| l.trim().trim_matches('\0'))
+        .filter(|l| !l.is_empty())
+        .map(|distro| TerminalProfile {
+            name: format!("WSL: {}", distro),
+            command: "wsl.exe".to_string(),
+            args: vec!["-d".to_string(), distro.to_string()],
+            env: Default::default(),
+        })
+        .collect()
+}
+
+fn ssh_profiles() -> Vec<TerminalProfile> {
+    let Some(path) = dirs::home_dir().map(|h| h.join(".ssh").join("config")) else {
+        return Vec::new();
+    };
+
+    std::fs::read_to_string(path)
+        .map(|content| ssh_config_hosts(&content))
+        .unwrap_or_default()
+        .into_iter()
+        .map(|host| TerminalProfile {
+            name: format!("ssh: {}", host),
+            command: "ssh".to_string(),
+            args: vec![host],
+            env: Default::default(),
+        })
+        .collect()
+}
+
+/// Host aliases of an ssh config, wildcard patterns are skipped
+fn ssh_config_hosts(content: &str) -> Vec<String> {
+    content.lines()
+        .map(str::trim)
+        .filter_map(|line| {
+            let (key, value) = line.split_once(char::is_whitespace)?;
+            key.eq_ignore_ascii_case("host").then_some(value)
+        })
+        .flat_map(str::split_whitespace)
+        .filter(|host| !host.contains(['*', '?', '!']))
+        .map(str::to_string)
+        .collect()
+}
 
 #[cfg(test)]
 mod tests {
@@ -185,12 +380,12 @@ mod tests {
             "test".to_string(),
             "session1".to_string(),
             30, 80,
-            Some("bash".to_string()),
+            Some(TerminalProfile::from_command_line("test", "bash")?),
             None,
             tx,
         ).await?;
 
-        terminal.send_input("echo test\n".to_string()).await?;
+        terminal.send_input(b"echo test\n".to_vec()).await?;
 
         let mut output = String::new();
         let _ = timeout(Duration::from_secs(2), async {
@@ -211,4 +406,32 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_output_decoder() {
+        let mut decoder = OutputDecoder::default();
+        // "é" is split between two reads
+        assert_eq!(decoder.decode(b"caf\xc3"), "caf");
+        assert_eq!(decoder.decode(b"\xa9 \xff!"), "é \u{fffd}!");
+
+        // Mode switches are seen across reads
+        decoder.decode(b"prompt \x1b[?20");
+        assert!(!decoder.bracketed_paste);
+        decoder.decode(b"04h$ ");
+        assert!(decoder.bracketed_paste);
+        decoder.decode(b"\x1b[?2004lvim\x1b[?2004h\x1b[?2004l");
+        assert!(!decoder.bracketed_paste);
+    }
+
+    #[test]
+    fn test_paste_bytes() {
+        assert_eq!(paste_bytes("ls\nrm -rf x\r\n", false), b"ls\rrm -rf x\r");
+        assert_eq!(paste_bytes("a\x1b[201~b\n", true), b"\x1b[200~ab\r\x1b[201~");
+    }
+
+    #[test]
+    fn test_ssh_config_hosts() {
+        let content = "Host dev staging\n    HostName 10.0.0.1\n\nHost *\n    User me\nhost gpu-box\n";
+        assert_eq!(ssh_config_hosts(content), ["dev", "staging", "gpu-box"]);
+    }
 }
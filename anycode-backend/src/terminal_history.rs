@@ -0,0 +1,321 @@
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use tracing::error;
+
+use crate::fuzzy::{fuzzy_match, FuzzyMatch};
+use crate::storage::{self, Storage};
+
+// Command lines run in the terminals, captured from the shell integration
+// markers in their output and kept per workspace and terminal profile in
+// the state storage, independent of the shell's own history file.
+// `OSC 633 ; E ; <command>` carries the command line itself, otherwise
+// the text echoed between `OSC 133 ; B` (end of the prompt) and
+// `OSC 133 ; C` (command starts) is taken. bash and zsh send the markers
+// through shell_integration.rs, other shells need their own setup.
+
+const NAMESPACE: &str = "terminal-history";
+/// Workspace state file of older versions, imported when the storage has
+/// no history for the workspace
+const LEGACY_FILE: &str = "terminal-history.json";
+/// Commands kept per profile, the oldest are dropped first
+const MAX_HISTORY: usize = 1000;
+
+const ESC: char = '\x1b';
+const BEL: char = '\x07';
+
+#[derive(Debug, Default)]
+enum ParseState {
+    #[default]
+    Text,
+    Escape,
+    Osc(String),
+    OscEscape(String),
+    Csi,
+}
+
+/// Finds command lines in a terminal output stream. Chunks may split
+/// escape sequences anywhere, the state carries over between them.
+#[derive(Debug, Default)]
+pub struct MarkerParser {
+    state: ParseState,
+    /// Text echoed since the end of the prompt
+    echo: Option<String>,
+    /// The command line came with 633;E, the echo is not needed
+    explicit: bool,
+}
+
+impl MarkerParser {
+    /// Feed an output chunk, returns the command lines it completed
+    pub fn feed(&mut self, chunk: &str) -> Vec<String> {
+        let mut commands = Vec::new();
+        for c in chunk.chars() {
+            self.state = match std::mem::take(&mut self.state) {
+                ParseState::Text if c == ESC => ParseState::Escape,
+                ParseState::Text => {
+                    self.echoed(c);
+                    ParseState::Text
+                }
+                ParseState::Escape => match c {
+                    ']' => ParseState::Osc(String::new()),
+                    '[' => ParseState::Csi,
+                    _ => ParseState::Text,
+                },
+                ParseState::Csi if ('\x40'..='\x7e').contains(&c) => ParseState::Text,
+                ParseState::Csi => ParseState::Csi,
+                ParseState::Osc(osc) if c == BEL => {
+                    commands.extend(self.marker(&osc));
+                    ParseState::Text
+                }
+                ParseState::Osc(osc) if c == ESC => ParseState::OscEscape(osc),
+                ParseState::Osc(mut osc) => {
+                    osc.push(c);
+                    ParseState::Osc(osc)
+                }
+                // ESC \ terminates the sequence, anything else aborts it
+                ParseState::OscEscape(osc) => {
+                    if c == '\\' {
+                        commands.extend(self.marker(&osc));
+                    }
+                    ParseState::Text
+                }
+            };
+        }
+        commands
+    }
+
+    fn echoed(&mut self, c: char) {
+        let Some(echo) = self.echo.as_mut() else { return };
+        match c {
+            '\x08' | '\x7f' => { echo.pop(); }
+            c if c.is_control() => {}
+            c => echo.push(c),
+        }
+    }
+
+    fn marker(&mut self, osc: &str) -> Option<String> {
+        let rest = osc.strip_prefix("133;").or_else(|| osc.strip_prefix("633;"))?;
+        let (code, args) = rest.split_once(';').unwrap_or((rest, ""));
+        match code {
+            "A" => {
+                self.echo = None;
+                self.explicit = false;
+                None
+            }
+            "B" => {
+                self.echo = Some(String::new());
+                self.explicit = false;
+                None
+            }
+            "E" => {
+                self.explicit = true;
+                self.echo = None;
+                // A nonce may follow the command line
+                let command = args.split(';').next().unwrap_or_default();
+                Some(unescape(command)).filter(|c| !c.trim().is_empty())
+            }
+            "C" => {
+                let echo = self.echo.take()?;
+                if self.explicit {
+                    return None;
+                }
+                Some(echo.trim().to_string()).filter(|c| !c.is_empty())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Undo the escaping of 633;E: `\\` and `\xAB` hex bytes, which may be
+/// the parts of a UTF-8 char
+fn unescape(text: &str) -> String {
+    let mut out = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+            continue;
+        }
+        match chars.peek() {
+            Some('\\') => {
+                chars.next();
+                out.push(b'\\');
+            }
+            Some('x') => {
+                chars.next();
+                let hex: String = chars.by_ref().take(2).collect();
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(byte) => out.push(byte),
+                    Err(_) => out.extend_from_slice(format!("\\x{}", hex).as_bytes()),
+                }
+            }
+            _ => out.push(b'\\'),
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub command: String,
+    pub terminal: String,
+    /// Unix time in seconds
+    pub at: i64,
+}
+
+/// Commands per profile, most recent first
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct History {
+    profiles: HashMap<String, VecDeque<HistoryEntry>>,
+}
+
+impl History {
+    fn add(&mut self, profile: &str, entry: HistoryEntry) {
+        let entries = self.profiles.entry(profile.to_string()).or_default();
+        entries.retain(|e| e.command != entry.command);
+        entries.push_front(entry);
+        entries.truncate(MAX_HISTORY);
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct HistoryMatch {
+    pub profile: String,
+    #[serde(flatten)]
+    pub entry: HistoryEntry,
+    #[serde(flatten)]
+    pub matched: FuzzyMatch,
+}
+
+static HISTORY: Mutex<Option<History>> = Mutex::new(None);
+
+/// Storage key of the current workspace's history
+fn workspace_key() -> String {
+    std::env::current_dir().unwrap_or_default().to_string_lossy().to_string()
+}
+
+fn legacy_file() -> PathBuf {
+    crate::store::workspace_dir().join(LEGACY_FILE)
+}
+
+fn load(storage: &dyn Storage, key: &str, legacy: &Path) -> History {
+    match storage.get_json(NAMESPACE, key) {
+        Ok(Some(history)) => history,
+        Ok(None) => {
+            let history: History = std::fs::read_to_string(legacy).ok()
+                .and_then(|text| serde_json::from_str(&text).ok())
+                .unwrap_or_default();
+            if !history.profiles.is_empty() && let Err(e) = storage.put_json(NAMESPACE, key, &history) {
+                error!("Failed to import {}: {}", legacy.display(), e);
+            }
+            history
+        }
+        Err(e) => {
+            error!("Failed to load terminal history: {}", e);
+            History::default()
+        }
+    }
+}
+
+/// Add a command line run in a terminal of the profile and persist it
+pub fn record(profile: &str, terminal: &str, command: &str) {
+    let (storage, key) = (storage::get(), workspace_key());
+    let mut history = HISTORY.lock();
+    let history = history.get_or_insert_with(|| load(storage, &key, &legacy_file()));
+    let entry = HistoryEntry {
+        command: command.to_string(),
+        terminal: terminal.to_string(),
+        at: chrono::Utc::now().timestamp(),
+    };
+
+    // Merged into what other backends of the workspace stored meanwhile
+    match storage.update_json(NAMESPACE, &key, |stored: &mut History| stored.add(profile, entry.clone())) {
+        Ok(stored) => *history = stored,
+        Err(e) => {
+            error!("Failed to save terminal history: {}", e);
+            history.add(profile, entry);
+        }
+    }
+}
+
+/// Commands matching the query, best first, the most recent first for an
+/// empty query. Searches all profiles unless one is given.
+pub fn search(profile: Option<&str>, query: &str, limit: usize) -> Vec<HistoryMatch> {
+    let mut history = HISTORY.lock();
+    let history = history.get_or_insert_with(|| load(storage::get(), &workspace_key(), &legacy_file()));
+    search_in(history, profile, query, limit)
+}
+
+fn search_in(history: &History, profile: Option<&str>, query: &str, limit: usize) -> Vec<HistoryMatch> {
+    let mut matches: Vec<HistoryMatch> = history.profiles.iter()
+        .filter(|(name, _)| profile.is_none_or(|p| p == name.as_str()))
+        .flat_map(|(name, entries)| entries.iter().filter_map(|entry| {
+            let matched = fuzzy_match(query, &entry.command)?;
+            Some(HistoryMatch { profile: name.clone(), entry: entry.clone(), matched })
+        }))
+        .collect();
+
+    matches.sort_by(|a, b| b.matched.score.cmp(&a.matched.score).then(b.entry.at.cmp(&a.entry.at)));
+    matches.truncate(limit);
+    matches
+}
+
+#[cfg(test)]
+mod terminal_history_tests {
+    use super::*;
+
+    #[test]
+    fn test_markers_split_across_chunks() {
+        let mut parser = MarkerParser::default();
+        let mut commands = Vec::new();
+
+        // 133: the command is the echo between the prompt end and the start
+        for chunk in ["\x1b]133;A\x07$ \x1b]13", "3;B\x07cargo bv\x08uild\x1b[0m", "\r\n\x1b]133;C\x07out\n"] {
+            commands.extend(parser.feed(chunk));
+        }
+        // 633;E wins over the echo, with ST as the terminator
+        for chunk in ["\x1b]633;B\x1b\\ls -la", "\x1b]633;E;echo a\\x3bb\\\\c\x1b\\\x1b]633;C\x07"] {
+            commands.extend(parser.feed(chunk));
+        }
+        // An empty command line is not history
+        commands.extend(parser.feed("\x1b]133;B\x07  \r\n\x1b]133;C\x07"));
+
+        assert_eq!(commands, ["cargo build", "echo a;b\\c"]);
+    }
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape("echo caf\\xc3\\xa9 \\\\ \\xzz"), "echo café \\ \\xzz");
+        assert_eq!(unescape("grep é"), "grep é");
+    }
+
+    #[test]
+    fn test_history_search() -> anyhow::Result<()> {
+        let entry = |command: &str, at| HistoryEntry { command: command.to_string(), terminal: "t".to_string(), at };
+        let mut history = History::default();
+        history.add("bash", entry("cargo test", 1));
+        history.add("bash", entry("git status", 2));
+        history.add("bash", entry("cargo test", 3));
+        history.add("ssh", entry("cargo build", 4));
+
+        assert_eq!(history.profiles["bash"].len(), 2);
+
+        let all: Vec<String> = search_in(&history, None, "", 10).into_iter().map(|m| m.entry.command).collect();
+        assert_eq!(all, ["cargo build", "cargo test", "git status"]);
+
+        let bash: Vec<String> = search_in(&history, Some("bash"), "cgt", 10).into_iter().map(|m| m.entry.command).collect();
+        assert_eq!(bash, ["cargo test"]);
+
+        // The history file of older versions is imported once
+        let dir = tempfile::tempdir()?;
+        let legacy = dir.path().join(LEGACY_FILE);
+        std::fs::write(&legacy, serde_json::to_string(&history)?)?;
+        let storage: &dyn Storage = &storage::MemoryStorage::default();
+        assert_eq!(load(storage, "/w", &legacy).profiles["ssh"][0].command, "cargo build");
+        std::fs::remove_file(&legacy)?;
+        assert_eq!(load(storage, "/w", &legacy).profiles["bash"].len(), 2);
+        assert!(load(storage, "/other", &legacy).profiles.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,108 @@
+use parking_lot::Mutex;
+use socketioxide::extract::SocketRef;
+
+// Read-only sharing of terminals with other sessions, for pair debugging.
+// `terminal:share` gives the owner a share id, sockets joining with it get
+// the output as `terminal:shared:<share id>` from then on. Guests can't
+// type into or resize the terminal, only the socket it is attached to
+// does (see `TerminalData::is_attached`), and lose it when the owner
+// revokes the share or closes the terminal.
+
+struct Share {
+    id: String,
+    /// Key of the terminal in the app state, `<session>-<name>`
+    terminal: String,
+    name: String,
+    guests: Vec<SocketRef>,
+}
+
+static SHARES: Mutex<Vec<Share>> = Mutex::new(Vec::new());
+
+fn new_id() -> String {
+    crate::crypt::generate_salt().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Share a terminal, the id of its share if it already has one
+pub fn share(terminal: &str, name: &str) -> String {
+    let mut shares = SHARES.lock();
+    if let Some(share) = shares.iter().find(|s| s.terminal == terminal) {
+        return share.id.clone();
+    }
+    let id = new_id();
+    shares.push(Share { id: id.clone(), terminal: terminal.to_string(), name: name.to_string(), guests: Vec::new() });
+    id
+}
+
+/// Add a guest to a share, the terminal key and name, None for an
+/// unknown or revoked share
+pub fn join(id: &str, guest: SocketRef) -> Option<(String, String)> {
+    let mut shares = SHARES.lock();
+    let share = shares.iter_mut().find(|s| s.id == id)?;
+    if !share.guests.iter().any(|g| g.id == guest.id) {
+        share.guests.push(guest);
+    }
+    Some((share.terminal.clone(), share.name.clone()))
+}
+
+/// Stop following a share, false if the socket was not a guest
+pub fn leave(id: &str, socket_id: &str) -> bool {
+    let mut shares = SHARES.lock();
+    let Some(share) = shares.iter_mut().find(|s| s.id == id) else { return false };
+    let count = share.guests.len();
+    share.guests.retain(|g| g.id.as_str() != socket_id);
+    share.guests.len() != count
+}
+
+/// Drop the guests of a disconnected socket
+pub fn leave_socket(socket_id: &str) {
+    for share in SHARES.lock().iter_mut() {
+        share.guests.retain(|g| g.id.as_str() != socket_id);
+    }
+}
+
+/// End the share of a terminal, its id and the guests to tell
+pub fn revoke(terminal: &str) -> Option<(String, Vec<SocketRef>)> {
+    let mut shares = SHARES.lock();
+    let index = shares.iter().position(|s| s.terminal == terminal)?;
+    let share = shares.remove(index);
+    Some((share.id, share.guests))
+}
+
+/// The socket follows the terminal as a guest, its input is refused
+pub fn is_guest(terminal: &str, socket_id: &str) -> bool {
+    SHARES.lock().iter()
+        .filter(|s| s.terminal == terminal)
+        .any(|s| s.guests.iter().any(|g| g.id.as_str() == socket_id))
+}
+
+/// Send output of a terminal to the guests of its share
+pub fn forward(terminal: &str, output: &str) {
+    let shares = SHARES.lock();
+    let Some(share) = shares.iter().find(|s| s.terminal == terminal) else { return };
+    let channel = format!("terminal:shared:{}", share.id);
+    for guest in share.guests.iter().filter(|g| g.connected()) {
+        let _ = guest.emit(&channel, &output);
+    }
+}
+
+#[cfg(test)]
+mod terminal_share_tests {
+    use super::*;
+
+    #[test]
+    fn test_share_and_revoke() {
+        let id = share("s1-share-test", "share-test");
+        assert_eq!(id.len(), 32);
+        assert_eq!(share("s1-share-test", "share-test"), id);
+        assert_ne!(share("s1-share-other", "share-other"), id);
+
+        assert!(!leave(&id, "no-such-socket"));
+        assert!(!is_guest("s1-share-test", "no-such-socket"));
+
+        let (revoked, guests) = revoke("s1-share-test").unwrap();
+        assert_eq!(revoked, id);
+        assert!(guests.is_empty());
+        assert!(revoke("s1-share-test").is_none());
+        assert!(revoke("s1-share-other").is_some());
+    }
+}
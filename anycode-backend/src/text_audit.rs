@@ -0,0 +1,394 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::config::SaveConfig;
+use crate::services::{Edit, Operation};
+use crate::utils::relative_to_current_dir;
+
+// Audit of the text format of the workspace files before a formatting
+// policy is enforced: mixed line endings, byte order marks, encodings
+// other than UTF-8 and trailing whitespace. Fixes are computed as edits
+// of the open buffers and go through the batch edit api, files that are
+// not UTF-8 are only reported.
+//
+// The save hooks of the config (trailing whitespace, final newlines) are
+// computed the same way, as a list the client can preview before the
+// save applies them.
+
+/// Larger files are skipped, they are not hand-edited sources
+const MAX_AUDIT_SIZE: u64 = 16 * 1024 * 1024;
+const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    Crlf,
+    Cr,
+}
+
+impl LineEnding {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Default, PartialEq)]
+pub struct LineEndings {
+    pub lf: usize,
+    pub crlf: usize,
+    pub cr: usize,
+}
+
+impl LineEndings {
+    pub fn mixed(&self) -> bool {
+        [self.lf, self.crlf, self.cr].iter().filter(|n| **n > 0).count() > 1
+    }
+
+    /// The most used one, LF on ties and for files without line breaks
+    pub fn dominant(&self) -> LineEnding {
+        if self.crlf > self.lf && self.crlf >= self.cr {
+            LineEnding::Crlf
+        } else if self.cr > self.lf && self.cr > self.crlf {
+            LineEnding::Cr
+        } else {
+            LineEnding::Lf
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct TextFormatReport {
+    pub path: String,
+    /// utf-8, utf-16le, utf-16be or unknown
+    pub encoding: &'static str,
+    pub bom: bool,
+    pub line_endings: LineEndings,
+    pub mixed_line_endings: bool,
+    /// Lines ending in spaces or tabs
+    pub trailing_whitespace: usize,
+}
+
+impl TextFormatReport {
+    fn has_issues(&self) -> bool {
+        self.encoding != "utf-8" || self.bom || self.mixed_line_endings || self.trailing_whitespace > 0
+    }
+}
+
+fn count_line_endings(text: &str) -> LineEndings {
+    let mut endings = LineEndings::default();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\r' if chars.peek() == Some(&'\n') => {
+                chars.next();
+                endings.crlf += 1;
+            }
+            '\r' => endings.cr += 1,
+            '\n' => endings.lf += 1,
+            _ => {}
+        }
+    }
+    endings
+}
+
+/// Lines with trailing spaces or tabs, any line ending
+fn trailing_whitespace_lines(text: &str) -> usize {
+    text.split(['\n', '\r'])
+        .filter(|line| line.ends_with([' ', '\t']))
+        .count()
+}
+
+/// Report of a file's content, None for binary content
+pub fn analyze(path: &str, bytes: &[u8]) -> Option<TextFormatReport> {
+    let utf16 = match bytes {
+        [0xff, 0xfe, ..] => Some("utf-16le"),
+        [0xfe, 0xff, ..] => Some("utf-16be"),
+        _ => None,
+    };
+    if let Some(encoding) = utf16 {
+        return Some(TextFormatReport {
+            path: path.to_string(),
+            encoding,
+            bom: true,
+            line_endings: LineEndings::default(),
+            mixed_line_endings: false,
+            trailing_whitespace: 0,
+        });
+    }
+    if bytes.iter().take(8192).any(|b| *b == 0) {
+        return None;
+    }
+
+    let bom = bytes.starts_with(UTF8_BOM);
+    let Ok(text) = std::str::from_utf8(bytes) else {
+        return Some(TextFormatReport {
+            path: path.to_string(),
+            encoding: "unknown",
+            bom,
+            line_endings: LineEndings::default(),
+            mixed_line_endings: false,
+            trailing_whitespace: 0,
+        });
+    };
+
+    let line_endings = count_line_endings(text);
+    Some(TextFormatReport {
+        path: path.to_string(),
+        encoding: "utf-8",
+        bom,
+        mixed_line_endings: line_endings.mixed(),
+        line_endings,
+        trailing_whitespace: trailing_whitespace_lines(text),
+    })
+}
+
+/// Files with format issues among the given ones. `progress` gets the
+/// scanned and total counts.
+pub fn audit(files: Vec<PathBuf>, mut progress: impl FnMut(usize, usize)) -> Vec<TextFormatReport> {
+    let total = files.len();
+    let mut reports = Vec::new();
+    for (i, file) in files.iter().enumerate() {
+        progress(i + 1, total);
+        if std::fs::metadata(file).map_or(true, |m| m.len() > MAX_AUDIT_SIZE) {
+            continue;
+        }
+        let Ok(bytes) = std::fs::read(file) else { continue };
+        let path = relative_to_current_dir(file)
+            .unwrap_or_else(|| file.to_path_buf())
+            .to_string_lossy()
+            .to_string();
+        if let Some(report) = analyze(&path, &bytes) && report.has_issues() {
+            reports.push(report);
+        }
+    }
+    reports
+}
+
+/// Fixes to apply, line endings are converted to `line_ending` or to the
+/// dominant one of each file
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct TextFormatFixes {
+    #[serde(default)]
+    pub line_endings: bool,
+    pub line_ending: Option<LineEnding>,
+    #[serde(default)]
+    pub bom: bool,
+    #[serde(default)]
+    pub trailing_whitespace: bool,
+}
+
+/// Edits fixing a text, at UTF-16 offsets and ordered from the end of the
+/// document so every offset stays valid while the others are applied
+pub fn fix_edits(text: &str, fixes: &TextFormatFixes) -> Vec<Edit> {
+    let target = fixes.line_ending.unwrap_or_else(|| count_line_endings(text).dominant());
+    // (start, removed, inserted) in document order
+    let mut replacements: Vec<(usize, String, &str)> = Vec::new();
+
+    let mut offset = 0;
+    let mut rest = text;
+    if fixes.bom && let Some(stripped) = rest.strip_prefix('\u{feff}') {
+        replacements.push((0, "\u{feff}".to_string(), ""));
+        offset += 1;
+        rest = stripped;
+    }
+
+    for line in lines(rest, offset) {
+        let content = line.text.trim_end_matches([' ', '\t']);
+        if fixes.trailing_whitespace && content.len() < line.text.len() {
+            let start = line.offset + content.encode_utf16().count();
+            replacements.push((start, line.text[content.len()..].to_string(), ""));
+        }
+        if fixes.line_endings && !line.ending.is_empty() && line.ending != target.as_str() {
+            replacements.push((line.end(), line.ending.to_string(), target.as_str()));
+        }
+    }
+
+    to_edits(replacements.into_iter().map(|(start, removed, inserted)| (start, removed, inserted.to_string())))
+}
+
+struct Line<'a> {
+    /// UTF-16 offset of the start of the line
+    offset: usize,
+    text: &'a str,
+    /// "\n", "\r\n", "\r" or "" for the last line
+    ending: &'a str,
+}
+
+impl Line<'_> {
+    /// UTF-16 offset of the line ending
+    fn end(&self) -> usize {
+        self.offset + self.text.encode_utf16().count()
+    }
+}
+
+/// Lines of `text` with any line ending, offsets starting at `offset`
+fn lines(text: &str, mut offset: usize) -> Vec<Line<'_>> {
+    let mut lines = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let end = rest.find(['\n', '\r']).unwrap_or(rest.len());
+        let ending = match &rest[end..] {
+            e if e.starts_with("\r\n") => "\r\n",
+            e if e.starts_with('\r') => "\r",
+            e if e.starts_with('\n') => "\n",
+            _ => "",
+        };
+        let line = Line { offset, text: &rest[..end], ending };
+        offset = line.end() + ending.len();
+        lines.push(line);
+        rest = &rest[end + ending.len()..];
+    }
+    lines
+}
+
+/// Edits of (start, removed, inserted) replacements in document order,
+/// ordered from the end of the document
+fn to_edits(replacements: impl DoubleEndedIterator<Item = (usize, String, String)>) -> Vec<Edit> {
+    replacements.rev()
+        .flat_map(|(start, removed, inserted)| {
+            let remove = (!removed.is_empty())
+                .then_some(Edit { operation: Operation::Remove, start, text: removed });
+            let insert = (!inserted.is_empty())
+                .then_some(Edit { operation: Operation::Insert, start, text: inserted });
+            remove.into_iter().chain(insert)
+        })
+        .collect()
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NormalizationKind {
+    TrailingWhitespace,
+    MissingFinalNewline,
+    ExtraFinalNewlines,
+}
+
+/// One edit a save would make, `removed` is replaced by `inserted` at the
+/// UTF-16 offset `start`
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Normalization {
+    pub kind: NormalizationKind,
+    /// Zero-based line of the edit
+    pub line: usize,
+    pub start: usize,
+    pub removed: String,
+    pub inserted: String,
+}
+
+/// Edits the save hooks would make to `text`, in document order
+pub fn normalizations(text: &str, config: &SaveConfig) -> Vec<Normalization> {
+    let lines = lines(text, 0);
+    // Lines after the last one with content are trimmed as a whole
+    let last = lines.iter().rposition(|l| !l.text.trim().is_empty());
+    let trim_tail = config.trim_final_newlines.unwrap_or(false) && last.is_some();
+    let kept = match (trim_tail, last) {
+        (true, Some(last)) => last + 1,
+        _ => lines.len(),
+    };
+
+    let mut normalizations = Vec::new();
+    if config.trim_trailing_whitespace.unwrap_or(false) {
+        for (i, line) in lines[..kept].iter().enumerate() {
+            let content = line.text.trim_end_matches([' ', '\t']);
+            if content.len() < line.text.len() {
+                normalizations.push(Normalization {
+                    kind: NormalizationKind::TrailingWhitespace,
+                    line: i,
+                    start: line.offset + content.encode_utf16().count(),
+                    removed: line.text[content.len()..].to_string(),
+                    inserted: String::new(),
+                });
+            }
+        }
+    }
+    if trim_tail && kept < lines.len() {
+        let removed: String = lines[kept..].iter().flat_map(|l| [l.text, l.ending]).collect();
+        normalizations.push(Normalization {
+            kind: NormalizationKind::ExtraFinalNewlines,
+            line: kept,
+            start: lines[kept].offset,
+            removed,
+            inserted: String::new(),
+        });
+    }
+    if config.insert_final_newline.unwrap_or(false) && let Some(line) = lines[..kept].last()
+        && line.ending.is_empty()
+    {
+        normalizations.push(Normalization {
+            kind: NormalizationKind::MissingFinalNewline,
+            line: kept - 1,
+            start: line.end(),
+            removed: String::new(),
+            inserted: count_line_endings(text).dominant().as_str().to_string(),
+        });
+    }
+    normalizations
+}
+
+/// Edits applying `normalizations`, for the batch edit api
+pub fn normalization_edits(normalizations: &[Normalization]) -> Vec<Edit> {
+    to_edits(normalizations.iter().map(|n| (n.start, n.removed.clone(), n.inserted.clone())))
+}
+
+#[cfg(test)]
+mod text_audit_tests {
+    use super::*;
+    use crate::fixtures::apply_edits;
+
+    #[test]
+    fn test_analyze() {
+        let report = analyze("a.txt", "\u{feff}one \r\ntwo\nthree\t\rfour".as_bytes()).unwrap();
+        assert_eq!((report.encoding, report.bom), ("utf-8", true));
+        assert_eq!(report.line_endings, LineEndings { lf: 1, crlf: 1, cr: 1 });
+        assert!(report.mixed_line_endings);
+        assert_eq!(report.trailing_whitespace, 2);
+
+        let clean = analyze("b.txt", b"one\r\ntwo\r\n").unwrap();
+        assert!(!clean.has_issues());
+        assert_eq!(clean.line_endings.dominant(), LineEnding::Crlf);
+
+        assert_eq!(analyze("c.txt", b"caf\xe9").unwrap().encoding, "unknown");
+        assert_eq!(analyze("d.txt", b"\xff\xfea\x00").unwrap().encoding, "utf-16le");
+        assert!(analyze("e.bin", b"\x7fELF\x00\x01").is_none());
+    }
+
+    #[test]
+    fn test_fix_edits() {
+        let text = "\u{feff}😀 a  \r\nb\t\nc\r\n";
+        let all = TextFormatFixes { line_endings: true, line_ending: None, bom: true, trailing_whitespace: true };
+        assert_eq!(apply_edits(text, &fix_edits(text, &all)), "😀 a\r\nb\r\nc\r\n");
+
+        let lf = TextFormatFixes { line_endings: true, line_ending: Some(LineEnding::Lf), ..Default::default() };
+        assert_eq!(apply_edits(text, &fix_edits(text, &lf)), "\u{feff}😀 a  \nb\t\nc\n");
+
+        let whitespace = TextFormatFixes { trailing_whitespace: true, ..Default::default() };
+        assert_eq!(apply_edits("x \ry\t", &fix_edits("x \ry\t", &whitespace)), "x\ry");
+
+        assert!(fix_edits("clean\n", &all).is_empty());
+    }
+
+    #[test]
+    fn test_normalizations() {
+        let all = SaveConfig {
+            trim_trailing_whitespace: Some(true), insert_final_newline: Some(true), trim_final_newlines: Some(true),
+        };
+        let text = "😀 a  \r\nb\r\n  \r\n\r\n";
+        let found = normalizations(text, &all);
+        let kinds: Vec<_> = found.iter().map(|n| (n.kind, n.line)).collect();
+        assert_eq!(kinds, [(NormalizationKind::TrailingWhitespace, 0), (NormalizationKind::ExtraFinalNewlines, 2)]);
+        assert_eq!(apply_edits(text, &normalization_edits(&found)), "😀 a\r\nb\r\n");
+
+        let missing = normalizations("one\r\ntwo", &all);
+        assert_eq!(missing[0].kind, NormalizationKind::MissingFinalNewline);
+        assert_eq!((missing[0].line, missing[0].inserted.as_str()), (1, "\r\n"));
+
+        assert!(normalizations("clean\n", &all).is_empty());
+        assert!(normalizations("a  \n\n", &SaveConfig::default()).is_empty());
+    }
+}
@@ -0,0 +1,162 @@
+use serde::Serialize;
+use serde_json::Value;
+use socketioxide::socket::Sid;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, Mutex, MutexGuard};
+use tracing::warn;
+
+const DEFAULT_SLOW_EVENT_MS: u64 = 200;
+
+#[derive(Debug, Serialize, Clone)]
+pub struct LockWait {
+    pub lock: &'static str,
+    pub waited_ms: u128,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct SlowEvent {
+    pub event: &'static str,
+    pub elapsed_ms: u128,
+    pub locks: Vec<LockWait>,
+}
+
+struct Settings {
+    threshold: Duration,
+    sender: Option<mpsc::Sender<SlowEvent>>,
+}
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+/// Configure the slow event threshold and the channel slow events are reported to.
+pub fn init(threshold_ms: Option<u64>, sender: mpsc::Sender<SlowEvent>) {
+    let threshold = Duration::from_millis(threshold_ms.unwrap_or(DEFAULT_SLOW_EVENT_MS));
+    let _ = SETTINGS.set(Settings { threshold, sender: Some(sender) });
+}
+
+fn settings() -> &'static Settings {
+    SETTINGS.get_or_init(|| Settings {
+        threshold: Duration::from_millis(DEFAULT_SLOW_EVENT_MS),
+        sender: None,
+    })
+}
+
+/// Measures a single socket event. Locks taken through `lock` are timed too,
+/// so a slow event report says which shared state it was waiting on. A
+/// panic of the handler holding it is reported as a crash. While profiling
+/// the event and its lock waits are spans of the profile.
+pub struct EventTimer {
+    event: &'static str,
+    start: Instant,
+    locks: Vec<LockWait>,
+    /// Socket the event came from, told about a crash of its handler
+    socket: Option<Sid>,
+    /// Payload for the crash report, serialized only when there is one
+    payload: Option<Box<dyn FnOnce() -> Option<Value> + Send + Sync>>,
+    span: tracing::Span,
+}
+
+impl EventTimer {
+    pub fn start(event: &'static str) -> Self {
+        let span = crate::profile::span("handler", event);
+        Self { event, start: Instant::now(), locks: Vec::new(), socket: None, payload: None, span }
+    }
+
+    /// The socket that sent the event
+    pub fn with_socket(mut self, socket: Sid) -> Self {
+        self.socket = Some(socket);
+        self
+    }
+
+    /// Keep the event payload for a crash report, where it shows up
+    /// without file contents
+    pub fn with_payload<T: Serialize + Clone + Send + Sync + 'static>(mut self, payload: &T) -> Self {
+        let payload = payload.clone();
+        self.payload = Some(Box::new(move || serde_json::to_value(payload).ok().map(crate::crash::redact)));
+        self
+    }
+
+    pub async fn lock<'a, T>(&mut self, name: &'static str, mutex: &'a Mutex<T>) -> MutexGuard<'a, T> {
+        let started = Instant::now();
+        let wait = crate::profile::child(&self.span, "lock", name);
+        let guard = mutex.lock().await;
+        drop(wait);
+        self.locks.push(LockWait { lock: name, waited_ms: started.elapsed().as_millis() });
+        guard
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+impl Drop for EventTimer {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            let payload = self.payload.take().and_then(|payload| payload());
+            crate::crash::report(self.event, self.socket, payload);
+        }
+
+        let settings = settings();
+        let elapsed = self.elapsed();
+        if elapsed < settings.threshold {
+            return;
+        }
+
+        let event = SlowEvent {
+            event: self.event,
+            elapsed_ms: elapsed.as_millis(),
+            locks: std::mem::take(&mut self.locks),
+        };
+
+        warn!("Slow event {} took {}ms, locks: {:?}", event.event, event.elapsed_ms, event.locks);
+
+        if let Some(sender) = &settings.sender {
+            let _ = sender.try_send(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_lock_waits_are_recorded() {
+        let mutex = Mutex::new(1);
+        let mut timer = EventTimer::start("test:event");
+
+        {
+            let guard = timer.lock("counter", &mutex).await;
+            assert_eq!(*guard, 1);
+        }
+
+        assert_eq!(timer.locks.len(), 1);
+        assert_eq!(timer.locks[0].lock, "counter");
+    }
+
+    #[test]
+    fn test_payload_is_serialized_on_crash_only() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static SERIALIZED: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(Clone)]
+        struct Request;
+        impl Serialize for Request {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                SERIALIZED.fetch_add(1, Ordering::Relaxed);
+                serializer.serialize_unit()
+            }
+        }
+
+        drop(EventTimer::start("test:event").with_payload(&Request));
+        assert_eq!(SERIALIZED.load(Ordering::Relaxed), 0);
+
+        let crashed = std::panic::catch_unwind(|| {
+            let _timer = EventTimer::start("test:event").with_payload(&Request);
+            panic!("handler bug");
+        });
+        assert!(crashed.is_err());
+        assert_eq!(SERIALIZED.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,123 @@
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::code::{Change, Code, Operation};
+use crate::storage;
+
+// Undo and redo histories of the buffers in the session storage, so they
+// outlive the backend. They are stored on save and when a client
+// disconnects, with the hash of the text they lead to, and restored when
+// the file is loaded again with that same text. A history of a buffer
+// that was not saved, or of a file changed since, is not restored.
+
+const NAMESPACE: &str = "undo";
+/// Changes kept of each history, the oldest groups are dropped first
+const MAX_CHANGES: usize = 1000;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct History {
+    hash: String,
+    undo: Vec<Change>,
+    redo: Vec<Change>,
+}
+
+fn text_hash(code: &Code) -> String {
+    let mut hasher = blake3::Hasher::new();
+    for chunk in code.text.chunks() {
+        hasher.update(chunk.as_bytes());
+    }
+    hasher.finalize().to_hex().to_string()
+}
+
+/// The newest `max` changes of `changes`, more when a group of changes
+/// would be cut
+fn newest(changes: &[Change], max: usize) -> Vec<Change> {
+    let cut = changes.len().saturating_sub(max);
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (i, change) in changes.iter().enumerate().take(cut + 1) {
+        if depth == 0 {
+            start = i;
+        }
+        match change.operation {
+            Operation::Start => depth += 1,
+            Operation::End => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    changes[start..].to_vec()
+}
+
+/// History of a buffer, None when it has none
+pub fn snapshot(code: &Code) -> Option<History> {
+    if code.undo_history.is_empty() && code.redo_history.is_empty() {
+        return None;
+    }
+    Some(History {
+        hash: text_hash(code),
+        undo: newest(&code.undo_history, MAX_CHANGES),
+        redo: newest(&code.redo_history, MAX_CHANGES),
+    })
+}
+
+pub fn store(path: &str, history: &History) {
+    if let Err(e) = storage::get().put_json(NAMESPACE, path, history) {
+        error!("Failed to store the undo history of {}: {}", path, e);
+    }
+}
+
+/// Give a freshly loaded buffer its stored history, if it was stored for
+/// the same text
+pub fn restore(code: &mut Code) {
+    let history: History = match storage::get().get_json(NAMESPACE, &code.abs_path) {
+        Ok(Some(history)) => history,
+        Ok(None) => return,
+        Err(e) => return error!("Failed to load the undo history of {}: {}", code.abs_path, e),
+    };
+    if history.hash == text_hash(code) {
+        code.undo_history = history.undo;
+        code.redo_history = history.redo;
+    }
+}
+
+#[cfg(test)]
+mod undo_store_tests {
+    use super::*;
+
+    #[test]
+    fn test_newest_keeps_groups() {
+        let change = |operation| Change { start: 0, operation, text: "a".to_string(), row: 0, column: 0 };
+        let changes = vec![
+            change(Operation::Insert),
+            change(Operation::Start), change(Operation::Remove), change(Operation::Insert), change(Operation::End),
+            change(Operation::Insert),
+        ];
+        assert_eq!(newest(&changes, 1).len(), 1);
+        // The group is kept whole
+        assert_eq!(newest(&changes, 3).len(), 5);
+        assert_eq!(newest(&changes, 10).len(), 6);
+    }
+
+    #[test]
+    fn test_restore_matching_text() {
+        let mut code = Code::from_str("hello");
+        code.abs_path = "/undo_store_tests/a.txt".to_string();
+        code.insert_text(" world", 0, 5);
+        let history = snapshot(&code).unwrap();
+        store(&code.abs_path, &history);
+
+        let mut reloaded = Code::from_str("hello world");
+        reloaded.abs_path = code.abs_path.clone();
+        reloaded.undo_history.clear();
+        restore(&mut reloaded);
+        assert_eq!(reloaded.undo_history.len(), code.undo_history.len());
+        reloaded.undo();
+        assert_eq!(reloaded.text.to_string(), "hello");
+
+        let mut changed = Code::from_str("hello there");
+        changed.abs_path = code.abs_path.clone();
+        changed.undo_history.clear();
+        restore(&mut changed);
+        assert!(changed.undo_history.is_empty());
+    }
+}
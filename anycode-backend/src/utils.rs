@@ -1,105 +1,6 @@
 use pathdiff::diff_paths;
 use std::path::{Path, PathBuf};
 
-pub const DEFAULT_IGNORE_DIRS: &[&str] = &[
-    // Version control and IDEs
-    ".git",
-    // Python
-    "__pycache__", ".pytest_cache", 
-];
-
-pub const DEFAULT_IGNORE_FILES: &[&str] = &[
-    // System files
-    ".DS_Store", "Thumbs.db", "desktop.ini",
-    // Certificate and key files
-    "*.pem", "*.key", "*.crt", "*.p12",
-    // Images and video
-    "*.png", "*.jpg", "*.jpeg", "*.gif", "*.bmp", "*.tiff", "*.webp",
-    "*.svg", "*.ico",
-    "*.mp4", "*.mov", "*.avi", "*.mkv", "*.webm", "*.flv", "*.wmv",
-    "*mp3", "*.wav", "*.ogg", "*.aac", "*.flac", "*.m4a", "*.opus", "*.wma",
-    // Archives
-    "*.zip", "*.tar", "*.gz", "*.bz2", "*.xz", "*.7z",
-];
-
-
-/// Get ignore directories with support for environment variable extension
-pub fn get_ignore_dirs() -> Vec<&'static str> {
-    let mut dirs = DEFAULT_IGNORE_DIRS.to_vec();
-
-    if let Ok(extra_dirs) = std::env::var("REDAI_IGNORE_DIRS") {
-        for dir in extra_dirs.split(',') {
-            let dir = dir.trim();
-            if !dir.is_empty() {
-                // We need to leak the string to make it 'static
-                // This is acceptable since ignore patterns are typically set once
-                dirs.push(Box::leak(dir.to_string().into_boxed_str()));
-            }
-        }
-    }
-
-    dirs
-}
-
-/// Get ignore files with support for environment variable extension
-pub fn get_ignore_files() -> Vec<&'static str> {
-    let mut files = DEFAULT_IGNORE_FILES.to_vec();
-
-    if let Ok(extra_files) = std::env::var("REDAI_IGNORE_FILES") {
-        for file in extra_files.split(',') {
-            let file = file.trim();
-            if !file.is_empty() {
-                // We need to leak the string to make it 'static
-                // This is acceptable since ignore patterns are typically set once
-                files.push(Box::leak(file.to_string().into_boxed_str()));
-            }
-        }
-    }
-
-    files
-}
-
-/// Checks if any part of the path matches an ignored directory
-pub fn is_ignored_dir(path: &std::path::Path) -> bool {
-    let ignore_dirs = get_ignore_dirs();
-    path.iter()
-        .any(|p|
-            ignore_dirs.contains(&p.to_string_lossy().as_ref())
-        )
-}
-
-/// Checks if a file should be ignored based on its name or extension
-pub fn is_ignored_file(file_name: &str) -> bool {
-    let ignore_files = get_ignore_files();
-    ignore_files.iter().any(|&pattern| {
-        if pattern.starts_with('*') && pattern.len() > 1 {
-            // Handle wildcard patterns like "*.log"
-            let extension = &pattern[1..];
-            file_name.ends_with(extension)
-        } else {
-            // Exact match
-            file_name == pattern
-        }
-    })
-}
-
-/// Checks if a path should be ignored (either directory or file)
-pub fn is_ignored_path(path: &std::path::Path) -> bool {
-    // Check if any directory in the path should be ignored
-    if is_ignored_dir(path) {
-        return true;
-    }
-
-    // Check if the file itself should be ignored
-    if let Some(file_name) = path.file_name() {
-        if let Some(file_name_str) = file_name.to_str() {
-            return is_ignored_file(file_name_str);
-        }
-    }
-
-    false
-}
-
 pub fn hex_to_rgb(hex_color: &str) -> (u8, u8, u8) {
     let hex = hex_color.trim_start_matches('#');
     let r = u8::from_str_radix(&hex[0..2], 16).unwrap_or(0);
@@ -110,8 +11,12 @@ pub fn hex_to_rgb(hex_color: &str) -> (u8, u8, u8) {
 
 pub fn abs_file(input: &str) -> anyhow::Result<String> {
     let srcdir = std::path::PathBuf::from(input);
-    let c = std::fs::canonicalize(&srcdir)?;
-    Ok(c.to_string_lossy().to_string())
+    let mut c = std::fs::canonicalize(&srcdir)?;
+    // canonicalize gives the case on disk on Windows only
+    if !cfg!(windows) && crate::paths::is_case_insensitive() {
+        c = crate::paths::canonical_case(&c);
+    }
+    Ok(crate::paths::strip_verbatim(&c.to_string_lossy()))
 }
 
 pub fn file_name(input: &str) -> String {
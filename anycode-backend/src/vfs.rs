@@ -0,0 +1,118 @@
+use anyhow::{anyhow, Result};
+use parking_lot::RwLock;
+use serde::Serialize;
+use std::sync::Arc;
+
+// Documents that do not live on the filesystem, addressed by
+// `<scheme>://...` uris: command output today, git revisions, diff views
+// and suggestions later. Subsystems register a provider for their scheme
+// at startup, file:open and search read through the registry instead of
+// each kind of document getting its own events.
+
+pub trait Provider: Send + Sync {
+    /// Uri scheme without `://`
+    fn scheme(&self) -> &str;
+
+    fn read(&self, uri: &str) -> Option<String>;
+
+    /// Language of the document
+    fn lang(&self, _uri: &str) -> String {
+        "text".to_string()
+    }
+
+    /// Uris of the documents currently served
+    fn list(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct VirtualDocument {
+    pub uri: String,
+    pub scheme: String,
+    pub lang: String,
+}
+
+static PROVIDERS: RwLock<Vec<Arc<dyn Provider>>> = RwLock::new(Vec::new());
+
+/// Register the provider of a scheme, replacing the previous one
+pub fn register(provider: Arc<dyn Provider>) {
+    let mut providers = PROVIDERS.write();
+    providers.retain(|p| p.scheme() != provider.scheme());
+    providers.push(provider);
+}
+
+fn scheme_of(uri: &str) -> Option<&str> {
+    let (scheme, _) = uri.split_once("://")?;
+    let valid = !scheme.is_empty()
+        && scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c));
+    valid.then_some(scheme)
+}
+
+fn provider(uri: &str) -> Option<Arc<dyn Provider>> {
+    let scheme = scheme_of(uri)?;
+    PROVIDERS.read().iter().find(|p| p.scheme() == scheme).cloned()
+}
+
+/// Whether a registered provider serves the uri, plain paths and
+/// `file://` uris are not virtual
+pub fn is_virtual(uri: &str) -> bool {
+    provider(uri).is_some()
+}
+
+/// Content and language of a virtual document
+pub fn read(uri: &str) -> Result<(String, String)> {
+    let provider = provider(uri).ok_or_else(|| anyhow!("No provider for {}", uri))?;
+    let content = provider.read(uri).ok_or_else(|| anyhow!("Unknown document {}", uri))?;
+    Ok((content, provider.lang(uri)))
+}
+
+/// Documents of all providers
+pub fn list() -> Vec<VirtualDocument> {
+    let providers: Vec<Arc<dyn Provider>> = PROVIDERS.read().clone();
+    providers.iter()
+        .flat_map(|p| p.list().into_iter().map(|uri| VirtualDocument {
+            lang: p.lang(&uri),
+            scheme: p.scheme().to_string(),
+            uri,
+        }))
+        .collect()
+}
+
+#[cfg(test)]
+mod vfs_tests {
+    use super::*;
+
+    struct Revisions;
+
+    impl Provider for Revisions {
+        fn scheme(&self) -> &str { "test-rev" }
+
+        fn read(&self, uri: &str) -> Option<String> {
+            uri.strip_prefix("test-rev://").map(|rev| format!("content at {}", rev))
+        }
+
+        fn lang(&self, _uri: &str) -> String { "rust".to_string() }
+
+        fn list(&self) -> Vec<String> { vec!["test-rev://HEAD".to_string()] }
+    }
+
+    #[test]
+    fn test_registry() -> Result<()> {
+        register(Arc::new(Revisions));
+
+        assert!(is_virtual("test-rev://HEAD"));
+        assert!(!is_virtual("/src/main.rs"));
+        assert!(!is_virtual("file:///src/main.rs"));
+        assert!(!is_virtual("other://HEAD"));
+
+        let (content, lang) = read("test-rev://abc")?;
+        assert_eq!(content, "content at abc");
+        assert_eq!(lang, "rust");
+        assert!(read("other://abc").is_err());
+
+        let listed = list();
+        assert!(listed.iter().any(|d| d.uri == "test-rev://HEAD" && d.scheme == "test-rev"));
+        Ok(())
+    }
+}
@@ -0,0 +1,465 @@
+use anyhow::Result;
+use notify::event::{ModifyKind, RenameMode};
+use notify::{recommended_watcher, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use socketioxide::SocketIo;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, Mutex};
+
+use crate::app_state::AppState;
+use crate::code::Code;
+use crate::event_log;
+use crate::file_index;
+use crate::output;
+use crate::paths;
+use crate::services;
+use crate::timing::EventTimer;
+use anycode_search::ignore::is_ignored_dir;
+
+/// How long the source side of a rename waits for its destination before
+/// it is reported as a remove
+const RENAME_WINDOW: Duration = Duration::from_millis(100);
+
+/// Events received from notify and not handled yet
+static BACKLOG: AtomicUsize = AtomicUsize::new(0);
+
+pub fn backlog() -> usize {
+    BACKLOG.load(Ordering::Relaxed)
+}
+
+struct Departed {
+    path: PathBuf,
+    tracker: Option<usize>,
+    id: Option<FileId>,
+    at: Instant,
+}
+
+/// What tells a renamed file from another one, the path aside
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FileId {
+    pub len: u64,
+    /// 0 where the platform has none
+    pub inode: u64,
+}
+
+impl FileId {
+    pub fn of(meta: &std::fs::Metadata) -> Self {
+        Self { len: meta.len(), inode: crate::code::inode(meta) }
+    }
+
+    /// The same inode, the same size without inodes
+    fn matches(&self, other: &FileId) -> bool {
+        match (self.inode, other.inode) {
+            (0, _) | (_, 0) => self.len == other.len,
+            (a, b) => a == b,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Arrival {
+    /// The path is the destination of a rename from this path
+    Renamed(PathBuf),
+    /// The path was removed and created again, like editors saving
+    /// through delete and write
+    Recreated,
+    New,
+}
+
+/// Pairs the two halves of renames notify reports separately: inotify
+/// sends From/To with a shared tracker cookie, other backends a remove and
+/// a create. A departed path without an arrival within RENAME_WINDOW was
+/// really removed, or moved out of the workspace, e.g. to the trash.
+/// Without a tracker, a remove and a create of another name in the same
+/// directory pair only when their `FileId`s are known and match.
+#[derive(Default)]
+pub struct RenamePairer {
+    departed: Vec<Departed>,
+}
+
+impl RenamePairer {
+    pub fn departed(&mut self, path: &Path, tracker: Option<usize>, id: Option<FileId>, now: Instant) {
+        self.departed.push(Departed { path: path.to_path_buf(), tracker, id, at: now });
+    }
+
+    /// Match an arrived path with a departed one: the same tracker, else
+    /// the same path, the same file name unless the ids differ, or the
+    /// same directory and id
+    pub fn arrived(&mut self, path: &Path, tracker: Option<usize>, id: Option<FileId>) -> Arrival {
+        let same_id = |d: &Departed| d.id.zip(id).map(|(a, b)| a.matches(&b));
+        let position = match tracker {
+            Some(_) => self.departed.iter().position(|d| d.tracker == tracker),
+            None => self.departed.iter().position(|d| d.tracker.is_none() && d.path == path)
+                .or_else(|| self.departed.iter().position(|d| {
+                    d.tracker.is_none() && d.path.file_name() == path.file_name() && same_id(d) != Some(false)
+                }))
+                .or_else(|| self.departed.iter().position(|d| {
+                    d.tracker.is_none() && d.path.parent() == path.parent() && same_id(d) == Some(true)
+                })),
+        };
+
+        match position.map(|i| self.departed.remove(i)) {
+            Some(departed) if departed.path == path => Arrival::Recreated,
+            Some(departed) => Arrival::Renamed(departed.path),
+            None => Arrival::New,
+        }
+    }
+
+    /// Departed paths whose window is over, in the order they left
+    pub fn expired(&mut self, now: Instant) -> Vec<PathBuf> {
+        let (expired, waiting) = std::mem::take(&mut self.departed).into_iter()
+            .partition(|d| now.duration_since(d.at) >= RENAME_WINDOW);
+        self.departed = waiting;
+        expired.into_iter().map(|d: Departed| d.path).collect()
+    }
+
+    /// When the oldest departed path expires
+    pub fn deadline(&self) -> Option<Instant> {
+        self.departed.iter().map(|d| d.at + RENAME_WINDOW).min()
+    }
+}
+
+/// Trash directories of the desktops, moving there is deleting
+fn is_trash(path: &Path) -> bool {
+    let path = path.to_string_lossy();
+    path.contains(".Trash") || path.contains("$RECYCLE.BIN")
+        || path.contains(&format!(".local{0}share{0}Trash", std::path::MAIN_SEPARATOR))
+}
+
+/// The running watcher, kept for the server's lifetime so roots opened
+/// later can be added to it
+static WATCHER: parking_lot::Mutex<Option<RecommendedWatcher>> = parking_lot::Mutex::new(None);
+
+/// Watch the workspace and forward changes to the clients
+pub fn start(io: Arc<SocketIo>, state: AppState) -> Result<()> {
+    let (watch_tx, mut watch_rx) = mpsc::channel::<notify::Result<Event>>(32);
+    let mut watcher = recommended_watcher(move |res| {
+        BACKLOG.fetch_add(1, Ordering::Relaxed);
+        if watch_tx.blocking_send(res).is_err() {
+            BACKLOG.fetch_sub(1, Ordering::Relaxed);
+        }
+    })?;
+
+    // Watched by absolute path so event paths match the file2code keys,
+    // the added roots follow with watch_root
+    watcher.watch(&crate::roots::primary(), RecursiveMode::Recursive)?;
+    *WATCHER.lock() = Some(watcher);
+
+    tokio::spawn(async move {
+        let mut pairer = RenamePairer::default();
+        loop {
+            let res = match pairer.deadline() {
+                Some(deadline) => {
+                    match tokio::time::timeout_at(deadline.into(), watch_rx.recv()).await {
+                        Ok(res) => res,
+                        Err(_) => {
+                            for path in pairer.expired(Instant::now()) {
+                                handle_removed(&path, &io, &state).await;
+                            }
+                            continue;
+                        }
+                    }
+                }
+                None => watch_rx.recv().await,
+            };
+            let Some(res) = res else { break };
+
+            BACKLOG.fetch_sub(1, Ordering::Relaxed);
+            match res {
+                Ok(event) => handle_event(&event, &mut pairer, &io, &state).await,
+                Err(e) => output::write("watcher", &format!("watch error: {:?}", e)),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Watch a root added to the workspace. Roots inside the primary root are
+/// watched with it already.
+pub fn watch_root(root: &Path) -> Result<()> {
+    if root.starts_with(crate::roots::primary()) {
+        return Ok(());
+    }
+    match WATCHER.lock().as_mut() {
+        Some(watcher) => Ok(watcher.watch(root, RecursiveMode::Recursive)?),
+        None => Ok(()),
+    }
+}
+
+/// Stop watching a removed root
+pub fn unwatch_root(root: &Path) -> Result<()> {
+    if root.starts_with(crate::roots::primary()) {
+        return Ok(());
+    }
+    match WATCHER.lock().as_mut() {
+        Some(watcher) => Ok(watcher.unwatch(root)?),
+        None => Ok(()),
+    }
+}
+
+async fn handle_event(event: &Event, pairer: &mut RenamePairer, io: &Arc<SocketIo>, state: &AppState) {
+    // Cached search results may not match the disk anymore
+    crate::search_jobs::invalidate();
+    // Reads, git's own included, leave the status and the matches as they are
+    if !matches!(event.kind, EventKind::Access(_)) {
+        if let Some(path) = event.paths.iter().find(|p| crate::git::affects_status(p)) {
+            crate::git::changed(path, io);
+        }
+        for path in &event.paths {
+            crate::file_hash::invalidate(path);
+            anycode_search::gitignore::invalidate(path);
+            crate::grep_watch::changed(path);
+        }
+    }
+    // Names resolved by paths::canonical_case may be gone or written
+    // another way now
+    if matches!(event.kind, EventKind::Create(_) | EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(_))) {
+        crate::paths::forget_cases();
+    }
+    let tracker = event.attrs.tracker();
+
+    match event.kind {
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => {
+            handle_renamed(&event.paths[0], &event.paths[1], io, state).await;
+        }
+        EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+            for path in &event.paths {
+                pairer.departed(path, tracker, departed_id(path, state).await, Instant::now());
+            }
+        }
+        EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+            for path in &event.paths {
+                handle_arrived(path, tracker, pairer, io, state).await;
+            }
+        }
+        // Backends that can't tell the sides apart
+        EventKind::Modify(ModifyKind::Name(_)) => {
+            for path in &event.paths {
+                if path.exists() {
+                    handle_arrived(path, tracker, pairer, io, state).await;
+                } else {
+                    pairer.departed(path, tracker, departed_id(path, state).await, Instant::now());
+                }
+            }
+        }
+        EventKind::Modify(ModifyKind::Data(_)) => {
+            for path in &event.paths {
+                if is_ignored_dir(path) { continue }
+                handle_modified(path, io, &state.file2code).await
+            }
+        }
+        _ => {}
+    }
+}
+
+async fn handle_arrived(
+    path: &Path,
+    tracker: Option<usize>,
+    pairer: &mut RenamePairer,
+    io: &Arc<SocketIo>,
+    state: &AppState,
+) {
+    let id = std::fs::metadata(path).ok().map(|meta| FileId::of(&meta));
+    match pairer.arrived(path, tracker, id) {
+        Arrival::Renamed(from) => handle_renamed(&from, path, io, state).await,
+        Arrival::Recreated => handle_modified(path, io, &state.file2code).await,
+        Arrival::New => handle_created(path, io, &state.file2code).await,
+    }
+}
+
+/// Id of a path gone from the disk, known from the stamp of its buffer
+async fn departed_id(path: &Path, state: &AppState) -> Option<FileId> {
+    let key = paths::absolute(path).to_string_lossy().to_string();
+    let f2c = state.file2code.lock().await;
+    let stamp = f2c.get(&key)?.stamp.as_ref()?;
+    Some(FileId { len: stamp.len, inode: stamp.inode })
+}
+
+/// Paths the tree doesn't show: ignored directories and what the
+/// .gitignore files exclude. Removed paths are taken for files.
+fn is_ignored(path: &Path) -> bool {
+    is_ignored_dir(path) || anycode_search::gitignore::is_ignored(path, path.is_dir())
+}
+
+async fn handle_created(path: &Path, io: &Arc<SocketIo>, file2code: &Arc<Mutex<HashMap<String, Code>>>) {
+    if is_ignored(path) {
+        return;
+    }
+    // Written through a temporary file over an open buffer
+    let key = paths::absolute(path).to_string_lossy().to_string();
+    if file2code.lock().await.contains_key(&key) {
+        handle_modified(path, io, file2code).await;
+        return;
+    }
+
+    output::write("watcher", &format!("create {}", path.display()));
+    file_index::add(path);
+    let is_file = path.is_file();
+    let seq = event_log::record("watcher:create", &(path, is_file));
+    let _ = io.emit("watcher:create", &(path, is_file, seq)).await;
+}
+
+async fn handle_removed(path: &Path, io: &Arc<SocketIo>, state: &AppState) {
+    if is_ignored(path) {
+        return;
+    }
+    output::write("watcher", &format!("remove {}", path.display()));
+    file_index::remove(path);
+    let is_file = path.is_file();
+    let seq = event_log::record("watcher:remove", &(path, is_file));
+    let _ = io.emit("watcher:remove", &(path, is_file, seq)).await;
+    handle_deleted_buffers(path, io, state).await;
+}
+
+/// Open buffers of deleted files stay alive with their content, marked
+/// unsaved. The clients get the content with `file:deletedExternally` and
+/// may write it back with `file:restoreFromBuffer`.
+async fn handle_deleted_buffers(path: &Path, io: &Arc<SocketIo>, state: &AppState) {
+    let abs = paths::absolute(path).to_string_lossy().to_string();
+    let prefix = format!("{}{}", abs, std::path::MAIN_SEPARATOR);
+
+    let mut f2c = state.file2code.lock().await;
+    let deleted: Vec<serde_json::Value> = f2c.iter_mut()
+        .filter(|(key, _)| (**key == abs || key.starts_with(&prefix)) && !Path::new(key.as_str()).exists())
+        .map(|(key, code)| {
+            let unsaved = code.changed;
+            code.changed = true;
+            serde_json::json!({ "path": key, "content": code.text.to_string(), "unsaved": unsaved })
+        })
+        .collect();
+    drop(f2c);
+
+    for file in deleted {
+        output::write("watcher", &format!("deleted open buffer {}", file["path"]));
+        let _ = io.emit("file:deletedExternally", &file).await;
+    }
+}
+
+/// Report a rename as one `watcher:rename {from, to}` and move the open
+/// buffers, their LSP documents and the index along. Renames into an
+/// ignored or trash directory are removes, out of one are creates.
+async fn handle_renamed(from: &Path, to: &Path, io: &Arc<SocketIo>, state: &AppState) {
+    if is_ignored(to) || is_trash(to) {
+        handle_removed(from, io, state).await;
+        return;
+    }
+    if is_ignored(from) {
+        handle_created(to, io, &state.file2code).await;
+        return;
+    }
+
+    output::write("watcher", &format!("rename {} -> {}", from.display(), to.display()));
+    file_index::rename(from, to);
+
+    // Renames through file:rename moved the buffers already
+    let from_abs = paths::absolute(from).to_string_lossy().to_string();
+    let to_abs = paths::absolute(to).to_string_lossy().to_string();
+    let mut timer = EventTimer::start("watcher:rename");
+    services::move_buffers(state, &mut timer, &from_abs, &to_abs).await;
+
+    let renamed = serde_json::json!({ "from": from, "to": to });
+    let seq = event_log::record("watcher:rename", &renamed);
+    let _ = io.emit("watcher:rename", &(&renamed, seq)).await;
+}
+
+async fn handle_modified(path: &Path, socket: &Arc<SocketIo>, file2code: &Arc<Mutex<HashMap<String, Code>>>) {
+    output::write("watcher", &format!("modify {}", path.display()));
+
+    let key = paths::absolute(path).to_string_lossy().to_string();
+    let mut f2c = file2code.lock().await;
+    let Some(code) = f2c.get_mut(&key) else {
+        let _ = socket.emit("watcher:modify", &(path, path.is_file())).await;
+        return;
+    };
+
+    // Our own save, the stamp already matches the disk
+    if !code.disk_changed() {
+        return;
+    }
+
+    // Unsaved edits in the buffer, let the user decide which side wins
+    if code.changed {
+        output::write("watcher", &format!("conflict {}", path.display()));
+        let _ = socket.emit("file:conflict", &path).await;
+        return;
+    }
+
+    if let Err(e) = code.reload() {
+        output::write("watcher", &format!("reload failed {}: {}", path.display(), e));
+        return;
+    }
+    let _ = socket.emit("watcher:modify", &(path, path.is_file())).await;
+}
+
+#[cfg(test)]
+mod watcher_tests {
+    use super::*;
+
+    #[test]
+    fn test_pair_by_tracker() {
+        let mut pairer = RenamePairer::default();
+        let now = Instant::now();
+        pairer.departed(Path::new("/w/a.rs"), Some(7), None, now);
+        pairer.departed(Path::new("/w/b.rs"), Some(8), None, now);
+
+        assert_eq!(pairer.arrived(Path::new("/w/c.rs"), Some(8), None), Arrival::Renamed("/w/b.rs".into()));
+        assert_eq!(pairer.arrived(Path::new("/w/d.rs"), Some(9), None), Arrival::New);
+        assert_eq!(pairer.expired(now + RENAME_WINDOW), [PathBuf::from("/w/a.rs")]);
+        assert!(pairer.deadline().is_none());
+    }
+
+    #[test]
+    fn test_pair_remove_and_create() {
+        let mut pairer = RenamePairer::default();
+        let now = Instant::now();
+        let id = |len, inode| Some(FileId { len, inode });
+        pairer.departed(Path::new("/w/src/a.rs"), None, None, now);
+        pairer.departed(Path::new("/w/old.rs"), None, id(10, 5), now);
+        pairer.departed(Path::new("/w/saved.rs"), None, None, now);
+        pairer.departed(Path::new("/w/deleted.txt"), None, id(10, 6), now);
+        pairer.departed(Path::new("/w/unknown.txt"), None, None, now);
+
+        // Moved to another directory
+        assert_eq!(pairer.arrived(Path::new("/w/lib/a.rs"), None, id(3, 1)), Arrival::Renamed("/w/src/a.rs".into()));
+        // Deleted and written again
+        assert_eq!(pairer.arrived(Path::new("/w/saved.rs"), None, id(3, 2)), Arrival::Recreated);
+        // Renamed in place
+        assert_eq!(pairer.arrived(Path::new("/w/new.rs"), None, id(10, 5)), Arrival::Renamed("/w/old.rs".into()));
+        // Another file created while one was deleted, ids unknown or not the same
+        assert_eq!(pairer.arrived(Path::new("/w/other.txt"), None, id(10, 7)), Arrival::New);
+        assert_eq!(pairer.arrived(Path::new("/w/more.txt"), None, None), Arrival::New);
+        assert_eq!(pairer.expired(now + RENAME_WINDOW), [PathBuf::from("/w/deleted.txt"), PathBuf::from("/w/unknown.txt")]);
+    }
+
+    #[test]
+    fn test_file_id_matches() {
+        let id = |len, inode| FileId { len, inode };
+        assert!(id(10, 5).matches(&id(12, 5)));
+        assert!(!id(10, 5).matches(&id(10, 6)));
+        // Without inodes the size decides
+        assert!(id(10, 0).matches(&id(10, 0)));
+        assert!(!id(10, 0).matches(&id(11, 0)));
+    }
+
+    #[test]
+    fn test_unpaired_expire_after_window() {
+        let mut pairer = RenamePairer::default();
+        let now = Instant::now();
+        pairer.departed(Path::new("/w/a.rs"), None, None, now);
+
+        assert_eq!(pairer.deadline(), Some(now + RENAME_WINDOW));
+        assert!(pairer.expired(now).is_empty());
+        assert_eq!(pairer.expired(now + RENAME_WINDOW), [PathBuf::from("/w/a.rs")]);
+    }
+
+    #[test]
+    fn test_is_trash() {
+        assert!(is_trash(Path::new("/home/u/.local/share/Trash/files/a.rs")));
+        assert!(is_trash(Path::new("/Users/u/.Trash/a.rs")));
+        assert!(!is_trash(Path::new("/w/src/trash.rs")));
+    }
+}
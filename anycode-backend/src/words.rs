@@ -0,0 +1,156 @@
+use ropey::Rope;
+use serde::Serialize;
+use std::collections::HashMap;
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Word {
+    /// Char offsets of the word in the document
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+/// Per language identifier rules on top of unicode alphanumerics and `_`
+struct WordRules {
+    extra: &'static [char],
+    /// Chars allowed only as the last char of an identifier, `valid?` and `save!` in ruby
+    suffix: &'static [char],
+}
+
+fn rules(lang: &str) -> WordRules {
+    match lang {
+        "css" | "scss" | "less" => WordRules { extra: &['-'], suffix: &[] },
+        "javascript" | "typescript" | "jsx" | "tsx" => WordRules { extra: &['$'], suffix: &[] },
+        "php" => WordRules { extra: &['$'], suffix: &[] },
+        "ruby" => WordRules { extra: &[], suffix: &['?', '!'] },
+        "lisp" | "clojure" | "scheme" => WordRules { extra: &['-', '?', '!', '*'], suffix: &[] },
+        _ => WordRules { extra: &[], suffix: &[] },
+    }
+}
+
+impl WordRules {
+    fn is_word_char(&self, c: char) -> bool {
+        c.is_alphanumeric() || c == '_' || self.extra.contains(&c)
+    }
+}
+
+/// Whether `c` can be part of an identifier in `lang`
+pub fn is_word_char(c: char, lang: &str) -> bool {
+    rules(lang).is_word_char(c)
+}
+
+/// The identifier under the cursor at `char_offset`. A cursor right after
+/// the last char of a word also hits the word. Words never span lines.
+pub fn word_at(text: &Rope, char_offset: usize, lang: &str) -> Option<Word> {
+    if char_offset > text.len_chars() {
+        return None;
+    }
+
+    let rules = rules(lang);
+    let line_idx = text.char_to_line(char_offset);
+    let line_start = text.line_to_char(line_idx);
+    let line: Vec<char> = text.line(line_idx).chars().collect();
+    let cursor = char_offset - line_start;
+
+    // Position of a word char at or just before the cursor
+    let at = if cursor < line.len() && rules.is_word_char(line[cursor]) {
+        cursor
+    } else if cursor > 0 && rules.is_word_char(line[cursor - 1]) {
+        cursor - 1
+    } else if cursor > 1 && rules.suffix.contains(&line[cursor - 1])
+        && rules.is_word_char(line[cursor - 2])
+    {
+        cursor - 2
+    } else {
+        return None;
+    };
+
+    let mut start = at;
+    while start > 0 && rules.is_word_char(line[start - 1]) {
+        start -= 1;
+    }
+
+    let mut end = at + 1;
+    while end < line.len() && rules.is_word_char(line[end]) {
+        end += 1;
+    }
+
+    // Take a ruby style suffix, but not the `!` of `!=`
+    if end < line.len() && rules.suffix.contains(&line[end])
+        && line.get(end + 1) != Some(&'=')
+    {
+        end += 1;
+    }
+
+    Some(Word {
+        start: line_start + start,
+        end: line_start + end,
+        text: line[start..end].iter().collect(),
+    })
+}
+
+/// Words of the document starting with `prefix`, the most frequent first.
+/// Completion without a language server.
+pub fn completions(text: &Rope, prefix: &str, lang: &str, limit: usize) -> Vec<String> {
+    let rules = rules(lang);
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    let mut word = String::new();
+
+    for c in text.chars().chain(std::iter::once(' ')) {
+        if rules.is_word_char(c) {
+            word.push(c);
+            continue;
+        }
+        if word.len() > prefix.len() && word.starts_with(prefix) {
+            *counts.entry(std::mem::take(&mut word)).or_default() += 1;
+        }
+        word.clear();
+    }
+
+    let mut words: Vec<(String, usize)> = counts.into_iter().collect();
+    words.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    words.into_iter().take(limit).map(|(word, _)| word).collect()
+}
+
+#[cfg(test)]
+mod words_tests {
+    use super::*;
+
+    fn word(text: &str, offset: usize, lang: &str) -> Option<String> {
+        word_at(&Rope::from_str(text), offset, lang).map(|w| w.text)
+    }
+
+    #[test]
+    fn test_word_at_default() {
+        assert_eq!(word("let foo_bar = 1;", 6, "rust"), Some("foo_bar".into()));
+        // Cursor right after the word
+        assert_eq!(word("let foo_bar = 1;", 11, "rust"), Some("foo_bar".into()));
+        assert_eq!(word("a = b", 2, "rust"), None);
+        assert_eq!(word("let x-y", 5, "rust"), Some("x".into()));
+    }
+
+    #[test]
+    fn test_word_at_unicode() {
+        let w = word_at(&Rope::from_str("x\nпусть имя = 1"), 9, "python").unwrap();
+        assert_eq!(w.text, "имя");
+        assert_eq!((w.start, w.end), (8, 11));
+    }
+
+    #[test]
+    fn test_word_at_language_rules() {
+        assert_eq!(word(".btn-primary { }", 3, "css"), Some("btn-primary".into()));
+        assert_eq!(word("const $el = x", 8, "javascript"), Some("$el".into()));
+        assert_eq!(word("if user.valid? then", 10, "ruby"), Some("valid?".into()));
+        assert_eq!(word("user.save!", 10, "ruby"), Some("save!".into()));
+        assert_eq!(word("a != b", 0, "ruby"), Some("a".into()));
+        assert_eq!(word("a!= b", 0, "ruby"), Some("a".into()));
+    }
+
+    #[test]
+    fn test_completions() {
+        let text = Rope::from_str("let value = values[0];\nvalue += valid(value);\nfn val() {}");
+        assert_eq!(completions(&text, "val", "rust", 10), ["value", "valid", "values"]);
+        assert_eq!(completions(&text, "val", "rust", 1), ["value"]);
+        assert!(completions(&text, "zzz", "rust", 10).is_empty());
+    }
+}